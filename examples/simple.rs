@@ -1,34 +1,23 @@
-use std::process;
-use std::io::{Cursor};
-use std::fs;
 use std::path::Path;
 
-use psarc_unpacker::psarc::PsarcFileHeader;
-use psarc_unpacker::psarc::PsarcTOC;
+use psarc_unpacker::extract::{extract_all, ExtractOptions};
 use psarc_unpacker::psarc::PsarcFile;
-use psarc_unpacker::psarc::BkhdAsset;
-use psarc_unpacker::file_reader::MemFile;
 
 
 fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
     let file_path = "mop.psarc";
     let output_folder = Path::new("mop");
 
-    let mem_file = fs::read(file_path)?;
-    let mut cursor =  Cursor::new(&mem_file);
-
+    let psarc_file = PsarcFile::open_path(file_path)?;
     println!("Successfully read file: {}", file_path);
-    println!("File size: {} bytes", mem_file.len());
-
-    let mut psarc_file = PsarcFile::open(&mut cursor)?;
-    psarc_file.read_manifest()?;
+    println!("File size: {} bytes", psarc_file.data.len());
 
-    // Iterate over the TOC entries and print each entry's path.
+    // Iterate over the TOC entries and print each entry's size and path.
     for (i, entry) in psarc_file.toc.entries.iter().enumerate() {
-        println!("Entry {} path: {:?}", i, entry.path);
+        println!("{:>5}  {:>10}  {}", i, entry.length, entry.path.as_deref().unwrap_or("<unnamed>"));
     }
-    psarc_file.dump_entries(output_folder)?;
-    psarc_file.convert_sng_assets_to_json(output_folder)?;
+
+    extract_all(file_path, output_folder, ExtractOptions::default())?;
 
     Ok(())
-}
\ No newline at end of file
+}
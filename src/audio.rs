@@ -0,0 +1,482 @@
+//! Converting wem streams to files a player opens, with the Vorbis codebooks injected
+//! rather than read from a fixed path.
+//!
+//! ```no_run
+//! use psarc_unpacker::audio::AudioConverter;
+//! # fn main() -> std::io::Result<()> {
+//! let converter = AudioConverter::new().codebooks_path("tools/packed_codebooks_aoTuV_603.bin");
+//! let wem = std::fs::read("song.wem")?;
+//! let audio = converter.convert(&wem)?;
+//! std::fs::write(format!("song.{}", audio.extension()), audio.data())?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use serde::Serialize;
+
+use crate::bnk::resolve_audio_entries;
+use crate::cache::{self, convert_cached, ConversionCache};
+use crate::codebook::CodebookLibrary;
+use crate::manifest::{read_hsan_manifests, read_manifests, SongManifest};
+use crate::psarc::PsarcFile;
+use crate::vorbis::{packet_format, wem_to_ogg_with};
+pub use crate::vorbis::{wem_to_ogg, PacketFormat};
+use crate::wem::{wem_to_wav, WemCodec, WemInfo};
+
+/// A converted wem.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConvertedAudio {
+    /// PCM and ADPCM streams, as a WAV file.
+    Wav(Vec<u8>),
+    /// Vorbis streams, as an Ogg Vorbis file.
+    Ogg(Vec<u8>),
+}
+
+impl ConvertedAudio {
+    /// File extension of the format, without the dot.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ConvertedAudio::Wav(_) => "wav",
+            ConvertedAudio::Ogg(_) => "ogg",
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        match self {
+            ConvertedAudio::Wav(data) | ConvertedAudio::Ogg(data) => data,
+        }
+    }
+
+    pub fn into_data(self) -> Vec<u8> {
+        match self {
+            ConvertedAudio::Wav(data) | ConvertedAudio::Ogg(data) => data,
+        }
+    }
+}
+
+/// A way of rebuilding a Vorbis stream: the packet format read and where the codebooks
+/// come from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ConversionStrategy {
+    pub packet_format: PacketFormat,
+    pub inline_codebooks: bool,
+}
+
+/// A strategy that failed, and why.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FailedAttempt {
+    pub strategy: ConversionStrategy,
+    pub error: String,
+}
+
+/// How a wem was converted.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ConversionReport {
+    /// The strategy that rebuilt the stream, `None` for PCM and ADPCM streams, which are
+    /// converted directly.
+    pub strategy: Option<ConversionStrategy>,
+    /// Strategies tried before it.
+    pub failed_attempts: Vec<FailedAttempt>,
+}
+
+impl ConversionReport {
+    /// Whether the stream needed a strategy other than the first.
+    pub fn fell_back(&self) -> bool {
+        !self.failed_attempts.is_empty()
+    }
+}
+
+/// How `AudioConverter` rebuilds Vorbis streams, for wems whose `vorb` data is not what
+/// their audio is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct AudioOptions {
+    /// Packet format to read instead of the one the `vorb` data declares.
+    pub packet_format: Option<PacketFormat>,
+    /// Whether the codebooks are read from the streams themselves first.
+    pub inline_codebooks: bool,
+    /// Whether the other strategies are tried when the first fails.
+    pub fallback: bool,
+}
+
+impl Default for AudioOptions {
+    fn default() -> Self {
+        AudioOptions { packet_format: None, inline_codebooks: false, fallback: true }
+    }
+}
+
+/// Converts wems by codec. The codebooks are only loaded for the first Vorbis stream,
+/// from the library given with `codebooks`, or else from the file
+/// `CodebookLibrary::load_shared` finds (the path given with `codebooks_path` first), so
+/// converters made per worker thread share one copy of the file.
+///
+/// A converter is `Sync`; `convert_with_codebooks` converts with a library borrowed from
+/// the caller instead of the converter's own.
+///
+/// Vorbis streams are first rebuilt with the packet format their `vorb` data declares and
+/// the codebooks the converter is set up for (`inline_codebooks`). When that fails, the
+/// other packet format is tried, then both formats with the other codebook source; the
+/// `ConversionReport` of `convert_with_report` tells which strategy worked. `options`
+/// forces a packet format or turns the fallback off.
+///
+/// With a `cache`, WAV and Ogg outputs are read back from it instead of being converted
+/// again; the report of a cached Vorbis stream names no strategy.
+#[derive(Debug, Default)]
+pub struct AudioConverter {
+    codebooks_path: Option<PathBuf>,
+    codebooks: OnceLock<CodebookLibrary>,
+    options: AudioOptions,
+    cache: Option<ConversionCache>,
+}
+
+impl AudioConverter {
+    pub fn new() -> Self {
+        AudioConverter::default()
+    }
+
+    /// Reads the codebooks from `path` instead of looking them up.
+    pub fn codebooks_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.codebooks_path = Some(path.into());
+        self
+    }
+
+    /// Uses `codebooks`, such as bytes embedded in the calling program or a library shared
+    /// with other converters (clones share the bytes).
+    pub fn codebooks(self, codebooks: CodebookLibrary) -> Self {
+        let _ = self.codebooks.set(codebooks);
+        self
+    }
+
+    /// Reads the codebooks from the Vorbis streams themselves, for wems encoded with
+    /// inline codebooks; no library is loaded.
+    pub fn inline_codebooks(mut self, inline: bool) -> Self {
+        self.options.inline_codebooks = inline;
+        self
+    }
+
+    /// Sets every Vorbis rebuild option at once.
+    pub fn options(mut self, options: AudioOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Reuses the conversions of `cache` and caches new ones.
+    pub fn cache(mut self, cache: Option<ConversionCache>) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// The codebooks, loaded on first use.
+    pub fn codebook_library(&self) -> io::Result<&CodebookLibrary> {
+        if let Some(codebooks) = self.codebooks.get() {
+            return Ok(codebooks);
+        }
+        let codebooks = CodebookLibrary::load_shared(self.codebooks_path.as_deref())?;
+        Ok(self.codebooks.get_or_init(|| codebooks))
+    }
+
+    /// Converts `data` in memory: PCM and ADPCM to WAV, Vorbis to Ogg (see
+    /// `vorbis::wem_to_ogg`). Other codecs are rejected with `ErrorKind::Unsupported`.
+    pub fn convert(&self, data: &[u8]) -> io::Result<ConvertedAudio> {
+        Ok(self.convert_with_report(data)?.0)
+    }
+
+    /// `convert`, telling how the stream was converted.
+    pub fn convert_with_report(&self, data: &[u8]) -> io::Result<(ConvertedAudio, ConversionReport)> {
+        self.convert_using(data, || self.codebook_library())
+    }
+
+    /// `convert` with `codebooks` rather than the converter's own library.
+    pub fn convert_with_codebooks(&self, data: &[u8], codebooks: &CodebookLibrary) -> io::Result<ConvertedAudio> {
+        Ok(self.convert_using(data, || Ok(codebooks))?.0)
+    }
+
+    fn convert_using<'a>(
+        &self,
+        data: &[u8],
+        codebooks: impl Fn() -> io::Result<&'a CodebookLibrary>,
+    ) -> io::Result<(ConvertedAudio, ConversionReport)> {
+        let info = WemInfo::parse(data)?;
+        let cache = self.cache.as_ref();
+        match info.codec() {
+            WemCodec::Pcm | WemCodec::Adpcm => {
+                let wav = convert_cached(cache, cache::WEM_WAV, data, wem_to_wav)?;
+                Ok((ConvertedAudio::Wav(wav), ConversionReport::default()))
+            }
+            WemCodec::Vorbis => {
+                let mut report = ConversionReport::default();
+                let ogg = convert_cached(cache, cache::OGG, data, |data| {
+                    let (ogg, rebuilt) = self.rebuild_vorbis(data, codebooks)?;
+                    report = rebuilt;
+                    Ok(ogg.into_data())
+                })?;
+                Ok((ConvertedAudio::Ogg(ogg), report))
+            }
+            WemCodec::Other(id) => {
+                Err(io::Error::new(io::ErrorKind::Unsupported, format!("Unsupported wem codec 0x{:04X}", id)))
+            }
+        }
+    }
+
+    /// Tries the strategies of `strategies` in order. Streams Wwise versions this crate
+    /// cannot rebuild (`ErrorKind::Unsupported`) are not retried; when every strategy
+    /// fails, the error of the first is returned, listing the others.
+    fn rebuild_vorbis<'a>(
+        &self,
+        data: &[u8],
+        codebooks: impl Fn() -> io::Result<&'a CodebookLibrary>,
+    ) -> io::Result<(ConvertedAudio, ConversionReport)> {
+        let mut report = ConversionReport::default();
+        let mut first_error = None;
+        for strategy in self.strategies(data)? {
+            let attempt = if strategy.inline_codebooks {
+                wem_to_ogg_with(data, None, Some(strategy.packet_format))
+            } else {
+                codebooks().and_then(|library| wem_to_ogg_with(data, Some(library), Some(strategy.packet_format)))
+            };
+            match attempt {
+                Ok(ogg) => {
+                    report.strategy = Some(strategy);
+                    return Ok((ConvertedAudio::Ogg(ogg), report));
+                }
+                Err(err) if err.kind() == io::ErrorKind::Unsupported => return Err(err),
+                Err(err) => {
+                    tracing::debug!("Vorbis rebuild with {:?} failed: {}", strategy, err);
+                    report.failed_attempts.push(FailedAttempt { strategy, error: err.to_string() });
+                    first_error.get_or_insert(err);
+                }
+            }
+        }
+        let first = first_error.unwrap_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "No conversion strategy"));
+        if report.failed_attempts.len() < 2 {
+            return Err(first);
+        }
+        let others: Vec<String> = report.failed_attempts[1..]
+            .iter()
+            .map(|attempt| format!("{:?}: {}", attempt.strategy, attempt.error))
+            .collect();
+        Err(io::Error::new(first.kind(), format!("{} (other strategies failed too: {})", first, others.join("; "))))
+    }
+
+    /// The strategies `rebuild_vorbis` tries, in order. A forced packet format is the only
+    /// one tried; without the fallback only the first strategy is.
+    fn strategies(&self, data: &[u8]) -> io::Result<Vec<ConversionStrategy>> {
+        let options = self.options;
+        let formats = match options.packet_format {
+            Some(forced) => vec![forced],
+            None => {
+                let declared = packet_format(data)?;
+                vec![declared, declared.alternate()]
+            }
+        };
+        let mut strategies = Vec::with_capacity(4);
+        for inline_codebooks in [options.inline_codebooks, !options.inline_codebooks] {
+            for &packet_format in &formats {
+                strategies.push(ConversionStrategy { packet_format, inline_codebooks });
+            }
+        }
+        if !options.fallback {
+            strategies.truncate(1);
+        }
+        Ok(strategies)
+    }
+}
+
+/// A wem written by `export_song_audio`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ExportedAudio {
+    /// The wem entry converted.
+    pub entry: String,
+    /// Song whose bank plays the wem, `None` for wems no song bank references.
+    pub song_key: Option<String>,
+    pub preview: bool,
+    pub path: PathBuf,
+    pub conversion: ConversionReport,
+}
+
+/// Converts every wem of `psarc` into `output_dir`, named after its song rather than its
+/// wem id: `Artist - Title.ogg` for the full track and `Artist - Title (preview).ogg` for
+/// the preview. Which wem is which comes from the song banks (see
+/// `bnk::resolve_audio_entries`), the names from the manifests; the song key stands in
+/// for a missing title. Wems no bank plays keep their id as name, and characters file
+/// systems reject become `_`. Wems of codecs the converter does not handle are skipped.
+pub fn export_song_audio(psarc: &PsarcFile, output_dir: &Path, converter: &AudioConverter) -> io::Result<Vec<ExportedAudio>> {
+    fs::create_dir_all(output_dir)?;
+    let mut manifests = read_manifests(psarc)?;
+    manifests.extend(read_hsan_manifests(psarc)?);
+    let mut named = Vec::new();
+    for song in resolve_audio_entries(psarc)? {
+        let name = song_display_name(&manifests, &song.song_key);
+        if let Some(wem) = song.main_wem {
+            named.push((wem, Some(song.song_key.clone()), false, name.clone()));
+        }
+        if let Some(wem) = song.preview_wem {
+            named.push((wem, Some(song.song_key), true, format!("{} (preview)", name)));
+        }
+    }
+    for path in psarc.toc.entries.iter().filter_map(|e| e.path.as_deref()).filter(|p| p.ends_with(".wem")) {
+        if !named.iter().any(|(wem, ..)| wem == path) {
+            let stem = Path::new(path).file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+            named.push((path.to_string(), None, false, stem));
+        }
+    }
+
+    let mut used = HashSet::new();
+    let mut exported = Vec::new();
+    for (entry, song_key, preview, name) in named {
+        let data = psarc.read_path(&entry)?;
+        let (audio, conversion) = match converter.convert_with_report(&data) {
+            Ok(converted) => converted,
+            Err(err) if err.kind() == io::ErrorKind::Unsupported => {
+                tracing::trace!("Skipping {}: {}", entry, err);
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+        let base = safe_file_name(&name);
+        let mut file_name = format!("{}.{}", base, audio.extension());
+        for copy in 2.. {
+            if used.insert(file_name.to_lowercase()) {
+                break;
+            }
+            file_name = format!("{} ({}).{}", base, copy, audio.extension());
+        }
+        let path = output_dir.join(file_name);
+        fs::write(&path, audio.data())?;
+        tracing::info!("Converted {} to {:?}", entry, path);
+        if conversion.fell_back() {
+            tracing::warn!("Converted {} with {:?} after {} failed attempts", entry, conversion.strategy, conversion.failed_attempts.len());
+        }
+        exported.push(ExportedAudio { entry, song_key, preview, path, conversion });
+    }
+    Ok(exported)
+}
+
+/// `Artist - Title` from the first manifest of the song `key`.
+fn song_display_name(manifests: &[SongManifest], key: &str) -> String {
+    let attributes = manifests
+        .iter()
+        .map(|m| &m.attributes)
+        .filter(|a| a.song_key.as_deref().is_some_and(|k| k.eq_ignore_ascii_case(key)))
+        .find(|a| a.song_name.is_some() || a.artist_name.is_some());
+    let title = attributes.and_then(|a| a.song_name.as_deref()).unwrap_or(key);
+    match attributes.and_then(|a| a.artist_name.as_deref()) {
+        Some(artist) => format!("{} - {}", artist, title),
+        None => title.to_string(),
+    }
+}
+
+/// `name` with the characters Windows, macOS or Linux reject in file names replaced by
+/// `_`, and without trailing dots and spaces.
+fn safe_file_name(name: &str) -> String {
+    let name: String =
+        name.chars().map(|c| if c.is_control() || r#"<>:"/\|?*"#.contains(c) { '_' } else { c }).collect();
+    let name = name.trim().trim_end_matches(['.', ' ']);
+    if name.is_empty() {
+        "_".to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A Vorbis wem with a `vorb` chunk of `vorb_size` bytes whose setup packet is three
+    /// bytes of noise.
+    fn vorbis_wem(vorb_size: usize) -> Vec<u8> {
+        let mut fmt = [0u8; 0x18];
+        fmt[..2].copy_from_slice(&0xFFFFu16.to_le_bytes());
+        fmt[2..4].copy_from_slice(&2u16.to_le_bytes());
+        fmt[4..8].copy_from_slice(&44100u32.to_le_bytes());
+        let mut vorb = vec![0u8; vorb_size];
+        if vorb_size == 0x2A {
+            vorb[0x14..0x18].copy_from_slice(&5u32.to_le_bytes());
+            vorb[0x28..0x2A].copy_from_slice(&[8, 11]);
+        }
+        let payload = [3, 0, 0xA5, 0x5A, 0xFF];
+        let mut wem = b"RIFF\0\0\0\0WAVE".to_vec();
+        for (id, chunk) in [(b"fmt ", &fmt[..]), (b"vorb", &vorb[..]), (b"data", &payload[..])] {
+            wem.extend_from_slice(id);
+            wem.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+            wem.extend_from_slice(chunk);
+        }
+        wem
+    }
+
+    fn library() -> CodebookLibrary {
+        CodebookLibrary::from_bytes(vec![0xFF, 0xFF, 0, 0, 0, 0, 2, 0, 0, 0]).unwrap()
+    }
+
+    #[test]
+    fn strategies_start_with_the_declared_format() {
+        let strategy = |packet_format, inline_codebooks| ConversionStrategy { packet_format, inline_codebooks };
+        let wem = vorbis_wem(0x2A);
+        assert_eq!(
+            AudioConverter::new().strategies(&wem).unwrap(),
+            [
+                strategy(PacketFormat::Modified, false),
+                strategy(PacketFormat::Standard, false),
+                strategy(PacketFormat::Modified, true),
+                strategy(PacketFormat::Standard, true),
+            ]
+        );
+        assert_eq!(AudioConverter::new().inline_codebooks(true).strategies(&wem).unwrap()[0], strategy(PacketFormat::Modified, true));
+    }
+
+    #[test]
+    fn options_narrow_the_strategies() {
+        let strategy = |packet_format, inline_codebooks| ConversionStrategy { packet_format, inline_codebooks };
+        let wem = vorbis_wem(0x2A);
+        let forced = AudioOptions { packet_format: Some(PacketFormat::Standard), ..AudioOptions::default() };
+        assert_eq!(
+            AudioConverter::new().options(forced).strategies(&wem).unwrap(),
+            [strategy(PacketFormat::Standard, false), strategy(PacketFormat::Standard, true)]
+        );
+        let single = AudioOptions { inline_codebooks: true, fallback: false, ..forced };
+        assert_eq!(AudioConverter::new().options(single).strategies(&wem).unwrap(), [strategy(PacketFormat::Standard, true)]);
+
+        let err = AudioConverter::new().codebooks(library()).options(single).convert(&wem).unwrap_err();
+        assert!(!err.to_string().contains("other strategies"), "{}", err);
+    }
+
+    #[test]
+    fn cached_streams_are_not_rebuilt() {
+        let dir = std::env::temp_dir().join(format!("psarc_audio_cache_{}", std::process::id()));
+        let cache = ConversionCache::new(&dir);
+        let wem = vorbis_wem(0x2A);
+        cache.put(cache::OGG, &wem, b"OggS cached").unwrap();
+        let converter = AudioConverter::new().codebooks(library()).cache(Some(cache));
+        let (audio, report) = converter.convert_with_report(&wem).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(audio, ConvertedAudio::Ogg(b"OggS cached".to_vec()));
+        assert_eq!(report.strategy, None);
+    }
+
+    #[test]
+    fn packet_format_names() {
+        assert_eq!("Modified".parse(), Ok(PacketFormat::Modified));
+        assert_eq!("standard".parse(), Ok(PacketFormat::Standard));
+        assert!("mod".parse::<PacketFormat>().is_err());
+    }
+
+    #[test]
+    fn every_strategy_is_tried() {
+        let converter = AudioConverter::new().codebooks(library());
+        let err = converter.convert(&vorbis_wem(0x2A)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string().matches("inline_codebooks").count(), 3, "{}", err);
+    }
+
+    #[test]
+    fn unsupported_streams_are_not_retried() {
+        let err = AudioConverter::new().codebooks(library()).convert(&vorbis_wem(0x2C)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        assert!(!err.to_string().contains("other strategies"));
+    }
+}
@@ -0,0 +1,216 @@
+//! Quick audition of charts: the MIDI export of an arrangement rendered to a short WAV.
+//!
+//! Listening to a few seconds of a chart is the fastest check that an extraction, a
+//! flattened difficulty or a transposition came out right. Given a SoundFont (`.sf2`),
+//! the MIDI is played by rustysynth with the track's programs, bends included, into a
+//! stereo clip. Without one, notes are played by a built-in plucked-string voice
+//! (Karplus-Strong) into a mono clip, so nothing needs to be installed; pitch bends are
+//! not rendered then.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use rustysynth::{SoundFont, Synthesizer, SynthesizerSettings};
+
+use crate::midi::{arrangement_track, MidiEventKind, MidiFile, MidiOptions, TempoMap, TICKS_PER_QUARTER};
+use crate::song::Song;
+use crate::wem::build_wav;
+
+/// Sample rate of rendered clips.
+pub const AUDITION_SAMPLE_RATE: u32 = 22050;
+
+/// Clip length used when none is given, in seconds.
+pub const DEFAULT_AUDITION_SECONDS: f64 = 15.0;
+
+/// Decay of a plucked string per sample, before the averaging filter.
+const STRING_DECAY: f32 = 0.996;
+
+/// Fade applied when a note is released, in seconds.
+const RELEASE_SECONDS: f64 = 0.05;
+
+/// Level of a single note in the mix.
+const NOTE_GAIN: f32 = 0.25;
+
+/// Seconds from the start of the file to `tick`, following the tempo changes.
+fn tick_seconds(tempo: &[(u32, u32)], tick: u32) -> f64 {
+    let mut seconds = 0.0;
+    let mut last = (0u32, 500_000u32);
+    for &(at, micros) in tempo.iter().take_while(|(at, _)| *at <= tick) {
+        seconds += (at - last.0) as f64 * last.1 as f64 / 1e6 / TICKS_PER_QUARTER as f64;
+        last = (at, micros);
+    }
+    seconds + (tick - last.0) as f64 * last.1 as f64 / 1e6 / TICKS_PER_QUARTER as f64
+}
+
+/// A plucked string sounding `key` for `length` seconds, `release` included.
+fn pluck(key: u8, length: f64, seed: u32) -> Vec<f32> {
+    let frequency = 440.0 * 2f64.powf((key as f64 - 69.0) / 12.0);
+    let period = ((AUDITION_SAMPLE_RATE as f64 / frequency).round() as usize).max(2);
+    // Deterministic noise burst, so renders are reproducible.
+    let mut state = seed.wrapping_mul(2_654_435_761).wrapping_add(key as u32) | 1;
+    let mut delay: Vec<f32> = (0..period)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            (state as f32 / u32::MAX as f32) * 2.0 - 1.0
+        })
+        .collect();
+    let total = ((length + RELEASE_SECONDS) * AUDITION_SAMPLE_RATE as f64) as usize;
+    let release_start = (length * AUDITION_SAMPLE_RATE as f64) as usize;
+    let release = (RELEASE_SECONDS * AUDITION_SAMPLE_RATE as f64).max(1.0);
+    (0..total)
+        .map(|i| {
+            let j = i % period;
+            let sample = delay[j];
+            delay[j] = STRING_DECAY * 0.5 * (sample + delay[(j + 1) % period]);
+            let fade = if i < release_start { 1.0 } else { 1.0 - (i - release_start) as f64 / release };
+            sample * fade.max(0.0) as f32
+        })
+        .collect()
+}
+
+/// Renders `seconds` of a MIDI file from `start` to mono 16-bit samples at
+/// `AUDITION_SAMPLE_RATE`. Every note of every track is played by the string voice.
+pub fn render_midi(file: &MidiFile, start: f64, seconds: f64) -> Vec<i16> {
+    let tempo = file.tempo.tempo_events();
+    let rate = AUDITION_SAMPLE_RATE as f64;
+    let mut mix = vec![0f32; (seconds.max(0.0) * rate) as usize];
+    let mut seed = 0;
+    for track in &file.tracks {
+        let mut held: HashMap<(u8, u8), u32> = HashMap::new();
+        for event in track.sorted_events() {
+            match event.kind {
+                MidiEventKind::NoteOn { channel, key, .. } => {
+                    held.insert((channel, key), event.tick);
+                }
+                MidiEventKind::NoteOff { channel, key } => {
+                    let Some(on) = held.remove(&(channel, key)) else {
+                        continue;
+                    };
+                    let (from, to) = (tick_seconds(&tempo, on), tick_seconds(&tempo, event.tick));
+                    if to + RELEASE_SECONDS < start || from >= start + seconds {
+                        continue;
+                    }
+                    seed += 1;
+                    let offset = ((from - start) * rate).round() as i64;
+                    for (i, sample) in pluck(key, to - from, seed).into_iter().enumerate() {
+                        if let Some(slot) = usize::try_from(offset + i as i64).ok().and_then(|at| mix.get_mut(at)) {
+                            *slot += sample * NOTE_GAIN;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    // Soft clipping keeps dense chords from wrapping around.
+    mix.iter().map(|sample| (sample.tanh() * i16::MAX as f32) as i16).collect()
+}
+
+/// Reads a SoundFont for `render_midi_with_sound_font`.
+pub fn load_sound_font(path: &Path) -> io::Result<Arc<SoundFont>> {
+    let mut file = io::BufReader::new(fs::File::open(path)?);
+    let sound_font = SoundFont::new(&mut file)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), err)))?;
+    Ok(Arc::new(sound_font))
+}
+
+/// Renders `seconds` of a MIDI file from `start` through `sound_font` to interleaved
+/// stereo 16-bit samples at `AUDITION_SAMPLE_RATE`.
+///
+/// The events of every track are played in time order from the start of the file, so
+/// notes held over `start` sound as they would in the song.
+pub fn render_midi_with_sound_font(
+    file: &MidiFile,
+    sound_font: &Arc<SoundFont>,
+    start: f64,
+    seconds: f64,
+) -> io::Result<Vec<i16>> {
+    let settings = SynthesizerSettings::new(AUDITION_SAMPLE_RATE as i32);
+    let mut synthesizer = Synthesizer::new(sound_font, &settings)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err.to_string()))?;
+    let tempo = file.tempo.tempo_events();
+    let rate = AUDITION_SAMPLE_RATE as f64;
+    let first = (start.max(0.0) * rate) as usize;
+    let end = first + (seconds.max(0.0) * rate) as usize;
+
+    let mut events: Vec<_> = file.tracks.iter().flat_map(|track| track.sorted_events()).collect();
+    events.sort_by_key(|event| event.tick);
+    let (mut left, mut right) = (vec![0f32; end], vec![0f32; end]);
+    let mut rendered = 0;
+    for event in &events {
+        let at = ((tick_seconds(&tempo, event.tick) * rate) as usize).min(end);
+        if at > rendered {
+            synthesizer.render(&mut left[rendered..at], &mut right[rendered..at]);
+            rendered = at;
+        }
+        if rendered == end {
+            break;
+        }
+        match event.kind {
+            MidiEventKind::NoteOn { channel, key, velocity } => synthesizer.note_on(channel as i32, key as i32, velocity as i32),
+            MidiEventKind::NoteOff { channel, key } => synthesizer.note_off(channel as i32, key as i32),
+            MidiEventKind::PitchBend { channel, value } => {
+                let value = (value as i32 + 8192).clamp(0, 16383);
+                synthesizer.process_midi_message(channel as i32, 0xE0, value & 0x7F, value >> 7);
+            }
+            MidiEventKind::ProgramChange { channel, program } => {
+                synthesizer.process_midi_message(channel as i32, 0xC0, program as i32, 0)
+            }
+            MidiEventKind::ControlChange { channel, controller, value } => {
+                synthesizer.process_midi_message(channel as i32, 0xB0, controller as i32, value as i32)
+            }
+            _ => {}
+        }
+    }
+    if rendered < end {
+        synthesizer.render(&mut left[rendered..], &mut right[rendered..]);
+    }
+    let to_i16 = |sample: f32| (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+    Ok(left[first..].iter().zip(&right[first..]).flat_map(|(&l, &r)| [to_i16(l), to_i16(r)]).collect())
+}
+
+/// Renders `seconds` of an arrangement of a song (`lead`, `bass`, ...) from `start` as a
+/// WAV, from the same track the MIDI export writes: stereo through `sound_font` when
+/// given (see `load_sound_font`), mono with the plucked-string voice otherwise.
+pub fn audition_arrangement(
+    song: &Song,
+    arrangement: &str,
+    start: f64,
+    seconds: f64,
+    sound_font: Option<&Arc<SoundFont>>,
+) -> io::Result<Vec<u8>> {
+    let found = song.arrangement(arrangement).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Song {} has no arrangement {}", song.key, arrangement),
+        )
+    })?;
+    let sng = found.sng()?;
+    let mut file = MidiFile::new(TempoMap::new(&sng.bpms));
+    file.tracks.push(arrangement_track(sng, &file.tempo, &found.name, 0, &MidiOptions::default()));
+    let (channels, samples) = match sound_font {
+        Some(sound_font) => (2, render_midi_with_sound_font(&file, sound_font, start, seconds)?),
+        None => (1, render_midi(&file, start, seconds)),
+    };
+    let bytes: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+    Ok(build_wav(channels, AUDITION_SAMPLE_RATE, 16, &bytes))
+}
+
+/// Writes an audition clip of an arrangement to a WAV file.
+pub fn export_audition(
+    song: &Song,
+    arrangement: &str,
+    start: f64,
+    seconds: f64,
+    sound_font: Option<&Arc<SoundFont>>,
+    output_path: &Path,
+) -> io::Result<()> {
+    fs::write(output_path, audition_arrangement(song, arrangement, start, seconds, sound_font)?)?;
+    tracing::info!("Written audition of {} to {:?}", arrangement, output_path);
+    Ok(())
+}
@@ -0,0 +1,292 @@
+//! Wwise soundbank (`.bnk`) parsing, enough to tell which wem is the full song and which
+//! is the preview.
+//!
+//! A bank is a list of chunks (`<tag> <u32 length> <data>`). Rocksmith song banks carry:
+//! - `BKHD`: bank version and id.
+//! - `DIDX`: media embedded in `DATA`, by wem id (the start of the stream, prefetched).
+//! - `DATA`: the embedded media.
+//! - `HIRC`: the object hierarchy. `Sound` objects name the wem they play, `Event` objects
+//!   (`Play_<key>`, `Play_<key>_Preview`, hashed) list the actions that play the sounds.
+//!
+//! Songs ship a `song_<key>.bnk` and a `song_<key>_preview.bnk` under `audio/<platform>/`.
+
+use std::collections::BTreeMap;
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use serde::Serialize;
+
+use crate::error::{PsarcError, Result};
+use crate::psarc::{PsarcFile, PsarcTOCEntry};
+
+const HIRC_SOUND: u8 = 2;
+const HIRC_ACTION: u8 = 3;
+const HIRC_EVENT: u8 = 4;
+/// Action type of `Play`; the low byte is the scope.
+const ACTION_PLAY: u16 = 0x0400;
+
+/// Last bank version storing the source stream type and the event action count on 32 bits.
+const LEGACY_LAYOUT_VERSION: u32 = 88;
+
+/// A media index entry of the `DIDX` chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct MediaIndex {
+    pub wem_id: u32,
+    /// Offset of the media in `DATA`.
+    pub offset: u32,
+    pub length: u32,
+}
+
+/// A `Sound` object of the hierarchy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct BankSound {
+    pub id: u32,
+    /// Id of the wem played, also the stem of its file name.
+    pub source_id: u32,
+    /// Played from a `.wem` file rather than from `DATA` only.
+    pub streamed: bool,
+}
+
+/// An `Action` object of the hierarchy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct BankAction {
+    pub id: u32,
+    pub action_type: u16,
+    /// Object the action applies to.
+    pub target_id: u32,
+}
+
+/// An `Event` object of the hierarchy. Its id is the hash of its name (see `wwise_hash`).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BankEvent {
+    pub id: u32,
+    pub action_ids: Vec<u32>,
+}
+
+/// A parsed soundbank.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct SoundBank {
+    pub version: u32,
+    pub id: u32,
+    pub media: Vec<MediaIndex>,
+    /// Offset and length of the `DATA` payload within the bank.
+    pub data: Option<(usize, usize)>,
+    pub sounds: Vec<BankSound>,
+    pub actions: Vec<BankAction>,
+    pub events: Vec<BankEvent>,
+}
+
+/// Hash Wwise gives to object names: 32 bit FNV-1 of the lowercase name.
+pub fn wwise_hash(name: &str) -> u32 {
+    name.to_lowercase().bytes().fold(2166136261u32, |hash, byte| hash.wrapping_mul(16777619) ^ u32::from(byte))
+}
+
+impl SoundBank {
+    /// Parses a bank. Chunks other than `BKHD`, `DIDX`, `DATA` and `HIRC` are skipped, as
+    /// are hierarchy objects other than sounds, actions and events.
+    pub fn parse(data: &[u8]) -> Result<SoundBank> {
+        if !data.starts_with(b"BKHD") {
+            return Err(PsarcError::InvalidAsset("Not a valid bnk file".to_string()));
+        }
+        let mut bank = SoundBank::default();
+        let mut position = 0usize;
+        while position + 8 <= data.len() {
+            let tag = &data[position..position + 4];
+            let length = u32::from_le_bytes([
+                data[position + 4],
+                data[position + 5],
+                data[position + 6],
+                data[position + 7],
+            ]) as usize;
+            let start = position + 8;
+            let end = start
+                .checked_add(length)
+                .filter(|&end| end <= data.len())
+                .ok_or_else(|| {
+                    PsarcError::InvalidAsset(format!("{} chunk runs past the end of the bank", String::from_utf8_lossy(tag)))
+                })?;
+            let chunk = &data[start..end];
+            match tag {
+                b"BKHD" => {
+                    let mut reader = Cursor::new(chunk);
+                    bank.version = reader.read_u32::<LittleEndian>()?;
+                    bank.id = reader.read_u32::<LittleEndian>()?;
+                }
+                b"DIDX" => {
+                    bank.media = chunk
+                        .chunks_exact(12)
+                        .map(|index| MediaIndex {
+                            wem_id: u32::from_le_bytes([index[0], index[1], index[2], index[3]]),
+                            offset: u32::from_le_bytes([index[4], index[5], index[6], index[7]]),
+                            length: u32::from_le_bytes([index[8], index[9], index[10], index[11]]),
+                        })
+                        .collect();
+                }
+                b"DATA" => bank.data = Some((start, length)),
+                b"HIRC" => bank.read_hierarchy(chunk)?,
+                _ => {}
+            }
+            position = end;
+        }
+        Ok(bank)
+    }
+
+    fn read_hierarchy(&mut self, chunk: &[u8]) -> Result<()> {
+        let legacy = self.version <= LEGACY_LAYOUT_VERSION;
+        let mut reader = Cursor::new(chunk);
+        let count = reader.read_u32::<LittleEndian>()?;
+        for _ in 0..count {
+            let object_type = reader.read_u8()?;
+            let length = reader.read_u32::<LittleEndian>()? as usize;
+            let start = reader.position() as usize;
+            let body = chunk
+                .get(start..start.saturating_add(length))
+                .ok_or_else(|| PsarcError::InvalidAsset("HIRC object runs past the end of the chunk".to_string()))?;
+            reader.set_position((start + length) as u64);
+            // A truncated object is ignored rather than failing the whole bank.
+            let mut body = Cursor::new(body);
+            let id = match body.read_u32::<LittleEndian>() {
+                Ok(id) => id,
+                Err(_) => continue,
+            };
+            match object_type {
+                HIRC_SOUND => {
+                    if let Ok(sound) = read_sound(&mut body, id, legacy) {
+                        self.sounds.push(sound);
+                    }
+                }
+                HIRC_ACTION => {
+                    let action = (|| -> std::io::Result<BankAction> {
+                        let action_type = body.read_u16::<LittleEndian>()?;
+                        let target_id = body.read_u32::<LittleEndian>()?;
+                        Ok(BankAction { id, action_type, target_id })
+                    })();
+                    if let Ok(action) = action {
+                        self.actions.push(action);
+                    }
+                }
+                HIRC_EVENT => {
+                    let action_ids = (|| -> std::io::Result<Vec<u32>> {
+                        let count = if legacy { body.read_u32::<LittleEndian>()? } else { u32::from(body.read_u8()?) };
+                        (0..count).map(|_| body.read_u32::<LittleEndian>()).collect()
+                    })();
+                    if let Ok(action_ids) = action_ids {
+                        self.events.push(BankEvent { id, action_ids });
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// The `DATA` bytes of an embedded media.
+    pub fn media_data<'d>(&self, bank: &'d [u8], media: &MediaIndex) -> Option<&'d [u8]> {
+        let (data_start, data_length) = self.data?;
+        let start = media.offset as usize;
+        let end = start.checked_add(media.length as usize).filter(|&end| end <= data_length)?;
+        bank.get(data_start + start..data_start + end)
+    }
+
+    /// Ids of the wems the bank references, from the sounds then the media index, without
+    /// duplicates.
+    pub fn wem_ids(&self) -> Vec<u32> {
+        let mut ids: Vec<u32> = self.sounds.iter().map(|s| s.source_id).collect();
+        ids.extend(self.media.iter().map(|m| m.wem_id));
+        let mut seen = std::collections::HashSet::new();
+        ids.retain(|id| seen.insert(*id));
+        ids
+    }
+
+    /// Ids of the wems played by the event named `name` (such as `Play_<key>_Preview`)
+    /// through `Play` actions targeting a sound directly. Empty when the event is missing.
+    pub fn event_wem_ids(&self, name: &str) -> Vec<u32> {
+        let hash = wwise_hash(name);
+        let Some(event) = self.events.iter().find(|e| e.id == hash) else {
+            return Vec::new();
+        };
+        event
+            .action_ids
+            .iter()
+            .filter_map(|id| self.actions.iter().find(|a| a.id == *id))
+            .filter(|a| a.action_type & 0xFF00 == ACTION_PLAY)
+            .filter_map(|a| self.sounds.iter().find(|s| s.id == a.target_id))
+            .map(|s| s.source_id)
+            .collect()
+    }
+}
+
+/// Reads the source of a `Sound` object after its id.
+fn read_sound<R: Read>(body: &mut R, id: u32, legacy: bool) -> std::io::Result<BankSound> {
+    let _plugin_id = body.read_u32::<LittleEndian>()?;
+    let stream_type = if legacy { body.read_u32::<LittleEndian>()? } else { u32::from(body.read_u8()?) };
+    let source_id = body.read_u32::<LittleEndian>()?;
+    // Older banks repeat the id of the file holding the media; it names the wem.
+    let source_id = if legacy { body.read_u32::<LittleEndian>().unwrap_or(source_id) } else { source_id };
+    Ok(BankSound { id, source_id, streamed: stream_type != 0 })
+}
+
+/// The audio of one song: the wem entries of its full track and of its preview.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct SongAudio {
+    /// Song key as written in the bank names (`song_<key>.bnk`), lowercase.
+    pub song_key: String,
+    pub main_wem: Option<String>,
+    pub preview_wem: Option<String>,
+}
+
+/// The wem entry of the first id of `ids` the archive holds.
+fn find_wem(wems: &[(&str, &PsarcTOCEntry)], ids: &[u32]) -> Option<String> {
+    ids.iter().find_map(|id| {
+        let id = id.to_string();
+        wems.iter().find(|(stem, _)| *stem == id).and_then(|(_, entry)| entry.path.clone())
+    })
+}
+
+/// Resolves the full track and preview wems of every song bank of `psarc`. The wems played
+/// by the bank's `Play_<key>` (or `Play_<key>_Preview`) event are preferred, then any wem
+/// the bank references. Banks that do not parse are skipped with a warning.
+pub fn resolve_audio_entries(psarc: &PsarcFile) -> Result<Vec<SongAudio>> {
+    let wems: Vec<(&str, &PsarcTOCEntry)> = psarc
+        .toc
+        .entries
+        .iter()
+        .filter_map(|entry| {
+            let path = entry.path.as_deref().filter(|p| p.ends_with(".wem"))?;
+            Some((Path::new(path).file_stem()?.to_str()?, entry))
+        })
+        .collect();
+    let mut songs: BTreeMap<String, SongAudio> = BTreeMap::new();
+    for entry in &psarc.toc.entries {
+        let Some(path) = entry.path.as_deref().filter(|p| p.ends_with(".bnk")) else {
+            continue;
+        };
+        let stem = Path::new(path).file_stem().and_then(|s| s.to_str()).unwrap_or_default().to_lowercase();
+        let Some(name) = stem.strip_prefix("song_") else {
+            continue;
+        };
+        let (key, preview) = match name.strip_suffix("_preview") {
+            Some(key) => (key, true),
+            None => (name, false),
+        };
+        let bank = match SoundBank::parse(&psarc.inflate_entry_data(entry)?) {
+            Ok(bank) => bank,
+            Err(err) => {
+                tracing::warn!("Could not parse soundbank {}: {}", path, err);
+                continue;
+            }
+        };
+        let event = if preview { format!("Play_{}_Preview", key) } else { format!("Play_{}", key) };
+        let wem = find_wem(&wems, &bank.event_wem_ids(&event)).or_else(|| find_wem(&wems, &bank.wem_ids()));
+        let song = songs
+            .entry(key.to_string())
+            .or_insert_with(|| SongAudio { song_key: key.to_string(), ..SongAudio::default() });
+        if preview {
+            song.preview_wem = wem;
+        } else {
+            song.main_wem = wem;
+        }
+    }
+    Ok(songs.into_values().collect())
+}
@@ -0,0 +1,101 @@
+//! Cache of converted outputs keyed by the content of the entry they were converted from.
+//!
+//! Re-extracting an archive, or extracting another pack that ships the same asset, then
+//! reads the conversion back instead of running it again. Entries are files named after
+//! the MD5 and length of the source bytes, in one folder per conversion kind; the kind
+//! carries a version so a changed converter never serves stale outputs.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::md5::{md5, to_hex};
+use crate::user_dirs::UserDirs;
+
+/// Conversion kind of SNG arrangements to pretty-printed JSON.
+pub const SNG_JSON: &str = "sng-json-v1";
+/// Conversion kind of PCM and ADPCM wems to WAV.
+pub const WEM_WAV: &str = "wem-wav-v1";
+/// Conversion kind of Vorbis wems to Ogg Vorbis. Every rebuild strategy that succeeds
+/// writes the same stream, so the kind does not depend on the one used.
+pub const OGG: &str = "wem-ogg-v1";
+/// Conversion kind of DDS textures to PNG, of the full size first layer; other surfaces
+/// append `-m<mip level>-l<layer>`.
+pub const PNG: &str = "dds-png-v1";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConversionCache {
+    dir: PathBuf,
+}
+
+impl ConversionCache {
+    /// A cache stored in `dir`, created on the first write.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        ConversionCache { dir: dir.into() }
+    }
+
+    /// The per-user cache folder of the platform (see `user_dirs`). `None` when the
+    /// environment names no home for it.
+    pub fn user_default() -> Option<Self> {
+        UserDirs::platform().conversion_cache()
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn path(&self, kind: &str, source: &[u8]) -> PathBuf {
+        self.dir.join(kind).join(format!("{}_{}", to_hex(&md5(source)), source.len()))
+    }
+
+    /// Output of an earlier `kind` conversion of `source`, if cached.
+    pub fn get(&self, kind: &str, source: &[u8]) -> Option<Vec<u8>> {
+        fs::read(self.path(kind, source)).ok()
+    }
+
+    /// Stores the output of a `kind` conversion of `source`. The file is written next to
+    /// its final name and renamed, so concurrent readers never see a partial output.
+    pub fn put(&self, kind: &str, source: &[u8], output: &[u8]) -> io::Result<()> {
+        let path = self.path(kind, source);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(format!(".{}.tmp", std::process::id()));
+        fs::write(&temp, output)?;
+        fs::rename(&temp, &path)
+    }
+
+    /// Returns the cached output of a `kind` conversion of `source`, running `convert` and
+    /// caching its result on a miss. Failing to store the result only logs a warning.
+    pub fn get_or_convert(
+        &self,
+        kind: &str,
+        source: &[u8],
+        convert: impl FnOnce(&[u8]) -> io::Result<Vec<u8>>,
+    ) -> io::Result<Vec<u8>> {
+        if let Some(output) = self.get(kind, source) {
+            tracing::trace!("Conversion cache hit ({}, {} bytes)", kind, source.len());
+            return Ok(output);
+        }
+        let output = convert(source)?;
+        if let Err(err) = self.put(kind, source, &output) {
+            tracing::warn!("Failed to cache {} conversion in {:?}: {}", kind, self.dir, err);
+        }
+        Ok(output)
+    }
+}
+
+/// `cache.get_or_convert` when a cache is given, else a plain conversion.
+#[cfg(any(feature = "sng", feature = "audio", feature = "image"))]
+pub(crate) fn convert_cached(
+    cache: Option<&ConversionCache>,
+    kind: &str,
+    source: &[u8],
+    convert: impl FnOnce(&[u8]) -> io::Result<Vec<u8>>,
+) -> io::Result<Vec<u8>> {
+    match cache {
+        Some(cache) => cache.get_or_convert(kind, source, convert),
+        None => convert(source),
+    }
+}
@@ -0,0 +1,194 @@
+//! The packed Vorbis codebooks Wwise leaves out of its streams.
+//!
+//! Wwise Vorbis wems usually refer to their codebooks by id instead of carrying them, so
+//! rebuilding a standard Ogg stream needs the library the encoder used: a
+//! `packed_codebooks_aoTuV_603.bin` file, as shipped with ww2ogg. The file is the packed
+//! codebooks one after the other, then a table of their little-endian `u32` offsets, and
+//! ends with the offset of that table.
+//!
+//! The file is looked for, in order:
+//! 1. at the path given by the caller (`CodebookLibrary::locate(Some(path))`);
+//! 2. at the path in the `PSARC_CODEBOOKS` environment variable;
+//! 3. next to the executable, under one of `CODEBOOKS_FILE_NAMES`.
+//!
+//! Library users holding the bytes already (embedded with `include_bytes!`, downloaded)
+//! pass them to `CodebookLibrary::from_bytes` instead.
+//!
+//! A library keeps its bytes in an `Arc<[u8]>`: clones share them, so converters running
+//! on several threads hold one copy. `CodebookLibrary::load_shared` also reads a given
+//! file only once per process.
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// Environment variable naming the codebooks file.
+pub const CODEBOOKS_ENV: &str = "PSARC_CODEBOOKS";
+
+/// File names looked for next to the executable.
+pub const CODEBOOKS_FILE_NAMES: [&str; 2] = ["packed_codebooks_aoTuV_603.bin", "packed_codebooks.bin"];
+
+/// A packed codebooks file, indexed by codebook id. Cloning is cheap: the clones share
+/// the bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodebookLibrary {
+    /// The whole file, offset table included.
+    data: Arc<[u8]>,
+    /// Start of each codebook in `data`; the last offset is the end of the codebooks.
+    offsets: Arc<[usize]>,
+}
+
+/// Libraries read by `load_shared`, by path.
+static SHARED: OnceLock<Mutex<HashMap<PathBuf, CodebookLibrary>>> = OnceLock::new();
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+impl CodebookLibrary {
+    /// Parses the bytes of a packed codebooks file.
+    pub fn from_bytes(data: Vec<u8>) -> io::Result<Self> {
+        Self::from_shared(data.into())
+    }
+
+    /// Parses the bytes of a packed codebooks file held elsewhere too, without copying
+    /// them.
+    pub fn from_shared(data: Arc<[u8]>) -> io::Result<Self> {
+        let Some(tail) = data.len().checked_sub(4) else {
+            return Err(invalid("Codebooks file is too short"));
+        };
+        let table_offset = u32::from_le_bytes(data[tail..].try_into().unwrap()) as usize;
+        if table_offset > tail {
+            return Err(invalid(format!("Codebooks offset table at {} is past the end of the file", table_offset)));
+        }
+        let offsets: Arc<[usize]> = data[table_offset..]
+            .chunks_exact(4)
+            .map(|offset| u32::from_le_bytes(offset.try_into().unwrap()) as usize)
+            .collect();
+        if offsets.windows(2).any(|pair| pair[0] > pair[1]) || offsets.last().is_some_and(|&end| end > table_offset) {
+            return Err(invalid("Codebooks offset table is not ordered"));
+        }
+        Ok(CodebookLibrary { data, offsets })
+    }
+
+    /// The bytes of the file, shared with this library.
+    pub fn shared_bytes(&self) -> Arc<[u8]> {
+        Arc::clone(&self.data)
+    }
+
+    /// Reads a packed codebooks file.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let data = fs::read(path).map_err(|err| io::Error::new(err.kind(), format!("{}: {}", path.display(), err)))?;
+        Self::from_bytes(data)
+    }
+
+    /// Finds the codebooks file in the lookup order of the module documentation.
+    pub fn locate(explicit: Option<&Path>) -> io::Result<PathBuf> {
+        if let Some(explicit) = explicit {
+            if !explicit.is_file() {
+                let message = format!("Codebooks file not found: {}", explicit.display());
+                return Err(io::Error::new(io::ErrorKind::NotFound, message));
+            }
+            return Ok(explicit.to_path_buf());
+        }
+        let mut candidates: Vec<PathBuf> = env::var_os(CODEBOOKS_ENV).map(PathBuf::from).into_iter().collect();
+        if let Some(exe_dir) = env::current_exe().ok().and_then(|exe| exe.parent().map(Path::to_path_buf)) {
+            candidates.extend(CODEBOOKS_FILE_NAMES.iter().map(|name| exe_dir.join(name)));
+        }
+        candidates.into_iter().find(|path| path.is_file()).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "Vorbis codebooks not found: set {} or put {} next to the executable",
+                    CODEBOOKS_ENV, CODEBOOKS_FILE_NAMES[0]
+                ),
+            )
+        })
+    }
+
+    /// `locate` then `open`.
+    pub fn load(explicit: Option<&Path>) -> io::Result<Self> {
+        let path = Self::locate(explicit)?;
+        tracing::debug!("Loading Vorbis codebooks from {:?}", path);
+        Self::open(&path)
+    }
+
+    /// `load`, reading each file once per process: later calls for the same file return
+    /// a library sharing the bytes of the first.
+    pub fn load_shared(explicit: Option<&Path>) -> io::Result<Self> {
+        let path = Self::locate(explicit)?;
+        let shared = SHARED.get_or_init(Default::default);
+        if let Some(library) = shared.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).get(&path) {
+            return Ok(library.clone());
+        }
+        tracing::debug!("Loading Vorbis codebooks from {:?}", path);
+        let library = Self::open(&path)?;
+        let mut shared = shared.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        Ok(shared.entry(path).or_insert(library).clone())
+    }
+
+    /// Number of codebooks.
+    pub fn len(&self) -> usize {
+        self.offsets.len().saturating_sub(1)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The packed bytes of codebook `id`.
+    pub fn codebook(&self, id: usize) -> io::Result<&[u8]> {
+        if id >= self.len() {
+            return Err(invalid(format!("Codebook {} out of range ({} codebooks)", id, self.len())));
+        }
+        Ok(&self.data[self.offsets[id]..self.offsets[id + 1]])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two codebooks, `[1, 2, 3]` and `[4, 5]`, then their offsets and the offset of the
+    /// table, which is also the end of the last codebook.
+    fn packed() -> Vec<u8> {
+        let mut data = vec![1, 2, 3, 4, 5];
+        for offset in [0u32, 3, 5] {
+            data.extend_from_slice(&offset.to_le_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn codebooks_by_id() {
+        let library = CodebookLibrary::from_bytes(packed()).unwrap();
+        assert_eq!(library.len(), 2);
+        assert_eq!(library.codebook(0).unwrap(), [1, 2, 3]);
+        assert_eq!(library.codebook(1).unwrap(), [4, 5]);
+        assert!(library.codebook(2).is_err());
+        assert!(CodebookLibrary::from_bytes(vec![1, 2]).is_err());
+    }
+
+    #[test]
+    fn clones_share_bytes() {
+        let bytes: Arc<[u8]> = packed().into();
+        let library = CodebookLibrary::from_shared(Arc::clone(&bytes)).unwrap();
+        assert!(Arc::ptr_eq(&library.shared_bytes(), &bytes));
+        assert!(Arc::ptr_eq(&library.clone().shared_bytes(), &bytes));
+    }
+
+    #[test]
+    fn load_shared_reads_once() {
+        let path = env::temp_dir().join(format!("psarc_codebooks_{}.bin", std::process::id()));
+        fs::write(&path, packed()).unwrap();
+        let first = CodebookLibrary::load_shared(Some(&path)).unwrap();
+        fs::remove_file(&path).unwrap();
+        fs::write(&path, b"not a codebooks file").unwrap();
+        let second = CodebookLibrary::load_shared(Some(&path)).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(Arc::ptr_eq(&first.shared_bytes(), &second.shared_bytes()));
+    }
+}
@@ -0,0 +1,149 @@
+use serde::Serialize;
+
+use crate::layout::PackageLayouts;
+
+/// Coarse classification of an entry's payload.
+///
+/// The type is determined from the leading magic bytes of the inflated entry rather than
+/// from its path, so assets stored outside the usual folders (lyric fonts, UI atlases in
+/// static archives, ...) are still recognised.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum ContentType {
+    /// DirectDraw Surface texture (`DDS `).
+    Dds,
+    /// Encrypted Rocksmith SNG arrangement (little-endian identifier 0x4A).
+    Sng,
+    /// Wwise soundbank (`BKHD`).
+    Bnk,
+    /// Wwise RIFF/RIFX audio stream.
+    Wem,
+    /// Scaleform GFx movie (`GFX`/`CFX`) or plain SWF (`FWS`/`CWS`).
+    Gfx,
+    /// JSON document (manifests, HSAN files).
+    Json,
+    /// XML document (xblocks, showlights, arrangement XML).
+    Xml,
+    /// Any other valid UTF-8 text (NamesBlock, aggregate graphs, ...).
+    Text,
+    /// Binary data with no recognised signature.
+    Unknown,
+}
+
+impl ContentType {
+    /// Detects the content type from the first bytes of an inflated entry.
+    pub fn sniff(data: &[u8]) -> Self {
+        if data.starts_with(b"DDS ") {
+            return ContentType::Dds;
+        }
+        if data.starts_with(b"BKHD") {
+            return ContentType::Bnk;
+        }
+        if data.starts_with(b"RIFF") || data.starts_with(b"RIFX") {
+            return ContentType::Wem;
+        }
+        if [b"GFX", b"CFX", b"FWS", b"CWS"].iter().any(|magic| data.starts_with(*magic)) {
+            return ContentType::Gfx;
+        }
+        if data.len() >= 4 && u32::from_le_bytes([data[0], data[1], data[2], data[3]]) == 0x4A {
+            return ContentType::Sng;
+        }
+
+        let text = match std::str::from_utf8(data) {
+            Ok(text) => text,
+            Err(_) => return ContentType::Unknown,
+        };
+        match text.trim_start_matches('\u{feff}').trim_start().chars().next() {
+            Some('{') | Some('[') => ContentType::Json,
+            Some('<') => ContentType::Xml,
+            _ => ContentType::Text,
+        }
+    }
+
+    /// Returns the conventional file extension for this content type, without the dot.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ContentType::Dds => "dds",
+            ContentType::Sng => "sng",
+            ContentType::Bnk => "bnk",
+            ContentType::Wem => "wem",
+            ContentType::Gfx => "gfx",
+            ContentType::Json => "json",
+            ContentType::Xml => "xml",
+            ContentType::Text => "txt",
+            ContentType::Unknown => "bin",
+        }
+    }
+}
+
+/// What an entry is for, in the terms a player would use: the classes behind
+/// `ExtractOptions::only`.
+///
+/// Derived from the sniffed `ContentType`, with the entry path used only to tell apart
+/// payloads sharing a format (the vocals SNG from instrument charts, lyric font textures
+/// from album art).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum AssetClass {
+    /// Wwise audio streams and soundbanks.
+    Audio,
+    /// Album art and other textures or UI movies.
+    Art,
+    /// Instrument arrangements (SNG and arrangement XML).
+    Charts,
+    /// Manifests, HSAN files, xblocks and aggregate graphs.
+    Manifests,
+    /// The vocals arrangement and lyric font textures.
+    Lyrics,
+}
+
+impl AssetClass {
+    pub const ALL: [AssetClass; 5] = [
+        AssetClass::Audio,
+        AssetClass::Art,
+        AssetClass::Charts,
+        AssetClass::Manifests,
+        AssetClass::Lyrics,
+    ];
+
+    /// Classifies an entry from its sniffed content type and path. Returns `None` for
+    /// entries outside every class, such as the NamesBlock.
+    pub fn of(content_type: ContentType, path: &str) -> Option<Self> {
+        let path = path.to_lowercase();
+        let lyrics = path.contains("vocals") || path.contains("lyric");
+        match content_type {
+            ContentType::Wem | ContentType::Bnk => Some(AssetClass::Audio),
+            ContentType::Dds if lyrics => Some(AssetClass::Lyrics),
+            ContentType::Dds | ContentType::Gfx => Some(AssetClass::Art),
+            ContentType::Sng | ContentType::Xml if lyrics => Some(AssetClass::Lyrics),
+            ContentType::Sng => Some(AssetClass::Charts),
+            ContentType::Xml if PackageLayouts::current().is_xml_chart(&path) => Some(AssetClass::Charts),
+            ContentType::Xml | ContentType::Json => Some(AssetClass::Manifests),
+            ContentType::Text if path.ends_with(".nt") => Some(AssetClass::Manifests),
+            ContentType::Text | ContentType::Unknown => None,
+        }
+    }
+
+    /// The name used on the command line.
+    pub fn name(&self) -> &'static str {
+        match self {
+            AssetClass::Audio => "audio",
+            AssetClass::Art => "art",
+            AssetClass::Charts => "charts",
+            AssetClass::Manifests => "manifests",
+            AssetClass::Lyrics => "lyrics",
+        }
+    }
+}
+
+impl std::str::FromStr for AssetClass {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        AssetClass::ALL
+            .into_iter()
+            .find(|class| class.name().eq_ignore_ascii_case(name.trim()))
+            .ok_or_else(|| {
+                let names: Vec<&str> = AssetClass::ALL.iter().map(|c| c.name()).collect();
+                format!("Unknown asset class {:?}, expected one of {}", name, names.join(", "))
+            })
+    }
+}
@@ -0,0 +1,680 @@
+use std::io::{self, Read};
+use flate2::read::ZlibDecoder;
+
+/// An 8-bit RGBA image, row-major without padding.
+#[derive(Debug, Clone)]
+pub struct RgbaImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl RgbaImage {
+    /// Colour of the pixel at `(x, y)`, transparent black outside the pixel buffer.
+    fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let at = (y as usize)
+            .checked_mul(self.width as usize)
+            .and_then(|i| i.checked_add(x as usize))
+            .and_then(|i| i.checked_mul(4));
+        match at.and_then(|i| self.pixels.get(i..)).and_then(|px| px.get(..4)) {
+            Some(px) => [px[0], px[1], px[2], px[3]],
+            None => [0; 4],
+        }
+    }
+
+    /// Scales the image to `width` x `height`, averaging the source pixels covered by each
+    /// destination pixel. Fails with `ErrorKind::InvalidInput` when the result would not
+    /// fit in memory.
+    pub fn resize(&self, width: u32, height: u32) -> io::Result<RgbaImage> {
+        let length = rgba_length(width, height)?;
+        if self.width == 0 || self.height == 0 {
+            return Ok(RgbaImage { width, height, pixels: vec![0u8; length] });
+        }
+        let mut pixels = Vec::with_capacity(length);
+        // Source coordinates in u64, since `(y + 1) * self.height` may not fit in a u32.
+        let scale = |i: u32, from: u32, to: u32| (i as u64 * from as u64 / to as u64) as u32;
+        for y in 0..height {
+            let y0 = scale(y, self.height, height);
+            let y1 = scale(y + 1, self.height, height).max(y0 + 1);
+            for x in 0..width {
+                let x0 = scale(x, self.width, width);
+                let x1 = scale(x + 1, self.width, width).max(x0 + 1);
+                let mut sum = [0u64; 4];
+                for sy in y0..y1 {
+                    for sx in x0..x1 {
+                        for (s, c) in sum.iter_mut().zip(self.pixel(sx, sy)) {
+                            *s += c as u64;
+                        }
+                    }
+                }
+                let count = (y1 - y0) as u64 * (x1 - x0) as u64;
+                pixels.extend(sum.iter().map(|s| (s / count) as u8));
+            }
+        }
+        Ok(RgbaImage { width, height, pixels })
+    }
+}
+
+/// Bytes of a `width` x `height` RGBA pixel buffer.
+fn rgba_length(width: u32, height: u32) -> io::Result<usize> {
+    (width as usize)
+        .checked_mul(height as usize)
+        .and_then(|pixels| pixels.checked_mul(4))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("{}x{} image is too large", width, height)))
+}
+
+/// Decodes a PNG file into RGBA pixels.
+///
+/// Covers what cover art exports use: 8-bit grayscale, RGB, palette, grayscale+alpha and
+/// RGBA images without interlacing.
+pub fn decode_png(data: &[u8]) -> io::Result<RgbaImage> {
+    const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    if data.get(..8) != Some(&SIGNATURE[..]) {
+        return Err(invalid("Not a PNG file"));
+    }
+
+    let (mut width, mut height, mut color_type) = (0u32, 0u32, 0u8);
+    let mut palette: &[u8] = &[];
+    let mut compressed = Vec::new();
+    let mut pos = 8;
+    while pos + 8 <= data.len() {
+        let length = u32::from_be_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]) as usize;
+        let kind = &data[pos + 4..pos + 8];
+        let body = data
+            .get(pos + 8..pos + 8 + length)
+            .ok_or_else(|| invalid("Truncated PNG chunk"))?;
+        match kind {
+            b"IHDR" => {
+                if body.len() < 13 {
+                    return Err(invalid("Invalid IHDR chunk"));
+                }
+                width = u32::from_be_bytes([body[0], body[1], body[2], body[3]]);
+                height = u32::from_be_bytes([body[4], body[5], body[6], body[7]]);
+                let bit_depth = body[8];
+                color_type = body[9];
+                let interlace = body[12];
+                if bit_depth != 8 || interlace != 0 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Unsupported,
+                        "Only 8-bit, non-interlaced PNG images are supported",
+                    ));
+                }
+            }
+            b"PLTE" => palette = body,
+            b"IDAT" => compressed.extend_from_slice(body),
+            b"IEND" => break,
+            _ => {}
+        }
+        // Skip length, type, body and CRC.
+        pos += 12 + length;
+    }
+
+    let channels = match color_type {
+        0 | 3 => 1,
+        4 => 2,
+        2 => 3,
+        6 => 4,
+        _ => return Err(invalid("Unknown PNG colour type")),
+    };
+    if width == 0 || height == 0 {
+        return Err(invalid("Missing PNG image header"));
+    }
+    let stride = width as usize * channels;
+    let mut raw = Vec::new();
+    ZlibDecoder::new(&compressed[..]).read_to_end(&mut raw)?;
+    if (stride + 1).checked_mul(height as usize).is_none_or(|needed| raw.len() < needed) {
+        return Err(invalid("PNG image data too short"));
+    }
+
+    let mut previous = vec![0u8; stride];
+    let mut pixels = Vec::with_capacity(width as usize * height as usize * 4);
+    for row in raw.chunks_exact(stride + 1).take(height as usize) {
+        let filter = row[0];
+        let mut line = row[1..].to_vec();
+        for i in 0..stride {
+            let left = if i >= channels { line[i - channels] } else { 0 };
+            let up = previous[i];
+            let up_left = if i >= channels { previous[i - channels] } else { 0 };
+            let predictor = match filter {
+                0 => 0,
+                1 => left,
+                2 => up,
+                3 => ((left as u16 + up as u16) / 2) as u8,
+                4 => paeth(left, up, up_left),
+                _ => return Err(invalid("Unknown PNG filter")),
+            };
+            line[i] = line[i].wrapping_add(predictor);
+        }
+        for px in line.chunks_exact(channels) {
+            let rgba = match color_type {
+                0 => [px[0], px[0], px[0], 255],
+                4 => [px[0], px[0], px[0], px[1]],
+                2 => [px[0], px[1], px[2], 255],
+                3 => {
+                    let i = px[0] as usize * 3;
+                    let color = palette.get(i..i + 3).ok_or_else(|| invalid("PNG palette index out of range"))?;
+                    [color[0], color[1], color[2], 255]
+                }
+                _ => [px[0], px[1], px[2], px[3]],
+            };
+            pixels.extend_from_slice(&rgba);
+        }
+        previous = line;
+    }
+    Ok(RgbaImage { width, height, pixels })
+}
+
+/// The PNG Paeth predictor of a byte from its left (`a`), upper (`b`) and upper left (`c`)
+/// neighbours.
+pub(crate) fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+fn to_rgb565(c: [u8; 4]) -> u16 {
+    ((c[0] as u16 >> 3) << 11) | ((c[1] as u16 >> 2) << 5) | (c[2] as u16 >> 3)
+}
+
+fn from_rgb565(c: u16) -> [i32; 3] {
+    let r = ((c >> 11) & 0x1F) as i32;
+    let g = ((c >> 5) & 0x3F) as i32;
+    let b = (c & 0x1F) as i32;
+    [(r << 3) | (r >> 2), (g << 2) | (g >> 4), (b << 3) | (b >> 2)]
+}
+
+/// Encodes one 4x4 block in BC1 four-colour mode, using the bounding box of the block's
+/// colours as endpoints.
+fn encode_bc1_block(block: &[[u8; 4]; 16]) -> [u8; 8] {
+    let mut min = [255u8; 3];
+    let mut max = [0u8; 3];
+    for px in block {
+        for c in 0..3 {
+            min[c] = min[c].min(px[c]);
+            max[c] = max[c].max(px[c]);
+        }
+    }
+    let mut c0 = to_rgb565([max[0], max[1], max[2], 255]);
+    let mut c1 = to_rgb565([min[0], min[1], min[2], 255]);
+    if c0 < c1 {
+        std::mem::swap(&mut c0, &mut c1);
+    }
+    let mut indices = 0u32;
+    if c0 != c1 {
+        let (e0, e1) = (from_rgb565(c0), from_rgb565(c1));
+        let palette = [
+            e0,
+            e1,
+            [(2 * e0[0] + e1[0]) / 3, (2 * e0[1] + e1[1]) / 3, (2 * e0[2] + e1[2]) / 3],
+            [(e0[0] + 2 * e1[0]) / 3, (e0[1] + 2 * e1[1]) / 3, (e0[2] + 2 * e1[2]) / 3],
+        ];
+        for (i, px) in block.iter().enumerate() {
+            let distance = |p: &[i32; 3]| (0..3).map(|c| (p[c] - px[c] as i32).pow(2)).sum::<i32>();
+            let best = (0..4).min_by_key(|&k| distance(&palette[k])).unwrap_or(0) as u32;
+            indices |= best << (i * 2);
+        }
+    }
+    let mut out = [0u8; 8];
+    out[..2].copy_from_slice(&c0.to_le_bytes());
+    out[2..4].copy_from_slice(&c1.to_le_bytes());
+    out[4..].copy_from_slice(&indices.to_le_bytes());
+    out
+}
+
+/// Encodes the alpha of one 4x4 block as a BC3 alpha block in eight-value mode, with the
+/// block's extreme alphas as endpoints.
+fn encode_alpha_block(block: &[[u8; 4]; 16]) -> [u8; 8] {
+    let max = block.iter().map(|px| px[3]).max().unwrap_or(255);
+    let min = block.iter().map(|px| px[3]).min().unwrap_or(255);
+    let mut indices = 0u64;
+    if max != min {
+        let (a0, a1) = (max as u32, min as u32);
+        let mut palette = [a0, a1, 0, 0, 0, 0, 0, 0];
+        for i in 1..7 {
+            palette[i + 1] = ((7 - i as u32) * a0 + i as u32 * a1) / 7;
+        }
+        for (i, px) in block.iter().enumerate() {
+            let best = (0..8).min_by_key(|&k| palette[k].abs_diff(px[3] as u32)).unwrap_or(0) as u64;
+            indices |= best << (i * 3);
+        }
+    }
+    let mut out = [0u8; 8];
+    out[0] = max;
+    out[1] = min;
+    out[2..].copy_from_slice(&indices.to_le_bytes()[..6]);
+    out
+}
+
+/// Encodes an image as a single-level BC1 (DXT1) DDS texture, the format Rocksmith uses
+/// for album art.
+pub fn encode_dds_bc1(image: &RgbaImage) -> Vec<u8> {
+    encode_dds_blocks(image, b"DXT1", 8, |block, dds| dds.extend_from_slice(&encode_bc1_block(block)))
+}
+
+/// Encodes an image as a single-level BC3 (DXT5) DDS texture, which keeps the alpha
+/// channel BC1 drops.
+pub fn encode_dds_bc3(image: &RgbaImage) -> Vec<u8> {
+    encode_dds_blocks(image, b"DXT5", 16, |block, dds| {
+        dds.extend_from_slice(&encode_alpha_block(block));
+        dds.extend_from_slice(&encode_bc1_block(block));
+    })
+}
+
+/// Encodes an image with `encode_dds_bc3` when it has pixels that are not opaque, and with
+/// `encode_dds_bc1` otherwise.
+pub fn encode_dds(image: &RgbaImage) -> Vec<u8> {
+    if image.pixels.chunks_exact(4).any(|px| px[3] < 255) {
+        encode_dds_bc3(image)
+    } else {
+        encode_dds_bc1(image)
+    }
+}
+
+/// Writes the DDS header of a single-level `four_cc` texture, then every 4x4 block of
+/// `image` (`block_bytes` each) with `encode_block`.
+fn encode_dds_blocks(
+    image: &RgbaImage,
+    four_cc: &[u8; 4],
+    block_bytes: u32,
+    encode_block: impl Fn(&[[u8; 4]; 16], &mut Vec<u8>),
+) -> Vec<u8> {
+    let blocks_x = image.width.div_ceil(4).max(1);
+    let blocks_y = image.height.div_ceil(4).max(1);
+    let linear_size = blocks_x.saturating_mul(blocks_y).saturating_mul(block_bytes);
+
+    let mut dds = Vec::with_capacity(128 + linear_size as usize);
+    dds.extend_from_slice(b"DDS ");
+    let header: [u32; 18] = [
+        124,
+        // CAPS | HEIGHT | WIDTH | PIXELFORMAT | LINEARSIZE
+        0x1 | 0x2 | 0x4 | 0x1000 | 0x80000,
+        image.height,
+        image.width,
+        linear_size,
+        0,
+        1,
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    ];
+    for value in header {
+        dds.extend_from_slice(&value.to_le_bytes());
+    }
+    // Pixel format: size, FOURCC flag, the FourCC, unused bit count and masks.
+    for value in [32u32, 0x4] {
+        dds.extend_from_slice(&value.to_le_bytes());
+    }
+    dds.extend_from_slice(four_cc);
+    dds.extend_from_slice(&[0u8; 20]);
+    // Caps (TEXTURE), caps2-4 and the trailing reserved field.
+    dds.extend_from_slice(&0x1000u32.to_le_bytes());
+    dds.extend_from_slice(&[0u8; 16]);
+
+    if image.width == 0 || image.height == 0 {
+        return dds;
+    }
+    for by in 0..blocks_y {
+        for bx in 0..blocks_x {
+            let mut block = [[0u8; 4]; 16];
+            for (i, px) in block.iter_mut().enumerate() {
+                // Edge blocks repeat the last row/column.
+                let x = (bx * 4 + i as u32 % 4).min(image.width - 1);
+                let y = (by * 4 + i as u32 / 4).min(image.height - 1);
+                *px = image.pixel(x, y);
+            }
+            encode_block(&block, &mut dds);
+        }
+    }
+    dds
+}
+
+/// Pixel formats `decode_dds` reads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DdsFormat {
+    /// BC1 (`DXT1`): colour with optional 1-bit alpha, 8 bytes per 4x4 block.
+    Bc1,
+    /// BC2 (`DXT3`): colour with explicit 4-bit alpha.
+    Bc2,
+    /// BC3 (`DXT5`): colour with interpolated alpha.
+    Bc3,
+    /// BC4 (`ATI1`): one channel, decoded as gray.
+    Bc4,
+    /// BC5 (`ATI2`): two channels, decoded as red and green.
+    Bc5,
+    /// Uncompressed pixels of `bits` bits, each channel picked by its mask (a zero alpha
+    /// mask means opaque).
+    Uncompressed { bits: u32, masks: [u32; 4] },
+}
+
+impl DdsFormat {
+    /// Bytes of one 4x4 block of the block-compressed formats.
+    fn block_bytes(self) -> Option<usize> {
+        match self {
+            DdsFormat::Bc1 | DdsFormat::Bc4 => Some(8),
+            DdsFormat::Bc2 | DdsFormat::Bc3 | DdsFormat::Bc5 => Some(16),
+            DdsFormat::Uncompressed { .. } => None,
+        }
+    }
+
+    /// Stored size of a `width` x `height` surface, `None` when it does not fit in a usize.
+    fn surface_size(self, width: u32, height: u32) -> Option<usize> {
+        match (self, self.block_bytes()) {
+            (DdsFormat::Uncompressed { bits, .. }, _) => {
+                (width as usize).checked_mul(height as usize)?.checked_mul(bits as usize / 8)
+            }
+            (_, block) => (width.div_ceil(4).max(1) as usize)
+                .checked_mul(height.div_ceil(4).max(1) as usize)?
+                .checked_mul(block.unwrap_or(8)),
+        }
+    }
+}
+
+/// The header fields of a DDS texture that locate its surfaces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DdsInfo {
+    pub width: u32,
+    pub height: u32,
+    /// Mip levels per layer, 1 when the texture has no mipmaps.
+    pub mip_count: u32,
+    /// Array layers, counting the six faces of each cube map.
+    pub layers: u32,
+    pub format: DdsFormat,
+    /// Offset of the first surface.
+    pub data_offset: usize,
+}
+
+/// Size of the `DDS ` magic and the header.
+const DDS_HEADER_SIZE: usize = 128;
+/// Size of the `DX10` extension header.
+const DX10_HEADER_SIZE: usize = 20;
+/// Most mip levels a texture can have: one per bit of its 32-bit dimensions.
+const MAX_MIP_COUNT: u32 = 32;
+
+impl DdsInfo {
+    /// Reads the header of a DDS file. Formats other than those of `DdsFormat` (BC6H, BC7,
+    /// floating point) are rejected with `ErrorKind::Unsupported`.
+    pub fn parse(data: &[u8]) -> io::Result<Self> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+        if !data.starts_with(b"DDS ") || data.len() < DDS_HEADER_SIZE {
+            return Err(invalid("Not a DDS file"));
+        }
+        let u32_at = |offset: usize| u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+        let height = u32_at(12);
+        let width = u32_at(16);
+        let mip_count = u32_at(28).max(1);
+        if mip_count > MAX_MIP_COUNT {
+            return Err(invalid(&format!("DDS claims {} mip levels", mip_count)));
+        }
+        let pixel_flags = u32_at(80);
+        let four_cc = &data[84..88];
+        let caps2 = u32_at(112);
+        let unsupported = |what: String| io::Error::new(io::ErrorKind::Unsupported, what);
+        let mut layers = if caps2 & 0x200 != 0 { 6 } else { 1 };
+        let mut data_offset = DDS_HEADER_SIZE;
+        let format = if pixel_flags & 0x4 == 0 {
+            let bits = u32_at(88);
+            if !matches!(bits, 8 | 16 | 24 | 32) {
+                return Err(unsupported(format!("Unsupported DDS pixel size: {} bits", bits)));
+            }
+            let alpha = if pixel_flags & 0x1 != 0 { u32_at(104) } else { 0 };
+            // Luminance textures only carry the red mask: spread it over the three colours.
+            let [r, g, b] = if pixel_flags & 0x20000 != 0 { [u32_at(92); 3] } else { [u32_at(92), u32_at(96), u32_at(100)] };
+            DdsFormat::Uncompressed { bits, masks: [r, g, b, alpha] }
+        } else {
+            match four_cc {
+                b"DXT1" => DdsFormat::Bc1,
+                b"DXT2" | b"DXT3" => DdsFormat::Bc2,
+                b"DXT4" | b"DXT5" => DdsFormat::Bc3,
+                b"ATI1" | b"BC4U" => DdsFormat::Bc4,
+                b"ATI2" | b"BC5U" => DdsFormat::Bc5,
+                b"DX10" => {
+                    if data.len() < DDS_HEADER_SIZE + DX10_HEADER_SIZE {
+                        return Err(invalid("DDS file ends in its DX10 header"));
+                    }
+                    data_offset += DX10_HEADER_SIZE;
+                    let cube = u32_at(136) & 0x4 != 0;
+                    layers = u32_at(140)
+                        .max(1)
+                        .checked_mul(if cube { 6 } else { 1 })
+                        .ok_or_else(|| invalid("DDS array size overflows"))?;
+                    const RGBA: [u32; 4] = [0xFF, 0xFF00, 0xFF_0000, 0xFF00_0000];
+                    const BGRA: [u32; 4] = [0xFF_0000, 0xFF00, 0xFF, 0xFF00_0000];
+                    match u32_at(128) {
+                        70..=72 => DdsFormat::Bc1,
+                        73..=75 => DdsFormat::Bc2,
+                        76..=78 => DdsFormat::Bc3,
+                        79..=81 => DdsFormat::Bc4,
+                        82..=84 => DdsFormat::Bc5,
+                        27..=29 => DdsFormat::Uncompressed { bits: 32, masks: RGBA },
+                        87 | 90 | 91 => DdsFormat::Uncompressed { bits: 32, masks: BGRA },
+                        88 | 92 | 93 => DdsFormat::Uncompressed { bits: 32, masks: [BGRA[0], BGRA[1], BGRA[2], 0] },
+                        other => return Err(unsupported(format!("Unsupported DXGI format {} in DDS", other))),
+                    }
+                }
+                other => {
+                    return Err(unsupported(format!("Unsupported DDS format {:?}", String::from_utf8_lossy(other))));
+                }
+            }
+        };
+        Ok(DdsInfo { width, height, mip_count, layers, format, data_offset })
+    }
+
+    /// Width and height of mip level `mip`; levels past the 32nd are 1 x 1.
+    pub fn mip_size(&self, mip: u32) -> (u32, u32) {
+        let level = |size: u32| size.checked_shr(mip).unwrap_or(0).max(1);
+        (level(self.width), level(self.height))
+    }
+
+    /// Offset and size of the surface of mip level `mip` of array layer `layer`, `None`
+    /// when they overflow. Layers are stored one after the other, each with its whole mip
+    /// chain.
+    fn surface(&self, mip: u32, layer: u32) -> Option<(usize, usize)> {
+        let chain = |mips: u32| {
+            (0..mips)
+                .map(|m| self.mip_size(m))
+                .try_fold(0usize, |total, (w, h)| total.checked_add(self.format.surface_size(w, h)?))
+        };
+        let offset = chain(self.mip_count)?
+            .checked_mul(layer as usize)?
+            .checked_add(chain(mip)?)?
+            .checked_add(self.data_offset)?;
+        let (width, height) = self.mip_size(mip);
+        Some((offset, self.format.surface_size(width, height)?))
+    }
+}
+
+/// Expands a 5:6:5 colour to 8 bits per channel.
+fn expand_rgb565(c: u16) -> [u8; 3] {
+    let (r, g, b) = ((c >> 11) & 0x1F, (c >> 5) & 0x3F, c & 0x1F);
+    [((r << 3) | (r >> 2)) as u8, ((g << 2) | (g >> 4)) as u8, ((b << 3) | (b >> 2)) as u8]
+}
+
+/// Decodes the colour half of a BC1/BC2/BC3 block into `out`. BC2 and BC3 always use the
+/// four-colour mode.
+fn decode_color_block(block: &[u8], four_colors: bool, out: &mut [[u8; 4]; 16]) {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let (a, b) = (expand_rgb565(c0), expand_rgb565(c1));
+    let mix = |wa: u16, wb: u16, total: u16| -> [u8; 4] {
+        let channel = |i: usize| ((a[i] as u16 * wa + b[i] as u16 * wb) / total) as u8;
+        [channel(0), channel(1), channel(2), 255]
+    };
+    let palette = if four_colors || c0 > c1 {
+        [[a[0], a[1], a[2], 255], [b[0], b[1], b[2], 255], mix(2, 1, 3), mix(1, 2, 3)]
+    } else {
+        [[a[0], a[1], a[2], 255], [b[0], b[1], b[2], 255], mix(1, 1, 2), [0, 0, 0, 0]]
+    };
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+    for (i, px) in out.iter_mut().enumerate() {
+        *px = palette[(indices >> (i * 2)) as usize & 0x3];
+    }
+}
+
+/// Decodes a BC3/BC4 style interpolated channel block into one value per pixel.
+fn decode_channel_block(block: &[u8]) -> [u8; 16] {
+    let (a0, a1) = (block[0] as u32, block[1] as u32);
+    let mut palette = [0u8; 8];
+    palette[0] = a0 as u8;
+    palette[1] = a1 as u8;
+    if a0 > a1 {
+        for i in 1..7 {
+            palette[i + 1] = (((7 - i as u32) * a0 + i as u32 * a1) / 7) as u8;
+        }
+    } else {
+        for i in 1..5 {
+            palette[i + 1] = (((5 - i as u32) * a0 + i as u32 * a1) / 5) as u8;
+        }
+        palette[6] = 0;
+        palette[7] = 255;
+    }
+    let mut bits = [0u8; 8];
+    bits[..6].copy_from_slice(&block[2..8]);
+    let indices = u64::from_le_bytes(bits);
+    let mut out = [0u8; 16];
+    for (i, value) in out.iter_mut().enumerate() {
+        *value = palette[(indices >> (i * 3)) as usize & 0x7];
+    }
+    out
+}
+
+/// Decodes one 4x4 block of a block-compressed format.
+fn decode_block(format: DdsFormat, block: &[u8]) -> [[u8; 4]; 16] {
+    let mut out = [[0u8; 4]; 16];
+    match format {
+        DdsFormat::Bc1 => decode_color_block(block, false, &mut out),
+        DdsFormat::Bc2 => {
+            decode_color_block(&block[8..], true, &mut out);
+            for (i, px) in out.iter_mut().enumerate() {
+                let alpha = (block[i / 2] >> ((i % 2) * 4)) & 0xF;
+                px[3] = alpha * 17;
+            }
+        }
+        DdsFormat::Bc3 => {
+            decode_color_block(&block[8..], true, &mut out);
+            for (px, alpha) in out.iter_mut().zip(decode_channel_block(block)) {
+                px[3] = alpha;
+            }
+        }
+        DdsFormat::Bc4 => {
+            for (px, value) in out.iter_mut().zip(decode_channel_block(block)) {
+                *px = [value, value, value, 255];
+            }
+        }
+        DdsFormat::Bc5 => {
+            let (red, green) = (decode_channel_block(block), decode_channel_block(&block[8..]));
+            for (i, px) in out.iter_mut().enumerate() {
+                *px = [red[i], green[i], 0, 255];
+            }
+        }
+        DdsFormat::Uncompressed { .. } => {}
+    }
+    out
+}
+
+/// Picks the channel of `mask` out of `value`, scaled to 8 bits.
+fn mask_channel(value: u32, mask: u32) -> u8 {
+    if mask == 0 {
+        return 0;
+    }
+    let shifted = (value & mask) >> mask.trailing_zeros();
+    let max = mask >> mask.trailing_zeros();
+    ((shifted as u64 * 255 + max as u64 / 2) / max as u64) as u8
+}
+
+/// Decodes mip level `mip` of array layer `layer` (a cube face counts as a layer) of a DDS
+/// texture into RGBA pixels. Levels and layers out of range are rejected with
+/// `ErrorKind::InvalidInput`.
+pub fn decode_dds(data: &[u8], mip: u32, layer: u32) -> io::Result<RgbaImage> {
+    let info = DdsInfo::parse(data)?;
+    if mip >= info.mip_count || layer >= info.layers {
+        let message = format!(
+            "DDS has {} mip levels and {} layers, mip {} of layer {} was asked for",
+            info.mip_count, info.layers, mip, layer
+        );
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+    }
+    // The surface is checked against the data before any pixel buffer is allocated, so a
+    // header claiming a huge texture fails instead of allocating for it.
+    let (offset, size) = info.surface(mip, layer).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("DDS surface of mip {} of layer {} overflows", mip, layer))
+    })?;
+    let surface = offset.checked_add(size).and_then(|end| data.get(offset..end)).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("DDS ends before mip {} of layer {}", mip, layer))
+    })?;
+    let (width, height) = info.mip_size(mip);
+    let mut pixels = vec![0u8; rgba_length(width, height)?];
+    match info.format {
+        DdsFormat::Uncompressed { bits, masks } => {
+            let bytes = bits as usize / 8;
+            for (px, stored) in pixels.chunks_exact_mut(4).zip(surface.chunks_exact(bytes)) {
+                let mut value = [0u8; 4];
+                value[..bytes].copy_from_slice(stored);
+                let value = u32::from_le_bytes(value);
+                for (channel, mask) in px.iter_mut().zip(masks) {
+                    *channel = mask_channel(value, mask);
+                }
+                if masks[3] == 0 {
+                    px[3] = 255;
+                }
+            }
+        }
+        format => {
+            let block_bytes = format.block_bytes().unwrap_or(8);
+            let blocks_x = width.div_ceil(4).max(1) as usize;
+            for (index, block) in surface.chunks_exact(block_bytes).enumerate() {
+                let (bx, by) = (index % blocks_x * 4, index / blocks_x * 4);
+                for (i, px) in decode_block(format, block).iter().enumerate() {
+                    let (x, y) = (bx + i % 4, by + i / 4);
+                    if x < width as usize && y < height as usize {
+                        let at = (y * width as usize + x) * 4;
+                        pixels[at..at + 4].copy_from_slice(px);
+                    }
+                }
+            }
+        }
+    }
+    Ok(RgbaImage { width, height, pixels })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An 8x8 gradient whose alpha falls from opaque to transparent across the rows.
+    fn translucent() -> RgbaImage {
+        let pixels = (0..64u32).flat_map(|i| [(i * 4) as u8, 128, 255 - (i * 4) as u8, 255 - (i / 8 * 36) as u8]).collect();
+        RgbaImage { width: 8, height: 8, pixels }
+    }
+
+    #[test]
+    fn encode_dds_keeps_alpha() {
+        let image = translucent();
+        let dds = encode_dds(&image);
+        assert_eq!(&dds[84..88], b"DXT5");
+        assert_eq!(dds.len(), 128 + 4 * 16);
+        let decoded = decode_dds(&dds, 0, 0).unwrap();
+        for (decoded, original) in decoded.pixels.chunks_exact(4).zip(image.pixels.chunks_exact(4)) {
+            assert!(decoded[3].abs_diff(original[3]) <= 8, "alpha {} for {}", decoded[3], original[3]);
+        }
+    }
+
+    #[test]
+    fn encode_dds_uses_bc1_for_opaque_images() {
+        let mut image = translucent();
+        image.pixels.chunks_exact_mut(4).for_each(|px| px[3] = 255);
+        let dds = encode_dds(&image);
+        assert_eq!(&dds[84..88], b"DXT1");
+        assert!(decode_dds(&dds, 0, 0).unwrap().pixels.chunks_exact(4).all(|px| px[3] == 255));
+    }
+
+    #[test]
+    fn uniform_alpha_block() {
+        let block = [[10, 20, 30, 77]; 16];
+        assert_eq!(encode_alpha_block(&block), [77, 77, 0, 0, 0, 0, 0, 0]);
+    }
+}
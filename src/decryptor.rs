@@ -1,10 +1,14 @@
 use aes::Aes256;
-use cfb_mode::Decryptor;
+use cfb_mode::{Decryptor, Encryptor};
 use ctr::{Ctr128BE};
 use ctr::cipher::{KeyIvInit, StreamCipher};
-use flate2::read::{ZlibDecoder, DeflateDecoder};
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
 use aes::cipher::{AsyncStreamCipher, generic_array::GenericArray};
-use std::io::{self, Cursor, Read, Seek};
+use std::io::{Cursor, Read, Seek, Write};
+
+use crate::error::{PsarcError, Result};
 
 /// Constants for PSARC decryption.
 pub const PSARC_KEY: [u8; 32] = [
@@ -25,6 +29,138 @@ pub const SNG_KEY_PC: [u8; 32] = [
     0x59, 0xDE, 0x7A, 0xDD, 0xA1, 0x8A, 0x3A, 0x30,
 ];
 
+/// Constant key for SNG decryption of Mac packages (SNG_KEY_MAC)
+pub const SNG_KEY_MAC: [u8; 32] = [
+    0x98, 0x21, 0x33, 0x0E, 0x34, 0xB9, 0x1F, 0x70,
+    0xD0, 0xA4, 0x8C, 0xBD, 0x62, 0x59, 0x93, 0x12,
+    0x69, 0x70, 0xCE, 0xA0, 0x91, 0x92, 0xC0, 0xE6,
+    0xCD, 0xA6, 0x76, 0xCC, 0x98, 0x38, 0x28, 0x9D,
+];
+
+/// Platform a package was built for. The TOC key is shared, the SNG key is not.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Platform {
+    #[default]
+    Pc,
+    Mac,
+}
+
+impl Platform {
+    /// Key of the SNG assets of the platform's packages.
+    pub fn sng_key(self) -> &'static [u8; 32] {
+        match self {
+            Platform::Pc => &SNG_KEY_PC,
+            Platform::Mac => &SNG_KEY_MAC,
+        }
+    }
+
+    /// Name of the platform in `PackageLayouts` (`pc`, `mac`).
+    pub fn name(self) -> &'static str {
+        match self {
+            Platform::Pc => "pc",
+            Platform::Mac => "mac",
+        }
+    }
+
+    /// The platform of a `PackageLayouts` name. Other platforms have no known SNG key.
+    pub fn from_name(name: &str) -> Option<Platform> {
+        match name.to_ascii_lowercase().as_str() {
+            "pc" | "windows" => Some(Platform::Pc),
+            "mac" | "macos" => Some(Platform::Mac),
+            _ => None,
+        }
+    }
+}
+
+impl std::str::FromStr for Platform {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        Platform::from_name(value).ok_or_else(|| format!("Unknown platform {:?} (expected pc or mac)", value))
+    }
+}
+
+/// AES keys for PSARC TOCs and SNG assets.
+///
+/// The default is the built-in PC key set. Callers that ship their own keys (other
+/// platforms, keys loaded from a config file) pass them to the `_with_key` functions and
+/// `PsarcFile::open_with_keys` instead of relying on the constants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CryptoKeys {
+    pub psarc: [u8; 32],
+    pub sng: [u8; 32],
+}
+
+impl Default for CryptoKeys {
+    fn default() -> Self {
+        CryptoKeys::for_platform(Platform::Pc)
+    }
+}
+
+impl CryptoKeys {
+    /// The built-in keys of `platform`.
+    pub fn for_platform(platform: Platform) -> Self {
+        CryptoKeys {
+            psarc: PSARC_KEY,
+            sng: *platform.sng_key(),
+        }
+    }
+}
+
+/// A named `CryptoKeys` candidate of a `KeyRing`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeySet {
+    pub name: String,
+    pub keys: CryptoKeys,
+}
+
+/// Key sets tried in order when opening an archive, so packages of a re-keyed update or
+/// another title open without knowing their keys up front
+/// (see `PsarcFile::open_with_key_ring`).
+///
+/// The default holds the built-in PC set only.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyRing {
+    sets: Vec<KeySet>,
+}
+
+impl Default for KeyRing {
+    fn default() -> Self {
+        KeyRing::empty().with("pc", CryptoKeys::default())
+    }
+}
+
+impl KeyRing {
+    /// The built-in PC key set.
+    pub fn new() -> Self {
+        KeyRing::default()
+    }
+
+    /// A ring without any key set, not even the built-in one.
+    pub fn empty() -> Self {
+        KeyRing { sets: Vec::new() }
+    }
+
+    /// Adds a key set, tried after those already in the ring.
+    pub fn with(mut self, name: impl Into<String>, keys: CryptoKeys) -> Self {
+        self.sets.push(KeySet { name: name.into(), keys });
+        self
+    }
+
+    /// Adds a key set tried before those already in the ring, for keys expected to be
+    /// the most common (the ones of the latest update).
+    pub fn with_first(mut self, name: impl Into<String>, keys: CryptoKeys) -> Self {
+        self.sets.insert(0, KeySet { name: name.into(), keys });
+        self
+    }
+
+    /// The key sets, in the order they are tried.
+    pub fn sets(&self) -> &[KeySet] {
+        &self.sets
+    }
+}
+
 /// A DecryptStream in PSARC or SNG mode.
 /// It decrypts a fixed-length block of data from an input stream and provides a
 /// Cursor over the decrypted data.
@@ -40,11 +176,21 @@ impl DecryptStream {
     ///
     /// This function reads the encrypted data into memory, decrypts it using AES-256 CFB with a zero IV,
     /// and returns a DecryptStream that provides access to the decrypted data.
-    pub fn new_psarc<R: Read + Seek>(mut input: R, length: usize) -> io::Result<Self> {
-        let mut encrypted_data = vec![0u8; length];
-        input.read_exact(&mut encrypted_data)?;
+    pub fn new_psarc<R: Read + Seek>(input: R, length: usize) -> Result<Self> {
+        DecryptStream::new_psarc_with_key(input, length, &PSARC_KEY)
+    }
 
-        let key = GenericArray::from_slice(&PSARC_KEY);
+    /// `new_psarc` with a caller-supplied key.
+    pub fn new_psarc_with_key<R: Read + Seek>(input: R, length: usize, key: &[u8; 32]) -> Result<Self> {
+        // The length comes from the header: read what is there rather than allocating for
+        // it up front.
+        let mut encrypted_data = Vec::new();
+        input.take(length as u64).read_to_end(&mut encrypted_data)?;
+        if encrypted_data.len() < length {
+            return Err(PsarcError::BadToc("encrypted TOC runs past the end of the file".to_string()));
+        }
+
+        let key = GenericArray::from_slice(key);
         let iv = GenericArray::from_slice(&PSARC_IV);
 
         let cipher = Decryptor::<Aes256>::new(key, iv);
@@ -54,15 +200,60 @@ impl DecryptStream {
         Ok(DecryptStream { reader })
     }
 
+    /// Encrypts a PSARC TOC in place (AES-256 CFB, zero IV), the inverse of `new_psarc`.
+    pub fn encrypt_psarc(data: &mut [u8]) {
+        DecryptStream::encrypt_psarc_with_key(data, &PSARC_KEY);
+    }
+
+    /// `encrypt_psarc` with a caller-supplied key.
+    pub fn encrypt_psarc_with_key(data: &mut [u8], key: &[u8; 32]) {
+        let key = GenericArray::from_slice(key);
+        let iv = GenericArray::from_slice(&PSARC_IV);
+        Encryptor::<Aes256>::new(key, iv).encrypt(data);
+    }
+
+    /// Builds an encrypted SNG file from its plain layout, the inverse of `new_sng`: the data
+    /// is zlib-compressed behind its uncompressed size, encrypted with AES-256 CTR under `iv`,
+    /// and framed with the 24 byte header and an empty 56 byte signature.
+    pub fn encrypt_sng(plain: &[u8], iv: &[u8; 16]) -> Result<Vec<u8>> {
+        DecryptStream::encrypt_sng_with_key(plain, iv, &SNG_KEY_PC)
+    }
+
+    /// `encrypt_sng` with a caller-supplied key.
+    pub fn encrypt_sng_with_key(plain: &[u8], iv: &[u8; 16], key: &[u8; 32]) -> Result<Vec<u8>> {
+        let mut payload = (plain.len() as u32).to_le_bytes().to_vec();
+        let mut encoder = ZlibEncoder::new(payload, Compression::best());
+        encoder.write_all(plain)?;
+        payload = encoder.finish()?;
+
+        type Aes256Ctr = Ctr128BE<Aes256>;
+        let mut cipher = Aes256Ctr::new(key.into(), iv.into());
+        cipher.apply_keystream(&mut payload);
+
+        let mut sng = Vec::with_capacity(24 + payload.len() + 56);
+        sng.extend_from_slice(&0x4Au32.to_le_bytes());
+        sng.extend_from_slice(&1u32.to_le_bytes());
+        sng.extend_from_slice(iv);
+        sng.extend_from_slice(&payload);
+        sng.extend_from_slice(&[0u8; 56]);
+        Ok(sng)
+    }
+
     /// Creates a new Rocksmith SNG decryption stream.
     ///
     /// # Arguments
     /// * `mut input` - The input stream (positioned at the beginning of the SNG file)
     /// * `length` - Total length in bytes (including the header)
+    /// * `platform` - Platform of the package, which selects the key
     ///
     /// # Errors
     /// Returns an error if the header is invalid or I/O fails.
-    pub fn new_sng<R: Read + Seek>(mut input: R, length: usize) -> io::Result<Self> {
+    pub fn new_sng<R: Read + Seek>(input: R, length: usize, platform: Platform) -> Result<Self> {
+        DecryptStream::new_sng_with_key(input, length, platform.sng_key())
+    }
+
+    /// `new_sng` with a caller-supplied key.
+    pub fn new_sng_with_key<R: Read + Seek>(mut input: R, length: usize, key: &[u8; 32]) -> Result<Self> {
         // --- Read Header (24 bytes) ---
         // 4 bytes: Identifier (must be 0x4A)
         // 4 bytes: Asset flags (bitfield; flag 0x1 indicates compression)
@@ -76,7 +267,7 @@ impl DecryptStream {
         header_cursor.read_exact(&mut id_buf)?;
         let identifier = u32::from_le_bytes(id_buf);
         if identifier != 0x4A {
-            return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a valid sng file"));
+            return Err(PsarcError::InvalidAsset("Not a valid sng file".to_string()));
         }
 
         // Asset flags (u32, little-endian)
@@ -90,14 +281,14 @@ impl DecryptStream {
 
         // --- Read Encrypted Data ---
         // Encrypted data length is total length minus header (24 bytes)
-        let encrypted_length = length - 24;
+        let encrypted_length = length.checked_sub(24)
+            .ok_or_else(|| PsarcError::InvalidAsset("SNG shorter than its header".to_string()))?;
         let mut encrypted_data = vec![0u8; encrypted_length];
         input.read_exact(&mut encrypted_data)?;
 
         // --- Decrypt using AES-256 in CTR mode ---
         // Use Ctr128BE (big-endian) to mimic the C# counter increment.
         type Aes256Ctr = Ctr128BE<Aes256>;
-        let key = &SNG_KEY_PC;
         let mut cipher = Aes256Ctr::new(key.into(), (&decrypt_iv).into());
         cipher.apply_keystream(&mut encrypted_data);
 
@@ -107,21 +298,23 @@ impl DecryptStream {
         let final_data = if asset_flags & SNG_ASSET_FLAG_COMPRESSED != 0 {
             // The first 4 bytes of the decrypted data indicate the uncompressed size.
             if encrypted_data.len() < 4 {
-                return Err(io::Error::new(
-                    io::ErrorKind::UnexpectedEof,
-                    "Decrypted data too short for uncompressed size",
-                ));
+                return Err(PsarcError::InvalidAsset("Decrypted data too short for uncompressed size".to_string()));
             }
-            let uncompressed_size = {
-                let size_bytes = &encrypted_data[..4];
-                u32::from_le_bytes(size_bytes.try_into().unwrap()) as usize
-            };
+            let uncompressed_size = u32::from_le_bytes([
+                encrypted_data[0],
+                encrypted_data[1],
+                encrypted_data[2],
+                encrypted_data[3],
+            ]) as usize;
 
             // The remainder (after the first 4 bytes) is compressed.
             let compressed_data = &encrypted_data[4..];
             let mut decoder = ZlibDecoder::new(compressed_data);
-            let mut decompressed_data = Vec::with_capacity(uncompressed_size);
-            decoder.read_to_end(&mut decompressed_data)?;
+            // The declared size is untrusted, so only use it as a hint bounded by a sane ratio.
+            let mut decompressed_data = Vec::with_capacity(uncompressed_size.min(compressed_data.len() * 16));
+            decoder.read_to_end(&mut decompressed_data).map_err(|err| {
+                PsarcError::Decompression(format!("SNG payload does not inflate, wrong key? ({})", err))
+            })?;
             decompressed_data
         } else {
             encrypted_data
@@ -0,0 +1,109 @@
+//! Error type of the archive, decryption and SNG parsing API.
+//!
+//! `psarc`, `decryptor` and `models` return `PsarcError` so callers can tell a damaged
+//! archive from a missing entry or a failed read. The rest of the crate works with
+//! `io::Result`; both convert into each other with `?`, and an `io::Error` made from a
+//! `PsarcError` converts back into the same variant, so the cause survives the round trip.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Why reading an archive or one of its assets failed.
+#[derive(Debug)]
+pub enum PsarcError {
+    /// The file does not start with a PSARC header, or the header holds impossible values.
+    InvalidHeader(String),
+    /// Decrypting the TOC or an SNG asset failed, or no key fits.
+    DecryptionFailed(String),
+    /// A zlib block or stream does not inflate.
+    Decompression(String),
+    /// The TOC or its block size table is inconsistent with the header.
+    BadToc(String),
+    /// No entry of the archive has this path.
+    MissingEntry(String),
+    /// An asset (SNG, soundbank, NamesBlock, ...) does not have the expected layout.
+    InvalidAsset(String),
+    /// An array count read from an asset is negative or larger than the data can hold.
+    CountOverflow { count: i64, limit: usize },
+    /// The archive uses a feature this build does not support.
+    Unsupported(String),
+    /// Reading or writing failed.
+    Io(io::Error),
+}
+
+/// `Result` of the archive API.
+pub type Result<T> = std::result::Result<T, PsarcError>;
+
+impl PsarcError {
+    /// The `io::ErrorKind` the error maps to when converted into an `io::Error`.
+    pub fn kind(&self) -> io::ErrorKind {
+        match self {
+            PsarcError::InvalidHeader(_)
+            | PsarcError::DecryptionFailed(_)
+            | PsarcError::Decompression(_)
+            | PsarcError::BadToc(_)
+            | PsarcError::InvalidAsset(_)
+            | PsarcError::CountOverflow { .. } => io::ErrorKind::InvalidData,
+            PsarcError::MissingEntry(_) => io::ErrorKind::NotFound,
+            PsarcError::Unsupported(_) => io::ErrorKind::Unsupported,
+            PsarcError::Io(err) => err.kind(),
+        }
+    }
+}
+
+impl fmt::Display for PsarcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PsarcError::InvalidHeader(message) => write!(f, "Invalid PSARC header: {}", message),
+            PsarcError::DecryptionFailed(message) => write!(f, "Decryption failed: {}", message),
+            PsarcError::Decompression(message) => write!(f, "Decompression failed: {}", message),
+            PsarcError::BadToc(message) => write!(f, "Bad TOC: {}", message),
+            PsarcError::MissingEntry(path) => write!(f, "No entry {}", path),
+            PsarcError::InvalidAsset(message) => write!(f, "{}", message),
+            PsarcError::CountOverflow { count, limit } => {
+                write!(f, "Array count {} is out of range (at most {})", count, limit)
+            }
+            PsarcError::Unsupported(message) => write!(f, "{}", message),
+            PsarcError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for PsarcError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PsarcError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for PsarcError {
+    fn from(err: io::Error) -> Self {
+        if !err.get_ref().is_some_and(|inner| inner.is::<PsarcError>()) {
+            return PsarcError::Io(err);
+        }
+        let kind = err.kind();
+        match err.into_inner().map(|inner| inner.downcast::<PsarcError>()) {
+            Some(Ok(inner)) => *inner,
+            _ => PsarcError::Io(kind.into()),
+        }
+    }
+}
+
+/// JSON exports failing to serialize are reported as I/O errors, as `io::Error::other`.
+impl From<serde_json::Error> for PsarcError {
+    fn from(err: serde_json::Error) -> Self {
+        PsarcError::Io(io::Error::other(err))
+    }
+}
+
+impl From<PsarcError> for io::Error {
+    fn from(err: PsarcError) -> Self {
+        match err {
+            PsarcError::Io(err) => err,
+            err => io::Error::new(err.kind(), err),
+        }
+    }
+}
@@ -0,0 +1,880 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+#[cfg(feature = "async-io")]
+use std::sync::mpsc;
+#[cfg(feature = "async-io")]
+use std::thread;
+use std::time::{Instant, SystemTime};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use crate::cache::ConversionCache;
+use crate::content_type::{AssetClass, ContentType};
+use crate::glob::GlobPattern;
+use crate::job_state::{self, JobState};
+use crate::md5::md5;
+use crate::names::{open_path_with_names, NameDictionary};
+use crate::psarc::{PsarcFile, PsarcTOCEntry};
+use crate::zstd::ZstdEncoder;
+
+/// Options controlling how an archive is extracted.
+///
+/// Built with chained setters and passed to `PsarcFile::dump_entries` or `extract_all`:
+/// `ExtractOptions::new().include("songs/").overwrite(false)`.
+#[derive(Debug, Clone)]
+pub struct ExtractOptions {
+    /// Entry path prefixes to extract. Empty means every entry.
+    pub include: Vec<String>,
+    /// Entry path prefixes to skip, applied after `include`.
+    pub exclude: Vec<String>,
+    /// Glob patterns over entry paths; when any is given, only matching entries are
+    /// extracted (see `glob::GlobPattern`).
+    pub patterns: Vec<GlobPattern>,
+    /// Replace files that already exist in the output directory.
+    pub overwrite: bool,
+    /// Recreate the archive folder layout instead of writing every entry into one folder.
+    pub preserve_paths: bool,
+    /// Also write every SNG arrangement as `<name>.sng.json`.
+    pub convert_sng_to_json: bool,
+    /// Compression of the text exports (SNG JSON), which get its extension:
+    /// `<name>.sng.json.gz`. Extracted entries are written as stored.
+    pub compress_output: Option<OutputCompression>,
+    /// Modification time given to every written file. `None` leaves the time of writing.
+    /// `extract_all` fills it with the archive's own modification time when unset.
+    pub mtime: Option<SystemTime>,
+    /// Hard link entries whose content repeats an entry already written, instead of
+    /// writing the bytes again. Falls back to a copy where links are not supported.
+    pub link_duplicates: bool,
+    /// Asset classes to extract, on top of the path filters. Empty means every class.
+    /// Entries are classified by their sniffed content, see `AssetClass::of`.
+    pub only: Vec<AssetClass>,
+    /// Skips entries smaller than this many (inflated) bytes.
+    pub min_size: Option<u64>,
+    /// Skips entries larger than this many (inflated) bytes.
+    pub max_size: Option<u64>,
+    /// Stop starting new entries once this instant has passed. Entries are then extracted
+    /// in priority order (see `priority_order`), and the ones left over are listed in
+    /// `ExtractReport::not_started`. A file being written is always finished.
+    pub deadline: Option<Instant>,
+    /// Extract stage by stage, following `ExtractStage`: metadata first, then art, charts
+    /// (with their JSON conversion) and audio last, instead of in archive order.
+    pub priority_order: bool,
+    /// Reuse conversions (SNG JSON, the audio of synthesized previews) cached from earlier
+    /// runs and cache new ones.
+    pub cache: Option<ConversionCache>,
+    /// Difficulty of the SNG JSON exports. `None` keeps every difficulty level as stored;
+    /// otherwise each export holds one flattened level (see `SngAsset::flattened_level`).
+    pub difficulty: Option<DifficultySelection>,
+    /// For songs without a preview soundbank, write `<key>_preview.ogg` (`.wav` for PCM
+    /// tracks) of this many seconds cut from the main track (see `Song::synthesize_preview`).
+    pub synthesize_preview: Option<f64>,
+    /// Names entries the archive lists by hash only (see `names::resolve_names`).
+    pub names: Option<Arc<NameDictionary>>,
+}
+
+impl Default for ExtractOptions {
+    fn default() -> Self {
+        ExtractOptions {
+            include: Vec::new(),
+            exclude: Vec::new(),
+            patterns: Vec::new(),
+            overwrite: true,
+            preserve_paths: false,
+            convert_sng_to_json: true,
+            compress_output: None,
+            mtime: None,
+            link_duplicates: false,
+            only: Vec::new(),
+            min_size: None,
+            max_size: None,
+            deadline: None,
+            priority_order: false,
+            cache: None,
+            synthesize_preview: None,
+            difficulty: None,
+            names: None,
+        }
+    }
+}
+
+impl ExtractOptions {
+    /// Options with the defaults: every entry, flat layout, overwrite, SNG to JSON.
+    pub fn new() -> Self {
+        ExtractOptions::default()
+    }
+
+    /// Only extracts entries whose path starts with `prefix`. Can be given several times.
+    pub fn include(mut self, prefix: impl Into<String>) -> Self {
+        self.include.push(prefix.into());
+        self
+    }
+
+    /// Skips entries whose path starts with `prefix`. Can be given several times.
+    pub fn exclude(mut self, prefix: impl Into<String>) -> Self {
+        self.exclude.push(prefix.into());
+        self
+    }
+
+    /// Only extracts entries whose path matches `pattern`. Can be given several times.
+    pub fn matching(mut self, pattern: GlobPattern) -> Self {
+        self.patterns.push(pattern);
+        self
+    }
+
+    pub fn overwrite(mut self, overwrite: bool) -> Self {
+        self.overwrite = overwrite;
+        self
+    }
+
+    pub fn preserve_paths(mut self, preserve_paths: bool) -> Self {
+        self.preserve_paths = preserve_paths;
+        self
+    }
+
+    pub fn convert_sng_to_json(mut self, convert: bool) -> Self {
+        self.convert_sng_to_json = convert;
+        self
+    }
+
+    pub fn compress_output(mut self, compression: Option<OutputCompression>) -> Self {
+        self.compress_output = compression;
+        self
+    }
+
+    pub fn mtime(mut self, mtime: Option<SystemTime>) -> Self {
+        self.mtime = mtime;
+        self
+    }
+
+    pub fn link_duplicates(mut self, link: bool) -> Self {
+        self.link_duplicates = link;
+        self
+    }
+
+    /// Only extracts entries of this asset class. Can be given several times.
+    pub fn only(mut self, class: AssetClass) -> Self {
+        self.only.push(class);
+        self
+    }
+
+    pub fn min_size(mut self, bytes: Option<u64>) -> Self {
+        self.min_size = bytes;
+        self
+    }
+
+    pub fn max_size(mut self, bytes: Option<u64>) -> Self {
+        self.max_size = bytes;
+        self
+    }
+
+    pub fn deadline(mut self, deadline: Option<Instant>) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    pub fn priority_order(mut self, priority_order: bool) -> Self {
+        self.priority_order = priority_order;
+        self
+    }
+
+    pub fn cache(mut self, cache: Option<ConversionCache>) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    pub fn synthesize_preview(mut self, seconds: Option<f64>) -> Self {
+        self.synthesize_preview = seconds;
+        self
+    }
+
+    pub fn difficulty(mut self, difficulty: Option<DifficultySelection>) -> Self {
+        self.difficulty = difficulty;
+        self
+    }
+
+    pub fn names(mut self, names: Option<Arc<NameDictionary>>) -> Self {
+        self.names = names;
+        self
+    }
+
+    /// Opens an archive, naming its hash-only entries when a dictionary is set.
+    fn open_archive(&self, path: &Path) -> io::Result<PsarcFile> {
+        match &self.names {
+            Some(names) => open_path_with_names(path, names).map(|(psarc, _)| psarc),
+            None => Ok(PsarcFile::open_path(path)?),
+        }
+    }
+
+    /// True when entries are extracted stage by stage.
+    pub(crate) fn staged(&self) -> bool {
+        self.priority_order || self.deadline.is_some()
+    }
+
+    pub(crate) fn past_deadline(&self) -> bool {
+        self.deadline.is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// True when an entry passes the path and size filters.
+    pub fn selects_entry(&self, entry: &PsarcTOCEntry) -> bool {
+        entry.path.as_deref().is_some_and(|path| self.selects(path))
+            && self.min_size.is_none_or(|min| entry.length >= min)
+            && self.max_size.is_none_or(|max| entry.length <= max)
+    }
+
+    /// True when an entry of this content type passes the `only` filter.
+    pub fn selects_content(&self, path: &str, content_type: ContentType) -> bool {
+        self.only.is_empty() || AssetClass::of(content_type, path).is_some_and(|class| self.only.contains(&class))
+    }
+
+    /// True when an entry with this path should be extracted.
+    pub fn selects(&self, path: &str) -> bool {
+        let included = self.include.is_empty() || self.include.iter().any(|p| path.starts_with(p.as_str()));
+        let matched = self.patterns.is_empty() || self.patterns.iter().any(|p| p.matches(path));
+        included && matched && !self.exclude.iter().any(|p| path.starts_with(p.as_str()))
+    }
+
+    /// Where an entry is written below `output_dir`. Only the normal components of the
+    /// entry path are kept, so a hostile path cannot escape the output directory.
+    pub fn output_path(&self, output_dir: &Path, entry_path: &str) -> Option<PathBuf> {
+        let relative: PathBuf = if self.preserve_paths {
+            Path::new(entry_path)
+                .components()
+                .filter(|c| matches!(c, Component::Normal(_)))
+                .collect()
+        } else {
+            PathBuf::from(Path::new(entry_path).file_name()?)
+        };
+        if relative.as_os_str().is_empty() {
+            return None;
+        }
+        Some(output_dir.join(relative))
+    }
+
+    /// Writes one output file, creating its parent folders and applying `mtime`.
+    pub(crate) fn write_output(&self, output_path: &Path, data: &[u8]) -> io::Result<()> {
+        self.create_output(output_path, |file| file.write_all(data))
+    }
+
+    /// `write_output` for content produced by `write` as it goes, through a buffer. When
+    /// `write` fails the partial file is removed.
+    pub(crate) fn create_output<E: From<io::Error>>(
+        &self,
+        output_path: &Path,
+        write: impl FnOnce(&mut io::BufWriter<fs::File>) -> Result<(), E>,
+    ) -> Result<(), E> {
+        if let Some(parent) = output_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if self.link_duplicates {
+            // The file may be a link from an earlier run: replace it rather than writing
+            // through to every name sharing it.
+            remove_existing(output_path)?;
+        }
+        let mut file = io::BufWriter::new(fs::File::create(output_path)?);
+        let written = write(&mut file).and_then(|()| Ok(file.into_inner().map_err(io::Error::from)?));
+        let file = match written {
+            Ok(file) => file,
+            Err(err) => {
+                let _ = fs::remove_file(output_path);
+                return Err(err);
+            }
+        };
+        if let Some(mtime) = self.mtime {
+            file.set_modified(mtime)?;
+        }
+        Ok(())
+    }
+
+    /// Hard links `output_path` to the already written `original`. Returns false when the
+    /// file system refused the link, so the caller writes a copy instead.
+    pub(crate) fn link_output(&self, original: &Path, output_path: &Path) -> io::Result<bool> {
+        if let Some(parent) = output_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        remove_existing(output_path)?;
+        match fs::hard_link(original, output_path) {
+            Ok(()) => Ok(true),
+            Err(err) => {
+                tracing::trace!("Hard link to {:?} failed, copying instead: {}", original, err);
+                Ok(false)
+            }
+        }
+    }
+}
+
+/// Entries `OutputWriter` holds before `write` waits for the background thread.
+#[cfg(feature = "async-io")]
+const WRITE_QUEUE: usize = 4;
+
+/// Queue of `OutputWriter` and the thread draining it.
+#[cfg(feature = "async-io")]
+type BackgroundWrites = (mpsc::SyncSender<(PathBuf, Vec<u8>)>, thread::JoinHandle<io::Result<()>>);
+
+/// Writes the entries of an extraction with `ExtractOptions::write_output`. With the
+/// `async-io` feature they go through a bounded queue to a background thread, so writing
+/// an entry overlaps with inflating the next; the first failed write stops the thread and
+/// is returned by the next `write` or by `flush`, which waits for the queued writes.
+pub(crate) struct OutputWriter<'a> {
+    options: &'a ExtractOptions,
+    #[cfg(feature = "async-io")]
+    background: Option<BackgroundWrites>,
+    /// Outputs handed to the thread since the last `flush`.
+    #[cfg(feature = "async-io")]
+    queued: HashSet<PathBuf>,
+}
+
+impl<'a> OutputWriter<'a> {
+    pub(crate) fn new(options: &'a ExtractOptions) -> Self {
+        OutputWriter {
+            options,
+            #[cfg(feature = "async-io")]
+            background: None,
+            #[cfg(feature = "async-io")]
+            queued: HashSet::new(),
+        }
+    }
+
+    pub(crate) fn write(&mut self, output_path: &Path, data: Vec<u8>) -> io::Result<()> {
+        #[cfg(feature = "async-io")]
+        {
+            let options = self.options;
+            let (sender, _) = self.background.get_or_insert_with(|| {
+                let options = options.clone();
+                let (sender, receiver) = mpsc::sync_channel::<(PathBuf, Vec<u8>)>(WRITE_QUEUE);
+                let handle = thread::spawn(move || {
+                    for (output_path, data) in receiver {
+                        options.write_output(&output_path, &data)?;
+                    }
+                    Ok(())
+                });
+                (sender, handle)
+            });
+            self.queued.insert(output_path.to_path_buf());
+            if sender.send((output_path.to_path_buf(), data)).is_err() {
+                // The thread only hangs up after a failed write.
+                return self.flush();
+            }
+            Ok(())
+        }
+        #[cfg(not(feature = "async-io"))]
+        self.options.write_output(output_path, &data)
+    }
+
+    /// Whether `output_path` is queued but maybe not written yet.
+    pub(crate) fn queued(&self, output_path: &Path) -> bool {
+        #[cfg(feature = "async-io")]
+        return self.queued.contains(output_path);
+        #[cfg(not(feature = "async-io"))]
+        {
+            let _ = output_path;
+            false
+        }
+    }
+
+    /// Waits until every queued entry is written.
+    pub(crate) fn flush(&mut self) -> io::Result<()> {
+        #[cfg(feature = "async-io")]
+        if let Some((sender, handle)) = self.background.take() {
+            drop(sender);
+            self.queued.clear();
+            return handle.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "async-io")]
+impl Drop for OutputWriter<'_> {
+    /// An extraction stopped by an error still leaves the queued writes done, not racing
+    /// whatever the caller does next.
+    fn drop(&mut self) {
+        if let Some((sender, handle)) = self.background.take() {
+            drop(sender);
+            let _ = handle.join();
+        }
+    }
+}
+
+fn remove_existing(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+/// Outcome of `PsarcFile::extract_entries`, which keeps going past entries that fail.
+#[derive(Debug, Default)]
+pub struct ExtractReport {
+    /// Every file written, including the SNG JSON conversions.
+    pub written: Vec<PathBuf>,
+    /// Files of `written` created as hard links to an identical entry.
+    pub linked: Vec<PathBuf>,
+    /// Entries that could not be inflated, with the error.
+    pub failed: Vec<(String, io::Error)>,
+    /// SNG arrangements that could not be converted to JSON, with the error.
+    pub conversion_failed: Vec<(String, io::Error)>,
+    /// Selected entries (and `.json` conversions) skipped because the deadline passed.
+    pub not_started: Vec<String>,
+}
+
+impl ExtractReport {
+    /// True when every selected entry was extracted and converted.
+    pub fn is_complete(&self) -> bool {
+        self.failed.is_empty() && self.conversion_failed.is_empty() && self.not_started.is_empty()
+    }
+
+    /// Appends the outcome of another part of the same extraction.
+    pub(crate) fn merge(&mut self, other: ExtractReport) {
+        self.written.extend(other.written);
+        self.linked.extend(other.linked);
+        self.failed.extend(other.failed);
+        self.conversion_failed.extend(other.conversion_failed);
+        self.not_started.extend(other.not_started);
+    }
+}
+
+/// Difficulty levels written by the SNG JSON export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DifficultySelection {
+    /// The full chart, every phrase at its own maximum difficulty (`<name>.sng.json`).
+    Max,
+    /// The chart as played at this dynamic difficulty level (`<name>.sng.json`).
+    Level(i32),
+    /// One export per level, `<name>.sng.d<level>.json`.
+    All,
+}
+
+impl std::str::FromStr for DifficultySelection {
+    type Err = String;
+
+    /// Parses `max`, `all` or a level number.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "max" => Ok(DifficultySelection::Max),
+            "all" => Ok(DifficultySelection::All),
+            level => level
+                .parse::<i32>()
+                .ok()
+                .filter(|level| *level >= 0)
+                .map(DifficultySelection::Level)
+                .ok_or_else(|| format!("Invalid difficulty {:?}, expected a level number, `max` or `all`", value)),
+        }
+    }
+}
+
+/// Compression applied to text exports (see `ExtractOptions::compress_output`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputCompression {
+    /// gzip at the default level, `.gz`.
+    Gzip,
+    /// Zstandard from the built-in encoder (see `crate::zstd`), `.zst`.
+    Zstd,
+}
+
+impl OutputCompression {
+    /// Extension added to the output name, without the dot.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputCompression::Gzip => "gz",
+            OutputCompression::Zstd => "zst",
+        }
+    }
+
+    /// Wraps `writer` in the encoder. Call `finish` on the result to write the trailer.
+    pub fn encoder<W: Write>(&self, writer: W) -> OutputEncoder<W> {
+        match self {
+            OutputCompression::Gzip => OutputEncoder::Gzip(GzEncoder::new(writer, Compression::default())),
+            OutputCompression::Zstd => OutputEncoder::Zstd(ZstdEncoder::new(writer)),
+        }
+    }
+}
+
+impl std::str::FromStr for OutputCompression {
+    type Err = String;
+
+    /// Parses `gzip` (or `gz`) and `zstd` (or `zst`).
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "gzip" | "gz" => Ok(OutputCompression::Gzip),
+            "zstd" | "zst" => Ok(OutputCompression::Zstd),
+            _ => Err(format!("Unknown output compression {:?}, expected `gzip` or `zstd`", value)),
+        }
+    }
+}
+
+/// Encoder returned by `OutputCompression::encoder`.
+pub enum OutputEncoder<W: Write> {
+    Gzip(GzEncoder<W>),
+    Zstd(ZstdEncoder<W>),
+}
+
+impl<W: Write> OutputEncoder<W> {
+    /// Writes the end of the compressed stream and returns the inner writer.
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            OutputEncoder::Gzip(encoder) => encoder.finish(),
+            OutputEncoder::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for OutputEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            OutputEncoder::Gzip(encoder) => encoder.write(buf),
+            OutputEncoder::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            OutputEncoder::Gzip(encoder) => encoder.flush(),
+            OutputEncoder::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Stages of a priority-ordered extraction, in the order they run. Song info is available
+/// once `Metadata` is done, while the slow audio comes last.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ExtractStage {
+    /// Manifests, song packs, xblocks and the other small descriptive files.
+    Metadata,
+    /// Album art and other textures.
+    Art,
+    /// SNG arrangements and their JSON conversions.
+    Charts,
+    /// Anything not covered by another stage.
+    Other,
+    /// Wwise sound banks and streams.
+    Audio,
+}
+
+impl ExtractStage {
+    pub const ALL: [ExtractStage; 5] = [
+        ExtractStage::Metadata,
+        ExtractStage::Art,
+        ExtractStage::Charts,
+        ExtractStage::Other,
+        ExtractStage::Audio,
+    ];
+
+    /// Stage of an entry, from the extension of its path.
+    pub fn of(path: &str) -> Self {
+        let extension = Path::new(path)
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        match extension.as_str() {
+            "json" | "hsan" | "xblock" | "nt" | "xml" | "appid" => ExtractStage::Metadata,
+            "dds" => ExtractStage::Art,
+            "sng" => ExtractStage::Charts,
+            "wem" | "bnk" => ExtractStage::Audio,
+            _ => ExtractStage::Other,
+        }
+    }
+}
+
+/// Opens the archive at `path` and extracts every selected entry into `output_dir`.
+/// Written files get the archive's modification time unless `options.mtime` is set, so
+/// repeated runs over an unchanged archive look unchanged to backup tools.
+///
+/// This is the one-liner for scripts:
+/// `extract_all("song_p.psarc", "out", ExtractOptions::default())`.
+pub fn extract_all(
+    path: impl AsRef<Path>,
+    output_dir: impl AsRef<Path>,
+    mut options: ExtractOptions,
+) -> io::Result<Vec<PathBuf>> {
+    if options.mtime.is_none() {
+        options.mtime = fs::metadata(path.as_ref()).and_then(|m| m.modified()).ok();
+    }
+    let psarc = options.open_archive(path.as_ref())?;
+    Ok(psarc.dump_entries(output_dir.as_ref(), &options)?)
+}
+
+/// Default name for an output renamed after a collision in a batch:
+/// `cover_256.png` from the archive of song `mysong` becomes `cover_256_mysong.png`.
+pub const DEFAULT_RENAME_TEMPLATE: &str = "{name}_{key}{ext}";
+
+/// An output written under another name because an earlier archive of the batch had
+/// already produced a file at the intended path.
+#[derive(Debug, Clone)]
+pub struct RenamedOutput {
+    pub archive: PathBuf,
+    pub entry: String,
+    pub intended: PathBuf,
+    pub written: PathBuf,
+}
+
+/// Outcome of `extract_batch`.
+#[derive(Debug, Default)]
+pub struct BatchReport {
+    /// Extraction report of every archive that could be opened, in input order.
+    pub archives: Vec<(PathBuf, ExtractReport)>,
+    /// Archives that could not be opened or extracted.
+    pub failed_archives: Vec<(PathBuf, io::Error)>,
+    /// Outputs renamed to avoid overwriting the output of another archive.
+    pub renamed: Vec<RenamedOutput>,
+    /// Archives skipped because the job state shows an earlier run extracted them.
+    pub resumed: Vec<PathBuf>,
+}
+
+/// Output paths already produced during a batch, and how colliding ones are renamed.
+#[derive(Debug)]
+pub(crate) struct OutputClaims {
+    template: String,
+    claimed: HashSet<PathBuf>,
+    renamed: Vec<RenamedOutput>,
+    archive: PathBuf,
+    archive_name: String,
+    key: String,
+    /// Job state of a resumable batch and the file it is saved to.
+    job: Option<(JobState, PathBuf)>,
+}
+
+impl OutputClaims {
+    fn new(template: &str) -> Self {
+        OutputClaims {
+            template: template.to_string(),
+            claimed: HashSet::new(),
+            renamed: Vec::new(),
+            archive: PathBuf::new(),
+            archive_name: String::new(),
+            key: String::new(),
+            job: None,
+        }
+    }
+
+    /// Tracks progress in `state`, saved to `state_path` after every output. Outputs of
+    /// earlier runs count as claimed.
+    fn with_job(mut self, state: JobState, state_path: PathBuf) -> Self {
+        self.claimed.extend(state.output_paths().map(Path::to_path_buf));
+        self.job = Some((state, state_path));
+        self
+    }
+
+    /// True when an earlier run of the job wrote `entry` and its output is still intact.
+    pub(crate) fn resumed(&self, entry: &str) -> bool {
+        self.job.as_ref().is_some_and(|(state, _)| state.output_intact(&self.archive, entry))
+    }
+
+    /// Records a written output in the job state, if any.
+    pub(crate) fn record(&mut self, entry: &str, output: &Path, data: &[u8]) -> io::Result<()> {
+        self.record_digest(entry, output, &md5(data))
+    }
+
+    /// `record` for an output hashed while it was written.
+    pub(crate) fn record_digest(&mut self, entry: &str, output: &Path, digest: &[u8; 16]) -> io::Result<()> {
+        match self.job.as_mut() {
+            Some((state, state_path)) => {
+                state.record_digest(&self.archive, entry, output, digest);
+                state.save(state_path)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Switches to the next archive of the batch.
+    fn start_archive(&mut self, archive_path: &Path, archive: &PsarcFile) {
+        self.archive = archive_path.to_path_buf();
+        self.archive_name = archive_path
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+        self.key = song_key(archive).unwrap_or_else(|| self.archive_name.clone());
+    }
+
+    /// Returns the path `entry` should be written to: `intended`, unless another output
+    /// of the batch already took it, in which case the rename template (plus a counter if
+    /// needed) gives a free name.
+    pub(crate) fn claim(&mut self, intended: PathBuf, entry: &str) -> PathBuf {
+        // An entry keeps the output an earlier run of the job gave it.
+        if let Some(output) = self.job.as_ref().and_then(|(state, _)| state.output(&self.archive, entry)) {
+            return output.path.clone();
+        }
+        if self.claimed.insert(intended.clone()) {
+            return intended;
+        }
+        let name = intended.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+        let ext = intended
+            .extension()
+            .map(|e| format!(".{}", e.to_string_lossy()))
+            .unwrap_or_default();
+        let rendered = self
+            .template
+            .replace("{name}", &name)
+            .replace("{ext}", &ext)
+            .replace("{archive}", &self.archive_name)
+            .replace("{key}", &self.key);
+        let mut candidate = intended.with_file_name(&rendered);
+        let mut counter = 2;
+        while !self.claimed.insert(candidate.clone()) {
+            let numbered = match rendered.strip_suffix(ext.as_str()).filter(|_| !ext.is_empty()) {
+                Some(base) => format!("{}_{}{}", base, counter, ext),
+                None => format!("{}_{}", rendered, counter),
+            };
+            candidate = intended.with_file_name(numbered);
+            counter += 1;
+        }
+        tracing::info!("{:?} already written by this batch, writing {} to {:?}", intended, entry, candidate);
+        self.renamed.push(RenamedOutput {
+            archive: self.archive.clone(),
+            entry: entry.to_string(),
+            intended,
+            written: candidate.clone(),
+        });
+        candidate
+    }
+
+    /// Gives back a claimed path whose output ended up not being written.
+    pub(crate) fn release(&mut self, path: &Path) {
+        self.claimed.remove(path);
+        self.renamed.retain(|r| r.written != path);
+    }
+}
+
+/// Song key of an archive, taken from its first SNG arrangement
+/// (`songs/bin/generic/mysong_lead.sng` gives `mysong`).
+fn song_key(archive: &PsarcFile) -> Option<String> {
+    archive
+        .toc
+        .entries
+        .iter()
+        .filter_map(|e| e.path.as_deref())
+        .filter(|path| path.ends_with(".sng"))
+        .find_map(|path| {
+            let stem = Path::new(path).file_stem()?.to_string_lossy().to_string();
+            stem.rsplit_once('_').map(|(key, _)| key.to_string())
+        })
+}
+
+/// Extracts several archives into one shared `output_dir`. When an archive would write a
+/// file another archive of the batch already wrote (two songs both producing
+/// `cover_256.png`), the output is renamed with `rename_template` instead of being
+/// overwritten. The template understands `{name}` (file name without extension), `{ext}`
+/// (extension with its dot), `{archive}` (archive file name without extension) and
+/// `{key}` (song key, or the archive name when it has no arrangement); see
+/// `DEFAULT_RENAME_TEMPLATE`.
+///
+/// Like `extract_all`, outputs get each archive's modification time unless
+/// `options.mtime` is set. Archives that fail to open or extract are reported and the
+/// batch moves on.
+pub fn extract_batch<P: AsRef<Path>>(
+    paths: &[P],
+    output_dir: impl AsRef<Path>,
+    options: &ExtractOptions,
+    rename_template: &str,
+) -> BatchReport {
+    run_batch(paths, output_dir.as_ref(), options, OutputClaims::new(rename_template))
+}
+
+/// `extract_batch` with its progress saved to the job state file at `state_path` (see
+/// `JobState`) after every output. Running it again with the same state file after a
+/// crash skips the archives already done, unless they changed since, and the outputs of
+/// the interrupted archive that are still intact on disk.
+pub fn extract_batch_resumable<P: AsRef<Path>>(
+    paths: &[P],
+    output_dir: impl AsRef<Path>,
+    options: &ExtractOptions,
+    rename_template: &str,
+    state_path: impl AsRef<Path>,
+) -> io::Result<BatchReport> {
+    let state_path = state_path.as_ref();
+    let state = JobState::load(state_path)?;
+    let claims = OutputClaims::new(rename_template).with_job(state, state_path.to_path_buf());
+    Ok(run_batch(paths, output_dir.as_ref(), options, claims))
+}
+
+/// Opens the archives of a batch in order. With the `async-io` feature the archive after
+/// the one being opened is read on a background thread, so reading it from disk overlaps
+/// with the extraction of the current one; at most two archives are in memory at a time.
+struct BatchOpener {
+    paths: Vec<PathBuf>,
+    options: ExtractOptions,
+    #[cfg(feature = "async-io")]
+    pending: Option<(usize, std::thread::JoinHandle<io::Result<PsarcFile>>)>,
+}
+
+impl BatchOpener {
+    fn new<P: AsRef<Path>>(paths: &[P], options: &ExtractOptions) -> Self {
+        BatchOpener {
+            paths: paths.iter().map(|path| path.as_ref().to_path_buf()).collect(),
+            options: options.clone(),
+            #[cfg(feature = "async-io")]
+            pending: None,
+        }
+    }
+
+    /// Opens the archive at `index` of the batch.
+    fn open(&mut self, index: usize) -> io::Result<PsarcFile> {
+        #[cfg(feature = "async-io")]
+        {
+            // An archive skipped by a resumed job leaves a read-ahead of another index.
+            let pending = self.pending.take().filter(|(pending, _)| *pending == index);
+            if let Some(next) = self.paths.get(index + 1).cloned() {
+                let options = self.options.clone();
+                let handle = std::thread::spawn(move || options.open_archive(&next));
+                self.pending = Some((index + 1, handle));
+            }
+            if let Some((_, handle)) = pending {
+                return handle.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+            }
+        }
+        self.options.open_archive(&self.paths[index])
+    }
+}
+
+fn run_batch<P: AsRef<Path>>(
+    paths: &[P],
+    output_dir: &Path,
+    options: &ExtractOptions,
+    mut claims: OutputClaims,
+) -> BatchReport {
+    let mut report = BatchReport::default();
+    let mut opener = BatchOpener::new(paths, options);
+    for (index, path) in paths.iter().enumerate() {
+        let path = path.as_ref();
+        let fingerprint = claims.job.as_ref().map(|_| job_state::fingerprint(path).unwrap_or_default());
+        if let (Some((state, state_path)), Some(fingerprint)) = (claims.job.as_mut(), &fingerprint) {
+            if state.is_done(path, fingerprint) {
+                tracing::info!("Skipping {:?}: extracted by an earlier run", path);
+                report.resumed.push(path.to_path_buf());
+                continue;
+            }
+            state.start(path, fingerprint);
+            if let Err(err) = state.save(state_path) {
+                report.failed_archives.push((path.to_path_buf(), err));
+                continue;
+            }
+        }
+        let mut archive_options = options.clone();
+        if archive_options.mtime.is_none() {
+            archive_options.mtime = fs::metadata(path).and_then(|m| m.modified()).ok();
+        }
+        let result = opener.open(index).and_then(|archive| {
+            claims.start_archive(path, &archive);
+            Ok(archive.extract_claimed(output_dir, &archive_options, &mut claims)?)
+        });
+        let complete = result.as_ref().is_ok_and(|r| r.is_complete());
+        if let Some((state, state_path)) = claims.job.as_mut() {
+            state.finish(path, complete);
+            if let Err(err) = state.save(state_path) {
+                tracing::warn!("Failed to save the job state {:?}: {}", state_path, err);
+            }
+        }
+        match result {
+            Ok(archive_report) => report.archives.push((path.to_path_buf(), archive_report)),
+            Err(err) => {
+                tracing::warn!("Failed to extract {:?}: {}", path, err);
+                report.failed_archives.push((path.to_path_buf(), err));
+            }
+        }
+    }
+    report.renamed = claims.renamed;
+    report
+}
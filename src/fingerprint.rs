@@ -0,0 +1,82 @@
+//! Identity of an archive from its header and TOC, independent of the file name.
+//!
+//! Reading the header and TOC is enough: no entry is inflated. Two fingerprints are the
+//! same archive when every field matches, and a patched variant of the same package when
+//! only the content root differs (same entry paths, different sizes).
+
+use std::fmt;
+use serde::{Deserialize, Serialize};
+
+use crate::md5::{md5, to_hex};
+use crate::psarc::PsarcFile;
+
+/// Header fields and Merkle roots of the TOC of an archive.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ArchiveFingerprint {
+    pub version: u32,
+    pub compression: String,
+    pub block_size: u32,
+    pub archive_flags: u32,
+    pub entry_count: u32,
+    /// Merkle root of the TOC hashes, in TOC order: the set of entry paths.
+    pub names_root: String,
+    /// Merkle root of the TOC hashes with the inflated length of each entry.
+    pub content_root: String,
+}
+
+/// Merkle root of `leaves`: each level hashes pairs of nodes, an odd last node is carried
+/// up unchanged. The root of no leaves is the MD5 of nothing.
+fn merkle_root(leaves: Vec<[u8; 16]>) -> [u8; 16] {
+    let mut level = leaves;
+    if level.is_empty() {
+        return md5(&[]);
+    }
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => md5(&[left.as_slice(), right.as_slice()].concat()),
+                [single] => *single,
+                _ => unreachable!(),
+            })
+            .collect();
+    }
+    level[0]
+}
+
+impl ArchiveFingerprint {
+    pub fn of(psarc: &PsarcFile) -> Self {
+        let header = &psarc.header;
+        let entries = &psarc.toc.entries;
+        let names = entries.iter().map(|entry| md5(entry.hash.as_bytes())).collect();
+        let content = entries
+            .iter()
+            .map(|entry| md5(&[entry.hash.as_bytes(), &entry.length.to_be_bytes()].concat()))
+            .collect();
+        ArchiveFingerprint {
+            version: header.version,
+            compression: header.compression.clone(),
+            block_size: header.block_size,
+            archive_flags: header.archive_flags.bits(),
+            entry_count: entries.len() as u32,
+            names_root: to_hex(&merkle_root(names)),
+            content_root: to_hex(&merkle_root(content)),
+        }
+    }
+
+    /// Same paths, but not the same archive: an update or patch of the same package.
+    pub fn is_variant_of(&self, other: &ArchiveFingerprint) -> bool {
+        self.names_root == other.names_root && self != other
+    }
+}
+
+/// Compact identity for indexes: `<version>-<compression>-<block size>-<flags>-<entries>-<content root>`.
+impl fmt::Display for ArchiveFingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:08X}-{}-{}-{:X}-{}-{}",
+            self.version, self.compression, self.block_size, self.archive_flags, self.entry_count, self.content_root
+        )
+    }
+}
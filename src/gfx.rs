@@ -0,0 +1,182 @@
+use std::io::{self, Cursor, Read, Seek};
+use byteorder::{LittleEndian, ReadBytesExt};
+use flate2::read::ZlibDecoder;
+use serde::Serialize;
+
+use crate::error::{PsarcError, Result};
+use crate::psarc::PsarcAsset;
+
+/// SWF tag codes that carry symbol names.
+const TAG_END: u16 = 0;
+const TAG_EXPORT_ASSETS: u16 = 56;
+const TAG_IMPORT_ASSETS: u16 = 57;
+const TAG_IMPORT_ASSETS2: u16 = 71;
+const TAG_SYMBOL_CLASS: u16 = 76;
+
+/// How a symbol is referenced by the movie.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum GfxSymbolKind {
+    /// Listed in an ExportAssets tag.
+    Export,
+    /// Listed in an ImportAssets/ImportAssets2 tag.
+    Import,
+    /// Bound to an ActionScript class through a SymbolClass tag.
+    Class,
+}
+
+/// A named character (symbol) embedded in a GFx movie.
+#[derive(Debug, Clone, Serialize)]
+pub struct GfxSymbol {
+    pub id: u16,
+    pub name: String,
+    pub kind: GfxSymbolKind,
+}
+
+/// Scaleform GFx movie (a SWF variant with a `GFX`/`CFX` signature).
+///
+/// Rocksmith UI archives ship their menus as .gfx files. The body is plain SWF, only the
+/// signature differs, so the asset can be turned back into a standard SWF with `to_swf`.
+#[derive(Default, Debug, Clone, Serialize)]
+pub struct GfxAsset {
+    #[serde(skip)]
+    pub raw: Vec<u8>,
+    pub version: u8,
+    pub compressed: bool,
+    pub file_length: u32,
+    pub frame_rate: f32,
+    pub frame_count: u16,
+    pub symbols: Vec<GfxSymbol>,
+}
+
+impl PsarcAsset for GfxAsset {
+    fn read_from<R: Read + Seek>(&mut self, reader: &mut R, _length: usize) -> Result<()> {
+        let mut raw = Vec::new();
+        reader.read_to_end(&mut raw)?;
+        if raw.len() < 8 {
+            return Err(PsarcError::InvalidAsset("GFx header too short".to_string()));
+        }
+        let compressed = match &raw[..3] {
+            b"GFX" | b"FWS" => false,
+            b"CFX" | b"CWS" => true,
+            _ => return Err(PsarcError::InvalidAsset("Not a valid gfx file".to_string())),
+        };
+        let version = raw[3];
+        let file_length = u32::from_le_bytes([raw[4], raw[5], raw[6], raw[7]]);
+
+        // The body (everything after the 8 byte header) is zlib-compressed for CFX/CWS.
+        let body = if compressed {
+            let mut decoder = ZlibDecoder::new(&raw[8..]);
+            let mut body = Vec::new();
+            decoder.read_to_end(&mut body).map_err(|err| PsarcError::Decompression(err.to_string()))?;
+            body
+        } else {
+            raw[8..].to_vec()
+        };
+
+        let mut cursor = Cursor::new(&body[..]);
+        skip_rect(&mut cursor)?;
+        let frame_rate = cursor.read_u16::<LittleEndian>()? as f32 / 256.0;
+        let frame_count = cursor.read_u16::<LittleEndian>()?;
+        let symbols = read_symbols(&mut cursor)?;
+
+        self.raw = raw;
+        self.version = version;
+        self.compressed = compressed;
+        self.file_length = file_length;
+        self.frame_rate = frame_rate;
+        self.frame_count = frame_count;
+        self.symbols = symbols;
+        Ok(())
+    }
+}
+
+impl GfxAsset {
+    /// Returns the movie with a standard SWF signature (`FWS`/`CWS`) so it can be opened
+    /// in regular SWF tools. The body is copied verbatim.
+    pub fn to_swf(&self) -> Vec<u8> {
+        let mut swf = self.raw.clone();
+        if swf.len() >= 3 {
+            swf[..3].copy_from_slice(if self.compressed { b"CWS" } else { b"FWS" });
+        }
+        swf
+    }
+}
+
+/// Skips the bit-packed RECT structure holding the stage size.
+fn skip_rect<R: Read>(reader: &mut R) -> io::Result<()> {
+    let first = reader.read_u8()?;
+    let nbits = (first >> 3) as usize;
+    let total_bits = 5 + 4 * nbits;
+    let total_bytes = total_bits.div_ceil(8);
+    for _ in 1..total_bytes {
+        reader.read_u8()?;
+    }
+    Ok(())
+}
+
+/// Reads a null-terminated string.
+fn read_c_string<R: Read>(reader: &mut R) -> io::Result<String> {
+    let mut buf = Vec::new();
+    loop {
+        let b = reader.read_u8()?;
+        if b == 0 {
+            break;
+        }
+        buf.push(b);
+    }
+    Ok(String::from_utf8_lossy(&buf).to_string())
+}
+
+/// Walks the tag list and collects every symbol name found in export, import and
+/// symbol-class tags. Stops at the End tag or at the end of the data.
+fn read_symbols(cursor: &mut Cursor<&[u8]>) -> io::Result<Vec<GfxSymbol>> {
+    let mut symbols = Vec::new();
+    let data_len = cursor.get_ref().len() as u64;
+    while cursor.position() + 2 <= data_len {
+        let code_and_length = cursor.read_u16::<LittleEndian>()?;
+        let code = code_and_length >> 6;
+        let mut length = (code_and_length & 0x3F) as u64;
+        if length == 0x3F {
+            length = cursor.read_u32::<LittleEndian>()? as u64;
+        }
+        if code == TAG_END {
+            break;
+        }
+        let tag_end = cursor.position() + length;
+        if tag_end > data_len {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Truncated gfx tag"));
+        }
+        let mut tag = Cursor::new(&cursor.get_ref()[cursor.position() as usize..tag_end as usize]);
+        match code {
+            TAG_EXPORT_ASSETS | TAG_SYMBOL_CLASS => {
+                let kind = if code == TAG_EXPORT_ASSETS {
+                    GfxSymbolKind::Export
+                } else {
+                    GfxSymbolKind::Class
+                };
+                let count = tag.read_u16::<LittleEndian>()?;
+                for _ in 0..count {
+                    let id = tag.read_u16::<LittleEndian>()?;
+                    let name = read_c_string(&mut tag)?;
+                    symbols.push(GfxSymbol { id, name, kind });
+                }
+            }
+            TAG_IMPORT_ASSETS | TAG_IMPORT_ASSETS2 => {
+                let _url = read_c_string(&mut tag)?;
+                if code == TAG_IMPORT_ASSETS2 {
+                    // Two reserved bytes.
+                    tag.read_u16::<LittleEndian>()?;
+                }
+                let count = tag.read_u16::<LittleEndian>()?;
+                for _ in 0..count {
+                    let id = tag.read_u16::<LittleEndian>()?;
+                    let name = read_c_string(&mut tag)?;
+                    symbols.push(GfxSymbol { id, name, kind: GfxSymbolKind::Import });
+                }
+            }
+            _ => {}
+        }
+        cursor.set_position(tag_end);
+    }
+    Ok(symbols)
+}
@@ -0,0 +1,148 @@
+//! Glob patterns over entry paths, for extracting a selection of an archive
+//! (`songs/arr/*.sng`, `*.xml`, `audio/**/*.wem`).
+//!
+//! `*` matches within one folder, `**` across folders (`a/**/b` also matches `a/b`), `?`
+//! one character and `[...]` a set such as `[a-z]` or `[!0-9]`. Matching ignores ASCII case
+//! and accepts `\` as a separator, like `normalize_entry_path`. A pattern without `/` is
+//! matched against the file name only, so `*.xml` finds XML files in every folder.
+
+use std::fmt;
+use std::io;
+
+use crate::psarc::normalize_entry_path;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Literal(char),
+    /// `?`
+    AnyChar,
+    /// `*`
+    Star,
+    /// `**`
+    DoubleStar,
+    /// `[...]`: inclusive ranges, negated with `!` or `^`.
+    Class { negated: bool, ranges: Vec<(char, char)> },
+}
+
+/// A parsed glob pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GlobPattern {
+    pattern: String,
+    tokens: Vec<Token>,
+    /// The pattern has no `/` and applies to the file name only.
+    file_name_only: bool,
+}
+
+impl GlobPattern {
+    /// Parses a pattern. Fails on an unterminated `[` set.
+    pub fn new(pattern: &str) -> io::Result<Self> {
+        let normalized = normalize_entry_path(pattern);
+        let mut tokens = Vec::new();
+        let mut chars = normalized.chars().peekable();
+        while let Some(c) = chars.next() {
+            let token = match c {
+                '?' => Token::AnyChar,
+                '*' if chars.next_if_eq(&'*').is_some() => {
+                    while chars.next_if_eq(&'*').is_some() {}
+                    Token::DoubleStar
+                }
+                '*' => Token::Star,
+                '[' => {
+                    let negated = chars.next_if(|c| *c == '!' || *c == '^').is_some();
+                    let mut ranges = Vec::new();
+                    loop {
+                        let start = match chars.next() {
+                            Some(']') if !ranges.is_empty() => break,
+                            Some(c) => c,
+                            None => {
+                                return Err(io::Error::new(
+                                    io::ErrorKind::InvalidInput,
+                                    format!("Unterminated [ in pattern {:?}", pattern),
+                                ))
+                            }
+                        };
+                        let end = match chars.peek() {
+                            Some('-') => {
+                                chars.next();
+                                match chars.next_if(|c| *c != ']') {
+                                    Some(end) => end,
+                                    None => {
+                                        // A trailing `-` is a literal dash.
+                                        ranges.push(('-', '-'));
+                                        start
+                                    }
+                                }
+                            }
+                            _ => start,
+                        };
+                        ranges.push((start, end));
+                    }
+                    Token::Class { negated, ranges }
+                }
+                c => Token::Literal(c),
+            };
+            tokens.push(token);
+        }
+        Ok(GlobPattern {
+            pattern: pattern.to_string(),
+            file_name_only: !normalized.contains('/'),
+            tokens,
+        })
+    }
+
+    /// The pattern as given.
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    /// True when the entry path matches the pattern.
+    pub fn matches(&self, path: &str) -> bool {
+        let path = normalize_entry_path(path);
+        let subject = match self.file_name_only {
+            true => path.rsplit('/').next().unwrap_or(&path),
+            false => &path,
+        };
+        let chars: Vec<char> = subject.chars().collect();
+        match_tokens(&self.tokens, &chars)
+    }
+}
+
+impl fmt::Display for GlobPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.pattern)
+    }
+}
+
+impl std::str::FromStr for GlobPattern {
+    type Err = io::Error;
+
+    fn from_str(pattern: &str) -> Result<Self, Self::Err> {
+        GlobPattern::new(pattern)
+    }
+}
+
+fn match_tokens(tokens: &[Token], path: &[char]) -> bool {
+    let Some((token, rest)) = tokens.split_first() else {
+        return path.is_empty();
+    };
+    match token {
+        Token::Literal(c) => path.first() == Some(c) && match_tokens(rest, &path[1..]),
+        Token::AnyChar => path.first().is_some_and(|c| *c != '/') && match_tokens(rest, &path[1..]),
+        Token::Class { negated, ranges } => {
+            path.first().is_some_and(|c| {
+                *c != '/' && ranges.iter().any(|(start, end)| (start..=end).contains(&c)) != *negated
+            }) && match_tokens(rest, &path[1..])
+        }
+        Token::Star => {
+            let segment = path.iter().position(|c| *c == '/').unwrap_or(path.len());
+            (0..=segment).any(|skip| match_tokens(rest, &path[skip..]))
+        }
+        Token::DoubleStar => {
+            // `**/` may also stand for no folder at all.
+            if rest.first() == Some(&Token::Literal('/')) && match_tokens(&rest[1..], path) {
+                return true;
+            }
+            (0..=path.len()).any(|skip| match_tokens(rest, &path[skip..]))
+        }
+    }
+}
@@ -0,0 +1,701 @@
+//! Converting DDS textures (album art, lyric fonts, UI atlases) to images other tools open,
+//! for asset pipelines as well as extraction.
+//!
+//! ```no_run
+//! use psarc_unpacker::images::{convert_dds, ImageFormat, ImageOptions};
+//! # fn main() -> std::io::Result<()> {
+//! let options = ImageOptions::new().format(ImageFormat::Jpeg { quality: 90 }).mip_level(1);
+//! let dds = std::fs::read("album_art_256.dds")?;
+//! std::fs::write(format!("album_art.{}", options.format.extension()), convert_dds(&dds, &options)?)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The encoders are small and dependency free: PNG keeps every pixel, JPEG is baseline
+//! without chroma subsampling (alpha is dropped), WebP is lossless (VP8L) without
+//! transforms or back references, so it is larger than what `cwebp` produces.
+
+use std::io::{self, Write};
+
+use flate2::write::ZlibEncoder;
+use flate2::{Compression, Crc};
+
+use crate::cache::{self, convert_cached, ConversionCache};
+use crate::dds::{decode_dds, paeth, RgbaImage};
+
+/// Output format of the converted textures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    /// Baseline JPEG of `quality` 1 to 100.
+    Jpeg { quality: u8 },
+    /// Lossless WebP.
+    WebP,
+}
+
+/// JPEG quality of `ImageFormat::from_str("jpeg")`.
+pub const DEFAULT_JPEG_QUALITY: u8 = 90;
+
+impl ImageFormat {
+    /// File extension of the format, without the dot.
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Jpeg { .. } => "jpg",
+            ImageFormat::WebP => "webp",
+        }
+    }
+}
+
+impl std::str::FromStr for ImageFormat {
+    type Err = String;
+
+    /// `png`, `webp`, `jpeg`/`jpg`, or `jpeg:<quality>`.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.to_ascii_lowercase();
+        let (name, quality) = match value.split_once(':') {
+            Some((name, quality)) => (name, Some(quality)),
+            None => (value.as_str(), None),
+        };
+        match (name, quality) {
+            ("png", None) => Ok(ImageFormat::Png),
+            ("webp", None) => Ok(ImageFormat::WebP),
+            ("jpeg" | "jpg", None) => Ok(ImageFormat::Jpeg { quality: DEFAULT_JPEG_QUALITY }),
+            ("jpeg" | "jpg", Some(quality)) => match quality.parse::<u8>() {
+                Ok(quality @ 1..=100) => Ok(ImageFormat::Jpeg { quality }),
+                _ => Err(format!("JPEG quality must be 1 to 100, got {:?}", quality)),
+            },
+            _ => Err(format!("Expected png, jpeg[:<quality>] or webp, got {:?}", value)),
+        }
+    }
+}
+
+/// How textures are converted: which surface of the DDS, to which format, and whether the
+/// DDS itself is kept next to the image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageOptions {
+    /// Mip level, 0 being the full size.
+    pub mip_level: u32,
+    /// Array layer, or cube face (`+X`, `-X`, `+Y`, `-Y`, `+Z`, `-Z`).
+    pub layer: u32,
+    pub format: ImageFormat,
+    /// Also write the DDS as stored in the archive.
+    pub keep_dds: bool,
+}
+
+impl Default for ImageOptions {
+    fn default() -> Self {
+        ImageOptions { mip_level: 0, layer: 0, format: ImageFormat::Png, keep_dds: false }
+    }
+}
+
+impl ImageOptions {
+    /// Options with the defaults: full size first layer, as PNG, without the DDS.
+    pub fn new() -> Self {
+        ImageOptions::default()
+    }
+
+    pub fn mip_level(mut self, mip_level: u32) -> Self {
+        self.mip_level = mip_level;
+        self
+    }
+
+    pub fn layer(mut self, layer: u32) -> Self {
+        self.layer = layer;
+        self
+    }
+
+    pub fn format(mut self, format: ImageFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn keep_dds(mut self, keep: bool) -> Self {
+        self.keep_dds = keep;
+        self
+    }
+}
+
+/// Decodes the surface of a DDS texture `options` selects and encodes it in their format.
+pub fn convert_dds(data: &[u8], options: &ImageOptions) -> io::Result<Vec<u8>> {
+    let image = decode_dds(data, options.mip_level, options.layer)?;
+    encode_image(&image, options.format)
+}
+
+/// `convert_dds` reusing the PNG conversions of `cache` and caching new ones. Other
+/// formats are converted every time.
+pub fn convert_dds_cached(data: &[u8], options: &ImageOptions, cache: Option<&ConversionCache>) -> io::Result<Vec<u8>> {
+    if options.format != ImageFormat::Png {
+        return convert_dds(data, options);
+    }
+    let kind = match (options.mip_level, options.layer) {
+        (0, 0) => cache::PNG.to_string(),
+        (mip_level, layer) => format!("{}-m{}-l{}", cache::PNG, mip_level, layer),
+    };
+    convert_cached(cache, &kind, data, |data| convert_dds(data, options))
+}
+
+/// Encodes `image` in `format`.
+pub fn encode_image(image: &RgbaImage, format: ImageFormat) -> io::Result<Vec<u8>> {
+    match format {
+        ImageFormat::Png => encode_png(image),
+        ImageFormat::Jpeg { quality } => Ok(encode_jpeg(image, quality)),
+        ImageFormat::WebP => encode_webp(image),
+    }
+}
+
+fn check_pixels(image: &RgbaImage) -> io::Result<()> {
+    if image.pixels.len() != image.width as usize * image.height as usize * 4 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Pixel buffer does not match the image size"));
+    }
+    Ok(())
+}
+
+// --- PNG ---
+
+/// Appends a PNG chunk with its length and CRC.
+fn png_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let mut crc = Crc::new();
+    crc.update(kind);
+    crc.update(data);
+    png.extend_from_slice(&crc.sum().to_be_bytes());
+}
+
+/// Encodes `image` as an 8-bit RGBA PNG. Each row takes the filter whose output has the
+/// smallest sum of absolute values, the usual heuristic.
+pub fn encode_png(image: &RgbaImage) -> io::Result<Vec<u8>> {
+    check_pixels(image)?;
+    let stride = image.width as usize * 4;
+    let mut filtered = Vec::with_capacity((stride + 1) * image.height as usize);
+    let zero_row = vec![0u8; stride];
+    let mut candidate = vec![0u8; stride];
+    let mut best = vec![0u8; stride];
+    for y in 0..image.height as usize {
+        let row = &image.pixels[y * stride..(y + 1) * stride];
+        let above = if y == 0 { &zero_row[..] } else { &image.pixels[(y - 1) * stride..y * stride] };
+        let mut best_filter = 0u8;
+        let mut best_cost = u64::MAX;
+        for filter in 0..5u8 {
+            for i in 0..stride {
+                let left = if i >= 4 { row[i - 4] } else { 0 };
+                let upper_left = if i >= 4 { above[i - 4] } else { 0 };
+                let predicted = match filter {
+                    0 => 0,
+                    1 => left,
+                    2 => above[i],
+                    3 => ((left as u16 + above[i] as u16) / 2) as u8,
+                    _ => paeth(left, above[i], upper_left),
+                };
+                candidate[i] = row[i].wrapping_sub(predicted);
+            }
+            let cost = candidate.iter().map(|&v| (v as i8).unsigned_abs() as u64).sum();
+            if cost < best_cost {
+                best_cost = cost;
+                best_filter = filter;
+                best.copy_from_slice(&candidate);
+            }
+        }
+        filtered.push(best_filter);
+        filtered.extend_from_slice(&best);
+    }
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(&filtered)?;
+    let compressed = encoder.finish()?;
+
+    let mut png = vec![0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&image.width.to_be_bytes());
+    header.extend_from_slice(&image.height.to_be_bytes());
+    // 8 bits per channel, RGBA, deflate, adaptive filtering, no interlacing.
+    header.extend_from_slice(&[8, 6, 0, 0, 0]);
+    png_chunk(&mut png, b"IHDR", &header);
+    png_chunk(&mut png, b"IDAT", &compressed);
+    png_chunk(&mut png, b"IEND", &[]);
+    Ok(png)
+}
+
+// --- Huffman codes (JPEG, WebP) ---
+
+/// Code lengths of a Huffman code for `counts`, none longer than `max_length`. Symbols
+/// with a zero count get no code. When the optimal code is too deep, the smallest counts
+/// are raised until it fits, as zlib and libwebp do.
+fn huffman_lengths(counts: &[u32], max_length: u8) -> Vec<u8> {
+    let mut lengths = vec![0u8; counts.len()];
+    let used: Vec<usize> = (0..counts.len()).filter(|&s| counts[s] > 0).collect();
+    match used.len() {
+        0 => return lengths,
+        1 => {
+            lengths[used[0]] = 1;
+            return lengths;
+        }
+        _ => {}
+    }
+    let mut floor = 1u32;
+    loop {
+        // Nodes: (weight, symbols below). Merging the two lightest deepens their symbols.
+        let mut nodes: Vec<(u64, Vec<usize>)> = used.iter().map(|&s| (counts[s].max(floor) as u64, vec![s])).collect();
+        let mut depths = vec![0u8; counts.len()];
+        while nodes.len() > 1 {
+            nodes.sort_by_key(|node| std::cmp::Reverse(node.0));
+            let (wa, a) = nodes.pop().unwrap();
+            let (wb, b) = nodes.pop().unwrap();
+            let mut merged = a;
+            merged.extend(b);
+            for &s in &merged {
+                depths[s] += 1;
+            }
+            nodes.push((wa + wb, merged));
+        }
+        if depths.iter().all(|&d| d <= max_length) {
+            return depths;
+        }
+        floor = floor.saturating_mul(2);
+    }
+}
+
+/// Canonical codes for `lengths`, as in DEFLATE: shorter codes first, then by symbol.
+fn canonical_codes(lengths: &[u8]) -> Vec<u16> {
+    let max = lengths.iter().copied().max().unwrap_or(0) as usize;
+    let mut per_length = vec![0u16; max + 1];
+    for &length in lengths.iter().filter(|&&l| l > 0) {
+        per_length[length as usize] += 1;
+    }
+    let mut next = vec![0u16; max + 2];
+    let mut code = 0u16;
+    for length in 1..=max {
+        code = (code + per_length[length - 1]) << 1;
+        next[length] = code;
+    }
+    lengths
+        .iter()
+        .map(|&length| {
+            if length == 0 {
+                return 0;
+            }
+            let code = next[length as usize];
+            next[length as usize] += 1;
+            code
+        })
+        .collect()
+}
+
+// --- JPEG ---
+
+/// Quantization tables of the JPEG standard (Annex K), in natural order.
+const LUMA_QUANT: [u8; 64] = [
+    16, 11, 10, 16, 24, 40, 51, 61, 12, 12, 14, 19, 26, 58, 60, 55, 14, 13, 16, 24, 40, 57, 69, 56, 14, 17, 22, 29, 51,
+    87, 80, 62, 18, 22, 37, 56, 68, 109, 103, 77, 24, 35, 55, 64, 81, 104, 113, 92, 49, 64, 78, 87, 103, 121, 120, 101,
+    72, 92, 95, 98, 112, 100, 103, 99,
+];
+const CHROMA_QUANT: [u8; 64] = [
+    17, 18, 24, 47, 99, 99, 99, 99, 18, 21, 26, 66, 99, 99, 99, 99, 24, 26, 56, 99, 99, 99, 99, 99, 47, 66, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99,
+];
+
+/// Position in natural order of each zigzag index.
+const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20, 13, 6, 7, 14, 21, 28,
+    35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59, 52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47,
+    55, 62, 63,
+];
+
+/// Huffman tables of the JPEG standard (Annex K): code counts per length 1 to 16, then the
+/// symbols.
+const LUMA_DC: ([u8; 16], &[u8]) = ([0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0], &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11]);
+const CHROMA_DC: ([u8; 16], &[u8]) =
+    ([0, 3, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0], &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11]);
+const LUMA_AC: ([u8; 16], &[u8]) = (
+    [0, 2, 1, 3, 3, 2, 4, 3, 5, 5, 4, 4, 0, 0, 1, 0x7D],
+    &[
+        0x01, 0x02, 0x03, 0x00, 0x04, 0x11, 0x05, 0x12, 0x21, 0x31, 0x41, 0x06, 0x13, 0x51, 0x61, 0x07, 0x22, 0x71, 0x14,
+        0x32, 0x81, 0x91, 0xA1, 0x08, 0x23, 0x42, 0xB1, 0xC1, 0x15, 0x52, 0xD1, 0xF0, 0x24, 0x33, 0x62, 0x72, 0x82, 0x09,
+        0x0A, 0x16, 0x17, 0x18, 0x19, 0x1A, 0x25, 0x26, 0x27, 0x28, 0x29, 0x2A, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3A,
+        0x43, 0x44, 0x45, 0x46, 0x47, 0x48, 0x49, 0x4A, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5A, 0x63, 0x64, 0x65,
+        0x66, 0x67, 0x68, 0x69, 0x6A, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7A, 0x83, 0x84, 0x85, 0x86, 0x87, 0x88,
+        0x89, 0x8A, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9A, 0xA2, 0xA3, 0xA4, 0xA5, 0xA6, 0xA7, 0xA8, 0xA9,
+        0xAA, 0xB2, 0xB3, 0xB4, 0xB5, 0xB6, 0xB7, 0xB8, 0xB9, 0xBA, 0xC2, 0xC3, 0xC4, 0xC5, 0xC6, 0xC7, 0xC8, 0xC9, 0xCA,
+        0xD2, 0xD3, 0xD4, 0xD5, 0xD6, 0xD7, 0xD8, 0xD9, 0xDA, 0xE1, 0xE2, 0xE3, 0xE4, 0xE5, 0xE6, 0xE7, 0xE8, 0xE9, 0xEA,
+        0xF1, 0xF2, 0xF3, 0xF4, 0xF5, 0xF6, 0xF7, 0xF8, 0xF9, 0xFA,
+    ],
+);
+const CHROMA_AC: ([u8; 16], &[u8]) = (
+    [0, 2, 1, 2, 4, 4, 3, 4, 7, 5, 4, 4, 0, 1, 2, 0x77],
+    &[
+        0x00, 0x01, 0x02, 0x03, 0x11, 0x04, 0x05, 0x21, 0x31, 0x06, 0x12, 0x41, 0x51, 0x07, 0x61, 0x71, 0x13, 0x22, 0x32,
+        0x81, 0x08, 0x14, 0x42, 0x91, 0xA1, 0xB1, 0xC1, 0x09, 0x23, 0x33, 0x52, 0xF0, 0x15, 0x62, 0x72, 0xD1, 0x0A, 0x16,
+        0x24, 0x34, 0xE1, 0x25, 0xF1, 0x17, 0x18, 0x19, 0x1A, 0x26, 0x27, 0x28, 0x29, 0x2A, 0x35, 0x36, 0x37, 0x38, 0x39,
+        0x3A, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, 0x49, 0x4A, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5A, 0x63, 0x64,
+        0x65, 0x66, 0x67, 0x68, 0x69, 0x6A, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7A, 0x82, 0x83, 0x84, 0x85, 0x86,
+        0x87, 0x88, 0x89, 0x8A, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9A, 0xA2, 0xA3, 0xA4, 0xA5, 0xA6, 0xA7,
+        0xA8, 0xA9, 0xAA, 0xB2, 0xB3, 0xB4, 0xB5, 0xB6, 0xB7, 0xB8, 0xB9, 0xBA, 0xC2, 0xC3, 0xC4, 0xC5, 0xC6, 0xC7, 0xC8,
+        0xC9, 0xCA, 0xD2, 0xD3, 0xD4, 0xD5, 0xD6, 0xD7, 0xD8, 0xD9, 0xDA, 0xE2, 0xE3, 0xE4, 0xE5, 0xE6, 0xE7, 0xE8, 0xE9,
+        0xEA, 0xF2, 0xF3, 0xF4, 0xF5, 0xF6, 0xF7, 0xF8, 0xF9, 0xFA,
+    ],
+);
+
+/// Codes and lengths by symbol of a JPEG Huffman table.
+struct JpegTable {
+    codes: [u16; 256],
+    lengths: [u8; 256],
+}
+
+impl JpegTable {
+    fn new((counts, symbols): ([u8; 16], &[u8])) -> Self {
+        let mut table = JpegTable { codes: [0; 256], lengths: [0; 256] };
+        let mut code = 0u16;
+        let mut symbols = symbols.iter();
+        for (length, &count) in counts.iter().enumerate() {
+            for _ in 0..count {
+                let symbol = *symbols.next().unwrap() as usize;
+                table.codes[symbol] = code;
+                table.lengths[symbol] = length as u8 + 1;
+                code += 1;
+            }
+            code <<= 1;
+        }
+        table
+    }
+}
+
+/// Big-endian bit writer with the 0xFF byte stuffing of JPEG entropy-coded data.
+struct JpegBits {
+    out: Vec<u8>,
+    buffer: u32,
+    count: u32,
+}
+
+impl JpegBits {
+    fn put(&mut self, value: u32, bits: u32) {
+        for i in (0..bits).rev() {
+            self.buffer = (self.buffer << 1) | ((value >> i) & 1);
+            self.count += 1;
+            if self.count == 8 {
+                let byte = self.buffer as u8;
+                self.out.push(byte);
+                if byte == 0xFF {
+                    self.out.push(0);
+                }
+                self.buffer = 0;
+                self.count = 0;
+            }
+        }
+    }
+
+    fn code(&mut self, table: &JpegTable, symbol: u8) {
+        self.put(table.codes[symbol as usize] as u32, table.lengths[symbol as usize] as u32);
+    }
+
+    /// Pads the last byte with ones.
+    fn flush(&mut self) {
+        if self.count > 0 {
+            self.put(0x7F, 8 - self.count);
+        }
+    }
+}
+
+/// Size category of a coefficient and its bits, as coded after the Huffman symbol.
+fn magnitude(value: i32) -> (u32, u32) {
+    let size = 32 - value.unsigned_abs().leading_zeros();
+    let bits = if value < 0 { (value - 1) as u32 & ((1 << size) - 1) } else { value as u32 };
+    (size, bits)
+}
+
+/// Quantization table scaled for `quality` the way libjpeg does.
+fn scaled_quant(base: &[u8; 64], quality: u8) -> [u8; 64] {
+    let quality = quality.clamp(1, 100) as u32;
+    let scale = if quality < 50 { 5000 / quality } else { 200 - quality * 2 };
+    base.map(|q| ((q as u32 * scale + 50) / 100).clamp(1, 255) as u8)
+}
+
+/// DCT basis: `basis[u][x]` weighs sample `x` into frequency `u`, normalization included.
+fn dct_basis() -> [[f32; 8]; 8] {
+    let mut basis = [[0f32; 8]; 8];
+    for (u, row) in basis.iter_mut().enumerate() {
+        let scale = if u == 0 { 0.5 * std::f32::consts::FRAC_1_SQRT_2 } else { 0.5 };
+        for (x, value) in row.iter_mut().enumerate() {
+            *value = scale * (((2 * x + 1) * u) as f32 * std::f32::consts::PI / 16.0).cos();
+        }
+    }
+    basis
+}
+
+/// Forward DCT of an 8x8 block of samples centred on zero, rows then columns.
+fn fdct(block: &[f32; 64], basis: &[[f32; 8]; 8]) -> [f32; 64] {
+    let mut rows = [0f32; 64];
+    for y in 0..8 {
+        for (u, weights) in basis.iter().enumerate() {
+            rows[y * 8 + u] = (0..8).map(|x| block[y * 8 + x] * weights[x]).sum();
+        }
+    }
+    let mut out = [0f32; 64];
+    for u in 0..8 {
+        for (v, weights) in basis.iter().enumerate() {
+            out[v * 8 + u] = (0..8).map(|y| rows[y * 8 + u] * weights[y]).sum();
+        }
+    }
+    out
+}
+
+/// Encodes `image` as a baseline JPEG of `quality` (1 to 100), in YCbCr without chroma
+/// subsampling. Alpha is dropped.
+pub fn encode_jpeg(image: &RgbaImage, quality: u8) -> Vec<u8> {
+    let quants = [scaled_quant(&LUMA_QUANT, quality), scaled_quant(&CHROMA_QUANT, quality)];
+    let mut jpeg = vec![0xFF, 0xD8];
+    let segment = |jpeg: &mut Vec<u8>, marker: u8, data: &[u8]| {
+        jpeg.extend_from_slice(&[0xFF, marker]);
+        jpeg.extend_from_slice(&(data.len() as u16 + 2).to_be_bytes());
+        jpeg.extend_from_slice(data);
+    };
+    segment(&mut jpeg, 0xE0, b"JFIF\0\x01\x01\0\0\x01\0\x01\0\0");
+    for (id, quant) in quants.iter().enumerate() {
+        let mut data = vec![id as u8];
+        data.extend(ZIGZAG.iter().map(|&i| quant[i]));
+        segment(&mut jpeg, 0xDB, &data);
+    }
+    let (width, height) = (image.width.min(u16::MAX as u32) as u16, image.height.min(u16::MAX as u32) as u16);
+    let mut frame = vec![8];
+    frame.extend_from_slice(&height.to_be_bytes());
+    frame.extend_from_slice(&width.to_be_bytes());
+    // Three components, 1x1 sampling, luma on table 0, chroma on table 1.
+    frame.extend_from_slice(&[3, 1, 0x11, 0, 2, 0x11, 1, 3, 0x11, 1]);
+    segment(&mut jpeg, 0xC0, &frame);
+    for (class_id, (counts, symbols)) in [(0x00, LUMA_DC), (0x10, LUMA_AC), (0x01, CHROMA_DC), (0x11, CHROMA_AC)] {
+        let mut data = vec![class_id];
+        data.extend_from_slice(&counts);
+        data.extend_from_slice(symbols);
+        segment(&mut jpeg, 0xC4, &data);
+    }
+    segment(&mut jpeg, 0xDA, &[3, 1, 0x00, 2, 0x11, 3, 0x11, 0, 63, 0]);
+
+    let dc = [JpegTable::new(LUMA_DC), JpegTable::new(CHROMA_DC)];
+    let ac = [JpegTable::new(LUMA_AC), JpegTable::new(CHROMA_AC)];
+    let basis = dct_basis();
+    let mut bits = JpegBits { out: Vec::new(), buffer: 0, count: 0 };
+    let mut previous_dc = [0i32; 3];
+    let (width, height) = (width as u32, height as u32);
+    for by in (0..height).step_by(8) {
+        for bx in (0..width).step_by(8) {
+            let mut planes = [[0f32; 64]; 3];
+            for i in 0..64 {
+                // Edge blocks repeat the last row/column.
+                let x = (bx + i as u32 % 8).min(width - 1);
+                let y = (by + i as u32 / 8).min(height - 1);
+                let at = ((y * image.width + x) * 4) as usize;
+                let [r, g, b] = [0, 1, 2].map(|c| image.pixels[at + c] as f32);
+                let ycbcr = [
+                    0.299 * r + 0.587 * g + 0.114 * b - 128.0,
+                    -0.168_736 * r - 0.331_264 * g + 0.5 * b,
+                    0.5 * r - 0.418_688 * g - 0.081_312 * b,
+                ];
+                for (plane, value) in planes.iter_mut().zip(ycbcr) {
+                    plane[i] = value;
+                }
+            }
+            for (component, plane) in planes.iter().enumerate() {
+                let table = component.min(1);
+                let coefficients = fdct(plane, &basis);
+                let quantized: Vec<i32> =
+                    ZIGZAG.iter().map(|&i| (coefficients[i] / quants[table][i] as f32).round() as i32).collect();
+                let (size, value) = magnitude(quantized[0] - previous_dc[component]);
+                previous_dc[component] = quantized[0];
+                bits.code(&dc[table], size as u8);
+                bits.put(value, size);
+                let mut run = 0;
+                for &coefficient in &quantized[1..] {
+                    if coefficient == 0 {
+                        run += 1;
+                        continue;
+                    }
+                    while run > 15 {
+                        bits.code(&ac[table], 0xF0);
+                        run -= 16;
+                    }
+                    let (size, value) = magnitude(coefficient);
+                    bits.code(&ac[table], ((run << 4) | size) as u8);
+                    bits.put(value, size);
+                    run = 0;
+                }
+                if run > 0 {
+                    bits.code(&ac[table], 0x00);
+                }
+            }
+        }
+    }
+    bits.flush();
+    jpeg.extend_from_slice(&bits.out);
+    jpeg.extend_from_slice(&[0xFF, 0xD9]);
+    jpeg
+}
+
+// --- WebP ---
+
+/// Largest width and height of a WebP image.
+const WEBP_MAX_SIZE: u32 = 16384;
+
+/// Order in which the code length code lengths are stored in VP8L.
+const CODE_LENGTH_ORDER: [usize; 19] = [17, 18, 0, 1, 2, 3, 4, 5, 16, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+
+/// Little-endian bit writer of the VP8L bitstream.
+struct WebpBits {
+    out: Vec<u8>,
+    buffer: u64,
+    count: u32,
+}
+
+impl WebpBits {
+    fn put(&mut self, value: u32, bits: u32) {
+        self.buffer |= (value as u64) << self.count;
+        self.count += bits;
+        while self.count >= 8 {
+            self.out.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.count > 0 {
+            self.out.push(self.buffer as u8);
+            self.buffer = 0;
+            self.count = 0;
+        }
+    }
+}
+
+/// A prefix code of the VP8L bitstream, ready to write symbols with.
+struct WebpCode {
+    /// Codes with their bits reversed, as the stream is read least significant bit first.
+    codes: Vec<u16>,
+    lengths: Vec<u8>,
+}
+
+impl WebpCode {
+    /// The canonical code of `lengths`. A single used symbol is written with no bits at
+    /// all, as the decoder reads it.
+    fn from_lengths(lengths: &[u8]) -> Self {
+        let codes = canonical_codes(lengths)
+            .iter()
+            .zip(lengths)
+            .map(|(&code, &length)| if length == 0 { 0 } else { code.reverse_bits() >> (16 - length as u32) })
+            .collect();
+        let mut lengths = lengths.to_vec();
+        if lengths.iter().filter(|&&l| l > 0).count() == 1 {
+            lengths.fill(0);
+        }
+        WebpCode { codes, lengths }
+    }
+
+    fn put(&self, bits: &mut WebpBits, symbol: usize) {
+        bits.put(self.codes[symbol] as u32, self.lengths[symbol] as u32);
+    }
+
+    /// Writes the code itself: a simple code for one or two symbols below 256, otherwise
+    /// the code lengths coded with a code length code.
+    fn write(counts: &[u32], bits: &mut WebpBits) -> Self {
+        let used: Vec<usize> = (0..counts.len()).filter(|&s| counts[s] > 0).collect();
+        if used.len() <= 2 && used.iter().all(|&s| s < 256) {
+            let first = used.first().copied().unwrap_or(0);
+            bits.put(1, 1);
+            bits.put(used.len().saturating_sub(1) as u32, 1);
+            if first < 2 {
+                bits.put(0, 1);
+                bits.put(first as u32, 1);
+            } else {
+                bits.put(1, 1);
+                bits.put(first as u32, 8);
+            }
+            if let Some(&second) = used.get(1) {
+                bits.put(second as u32, 8);
+            }
+            // The first symbol reads as bit 0, the second as bit 1.
+            let mut lengths = vec![0u8; counts.len()];
+            let mut codes = vec![0u16; counts.len()];
+            if let Some(&second) = used.get(1) {
+                lengths[first] = 1;
+                lengths[second] = 1;
+                codes[second] = 1;
+            }
+            return WebpCode { codes, lengths };
+        }
+        let lengths = huffman_lengths(counts, 15);
+        let mut length_counts = [0u32; 19];
+        for &length in &lengths {
+            length_counts[length as usize] += 1;
+        }
+        let length_code_lengths = huffman_lengths(&length_counts, 7);
+        let length_code = WebpCode::from_lengths(&length_code_lengths);
+        let stored = CODE_LENGTH_ORDER.iter().rposition(|&i| length_code_lengths[i] > 0).map_or(4, |last| (last + 1).max(4));
+        bits.put(0, 1);
+        bits.put(stored as u32 - 4, 4);
+        for &i in &CODE_LENGTH_ORDER[..stored] {
+            bits.put(length_code_lengths[i] as u32, 3);
+        }
+        // Every symbol's length follows (no max_symbol).
+        bits.put(0, 1);
+        for &length in &lengths {
+            length_code.put(bits, length as usize);
+        }
+        WebpCode::from_lengths(&lengths)
+    }
+}
+
+/// Encodes `image` as a lossless WebP. Images over 16384 pixels wide or high, the format's
+/// limit, are rejected with `ErrorKind::InvalidInput`.
+pub fn encode_webp(image: &RgbaImage) -> io::Result<Vec<u8>> {
+    check_pixels(image)?;
+    if image.width == 0 || image.height == 0 || image.width > WEBP_MAX_SIZE || image.height > WEBP_MAX_SIZE {
+        let message = format!("WebP images are 1 to {} pixels a side, not {}x{}", WEBP_MAX_SIZE, image.width, image.height);
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+    }
+    let mut histograms = [[0u32; 256]; 4];
+    for px in image.pixels.chunks_exact(4) {
+        // Green, red, blue, alpha: the order of the prefix codes.
+        for (histogram, channel) in histograms.iter_mut().zip([px[1], px[0], px[2], px[3]]) {
+            histogram[channel as usize] += 1;
+        }
+    }
+    let alpha_used = histograms[3][255] as usize != image.pixels.len() / 4;
+
+    let mut bits = WebpBits { out: Vec::new(), buffer: 0, count: 0 };
+    bits.put(0x2F, 8);
+    bits.put(image.width - 1, 14);
+    bits.put(image.height - 1, 14);
+    bits.put(alpha_used as u32, 1);
+    bits.put(0, 3);
+    // No transform, no colour cache, one prefix code group.
+    bits.put(0, 1);
+    bits.put(0, 1);
+    bits.put(0, 1);
+    let mut green_counts = histograms[0].to_vec();
+    // Length prefixes and colour cache indexes share the green alphabet, unused here.
+    green_counts.resize(256 + 24, 0);
+    let green = WebpCode::write(&green_counts, &mut bits);
+    let red = WebpCode::write(&histograms[1], &mut bits);
+    let blue = WebpCode::write(&histograms[2], &mut bits);
+    let alpha = WebpCode::write(&histograms[3], &mut bits);
+    WebpCode::write(&[1], &mut bits);
+    for px in image.pixels.chunks_exact(4) {
+        green.put(&mut bits, px[1] as usize);
+        red.put(&mut bits, px[0] as usize);
+        blue.put(&mut bits, px[2] as usize);
+        alpha.put(&mut bits, px[3] as usize);
+    }
+    bits.flush();
+
+    let mut chunk = bits.out;
+    let chunk_size = chunk.len() as u32;
+    if chunk.len() % 2 == 1 {
+        chunk.push(0);
+    }
+    let mut webp = Vec::with_capacity(chunk.len() + 20);
+    webp.extend_from_slice(b"RIFF");
+    webp.extend_from_slice(&(chunk.len() as u32 + 12).to_le_bytes());
+    webp.extend_from_slice(b"WEBPVP8L");
+    webp.extend_from_slice(&chunk_size.to_le_bytes());
+    webp.extend_from_slice(&chunk);
+    Ok(webp)
+}
@@ -0,0 +1,166 @@
+//! Persistent state of a batch extraction, so an interrupted run picks up where it stopped.
+//!
+//! The state is a small JSON file listing, per archive, a fingerprint of the archive file,
+//! its status and every output written so far with the MD5 of its content. Archives marked
+//! done with an unchanged fingerprint are skipped without being opened; for the archive that
+//! was interrupted, outputs whose file still matches the recorded hash are kept.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use serde::{Deserialize, Serialize};
+
+use crate::md5::{md5, to_hex};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ArchiveStatus {
+    /// Extraction started but did not finish (the run was interrupted).
+    InProgress,
+    /// Every selected entry was extracted and converted.
+    Done,
+    /// Extraction finished with failures or skipped entries; retried on the next run.
+    Incomplete,
+}
+
+/// An output written for an entry.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutputState {
+    pub path: PathBuf,
+    /// Uppercase hex MD5 of the written content.
+    pub md5: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchiveState {
+    /// Size and modification time of the archive file when it was extracted.
+    pub fingerprint: String,
+    pub status: ArchiveStatus,
+    /// Outputs written so far, keyed by entry path (`<entry>.json` for SNG conversions).
+    pub outputs: BTreeMap<String, OutputState>,
+}
+
+/// Job state of a batch extraction, keyed by archive path.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct JobState {
+    pub archives: BTreeMap<PathBuf, ArchiveState>,
+}
+
+/// On-disk form: the state and the MD5 of its serialization, to notice a damaged file.
+#[derive(Serialize, Deserialize)]
+struct StateFile {
+    checksum: String,
+    state: JobState,
+}
+
+/// Cheap identity of an archive file: its size and modification time.
+pub fn fingerprint(archive: &Path) -> io::Result<String> {
+    let metadata = fs::metadata(archive)?;
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_nanos());
+    Ok(format!("{}:{}", metadata.len(), modified))
+}
+
+impl JobState {
+    /// Reads a state file. A missing file is an empty state; a damaged one (bad JSON or
+    /// checksum mismatch) is discarded with a warning, which only costs re-extraction.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(JobState::default()),
+            Err(err) => return Err(err),
+        };
+        let file: StateFile = match serde_json::from_str(&text) {
+            Ok(file) => file,
+            Err(err) => {
+                tracing::warn!("Ignoring unreadable job state {:?}: {}", path, err);
+                return Ok(JobState::default());
+            }
+        };
+        let body = serde_json::to_string(&file.state).map_err(io::Error::other)?;
+        if to_hex(&md5(body.as_bytes())) != file.checksum {
+            tracing::warn!("Ignoring job state {:?}: checksum mismatch", path);
+            return Ok(JobState::default());
+        }
+        Ok(file.state)
+    }
+
+    /// Writes the state file atomically: a temporary file next to it is renamed over it, so
+    /// a crash leaves either the old or the new state.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let body = serde_json::to_string(self).map_err(io::Error::other)?;
+        let file = StateFile {
+            checksum: to_hex(&md5(body.as_bytes())),
+            state: self.clone(),
+        };
+        let json = serde_json::to_string(&file).map_err(io::Error::other)?;
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+        fs::write(&temp, json)?;
+        fs::rename(&temp, path)
+    }
+
+    /// True when the archive was fully extracted by an earlier run and has not changed since.
+    pub fn is_done(&self, archive: &Path, fingerprint: &str) -> bool {
+        self.archives
+            .get(archive)
+            .is_some_and(|a| a.status == ArchiveStatus::Done && a.fingerprint == fingerprint)
+    }
+
+    /// Marks an archive as being extracted. Outputs recorded by an interrupted run are kept
+    /// when the archive is unchanged, and dropped otherwise.
+    pub fn start(&mut self, archive: &Path, fingerprint: &str) {
+        let state = self.archives.entry(archive.to_path_buf()).or_insert_with(|| ArchiveState {
+            fingerprint: fingerprint.to_string(),
+            status: ArchiveStatus::InProgress,
+            outputs: BTreeMap::new(),
+        });
+        if state.fingerprint != fingerprint {
+            state.fingerprint = fingerprint.to_string();
+            state.outputs.clear();
+        }
+        state.status = ArchiveStatus::InProgress;
+    }
+
+    /// Output recorded for an entry of an archive, if any.
+    pub fn output(&self, archive: &Path, entry: &str) -> Option<&OutputState> {
+        self.archives.get(archive)?.outputs.get(entry)
+    }
+
+    /// True when the recorded output of an entry is still on disk with the recorded content.
+    pub fn output_intact(&self, archive: &Path, entry: &str) -> bool {
+        self.output(archive, entry).is_some_and(|output| {
+            fs::read(&output.path).is_ok_and(|data| to_hex(&md5(&data)) == output.md5)
+        })
+    }
+
+    /// Records an output written for an entry.
+    pub fn record(&mut self, archive: &Path, entry: &str, output: &Path, data: &[u8]) {
+        self.record_digest(archive, entry, output, &md5(data));
+    }
+
+    /// `record` for an output whose MD5 was computed while it was written.
+    pub fn record_digest(&mut self, archive: &Path, entry: &str, output: &Path, digest: &[u8; 16]) {
+        if let Some(state) = self.archives.get_mut(archive) {
+            state.outputs.insert(
+                entry.to_string(),
+                OutputState { path: output.to_path_buf(), md5: to_hex(digest) },
+            );
+        }
+    }
+
+    pub fn finish(&mut self, archive: &Path, complete: bool) {
+        if let Some(state) = self.archives.get_mut(archive) {
+            state.status = if complete { ArchiveStatus::Done } else { ArchiveStatus::Incomplete };
+        }
+    }
+
+    /// Every output path recorded, for every archive.
+    pub fn output_paths(&self) -> impl Iterator<Item = &Path> {
+        self.archives.values().flat_map(|a| a.outputs.values().map(|o| o.path.as_path()))
+    }
+}
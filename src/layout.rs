@@ -0,0 +1,167 @@
+//! Folder conventions of packages per platform, with user overrides.
+//!
+//! Packages keep their audio, charts and art in platform specific folders
+//! (`audio/windows`, `songs/bin/macos`, ...). The code asks this table instead of
+//! matching folder names itself, so a package with an unusual layout, or a platform not
+//! known here, is supported by an overrides file rather than a code change. The file is
+//! a JSON object of platform names to folders; missing folders keep their built-in value:
+//!
+//! ```json
+//! { "pc": { "album_art": "gfxassets/custom_art" },
+//!   "switch": { "audio": "audio/switch", "charts": "songs/bin/switch" } }
+//! ```
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, OnceLock, RwLock};
+use serde::{Deserialize, Serialize};
+
+/// Folders of one platform, as lowercase paths relative to the archive root. An empty
+/// folder matches no entry.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FolderLayout {
+    /// Wwise soundbanks and streams.
+    pub audio: String,
+    /// SNG arrangements.
+    pub charts: String,
+    /// XML arrangements, when a package ships them.
+    pub xml_charts: String,
+    /// Album art textures.
+    pub album_art: String,
+}
+
+impl FolderLayout {
+    fn new(audio: &str, charts: &str) -> Self {
+        FolderLayout {
+            audio: audio.to_string(),
+            charts: charts.to_string(),
+            xml_charts: "songs/arr".to_string(),
+            album_art: "gfxassets/album_art".to_string(),
+        }
+    }
+
+    /// This layout with the non-empty folders of `overrides` applied.
+    fn merged(mut self, overrides: FolderLayout) -> Self {
+        for (folder, value) in [
+            (&mut self.audio, overrides.audio),
+            (&mut self.charts, overrides.charts),
+            (&mut self.xml_charts, overrides.xml_charts),
+            (&mut self.album_art, overrides.album_art),
+        ] {
+            if !value.is_empty() {
+                *folder = value.trim_matches('/').to_lowercase();
+            }
+        }
+        self
+    }
+}
+
+/// True when `path` lies inside `folder`.
+fn in_folder(path: &str, folder: &str) -> bool {
+    !folder.is_empty()
+        && path.len() > folder.len()
+        && path.as_bytes()[folder.len()] == b'/'
+        && path[..folder.len()].eq_ignore_ascii_case(folder)
+}
+
+/// The folder table of every known platform.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PackageLayouts {
+    platforms: BTreeMap<String, FolderLayout>,
+}
+
+impl Default for PackageLayouts {
+    /// The layouts of the PC, Mac, Xbox 360 and PS3 packages.
+    fn default() -> Self {
+        let platforms = [
+            ("pc", FolderLayout::new("audio/windows", "songs/bin/generic")),
+            ("mac", FolderLayout::new("audio/mac", "songs/bin/macos")),
+            ("xbox360", FolderLayout::new("audio/xbox360", "songs/bin/xbox360")),
+            ("ps3", FolderLayout::new("audio/ps3", "songs/bin/ps3")),
+        ];
+        PackageLayouts { platforms: platforms.into_iter().map(|(name, layout)| (name.to_string(), layout)).collect() }
+    }
+}
+
+impl PackageLayouts {
+    pub fn new() -> Self {
+        PackageLayouts::default()
+    }
+
+    /// Sets (or adds) the folders of `platform`; empty folders of `layout` keep the
+    /// current value.
+    pub fn platform(mut self, platform: &str, layout: FolderLayout) -> Self {
+        let platform = platform.to_lowercase();
+        let current = self.platforms.remove(&platform).unwrap_or_default();
+        self.platforms.insert(platform, current.merged(layout));
+        self
+    }
+
+    /// Applies the overrides of a JSON file (see the module documentation).
+    pub fn with_overrides_file(self, path: &Path) -> io::Result<Self> {
+        let overrides: BTreeMap<String, FolderLayout> = serde_json::from_slice(&fs::read(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Bad layout file {:?}: {}", path, e)))?;
+        Ok(overrides.into_iter().fold(self, |layouts, (platform, layout)| layouts.platform(&platform, layout)))
+    }
+
+    pub fn get(&self, platform: &str) -> Option<&FolderLayout> {
+        self.platforms.get(&platform.to_lowercase())
+    }
+
+    pub fn platforms(&self) -> impl Iterator<Item = (&str, &FolderLayout)> {
+        self.platforms.iter().map(|(name, layout)| (name.as_str(), layout))
+    }
+
+    /// Platform whose audio or chart folder holds `path`.
+    pub fn platform_of(&self, path: &str) -> Option<&str> {
+        self.platforms()
+            .find(|(_, layout)| in_folder(path, &layout.audio) || in_folder(path, &layout.charts))
+            .map(|(name, _)| name)
+    }
+
+    /// Platform of a package, from the first of its entry paths in a platform folder.
+    pub fn detect_platform<'p>(&self, mut paths: impl Iterator<Item = &'p str>) -> Option<&str> {
+        paths.find_map(|path| self.platform_of(path))
+    }
+
+    fn any(&self, path: &str, folder: impl Fn(&FolderLayout) -> &str) -> bool {
+        self.platforms.values().any(|layout| in_folder(path, folder(layout)))
+    }
+
+    pub fn is_audio(&self, path: &str) -> bool {
+        self.any(path, |l| &l.audio)
+    }
+
+    pub fn is_chart(&self, path: &str) -> bool {
+        self.any(path, |l| &l.charts)
+    }
+
+    pub fn is_xml_chart(&self, path: &str) -> bool {
+        self.any(path, |l| &l.xml_charts)
+    }
+
+    pub fn is_album_art(&self, path: &str) -> bool {
+        self.any(path, |l| &l.album_art)
+    }
+
+    fn slot() -> &'static RwLock<Arc<PackageLayouts>> {
+        static LAYOUTS: OnceLock<RwLock<Arc<PackageLayouts>>> = OnceLock::new();
+        LAYOUTS.get_or_init(|| RwLock::new(Arc::new(PackageLayouts::default())))
+    }
+
+    /// The layouts used by the library: the built-in table unless `install` replaced it.
+    pub fn current() -> Arc<PackageLayouts> {
+        Self::slot().read().map(|layouts| layouts.clone()).unwrap_or_default()
+    }
+
+    /// Makes `self` the layouts used by the library, for the rest of the process.
+    pub fn install(self) {
+        match Self::slot().write() {
+            Ok(mut layouts) => *layouts = Arc::new(self),
+            Err(poisoned) => *poisoned.into_inner() = Arc::new(self),
+        }
+    }
+}
@@ -1,4 +1,73 @@
+pub mod error;
 pub mod psarc;
+#[cfg(feature = "crypto")]
 pub mod decryptor;
 pub mod file_reader;
-pub mod models;
\ No newline at end of file
+pub mod models;
+pub mod content_type;
+pub mod layout;
+#[cfg(feature = "image")]
+pub mod gfx;
+#[cfg(feature = "image")]
+pub mod dds;
+#[cfg(feature = "image")]
+pub mod images;
+#[cfg(feature = "audio")]
+pub mod wem;
+#[cfg(feature = "audio")]
+pub mod codebook;
+#[cfg(feature = "audio")]
+pub mod audio;
+#[cfg(feature = "audio")]
+pub mod vorbis;
+#[cfg(feature = "sng")]
+pub mod library;
+#[cfg(feature = "sng")]
+pub mod library_service;
+#[cfg(feature = "sng")]
+pub mod search;
+#[cfg(feature = "sng")]
+pub mod playlist;
+#[cfg(all(feature = "sng", feature = "audio"))]
+pub mod sync_check;
+pub mod sections;
+#[cfg(feature = "sng")]
+pub mod song;
+pub mod psarc_set;
+pub mod extract;
+pub mod md5;
+pub mod zstd;
+pub mod writer;
+pub mod repack;
+pub mod patch;
+pub mod lyrics;
+#[cfg(feature = "romanize")]
+pub mod romanize;
+pub mod tones;
+pub mod job_state;
+pub mod cache;
+pub mod user_dirs;
+pub mod pitch;
+pub mod references;
+pub mod lint;
+pub mod names;
+pub mod fingerprint;
+pub mod glob;
+pub mod stream;
+pub mod manifest;
+pub mod bnk;
+pub mod origin;
+pub mod toolkit;
+pub mod steam;
+#[cfg(feature = "sng")]
+pub mod practice;
+#[cfg(feature = "sng")]
+pub mod tab;
+#[cfg(feature = "sng")]
+pub mod midi;
+#[cfg(feature = "sng")]
+pub mod transpose;
+#[cfg(feature = "sng")]
+pub mod xml;
+#[cfg(feature = "audition")]
+pub mod audition;
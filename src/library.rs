@@ -0,0 +1,547 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+
+use crate::origin::OriginReport;
+pub use crate::psarc_set::find_archives;
+use crate::psarc::{PsarcFile, SngAsset};
+
+/// Summary of one arrangement (one .sng entry) of a song.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArrangementSummary {
+    /// Arrangement name taken from the entry path (`lead`, `rhythm`, `bass`, `vocals`, ...).
+    pub name: String,
+    pub entry_path: String,
+    pub tuning: Vec<i16>,
+    pub tuning_name: String,
+    pub song_length: f32,
+    /// Average tempo in beats per minute, computed from the beat map.
+    pub average_tempo: f32,
+    /// Notes of the full chart, at the maximum difficulty of every phrase.
+    pub note_count: usize,
+    pub max_difficulty: i32,
+    pub last_conversion_date_time: String,
+}
+
+/// Summary of one song (all arrangements sharing a song key) inside an archive.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SongSummary {
+    pub key: String,
+    pub artist: Option<String>,
+    pub title: Option<String>,
+    pub album: Option<String>,
+    pub year: Option<i64>,
+    pub arrangements: Vec<ArrangementSummary>,
+}
+
+impl SongSummary {
+    /// Length of the song, taken as the longest arrangement.
+    pub fn song_length(&self) -> f32 {
+        self.arrangements.iter().map(|a| a.song_length).fold(0.0, f32::max)
+    }
+
+    /// True when any arrangement has more than one difficulty level.
+    pub fn has_dynamic_difficulty(&self) -> bool {
+        self.arrangements.iter().any(|a| a.max_difficulty > 0)
+    }
+}
+
+/// Summary of every song found in one archive.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchiveSummary {
+    pub path: PathBuf,
+    /// Steam app id from the `appid.appid` entry.
+    pub app_id: Option<String>,
+    /// Official DLC or custom song. Indexes written before it was recorded read as unknown.
+    #[serde(default)]
+    pub origin: OriginReport,
+    pub songs: Vec<SongSummary>,
+}
+
+/// One copy of a duplicated song.
+#[derive(Debug, Clone, Serialize)]
+pub struct DuplicateCandidate {
+    pub archive: PathBuf,
+    pub song_length: f32,
+    pub arrangement_count: usize,
+    pub note_count: usize,
+    /// Newest conversion timestamp among the song's arrangements, as stored in the SNG.
+    pub last_conversion_date_time: String,
+}
+
+/// A song key found in more than one archive.
+#[derive(Debug, Clone, Serialize)]
+pub struct DuplicateSong {
+    pub key: String,
+    pub candidates: Vec<DuplicateCandidate>,
+    /// True when every copy has the same arrangements, lengths and note counts.
+    pub identical: bool,
+    /// The archive holding the newest (then longest, then most complete) version.
+    pub preferred: PathBuf,
+}
+
+/// Statistics aggregated over a whole library of archives.
+#[derive(Default, Debug, Clone, Serialize)]
+pub struct LibraryStats {
+    pub archive_count: usize,
+    pub song_count: usize,
+    pub arrangement_count: usize,
+    /// Arrangement name → number of arrangements.
+    pub arrangement_counts: BTreeMap<String, usize>,
+    /// Tuning name → number of instrumental arrangements using it.
+    pub tuning_distribution: BTreeMap<String, usize>,
+    pub min_tempo: Option<f32>,
+    pub max_tempo: Option<f32>,
+    /// Sum of song lengths in seconds.
+    pub total_play_length: f64,
+    pub duplicates: Vec<DuplicateSong>,
+}
+
+/// Returns a readable name for a six string tuning given as per-string semitone offsets.
+pub fn tuning_name(tuning: &[i16]) -> String {
+    if tuning.is_empty() {
+        return "Unknown".to_string();
+    }
+    let first = tuning[0];
+    let rest_equal = tuning[1..].iter().all(|&t| t == tuning[1]);
+    let all_equal = tuning.iter().all(|&t| t == first);
+    let note = |offset: i16| {
+        const NOTES: [&str; 12] = ["E", "F", "F#", "G", "Ab", "A", "Bb", "B", "C", "C#", "D", "Eb"];
+        NOTES[offset.rem_euclid(12) as usize]
+    };
+    if all_equal {
+        format!("{} Standard", note(first))
+    } else if rest_equal && tuning[1].checked_sub(2) == Some(first) {
+        format!("Drop {}", note(first))
+    } else {
+        tuning.iter().map(|t| t.to_string()).collect::<Vec<_>>().join(" ")
+    }
+}
+
+/// Splits an SNG entry path such as `songs/bin/generic/mysong_lead.sng` into its song key
+/// (`mysong`) and arrangement name (`lead`).
+pub fn split_sng_path(path: &str) -> Option<(String, String)> {
+    let stem = Path::new(path).file_stem()?.to_string_lossy().to_string();
+    let (key, arrangement) = stem.rsplit_once('_')?;
+    Some((key.to_string(), arrangement.to_string()))
+}
+
+/// Parses the SNG conversion timestamp (`M-D-YY HH:MM`) into a sortable tuple.
+fn parse_conversion_date(value: &str) -> Option<(u32, u32, u32, u32, u32)> {
+    let (date, time) = value.trim().split_once(' ')?;
+    let mut date_parts = date.split('-').map(|p| p.parse::<u32>().ok());
+    let (month, day, year) = (date_parts.next()??, date_parts.next()??, date_parts.next()??);
+    let (hour, minute) = time.split_once(':')?;
+    Some((year, month, day, hour.parse().ok()?, minute.parse().ok()?))
+}
+
+impl DuplicateCandidate {
+    fn from_song(archive: &Path, song: &SongSummary) -> Self {
+        let last_conversion_date_time = song
+            .arrangements
+            .iter()
+            .map(|a| &a.last_conversion_date_time)
+            .max_by_key(|d| parse_conversion_date(d))
+            .cloned()
+            .unwrap_or_default();
+        DuplicateCandidate {
+            archive: archive.to_path_buf(),
+            song_length: song.song_length(),
+            arrangement_count: song.arrangements.len(),
+            note_count: song.arrangements.iter().map(|a| a.note_count).sum(),
+            last_conversion_date_time,
+        }
+    }
+}
+
+/// Content signature used to decide whether two copies of a song are identical.
+fn song_signature(song: &SongSummary) -> Vec<(String, usize, u32)> {
+    let mut signature: Vec<_> = song
+        .arrangements
+        .iter()
+        .map(|a| (a.name.clone(), a.note_count, a.song_length.to_bits()))
+        .collect();
+    signature.sort();
+    signature
+}
+
+/// Finds song keys present in more than one archive, compares the copies and picks the
+/// one worth keeping.
+pub fn find_duplicates(summaries: &[ArchiveSummary]) -> Vec<DuplicateSong> {
+    let mut by_key: BTreeMap<&str, Vec<(&Path, &SongSummary)>> = BTreeMap::new();
+    for archive in summaries {
+        for song in &archive.songs {
+            by_key.entry(&song.key).or_default().push((&archive.path, song));
+        }
+    }
+
+    let mut duplicates = Vec::new();
+    for (key, copies) in by_key {
+        if copies.len() < 2 {
+            continue;
+        }
+        let first_signature = song_signature(copies[0].1);
+        let identical = copies.iter().all(|(_, song)| song_signature(song) == first_signature);
+        let candidates: Vec<DuplicateCandidate> = copies
+            .iter()
+            .map(|(archive, song)| DuplicateCandidate::from_song(archive, song))
+            .collect();
+        let preferred = candidates
+            .iter()
+            .max_by(|a, b| {
+                parse_conversion_date(&a.last_conversion_date_time)
+                    .cmp(&parse_conversion_date(&b.last_conversion_date_time))
+                    .then(a.song_length.total_cmp(&b.song_length))
+                    .then(a.arrangement_count.cmp(&b.arrangement_count))
+                    .then(a.note_count.cmp(&b.note_count))
+            })
+            .map(|c| c.archive.clone())
+            .unwrap_or_default();
+        duplicates.push(DuplicateSong {
+            key: key.to_string(),
+            candidates,
+            identical,
+            preferred,
+        });
+    }
+    duplicates
+}
+
+/// Average tempo (BPM) implied by the beat map of an SNG asset.
+pub(crate) fn average_tempo(asset: &SngAsset) -> f32 {
+    match (asset.bpms.first(), asset.bpms.last()) {
+        (Some(first), Some(last)) if asset.bpms.len() > 1 && last.time > first.time => {
+            (asset.bpms.len() - 1) as f32 * 60.0 / (last.time - first.time)
+        }
+        _ => 0.0,
+    }
+}
+
+/// Song level attributes read from a manifest JSON entry.
+#[derive(Default)]
+struct ManifestAttributes {
+    artist: Option<String>,
+    title: Option<String>,
+    album: Option<String>,
+    year: Option<i64>,
+}
+
+/// Reads the song attributes of every manifest JSON entry, keyed by lowercase song key.
+fn read_manifest_attributes(psarc: &PsarcFile) -> BTreeMap<String, ManifestAttributes> {
+    let mut attributes = BTreeMap::new();
+    for entry in &psarc.toc.entries {
+        let entry_path = match &entry.path {
+            Some(p) if p.starts_with("manifests/") && p.ends_with(".json") => p,
+            _ => continue,
+        };
+        let value: serde_json::Value = match psarc
+            .inflate_entry_data(entry)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+        {
+            Some(value) => value,
+            None => {
+                tracing::warn!("Could not parse manifest {}", entry_path);
+                continue;
+            }
+        };
+        let entries = match value.get("Entries").and_then(|e| e.as_object()) {
+            Some(entries) => entries,
+            None => continue,
+        };
+        for manifest in entries.values() {
+            let attrs = match manifest.get("Attributes") {
+                Some(attrs) => attrs,
+                None => continue,
+            };
+            let text = |name: &str| attrs.get(name).and_then(|v| v.as_str()).map(|s| s.to_string());
+            let key = match text("SongKey") {
+                Some(key) => key.to_lowercase(),
+                None => continue,
+            };
+            let slot: &mut ManifestAttributes = attributes.entry(key).or_default();
+            slot.artist = slot.artist.take().or_else(|| text("ArtistName"));
+            slot.title = slot.title.take().or_else(|| text("SongName"));
+            slot.album = slot.album.take().or_else(|| text("AlbumName"));
+            slot.year = slot.year.or_else(|| attrs.get("SongYear").and_then(|v| v.as_i64()));
+        }
+    }
+    attributes
+}
+
+/// Parses every SNG entry of an archive and groups the arrangements by song key.
+pub fn summarize_archive(path: &Path) -> io::Result<ArchiveSummary> {
+    let psarc = PsarcFile::open_path(path)?;
+    let mut manifest_attributes = read_manifest_attributes(&psarc);
+    let mut songs: BTreeMap<String, Vec<ArrangementSummary>> = BTreeMap::new();
+    for entry in &psarc.toc.entries {
+        let entry_path = match &entry.path {
+            Some(p) if p.ends_with(".sng") => p,
+            _ => continue,
+        };
+        let (key, name) = match split_sng_path(entry_path) {
+            Some(parts) => parts,
+            None => continue,
+        };
+        let asset = psarc.read_sng_entry(entry)?;
+        let note_count = asset.max_difficulty_notes().len();
+        songs.entry(key).or_default().push(ArrangementSummary {
+            name,
+            entry_path: entry_path.clone(),
+            tuning_name: tuning_name(&asset.metadata.tuning),
+            tuning: asset.metadata.tuning.clone(),
+            song_length: asset.metadata.song_length,
+            average_tempo: average_tempo(&asset),
+            note_count,
+            max_difficulty: asset.metadata.max_difficulty,
+            last_conversion_date_time: asset.metadata.last_conversion_date_time.clone(),
+        });
+    }
+    Ok(ArchiveSummary {
+        path: path.to_path_buf(),
+        app_id: psarc.app_id().unwrap_or_else(|e| {
+            tracing::warn!("Could not read app id of {:?}: {}", path, e);
+            None
+        }),
+        origin: psarc.origin().unwrap_or_else(|e| {
+            tracing::warn!("Could not classify {:?}: {}", path, e);
+            OriginReport::default()
+        }),
+        songs: songs
+            .into_iter()
+            .map(|(key, arrangements)| {
+                let attrs = manifest_attributes.remove(&key.to_lowercase()).unwrap_or_default();
+                SongSummary {
+                    key,
+                    artist: attrs.artist,
+                    title: attrs.title,
+                    album: attrs.album,
+                    year: attrs.year,
+                    arrangements,
+                }
+            })
+            .collect(),
+    })
+}
+
+/// Summarizes every archive below `dir`. Archives that fail to parse are logged and skipped
+/// so one corrupt package does not abort a library-wide run.
+pub fn scan_library(dir: &Path) -> io::Result<Vec<ArchiveSummary>> {
+    let mut summaries = Vec::new();
+    for path in find_archives(dir)? {
+        match summarize_archive(&path) {
+            Ok(summary) => summaries.push(summary),
+            Err(e) => tracing::warn!("Skipping {:?}: {}", path, e),
+        }
+    }
+    Ok(summaries)
+}
+
+impl LibraryStats {
+    /// Scans `dir` and aggregates statistics over every archive found.
+    pub fn collect(dir: &Path) -> io::Result<Self> {
+        Ok(Self::from_summaries(&scan_library(dir)?))
+    }
+
+    /// Aggregates statistics over already summarized archives.
+    pub fn from_summaries(summaries: &[ArchiveSummary]) -> Self {
+        let mut stats = LibraryStats {
+            archive_count: summaries.len(),
+            ..Default::default()
+        };
+        for archive in summaries {
+            for song in &archive.songs {
+                stats.song_count += 1;
+                stats.total_play_length += song.song_length() as f64;
+                for arrangement in &song.arrangements {
+                    stats.arrangement_count += 1;
+                    *stats.arrangement_counts.entry(arrangement.name.clone()).or_default() += 1;
+                    if arrangement.name.contains("vocals") {
+                        continue;
+                    }
+                    *stats.tuning_distribution.entry(arrangement.tuning_name.clone()).or_default() += 1;
+                    if arrangement.average_tempo > 0.0 {
+                        let tempo = arrangement.average_tempo;
+                        stats.min_tempo = Some(stats.min_tempo.map_or(tempo, |t| t.min(tempo)));
+                        stats.max_tempo = Some(stats.max_tempo.map_or(tempo, |t| t.max(tempo)));
+                    }
+                }
+            }
+        }
+        stats.duplicates = find_duplicates(summaries);
+        stats
+    }
+}
+
+/// Quotes a CSV field when it contains a separator, quote or line break.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Writes the library as CSV, one row per song, in the column layout used by community
+/// song-list spreadsheets: `Artist,Title,Album,Year,Arrangements,Tunings,DD,Path`.
+pub fn write_csv<W: Write>(summaries: &[ArchiveSummary], mut writer: W) -> io::Result<()> {
+    writeln!(writer, "Artist,Title,Album,Year,Arrangements,Tunings,DD,Path")?;
+    for archive in summaries {
+        for song in &archive.songs {
+            let arrangements: Vec<&str> = song.arrangements.iter().map(|a| a.name.as_str()).collect();
+            let mut tunings: Vec<&str> = song
+                .arrangements
+                .iter()
+                .filter(|a| !a.name.contains("vocals"))
+                .map(|a| a.tuning_name.as_str())
+                .collect();
+            tunings.sort_unstable();
+            tunings.dedup();
+            let row = [
+                csv_field(song.artist.as_deref().unwrap_or("")),
+                csv_field(song.title.as_deref().unwrap_or(&song.key)),
+                csv_field(song.album.as_deref().unwrap_or("")),
+                song.year.map(|y| y.to_string()).unwrap_or_default(),
+                csv_field(&arrangements.join(", ")),
+                csv_field(&tunings.join(", ")),
+                if song.has_dynamic_difficulty() { "Yes" } else { "No" }.to_string(),
+                csv_field(&archive.path.to_string_lossy()),
+            ];
+            writeln!(writer, "{}", row.join(","))?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes the CSV song list to `output_path`.
+pub fn export_csv(summaries: &[ArchiveSummary], output_path: &Path) -> io::Result<()> {
+    let file = io::BufWriter::new(fs::File::create(output_path)?);
+    write_csv(summaries, file)?;
+    tracing::info!("Written song list to {:?}", output_path);
+    Ok(())
+}
+
+/// A chord shape, as fretted: per string (low to high) the fret and finger, `-1` for
+/// strings not played and fingers not given.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChordShape {
+    /// Most frequent name given to the shape by chart authors (may be empty).
+    pub name: String,
+    /// Every name the shape was found under, sorted.
+    pub names: Vec<String>,
+    pub frets: [i8; 6],
+    pub fingers: [i8; 6],
+    /// Times the chord is played in the full-difficulty charts.
+    pub usage_count: usize,
+    /// Distinct songs (by key) using the shape.
+    pub song_count: usize,
+}
+
+/// Unique chord shapes of a library, deduplicated by frets and fingers.
+#[derive(Default, Debug, Clone, Serialize)]
+pub struct ChordDictionary {
+    /// Shapes sorted by usage, most played first.
+    pub shapes: Vec<ChordShape>,
+}
+
+/// Converts a fret or finger byte, where 255 means unused, to the dictionary form.
+fn shape_value(value: u8) -> i8 {
+    if value == u8::MAX { -1 } else { value.min(i8::MAX as u8) as i8 }
+}
+
+/// Accumulates chord shapes while a dictionary is being built.
+#[derive(Default)]
+struct ChordTally {
+    names: BTreeMap<String, usize>,
+    usage_count: usize,
+    songs: std::collections::BTreeSet<String>,
+}
+
+impl ChordDictionary {
+    /// Scans `dir` and builds the dictionary of every archive found.
+    pub fn collect(dir: &Path) -> io::Result<Self> {
+        Ok(Self::from_archives(&find_archives(dir)?))
+    }
+
+    /// Builds the dictionary of the given archives. Archives and arrangements that fail to
+    /// parse are logged and skipped.
+    pub fn from_archives(paths: &[PathBuf]) -> Self {
+        let mut tallies: BTreeMap<([i8; 6], [i8; 6]), ChordTally> = BTreeMap::new();
+        for path in paths {
+            let psarc = match PsarcFile::open_path(path) {
+                Ok(psarc) => psarc,
+                Err(e) => {
+                    tracing::warn!("Skipping {:?}: {}", path, e);
+                    continue;
+                }
+            };
+            for entry in &psarc.toc.entries {
+                let Some((key, name)) = entry.path.as_deref().filter(|p| p.ends_with(".sng")).and_then(split_sng_path) else {
+                    continue;
+                };
+                if name.contains("vocals") {
+                    continue;
+                }
+                match psarc.read_sng_entry(entry) {
+                    Ok(asset) => tally_chords(&mut tallies, &asset, &key),
+                    Err(e) => tracing::warn!("Skipping {:?} in {:?}: {}", entry.path, path, e),
+                }
+            }
+        }
+        let mut shapes: Vec<ChordShape> = tallies
+            .into_iter()
+            .map(|((frets, fingers), tally)| {
+                // Most used name, ties going to the first in alphabetical order.
+                let name = tally
+                    .names
+                    .iter()
+                    .filter(|(name, _)| !name.is_empty())
+                    .max_by(|a, b| a.1.cmp(b.1).then(b.0.cmp(a.0)))
+                    .map(|(name, _)| name.clone())
+                    .unwrap_or_default();
+                ChordShape {
+                    name,
+                    names: tally.names.into_keys().filter(|name| !name.is_empty()).collect(),
+                    frets,
+                    fingers,
+                    usage_count: tally.usage_count,
+                    song_count: tally.songs.len(),
+                }
+            })
+            .collect();
+        shapes.sort_by(|a, b| b.usage_count.cmp(&a.usage_count).then(b.song_count.cmp(&a.song_count)));
+        ChordDictionary { shapes }
+    }
+}
+
+/// Adds the chord shapes of one arrangement, counting how often each is played.
+fn tally_chords(tallies: &mut BTreeMap<([i8; 6], [i8; 6]), ChordTally>, asset: &SngAsset, key: &str) {
+    let mut played = vec![0usize; asset.chords.len()];
+    for note in asset.max_difficulty_notes() {
+        if let Some(count) = usize::try_from(note.chord_id).ok().and_then(|id| played.get_mut(id)) {
+            *count += 1;
+        }
+    }
+    for (chord, count) in asset.chords.iter().zip(played) {
+        let frets = chord.frets.map(shape_value);
+        if frets.iter().all(|fret| *fret < 0) {
+            continue;
+        }
+        let tally = tallies.entry((frets, chord.fingers.map(shape_value))).or_default();
+        *tally.names.entry(chord.name.trim().to_string()).or_default() += 1;
+        tally.usage_count += count;
+        if count > 0 {
+            tally.songs.insert(key.to_lowercase());
+        }
+    }
+}
+
+/// Writes a chord dictionary as pretty-printed JSON.
+pub fn export_chord_dictionary(dictionary: &ChordDictionary, output_path: &Path) -> io::Result<()> {
+    let json = serde_json::to_string_pretty(dictionary).map_err(io::Error::other)?;
+    fs::write(output_path, json)?;
+    tracing::info!("Written chord dictionary with {} shapes to {:?}", dictionary.shapes.len(), output_path);
+    Ok(())
+}
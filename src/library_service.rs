@@ -0,0 +1,206 @@
+//! A long-running view of a song library, for hosts (GUIs, servers) that embed it.
+//!
+//! The service keeps an index of every archive below a folder: the archive's fingerprint
+//! and its summary. `refresh` compares the folder with the index, summarizes only the
+//! archives that are new or changed, and reports what happened as song-level events;
+//! `watch` repeats it on an interval. The index can be kept in a JSON file, so a restart
+//! only re-reads the archives that changed in the meantime. Songs are opened on demand.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+
+use crate::job_state::fingerprint;
+use crate::library::{find_archives, summarize_archive, ArchiveSummary, SongSummary};
+use crate::psarc::PsarcFile;
+use crate::search::{search_archives, SearchHit, SongQuery};
+use crate::song::Song;
+
+/// A change of the library found by `LibraryService::refresh`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum LibraryEvent {
+    SongAdded { archive: PathBuf, key: String },
+    SongRemoved { archive: PathBuf, key: String },
+    /// The archive holding the song changed; its summary was read again.
+    SongUpdated { archive: PathBuf, key: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct IndexedArchive {
+    fingerprint: String,
+    summary: ArchiveSummary,
+}
+
+#[derive(Debug)]
+pub struct LibraryService {
+    dir: PathBuf,
+    index_path: Option<PathBuf>,
+    archives: BTreeMap<PathBuf, IndexedArchive>,
+}
+
+impl LibraryService {
+    /// A service over the archives below `dir`, with an empty index: the first `refresh`
+    /// reports every song as added.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        LibraryService { dir: dir.into(), index_path: None, archives: BTreeMap::new() }
+    }
+
+    /// Keeps the index in `path`: loads it now and saves it after every refresh that
+    /// changed something. A missing or unreadable index file starts an empty index.
+    pub fn with_index_file(mut self, path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        match fs::read(&path) {
+            Ok(data) => match serde_json::from_slice(&data) {
+                Ok(archives) => self.archives = archives,
+                Err(err) => tracing::warn!("Ignoring damaged library index {:?}: {}", path, err),
+            },
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => tracing::warn!("Could not read library index {:?}: {}", path, err),
+        }
+        self.index_path = Some(path);
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Summaries of the indexed archives, by path.
+    pub fn archives(&self) -> impl Iterator<Item = &ArchiveSummary> {
+        self.archives.values().map(|a| &a.summary)
+    }
+
+    /// Every indexed song with the archive holding it.
+    pub fn songs(&self) -> impl Iterator<Item = (&Path, &SongSummary)> {
+        self.archives().flat_map(|a| a.songs.iter().map(move |song| (a.path.as_path(), song)))
+    }
+
+    /// Archive holding the song `key` (case-insensitive), if indexed.
+    pub fn find_song(&self, key: &str) -> Option<&Path> {
+        self.songs().find(|(_, song)| song.key.eq_ignore_ascii_case(key)).map(|(path, _)| path)
+    }
+
+    /// Indexed songs matching `query` (see `search`).
+    pub fn search(&self, query: &SongQuery) -> Vec<SearchHit> {
+        search_archives(self.archives(), query)
+    }
+
+    /// Opens the song `key` and passes it to `f`. The archive is read for this call only,
+    /// so the service holds no archive open between requests.
+    pub fn with_song<R>(&self, key: &str, f: impl FnOnce(&Song) -> R) -> io::Result<R> {
+        let path = self
+            .find_song(key)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("Song {} is not in the library", key)))?;
+        let psarc = PsarcFile::open_path(path)?;
+        let song = Song::open(&psarc, key).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("Song {} is no longer in {:?}", key, path))
+        })?;
+        Ok(f(&song))
+    }
+
+    /// Brings the index up to date with the folder and returns the changes. Archives
+    /// that fail to parse are logged and left out, like in `scan_library`.
+    pub fn refresh(&mut self) -> io::Result<Vec<LibraryEvent>> {
+        let mut events = Vec::new();
+        let mut seen = BTreeMap::new();
+        for path in find_archives(&self.dir)? {
+            let current = match fingerprint(&path) {
+                Ok(current) => current,
+                Err(err) => {
+                    tracing::warn!("Skipping {:?}: {}", path, err);
+                    continue;
+                }
+            };
+            if let Some(indexed) = self.archives.remove(&path) {
+                if indexed.fingerprint == current {
+                    seen.insert(path, indexed);
+                    continue;
+                }
+                let summary = match summarize_archive(&path) {
+                    Ok(summary) => summary,
+                    Err(err) => {
+                        tracing::warn!("Skipping {:?}: {}", path, err);
+                        self.archives.insert(path, indexed);
+                        continue;
+                    }
+                };
+                diff_songs(&path, &indexed.summary, &summary, &mut events);
+                seen.insert(path, IndexedArchive { fingerprint: current, summary });
+            } else {
+                match summarize_archive(&path) {
+                    Ok(summary) => {
+                        events.extend(summary.songs.iter().map(|song| LibraryEvent::SongAdded {
+                            archive: path.clone(),
+                            key: song.key.clone(),
+                        }));
+                        seen.insert(path, IndexedArchive { fingerprint: current, summary });
+                    }
+                    Err(err) => tracing::warn!("Skipping {:?}: {}", path, err),
+                }
+            }
+        }
+        // What is left in the old index was deleted, or failed to parse after a change.
+        for (path, indexed) in std::mem::replace(&mut self.archives, seen) {
+            if !path.exists() {
+                events.extend(indexed.summary.songs.into_iter().map(|song| LibraryEvent::SongRemoved {
+                    archive: path.clone(),
+                    key: song.key,
+                }));
+            } else {
+                self.archives.insert(path, indexed);
+            }
+        }
+        if !events.is_empty() {
+            tracing::info!("Library {:?} changed: {} events", self.dir, events.len());
+            self.save_index()?;
+        }
+        Ok(events)
+    }
+
+    /// Refreshes every `interval` until `stop` is set, passing each change to `on_event`.
+    /// A failed refresh (the folder went away, say) is logged and retried.
+    pub fn watch(&mut self, interval: Duration, stop: &AtomicBool, on_event: &mut dyn FnMut(&LibraryEvent)) {
+        while !stop.load(Ordering::Relaxed) {
+            match self.refresh() {
+                Ok(events) => events.iter().for_each(&mut *on_event),
+                Err(err) => tracing::warn!("Refreshing library {:?} failed: {}", self.dir, err),
+            }
+            thread::sleep(interval);
+        }
+    }
+
+    fn save_index(&self) -> io::Result<()> {
+        let Some(path) = &self.index_path else {
+            return Ok(());
+        };
+        let json = serde_json::to_vec(&self.archives).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+        fs::write(&temp, json)?;
+        fs::rename(&temp, path)
+    }
+}
+
+/// Events between two summaries of the same archive.
+fn diff_songs(path: &Path, before: &ArchiveSummary, after: &ArchiveSummary, events: &mut Vec<LibraryEvent>) {
+    let has = |summary: &ArchiveSummary, key: &str| summary.songs.iter().any(|s| s.key == key);
+    for song in &after.songs {
+        let (archive, key) = (path.to_path_buf(), song.key.clone());
+        events.push(if has(before, &song.key) {
+            LibraryEvent::SongUpdated { archive, key }
+        } else {
+            LibraryEvent::SongAdded { archive, key }
+        });
+    }
+    for song in before.songs.iter().filter(|song| !has(after, &song.key)) {
+        events.push(LibraryEvent::SongRemoved { archive: path.to_path_buf(), key: song.key.clone() });
+    }
+}
@@ -0,0 +1,239 @@
+//! Pre-flight checks of a package before it is distributed.
+//!
+//! Built on the reference graph: references naming nothing in the package are errors
+//! (the game fails to load the asset), entries nothing references are warnings (dead
+//! weight, often left over from an edit). With the `sng` feature, each arrangement
+//! manifest is also compared with the chart it describes.
+//!
+//! `validate_pack` checks what the game loader itself enforces and refuses packages over:
+//! 64 KiB blocks, an encrypted TOC, the root entries of a song package and lowercase
+//! paths, with manifests one folder deep. It runs on archives (`lint_archive`) and on a
+//! `PsarcWriter` before anything is written (`PsarcWriter::validate`).
+
+use std::io;
+use std::path::Path;
+use serde::Serialize;
+
+use crate::psarc::PsarcFile;
+use crate::references::ReferenceGraph;
+#[cfg(feature = "sng")]
+use crate::song::Song;
+
+/// Entries the game finds by location rather than by reference, so never referenced.
+const ROOT_EXTENSIONS: [&str; 5] = ["xblock", "nt", "hsan", "appid", "version"];
+
+/// Block size of every package the game loads.
+pub const GAME_BLOCK_SIZE: u32 = 65536;
+
+/// Largest difference between the song length of a manifest and its chart, in seconds.
+#[cfg(feature = "sng")]
+const SONG_LENGTH_TOLERANCE: f64 = 1.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub enum LintSeverity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum LintCheck {
+    /// A reference names an entry missing from the package.
+    MissingAsset,
+    /// An entry no other entry references.
+    UnreferencedAsset,
+    /// An arrangement without a manifest.
+    MissingManifest,
+    /// A manifest attribute disagrees with the chart.
+    MetadataMismatch,
+    /// The package holds no entries.
+    EntryCount,
+    /// An entry every song package needs is missing.
+    MissingEntry,
+    /// A manifest is not at `manifests/<folder>/<folder>_<name>.json`.
+    ManifestNaming,
+    /// A path the game cannot look up: uppercase letters, `\` separators, empty segments.
+    PathFormat,
+    /// The block size is not the game's.
+    BlockSize,
+    /// The TOC is not encrypted.
+    TocEncryption,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LintIssue {
+    pub severity: LintSeverity,
+    pub check: LintCheck,
+    /// Entry the issue is about.
+    pub path: String,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LintReport {
+    pub issues: Vec<LintIssue>,
+}
+
+impl LintReport {
+    fn push(&mut self, severity: LintSeverity, check: LintCheck, path: &str, message: String) {
+        self.issues.push(LintIssue { severity, check, path: path.to_string(), message });
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &LintIssue> {
+        self.issues.iter().filter(|i| i.severity == LintSeverity::Error)
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.errors().next().is_some()
+    }
+}
+
+/// Runs every check on an archive whose manifest has been read.
+pub fn lint_archive(archive: &PsarcFile) -> io::Result<LintReport> {
+    let paths = archive.toc.entries.iter().skip(1).filter_map(|e| e.path.as_deref());
+    let mut report = validate_pack(paths, archive.header.block_size, archive.toc.encrypted);
+    let graph = ReferenceGraph::build(archive)?;
+    for reference in graph.dangling() {
+        report.push(
+            LintSeverity::Error,
+            LintCheck::MissingAsset,
+            &reference.from,
+            format!("references {}, which is not in the package", reference.target),
+        );
+    }
+    for path in graph.unreferenced() {
+        let extension = Path::new(path).extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+        if !ROOT_EXTENSIONS.contains(&extension.as_str()) {
+            report.push(LintSeverity::Warning, LintCheck::UnreferencedAsset, path, "is not referenced by any entry".to_string());
+        }
+    }
+    #[cfg(feature = "sng")]
+    lint_manifests(archive, &mut report)?;
+    report.issues.sort_by(|a, b| b.severity.cmp(&a.severity).then_with(|| a.path.cmp(&b.path)));
+    tracing::debug!("Lint found {} issues ({} errors)", report.issues.len(), report.errors().count());
+    Ok(report)
+}
+
+/// Compares each arrangement manifest with its chart.
+#[cfg(feature = "sng")]
+fn lint_manifests(archive: &PsarcFile, report: &mut LintReport) -> io::Result<()> {
+    for song in Song::list(archive) {
+        for arrangement in song.arrangements() {
+            let path = arrangement.entry.path.as_deref().unwrap_or_default();
+            let Some(attributes) = arrangement.manifest_attributes() else {
+                report.push(LintSeverity::Warning, LintCheck::MissingManifest, path, "has no manifest".to_string());
+                continue;
+            };
+            let sng = arrangement.sng()?;
+            let mut mismatch = |what: &str, manifest: String, chart: String| {
+                report.push(
+                    LintSeverity::Warning,
+                    LintCheck::MetadataMismatch,
+                    path,
+                    format!("manifest {} is {}, the chart says {}", what, manifest, chart),
+                );
+            };
+            if let Some(key) = attributes.get("SongKey").and_then(|v| v.as_str()) {
+                if !key.eq_ignore_ascii_case(&song.key) {
+                    mismatch("SongKey", key.to_string(), song.key.clone());
+                }
+            }
+            if let Some(length) = attributes.get("SongLength").and_then(|v| v.as_f64()) {
+                if (length - sng.metadata.song_length as f64).abs() > SONG_LENGTH_TOLERANCE {
+                    mismatch("SongLength", format!("{:.1}s", length), format!("{:.1}s", sng.metadata.song_length));
+                }
+            }
+            if let Some(capo) = attributes.get("CapoFret").and_then(|v| v.as_i64()) {
+                let chart = match sng.metadata.capo_fret_id {
+                    u8::MAX => 0,
+                    capo => capo as i64,
+                };
+                if capo.max(0) != chart {
+                    mismatch("CapoFret", capo.to_string(), chart.to_string());
+                }
+            }
+            if let Some(tuning) = attributes.get("Tuning").and_then(|v| v.as_object()) {
+                let manifest: Vec<i64> =
+                    (0..sng.metadata.tuning.len()).map(|i| tuning.get(&format!("string{}", i)).and_then(|v| v.as_i64()).unwrap_or(0)).collect();
+                let chart: Vec<i64> = sng.metadata.tuning.iter().map(|t| *t as i64).collect();
+                if manifest != chart && !arrangement.is_vocals() {
+                    mismatch("Tuning", format!("{:?}", manifest), format!("{:?}", chart));
+                }
+            }
+            if let Some(max) = attributes.get("MaxPhraseDifficulty").and_then(|v| v.as_i64()) {
+                if max != sng.metadata.max_difficulty as i64 && !arrangement.is_vocals() {
+                    mismatch("MaxPhraseDifficulty", max.to_string(), sng.metadata.max_difficulty.to_string());
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks a package of the entries at `paths` (NamesBlock excluded), written with
+/// `block_size` and `encrypt_toc`, against the constraints of the game loader. Issues
+/// about the package as a whole have an empty path.
+pub fn validate_pack<'a>(paths: impl IntoIterator<Item = &'a str>, block_size: u32, encrypt_toc: bool) -> LintReport {
+    let mut report = LintReport::default();
+    let paths: Vec<&str> = paths.into_iter().collect();
+    if block_size != GAME_BLOCK_SIZE {
+        let message = format!("block size is {} bytes, the game only loads {} byte blocks", block_size, GAME_BLOCK_SIZE);
+        report.push(LintSeverity::Error, LintCheck::BlockSize, "", message);
+    }
+    if !encrypt_toc {
+        let message = "TOC is not encrypted, the game only loads encrypted TOCs (pack without --plain-toc)".to_string();
+        report.push(LintSeverity::Error, LintCheck::TocEncryption, "", message);
+    }
+    if paths.is_empty() {
+        report.push(LintSeverity::Error, LintCheck::EntryCount, "", "package holds no entries".to_string());
+        return report;
+    }
+
+    for path in &paths {
+        if path.contains('\\') || path.split('/').any(str::is_empty) {
+            let message = "is not a relative path with `/` separators and no empty folder names".to_string();
+            report.push(LintSeverity::Error, LintCheck::PathFormat, path, message);
+        } else if path.chars().any(|c| c.is_uppercase()) {
+            let message = format!("has uppercase letters, the game looks entries up by lowercase path ({})", path.to_lowercase());
+            report.push(LintSeverity::Error, LintCheck::PathFormat, path, message);
+        }
+    }
+
+    let extension = |path: &str| Path::new(path).extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+    let stem = |path: &str| Path::new(path).file_stem().map(|s| s.to_string_lossy().to_lowercase()).unwrap_or_default();
+    let mut manifest_names = Vec::new();
+    for path in paths.iter().filter(|p| p.starts_with("manifests/")) {
+        let segments: Vec<&str> = path.split('/').collect();
+        let [_, folder, file] = segments[..] else {
+            let message = "is not one folder deep: manifests go at manifests/<folder>/<file>".to_string();
+            report.push(LintSeverity::Error, LintCheck::ManifestNaming, path, message);
+            continue;
+        };
+        let name = stem(file);
+        if name != folder.to_lowercase() && !name.starts_with(&format!("{}_", folder.to_lowercase())) {
+            let message = format!("is named {}, manifests of {} are named {}_<arrangement>", file, folder, folder);
+            report.push(LintSeverity::Error, LintCheck::ManifestNaming, path, message);
+        }
+        manifest_names.push(name);
+    }
+
+    let has = |ext: &str| paths.iter().any(|p| extension(p) == ext);
+    for (ext, what) in [
+        ("appid", "an appid.appid entry, the Steam app id the package belongs to"),
+        ("xblock", "a gamexblocks/nsongs/<key>.xblock entry, which lists the songs"),
+        ("nt", "a <key>_aggregategraph.nt entry, which lists the assets"),
+        ("hsan", "a manifests/<folder>/<folder>.hsan entry, the song header manifest"),
+        ("sng", "an .sng arrangement"),
+    ] {
+        if !has(ext) {
+            report.push(LintSeverity::Error, LintCheck::MissingEntry, "", format!("package is missing {}", what));
+        }
+    }
+    for path in paths.iter().filter(|p| extension(p) == "sng") {
+        let name = stem(path);
+        if !manifest_names.iter().any(|manifest| manifest.ends_with(&format!("_{}", name))) {
+            let message = format!("has no manifest (manifests/songs_dlc_<key>/songs_dlc_{}.json)", name);
+            report.push(LintSeverity::Error, LintCheck::MissingEntry, path, message);
+        }
+    }
+    report
+}
@@ -0,0 +1,300 @@
+use std::io::{self, Cursor};
+
+use crate::models::{
+    read_vec_from_buffer, BinarySerializable, Bpm, Chord, ChordNotes, Phrase, Vocal,
+};
+
+/// Pitch given to imported syllables. Lyric files carry no pitch, and the game treats this
+/// value as "no note".
+pub const UNPITCHED_NOTE: i32 = 254;
+
+/// Duration given to the last line of a lyric file, which has no following timestamp.
+const LAST_LINE_SECONDS: f32 = 4.0;
+
+/// Share of a syllable's slot it is sung for, leaving a short gap before the next one.
+const SUNG_FRACTION: f32 = 0.9;
+
+/// A syllable of a vocals entry split into its parts. Japanese vocals (`jvocals`) write
+/// the reading of kanji after the base text, as `漢字|かんじ` or `漢字(かんじ)` (full width
+/// parentheses too); the markers come last, after the reading.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LyricParts {
+    /// The syllable as displayed, without reading or markers.
+    pub text: String,
+    /// Furigana (ruby) reading of `text`, when given.
+    pub ruby: Option<String>,
+    /// `-` marker: the next syllable continues the same word.
+    pub continues: bool,
+    /// `+` marker: the syllable ends the line.
+    pub line_end: bool,
+}
+
+/// Splits a stored lyric into its text, reading and markers.
+pub fn split_lyric(lyric: &str) -> LyricParts {
+    let (body, line_end) = match lyric.strip_suffix('+') {
+        Some(body) => (body, true),
+        None => (lyric, false),
+    };
+    let (body, continues) = match body.strip_suffix('-') {
+        // A lone `-` is a syllable, not a marker.
+        Some(rest) if !rest.is_empty() => (rest, true),
+        _ => (body, false),
+    };
+    let split = body
+        .split_once('|')
+        .or_else(|| {
+            let (open, close) = [('(', ')'), ('（', '）')]
+                .into_iter()
+                .find(|(_, close)| body.ends_with(*close))?;
+            let (text, ruby) = body.strip_suffix(close)?.split_once(open)?;
+            Some((text, ruby))
+        })
+        .filter(|(text, ruby)| !text.is_empty() && !ruby.is_empty());
+    let (text, ruby) = match split {
+        Some((text, ruby)) => (text, Some(ruby.to_string())),
+        None => (body, None),
+    };
+    LyricParts { text: text.to_string(), ruby, continues, line_end }
+}
+
+/// Parses an `mm:ss.xx` timestamp into seconds.
+fn parse_timestamp(tag: &str) -> Option<f32> {
+    let (minutes, seconds) = tag.trim().split_once(':')?;
+    Some(minutes.parse::<f32>().ok()? * 60.0 + seconds.parse::<f32>().ok()?)
+}
+
+/// One lyric line with its start time and, for enhanced LRC, per-word start times.
+struct TimedLine {
+    time: f32,
+    words: Vec<(Option<f32>, String)>,
+}
+
+/// Splits the text of a line into words, reading enhanced LRC `<mm:ss.xx>` word tags.
+/// Syllables of a word split by tags get the `-` continuation marker used by the game.
+fn parse_words(text: &str) -> Vec<(Option<f32>, String)> {
+    let mut words = Vec::new();
+    let mut pending_time = None;
+    let mut rest = text;
+    loop {
+        let (before, tag_and_after) = match rest.find('<') {
+            Some(pos) => (&rest[..pos], Some(&rest[pos + 1..])),
+            None => (rest, None),
+        };
+        let before_words = before.split_whitespace().count();
+        for word in before.split_whitespace() {
+            words.push((pending_time.take(), word.to_string()));
+        }
+        match tag_and_after.and_then(|t| t.split_once('>')) {
+            Some((tag, after)) => {
+                // A word split by a timing tag is sung as syllables: mark the continuation.
+                let split_word = !before.ends_with(char::is_whitespace)
+                    && after.starts_with(|c: char| !c.is_whitespace() && c != '<');
+                if split_word && before_words > 0 {
+                    if let Some((_, word)) = words.last_mut() {
+                        word.push('-');
+                    }
+                }
+                pending_time = parse_timestamp(tag);
+                rest = after;
+            }
+            None => break,
+        }
+    }
+    words
+}
+
+/// Converts timed lines into vocals. Words without their own timestamp share the line's
+/// slot evenly; the last word of every line gets the `+` line-break marker.
+fn lines_to_vocals(lines: &[TimedLine], last_end: f32) -> Vec<Vocal> {
+    let mut vocals = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        let line_end = lines.get(i + 1).map_or(last_end, |next| next.time);
+        let count = line.words.len();
+        let even_slot = (line_end - line.time).max(0.0) / count.max(1) as f32;
+        for (w, (time, word)) in line.words.iter().enumerate() {
+            let start = time.unwrap_or(line.time + even_slot * w as f32);
+            let next_start = line
+                .words
+                .get(w + 1)
+                .map(|(t, _)| t.unwrap_or(line.time + even_slot * (w + 1) as f32))
+                .unwrap_or(line_end);
+            let lyric = if w + 1 == count { format!("{}+", word) } else { word.clone() };
+            vocals.push(Vocal {
+                time: start,
+                note: UNPITCHED_NOTE,
+                length: (next_start - start).max(0.0) * SUNG_FRACTION,
+                lyric,
+            });
+        }
+    }
+    vocals
+}
+
+/// Parses an LRC lyric file (including the enhanced `<mm:ss.xx>` word timing) into vocals.
+/// Metadata tags such as `[ar:...]` are ignored and lines with several timestamps are
+/// repeated at each of them.
+pub fn parse_lrc(text: &str) -> io::Result<Vec<Vocal>> {
+    let mut lines = Vec::new();
+    for raw in text.lines() {
+        let mut rest = raw.trim();
+        let mut times = Vec::new();
+        while let Some(tag_end) = rest.strip_prefix('[').and_then(|r| r.find(']')) {
+            if let Some(time) = parse_timestamp(&rest[1..tag_end + 1]) {
+                times.push(time);
+            }
+            rest = &rest[tag_end + 2..];
+        }
+        let words = parse_words(rest);
+        if words.is_empty() {
+            continue;
+        }
+        for time in times {
+            lines.push(TimedLine { time, words: words.clone() });
+        }
+    }
+    if lines.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "No timed lyric lines found"));
+    }
+    lines.sort_by(|a, b| a.time.total_cmp(&b.time));
+    let last_end = lines.last().map_or(0.0, |l| l.time) + LAST_LINE_SECONDS;
+    Ok(lines_to_vocals(&lines, last_end))
+}
+
+/// Spreads the lines of an untimed lyric text evenly between `start` and `end` (seconds).
+pub fn parse_plain_lyrics(text: &str, start: f32, end: f32) -> Vec<Vocal> {
+    let texts: Vec<&str> = text.lines().map(str::trim).filter(|l| !l.is_empty()).collect();
+    let slot = (end - start).max(0.0) / texts.len().max(1) as f32;
+    let lines: Vec<TimedLine> = texts
+        .iter()
+        .enumerate()
+        .map(|(i, line)| TimedLine {
+            time: start + slot * i as f32,
+            words: parse_words(line),
+        })
+        .collect();
+    lines_to_vocals(&lines, end)
+}
+
+/// A line of lyrics: the syllables up to a `+` marker, joined into words.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LyricLine {
+    pub start: f32,
+    pub end: f32,
+    pub text: String,
+}
+
+/// Settings of the LRC and SRT exports.
+#[derive(Debug, Clone, Default)]
+pub struct LyricExportOptions {
+    #[cfg(feature = "romanize")]
+    romanize: bool,
+}
+
+impl LyricExportOptions {
+    pub fn new() -> Self {
+        LyricExportOptions::default()
+    }
+
+    /// Writes kana as romaji, using the furigana reading of syllables that have one (see
+    /// `romanize::to_romaji`). Kanji without a reading are kept; `romanize::unconverted_kanji`
+    /// lists those of an export.
+    #[cfg(feature = "romanize")]
+    pub fn romanize(mut self, romanize: bool) -> Self {
+        self.romanize = romanize;
+        self
+    }
+
+    /// The text a syllable is exported as.
+    fn syllable_text(&self, parts: &LyricParts) -> String {
+        #[cfg(feature = "romanize")]
+        if self.romanize {
+            return crate::romanize::to_romaji(parts.ruby.as_deref().unwrap_or(&parts.text));
+        }
+        parts.text.clone()
+    }
+}
+
+/// Groups vocals into lines. Syllables marked `-` are joined to the next one, other
+/// syllables are separated by a space.
+pub fn lyric_lines(vocals: &[Vocal], options: &LyricExportOptions) -> Vec<LyricLine> {
+    let mut lines = Vec::new();
+    let mut current: Option<LyricLine> = None;
+    let mut joined = true;
+    for vocal in vocals {
+        let parts = vocal.parts();
+        let text = options.syllable_text(&parts);
+        let line = current.get_or_insert_with(|| LyricLine { start: vocal.time, end: vocal.time, text: String::new() });
+        if !joined {
+            line.text.push(' ');
+        }
+        line.text.push_str(&text);
+        line.end = vocal.time + vocal.length;
+        joined = parts.continues;
+        if parts.line_end {
+            lines.extend(current.take());
+            joined = true;
+        }
+    }
+    lines.extend(current);
+    lines.retain(|line| !line.text.trim().is_empty());
+    lines
+}
+
+/// Formats seconds as an LRC `mm:ss.xx` timestamp.
+fn lrc_timestamp(seconds: f32) -> String {
+    let hundredths = (seconds.max(0.0) * 100.0).round() as u64;
+    format!("{:02}:{:02}.{:02}", hundredths / 6000, hundredths / 100 % 60, hundredths % 100)
+}
+
+/// Formats seconds as an SRT `hh:mm:ss,mmm` timestamp.
+fn srt_timestamp(seconds: f32) -> String {
+    let millis = (seconds.max(0.0) * 1000.0).round() as u64;
+    format!("{:02}:{:02}:{:02},{:03}", millis / 3_600_000, millis / 60_000 % 60, millis / 1000 % 60, millis % 1000)
+}
+
+/// Exports vocals as an LRC file, one timed line per lyric line.
+pub fn to_lrc(vocals: &[Vocal], options: &LyricExportOptions) -> String {
+    lyric_lines(vocals, options)
+        .iter()
+        .map(|line| format!("[{}]{}\n", lrc_timestamp(line.start), line.text))
+        .collect()
+}
+
+/// Exports vocals as SubRip subtitles, one cue per lyric line.
+pub fn to_srt(vocals: &[Vocal], options: &LyricExportOptions) -> String {
+    lyric_lines(vocals, options)
+        .iter()
+        .enumerate()
+        .map(|(i, line)| {
+            format!("{}\n{} --> {}\n{}\n\n", i + 1, srt_timestamp(line.start), srt_timestamp(line.end), line.text)
+        })
+        .collect()
+}
+
+/// Replaces the vocals of a decrypted vocals SNG, keeping everything else (including the
+/// lyric font symbol tables) byte for byte.
+pub fn replace_vocals(plain_sng: &[u8], vocals: &[Vocal]) -> io::Result<Vec<u8>> {
+    if vocals.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "No vocals to write"));
+    }
+    let mut cursor = Cursor::new(plain_sng);
+    read_vec_from_buffer(&mut cursor, Bpm::read_from)?;
+    read_vec_from_buffer(&mut cursor, Phrase::read_from)?;
+    read_vec_from_buffer(&mut cursor, Chord::read_from)?;
+    read_vec_from_buffer(&mut cursor, ChordNotes::read_from)?;
+    let vocals_start = cursor.position() as usize;
+    let old_vocals = read_vec_from_buffer(&mut cursor, Vocal::read_from)?;
+    let vocals_end = cursor.position() as usize;
+
+    let mut sng = plain_sng[..vocals_start].to_vec();
+    sng.extend_from_slice(&(vocals.len() as u32).to_le_bytes());
+    for vocal in vocals {
+        vocal.write_to(&mut sng)?;
+    }
+    if old_vocals.is_empty() {
+        // Symbol tables are only present when there are vocals: add empty ones.
+        sng.extend_from_slice(&[0u8; 12]);
+    }
+    sng.extend_from_slice(&plain_sng[vocals_end..]);
+    Ok(sng)
+}
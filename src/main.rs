@@ -0,0 +1,2245 @@
+//! Command line front end. The first argument names the command (`extract`, `list`,
+//! `info`, `cat`, `lint`, `analyze-compression`, `pack`, `replace`, `compact`, `strip`,
+//! `pack-merge`, `edit`, `tone`, `search`, `stats`, `convert`, `lyrics`, `audio`,
+//! `audio-info`, `audition` or `help`); without one the arguments are those of `extract`.
+//!
+//! * `psarc_unpacker [extract] [--filter <prefix>]... [--match <glob>]... [--output <dir>]
+//!   [--json-errors] [--no-color] [--touch archive|now] [--link-duplicates] [--rename-template <template>]
+//!   <archive.psarc>... [<output_dir>]` extracts archives into `--output`, or the last
+//!   argument when it is not given. `--filter` (repeatable) only extracts entries whose
+//!   path starts with the prefix, and `--match` (repeatable) those whose path matches a glob
+//!   pattern such as `songs/arr/*.sng` or `*.xml` (see `psarc_unpacker::glob`).
+//!   Outputs of several archives sharing a name (every song has a `cover_256.png`) are
+//!   renamed with the template, `{name}_{key}{ext}` by default, and reported.
+//!   `--only audio|art|charts|manifests|lyrics` (repeatable or comma separated) limits the
+//!   extraction to those asset classes, and `--min-size`/`--max-size` (bytes, or with a
+//!   `K`, `M` or `G` binary suffix) to entries within those inflated sizes.
+//!   `--max-duration <time>` (`90s`, `2m`, `500ms`) stops starting new entries once the
+//!   budget is spent, extracting metadata and art first; the skipped entries are reported
+//!   and the run ends as a partial extraction. `--state <file>` saves the progress of the
+//!   batch to a job state file; rerunning the same command after a crash skips the work
+//!   already done. `--cache <dir>` keeps the SNG JSON conversions (and the audio converted
+//!   for `--synthesize-preview`) in `dir`, keyed by the content of the source entry, and
+//!   reuses them on later runs; `--user-cache` uses the
+//!   per-user cache folder instead. `--synthesize-preview <time>`
+//!   writes a `<key>_preview.ogg` of that length, cut from the main track at the preview
+//!   start, for songs shipped without a preview (`.wav` for PCM tracks; tracks in other
+//!   codecs are skipped with a warning). `--difficulty <level>|max` exports the SNG
+//!   JSON of each arrangement as that single dynamic difficulty level, and `--difficulty all`
+//!   writes one `<name>.sng.d<level>.json` per level. `--compress-output gzip|zstd` compresses those
+//!   JSON exports (`<name>.sng.json.gz`, `<name>.sng.json.zst`); extracted entries are written as stored. Extracted files get the archive's
+//!   modification time, or the current time with `--touch now`. `--link-duplicates` hard
+//!   links entries with identical content instead of writing them twice. `--steam` adds
+//!   every archive of the Rocksmith 2014 `dlc` folders of the local Steam libraries (see
+//!   `psarc_unpacker::steam`), so `extract --steam <output_dir>` unpacks the whole library.
+//! * `psarc_unpacker list [--paths-only] [-0] [--largest <n>] <archive.psarc>` lists its
+//!   entries. With `--paths-only` every internal path is printed on its own line, and `-0`
+//!   separates them with NUL bytes instead, for `xargs -0` and similar tools.
+//!   `--largest <n>` only lists the `n` biggest entries, biggest first.
+//! * `psarc_unpacker info <archive.psarc>` prints the header fields (version, compression,
+//!   flags, TOC and block sizes) and TOC statistics: entry count, inflated and stored sizes,
+//!   the app id of the `appid.appid` entry, and whether the package is official DLC or a custom song, with the toolkit that built
+//!   it (see `psarc_unpacker::origin`).
+//! * `psarc_unpacker cat <archive.psarc> <entry>` writes the inflated content of an entry
+//!   to stdout. The path may use `\` separators and any case
+//!   (`Songs\Bin\Generic\x_lead.sng`).
+//! * `psarc_unpacker lint <archive.psarc>` checks a package before distribution: references
+//!   to missing assets are errors, unreferenced assets and manifests disagreeing with their
+//!   charts are warnings. With `--json-errors` the issues are in the JSON summary.
+//! * `psarc_unpacker analyze-compression [--output <optimized.psarc>] <archive.psarc>` lists
+//!   the entries that would take less space recompressed at the best zlib level or stored
+//!   uncompressed, biggest saving first, with the total. With `--output` the archive is
+//!   repacked that way (see `repack::optimize_compression`).
+//! * `psarc_unpacker pack [--plain-toc] [--dry-run|--no-validate] [--keep-manifests]
+//!   [--appid <id>] <folder> <archive.psarc>` builds an archive from the files below the folder, named by their path
+//!   relative to it (see `PsarcWriter::add_dir`). The TOC is encrypted unless `--plain-toc`
+//!   is given. The manifest attributes derived from the charts (song length, maximum
+//!   difficulty, note counts, score) are refreshed from the SNG files, so edited charts keep
+//!   a consistent package (see `repack::refresh_manifests_in`); `--keep-manifests` packs the
+//!   manifests as they are. The
+//!   package is first checked against what the game loads (see `lint::validate_pack`):
+//!   errors stop the pack before anything is written, unless `--no-validate` is given.
+//!   `--dry-run` only prints the checks; the archive argument can then be left out.
+//!   `--appid <id>` sets the `appid.appid` entry to that Steam app id.
+//! * `psarc_unpacker replace [--appid <id>] <archive.psarc> [<entry> <file>]` replaces the
+//!   content of an entry with the file, in place: the new blocks are appended to the archive
+//!   and only the TOC is rewritten, so editing one chart of a large pack does not rewrite the
+//!   pack (see `psarc_unpacker::patch`). The old blocks are left as dead space. `--appid`
+//!   sets the app id the same way, or adds the entry by rewriting the archive when it has
+//!   none; a mismatched app id is a common reason a package does not load.
+//! * `psarc_unpacker compact <archive.psarc> [<output.psarc>]` rewrites the archive without
+//!   dead space, over itself unless an output is given. Stored blocks are copied as they
+//!   are.
+//! * `psarc_unpacker strip [--filter <prefix>]... [--match <glob>]... <archive.psarc>
+//!   <output.psarc>` writes the archive without the entries the filters select (preview
+//!   audio, console variants), copying the stored blocks of the others as they are (see
+//!   `repack::strip_archive`).
+//! * `psarc_unpacker pack-merge <output.psarc> <archive.psarc>...` combines archives, such
+//!   as single-song packs, into one. Entries found in several archives with the same content
+//!   are stored once; differing entries with the same path fail the merge (see
+//!   `repack::merge_archives`).
+//! * `psarc_unpacker edit <operation> <archive.psarc> ... [<output.psarc>]` repacks a
+//!   package with one edit, over itself unless an output is given:
+//!   `remove-arrangement --arrangement <name>` drops an arrangement (`bass`, `rhythm`, ...)
+//!   with its manifest and references, `extract-arrangement --arrangement <name>` keeps only
+//!   that one (see `repack::remove_arrangement`), `set-art <image.png>` replaces the album
+//!   art textures (needs the `image` feature), and `set-audio [--preview] [--encoder
+//!   <program>] <audio>` replaces the main or preview audio (needs the `audio` feature).
+//!   Audio that is not a wem is converted by running `<program> <input> <output.wem>`.
+//! * `psarc_unpacker tone export <archive.psarc> [<tone key> [<tone.json>]]` lists the tone
+//!   keys of a package, or writes the definition of one tone as JSON (to stdout without a
+//!   file). `psarc_unpacker tone import --arrangement <name> [--slot base|a|b|c|d]
+//!   <archive.psarc> <tone.json> [<output.psarc>]` puts that tone into a slot (the base tone
+//!   by default) of an arrangement of another package (see `psarc_unpacker::tones`).
+//! * `psarc_unpacker search [--index <file>] [--playlist <file>] [--audio-dir <dir>]
+//!   <folder> <query>` prints the archives and SNG entries below the folder matching a query
+//!   such as `"artist:metallica tuning:drop-d"` (see `psarc_unpacker::search`), one
+//!   `<archive>\t<entry>` line each, for other commands to consume. The library index is
+//!   kept in the per-user data folder, or in the JSON file given with `--index`, so later
+//!   searches only read the archives that changed.
+//!   `--playlist` also writes the hits as an M3U (`.m3u`), TABS setlist (`.setlist`) or
+//!   JSON playlist (see `psarc_unpacker::playlist`), whose audio points at the extracted
+//!   `<wem id>.ogg` files in `--audio-dir` (the current folder by default). Needs the `sng`
+//!   feature.
+//! * `psarc_unpacker stats --library <folder>` aggregates the archives below the folder:
+//!   arrangement counts, tuning distribution, tempo range, total play length and songs found
+//!   in several packs (see `psarc_unpacker::library::LibraryStats`). Needs the `sng` feature.
+//! * `psarc_unpacker convert [--format xml|json] [--compact] <archive.psarc> <output_dir>`
+//!   converts every SNG arrangement of the archive: `xml` (the default) writes the
+//!   Rocksmith arrangement XML read by EOF and the toolkits as `<name>.xml` (see
+//!   `psarc_unpacker::xml`), `json` the whole parsed arrangement (beats, phrases, chords,
+//!   every level's notes, metadata) as `<name>.sng.json`, on one line with `--compact`.
+//!   Needs the `sng` feature.
+//! * `psarc_unpacker lyrics [--format lrc|srt] [--romanize] <archive.psarc> [<output>]`
+//!   writes the lyrics of the first song with vocals as LRC (the default) or SubRip
+//!   subtitles, to stdout without an output file (see `psarc_unpacker::lyrics`). Needs the
+//!   `sng` feature. `--romanize` writes kana as romaji (needs the `romanize` feature):
+//!   there is no kanji dictionary, so kanji are only converted through the furigana
+//!   reading a syllable carries; the others are kept and reported on stderr and in the
+//!   `--json-errors` summary.
+//! * `psarc_unpacker audio [--codebooks <file>] [--packet-format modified|standard]
+//!   [--inline-codebooks] [--no-fallback] [--cache <dir>|--user-cache] <archive.psarc>
+//!   <output_dir>` converts the
+//!   song audio of the archive to Ogg Vorbis (WAV for PCM streams), named
+//!   `Artist - Title.ogg` and `Artist - Title (preview).ogg` from the manifests, the full
+//!   track and the preview told apart through the song banks (see
+//!   `psarc_unpacker::audio::export_song_audio`). `--codebooks` names the packed Vorbis
+//!   codebooks file, looked up as `psarc_unpacker::codebook` describes otherwise.
+//!   `--packet-format` reads the Vorbis packets in that format whatever the wem declares,
+//!   `--inline-codebooks` reads the codebooks from the streams first, and `--no-fallback`
+//!   stops at the first failed rebuild instead of trying the other packet format and
+//!   codebook source (see `psarc_unpacker::audio::AudioOptions`). `--cache` and
+//!   `--user-cache` reuse the WAV and Ogg conversions of earlier runs, as for `extract`.
+//!   Needs the `audio` feature.
+//! * `psarc_unpacker audio-info <archive.psarc>` prints the codec, channels, sample rate and
+//!   length of every wem of the archive without converting anything. Needs the `audio`
+//!   feature.
+//! * `psarc_unpacker audition [--arrangement <name>] [--start <time>] [--duration <time>]
+//!   [--soundfont <file.sf2>] <archive.psarc> <output.wav>` renders a clip of an
+//!   arrangement (`lead` by default) of the first song of the archive from its MIDI export,
+//!   10 seconds from the start unless told otherwise. With `--soundfont` the notes are
+//!   played by that SoundFont, otherwise by a built-in plucked string (see
+//!   `psarc_unpacker::audition`). Needs the `audition` feature.
+//!
+//! In every mode `--layouts <file>` reads platform folder overrides (a JSON object such as
+//! `{"pc": {"album_art": "gfxassets/custom_art"}}`, see `psarc_unpacker::layout`) for
+//! packages that keep their audio, charts or art in unusual folders, and `--names <file>`
+//! (repeatable) reads a dictionary of known entry paths, one per line, to name the entries
+//! of archives that list them by hash only (see `psarc_unpacker::names`). Without them,
+//! `layouts.json` and `names.txt` of the per-user config folder are read when present.
+//! The per-user cache, config and data folders follow the platform conventions and can
+//! be moved with `PSARC_UNPACKER_CACHE_DIR`, `PSARC_UNPACKER_CONFIG_DIR` and
+//! `PSARC_UNPACKER_DATA_DIR` (see `psarc_unpacker::user_dirs`).
+//!
+//! Exit codes are stable so scripts (and the TABS importer) can branch on the outcome:
+//!
+//! | Code | Meaning |
+//! |------|---------|
+//! | 0 | Every entry extracted and converted |
+//! | 2 | Invalid command line |
+//! | 3 | Bad archive: the file is not a readable PSARC |
+//! | 4 | Partial extraction: some entries could not be inflated, or were skipped by `--max-duration` |
+//! | 5 | Conversion failures: entries extracted, but some SNG to JSON conversions failed |
+//! | 6 | I/O error reading the archive or writing the output |
+//! | 7 | `lint` or the `pack` validation found errors |
+//!
+//! With `--json-errors` the last line on stdout is a JSON summary such as
+//! `{"status":"partial_extraction","exit_code":4,"written":12,"failed":[...],"conversion_failed":[]}`.
+//! Log output goes to stderr.
+//!
+//! Human output is coloured when stdout is a terminal, unless `--no-color` is given or the
+//! `NO_COLOR` environment variable is set.
+
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use serde_json::json;
+
+#[cfg(feature = "audio")]
+use psarc_unpacker::audio::{export_song_audio, AudioConverter, AudioOptions, PacketFormat};
+#[cfg(feature = "audition")]
+use psarc_unpacker::audition::{export_audition, load_sound_font};
+#[cfg(feature = "image")]
+use psarc_unpacker::dds::decode_png;
+use psarc_unpacker::cache::ConversionCache;
+use psarc_unpacker::content_type::AssetClass;
+use psarc_unpacker::extract::{
+    extract_batch, extract_batch_resumable, BatchReport, DifficultySelection, ExtractOptions, ExtractReport,
+    OutputCompression, DEFAULT_RENAME_TEMPLATE,
+};
+use psarc_unpacker::glob::GlobPattern;
+use psarc_unpacker::layout::PackageLayouts;
+use psarc_unpacker::lint::{lint_archive, LintIssue, LintSeverity};
+use psarc_unpacker::names::{open_path_with_names, NameDictionary};
+use psarc_unpacker::origin::OriginReport;
+use psarc_unpacker::patch::{compact_path, replace_entry_in_place, set_app_id_in_place};
+use psarc_unpacker::psarc::PsarcFile;
+#[cfg(feature = "sng")]
+use psarc_unpacker::library::{split_sng_path, LibraryStats};
+#[cfg(feature = "sng")]
+use psarc_unpacker::library_service::LibraryService;
+use psarc_unpacker::repack::{
+    analyze_compression, extract_arrangement, merge_archives, optimize_compression, remove_arrangement, set_app_id,
+    set_app_id_in, strip_archive, CompressionChoice, CompressionReport,
+};
+#[cfg(feature = "image")]
+use psarc_unpacker::repack::set_album_art;
+#[cfg(feature = "audio")]
+use psarc_unpacker::repack::{set_audio, AudioSlot, WemEncoder};
+#[cfg(feature = "sng")]
+use psarc_unpacker::repack::refresh_manifests_in;
+#[cfg(feature = "sng")]
+use psarc_unpacker::song::Song;
+#[cfg(feature = "sng")]
+use psarc_unpacker::lyrics::{to_lrc, to_srt, LyricExportOptions};
+#[cfg(all(feature = "sng", feature = "romanize"))]
+use psarc_unpacker::romanize::unconverted_kanji;
+#[cfg(feature = "sng")]
+use psarc_unpacker::playlist::{Playlist, PlaylistFormat};
+#[cfg(feature = "sng")]
+use psarc_unpacker::search::SongQuery;
+use psarc_unpacker::steam::{steam_dlc_archives, STEAM_DIR_ENV};
+use psarc_unpacker::tones::{export_tone, import_tone, tone_keys, ToneSlot};
+use psarc_unpacker::user_dirs::{UserDirs, CACHE_DIR_ENV};
+use psarc_unpacker::writer::PsarcWriter;
+
+const USAGE: &str = "Usage: psarc_unpacker [extract] [--filter <prefix>]... [--match <glob>]... [--output <dir>]
+                      [--json-errors] [--no-color] [--touch archive|now] [--link-duplicates]
+                      [--rename-template <template>] [--only <class>[,<class>...]]
+                      [--min-size <size>] [--max-size <size>] [--max-duration <time>]
+                      [--state <file>] [--cache <dir>|--user-cache] [--synthesize-preview <time>]
+                      [--difficulty <level>|max|all] [--compress-output gzip|zstd]
+                      [--layouts <file>] [--names <file>] [--steam]
+                      <archive.psarc>... [<output_dir>]
+       psarc_unpacker list [--paths-only] [-0] [--largest <n>] [--json-errors] [--no-color]
+                      [--layouts <file>] [--names <file>] <archive.psarc>
+       psarc_unpacker info [--json-errors] [--no-color] [--names <file>] <archive.psarc>
+       psarc_unpacker cat [--layouts <file>] [--names <file>] <archive.psarc> <entry>
+       psarc_unpacker lint [--json-errors] [--no-color] [--layouts <file>] [--names <file>] <archive.psarc>
+       psarc_unpacker analyze-compression [--output <optimized.psarc>] [--json-errors] [--no-color]
+                      [--names <file>] <archive.psarc>
+       psarc_unpacker pack [--plain-toc] [--dry-run|--no-validate] [--keep-manifests] [--appid <id>]
+                      [--json-errors] [--no-color] <folder> [<archive.psarc>]
+       psarc_unpacker replace [--appid <id>] [--json-errors] [--no-color] <archive.psarc> [<entry> <file>]
+       psarc_unpacker compact [--json-errors] [--no-color] <archive.psarc> [<output.psarc>]
+       psarc_unpacker strip [--filter <prefix>]... [--match <glob>]... [--json-errors] [--no-color]
+                      <archive.psarc> <output.psarc>
+       psarc_unpacker pack-merge [--json-errors] [--no-color] <output.psarc> <archive.psarc>...
+       psarc_unpacker edit remove-arrangement|extract-arrangement --arrangement <name> [--json-errors]
+                      [--no-color] <archive.psarc> [<output.psarc>]
+       psarc_unpacker edit set-art [--json-errors] [--no-color] <archive.psarc> <image.png> [<output.psarc>]
+       psarc_unpacker edit set-audio [--preview] [--encoder <program>] [--json-errors] [--no-color]
+                      <archive.psarc> <audio> [<output.psarc>]
+       psarc_unpacker tone export [--json-errors] [--no-color] <archive.psarc> [<tone key> [<tone.json>]]
+       psarc_unpacker tone import --arrangement <name> [--slot base|a|b|c|d] [--json-errors] [--no-color]
+                      <archive.psarc> <tone.json> [<output.psarc>]
+       psarc_unpacker search [--index <file>] [--playlist <file>] [--audio-dir <dir>] [--json-errors]
+                      <folder> <query>
+       psarc_unpacker stats --library <folder> [--json-errors] [--no-color]
+       psarc_unpacker convert [--format xml|json] [--compact] [--json-errors] [--no-color] <archive.psarc> <output_dir>
+       psarc_unpacker lyrics [--format lrc|srt] [--romanize] [--json-errors] [--no-color] <archive.psarc> [<output>]
+       psarc_unpacker audio [--codebooks <file>] [--packet-format modified|standard] [--inline-codebooks]
+                      [--no-fallback] [--cache <dir>|--user-cache] [--json-errors] [--no-color]
+                      <archive.psarc> <output_dir>
+       psarc_unpacker audio-info [--json-errors] [--no-color] <archive.psarc>
+       psarc_unpacker audition [--arrangement <name>] [--start <time>] [--duration <time>]
+                      [--soundfont <file.sf2>] [--json-errors] [--no-color] <archive.psarc> <output.wav>
+
+Commands:
+  extract  Unpack archives into a folder (the default when no command is given)
+  list     Print the entries of an archive with their sizes
+  info     Print the header fields and TOC statistics of an archive
+  cat      Write the content of one entry to stdout
+  lint     Check the references and manifests of a package
+  analyze-compression
+           Report entries that would be smaller recompressed or stored raw
+  pack     Build an archive from the files of a folder
+  replace  Replace the content of one entry in place, appending its blocks
+  compact  Rewrite an archive without the dead space left by replace
+  strip    Copy an archive without the entries --filter and --match select
+  pack-merge
+           Combine several archives into one, storing shared entries once
+  edit     Repack a package without an arrangement (remove-arrangement), with only one
+           (extract-arrangement), or with new album art (set-art) or audio (set-audio)
+  tone     List and export the tones of a package, or import one into an arrangement
+  search   Find songs of a library folder (`artist:<name> title:<name> album:<name>
+           tuning:<name> year:<year>[-<year>] arrangement:<name> origin:official|custom`)
+  stats    Aggregate tunings, tempos, arrangements and duplicates over a library folder
+  convert  Write the SNG arrangements of an archive as Rocksmith XML (or JSON)
+  lyrics   Write the lyrics of a song as LRC or SRT; --romanize writes kana as romaji,
+           kanji without a furigana reading are kept (there is no kanji dictionary)
+           and reported
+  audio    Convert the song audio of an archive to Ogg, named `Artist - Title.ogg`
+  audio-info
+           Print the codec, channels, sample rate and length of every wem
+  audition Render a clip of an arrangement from its MIDI, through a SoundFont or a
+           built-in plucked string
+  help     Print this help
+
+Without --output the last argument of extract is the output folder. --filter only unpacks
+entries whose path starts with the prefix, --match those whose path matches the glob
+(`songs/arr/*.sng`, `*.xml`, `audio/**/*.wem`); both can be given several times.
+--steam adds the DLC archives of the Rocksmith 2014 Steam install (set STEAM_DIR when Steam
+is not in its default folder).
+
+The per-user folders hold the conversion cache (--user-cache), layouts.json and names.txt
+(read when --layouts and --names are not given) and the search indexes. Move them with
+PSARC_UNPACKER_CACHE_DIR, PSARC_UNPACKER_CONFIG_DIR and PSARC_UNPACKER_DATA_DIR.";
+
+/// Subcommands; without one the arguments are those of `extract`.
+const COMMANDS: [&str; 21] = [
+    "extract", "list", "info", "cat", "lint", "analyze-compression", "pack", "replace", "compact", "strip", "pack-merge",
+    "edit", "tone", "search", "stats", "convert", "lyrics", "audio", "audio-info", "audition", "help",
+];
+
+/// Failure classes reported through the exit code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Success,
+    Usage,
+    BadArchive,
+    PartialExtraction,
+    ConversionFailures,
+    Io,
+    LintErrors,
+}
+
+impl Outcome {
+    fn code(self) -> u8 {
+        match self {
+            Outcome::Success => 0,
+            Outcome::Usage => 2,
+            Outcome::BadArchive => 3,
+            Outcome::PartialExtraction => 4,
+            Outcome::ConversionFailures => 5,
+            Outcome::Io => 6,
+            Outcome::LintErrors => 7,
+        }
+    }
+
+    fn status(self) -> &'static str {
+        match self {
+            Outcome::Success => "ok",
+            Outcome::Usage => "usage",
+            Outcome::BadArchive => "bad_archive",
+            Outcome::PartialExtraction => "partial_extraction",
+            Outcome::ConversionFailures => "conversion_failures",
+            Outcome::Io => "io_error",
+            Outcome::LintErrors => "lint_errors",
+        }
+    }
+
+    /// Class of an error raised while opening an archive: malformed data means a bad
+    /// archive, anything else (missing file, permissions) is an I/O error.
+    fn of_open_error(err: &io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof | io::ErrorKind::Unsupported => {
+                Outcome::BadArchive
+            }
+            _ => Outcome::Io,
+        }
+    }
+
+    /// The more severe of two outcomes, for batches: an unreadable archive outranks I/O
+    /// errors, which outrank partial extractions, which outrank conversion failures.
+    fn worst(self, other: Outcome) -> Self {
+        let rank = |outcome: Outcome| match outcome {
+            Outcome::Success => 0,
+            Outcome::ConversionFailures | Outcome::LintErrors => 1,
+            Outcome::PartialExtraction => 2,
+            Outcome::Io => 3,
+            Outcome::BadArchive => 4,
+            Outcome::Usage => 5,
+        };
+        if rank(other) > rank(self) {
+            other
+        } else {
+            self
+        }
+    }
+
+    fn of_report(report: &ExtractReport) -> Self {
+        if !report.failed.is_empty() || !report.not_started.is_empty() {
+            Outcome::PartialExtraction
+        } else if !report.conversion_failed.is_empty() {
+            Outcome::ConversionFailures
+        } else {
+            Outcome::Success
+        }
+    }
+}
+
+// Built once per run, so the size of `Extract` does not matter.
+#[allow(clippy::large_enum_variant)]
+enum Mode {
+    /// Extracts one or more archives into `output_dir`. `touch_now` writes files with the
+    /// current time instead of the archive's; `rename_template` names outputs colliding
+    /// with those of an earlier archive.
+    Extract {
+        output_dir: PathBuf,
+        /// Entry path prefixes to extract (`--filter`), every entry when empty.
+        filters: Vec<String>,
+        /// Glob patterns the extracted entry paths match (`--match`).
+        patterns: Vec<GlobPattern>,
+        touch_now: bool,
+        link_duplicates: bool,
+        rename_template: String,
+        only: Vec<AssetClass>,
+        min_size: Option<u64>,
+        max_size: Option<u64>,
+        max_duration: Option<Duration>,
+        state: Option<PathBuf>,
+        cache: Option<PathBuf>,
+        preview: Option<Duration>,
+        difficulty: Option<DifficultySelection>,
+        compress_output: Option<OutputCompression>,
+    },
+    /// `separator` is `None` for the human listing, otherwise the byte printed after each
+    /// path. `largest` limits the listing to the biggest entries.
+    List { separator: Option<u8>, largest: Option<usize> },
+    /// Writes the inflated content of one entry to stdout.
+    Cat { entry: String },
+    /// Checks the references and manifests of an archive.
+    Lint,
+    /// Prints the header fields and TOC statistics of an archive.
+    Info,
+    /// Reports the entries that would be smaller recompressed or stored raw, and writes
+    /// the optimized archive to `output` when given.
+    AnalyzeCompression { output: Option<PathBuf> },
+    /// Packs the files below `folder` into the archive, with an encrypted TOC unless
+    /// `plain_toc`, once they pass the game loader checks unless `validate` is off.
+    /// `dry_run` only runs the checks. With `refresh`, the manifests are updated from the charts first.
+    /// `app_id` goes into the `appid.appid` entry.
+    Pack { folder: PathBuf, plain_toc: bool, validate: bool, dry_run: bool, refresh: bool, app_id: Option<String> },
+    /// Replaces the content of an entry with a file (`content`, entry path then file) and
+    /// sets the app id to `app_id`, in place.
+    Replace { content: Option<(String, PathBuf)>, app_id: Option<String> },
+    /// Rewrites the archive without dead space into `output`, over itself when `None`.
+    Compact { output: Option<PathBuf> },
+    /// Writes the archive without the entries `selection` selects into `output`.
+    Strip { output: PathBuf, selection: ExtractOptions },
+    /// Merges the archives into `output`.
+    PackMerge { output: PathBuf },
+    /// Repacks the archive with `edit` applied into `output`, over itself when `None`.
+    Edit { edit: Edit, output: Option<PathBuf> },
+    /// Prints the tone keys of an archive, or writes the tone `key` as JSON into `output`
+    /// (stdout when `None`).
+    ToneExport { key: Option<String>, output: Option<PathBuf> },
+    /// Repacks the archive with the tone read from `tone` in `slot` of `arrangement`, into
+    /// `output`, over itself when `None`.
+    ToneImport { tone: PathBuf, arrangement: String, slot: ToneSlot, output: Option<PathBuf> },
+    /// Prints the songs of the library below `folder` matching `query`, indexed in `index`
+    /// (the per-user index of the folder by default), and writes them to `playlist` with their audio in `audio_dir`.
+    #[cfg(feature = "sng")]
+    Search { folder: PathBuf, query: SongQuery, index: Option<PathBuf>, playlist: Option<PathBuf>, audio_dir: Option<PathBuf> },
+    /// Writes every SNG arrangement of an archive into `output_dir` in `format`.
+    #[cfg(feature = "sng")]
+    Convert { output_dir: PathBuf, format: ConvertFormat },
+    /// Prints the statistics of the library below `folder`.
+    #[cfg(feature = "sng")]
+    Stats { folder: PathBuf },
+    /// Writes the lyrics of an archive in `format` into `output` (stdout when `None`), as
+    /// romaji when `romanize` is set.
+    #[cfg(feature = "sng")]
+    Lyrics { format: LyricFormat, romanize: bool, output: Option<PathBuf> },
+    /// Converts the song audio of an archive into `output_dir`, named from the manifests,
+    /// with the codebooks read from `codebooks` when given, rebuilt as `options` says,
+    /// reusing the conversions cached in `cache`.
+    #[cfg(feature = "audio")]
+    Audio { output_dir: PathBuf, codebooks: Option<PathBuf>, options: AudioOptions, cache: Option<PathBuf> },
+    /// Prints the format of every wem of an archive.
+    #[cfg(feature = "audio")]
+    AudioInfo,
+    /// Renders `duration` of `arrangement` from `start` into `output`, through the
+    /// SoundFont `sound_font` when given.
+    #[cfg(feature = "audition")]
+    Audition { output: PathBuf, arrangement: String, start: Duration, duration: Duration, sound_font: Option<PathBuf> },
+}
+
+/// Package edits of `edit`.
+enum Edit {
+    RemoveArrangement(String),
+    ExtractArrangement(String),
+    /// Album art from the PNG file.
+    #[cfg(feature = "image")]
+    SetArt(PathBuf),
+    /// Audio from the file, a wem or anything `encoder` converts.
+    #[cfg(feature = "audio")]
+    SetAudio { audio: PathBuf, slot: AudioSlot, encoder: Option<String> },
+}
+
+/// Output of `convert`.
+#[cfg(feature = "sng")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ConvertFormat {
+    Xml,
+    Json,
+    /// JSON on one line (`--compact`).
+    CompactJson,
+}
+
+#[cfg(feature = "sng")]
+impl std::str::FromStr for ConvertFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "xml" => Ok(ConvertFormat::Xml),
+            "json" => Ok(ConvertFormat::Json),
+            other => Err(format!("--format expects `xml` or `json`, got {:?}", other)),
+        }
+    }
+}
+
+/// Output of `lyrics`.
+#[cfg(feature = "sng")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LyricFormat {
+    Lrc,
+    Srt,
+}
+
+#[cfg(feature = "sng")]
+impl std::str::FromStr for LyricFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "lrc" => Ok(LyricFormat::Lrc),
+            "srt" => Ok(LyricFormat::Srt),
+            other => Err(format!("--format expects `lrc` or `srt`, got {:?}", other)),
+        }
+    }
+}
+
+struct Args {
+    mode: Mode,
+    archives: Vec<PathBuf>,
+    json_errors: bool,
+    no_color: bool,
+    /// Folder layout overrides (`--layouts`).
+    layouts: Option<PathBuf>,
+    /// Entry path dictionaries (`--names`).
+    names: Vec<PathBuf>,
+}
+
+/// ANSI styling for human output, disabled when not writing to a terminal.
+#[derive(Debug, Clone, Copy)]
+struct Style {
+    color: bool,
+}
+
+impl Style {
+    fn new(no_color: bool) -> Self {
+        let no_color_env = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+        Style { color: !no_color && !no_color_env && io::stdout().is_terminal() }
+    }
+
+    fn paint(&self, code: &str, text: &str) -> String {
+        if self.color {
+            format!("\x1b[{}m{}\x1b[0m", code, text)
+        } else {
+            text.to_string()
+        }
+    }
+
+    fn bold(&self, text: &str) -> String {
+        self.paint("1", text)
+    }
+
+    fn green(&self, text: &str) -> String {
+        self.paint("32", text)
+    }
+
+    fn yellow(&self, text: &str) -> String {
+        self.paint("33", text)
+    }
+
+    fn red(&self, text: &str) -> String {
+        self.paint("31", text)
+    }
+}
+
+/// Formats a byte count with binary units: `512 B`, `3.4 MiB`.
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+/// Formats a duration as `850 ms`, `12.3 s` or `2m 05s`.
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs_f64();
+    if seconds < 1.0 {
+        format!("{} ms", duration.as_millis())
+    } else if seconds < 60.0 {
+        format!("{:.1} s", seconds)
+    } else {
+        format!("{}m {:02}s", duration.as_secs() / 60, duration.as_secs() % 60)
+    }
+}
+
+/// Header fields, TOC statistics and origin printed by `info`.
+fn archive_info(psarc: &PsarcFile) -> serde_json::Value {
+    let header = &psarc.header;
+    let entries = &psarc.toc.entries;
+    let inflated: u64 = entries.iter().map(|e| e.length).sum();
+    let stored: u64 = entries.iter().map(|e| psarc.stored_length(e)).sum();
+    json!({
+        "identifier": header.identifier,
+        "version": format!("{}.{}", header.version_parts().0, header.version_parts().1),
+        "compression": header.compression,
+        "flags": format!("{:?}", header.archive_flags),
+        "toc_size": header.toc_size,
+        "toc_entry_size": header.toc_entry_size,
+        "toc_encrypted": psarc.toc.encrypted,
+        "block_size": header.block_size,
+        "blocks": psarc.toc.zip_block_sizes.len(),
+        "entries": entries.len(),
+        "named_entries": entries.iter().filter(|e| e.path.is_some()).count(),
+        "inflated_size": inflated,
+        "stored_size": stored,
+        "app_id": psarc.app_id().unwrap_or_else(|err| {
+            tracing::warn!("Could not read the app id: {}", err);
+            None
+        }),
+        "origin": psarc.origin().unwrap_or_else(|err| {
+            tracing::warn!("Could not classify the package: {}", err);
+            OriginReport::default()
+        }),
+    })
+}
+
+/// Prints `archive_info` as aligned label/value lines.
+fn print_info(style: Style, archive: &Path, info: &serde_json::Value) {
+    let inflated = info["inflated_size"].as_u64().unwrap_or(0);
+    let stored = info["stored_size"].as_u64().unwrap_or(0);
+    let ratio = if inflated == 0 { 0.0 } else { 100.0 * stored as f64 / inflated as f64 };
+    let lines = [
+        ("Archive", archive.display().to_string()),
+        (
+            "Format",
+            format!("{} {}, {}", info["identifier"].as_str().unwrap_or(""), info["version"].as_str().unwrap_or(""), info["compression"].as_str().unwrap_or("")),
+        ),
+        ("Flags", info["flags"].as_str().unwrap_or("").to_string()),
+        (
+            "TOC",
+            format!(
+                "{} bytes, {} byte entries{}",
+                info["toc_size"],
+                info["toc_entry_size"],
+                if info["toc_encrypted"].as_bool() == Some(true) { ", encrypted" } else { "" }
+            ),
+        ),
+        ("Blocks", format!("{} of {} bytes", info["blocks"], info["block_size"])),
+        ("Entries", format!("{} ({} named)", info["entries"], info["named_entries"])),
+        ("Inflated", format_size(inflated)),
+        ("Stored", format!("{} ({:.1}%)", format_size(stored), ratio)),
+        ("App ID", info["app_id"].as_str().unwrap_or("none").to_string()),
+        ("Origin", origin_line(&info["origin"])),
+    ];
+    for (label, value) in lines {
+        println!("{} {}", style.bold(&format!("{:<9}", format!("{}:", label))), value);
+    }
+    let toolkit = &info["origin"]["toolkit"];
+    let fields = [
+        ("Toolkit", "toolkit_version"),
+        ("Author", "package_author"),
+        ("Package", "package_version"),
+        ("Comment", "package_comment"),
+    ];
+    for (label, key) in fields {
+        if let Some(value) = toolkit[key].as_str() {
+            println!("{} {}", style.bold(&format!("{:<9}", format!("{}:", label))), value);
+        }
+    }
+}
+
+/// `custom (toolkit.version entry, ...)` for an `OriginReport` as JSON.
+fn origin_line(origin: &serde_json::Value) -> String {
+    let name = origin["origin"].as_str().unwrap_or("unknown");
+    let signals: Vec<&str> = origin["signals"].as_array().into_iter().flatten().filter_map(|s| s.as_str()).collect();
+    if signals.is_empty() {
+        name.to_string()
+    } else {
+        format!("{} ({})", name, signals.join(", "))
+    }
+}
+
+/// Prints the entries `analyze-compression` would shrink, biggest saving first, and the total.
+fn print_compression(style: Style, report: &CompressionReport) {
+    let improvable = report.improvable();
+    let width = improvable.iter().map(|e| format_size(e.saving()).len()).max().unwrap_or(0);
+    for entry in &improvable {
+        let choice = match entry.choice {
+            CompressionChoice::Recompress => "recompress",
+            CompressionChoice::Store => "store",
+            CompressionChoice::Keep => "keep",
+        };
+        println!(
+            "{:>width$}  {}  {}",
+            format_size(entry.saving()),
+            style.yellow(&format!("{:<10}", choice)),
+            entry.path,
+            width = width
+        );
+    }
+    let stored = report.stored();
+    let percent = if stored == 0 { 0.0 } else { 100.0 * report.saving() as f64 / stored as f64 };
+    println!(
+        "{} {} of {} could be saved ({:.1}%) in {} of {} entries",
+        style.bold("Total:"),
+        format_size(report.saving()),
+        format_size(stored),
+        percent,
+        improvable.len(),
+        report.entries.len()
+    );
+}
+
+/// Prints the extraction summary of one archive and one aligned line per failure.
+fn print_report(style: Style, report: &ExtractReport, archive: &Path, elapsed: Option<Duration>) {
+    let bytes: u64 = report.written.iter().filter_map(|p| fs::metadata(p).ok()).map(|m| m.len()).sum();
+    let status = if report.is_complete() { style.green("Extracted") } else { style.yellow("Extracted") };
+    let linked = if report.linked.is_empty() {
+        String::new()
+    } else {
+        format!(", {} linked", report.linked.len())
+    };
+    let elapsed = elapsed.map(|d| format!(" in {}", format_duration(d))).unwrap_or_default();
+    println!(
+        "{} {} files ({}{}) from {}{}",
+        style.bold(&status),
+        report.written.len(),
+        format_size(bytes),
+        linked,
+        archive.display(),
+        elapsed
+    );
+    let width = report
+        .failed
+        .iter()
+        .chain(&report.conversion_failed)
+        .map(|(path, _)| path.len())
+        .max()
+        .unwrap_or(0);
+    for (label, failures) in [("failed", &report.failed), ("not converted", &report.conversion_failed)] {
+        for (path, err) in failures {
+            println!("  {} {:<width$}  {}", style.red(&format!("{:<13}", label)), path, err, width = width);
+        }
+    }
+    if !report.not_started.is_empty() {
+        println!(
+            "  {} {} entries skipped, the time budget ran out",
+            style.yellow(&format!("{:<13}", "not started")),
+            report.not_started.len()
+        );
+    }
+}
+
+/// Parses a size such as `4096`, `512K`, `20M` or `1.5G` (binary units) into bytes.
+fn parse_size(value: Option<String>) -> Result<u64, String> {
+    let value = value.ok_or("Expected a size")?;
+    let trimmed = value.trim().trim_end_matches("iB").trim_end_matches(['B', 'b']);
+    let (number, multiplier) = match trimmed.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&trimmed[..i], 1u64 << 10),
+        Some((i, 'm' | 'M')) => (&trimmed[..i], 1 << 20),
+        Some((i, 'g' | 'G')) => (&trimmed[..i], 1 << 30),
+        _ => (trimmed, 1),
+    };
+    number
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|n| *n >= 0.0)
+        .map(|n| (n * multiplier as f64) as u64)
+        .ok_or_else(|| format!("Invalid size: {}", value))
+}
+
+/// Parses a duration such as `90s`, `2m`, `1h` or `500ms`. A bare number is seconds.
+fn parse_duration(value: Option<String>) -> Result<Duration, String> {
+    let value = value.ok_or("Expected a duration")?;
+    let trimmed = value.trim();
+    let (number, unit_seconds) = if let Some(number) = trimmed.strip_suffix("ms") {
+        (number, 0.001)
+    } else if let Some(number) = trimmed.strip_suffix('s') {
+        (number, 1.0)
+    } else if let Some(number) = trimmed.strip_suffix('m') {
+        (number, 60.0)
+    } else if let Some(number) = trimmed.strip_suffix('h') {
+        (number, 3600.0)
+    } else {
+        (trimmed, 1.0)
+    };
+    number
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .and_then(|n| Duration::try_from_secs_f64(n * unit_seconds).ok())
+        .ok_or_else(|| format!("Invalid duration: {}", value))
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = std::env::args().skip(1).peekable();
+    let command = args.next_if(|arg| COMMANDS.contains(&arg.as_str()));
+    let command = command.as_deref().unwrap_or("extract");
+    if command == "help" {
+        return Err(String::new());
+    }
+    let operation = match command {
+        "edit" | "tone" => Some(args.next().ok_or_else(|| format!("{} expects an operation", command))?),
+        _ => None,
+    };
+    let list = command == "list";
+    let cat = command == "cat";
+    let lint = command == "lint";
+    let info = command == "info";
+    let extract = command == "extract";
+    let analyze = command == "analyze-compression";
+    let pack = command == "pack";
+    let replace = command == "replace";
+    let compact_archive = command == "compact";
+    let search = command == "search";
+    let convert = command == "convert";
+    let lyrics = command == "lyrics";
+    let audio = command == "audio";
+    let audio_info = command == "audio-info";
+    let stats = command == "stats";
+    let strip = command == "strip";
+    let pack_merge = command == "pack-merge";
+    let edit = command == "edit";
+    let tone = command == "tone";
+    let audition = command == "audition";
+    let mut json_errors = false;
+    let mut no_color = false;
+    let mut plain_toc = false;
+    let mut dry_run = false;
+    let mut validate = true;
+    let mut refresh = true;
+    let mut paths_only = false;
+    let mut nul = false;
+    let mut touch_now = false;
+    let mut link_duplicates = false;
+    let mut rename_template = DEFAULT_RENAME_TEMPLATE.to_string();
+    let mut only = Vec::new();
+    let mut min_size = None;
+    let mut max_size = None;
+    let mut largest = None;
+    let mut max_duration = None;
+    let mut state = None;
+    let mut cache = None;
+    let mut preview = None;
+    let mut difficulty = None;
+    let mut compress_output = None;
+    let mut layouts = None;
+    let mut index = None;
+    let mut playlist = None;
+    let mut audio_dir = None;
+    let mut format = None;
+    let mut codebooks = None;
+    let mut packet_format = None;
+    let mut inline_codebooks = false;
+    let mut fallback = true;
+    let mut library = None;
+    let mut arrangement = None;
+    let mut slot = ToneSlot::Base;
+    let mut preview_audio = false;
+    let mut encoder = None;
+    let mut compact = false;
+    let mut romanize = false;
+    let mut steam = false;
+    let mut app_id = None;
+    let mut start = Duration::ZERO;
+    let mut duration = Duration::from_secs(10);
+    let mut sound_font = None;
+    let mut names = Vec::new();
+    let mut filters = Vec::new();
+    let mut patterns = Vec::new();
+    let mut output = None;
+    let mut positional = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--filter" if extract || strip => filters.push(args.next().ok_or("--filter expects an entry path prefix")?),
+            "--match" if extract || strip => {
+                let pattern = args.next().ok_or("--match expects a glob pattern")?;
+                patterns.push(pattern.parse::<GlobPattern>().map_err(|err| err.to_string())?);
+            }
+            "--output" | "-o" if extract || analyze => {
+                output = Some(PathBuf::from(args.next().ok_or("--output expects a path")?));
+            }
+            "--link-duplicates" if !list => link_duplicates = true,
+            "--min-size" if !list => min_size = Some(parse_size(args.next())?),
+            "--max-size" if !list => max_size = Some(parse_size(args.next())?),
+            "--state" if !list => state = Some(PathBuf::from(args.next().ok_or("--state expects a file")?)),
+            "--cache" if !list => cache = Some(PathBuf::from(args.next().ok_or("--cache expects a folder")?)),
+            "--user-cache" if extract || audio => {
+                let dir = UserDirs::platform().cache_dir().map(Path::to_path_buf);
+                cache = Some(dir.ok_or_else(|| format!("--user-cache: no per-user cache folder (set {})", CACHE_DIR_ENV))?);
+            }
+            "--difficulty" if !list => {
+                difficulty = Some(args.next().ok_or("--difficulty expects a level, `max` or `all`")?.parse()?);
+            }
+            "--steam" if extract => steam = true,
+            "--compress-output" if extract => {
+                compress_output = Some(args.next().ok_or("--compress-output expects `gzip` or `zstd`")?.parse()?);
+            }
+            "--synthesize-preview" if !list => preview = Some(parse_duration(args.next())?),
+            "--max-duration" if !list => max_duration = Some(parse_duration(args.next())?),
+            "--largest" if list => {
+                let count = args.next().and_then(|n| n.parse().ok());
+                largest = Some(count.ok_or("--largest expects a number of entries")?);
+            }
+            "--only" if !list => {
+                let classes = args.next().ok_or("--only expects an asset class")?;
+                for class in classes.split(',') {
+                    only.push(class.parse::<AssetClass>()?);
+                }
+            }
+            "--rename-template" if !list => {
+                rename_template = args.next().ok_or("--rename-template expects a template")?;
+            }
+            "--touch" if !list => match args.next().as_deref() {
+                Some("now") => touch_now = true,
+                Some("archive") => touch_now = false,
+                other => return Err(format!("--touch expects `archive` or `now`, got {:?}", other.unwrap_or(""))),
+            },
+            "--layouts" => layouts = Some(PathBuf::from(args.next().ok_or("--layouts expects a file")?)),
+            "--names" => names.push(PathBuf::from(args.next().ok_or("--names expects a file")?)),
+            "--json-errors" => json_errors = true,
+            "--no-color" => no_color = true,
+            "--plain-toc" if pack => plain_toc = true,
+            "--appid" if pack || replace => app_id = Some(args.next().ok_or("--appid expects a Steam app id")?),
+            "--dry-run" if pack => dry_run = true,
+            "--no-validate" if pack => validate = false,
+            "--keep-manifests" if pack => refresh = false,
+            "--compact" if convert => compact = true,
+            "--format" if convert => format = Some(args.next().ok_or("--format expects `xml` or `json`")?),
+            "--format" if lyrics => format = Some(args.next().ok_or("--format expects `lrc` or `srt`")?),
+            "--romanize" if lyrics => romanize = true,
+            "--codebooks" if audio => codebooks = Some(PathBuf::from(args.next().ok_or("--codebooks expects a file")?)),
+            "--packet-format" if audio => {
+                packet_format = Some(args.next().ok_or("--packet-format expects `modified` or `standard`")?)
+            }
+            "--inline-codebooks" if audio => inline_codebooks = true,
+            "--no-fallback" if audio => fallback = false,
+            "--playlist" if search => playlist = Some(PathBuf::from(args.next().ok_or("--playlist expects a file")?)),
+            "--audio-dir" if search => audio_dir = Some(PathBuf::from(args.next().ok_or("--audio-dir expects a folder")?)),
+            "--index" if search => index = Some(PathBuf::from(args.next().ok_or("--index expects a file")?)),
+            "--library" if stats => library = Some(PathBuf::from(args.next().ok_or("--library expects a folder")?)),
+            "--arrangement" if edit || tone || audition => arrangement = Some(args.next().ok_or("--arrangement expects a name")?),
+            "--slot" if tone => slot = args.next().ok_or("--slot expects `base`, `a`, `b`, `c` or `d`")?.parse()?,
+            "--preview" if edit => preview_audio = true,
+            "--start" if audition => start = parse_duration(args.next())?,
+            "--duration" if audition => duration = parse_duration(args.next())?,
+            "--soundfont" if audition => {
+                sound_font = Some(PathBuf::from(args.next().ok_or("--soundfont expects a .sf2 file")?))
+            }
+            "--encoder" if edit => encoder = Some(args.next().ok_or("--encoder expects a program")?),
+            "--paths-only" if list => paths_only = true,
+            "-0" if list => nul = true,
+            "-h" | "--help" => return Err(String::new()),
+            flag if flag.starts_with('-') && flag.len() > 1 => return Err(format!("Unknown option: {}", flag)),
+            _ => positional.push(PathBuf::from(arg)),
+        }
+    }
+    if pack {
+        let expected = if dry_run { 1..=2 } else { 2..=2 };
+        let folder = positional.first().cloned().filter(|_| expected.contains(&positional.len())).ok_or("Expected a folder and an archive")?;
+        positional.remove(0);
+        let mode = Mode::Pack { folder, plain_toc, validate, dry_run, refresh, app_id };
+        return Ok(Args { mode, archives: positional, json_errors, no_color, layouts, names });
+    }
+    if replace {
+        let content = match positional.len() {
+            1 if app_id.is_some() => None,
+            3 => {
+                let file = positional.remove(2);
+                Some((positional.remove(1).to_string_lossy().into_owned(), file))
+            }
+            _ => return Err("Expected an archive, an entry path and a file".to_string()),
+        };
+        let mode = Mode::Replace { content, app_id };
+        return Ok(Args { mode, archives: positional, json_errors, no_color, layouts, names });
+    }
+    if compact_archive {
+        if !(1..=2).contains(&positional.len()) {
+            return Err("Expected an archive".to_string());
+        }
+        let output = positional.get(1).cloned();
+        positional.truncate(1);
+        return Ok(Args { mode: Mode::Compact { output }, archives: positional, json_errors, no_color, layouts, names });
+    }
+    if strip {
+        if filters.is_empty() && patterns.is_empty() {
+            return Err("strip expects --filter or --match".to_string());
+        }
+        let output = positional.pop().filter(|_| positional.len() == 1).ok_or("Expected an archive and an output archive")?;
+        let selection = filters.into_iter().fold(ExtractOptions::new(), ExtractOptions::include);
+        let selection = patterns.into_iter().fold(selection, ExtractOptions::matching);
+        return Ok(Args { mode: Mode::Strip { output, selection }, archives: positional, json_errors, no_color, layouts, names });
+    }
+    if pack_merge {
+        if positional.len() < 2 {
+            return Err("Expected an output archive and the archives to merge".to_string());
+        }
+        let output = positional.remove(0);
+        return Ok(Args { mode: Mode::PackMerge { output }, archives: positional, json_errors, no_color, layouts, names });
+    }
+    if edit {
+        let operation = operation.unwrap_or_default();
+        let edit = match operation.as_str() {
+            "remove-arrangement" | "extract-arrangement" => {
+                let name = arrangement.ok_or_else(|| format!("{} expects --arrangement <name>", operation))?;
+                if operation == "remove-arrangement" {
+                    Edit::RemoveArrangement(name)
+                } else {
+                    Edit::ExtractArrangement(name)
+                }
+            }
+            #[cfg(feature = "image")]
+            "set-art" => {
+                if positional.len() < 2 {
+                    return Err("Expected an archive and an image".to_string());
+                }
+                Edit::SetArt(positional.remove(1))
+            }
+            #[cfg(not(feature = "image"))]
+            "set-art" => return Err("set-art needs the `image` feature".to_string()),
+            #[cfg(feature = "audio")]
+            "set-audio" => {
+                if positional.len() < 2 {
+                    return Err("Expected an archive and an audio file".to_string());
+                }
+                let slot = if preview_audio { AudioSlot::Preview } else { AudioSlot::Main };
+                Edit::SetAudio { audio: positional.remove(1), slot, encoder }
+            }
+            #[cfg(not(feature = "audio"))]
+            "set-audio" => return Err("set-audio needs the `audio` feature".to_string()),
+            other => {
+                return Err(format!(
+                    "Unknown edit {:?}, expected remove-arrangement, extract-arrangement, set-art or set-audio",
+                    other
+                ))
+            }
+        };
+        #[cfg(not(feature = "audio"))]
+        let _ = (preview_audio, encoder);
+        if !(1..=2).contains(&positional.len()) {
+            return Err("Expected an archive".to_string());
+        }
+        let output = positional.get(1).cloned();
+        positional.truncate(1);
+        return Ok(Args { mode: Mode::Edit { edit, output }, archives: positional, json_errors, no_color, layouts, names });
+    }
+    if tone {
+        let mode = match operation.as_deref() {
+            Some("export") => {
+                if !(1..=3).contains(&positional.len()) {
+                    return Err("Expected an archive".to_string());
+                }
+                let output = positional.get(2).cloned();
+                let key = positional.get(1).map(|key| key.to_string_lossy().into_owned());
+                positional.truncate(1);
+                Mode::ToneExport { key, output }
+            }
+            Some("import") => {
+                let arrangement = arrangement.ok_or("tone import expects --arrangement <name>")?;
+                if !(2..=3).contains(&positional.len()) {
+                    return Err("Expected an archive and a tone file".to_string());
+                }
+                let output = positional.get(2).cloned();
+                let tone = positional.remove(1);
+                positional.truncate(1);
+                Mode::ToneImport { tone, arrangement, slot, output }
+            }
+            other => return Err(format!("Unknown tone operation {:?}, expected export or import", other.unwrap_or(""))),
+        };
+        return Ok(Args { mode, archives: positional, json_errors, no_color, layouts, names });
+    }
+    if stats {
+        let folder = library.filter(|_| positional.is_empty()).ok_or("Expected --library <folder>")?;
+        #[cfg(feature = "sng")]
+        {
+            return Ok(Args { mode: Mode::Stats { folder }, archives: Vec::new(), json_errors, no_color, layouts, names });
+        }
+        #[cfg(not(feature = "sng"))]
+        {
+            let _ = folder;
+            return Err("stats needs the `sng` feature".to_string());
+        }
+    }
+    if audio_info {
+        if positional.len() != 1 {
+            return Err("Expected an archive".to_string());
+        }
+        #[cfg(feature = "audio")]
+        {
+            return Ok(Args { mode: Mode::AudioInfo, archives: positional, json_errors, no_color, layouts, names });
+        }
+        #[cfg(not(feature = "audio"))]
+        {
+            return Err("audio-info needs the `audio` feature".to_string());
+        }
+    }
+    if audition {
+        let output = positional.pop().filter(|_| positional.len() == 1).ok_or("Expected an archive and an output file")?;
+        #[cfg(feature = "audition")]
+        {
+            let arrangement = arrangement.unwrap_or_else(|| "lead".to_string());
+            let mode = Mode::Audition { output, arrangement, start, duration, sound_font };
+            return Ok(Args { mode, archives: positional, json_errors, no_color, layouts, names });
+        }
+        #[cfg(not(feature = "audition"))]
+        {
+            let _ = (output, start, duration, sound_font);
+            return Err("audition needs the `audition` feature".to_string());
+        }
+    }
+    if search {
+        if positional.len() != 2 {
+            return Err("Expected a folder and a query".to_string());
+        }
+        #[cfg(feature = "sng")]
+        {
+            let query = positional[1].to_string_lossy().parse()?;
+            let mode = Mode::Search { folder: positional.remove(0), query, index, playlist, audio_dir };
+            return Ok(Args { mode, archives: Vec::new(), json_errors, no_color, layouts, names });
+        }
+        #[cfg(not(feature = "sng"))]
+        {
+            let _ = (index, playlist, audio_dir);
+            return Err("search needs the `sng` feature".to_string());
+        }
+    }
+    if convert {
+        let output_dir = positional.pop().filter(|_| positional.len() == 1).ok_or("Expected an archive and an output directory")?;
+        #[cfg(feature = "sng")]
+        {
+            let format = match format.as_deref().unwrap_or("xml").parse()? {
+                ConvertFormat::Json if compact => ConvertFormat::CompactJson,
+                format => format,
+            };
+            let mode = Mode::Convert { output_dir, format };
+            return Ok(Args { mode, archives: positional, json_errors, no_color, layouts, names });
+        }
+        #[cfg(not(feature = "sng"))]
+        {
+            let _ = (output_dir, format, compact);
+            return Err("convert needs the `sng` feature".to_string());
+        }
+    }
+    if lyrics {
+        if !(1..=2).contains(&positional.len()) {
+            return Err("Expected an archive".to_string());
+        }
+        let output = positional.get(1).cloned();
+        positional.truncate(1);
+        if romanize && cfg!(not(feature = "romanize")) {
+            return Err("--romanize needs the `romanize` feature".to_string());
+        }
+        #[cfg(feature = "sng")]
+        {
+            let format = format.as_deref().unwrap_or("lrc").parse()?;
+            let mode = Mode::Lyrics { format, romanize, output };
+            return Ok(Args { mode, archives: positional, json_errors, no_color, layouts, names });
+        }
+        #[cfg(not(feature = "sng"))]
+        {
+            let _ = (output, format);
+            return Err("lyrics needs the `sng` feature".to_string());
+        }
+    }
+    if audio {
+        let output_dir = positional.pop().filter(|_| positional.len() == 1).ok_or("Expected an archive and an output directory")?;
+        #[cfg(feature = "audio")]
+        {
+            let packet_format = packet_format.map(|format| format.parse::<PacketFormat>()).transpose()?;
+            let options = AudioOptions { packet_format, inline_codebooks, fallback };
+            let mode = Mode::Audio { output_dir, codebooks, options, cache };
+            return Ok(Args { mode, archives: positional, json_errors, no_color, layouts, names });
+        }
+        #[cfg(not(feature = "audio"))]
+        {
+            let _ = (output_dir, codebooks, packet_format, inline_codebooks, fallback, cache);
+            return Err("audio needs the `audio` feature".to_string());
+        }
+    }
+    if analyze {
+        if positional.len() != 1 {
+            return Err("Expected an archive".to_string());
+        }
+        let mode = Mode::AnalyzeCompression { output };
+        return Ok(Args { mode, archives: positional, json_errors, no_color, layouts, names });
+    }
+    if lint || info {
+        if positional.len() != 1 {
+            return Err("Expected an archive".to_string());
+        }
+        let mode = if lint { Mode::Lint } else { Mode::Info };
+        return Ok(Args { mode, archives: positional, json_errors, no_color, layouts, names });
+    }
+    if cat {
+        let entry = positional.pop().filter(|_| positional.len() == 1).ok_or("Expected an archive and an entry path")?;
+        let mode = Mode::Cat { entry: entry.to_string_lossy().into_owned() };
+        return Ok(Args { mode, archives: positional, json_errors, no_color, layouts, names });
+    }
+    if list {
+        let separator = match (paths_only, nul) {
+            (_, true) => Some(b'\0'),
+            (true, false) => Some(b'\n'),
+            (false, false) => None,
+        };
+        if positional.len() != 1 {
+            return Err("Expected an archive".to_string());
+        }
+        return Ok(Args { mode: Mode::List { separator, largest }, archives: positional, json_errors, no_color, layouts, names });
+    }
+    let output_dir = match output {
+        Some(output) if !positional.is_empty() || steam => output,
+        Some(_) => return Err("Expected at least one archive".to_string()),
+        None => positional
+            .pop()
+            .filter(|_| !positional.is_empty() || steam)
+            .ok_or("Expected at least one archive and an output directory")?,
+    };
+    if steam {
+        let archives = steam_dlc_archives().map_err(|err| format!("Could not read the Steam DLC folder: {}", err))?;
+        if archives.is_empty() {
+            return Err(format!(
+                "No Rocksmith 2014 DLC folder found in the Steam libraries (set {} to the Steam folder)",
+                STEAM_DIR_ENV
+            ));
+        }
+        positional.extend(archives);
+    }
+    let mode = Mode::Extract { output_dir, filters, patterns, touch_now, link_duplicates, rename_template, only, min_size, max_size, max_duration, state, cache, preview, difficulty, compress_output };
+    Ok(Args { mode, archives: positional, json_errors, no_color, layouts, names })
+}
+
+/// Prints lint issues, one line each; package wide issues have no path.
+fn print_issues(style: Style, issues: &[LintIssue]) {
+    for issue in issues {
+        let label = match issue.severity {
+            LintSeverity::Error => style.red("error:"),
+            LintSeverity::Warning => style.yellow("warning:"),
+        };
+        if issue.path.is_empty() {
+            println!("{} {}", label, issue.message);
+        } else {
+            println!("{} {} {}", label, issue.path, issue.message);
+        }
+    }
+}
+
+/// Prints the entry paths (only the `largest` ones, biggest first, when given), either as
+/// an aligned size/path table or separated by `separator` only. A closed pipe (`| head`) ends the listing without an error.
+fn list_entries(psarc: &PsarcFile, separator: Option<u8>, largest: Option<usize>) -> io::Result<()> {
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    let mut entries: Vec<_> = psarc.toc.entries.iter().filter_map(|e| e.path.as_deref().map(|p| (e, p))).collect();
+    if let Some(count) = largest {
+        entries.sort_by_key(|(entry, _)| std::cmp::Reverse(entry.length));
+        entries.truncate(count);
+    }
+    let paths = entries.iter().copied();
+    let result = match separator {
+        Some(separator) => paths.clone().try_for_each(|(_, path)| {
+            out.write_all(path.as_bytes())?;
+            out.write_all(&[separator])
+        }),
+        None => {
+            let width = paths.clone().map(|(e, _)| format_size(e.length).len()).max().unwrap_or(0);
+            paths.clone().try_for_each(|(entry, path)| {
+                writeln!(out, "{:>width$}  {}", format_size(entry.length), path, width = width)
+            })
+        }
+    };
+    match result.and_then(|_| out.flush()) {
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        other => other,
+    }
+}
+
+/// Writes `writer` into `output`, or over `archive` through a temporary file renamed into
+/// place when `output` is `None`. Returns the path written.
+fn write_repacked(writer: &PsarcWriter, archive: &Path, output: Option<&Path>) -> io::Result<PathBuf> {
+    if let Some(output) = output {
+        writer.write_path(output)?;
+        return Ok(output.to_path_buf());
+    }
+    let mut temp = archive.as_os_str().to_owned();
+    temp.push(".tmp");
+    writer.write_path(&temp)?;
+    fs::rename(&temp, archive)?;
+    Ok(archive.to_path_buf())
+}
+
+/// Applies `edit` to `psarc`, returning the repacked archive, a summary line and the
+/// details for `--json-errors`.
+fn apply_edit(psarc: &PsarcFile, edit: &Edit) -> io::Result<(PsarcWriter, String, serde_json::Value)> {
+    match edit {
+        Edit::RemoveArrangement(name) | Edit::ExtractArrangement(name) => {
+            let (writer, report) = match edit {
+                Edit::RemoveArrangement(_) => remove_arrangement(psarc, name)?,
+                _ => extract_arrangement(psarc, name)?,
+            };
+            let summary = format!(
+                "{} arrangements removed, {} entries dropped, {} rewritten",
+                report.arrangements.len(),
+                report.removed_entries.len(),
+                report.rewritten.len()
+            );
+            Ok((writer, summary, json!({ "arrangements": report })))
+        }
+        #[cfg(feature = "image")]
+        Edit::SetArt(image) => {
+            let art = decode_png(&fs::read(image)?)?;
+            let (writer, replaced) = set_album_art(psarc, &art)?;
+            let summary = format!("{} album art textures replaced", replaced.len());
+            Ok((writer, summary, json!({ "replaced": replaced })))
+        }
+        #[cfg(feature = "audio")]
+        Edit::SetAudio { audio, slot, encoder } => {
+            let data = fs::read(audio)?;
+            let extension = audio.extension().and_then(|e| e.to_str()).unwrap_or("audio");
+            let encode = |input: &[u8]| run_encoder(encoder.as_deref().unwrap_or_default(), extension, input);
+            let encoder: Option<&dyn WemEncoder> = if encoder.is_some() { Some(&encode) } else { None };
+            let (writer, report) = set_audio(psarc, *slot, &data, encoder)?;
+            let summary = format!("{} replaced, {} banks refreshed", report.wem, report.banks.len());
+            Ok((writer, summary, json!({ "audio": report })))
+        }
+    }
+}
+
+/// Converts `audio` to a wem by running `program <input> <output.wem>` on temporary files,
+/// the input named with `extension` so the encoder can tell its format.
+#[cfg(feature = "audio")]
+fn run_encoder(program: &str, extension: &str, audio: &[u8]) -> io::Result<Vec<u8>> {
+    let (input, mut file) = create_temp_file(&format!("_input.{}", extension))?;
+    if let Err(err) = file.write_all(audio) {
+        let _ = fs::remove_file(&input);
+        return Err(err);
+    }
+    drop(file);
+    let output = match create_temp_file("_output.wem") {
+        Ok((output, _)) => output,
+        Err(err) => {
+            let _ = fs::remove_file(&input);
+            return Err(err);
+        }
+    };
+    let status = std::process::Command::new(program).arg(&input).arg(&output).status();
+    let _ = fs::remove_file(&input);
+    let wem = match status? {
+        status if status.success() => fs::read(&output),
+        status => Err(io::Error::other(format!("{} failed ({})", program, status))),
+    };
+    let _ = fs::remove_file(&output);
+    wem
+}
+
+/// Creates a new file in the temporary folder, named `psarc_unpacker_<random><suffix>`.
+/// The file is opened with `create_new`, so a file or link another user planted under the
+/// name is never written through; a taken name is retried with another suffix.
+#[cfg(feature = "audio")]
+fn create_temp_file(suffix: &str) -> io::Result<(PathBuf, fs::File)> {
+    use std::hash::{BuildHasher, Hasher};
+
+    for _ in 0..16 {
+        // Every `RandomState` is seeded with fresh random keys.
+        let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+        hasher.write_u32(std::process::id());
+        hasher.write_u128(SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_nanos());
+        let path = std::env::temp_dir().join(format!("psarc_unpacker_{:016x}{}", hasher.finish(), suffix));
+        match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::AlreadyExists, "No free temporary file name"))
+}
+
+/// Prints `LibraryStats` as aligned label/value lines, then the songs found in several
+/// archives.
+#[cfg(feature = "sng")]
+fn print_stats(style: Style, folder: &Path, stats: &LibraryStats) {
+    let counts = |map: &std::collections::BTreeMap<String, usize>| {
+        let mut counts: Vec<(&String, &usize)> = map.iter().collect();
+        counts.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        counts.iter().map(|(name, count)| format!("{} {}", name, count)).collect::<Vec<_>>().join(", ")
+    };
+    let tempo = match (stats.min_tempo, stats.max_tempo) {
+        (Some(min), Some(max)) => format!("{:.0} to {:.0} BPM", min, max),
+        _ => "unknown".to_string(),
+    };
+    let lines = [
+        ("Library", folder.display().to_string()),
+        ("Archives", stats.archive_count.to_string()),
+        ("Songs", stats.song_count.to_string()),
+        ("Arrangements", format!("{} ({})", stats.arrangement_count, counts(&stats.arrangement_counts))),
+        ("Tunings", counts(&stats.tuning_distribution)),
+        ("Tempo", tempo),
+        ("Play time", format_duration(Duration::try_from_secs_f64(stats.total_play_length).unwrap_or_default())),
+        ("Duplicates", stats.duplicates.len().to_string()),
+    ];
+    for (label, value) in lines {
+        println!("{} {}", style.bold(&format!("{:<13}", format!("{}:", label))), value);
+    }
+    for duplicate in &stats.duplicates {
+        let kind = if duplicate.identical { "identical" } else { "differing" };
+        println!("  {} {} copies ({}), keep {}", duplicate.key, duplicate.candidates.len(), kind, duplicate.preferred.display());
+    }
+}
+
+/// Writes every SNG arrangement of `psarc` into `output_dir` (see `Mode::Convert`), and
+/// returns the files written and the entries that failed.
+#[cfg(feature = "sng")]
+fn convert_arrangements(psarc: &PsarcFile, output_dir: &Path, format: ConvertFormat) -> (Vec<PathBuf>, Vec<(String, io::Error)>) {
+    let mut written = Vec::new();
+    let mut failed = Vec::new();
+    for entry in &psarc.toc.entries {
+        let Some(path) = entry.path.as_deref().filter(|p| p.ends_with(".sng")) else {
+            continue;
+        };
+        let stem = Path::new(path).file_stem().and_then(|s| s.to_str()).unwrap_or("arrangement");
+        let output = match format {
+            ConvertFormat::Xml => output_dir.join(format!("{}.xml", stem)),
+            ConvertFormat::Json | ConvertFormat::CompactJson => output_dir.join(format!("{}.sng.json", stem)),
+        };
+        // `<key>_<arrangement>.sng`: the XML names the arrangement as `Lead`, `Bass`...
+        let arrangement = split_sng_path(path).map(|(_, name)| {
+            let mut chars = name.chars();
+            chars.next().map(|first| first.to_uppercase().chain(chars).collect::<String>()).unwrap_or_default()
+        });
+        let result = psarc.read_sng_entry(entry).map_err(io::Error::from).and_then(|sng| {
+            let file = io::BufWriter::new(fs::File::create(&output)?);
+            match format {
+                ConvertFormat::Xml => sng.write_xml(file, arrangement.as_deref()),
+                ConvertFormat::Json => sng.write_json(file, None),
+                ConvertFormat::CompactJson => sng.write_json_compact(file, None),
+            }
+            .map_err(io::Error::from)
+        });
+        match result {
+            Ok(()) => written.push(output),
+            Err(err) => failed.push((path.to_string(), err)),
+        }
+    }
+    (written, failed)
+}
+
+fn failures_json(archive: &Path, failures: &[(String, io::Error)]) -> Vec<serde_json::Value> {
+    failures
+        .iter()
+        .map(|(path, err)| json!({ "archive": archive, "path": path, "error": err.to_string() }))
+        .collect()
+}
+
+/// Summary fields of a batch for `--json-errors`.
+fn batch_json(batch: &BatchReport) -> serde_json::Value {
+    let reports = || batch.archives.iter();
+    json!({
+        "written": reports().map(|(_, r)| r.written.len()).sum::<usize>(),
+        "failed": reports().flat_map(|(a, r)| failures_json(a, &r.failed)).collect::<Vec<_>>(),
+        "conversion_failed": reports().flat_map(|(a, r)| failures_json(a, &r.conversion_failed)).collect::<Vec<_>>(),
+        "not_started": reports()
+            .flat_map(|(a, r)| r.not_started.iter().map(move |path| json!({ "archive": a, "path": path })))
+            .collect::<Vec<_>>(),
+        "renamed": batch.renamed.iter().map(|r| json!({
+            "archive": r.archive,
+            "entry": r.entry,
+            "intended": r.intended,
+            "written": r.written,
+        })).collect::<Vec<_>>(),
+        "resumed": batch.resumed,
+        "failed_archives": batch.failed_archives.iter().map(|(a, err)| json!({
+            "archive": a,
+            "error": err.to_string(),
+        })).collect::<Vec<_>>(),
+    })
+}
+
+fn finish(outcome: Outcome, json_errors: bool, details: Option<serde_json::Value>, error: Option<&io::Error>) -> ExitCode {
+    if json_errors {
+        let mut summary = json!({ "status": outcome.status(), "exit_code": outcome.code() });
+        if let (Some(summary), Some(serde_json::Value::Object(details))) = (summary.as_object_mut(), details) {
+            summary.extend(details);
+        }
+        if let Some(err) = error {
+            summary["error"] = json!(err.to_string());
+        }
+        println!("{}", summary);
+    }
+    ExitCode::from(outcome.code())
+}
+
+fn main() -> ExitCode {
+    tracing_subscriber::fmt().with_writer(io::stderr).init();
+
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(message) => {
+            if message.is_empty() {
+                println!("{}", USAGE);
+                return ExitCode::SUCCESS;
+            }
+            eprintln!("{}\n{}", message, USAGE);
+            return ExitCode::from(Outcome::Usage.code());
+        }
+    };
+
+    let user_dirs = UserDirs::platform();
+    if let Some(path) = args.layouts.clone().or_else(|| user_dirs.layouts_file()) {
+        match PackageLayouts::new().with_overrides_file(&path) {
+            Ok(layouts) => layouts.install(),
+            Err(err) => {
+                eprintln!("{}", err);
+                return finish(Outcome::Usage, args.json_errors, None, Some(&err));
+            }
+        }
+    }
+
+    let name_files = match &args.names[..] {
+        [] => user_dirs.names_file().into_iter().collect(),
+        files => files.to_vec(),
+    };
+    let names = if name_files.is_empty() {
+        None
+    } else {
+        let mut dictionary = NameDictionary::new();
+        for path in &name_files {
+            if let Err(err) = dictionary.load_file(path) {
+                eprintln!("{}", err);
+                return finish(Outcome::Usage, args.json_errors, None, Some(&err));
+            }
+        }
+        Some(Arc::new(dictionary))
+    };
+    let open_archive = |archive: &Path| match &names {
+        Some(names) => open_path_with_names(archive, names).map(|(psarc, _)| psarc),
+        None => PsarcFile::open_path(archive).map_err(io::Error::from),
+    };
+
+    let style = Style::new(args.no_color);
+    let started = Instant::now();
+    let (output_dir, rename_template, options, state) = match args.mode {
+        Mode::Extract {
+            output_dir,
+            filters,
+            patterns,
+            touch_now,
+            link_duplicates,
+            rename_template,
+            only,
+            min_size,
+            max_size,
+            max_duration,
+            state,
+            cache,
+            preview,
+            difficulty,
+            compress_output,
+        } => {
+            // Unset, the batch gives every archive's outputs that archive's modification time.
+            let options = filters.into_iter().fold(ExtractOptions::new(), ExtractOptions::include);
+            let options = patterns.into_iter().fold(options, ExtractOptions::matching);
+            let options = only.into_iter().fold(
+                options
+                    .mtime(touch_now.then(SystemTime::now))
+                    .link_duplicates(link_duplicates)
+                    .min_size(min_size)
+                    .max_size(max_size)
+                    .deadline(max_duration.map(|d| started + d))
+                    .cache(cache.map(ConversionCache::new))
+                    .synthesize_preview(preview.map(|d| d.as_secs_f64()))
+                    .difficulty(difficulty)
+                    .compress_output(compress_output)
+                    .names(names.clone()),
+                ExtractOptions::only,
+            );
+            (output_dir, rename_template, options, state)
+        }
+        Mode::List { separator, largest } => {
+            let archive = &args.archives[0];
+            let psarc = match open_archive(archive) {
+                Ok(psarc) => psarc,
+                Err(err) => {
+                    eprintln!("{} cannot read {}: {}", style.red("error:"), archive.display(), err);
+                    return finish(Outcome::of_open_error(&err), args.json_errors, None, Some(&err));
+                }
+            };
+            return match list_entries(&psarc, separator, largest) {
+                Ok(()) => finish(Outcome::Success, args.json_errors, None, None),
+                Err(err) => finish(Outcome::Io, args.json_errors, None, Some(&err)),
+            };
+        }
+        Mode::Lint => {
+            let archive = &args.archives[0];
+            let report = match open_archive(archive).and_then(|psarc| lint_archive(&psarc)) {
+                Ok(report) => report,
+                Err(err) => {
+                    eprintln!("{} cannot lint {}: {}", style.red("error:"), archive.display(), err);
+                    return finish(Outcome::of_open_error(&err), args.json_errors, None, Some(&err));
+                }
+            };
+            print_issues(style, &report.issues);
+            let outcome = if report.has_errors() { Outcome::LintErrors } else { Outcome::Success };
+            if report.issues.is_empty() {
+                println!("{} {}", style.bold(&style.green("Clean:")), archive.display());
+            }
+            return finish(outcome, args.json_errors, Some(json!({ "issues": report.issues })), None);
+        }
+        Mode::Info => {
+            let archive = &args.archives[0];
+            let psarc = match open_archive(archive) {
+                Ok(psarc) => psarc,
+                Err(err) => {
+                    eprintln!("{} cannot read {}: {}", style.red("error:"), archive.display(), err);
+                    return finish(Outcome::of_open_error(&err), args.json_errors, None, Some(&err));
+                }
+            };
+            let info = archive_info(&psarc);
+            print_info(style, archive, &info);
+            return finish(Outcome::Success, args.json_errors, Some(json!({ "info": info })), None);
+        }
+        Mode::Pack { folder, plain_toc, validate, dry_run, refresh, app_id } => {
+            let mut writer = PsarcWriter::new().encrypt_toc(!plain_toc);
+            let count = match writer.add_dir(&folder) {
+                Ok(count) => count,
+                Err(err) => {
+                    eprintln!("{} cannot pack {}: {}", style.red("error:"), folder.display(), err);
+                    return finish(Outcome::Io, args.json_errors, None, Some(&err));
+                }
+            };
+            if let Some(Err(err)) = app_id.map(|app_id| set_app_id_in(&mut writer, &app_id)) {
+                eprintln!("{} {}", style.red("error:"), err);
+                return finish(Outcome::Usage, args.json_errors, None, Some(&err));
+            }
+            #[cfg(feature = "sng")]
+            if refresh {
+                match refresh_manifests_in(&mut writer) {
+                    Ok(report) => {
+                        for change in &report.changes {
+                            println!("Updated {} {}: {} -> {}", change.manifest, change.attribute, change.old, change.new);
+                        }
+                        for (path, err) in &report.unreadable {
+                            eprintln!("{} {} not read, its manifest is kept: {}", style.yellow("warning:"), path, err);
+                        }
+                    }
+                    Err(err) => {
+                        eprintln!("{} cannot refresh the manifests of {}: {}", style.red("error:"), folder.display(), err);
+                        return finish(Outcome::Io, args.json_errors, None, Some(&err));
+                    }
+                }
+            }
+            #[cfg(not(feature = "sng"))]
+            let _ = refresh;
+            if validate || dry_run {
+                let report = writer.validate();
+                print_issues(style, &report.issues);
+                let details = json!({ "entries": count, "issues": report.issues });
+                if dry_run {
+                    if !report.has_errors() {
+                        println!("{} {} entries from {}", style.bold(&style.green("Valid:")), count, folder.display());
+                    }
+                    let outcome = if report.has_errors() { Outcome::LintErrors } else { Outcome::Success };
+                    return finish(outcome, args.json_errors, Some(details), None);
+                }
+                if report.has_errors() {
+                    eprintln!(
+                        "{} not writing the archive: fix the errors above, or pack with --no-validate",
+                        style.red("error:")
+                    );
+                    return finish(Outcome::LintErrors, args.json_errors, Some(details), None);
+                }
+            }
+            let archive = &args.archives[0];
+            return match writer.write_path(archive).map(|()| count) {
+                Ok(count) => {
+                    let size = fs::metadata(archive).map_or(0, |m| m.len());
+                    println!(
+                        "Packed {} entries from {} into {} ({})",
+                        count,
+                        folder.display(),
+                        archive.display(),
+                        format_size(size)
+                    );
+                    finish(Outcome::Success, args.json_errors, Some(json!({ "entries": count, "size": size })), None)
+                }
+                Err(err) => {
+                    eprintln!("{} cannot pack {}: {}", style.red("error:"), folder.display(), err);
+                    finish(Outcome::Io, args.json_errors, None, Some(&err))
+                }
+            };
+        }
+        Mode::Replace { content, app_id } => {
+            let archive = &args.archives[0];
+            let mut details = json!({});
+            if let Some((entry, file)) = content {
+                let data = match fs::read(&file) {
+                    Ok(data) => data,
+                    Err(err) => {
+                        eprintln!("{} cannot read {}: {}", style.red("error:"), file.display(), err);
+                        return finish(Outcome::Io, args.json_errors, None, Some(&err));
+                    }
+                };
+                match replace_entry_in_place(archive, &entry, &data) {
+                    Ok(report) => {
+                        println!(
+                            "{} {} in {} ({} appended, {} entries moved, {} of dead space; run compact to reclaim it)",
+                            style.bold(&style.green("Replaced")),
+                            entry,
+                            archive.display(),
+                            format_size(report.appended),
+                            report.relocated,
+                            format_size(report.dead_bytes)
+                        );
+                        details["replace"] = json!(report);
+                    }
+                    Err(err) => {
+                        eprintln!("{} cannot replace {} in {}: {}", style.red("error:"), entry, archive.display(), err);
+                        let missing_entry = err.kind() == io::ErrorKind::NotFound && archive.is_file();
+                        let outcome = if missing_entry { Outcome::Usage } else { Outcome::of_open_error(&err) };
+                        return finish(outcome, args.json_errors, None, Some(&err));
+                    }
+                }
+            }
+            if let Some(app_id) = app_id {
+                // Archives without the entry are rewritten with one.
+                let result = match set_app_id_in_place(archive, &app_id) {
+                    Err(err) if err.kind() == io::ErrorKind::NotFound && archive.is_file() => open_archive(archive)
+                        .and_then(|psarc| set_app_id(&psarc, &app_id))
+                        .and_then(|writer| write_repacked(&writer, archive, None).map(drop)),
+                    result => result.map(drop),
+                };
+                if let Err(err) = result {
+                    eprintln!("{} cannot set the app id of {}: {}", style.red("error:"), archive.display(), err);
+                    let outcome = if err.kind() == io::ErrorKind::InvalidInput { Outcome::Usage } else { Outcome::of_open_error(&err) };
+                    return finish(outcome, args.json_errors, None, Some(&err));
+                }
+                println!("{} the app id of {} to {}", style.bold(&style.green("Set")), archive.display(), app_id);
+                details["app_id"] = json!(app_id);
+            }
+            return finish(Outcome::Success, args.json_errors, Some(details), None);
+        }
+        Mode::Compact { output } => {
+            let archive = &args.archives[0];
+            return match compact_path(archive, output.as_deref()) {
+                Ok(reclaimed) => {
+                    let written = output.as_deref().unwrap_or(archive);
+                    println!("{} {} ({} reclaimed)", style.bold(&style.green("Compacted")), written.display(), format_size(reclaimed));
+                    finish(Outcome::Success, args.json_errors, Some(json!({ "reclaimed": reclaimed })), None)
+                }
+                Err(err) => {
+                    eprintln!("{} cannot compact {}: {}", style.red("error:"), archive.display(), err);
+                    finish(Outcome::of_open_error(&err), args.json_errors, None, Some(&err))
+                }
+            };
+        }
+        Mode::Strip { output, selection } => {
+            let archive = &args.archives[0];
+            let result = open_archive(archive).and_then(|psarc| {
+                let (writer, report) = strip_archive(&psarc, |path| selection.selects(path))?;
+                writer.write_path(&output)?;
+                Ok(report)
+            });
+            return match result {
+                Ok(report) => {
+                    for path in &report.removed {
+                        println!("  removed {}", path);
+                    }
+                    println!(
+                        "{} {} into {} ({} entries kept, {} removed, {} saved)",
+                        style.bold(&style.green("Stripped")),
+                        archive.display(),
+                        output.display(),
+                        report.kept,
+                        report.removed.len(),
+                        format_size(report.bytes_saved)
+                    );
+                    finish(Outcome::Success, args.json_errors, Some(json!({ "strip": report })), None)
+                }
+                Err(err) => {
+                    eprintln!("{} cannot strip {}: {}", style.red("error:"), archive.display(), err);
+                    finish(Outcome::of_open_error(&err), args.json_errors, None, Some(&err))
+                }
+            };
+        }
+        Mode::PackMerge { output } => {
+            let mut archives = Vec::new();
+            for archive in &args.archives {
+                match open_archive(archive) {
+                    Ok(psarc) => archives.push(psarc),
+                    Err(err) => {
+                        eprintln!("{} cannot read {}: {}", style.red("error:"), archive.display(), err);
+                        return finish(Outcome::of_open_error(&err), args.json_errors, None, Some(&err));
+                    }
+                }
+            }
+            return match merge_archives(&archives).and_then(|(writer, report)| writer.write_path(&output).map(|()| report)) {
+                Ok(report) => {
+                    println!(
+                        "{} {} archives into {} ({} entries, {} shared)",
+                        style.bold(&style.green("Merged")),
+                        archives.len(),
+                        output.display(),
+                        report.entries,
+                        report.deduplicated.len()
+                    );
+                    finish(Outcome::Success, args.json_errors, Some(json!({ "merge": report })), None)
+                }
+                Err(err) => {
+                    eprintln!("{} cannot merge into {}: {}", style.red("error:"), output.display(), err);
+                    let outcome = if err.kind() == io::ErrorKind::AlreadyExists { Outcome::BadArchive } else { Outcome::Io };
+                    finish(outcome, args.json_errors, None, Some(&err))
+                }
+            };
+        }
+        Mode::Edit { edit, output } => {
+            let archive = &args.archives[0];
+            let psarc = match open_archive(archive) {
+                Ok(psarc) => psarc,
+                Err(err) => {
+                    eprintln!("{} cannot read {}: {}", style.red("error:"), archive.display(), err);
+                    return finish(Outcome::of_open_error(&err), args.json_errors, None, Some(&err));
+                }
+            };
+            let result = apply_edit(&psarc, &edit).and_then(|(writer, summary, details)| {
+                Ok((write_repacked(&writer, archive, output.as_deref())?, summary, details))
+            });
+            return match result {
+                Ok((written, summary, details)) => {
+                    println!("{} {}: {}", style.bold(&style.green("Edited")), written.display(), summary);
+                    finish(Outcome::Success, args.json_errors, Some(details), None)
+                }
+                Err(err) => {
+                    eprintln!("{} cannot edit {}: {}", style.red("error:"), archive.display(), err);
+                    let outcome = match err.kind() {
+                        io::ErrorKind::NotFound | io::ErrorKind::InvalidInput => Outcome::Usage,
+                        _ => Outcome::of_open_error(&err),
+                    };
+                    finish(outcome, args.json_errors, None, Some(&err))
+                }
+            };
+        }
+        Mode::ToneExport { key, output } => {
+            let archive = &args.archives[0];
+            let psarc = match open_archive(archive) {
+                Ok(psarc) => psarc,
+                Err(err) => {
+                    eprintln!("{} cannot read {}: {}", style.red("error:"), archive.display(), err);
+                    return finish(Outcome::of_open_error(&err), args.json_errors, None, Some(&err));
+                }
+            };
+            let Some(key) = key else {
+                return match tone_keys(&psarc) {
+                    Ok(keys) => {
+                        keys.iter().for_each(|key| println!("{}", key));
+                        finish(Outcome::Success, args.json_errors, Some(json!({ "tones": keys })), None)
+                    }
+                    Err(err) => {
+                        eprintln!("{} cannot read the tones of {}: {}", style.red("error:"), archive.display(), err);
+                        finish(Outcome::of_open_error(&err), args.json_errors, None, Some(&err))
+                    }
+                };
+            };
+            let result = export_tone(&psarc, &key).and_then(|tone| {
+                let json = serde_json::to_string_pretty(&tone).map_err(io::Error::other)?;
+                match &output {
+                    Some(output) => fs::write(output, json),
+                    None => writeln!(io::stdout().lock(), "{}", json),
+                }
+            });
+            return match result {
+                Ok(()) => {
+                    if let Some(output) = &output {
+                        println!("{} {} into {}", style.bold(&style.green("Exported")), key, output.display());
+                    }
+                    finish(Outcome::Success, args.json_errors, None, None)
+                }
+                Err(err) => {
+                    eprintln!("{} cannot export {} from {}: {}", style.red("error:"), key, archive.display(), err);
+                    let outcome = if err.kind() == io::ErrorKind::NotFound { Outcome::Usage } else { Outcome::of_open_error(&err) };
+                    finish(outcome, args.json_errors, None, Some(&err))
+                }
+            };
+        }
+        Mode::ToneImport { tone, arrangement, slot, output } => {
+            let archive = &args.archives[0];
+            let tone = match fs::read(&tone).and_then(|data| serde_json::from_slice::<serde_json::Value>(&data).map_err(io::Error::from)) {
+                Ok(tone) => tone,
+                Err(err) => {
+                    eprintln!("{} cannot read {}: {}", style.red("error:"), tone.display(), err);
+                    return finish(Outcome::Io, args.json_errors, None, Some(&err));
+                }
+            };
+            let psarc = match open_archive(archive) {
+                Ok(psarc) => psarc,
+                Err(err) => {
+                    eprintln!("{} cannot read {}: {}", style.red("error:"), archive.display(), err);
+                    return finish(Outcome::of_open_error(&err), args.json_errors, None, Some(&err));
+                }
+            };
+            let result = import_tone(&psarc, &arrangement, &tone, slot).and_then(|(writer, rewritten)| {
+                Ok((write_repacked(&writer, archive, output.as_deref())?, rewritten))
+            });
+            return match result {
+                Ok((written, rewritten)) => {
+                    println!("{} {}: {} manifests updated", style.bold(&style.green("Imported")), written.display(), rewritten.len());
+                    finish(Outcome::Success, args.json_errors, Some(json!({ "rewritten": rewritten })), None)
+                }
+                Err(err) => {
+                    eprintln!("{} cannot import the tone into {}: {}", style.red("error:"), archive.display(), err);
+                    let outcome = match err.kind() {
+                        io::ErrorKind::NotFound | io::ErrorKind::InvalidInput => Outcome::Usage,
+                        _ => Outcome::of_open_error(&err),
+                    };
+                    finish(outcome, args.json_errors, None, Some(&err))
+                }
+            };
+        }
+        #[cfg(feature = "sng")]
+        Mode::Stats { folder } => {
+            return match LibraryStats::collect(&folder) {
+                Ok(stats) => {
+                    print_stats(style, &folder, &stats);
+                    finish(Outcome::Success, args.json_errors, Some(json!({ "stats": stats })), None)
+                }
+                Err(err) => {
+                    eprintln!("{} cannot scan {}: {}", style.red("error:"), folder.display(), err);
+                    finish(Outcome::Io, args.json_errors, None, Some(&err))
+                }
+            };
+        }
+        #[cfg(feature = "audio")]
+        Mode::AudioInfo => {
+            let archive = &args.archives[0];
+            let result = open_archive(archive).and_then(|psarc| psarc.audio_info().map_err(io::Error::from));
+            return match result {
+                Ok(wems) => {
+                    let width = wems.iter().map(|wem| wem.path.len()).max().unwrap_or(0);
+                    for wem in &wems {
+                        let info = &wem.info;
+                        println!(
+                            "{:<width$}  {}, {} ch, {} Hz, {}",
+                            wem.path,
+                            info.codec_name,
+                            info.channels,
+                            info.sample_rate,
+                            format_duration(Duration::try_from_secs_f64(info.duration).unwrap_or_default()),
+                            width = width
+                        );
+                    }
+                    finish(Outcome::Success, args.json_errors, Some(json!({ "audio": wems })), None)
+                }
+                Err(err) => {
+                    eprintln!("{} cannot read the audio of {}: {}", style.red("error:"), archive.display(), err);
+                    finish(Outcome::of_open_error(&err), args.json_errors, None, Some(&err))
+                }
+            };
+        }
+        #[cfg(feature = "sng")]
+        Mode::Lyrics { format, romanize, output } => {
+            let archive = &args.archives[0];
+            let psarc = match open_archive(archive) {
+                Ok(psarc) => psarc,
+                Err(err) => {
+                    eprintln!("{} cannot read {}: {}", style.red("error:"), archive.display(), err);
+                    return finish(Outcome::of_open_error(&err), args.json_errors, None, Some(&err));
+                }
+            };
+            let options = LyricExportOptions::new();
+            #[cfg(feature = "romanize")]
+            let options = options.romanize(romanize);
+            let songs = Song::list(&psarc);
+            let vocals = songs.iter().find_map(|song| song.arrangements().iter().find(|a| a.is_vocals()));
+            let result = match vocals {
+                Some(vocals) => vocals.sng().map(|sng| match format {
+                    LyricFormat::Lrc => to_lrc(&sng.vocals, &options),
+                    LyricFormat::Srt => to_srt(&sng.vocals, &options),
+                }),
+                None => Err(io::Error::new(io::ErrorKind::NotFound, format!("{} has no vocals", archive.display()))),
+            };
+            let text = match result {
+                Ok(text) => text,
+                Err(err) => {
+                    eprintln!("{} cannot read the lyrics of {}: {}", style.red("error:"), archive.display(), err);
+                    let outcome = if err.kind() == io::ErrorKind::NotFound { Outcome::BadArchive } else { Outcome::Io };
+                    return finish(outcome, args.json_errors, None, Some(&err));
+                }
+            };
+            let kanji: Vec<char> = Vec::new();
+            #[cfg(feature = "romanize")]
+            let kanji = if romanize { unconverted_kanji(&text) } else { kanji };
+            #[cfg(not(feature = "romanize"))]
+            let _ = romanize;
+            if !kanji.is_empty() {
+                let listed: String = kanji.iter().collect();
+                eprintln!("{} {} kanji without a furigana reading kept as they are: {}", style.yellow("warning:"), kanji.len(), listed);
+            }
+            let written = match &output {
+                Some(path) => fs::write(path, &text),
+                None => io::stdout().write_all(text.as_bytes()),
+            };
+            if let Err(err) = written {
+                return finish(Outcome::Io, args.json_errors, None, Some(&err));
+            }
+            return finish(Outcome::Success, args.json_errors, Some(json!({ "output": output, "unconverted_kanji": kanji })), None);
+        }
+        #[cfg(feature = "audition")]
+        Mode::Audition { output, arrangement, start, duration, sound_font } => {
+            let sound_font = match sound_font.as_deref().map(load_sound_font).transpose() {
+                Ok(sound_font) => sound_font,
+                Err(err) => {
+                    eprintln!("{} cannot read the SoundFont: {}", style.red("error:"), err);
+                    return finish(Outcome::Io, args.json_errors, None, Some(&err));
+                }
+            };
+            let archive = &args.archives[0];
+            let psarc = match open_archive(archive) {
+                Ok(psarc) => psarc,
+                Err(err) => {
+                    eprintln!("{} cannot read {}: {}", style.red("error:"), archive.display(), err);
+                    return finish(Outcome::of_open_error(&err), args.json_errors, None, Some(&err));
+                }
+            };
+            let result = Song::list(&psarc)
+                .first()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} holds no song", archive.display())))
+                .and_then(|song| {
+                    let (start, duration) = (start.as_secs_f64(), duration.as_secs_f64());
+                    export_audition(song, &arrangement, start, duration, sound_font.as_ref(), &output)
+                });
+            return match result {
+                Ok(()) => {
+                    println!("{} {}", style.green("written"), output.display());
+                    finish(Outcome::Success, args.json_errors, Some(json!({ "output": output })), None)
+                }
+                Err(err) => {
+                    eprintln!("{} cannot render the audition: {}", style.red("error:"), err);
+                    let outcome = if err.kind() == io::ErrorKind::NotFound { Outcome::BadArchive } else { Outcome::Io };
+                    finish(outcome, args.json_errors, None, Some(&err))
+                }
+            };
+        }
+        #[cfg(feature = "sng")]
+        Mode::Search { folder, query, index, playlist, audio_dir } => {
+            let service = LibraryService::new(&folder);
+            let mut service = match index.or_else(|| UserDirs::platform().library_index(&folder)) {
+                Some(index) => service.with_index_file(index),
+                None => service,
+            };
+            if let Err(err) = service.refresh() {
+                eprintln!("{} cannot index {}: {}", style.red("error:"), folder.display(), err);
+                return finish(Outcome::Io, args.json_errors, None, Some(&err));
+            }
+            let hits = service.search(&query);
+            if let Some(path) = &playlist {
+                let name = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+                let audio_dir = audio_dir.unwrap_or_else(|| PathBuf::from("."));
+                let list = Playlist::from_hits(name, &hits).resolve_audio(&audio_dir);
+                if let Err(err) = list.export(path, PlaylistFormat::from_path(path)) {
+                    eprintln!("{} cannot write {}: {}", style.red("error:"), path.display(), err);
+                    return finish(Outcome::Io, args.json_errors, None, Some(&err));
+                }
+            }
+            let mut out = io::BufWriter::new(io::stdout().lock());
+            let printed = hits.iter().try_for_each(|hit| {
+                if hit.entries.is_empty() {
+                    return writeln!(out, "{}", hit.archive.display());
+                }
+                hit.entries.iter().try_for_each(|entry| writeln!(out, "{}\t{}", hit.archive.display(), entry))
+            });
+            return match printed.and_then(|_| out.flush()) {
+                Err(err) if err.kind() != io::ErrorKind::BrokenPipe => finish(Outcome::Io, args.json_errors, None, Some(&err)),
+                _ => finish(Outcome::Success, args.json_errors, Some(json!({ "hits": hits })), None),
+            };
+        }
+        #[cfg(feature = "sng")]
+        Mode::Convert { output_dir, format } => {
+            let archive = &args.archives[0];
+            let psarc = match open_archive(archive) {
+                Ok(psarc) => psarc,
+                Err(err) => {
+                    eprintln!("{} cannot read {}: {}", style.red("error:"), archive.display(), err);
+                    return finish(Outcome::of_open_error(&err), args.json_errors, None, Some(&err));
+                }
+            };
+            if let Err(err) = fs::create_dir_all(&output_dir) {
+                return finish(Outcome::Io, args.json_errors, None, Some(&err));
+            }
+            let (written, failed) = convert_arrangements(&psarc, &output_dir, format);
+            for (path, err) in &failed {
+                println!("  {} {}  {}", style.red("not converted"), path, err);
+            }
+            println!("{} {} arrangements from {}", style.bold(&style.green("Converted")), written.len(), archive.display());
+            let outcome = if failed.is_empty() { Outcome::Success } else { Outcome::ConversionFailures };
+            let details = json!({ "written": written, "conversion_failed": failures_json(archive, &failed) });
+            return finish(outcome, args.json_errors, Some(details), None);
+        }
+        #[cfg(feature = "audio")]
+        Mode::Audio { output_dir, codebooks, options, cache } => {
+            let archive = &args.archives[0];
+            let psarc = match open_archive(archive) {
+                Ok(psarc) => psarc,
+                Err(err) => {
+                    eprintln!("{} cannot read {}: {}", style.red("error:"), archive.display(), err);
+                    return finish(Outcome::of_open_error(&err), args.json_errors, None, Some(&err));
+                }
+            };
+            let converter = match codebooks {
+                Some(path) => AudioConverter::new().codebooks_path(path),
+                None => AudioConverter::new(),
+            }
+            .options(options)
+            .cache(cache.map(ConversionCache::new));
+            return match export_song_audio(&psarc, &output_dir, &converter) {
+                Ok(exported) => {
+                    for audio in &exported {
+                        println!("  {} -> {}", audio.entry, audio.path.display());
+                    }
+                    println!("{} {} audio files from {}", style.bold(&style.green("Converted")), exported.len(), archive.display());
+                    finish(Outcome::Success, args.json_errors, Some(json!({ "audio": exported })), None)
+                }
+                Err(err) => {
+                    eprintln!("{} cannot convert the audio of {}: {}", style.red("error:"), archive.display(), err);
+                    finish(Outcome::Io, args.json_errors, None, Some(&err))
+                }
+            };
+        }
+        Mode::AnalyzeCompression { output } => {
+            let archive = &args.archives[0];
+            let psarc = match open_archive(archive) {
+                Ok(psarc) => psarc,
+                Err(err) => {
+                    eprintln!("{} cannot read {}: {}", style.red("error:"), archive.display(), err);
+                    return finish(Outcome::of_open_error(&err), args.json_errors, None, Some(&err));
+                }
+            };
+            let result = match &output {
+                Some(output) => optimize_compression(&psarc).and_then(|(writer, report)| {
+                    writer.write_path(output)?;
+                    Ok(report)
+                }),
+                None => analyze_compression(&psarc),
+            };
+            let report = match result {
+                Ok(report) => report,
+                Err(err) => {
+                    eprintln!("{} cannot analyze {}: {}", style.red("error:"), archive.display(), err);
+                    return finish(Outcome::of_open_error(&err), args.json_errors, None, Some(&err));
+                }
+            };
+            print_compression(style, &report);
+            if let Some(output) = &output {
+                println!("{} {}", style.bold(&style.green("Wrote")), output.display());
+            }
+            let details = json!({
+                "stored": report.stored(),
+                "saving": report.saving(),
+                "entries": report.improvable(),
+            });
+            return finish(Outcome::Success, args.json_errors, Some(details), None);
+        }
+        Mode::Cat { entry } => {
+            let archive = &args.archives[0];
+            let psarc = match open_archive(archive) {
+                Ok(psarc) => psarc,
+                Err(err) => {
+                    eprintln!("{} cannot read {}: {}", style.red("error:"), archive.display(), err);
+                    return finish(Outcome::of_open_error(&err), args.json_errors, None, Some(&err));
+                }
+            };
+            let Some(found) = psarc.entry_by_path(&entry) else {
+                let err = io::Error::new(io::ErrorKind::NotFound, format!("No entry {} in {}", entry, archive.display()));
+                eprintln!("{} {}", style.red("error:"), err);
+                return finish(Outcome::Usage, args.json_errors, None, Some(&err));
+            };
+            let written = psarc.inflate_entry_data(found).map_err(io::Error::from).and_then(|data| {
+                let mut out = io::stdout().lock();
+                out.write_all(&data).and_then(|_| out.flush())
+            });
+            return match written {
+                Ok(()) => finish(Outcome::Success, args.json_errors, None, None),
+                Err(err) if err.kind() == io::ErrorKind::BrokenPipe => finish(Outcome::Success, args.json_errors, None, None),
+                Err(err) => finish(Outcome::Io, args.json_errors, None, Some(&err)),
+            };
+        }
+    };
+
+    let batch = match state {
+        Some(state) => match extract_batch_resumable(&args.archives, &output_dir, &options, &rename_template, &state) {
+            Ok(batch) => batch,
+            Err(err) => {
+                eprintln!("{} cannot read job state {}: {}", style.red("error:"), state.display(), err);
+                return finish(Outcome::Io, args.json_errors, None, Some(&err));
+            }
+        },
+        None => extract_batch(&args.archives, &output_dir, &options, &rename_template),
+    };
+
+    let single = args.archives.len() == 1;
+    let mut outcome = Outcome::Success;
+    for (archive, report) in &batch.archives {
+        print_report(style, report, archive, single.then(|| started.elapsed()));
+        outcome = outcome.worst(Outcome::of_report(report));
+    }
+    for renamed in &batch.renamed {
+        println!(
+            "  {} {} -> {}",
+            style.yellow(&format!("{:<13}", "renamed")),
+            renamed.intended.display(),
+            renamed.written.display()
+        );
+    }
+    for archive in &batch.resumed {
+        println!("{} {} (done by an earlier run)", style.bold(&style.green("Skipped")), archive.display());
+    }
+    for (archive, err) in &batch.failed_archives {
+        eprintln!("{} cannot extract {}: {}", style.red("error:"), archive.display(), err);
+        outcome = outcome.worst(Outcome::of_open_error(err));
+    }
+    if !single {
+        println!(
+            "{} {} of {} archives in {}",
+            style.bold("Done:"),
+            batch.archives.len() + batch.resumed.len(),
+            args.archives.len(),
+            format_duration(started.elapsed())
+        );
+    }
+    let error = batch.failed_archives.first().map(|(_, err)| err);
+    finish(outcome, args.json_errors, Some(batch_json(&batch)), error)
+}
@@ -0,0 +1,260 @@
+//! Typed model of the Rocksmith manifests: the per-arrangement JSON documents
+//! (`manifests/songs_dlc_<key>/<key>_<arrangement>.json`) and the song pack HSAN
+//! (`manifests/songs_dlc_<key>/songs_dlc_<key>.hsan`), which share one layout:
+//!
+//! ```json
+//! {"Entries": {"<id>": {"Attributes": {"SongName": "...", "ArtistName": "...", ...}}},
+//!  "ModelName": "RSEnumerable_Song", "IterationVersion": 2, "InsertRoot": "Static.Songs.Entries"}
+//! ```
+//!
+//! The common attributes are typed; every other attribute is kept in
+//! `ManifestAttributes::other`, so a document survives a read/write round trip.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::error::Result;
+use crate::psarc::{PsarcFile, PsarcTOCEntry};
+
+/// A manifest JSON or HSAN document.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ManifestDocument {
+    /// Entries by id: one in an arrangement manifest, one per arrangement in an HSAN.
+    #[serde(default)]
+    pub entries: BTreeMap<String, ManifestEntry>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iteration_version: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub insert_root: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ManifestEntry {
+    #[serde(default)]
+    pub attributes: ManifestAttributes,
+}
+
+/// The `Attributes` object of a manifest entry. HSAN entries only carry the song level
+/// fields, so everything is optional.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ManifestAttributes {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub song_key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub song_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub song_name_sort: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artist_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artist_name_sort: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub album_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub album_name_sort: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub song_year: Option<i64>,
+    /// `Lead`, `Rhythm`, `Combo`, `Bass`, `Vocals`, ...
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arrangement_name: Option<String>,
+    /// `<key>_<arrangement>`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub full_name: Option<String>,
+    #[serde(rename = "PersistentID", default, skip_serializing_if = "Option::is_none")]
+    pub persistent_id: Option<String>,
+    /// Song length in seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub song_length: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub song_average_tempo: Option<f64>,
+    /// Seconds between the start of the audio and the start of the chart.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub song_offset: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tuning: Option<Tuning>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cent_offset: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capo_fret: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_phrase_difficulty: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arrangement_properties: Option<ArrangementProperties>,
+    #[serde(rename = "Tone_Base", default, skip_serializing_if = "Option::is_none")]
+    pub tone_base: Option<String>,
+    /// Tone definitions, kept as JSON (see the `tones` module).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tones: Option<Vec<Value>>,
+    #[serde(rename = "DLC", default, skip_serializing_if = "Option::is_none")]
+    pub dlc: Option<bool>,
+    /// Every attribute not listed above.
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+/// Offset in semitones of each string from standard tuning, low string first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tuning {
+    #[serde(default)]
+    pub string0: i32,
+    #[serde(default)]
+    pub string1: i32,
+    #[serde(default)]
+    pub string2: i32,
+    #[serde(default)]
+    pub string3: i32,
+    #[serde(default)]
+    pub string4: i32,
+    #[serde(default)]
+    pub string5: i32,
+}
+
+impl Tuning {
+    pub fn offsets(&self) -> [i32; 6] {
+        [self.string0, self.string1, self.string2, self.string3, self.string4, self.string5]
+    }
+}
+
+/// Techniques and path flags of an arrangement, 1 when set.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ArrangementProperties {
+    #[serde(default)]
+    pub represent: i32,
+    #[serde(default)]
+    pub bonus_arr: i32,
+    #[serde(default)]
+    pub standard_tuning: i32,
+    #[serde(default)]
+    pub non_standard_chords: i32,
+    #[serde(default)]
+    pub barre_chords: i32,
+    #[serde(default)]
+    pub power_chords: i32,
+    #[serde(default, rename = "dropDPower")]
+    pub drop_d_power: i32,
+    #[serde(default)]
+    pub open_chords: i32,
+    #[serde(default)]
+    pub finger_picking: i32,
+    #[serde(default)]
+    pub pick_direction: i32,
+    #[serde(default)]
+    pub double_stops: i32,
+    #[serde(default)]
+    pub palm_mutes: i32,
+    #[serde(default)]
+    pub harmonics: i32,
+    #[serde(default)]
+    pub pinch_harmonics: i32,
+    #[serde(default)]
+    pub hopo: i32,
+    #[serde(default)]
+    pub tremolo: i32,
+    #[serde(default)]
+    pub slides: i32,
+    #[serde(default)]
+    pub unpitched_slides: i32,
+    #[serde(default)]
+    pub bends: i32,
+    #[serde(default)]
+    pub tapping: i32,
+    #[serde(default)]
+    pub vibrato: i32,
+    #[serde(default)]
+    pub fret_hand_mutes: i32,
+    #[serde(default)]
+    pub slap_pop: i32,
+    #[serde(default)]
+    pub two_finger_picking: i32,
+    #[serde(default)]
+    pub fifths_and_octaves: i32,
+    #[serde(default)]
+    pub syncopation: i32,
+    #[serde(default)]
+    pub bass_pick: i32,
+    #[serde(default)]
+    pub sustain: i32,
+    #[serde(default)]
+    pub path_lead: i32,
+    #[serde(default)]
+    pub path_rhythm: i32,
+    #[serde(default)]
+    pub path_bass: i32,
+    #[serde(default)]
+    pub route_mask: i32,
+}
+
+/// One entry of a manifest document with the archive entry it was read from.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SongManifest {
+    /// Archive path of the manifest document.
+    pub entry_path: String,
+    /// Id of the entry in the document's `Entries`.
+    pub id: String,
+    pub attributes: ManifestAttributes,
+}
+
+/// True for per-arrangement manifest entries (`manifests/.../<key>_<arrangement>.json`).
+fn is_arrangement_manifest(path: &str) -> bool {
+    path.starts_with("manifests/") && path.ends_with(".json")
+}
+
+/// True for song pack manifests (`manifests/.../<pack>.hsan`).
+fn is_hsan(path: &str) -> bool {
+    path.starts_with("manifests/") && path.ends_with(".hsan")
+}
+
+/// Parses a manifest JSON or HSAN document.
+pub fn parse_manifest(data: &[u8]) -> Result<ManifestDocument> {
+    Ok(serde_json::from_slice(data)?)
+}
+
+/// Reads the entries of every manifest document of `psarc` whose path passes `wanted`.
+/// A document that does not parse is skipped with a warning.
+fn read_documents(psarc: &PsarcFile, wanted: fn(&str) -> bool) -> Result<Vec<SongManifest>> {
+    let documents = psarc
+        .toc
+        .entries
+        .iter()
+        .filter_map(|entry| Some((entry, entry.path.as_deref().filter(|path| wanted(path))?)));
+    let mut manifests = Vec::new();
+    for (entry, path) in documents {
+        let document = match parse_manifest(&psarc.inflate_entry_data(entry)?) {
+            Ok(document) => document,
+            Err(err) => {
+                tracing::warn!("Could not parse manifest {}: {}", path, err);
+                continue;
+            }
+        };
+        manifests.extend(document.entries.into_iter().map(|(id, entry)| SongManifest {
+            entry_path: path.to_string(),
+            id,
+            attributes: entry.attributes,
+        }));
+    }
+    Ok(manifests)
+}
+
+/// The per-arrangement manifests of `psarc`, in archive order.
+pub fn read_manifests(psarc: &PsarcFile) -> Result<Vec<SongManifest>> {
+    read_documents(psarc, is_arrangement_manifest)
+}
+
+/// The entries of the song pack HSAN of `psarc`, one per arrangement with the song level
+/// attributes only.
+pub fn read_hsan_manifests(psarc: &PsarcFile) -> Result<Vec<SongManifest>> {
+    read_documents(psarc, is_hsan)
+}
+
+/// The manifest document stored in `entry`.
+pub fn read_manifest_entry(psarc: &PsarcFile, entry: &PsarcTOCEntry) -> Result<ManifestDocument> {
+    parse_manifest(&psarc.inflate_entry_data(entry)?)
+}
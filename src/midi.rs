@@ -0,0 +1,824 @@
+//! Standard MIDI File export of charts and vocals.
+//!
+//! Every arrangement becomes one track (format 1) on its own channel, placed on a tempo
+//! map rebuilt from the chart's beats: each beat is a quarter note, so bars and tempo
+//! changes line up in a DAW or notation program. Vocals carry their lyrics as lyric meta
+//! events, which karaoke players display in time with the pitch track. `duo_midi` pairs
+//! two arrangements (lead and rhythm) as the two voices of one file for duo practice.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::models::{BendData32, Bpm, NoteMask, Vocal};
+use crate::pitch::sounding_pitch;
+use crate::psarc::SngAsset;
+use crate::song::{Song, SongArrangement};
+
+/// Resolution of exported files.
+pub const TICKS_PER_QUARTER: u16 = 480;
+
+/// Length of notes charted without a sustain, in seconds.
+pub const UNSUSTAINED_SECONDS: f32 = 0.25;
+
+/// Pitch-bend range set on instrument channels, in semitones either way. Wide enough
+/// for slides across most of the neck; the General MIDI default of 2 barely fits a bend.
+pub const BEND_RANGE_SEMITONES: u8 = 24;
+
+/// Spacing of the pitch-bend events drawing a slide or bend curve, in seconds.
+const BEND_SAMPLE_SECONDS: f32 = 0.02;
+
+/// Beat length assumed for charts without a beat map (120 BPM).
+const DEFAULT_BEAT_SECONDS: f32 = 0.5;
+
+/// Maps chart times to ticks, one quarter note per beat of the chart.
+#[derive(Debug, Clone)]
+pub struct TempoMap {
+    beats: Vec<f32>,
+    /// Indexes of the beats starting a measure.
+    downbeats: Vec<usize>,
+}
+
+impl TempoMap {
+    pub fn new(bpms: &[Bpm]) -> Self {
+        TempoMap {
+            beats: bpms.iter().map(|b| b.time).collect(),
+            downbeats: bpms.iter().enumerate().filter(|(_, b)| b.beat == 0).map(|(i, _)| i).collect(),
+        }
+    }
+
+    /// Ticks of the bar lines of the beat map.
+    pub fn bar_ticks(&self) -> Vec<u32> {
+        self.downbeats.iter().map(|&i| self.tick(self.beats[i])).collect()
+    }
+
+    /// Length of beat `i`; beats past either end repeat the nearest beat length.
+    fn beat_length(&self, i: usize) -> f32 {
+        let n = self.beats.len();
+        let length = match n {
+            0 | 1 => DEFAULT_BEAT_SECONDS,
+            _ if i + 1 < n => self.beats[i + 1] - self.beats[i],
+            _ => self.beats[n - 1] - self.beats[n - 2],
+        };
+        if length > 0.0 { length } else { DEFAULT_BEAT_SECONDS }
+    }
+
+    /// Tick of a time in seconds. Time 0 is tick 0, so audio and MIDI start together.
+    pub fn tick(&self, time: f32) -> u32 {
+        let quarter = TICKS_PER_QUARTER as f64;
+        let first = self.beats.first().copied().unwrap_or(0.0);
+        let lead_in = first as f64 / self.beat_length(0) as f64 * quarter;
+        let ticks = if time < first || self.beats.is_empty() {
+            lead_in - (first - time) as f64 / self.beat_length(0) as f64 * quarter
+        } else {
+            let beat = self.beats.partition_point(|b| *b <= time) - 1;
+            lead_in + (beat as f64 + (time - self.beats[beat]) as f64 / self.beat_length(beat) as f64) * quarter
+        };
+        ticks.round().max(0.0) as u32
+    }
+
+    /// Tempo changes as (tick, microseconds per quarter note), starting at tick 0.
+    pub fn tempo_events(&self) -> Vec<(u32, u32)> {
+        let mut events: Vec<(u32, u32)> = Vec::new();
+        let count = self.beats.len().max(1);
+        for i in 0..count {
+            let tick = if i == 0 { 0 } else { self.tick(self.beats[i]) };
+            let tempo = (self.beat_length(i) as f64 * 1_000_000.0).round() as u32;
+            if events.last().is_none_or(|(_, last)| *last != tempo) {
+                events.push((tick, tempo));
+            }
+        }
+        events
+    }
+}
+
+/// Timing of exported notes: where they start and how charted sustains become lengths.
+///
+/// Chart times and durations suit a DAW, which plays back what it is given. Notation
+/// programs (MuseScore and the like) turn every odd time into a chain of tiny rests and
+/// ties, so `MidiOptions::for_notation` quantizes starts and ends to a grid of the tempo
+/// map, drops sustains too short to read and splits notes held over a bar line into tied
+/// notes, one per measure.
+#[derive(Debug, Clone)]
+pub struct MidiOptions {
+    /// Divisions of a beat that note ends snap to. `None` keeps the chart durations.
+    pub sustain_grid: Option<u32>,
+    /// Sustains shorter than this, in seconds, are played as unsustained notes.
+    pub min_sustain: f32,
+    /// Length of unsustained notes, in seconds.
+    pub unsustained_length: f32,
+    /// Splits notes held over a bar line at the bar line.
+    pub tie_over_bars: bool,
+    /// Divisions of a beat that note starts are pulled towards. `None` keeps chart times.
+    pub quantize: Option<u32>,
+    /// Share of the distance to the grid a note moves: 1 snaps onto the grid, lower
+    /// values tighten the timing while keeping some of the feel of the performance.
+    pub quantize_strength: f32,
+}
+
+impl Default for MidiOptions {
+    fn default() -> Self {
+        MidiOptions {
+            sustain_grid: None,
+            min_sustain: 0.0,
+            unsustained_length: UNSUSTAINED_SECONDS,
+            tie_over_bars: false,
+            quantize: None,
+            quantize_strength: 1.0,
+        }
+    }
+}
+
+impl MidiOptions {
+    pub fn new() -> Self {
+        MidiOptions::default()
+    }
+
+    /// Notes and sustains on a sixteenth-note grid, sustains under a tenth of a second
+    /// dropped, ties at bar lines.
+    pub fn for_notation() -> Self {
+        MidiOptions {
+            sustain_grid: Some(4),
+            min_sustain: 0.1,
+            tie_over_bars: true,
+            quantize: Some(4),
+            ..MidiOptions::default()
+        }
+    }
+
+    pub fn sustain_grid(mut self, divisions: Option<u32>) -> Self {
+        self.sustain_grid = divisions.map(|d| d.max(1));
+        self
+    }
+
+    pub fn min_sustain(mut self, seconds: f32) -> Self {
+        self.min_sustain = seconds.max(0.0);
+        self
+    }
+
+    pub fn unsustained_length(mut self, seconds: f32) -> Self {
+        self.unsustained_length = seconds.max(0.0);
+        self
+    }
+
+    pub fn tie_over_bars(mut self, tie: bool) -> Self {
+        self.tie_over_bars = tie;
+        self
+    }
+
+    pub fn quantize(mut self, divisions: Option<u32>) -> Self {
+        self.quantize = divisions.map(|d| d.max(1));
+        self
+    }
+
+    pub fn quantize_strength(mut self, strength: f32) -> Self {
+        self.quantize_strength = strength.clamp(0.0, 1.0);
+        self
+    }
+
+    /// `tick` moved towards the nearest line of a grid of `divisions` per beat.
+    fn quantized(&self, tick: u32, divisions: u32) -> u32 {
+        let step = (TICKS_PER_QUARTER as u32 / divisions.max(1)).max(1);
+        let nearest = ((tick + step / 2) / step) * step;
+        let moved = (nearest as f32 - tick as f32) * self.quantize_strength.clamp(0.0, 1.0);
+        (tick as f32 + moved).round() as u32
+    }
+
+    /// Sounding length of a note with the given sustain, in seconds.
+    fn note_length(&self, sustain: f32) -> f32 {
+        if sustain > 0.0 && sustain >= self.min_sustain { sustain } else { self.unsustained_length }
+    }
+
+    /// Spans (start, end) in ticks of a note held from `start` to `end`: the end snapped to
+    /// the grid (never before the first grid line after the start), then split at bar lines.
+    fn held_spans(&self, bars: &[u32], start: u32, end: u32) -> Vec<(u32, u32)> {
+        let end = match self.sustain_grid {
+            Some(divisions) => {
+                let step = (TICKS_PER_QUARTER as u32 / divisions.max(1)).max(1);
+                let snapped = ((end + step / 2) / step) * step;
+                snapped.max((start / step + 1) * step)
+            }
+            None => end,
+        };
+        let mut spans = Vec::new();
+        let mut from = start;
+        if self.tie_over_bars {
+            for &bar in bars.iter().filter(|bar| **bar > start && **bar < end) {
+                spans.push((from, bar));
+                from = bar;
+            }
+        }
+        spans.push((from, end));
+        spans
+    }
+}
+
+/// How far quantization moved the notes of a track, in ticks.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct QuantizeReport {
+    pub notes: usize,
+    /// Notes whose start changed.
+    pub moved: usize,
+    pub max_shift: u32,
+    pub total_shift: u64,
+}
+
+impl QuantizeReport {
+    fn add(&mut self, from: u32, to: u32) {
+        let shift = from.abs_diff(to);
+        self.notes += 1;
+        if shift > 0 {
+            self.moved += 1;
+        }
+        self.max_shift = self.max_shift.max(shift);
+        self.total_shift += shift as u64;
+    }
+
+    /// Average shift of a note, in ticks.
+    pub fn mean_shift(&self) -> f64 {
+        if self.notes == 0 { 0.0 } else { self.total_shift as f64 / self.notes as f64 }
+    }
+}
+
+/// One event of a track.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MidiEventKind {
+    NoteOff { channel: u8, key: u8 },
+    NoteOn { channel: u8, key: u8, velocity: u8 },
+    /// Pitch bend, -8192..=8191 around the centre.
+    PitchBend { channel: u8, value: i16 },
+    ProgramChange { channel: u8, program: u8 },
+    ControlChange { channel: u8, controller: u8, value: u8 },
+    /// Microseconds per quarter note.
+    Tempo(u32),
+    TrackName(String),
+    Lyric(String),
+    /// Text event, used for the furigana reading of a lyric.
+    Text(String),
+}
+
+impl MidiEventKind {
+    /// Order of events sharing a tick: note offs and settings before the notes they affect.
+    fn order(&self) -> u8 {
+        match self {
+            MidiEventKind::TrackName(_) | MidiEventKind::Tempo(_) => 0,
+            MidiEventKind::ProgramChange { .. } | MidiEventKind::ControlChange { .. } => 1,
+            MidiEventKind::NoteOff { .. } => 2,
+            MidiEventKind::PitchBend { .. } => 3,
+            MidiEventKind::Text(_) | MidiEventKind::Lyric(_) => 4,
+            MidiEventKind::NoteOn { .. } => 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MidiEvent {
+    pub tick: u32,
+    pub kind: MidiEventKind,
+}
+
+/// A track of a MIDI file. Events may be added in any order.
+#[derive(Debug, Clone, Default)]
+pub struct MidiTrack {
+    pub events: Vec<MidiEvent>,
+    /// How far the notes of the track moved, when it was quantized.
+    pub quantization: Option<QuantizeReport>,
+}
+
+impl MidiTrack {
+    pub fn new(name: &str) -> Self {
+        let mut track = MidiTrack::default();
+        track.push(0, MidiEventKind::TrackName(name.to_string()));
+        track
+    }
+
+    pub fn push(&mut self, tick: u32, kind: MidiEventKind) {
+        self.events.push(MidiEvent { tick, kind });
+    }
+
+    /// Adds a note held from `start` to `end` (ticks). A zero length is stretched to one tick.
+    pub fn note(&mut self, start: u32, end: u32, channel: u8, key: u8, velocity: u8) {
+        self.push(start, MidiEventKind::NoteOn { channel, key, velocity });
+        self.push(end.max(start + 1), MidiEventKind::NoteOff { channel, key });
+    }
+
+    /// Sets the pitch-bend range of a channel (registered parameter 0) to `semitones`.
+    pub fn bend_range(&mut self, tick: u32, channel: u8, semitones: u8) {
+        for (controller, value) in [(101, 0), (100, 0), (6, semitones), (38, 0), (101, 127), (100, 127)] {
+            self.push(tick, MidiEventKind::ControlChange { channel, controller, value });
+        }
+    }
+
+    /// Draws a pitch curve of (time, semitones) points, joined linearly, as pitch-bend
+    /// events from the first point to `end`, then returns the channel to its centre.
+    /// `shift` moves every event by that many ticks, to follow a quantized note.
+    fn bend_curve(&mut self, tempo: &TempoMap, channel: u8, curve: &[(f32, f32)], end: f32, shift: i64) {
+        let at = |time: f32| (tempo.tick(time) as i64 + shift).max(0) as u32;
+        let (Some(&(start, _)), Some(&(last, _))) = (curve.first(), curve.last()) else {
+            return;
+        };
+        let scale = 8192.0 / BEND_RANGE_SEMITONES as f32;
+        let mut previous = None;
+        let samples = ((last.max(end) - start) / BEND_SAMPLE_SECONDS).ceil() as usize;
+        for i in 0..=samples {
+            let time = (start + i as f32 * BEND_SAMPLE_SECONDS).min(end);
+            let value = (curve_at(curve, time) * scale).round().clamp(-8192.0, 8191.0) as i16;
+            if previous != Some(value) {
+                self.push(at(time), MidiEventKind::PitchBend { channel, value });
+                previous = Some(value);
+            }
+        }
+        if previous.is_some_and(|value| value != 0) {
+            self.push(at(end), MidiEventKind::PitchBend { channel, value: 0 });
+        }
+    }
+
+    /// The events in playing order: by tick, note offs before the notes they make room for.
+    pub fn sorted_events(&self) -> Vec<MidiEvent> {
+        let mut events = self.events.clone();
+        events.sort_by_key(|e| (e.tick, e.kind.order()));
+        events
+    }
+
+    /// Encodes the track chunk, events sorted by tick.
+    fn to_chunk(&self) -> Vec<u8> {
+        let events = self.sorted_events();
+        let mut data = Vec::new();
+        let mut last_tick = 0;
+        for event in &events {
+            write_vlq(&mut data, event.tick - last_tick);
+            last_tick = event.tick;
+            match &event.kind {
+                MidiEventKind::NoteOff { channel, key } => data.extend_from_slice(&[0x80 | channel, *key, 0]),
+                MidiEventKind::NoteOn { channel, key, velocity } => {
+                    data.extend_from_slice(&[0x90 | channel, *key, *velocity])
+                }
+                MidiEventKind::PitchBend { channel, value } => {
+                    let raw = (*value as i32 + 8192).clamp(0, 16383) as u16;
+                    data.extend_from_slice(&[0xE0 | channel, (raw & 0x7F) as u8, (raw >> 7) as u8]);
+                }
+                MidiEventKind::ProgramChange { channel, program } => data.extend_from_slice(&[0xC0 | channel, *program]),
+                MidiEventKind::ControlChange { channel, controller, value } => {
+                    data.extend_from_slice(&[0xB0 | channel, *controller, *value])
+                }
+                MidiEventKind::Tempo(tempo) => {
+                    data.extend_from_slice(&[0xFF, 0x51, 3]);
+                    data.extend_from_slice(&tempo.to_be_bytes()[1..]);
+                }
+                MidiEventKind::TrackName(text) => write_meta_text(&mut data, 0x03, text),
+                MidiEventKind::Lyric(text) => write_meta_text(&mut data, 0x05, text),
+                MidiEventKind::Text(text) => write_meta_text(&mut data, 0x01, text),
+            }
+        }
+        data.extend_from_slice(&[0, 0xFF, 0x2F, 0]);
+        let mut chunk = b"MTrk".to_vec();
+        chunk.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        chunk.extend_from_slice(&data);
+        chunk
+    }
+}
+
+fn write_vlq(data: &mut Vec<u8>, mut value: u32) {
+    let mut bytes = vec![(value & 0x7F) as u8];
+    value >>= 7;
+    while value > 0 {
+        bytes.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+    data.extend(bytes.iter().rev());
+}
+
+fn write_meta_text(data: &mut Vec<u8>, kind: u8, text: &str) {
+    data.extend_from_slice(&[0xFF, kind]);
+    write_vlq(data, text.len() as u32);
+    data.extend_from_slice(text.as_bytes());
+}
+
+/// A format 1 MIDI file: a tempo track followed by the instrument and vocal tracks.
+#[derive(Debug, Clone)]
+pub struct MidiFile {
+    pub tempo: TempoMap,
+    pub tracks: Vec<MidiTrack>,
+}
+
+impl MidiFile {
+    pub fn new(tempo: TempoMap) -> Self {
+        MidiFile { tempo, tracks: Vec::new() }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut conductor = MidiTrack::new("Tempo");
+        for (tick, tempo) in self.tempo.tempo_events() {
+            conductor.push(tick, MidiEventKind::Tempo(tempo));
+        }
+        let mut bytes = b"MThd".to_vec();
+        bytes.extend_from_slice(&6u32.to_be_bytes());
+        bytes.extend_from_slice(&1u16.to_be_bytes());
+        bytes.extend_from_slice(&(self.tracks.len() as u16 + 1).to_be_bytes());
+        bytes.extend_from_slice(&TICKS_PER_QUARTER.to_be_bytes());
+        bytes.extend(conductor.to_chunk());
+        for track in &self.tracks {
+            bytes.extend(track.to_chunk());
+        }
+        bytes
+    }
+
+    pub fn write_path(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path.as_ref(), self.to_bytes())?;
+        tracing::info!("Written MIDI file to {:?}", path.as_ref());
+        Ok(())
+    }
+}
+
+/// General MIDI program (0-based) of an arrangement.
+fn program_for(name: &str) -> u8 {
+    match name {
+        n if n.contains("bass") => 33,
+        n if n.contains("vocals") => 53,
+        n if n.contains("rhythm") => 29,
+        _ => 30,
+    }
+}
+
+/// Vocal track: one note per pitched syllable, and every syllable as a lyric event.
+/// The `+` line-break marker becomes a carriage return, as karaoke players expect; the
+/// `-` continuation marker of split words is kept. A furigana reading is left out of the
+/// lyric and written as a text event at the same tick.
+pub fn vocals_track(vocals: &[Vocal], tempo: &TempoMap, name: &str, channel: u8) -> MidiTrack {
+    let mut track = MidiTrack::new(name);
+    track.push(0, MidiEventKind::ProgramChange { channel, program: program_for("vocals") });
+    for vocal in vocals {
+        let start = tempo.tick(vocal.time);
+        let parts = vocal.parts();
+        let mut text = parts.text;
+        if parts.continues {
+            text.push('-');
+        }
+        if parts.line_end {
+            text.push('\r');
+        }
+        if let Some(ruby) = parts.ruby {
+            track.push(start, MidiEventKind::Text(ruby));
+        }
+        track.push(start, MidiEventKind::Lyric(text));
+        if let Ok(key) = u8::try_from(vocal.note) {
+            if key <= 127 {
+                track.note(start, tempo.tick(vocal.time + vocal.length), channel, key, 96);
+            }
+        }
+    }
+    track
+}
+
+/// Instrument track of the full-difficulty chart, each note at its sounding pitch
+/// (harmonics included, see `pitch::sounding_pitch`). Bends and slides become pitch-bend
+/// curves; as bends act on the whole channel, a chord follows the curve of its first
+/// bending or sliding string.
+pub fn arrangement_track(sng: &SngAsset, tempo: &TempoMap, name: &str, channel: u8, options: &MidiOptions) -> MidiTrack {
+    let bass = name.contains("bass");
+    let capo = sng.metadata.capo_fret_id;
+    let tuning = &sng.metadata.tuning;
+    let mut track = MidiTrack::new(name);
+    track.push(0, MidiEventKind::ProgramChange { channel, program: program_for(name) });
+    track.bend_range(0, channel, BEND_RANGE_SEMITONES);
+    let bars = tempo.bar_ticks();
+    let mut report = QuantizeReport::default();
+    for note in sng.max_difficulty_notes() {
+        let end_time = note.time + options.note_length(note.sustain);
+        let (raw_start, raw_end) = (tempo.tick(note.time), tempo.tick(end_time));
+        let start = match options.quantize {
+            Some(divisions) => {
+                let start = options.quantized(raw_start, divisions);
+                report.add(raw_start, start);
+                start
+            }
+            None => raw_start,
+        };
+        let end = (raw_end as i64 + start as i64 - raw_start as i64).max(start as i64 + 1) as u32;
+        // Per string: fret, technique flags and pitch curve; chords take theirs from the
+        // chord notes.
+        let strings: Vec<(usize, u8, NoteMask, PitchCurve)> =
+            match usize::try_from(note.chord_id).ok().and_then(|id| sng.chords.get(id)) {
+                Some(chord) => {
+                    let chord_notes = usize::try_from(note.chord_notes_id).ok().and_then(|id| sng.chord_notes.get(id));
+                    chord
+                        .frets
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, fret)| **fret != u8::MAX)
+                        .map(|(string, &fret)| match chord_notes {
+                            Some(n) => {
+                                let bend = &n.bend_data[string];
+                                let points = &bend.bend_data[..(bend.used_count.clamp(0, 32) as usize)];
+                                let curve = pitch_curve(
+                                    note.time,
+                                    end_time,
+                                    fret,
+                                    n.slide_to[string],
+                                    n.slide_unpitch_to[string],
+                                    points,
+                                );
+                                (string, fret, NoteMask::from_bits_truncate(n.note_mask[string] as u32), curve)
+                            }
+                            None => (string, fret, NoteMask::empty(), Vec::new()),
+                        })
+                        .collect()
+                }
+                None => {
+                    let curve = pitch_curve(
+                        note.time,
+                        end_time,
+                        note.fret_id,
+                        note.slide_to,
+                        note.slide_unpitch_to,
+                        &note.bend_data,
+                    );
+                    vec![(note.string_index as usize, note.fret_id, note.mask(), curve)]
+                }
+            };
+        if let Some((_, _, _, curve)) = strings.iter().find(|(_, _, _, curve)| !curve.is_empty()) {
+            track.bend_curve(tempo, channel, curve, end_time, start as i64 - raw_start as i64);
+        }
+        for (string, fret, mask, _) in strings {
+            let pitch = sounding_pitch(tuning, string, fret, mask, capo, bass);
+            if let Ok(key) = u8::try_from(pitch) {
+                if key <= 127 {
+                    for (from, to) in options.held_spans(&bars, start, end) {
+                        track.note(from, to, channel, key, 100);
+                    }
+                }
+            }
+        }
+    }
+    if options.quantize.is_some() {
+        tracing::debug!(
+            "Quantized {}: {} of {} notes moved, by up to {} ticks",
+            name,
+            report.moved,
+            report.notes,
+            report.max_shift
+        );
+        track.quantization = Some(report);
+    }
+    track
+}
+
+/// Points (time, semitones) of a pitch curve, joined linearly.
+type PitchCurve = Vec<(f32, f32)>;
+
+/// Value of a pitch curve at `time`, holding the end points outside it.
+fn curve_at(curve: &[(f32, f32)], time: f32) -> f32 {
+    let next = curve.partition_point(|(t, _)| *t <= time);
+    match (next.checked_sub(1).map(|i| curve[i]), curve.get(next)) {
+        (Some((t0, v0)), Some(&(t1, v1))) if t1 > t0 => v0 + (v1 - v0) * (time - t0) / (t1 - t0),
+        (Some((_, v)), _) => v,
+        (None, Some(&(_, v))) => v,
+        (None, None) => 0.0,
+    }
+}
+
+/// Pitch curve of a note from `start` to `end`, in semitones above the note: the bend
+/// points (steps are whole tones) when the note bends, else a glide to the slide
+/// target over the sustain. Empty when the pitch never moves.
+fn pitch_curve(start: f32, end: f32, fret: u8, slide_to: u8, unpitched_slide_to: u8, bends: &[BendData32]) -> PitchCurve {
+    if bends.iter().any(|b| b.step != 0.0) {
+        let mut curve = vec![(start, 0.0)];
+        curve.extend(bends.iter().filter(|b| b.time >= start).map(|b| (b.time, b.step * 2.0)));
+        return curve;
+    }
+    let target = [slide_to, unpitched_slide_to].into_iter().find(|fret| *fret != u8::MAX && *fret != 0);
+    match target {
+        Some(target) if target != fret => vec![(start, 0.0), (end, target as f32 - fret as f32)],
+        _ => Vec::new(),
+    }
+}
+
+/// Channel of the `index`-th track, skipping the General MIDI drum channel.
+fn channel_for(index: usize) -> u8 {
+    let channel = (index % 15) as u8;
+    if channel >= 9 { channel + 1 } else { channel }
+}
+
+/// Builds the MIDI file of a song: one track per arrangement, vocals with their lyrics,
+/// all on the tempo map of the first arrangement.
+pub fn song_midi(song: &Song, options: &MidiOptions) -> io::Result<MidiFile> {
+    let arrangements: Vec<&SongArrangement> = song.arrangements().iter().collect();
+    arrangements_midi(song, &arrangements, options)
+}
+
+/// Builds the MIDI file of two arrangements of a song played together, `lead` as voice 1
+/// (first track, channel 1) and `rhythm` as voice 2 (second track, channel 2), both on
+/// the tempo map of `lead` so their notes line up.
+pub fn duo_midi(song: &Song, lead: &str, rhythm: &str, options: &MidiOptions) -> io::Result<MidiFile> {
+    let find = |name: &str| {
+        song.arrangement(name).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("Song {} has no arrangement {}", song.key, name))
+        })
+    };
+    arrangements_midi(song, &[find(lead)?, find(rhythm)?], options)
+}
+
+/// One track per arrangement, in order, on the tempo map of the first.
+fn arrangements_midi(song: &Song, arrangements: &[&SongArrangement], options: &MidiOptions) -> io::Result<MidiFile> {
+    let first = arrangements
+        .first()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("Song {} has no arrangements", song.key)))?;
+    let mut file = MidiFile::new(TempoMap::new(&first.sng()?.bpms));
+    for (i, arrangement) in arrangements.iter().enumerate() {
+        let sng = arrangement.sng()?;
+        let track = if arrangement.is_vocals() {
+            vocals_track(&sng.vocals, &file.tempo, &arrangement.name, channel_for(i))
+        } else {
+            arrangement_track(sng, &file.tempo, &arrangement.name, channel_for(i), options)
+        };
+        file.tracks.push(track);
+    }
+    Ok(file)
+}
+
+/// Writes the vocal line of a song (pitch and lyrics) as a MIDI file.
+pub fn export_vocals_midi(song: &Song, output_path: &Path) -> io::Result<()> {
+    let vocals = song
+        .arrangements()
+        .iter()
+        .find(|a| a.is_vocals())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("Song {} has no vocals", song.key)))?;
+    let sng = vocals.sng()?;
+    let tempo = TempoMap::new(&sng.bpms);
+    let mut file = MidiFile::new(tempo);
+    file.tracks.push(vocals_track(&sng.vocals, &file.tempo, &vocals.name, 0));
+    file.write_path(output_path)
+}
+
+/// Writes every arrangement of a song, vocals included, as one MIDI file.
+pub fn export_song_midi(song: &Song, options: &MidiOptions, output_path: &Path) -> io::Result<()> {
+    song_midi(song, options)?.write_path(output_path)
+}
+
+/// Writes the `duo_midi` file of two arrangements of a song.
+pub fn export_duo_midi(song: &Song, lead: &str, rhythm: &str, options: &MidiOptions, output_path: &Path) -> io::Result<()> {
+    duo_midi(song, lead, rhythm, options)?.write_path(output_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bpms(times: &[f32], beats_per_bar: i16) -> Vec<Bpm> {
+        times
+            .iter()
+            .enumerate()
+            .map(|(i, &time)| Bpm { time, measure: 0, beat: i as i16 % beats_per_bar, phrase_iteration: 0, mask: 0 })
+            .collect()
+    }
+
+    #[test]
+    fn ticks_follow_the_beat_map() {
+        // Two beats at 120 BPM, then one at 60 BPM, starting half a second in.
+        let tempo = TempoMap::new(&bpms(&[0.5, 1.0, 1.5, 2.5], 2));
+        assert_eq!(tempo.tick(0.0), 0);
+        assert_eq!(tempo.tick(0.25), 240);
+        assert_eq!(tempo.tick(0.5), 480);
+        assert_eq!(tempo.tick(0.75), 720);
+        assert_eq!(tempo.tick(2.0), 1680);
+        // Past the beat map the last beat length repeats.
+        assert_eq!(tempo.tick(3.0), 2160);
+        assert_eq!(tempo.bar_ticks(), [480, 1440]);
+        assert_eq!(tempo.tempo_events(), [(0, 500_000), (1440, 1_000_000)]);
+    }
+
+    #[test]
+    fn charts_without_a_beat_map_play_at_120_bpm() {
+        let tempo = TempoMap::new(&[]);
+        assert_eq!(tempo.tick(1.0), 960);
+        assert_eq!(tempo.tempo_events(), [(0, 500_000)]);
+    }
+
+    #[test]
+    fn vocals_become_notes_and_lyrics() {
+        let tempo = TempoMap::new(&bpms(&[0.0, 0.5, 1.0, 1.5], 4));
+        let vocal = |time, note, lyric: &str| Vocal { time, note, length: 0.25, lyric: lyric.to_string() };
+        let track = vocals_track(&[vocal(0.5, 60, "Hel-"), vocal(1.0, 62, "lo+"), vocal(1.5, 254, "hey")], &tempo, "Vocals", 3);
+        let lyrics: Vec<(u32, &str)> = track
+            .events
+            .iter()
+            .filter_map(|e| match &e.kind {
+                MidiEventKind::Lyric(text) => Some((e.tick, text.as_str())),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(lyrics, [(480, "Hel-"), (960, "lo\r"), (1440, "hey")]);
+        let notes: Vec<(u32, u8, bool)> = track
+            .sorted_events()
+            .iter()
+            .filter_map(|e| match e.kind {
+                MidiEventKind::NoteOn { channel: 3, key, .. } => Some((e.tick, key, true)),
+                MidiEventKind::NoteOff { channel: 3, key } => Some((e.tick, key, false)),
+                _ => None,
+            })
+            .collect();
+        // Unpitched syllables (note 254) are lyrics only.
+        assert_eq!(notes, [(480, 60, true), (720, 60, false), (960, 62, true), (1200, 62, false)]);
+    }
+
+    fn bend(time: f32, step: f32) -> BendData32 {
+        BendData32 { time, step, unk3_0: 0, unk4_0: 0, unk5: 0 }
+    }
+
+    fn bend_values(track: &MidiTrack) -> Vec<(u32, i16)> {
+        track
+            .events
+            .iter()
+            .filter_map(|e| match e.kind {
+                MidiEventKind::PitchBend { value, .. } => Some((e.tick, value)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn slides_glide_to_the_target_fret() {
+        assert_eq!(pitch_curve(0.0, 1.0, 5, 7, u8::MAX, &[]), [(0.0, 0.0), (1.0, 2.0)]);
+        assert_eq!(pitch_curve(0.0, 1.0, 5, u8::MAX, 3, &[]), [(0.0, 0.0), (1.0, -2.0)]);
+        assert!(pitch_curve(0.0, 1.0, 5, 5, u8::MAX, &[]).is_empty());
+        assert!(pitch_curve(0.0, 1.0, 5, 0, u8::MAX, &[]).is_empty());
+        let curve = pitch_curve(0.0, 1.0, 5, 7, u8::MAX, &[]);
+        assert_eq!(curve_at(&curve, 0.5), 1.0);
+        assert_eq!(curve_at(&curve, 2.0), 2.0);
+    }
+
+    #[test]
+    fn bend_steps_are_whole_tones_on_the_bend_range() {
+        let tempo = TempoMap::new(&bpms(&[0.0, 0.5, 1.0, 1.5], 4));
+        let curve = pitch_curve(0.5, 1.0, 5, u8::MAX, u8::MAX, &[bend(0.75, 1.0)]);
+        assert_eq!(curve, [(0.5, 0.0), (0.75, 2.0)]);
+        let mut track = MidiTrack::default();
+        track.bend_curve(&tempo, 0, &curve, 1.0, 0);
+        let values = bend_values(&track);
+        // Two semitones of a 24-semitone range: 8192 / 12.
+        assert_eq!(values.first(), Some(&(480, 0)));
+        assert_eq!(values.iter().map(|(_, v)| *v).max(), Some(683));
+        assert!(values.windows(2).all(|w| w[0].0 <= w[1].0));
+        assert_eq!(values.last(), Some(&(960, 0)));
+
+        // A quantized note carries its bend along; bends past the range are clamped.
+        let mut track = MidiTrack::default();
+        track.bend_curve(&tempo, 0, &[(0.5, 0.0), (0.5, 26.0)], 0.5, -30);
+        assert_eq!(bend_values(&track), [(450, 8191), (450, 0)]);
+    }
+
+    #[test]
+    fn held_notes_are_tied_over_bar_lines() {
+        let bars = [0, 1920, 3840];
+        assert_eq!(MidiOptions::new().held_spans(&bars, 1800, 4000), [(1800, 4000)]);
+        let tied = MidiOptions::new().tie_over_bars(true);
+        assert_eq!(tied.held_spans(&bars, 1800, 4000), [(1800, 1920), (1920, 3840), (3840, 4000)]);
+        // A note ending on a bar line needs no tie.
+        assert_eq!(tied.held_spans(&bars, 1800, 1920), [(1800, 1920)]);
+    }
+
+    #[test]
+    fn sustains_snap_to_the_grid() {
+        let options = MidiOptions::new().sustain_grid(Some(4)).tie_over_bars(true);
+        assert_eq!(options.held_spans(&[0, 1920], 1800, 1990), [(1800, 1920), (1920, 2040)]);
+        // A note never snaps shorter than one grid step.
+        assert_eq!(options.held_spans(&[0, 1920], 10, 20), [(10, 120)]);
+
+        let options = MidiOptions::new().min_sustain(0.1).unsustained_length(0.2);
+        assert_eq!(options.note_length(0.05), 0.2);
+        assert_eq!(options.note_length(0.0), 0.2);
+        assert_eq!(options.note_length(0.5), 0.5);
+    }
+
+    #[test]
+    fn starts_move_towards_the_grid() {
+        let options = MidiOptions::new();
+        assert_eq!(options.quantized(130, 4), 120);
+        assert_eq!(options.quantized(175, 4), 120);
+        assert_eq!(options.quantized(185, 4), 240);
+        assert_eq!(options.quantized(130, 1), 0);
+        assert_eq!(options.quantize_strength(0.5).quantized(130, 4), 125);
+        assert_eq!(MidiOptions::new().quantize_strength(0.0).quantized(130, 4), 130);
+    }
+
+    #[test]
+    fn quantize_reports_count_the_moved_notes() {
+        let mut report = QuantizeReport::default();
+        assert_eq!(report.mean_shift(), 0.0);
+        report.add(130, 120);
+        report.add(240, 240);
+        report.add(470, 480);
+        report.add(600, 570);
+        assert_eq!(report, QuantizeReport { notes: 4, moved: 3, max_shift: 30, total_shift: 50 });
+        assert_eq!(report.mean_shift(), 12.5);
+
+        // Only quantized tracks carry a report.
+        let sng = SngAsset::default();
+        let tempo = TempoMap::new(&sng.bpms);
+        let quantized = MidiOptions::new().quantize(Some(4));
+        assert_eq!(arrangement_track(&sng, &tempo, "lead", 0, &quantized).quantization, Some(QuantizeReport::default()));
+        assert_eq!(arrangement_track(&sng, &tempo, "lead", 0, &MidiOptions::new()).quantization, None);
+    }
+}
@@ -1,43 +1,182 @@
-use std::io::{self, Read};
-use byteorder::{LittleEndian, ReadBytesExt};
+use std::io::{Cursor, Read, Write};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use serde::Serialize;
+use crate::error::{PsarcError, Result};
 
 /// A trait for types that can be read from a binary stream.
 pub trait BinarySerializable: Sized {
-    fn read_from<R: Read>(reader: &mut R) -> io::Result<Self>;
+    fn read_from<R: Read>(reader: &mut R) -> Result<Self>;
+
+    /// Reads with the options of `context`, recording what was noticed in its diagnostics.
+    /// Only types holding fixed strings use the context.
+    fn read_with<R: Read>(reader: &mut R, _context: &mut ParseContext) -> Result<Self> {
+        Self::read_from(reader)
+    }
+}
+
+/// How fixed-length strings that are not valid UTF-8 are decoded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StringPolicy {
+    /// Invalid sequences become U+FFFD.
+    #[default]
+    Lossy,
+    /// Invalid UTF-8 fails the parse with `PsarcError::InvalidAsset`.
+    Strict,
+    /// A string that is not valid UTF-8 is read as Latin-1, one character per byte, which
+    /// keeps localized names written by older tools readable.
+    Latin1Fallback,
+}
+
+/// Options of SNG parsing.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ParseOptions {
+    string_policy: StringPolicy,
+}
+
+impl ParseOptions {
+    pub fn new() -> Self {
+        ParseOptions::default()
+    }
+
+    pub fn string_policy(mut self, policy: StringPolicy) -> Self {
+        self.string_policy = policy;
+        self
+    }
+}
+
+/// What parsing had to repair, so callers can flag damaged or oddly encoded assets.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ParseDiagnostics {
+    /// Strings in which invalid UTF-8 was replaced by U+FFFD, as decoded.
+    pub lossy_strings: Vec<String>,
+    /// Strings read as Latin-1 under `StringPolicy::Latin1Fallback`, as decoded.
+    pub latin1_strings: Vec<String>,
+}
+
+impl ParseDiagnostics {
+    pub fn is_empty(&self) -> bool {
+        self.lossy_strings.is_empty() && self.latin1_strings.is_empty()
+    }
+}
+
+/// Options and diagnostics threaded through `BinarySerializable::read_with`.
+#[derive(Debug, Clone, Default)]
+pub struct ParseContext {
+    pub options: ParseOptions,
+    pub diagnostics: ParseDiagnostics,
+}
+
+impl ParseContext {
+    pub fn new(options: ParseOptions) -> Self {
+        ParseContext { options, diagnostics: ParseDiagnostics::default() }
+    }
+
+    /// Reads a fixed-length (zero-padded) string, decoded under the string policy.
+    fn read_string<R: Read>(&mut self, reader: &mut R, size: usize) -> Result<String> {
+        let mut buf = vec![0u8; size];
+        reader.read_exact(&mut buf)?;
+        // Trim at the first zero byte, if any.
+        let end = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+        let text = &buf[..end];
+        match std::str::from_utf8(text) {
+            Ok(valid) => return Ok(valid.to_string()),
+            // A field filled to the last byte may end in the middle of a multi-byte
+            // character (Japanese lyrics): drop the partial character.
+            Err(err) if err.error_len().is_none() => {
+                return Ok(String::from_utf8_lossy(&text[..err.valid_up_to()]).to_string());
+            }
+            Err(_) => {}
+        }
+        match self.options.string_policy {
+            StringPolicy::Lossy => {
+                let decoded = String::from_utf8_lossy(text).to_string();
+                self.diagnostics.lossy_strings.push(decoded.clone());
+                Ok(decoded)
+            }
+            StringPolicy::Strict => Err(PsarcError::InvalidAsset(format!(
+                "Invalid UTF-8 in a fixed string: {:?}",
+                String::from_utf8_lossy(text)
+            ))),
+            StringPolicy::Latin1Fallback => {
+                let decoded: String = text.iter().map(|&b| char::from(b)).collect();
+                self.diagnostics.latin1_strings.push(decoded.clone());
+                Ok(decoded)
+            }
+        }
+    }
 }
 
-/// Read a fixed-length (zero–padded) UTF-8 string from the stream.
-fn read_fixed_string<R: Read>(reader: &mut R, size: usize) -> io::Result<String> {
+/// Upper bound for capacity reserved up front from a count read out of the stream. Counts
+/// come from untrusted data, so larger arrays grow as elements are actually read.
+const MAX_PREALLOCATED: usize = 4096;
+
+/// Write a string as a fixed-length zero-padded field, truncated on a character boundary so
+/// at least one terminating zero byte remains.
+fn write_fixed_string<W: Write>(writer: &mut W, value: &str, size: usize) -> Result<()> {
+    let mut end = value.len().min(size.saturating_sub(1));
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
     let mut buf = vec![0u8; size];
-    reader.read_exact(&mut buf)?;
-    // Trim at the first zero byte, if any.
-    let end = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
-    Ok(String::from_utf8_lossy(&buf[..end]).to_string())
+    buf[..end].copy_from_slice(&value.as_bytes()[..end]);
+    writer.write_all(&buf)?;
+    Ok(())
 }
 
+/// Most elements an array may hold. Charts stay far below (a few thousand notes per
+/// level), so larger counts come from corrupt data.
+pub const MAX_ELEMENTS: usize = 1 << 20;
+
+/// Fewest bytes any array element takes; bounds counts by the bytes left in a buffer.
+const MIN_ELEMENT_BYTES: usize = 4;
+
 /// Reads an array from the stream. It is assumed that the number of elements (as an i32)
-/// comes first.
-pub fn read_vec<T, R: Read, F>(reader: &mut R, read_func: F) -> io::Result<Vec<T>>
+/// comes first. Counts above `MAX_ELEMENTS` fail with `PsarcError::CountOverflow`.
+pub fn read_vec<T, R: Read, F>(reader: &mut R, read_func: F) -> Result<Vec<T>>
+where
+    F: FnMut(&mut R) -> Result<T>,
+{
+    read_vec_bounded(reader, MAX_ELEMENTS, read_func)
+}
+
+/// `read_vec` failing with `PsarcError::CountOverflow` when the count exceeds `max_count`,
+/// before reading any element.
+pub fn read_vec_bounded<T, R: Read, F>(reader: &mut R, max_count: usize, mut read_func: F) -> Result<Vec<T>>
 where
-    F: Fn(&mut R) -> io::Result<T>,
+    F: FnMut(&mut R) -> Result<T>,
 {
     let count = reader.read_u32::<LittleEndian>()?;
-    if count < 0 {
-        return Err(io::Error::new(io::ErrorKind::InvalidData, "negative count"));
-    }
-    let count = count as usize;
-    
-    let mut v = Vec::with_capacity(count);
+    let count = checked_count(i64::from(count), max_count)?;
+    let mut v = Vec::with_capacity(count.min(MAX_PREALLOCATED));
     for _ in 0..count {
         v.push(read_func(reader)?);
     }
     Ok(v)
 }
 
+/// `read_vec` over an in-memory buffer: the count is also bounded by the bytes left after
+/// it, as no element is shorter than `MIN_ELEMENT_BYTES`.
+pub fn read_vec_from_buffer<'d, T, F>(reader: &mut Cursor<&'d [u8]>, read_func: F) -> Result<Vec<T>>
+where
+    F: FnMut(&mut Cursor<&'d [u8]>) -> Result<T>,
+{
+    let remaining = reader.get_ref().len().saturating_sub(reader.position() as usize);
+    let max_count = (remaining.saturating_sub(4) / MIN_ELEMENT_BYTES).min(MAX_ELEMENTS);
+    read_vec_bounded(reader, max_count, read_func)
+}
+
+/// Validates an element count read from the stream.
+fn checked_count(count: i64, limit: usize) -> Result<usize> {
+    match usize::try_from(count) {
+        Ok(valid) if valid <= limit => Ok(valid),
+        _ => Err(PsarcError::CountOverflow { count, limit }),
+    }
+}
+
 /// Reads a vector of f32 values with a given count.
-fn read_vec_of_f32<R: Read>(reader: &mut R, count: usize) -> io::Result<Vec<f32>> {
-    let mut v = Vec::with_capacity(count);
+fn read_vec_of_f32<R: Read>(reader: &mut R, count: i32) -> Result<Vec<f32>> {
+    let count = checked_count(i64::from(count), MAX_ELEMENTS)?;
+    let mut v = Vec::with_capacity(count.min(MAX_PREALLOCATED));
     for _ in 0..count {
         v.push(reader.read_f32::<LittleEndian>()?);
     }
@@ -45,15 +184,16 @@ fn read_vec_of_f32<R: Read>(reader: &mut R, count: usize) -> io::Result<Vec<f32>
 }
 
 /// Reads a vector of i32 values with a given count.
-fn read_vec_of_i32<R: Read>(reader: &mut R, count: usize) -> io::Result<Vec<i32>> {
-    let mut v = Vec::with_capacity(count);
+fn read_vec_of_i32<R: Read>(reader: &mut R, count: i32) -> Result<Vec<i32>> {
+    let count = checked_count(i64::from(count), MAX_ELEMENTS)?;
+    let mut v = Vec::with_capacity(count.min(MAX_PREALLOCATED));
     for _ in 0..count {
         v.push(reader.read_i32::<LittleEndian>()?);
     }
     Ok(v)
 }
 
-/// ----------------- Model definitions -----------------
+// ----------------- Model definitions -----------------
 
 /// Corresponds to C#:
 /// public struct Action { public float Time; [MarshalAs(UnmanagedType.ByValTStr, SizeConst = 256)] public string ActionName; }
@@ -64,9 +204,13 @@ pub struct Action {
 }
 
 impl BinarySerializable for Action {
-    fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+    fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
+        Self::read_with(reader, &mut ParseContext::default())
+    }
+
+    fn read_with<R: Read>(reader: &mut R, context: &mut ParseContext) -> Result<Self> {
         let time = reader.read_f32::<LittleEndian>()?;
-        let action_name = read_fixed_string(reader, 256)?;
+        let action_name = context.read_string(reader, 256)?;
         Ok(Action { time, action_name })
     }
 }
@@ -75,7 +219,7 @@ impl BinarySerializable for Action {
 /// public struct Anchor { public float StartBeatTime; public float EndBeatTime; public float Unk3_FirstNoteTime;
 /// public float Unk4_LastNoteTime; public byte FretId; [MarshalAs(UnmanagedType.ByValArray, SizeConst = 3)] public byte[] Padding;
 /// public int Width; public int PhraseIterationId; }
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Anchor {
     pub start_beat_time: f32,
     pub end_beat_time: f32,
@@ -88,7 +232,7 @@ pub struct Anchor {
 }
 
 impl BinarySerializable for Anchor {
-    fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+    fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
         let start_beat_time = reader.read_f32::<LittleEndian>()?;
         let end_beat_time = reader.read_f32::<LittleEndian>()?;
         let unk3_first_note_time = reader.read_f32::<LittleEndian>()?;
@@ -114,7 +258,7 @@ impl BinarySerializable for Anchor {
 /// C# AnchorExtension:
 /// public struct AnchorExtension { public float BeatTime; public byte FretId; public int Unk2_0;
 /// public short Unk3_0; public byte Unk4_0; }
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct AnchorExtension {
     pub beat_time: f32,
     pub fret_id: u8,
@@ -124,7 +268,7 @@ pub struct AnchorExtension {
 }
 
 impl BinarySerializable for AnchorExtension {
-    fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+    fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
         let beat_time = reader.read_f32::<LittleEndian>()?;
         let fret_id = reader.read_u8()?;
         let unk2_0 = reader.read_i32::<LittleEndian>()?;
@@ -143,7 +287,7 @@ impl BinarySerializable for AnchorExtension {
 /// C# Fingerprint:
 /// public struct Fingerprint { public int ChordId; public float StartTime; public float EndTime;
 /// public float Unk3_FirstNoteTime; public float Unk4_LastNoteTime; }
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Fingerprint {
     pub chord_id: i32,
     pub start_time: f32,
@@ -153,7 +297,7 @@ pub struct Fingerprint {
 }
 
 impl BinarySerializable for Fingerprint {
-    fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+    fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
         let chord_id = reader.read_i32::<LittleEndian>()?;
         let start_time = reader.read_f32::<LittleEndian>()?;
         let end_time = reader.read_f32::<LittleEndian>()?;
@@ -169,6 +313,41 @@ impl BinarySerializable for Fingerprint {
     }
 }
 
+bitflags::bitflags! {
+    /// Technique flags of `Note::note_mask` (and of each string in `ChordNotes::note_mask`).
+    pub struct NoteMask: u32 {
+        const CHORD            = 0x0000_0002;
+        const OPEN             = 0x0000_0004;
+        const FRET_HAND_MUTE   = 0x0000_0008;
+        const TREMOLO          = 0x0000_0010;
+        const HARMONIC         = 0x0000_0020;
+        const PALM_MUTE        = 0x0000_0040;
+        const SLAP             = 0x0000_0080;
+        const PLUCK            = 0x0000_0100;
+        const HAMMER_ON        = 0x0000_0200;
+        const PULL_OFF         = 0x0000_0400;
+        const SLIDE            = 0x0000_0800;
+        const BEND             = 0x0000_1000;
+        const SUSTAIN          = 0x0000_2000;
+        const TAP              = 0x0000_4000;
+        const PINCH_HARMONIC   = 0x0000_8000;
+        const VIBRATO          = 0x0001_0000;
+        const MUTE             = 0x0002_0000;
+        const IGNORE           = 0x0004_0000;
+        const LEFT_HAND        = 0x0008_0000;
+        const RIGHT_HAND       = 0x0010_0000;
+        const HIGH_DENSITY     = 0x0020_0000;
+        const SLIDE_UNPITCHED  = 0x0040_0000;
+        const SINGLE           = 0x0080_0000;
+        const CHORD_NOTES      = 0x0100_0000;
+        const DOUBLE_STOP      = 0x0200_0000;
+        const ACCENT           = 0x0400_0000;
+        const PARENT           = 0x0800_0000;
+        const CHILD            = 0x1000_0000;
+        const ARPEGGIO         = 0x2000_0000;
+    }
+}
+
 /// C# Note:
 /// public struct Note : IBinarySerializable { public uint NoteMask; public uint NoteFlags; public uint Hash;
 /// public float Time; public byte StringIndex; public byte FretId; public byte AnchorFretId; public byte AnchorWidth;
@@ -178,7 +357,7 @@ impl BinarySerializable for Fingerprint {
 /// public byte SlideTo; public byte SlideUnpitchTo; public byte LeftHand; public byte Tap;
 /// public byte PickDirection; public byte Slap; public byte Pluck; public short Vibrato;
 /// public float Sustain; public float MaxBend; public BendData32[] BendData; }
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Note {
     pub note_mask: u32,
     pub note_flags: u32,
@@ -210,7 +389,7 @@ pub struct Note {
 }
 
 impl BinarySerializable for Note {
-    fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+    fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
         let note_mask = reader.read_u32::<LittleEndian>()?;
         let note_flags = reader.read_u32::<LittleEndian>()?;
         let hash = reader.read_u32::<LittleEndian>()?;
@@ -224,8 +403,8 @@ impl BinarySerializable for Note {
         let phrase_id = reader.read_i32::<LittleEndian>()?;
         let phrase_iteration_id = reader.read_i32::<LittleEndian>()?;
         let mut finger_print_id = [0i16; 2];
-        for i in 0..2 {
-            finger_print_id[i] = reader.read_i16::<LittleEndian>()?;
+        for id in finger_print_id.iter_mut() {
+            *id = reader.read_i16::<LittleEndian>()?;
         }
         let next_iter_note = reader.read_i16::<LittleEndian>()?;
         let prev_iter_note = reader.read_i16::<LittleEndian>()?;
@@ -241,8 +420,8 @@ impl BinarySerializable for Note {
         let sustain = reader.read_f32::<LittleEndian>()?;
         let max_bend = reader.read_f32::<LittleEndian>()?;
         // For this example, assume the number of BendData32 entries is stored as an i32.
-        let bend_data_count = reader.read_i32::<LittleEndian>()? as usize;
-        let mut bend_data = Vec::with_capacity(bend_data_count);
+        let bend_data_count = reader.read_i32::<LittleEndian>()?.max(0) as usize;
+        let mut bend_data = Vec::with_capacity(bend_data_count.min(MAX_PREALLOCATED));
         for _ in 0..bend_data_count {
             bend_data.push(BendData32::read_from(reader)?);
         }
@@ -278,6 +457,13 @@ impl BinarySerializable for Note {
     }
 }
 
+impl Note {
+    /// Technique flags of the note.
+    pub fn mask(&self) -> NoteMask {
+        NoteMask::from_bits_truncate(self.note_mask)
+    }
+}
+
 /// C# BendData32:
 /// public struct BendData32 { public float Time; public float Step; public short Unk3_0;
 /// public byte Unk4_0; public byte Unk5; }
@@ -291,7 +477,7 @@ pub struct BendData32 {
 }
 
 impl BinarySerializable for BendData32 {
-    fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+    fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
         let time = reader.read_f32::<LittleEndian>()?;
         let step = reader.read_f32::<LittleEndian>()?;
         let unk3_0 = reader.read_i16::<LittleEndian>()?;
@@ -317,7 +503,7 @@ pub struct BendData {
 }
 
 impl BinarySerializable for BendData {
-    fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+    fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
         let mut arr = [BendData32 {
             time: 0.0,
             step: 0.0,
@@ -325,8 +511,8 @@ impl BinarySerializable for BendData {
             unk4_0: 0,
             unk5: 0,
         }; 32];
-        for i in 0..32 {
-            arr[i] = BendData32::read_from(reader)?;
+        for bend in arr.iter_mut() {
+            *bend = BendData32::read_from(reader)?;
         }
         let used_count = reader.read_i32::<LittleEndian>()?;
         Ok(BendData {
@@ -349,7 +535,7 @@ pub struct Bpm {
 }
 
 impl BinarySerializable for Bpm {
-    fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+    fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
         let time = reader.read_f32::<LittleEndian>()?;
         let measure = reader.read_i16::<LittleEndian>()?;
         let beat = reader.read_i16::<LittleEndian>()?;
@@ -380,17 +566,21 @@ pub struct Chord {
 }
 
 impl BinarySerializable for Chord {
-    fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+    fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
+        Self::read_with(reader, &mut ParseContext::default())
+    }
+
+    fn read_with<R: Read>(reader: &mut R, context: &mut ParseContext) -> Result<Self> {
         let mask = reader.read_u32::<LittleEndian>()?;
         let mut frets = [0u8; 6];
         reader.read_exact(&mut frets)?;
         let mut fingers = [0u8; 6];
         reader.read_exact(&mut fingers)?;
         let mut notes = [0i32; 6];
-        for i in 0..6 {
-            notes[i] = reader.read_i32::<LittleEndian>()?;
+        for note in notes.iter_mut() {
+            *note = reader.read_i32::<LittleEndian>()?;
         }
-        let name = read_fixed_string(reader, 32)?;
+        let name = context.read_string(reader, 32)?;
         Ok(Chord {
             mask,
             frets,
@@ -417,10 +607,10 @@ pub struct ChordNotes {
 }
 
 impl BinarySerializable for ChordNotes {
-    fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+    fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
         let mut note_mask = [0i32; 6];
-        for i in 0..6 {
-            note_mask[i] = reader.read_i32::<LittleEndian>()?;
+        for mask in note_mask.iter_mut() {
+            *mask = reader.read_i32::<LittleEndian>()?;
         }
         let mut bend_data = [BendData {
             bend_data: [BendData32 {
@@ -432,16 +622,16 @@ impl BinarySerializable for ChordNotes {
             }; 32],
             used_count: 0,
         }; 6];
-        for i in 0..6 {
-            bend_data[i] = BendData::read_from(reader)?;
+        for bend in bend_data.iter_mut() {
+            *bend = BendData::read_from(reader)?;
         }
         let mut slide_to = [0u8; 6];
         reader.read_exact(&mut slide_to)?;
         let mut slide_unpitch_to = [0u8; 6];
         reader.read_exact(&mut slide_unpitch_to)?;
         let mut vibrato = [0i16; 6];
-        for i in 0..6 {
-            vibrato[i] = reader.read_i16::<LittleEndian>()?;
+        for v in vibrato.iter_mut() {
+            *v = reader.read_i16::<LittleEndian>()?;
         }
         Ok(ChordNotes {
             note_mask,
@@ -462,7 +652,7 @@ pub struct Dna {
 }
 
 impl BinarySerializable for Dna {
-    fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+    fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
         let time = reader.read_f32::<LittleEndian>()?;
         let dna_id = reader.read_i32::<LittleEndian>()?;
         Ok(Dna { time, dna_id })
@@ -478,9 +668,13 @@ pub struct Event {
 }
 
 impl BinarySerializable for Event {
-    fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+    fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
+        Self::read_with(reader, &mut ParseContext::default())
+    }
+
+    fn read_with<R: Read>(reader: &mut R, context: &mut ParseContext) -> Result<Self> {
         let time = reader.read_f32::<LittleEndian>()?;
-        let event_name = read_fixed_string(reader, 256)?;
+        let event_name = context.read_string(reader, 256)?;
         Ok(Event { time, event_name })
     }
 }
@@ -511,7 +705,11 @@ pub struct Metadata {
 }
 
 impl BinarySerializable for Metadata {
-    fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+    fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
+        Self::read_with(reader, &mut ParseContext::default())
+    }
+
+    fn read_with<R: Read>(reader: &mut R, context: &mut ParseContext) -> Result<Self> {
         let max_score = reader.read_f64::<LittleEndian>()?;
         let max_notes_and_chords = reader.read_f64::<LittleEndian>()?;
         let max_notes_and_chords_real = reader.read_f64::<LittleEndian>()?;
@@ -519,11 +717,11 @@ impl BinarySerializable for Metadata {
         let first_beat_length = reader.read_f32::<LittleEndian>()?;
         let start_time = reader.read_f32::<LittleEndian>()?;
         let capo_fret_id = reader.read_u8()?;
-        let last_conversion_date_time = read_fixed_string(reader, 32)?;
+        let last_conversion_date_time = context.read_string(reader, 32)?;
         let part = reader.read_i16::<LittleEndian>()?;
         let song_length = reader.read_f32::<LittleEndian>()?;
         let string_count = reader.read_i32::<LittleEndian>()?;
-        let mut tuning = Vec::with_capacity(string_count as usize);
+        let mut tuning = Vec::with_capacity((string_count.max(0) as usize).min(MAX_PREALLOCATED));
         for _ in 0..string_count {
             tuning.push(reader.read_i16::<LittleEndian>()?);
         }
@@ -561,10 +759,10 @@ pub struct NLinkedDifficulty {
 }
 
 impl BinarySerializable for NLinkedDifficulty {
-    fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+    fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
         let level_break = reader.read_i32::<LittleEndian>()?;
         let phrase_count = reader.read_i32::<LittleEndian>()?;
-        let nld_phrase = read_vec_of_i32(reader, phrase_count as usize)?;
+        let nld_phrase = read_vec_of_i32(reader, phrase_count)?;
         Ok(NLinkedDifficulty {
             level_break,
             phrase_count,
@@ -588,14 +786,18 @@ pub struct Phrase {
 }
 
 impl BinarySerializable for Phrase {
-    fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+    fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
+        Self::read_with(reader, &mut ParseContext::default())
+    }
+
+    fn read_with<R: Read>(reader: &mut R, context: &mut ParseContext) -> Result<Self> {
         let solo = reader.read_u8()?;
         let disparity = reader.read_u8()?;
         let ignore = reader.read_u8()?;
         let padding = reader.read_u8()?;
         let max_difficulty = reader.read_i32::<LittleEndian>()?;
         let phrase_iteration_links = reader.read_i32::<LittleEndian>()?;
-        let name = read_fixed_string(reader, 32)?;
+        let name = context.read_string(reader, 32)?;
         Ok(Phrase {
             solo,
             disparity,
@@ -623,7 +825,7 @@ pub struct PhraseExtraInfoByLevel {
 }
 
 impl BinarySerializable for PhraseExtraInfoByLevel {
-    fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+    fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
         let phrase_id = reader.read_i32::<LittleEndian>()?;
         let difficulty = reader.read_i32::<LittleEndian>()?;
         let empty = reader.read_i32::<LittleEndian>()?;
@@ -653,13 +855,13 @@ pub struct PhraseIteration {
 }
 
 impl BinarySerializable for PhraseIteration {
-    fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+    fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
         let phrase_id = reader.read_i32::<LittleEndian>()?;
         let start_time = reader.read_f32::<LittleEndian>()?;
         let next_phrase_time = reader.read_f32::<LittleEndian>()?;
         let mut difficulty = [0i32; 3];
-        for i in 0..3 {
-            difficulty[i] = reader.read_i32::<LittleEndian>()?;
+        for d in difficulty.iter_mut() {
+            *d = reader.read_i32::<LittleEndian>()?;
         }
         Ok(PhraseIteration {
             phrase_id,
@@ -686,14 +888,18 @@ pub struct Section {
 }
 
 impl BinarySerializable for Section {
-    fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
-        let name = read_fixed_string(reader, 32)?;
+    fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
+        Self::read_with(reader, &mut ParseContext::default())
+    }
+
+    fn read_with<R: Read>(reader: &mut R, context: &mut ParseContext) -> Result<Self> {
+        let name = context.read_string(reader, 32)?;
         let number = reader.read_i32::<LittleEndian>()?;
         let start_time = reader.read_f32::<LittleEndian>()?;
         let end_time = reader.read_f32::<LittleEndian>()?;
         let start_phrase_iteration_id = reader.read_i32::<LittleEndian>()?;
         let end_phrase_iteration_id = reader.read_i32::<LittleEndian>()?;
-        let string_mask = read_fixed_string(reader, 36)?;
+        let string_mask = context.read_string(reader, 36)?;
         Ok(Section {
             name,
             number,
@@ -717,7 +923,7 @@ pub struct Rect {
 }
 
 impl BinarySerializable for Rect {
-    fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+    fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
         let y_min = reader.read_f32::<LittleEndian>()?;
         let x_min = reader.read_f32::<LittleEndian>()?;
         let y_max = reader.read_f32::<LittleEndian>()?;
@@ -742,8 +948,12 @@ pub struct SymbolDefinition {
 }
 
 impl BinarySerializable for SymbolDefinition {
-    fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
-        let text = read_fixed_string(reader, 12)?;
+    fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
+        Self::read_with(reader, &mut ParseContext::default())
+    }
+
+    fn read_with<R: Read>(reader: &mut R, context: &mut ParseContext) -> Result<Self> {
+        let text = context.read_string(reader, 12)?;
         let rect_outter = Rect::read_from(reader)?;
         let rect_inner = Rect::read_from(reader)?;
         Ok(SymbolDefinition {
@@ -770,7 +980,7 @@ pub struct SymbolsHeader {
 }
 
 impl BinarySerializable for SymbolsHeader {
-    fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+    fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
         let unk1 = reader.read_i32::<LittleEndian>()?;
         let unk2 = reader.read_i32::<LittleEndian>()?;
         let unk3 = reader.read_i32::<LittleEndian>()?;
@@ -805,8 +1015,12 @@ pub struct SymbolsTexture {
 }
 
 impl BinarySerializable for SymbolsTexture {
-    fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
-        let font = read_fixed_string(reader, 128)?;
+    fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
+        Self::read_with(reader, &mut ParseContext::default())
+    }
+
+    fn read_with<R: Read>(reader: &mut R, context: &mut ParseContext) -> Result<Self> {
+        let font = context.read_string(reader, 128)?;
         let fontpath_length = reader.read_i32::<LittleEndian>()?;
         let unk1_0 = reader.read_i32::<LittleEndian>()?;
         let width = reader.read_i32::<LittleEndian>()?;
@@ -830,7 +1044,7 @@ pub struct Tone {
 }
 
 impl BinarySerializable for Tone {
-    fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+    fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
         let time = reader.read_f32::<LittleEndian>()?;
         let tone_id = reader.read_i32::<LittleEndian>()?;
         Ok(Tone { time, tone_id })
@@ -840,7 +1054,7 @@ impl BinarySerializable for Tone {
 /// C# Vocal:
 /// public struct Vocal { public float Time; public int Note; public float Length;
 /// [MarshalAs(UnmanagedType.ByValTStr, SizeConst = 48)] public string Lyric; }
-#[derive(Debug, Serialize)]
+#[derive(Debug)]
 pub struct Vocal {
     pub time: f32,
     pub note: i32,
@@ -849,11 +1063,15 @@ pub struct Vocal {
 }
 
 impl BinarySerializable for Vocal {
-    fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+    fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
+        Self::read_with(reader, &mut ParseContext::default())
+    }
+
+    fn read_with<R: Read>(reader: &mut R, context: &mut ParseContext) -> Result<Self> {
         let time = reader.read_f32::<LittleEndian>()?;
         let note = reader.read_i32::<LittleEndian>()?;
         let length = reader.read_f32::<LittleEndian>()?;
-        let lyric = read_fixed_string(reader, 48)?;
+        let lyric = context.read_string(reader, 48)?;
         Ok(Vocal {
             time,
             note,
@@ -863,6 +1081,40 @@ impl BinarySerializable for Vocal {
     }
 }
 
+/// Serialized with the stored lyric, plus its `text` and `ruby` reading for syllables
+/// carrying furigana (see `lyrics::split_lyric`).
+impl Serialize for Vocal {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let parts = self.parts();
+        let mut state = serializer.serialize_struct("Vocal", if parts.ruby.is_some() { 6 } else { 4 })?;
+        state.serialize_field("time", &self.time)?;
+        state.serialize_field("note", &self.note)?;
+        state.serialize_field("length", &self.length)?;
+        state.serialize_field("lyric", &self.lyric)?;
+        if let Some(ruby) = &parts.ruby {
+            state.serialize_field("text", &parts.text)?;
+            state.serialize_field("ruby", ruby)?;
+        }
+        state.end()
+    }
+}
+
+impl Vocal {
+    /// The lyric split into its text, furigana reading and markers.
+    pub fn parts(&self) -> crate::lyrics::LyricParts {
+        crate::lyrics::split_lyric(&self.lyric)
+    }
+
+    /// Writes the vocal in its SNG layout (the inverse of `read_from`).
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_f32::<LittleEndian>(self.time)?;
+        writer.write_i32::<LittleEndian>(self.note)?;
+        writer.write_f32::<LittleEndian>(self.length)?;
+        write_fixed_string(writer, &self.lyric, 48)
+    }
+}
+
 /// C# Arrangement:
 /// public struct Arrangement : IBinarySerializable { public int Difficulty;
 /// public Anchor[] Anchors; public AnchorExtension[] AnchorExtensions;
@@ -870,7 +1122,7 @@ impl BinarySerializable for Vocal {
 /// public Note[] Notes; public int PhraseCount; public float[] AverageNotesPerIteration;
 /// public int PhraseIterationCount1; public int[] NotesInIteration1;
 /// public int PhraseIterationCount2; public int[] NotesInIteration2; }
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Arrangement {
     pub difficulty: i32,
     pub anchors: Vec<Anchor>,
@@ -887,7 +1139,7 @@ pub struct Arrangement {
 }
 
 impl BinarySerializable for Arrangement {
-    fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+    fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
         let difficulty = reader.read_i32::<LittleEndian>()?;
         let anchors = read_vec(reader, Anchor::read_from)?;
         let anchor_extensions = read_vec(reader, AnchorExtension::read_from)?;
@@ -895,11 +1147,11 @@ impl BinarySerializable for Arrangement {
         let fingerprints2 = read_vec(reader, Fingerprint::read_from)?;
         let notes = read_vec(reader, Note::read_from)?;
         let phrase_count = reader.read_i32::<LittleEndian>()?;
-        let average_notes_per_iteration = read_vec_of_f32(reader, phrase_count as usize)?;
+        let average_notes_per_iteration = read_vec_of_f32(reader, phrase_count)?;
         let phrase_iteration_count1 = reader.read_i32::<LittleEndian>()?;
-        let notes_in_iteration1 = read_vec_of_i32(reader, phrase_iteration_count1 as usize)?;
+        let notes_in_iteration1 = read_vec_of_i32(reader, phrase_iteration_count1)?;
         let phrase_iteration_count2 = reader.read_i32::<LittleEndian>()?;
-        let notes_in_iteration2 = read_vec_of_i32(reader, phrase_iteration_count2 as usize)?;
+        let notes_in_iteration2 = read_vec_of_i32(reader, phrase_iteration_count2)?;
         Ok(Arrangement {
             difficulty,
             anchors,
@@ -0,0 +1,163 @@
+//! Path dictionaries for archives whose entries are only known by hash.
+//!
+//! The TOC stores the MD5 of every entry path, and Rocksmith packages list the paths
+//! themselves in the NamesBlock. PSARCs of other games often leave the names out, so only
+//! the hashes remain. A dictionary of known paths (a text file with one path per line, as
+//! collected from other packages or game executables) is hashed and matched against the
+//! TOC to give those entries their names back.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Cursor};
+use std::path::Path;
+use serde::Serialize;
+
+use crate::md5::path_hash;
+use crate::psarc::{PsarcFile, PsarcTOCEntry};
+
+/// Hash of the NamesBlock entry, which has no path of its own.
+const NAMES_BLOCK_HASH: &str = "00000000000000000000000000000000";
+
+/// Known entry paths indexed by their TOC hash.
+#[derive(Debug, Clone, Default)]
+pub struct NameDictionary {
+    by_hash: HashMap<String, String>,
+}
+
+impl NameDictionary {
+    pub fn new() -> Self {
+        NameDictionary::default()
+    }
+
+    /// Adds a path. Archives differ in how they spell paths before hashing them, so the
+    /// path is also added lowercased, uppercased and with or without a leading `/`.
+    pub fn insert(&mut self, path: &str) {
+        let path = path.trim().replace('\\', "/");
+        if path.is_empty() {
+            return;
+        }
+        let bare = path.trim_start_matches('/');
+        for base in [bare.to_string(), bare.to_lowercase(), bare.to_uppercase()] {
+            for variant in [format!("/{}", base), base] {
+                self.by_hash.entry(path_hash(&variant)).or_insert(variant);
+            }
+        }
+        // The path as written wins over a variant of another path with the same hash.
+        self.by_hash.insert(path_hash(&path), path);
+    }
+
+    /// Reads a dictionary file: one path per line, blank lines and lines starting with
+    /// `#` are skipped.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut dictionary = NameDictionary::new();
+        dictionary.load_file(path)?;
+        Ok(dictionary)
+    }
+
+    /// Adds the paths of a dictionary file to this one.
+    pub fn load_file(&mut self, path: impl AsRef<Path>) -> io::Result<&mut Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .map_err(|err| io::Error::new(err.kind(), format!("Cannot read name dictionary {:?}: {}", path, err)))?;
+        for line in text.lines().filter(|line| !line.trim_start().starts_with('#')) {
+            self.insert(line);
+        }
+        tracing::debug!("Name dictionary {:?}: {} hashes", path, self.len());
+        Ok(self)
+    }
+
+    /// Adds the paths of another dictionary. Paths already known are kept.
+    pub fn merge(&mut self, other: &NameDictionary) {
+        for (hash, path) in &other.by_hash {
+            self.by_hash.entry(hash.clone()).or_insert_with(|| path.clone());
+        }
+    }
+
+    /// Number of hashes known, variants included.
+    pub fn len(&self) -> usize {
+        self.by_hash.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_hash.is_empty()
+    }
+
+    /// Path whose hash is `hash` (uppercase or lowercase hex).
+    pub fn lookup(&self, hash: &str) -> Option<&str> {
+        self.by_hash.get(&hash.to_uppercase()).map(String::as_str)
+    }
+}
+
+/// How many entries of an archive a dictionary named.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct NameResolution {
+    /// Entries in the TOC.
+    pub entries: usize,
+    /// Entries without a path matching their hash before the dictionary was applied.
+    pub unnamed: usize,
+    /// Unnamed entries the dictionary found a path for.
+    pub resolved: usize,
+    /// Hashes of the entries still unnamed.
+    pub unresolved: Vec<String>,
+}
+
+impl NameResolution {
+    /// Share of the unnamed entries that were resolved, 1.0 when none were unnamed.
+    pub fn resolved_ratio(&self) -> f64 {
+        if self.unnamed == 0 {
+            1.0
+        } else {
+            self.resolved as f64 / self.unnamed as f64
+        }
+    }
+}
+
+/// Whether the entry has a path its TOC hash confirms (or is the NamesBlock).
+fn is_named(entry: &PsarcTOCEntry) -> bool {
+    match &entry.path {
+        Some(_) if entry.hash == NAMES_BLOCK_HASH => true,
+        Some(path) => path_hash(path).eq_ignore_ascii_case(&entry.hash),
+        None => false,
+    }
+}
+
+/// Names the entries of `psarc` that have no path, or a path that does not hash to their
+/// TOC hash, from `names`. Entries the dictionary does not know keep what they had.
+pub fn resolve_names(psarc: &mut PsarcFile, names: &NameDictionary) -> NameResolution {
+    let mut resolution = NameResolution { entries: psarc.toc.entries.len(), ..NameResolution::default() };
+    for entry in psarc.toc.entries.iter_mut().filter(|entry| !is_named(entry)) {
+        resolution.unnamed += 1;
+        match names.lookup(&entry.hash) {
+            Some(path) => {
+                entry.path = Some(path.to_string());
+                resolution.resolved += 1;
+            }
+            None => resolution.unresolved.push(entry.hash.clone()),
+        }
+    }
+    psarc.reindex();
+    tracing::info!(
+        "Resolved {} of {} unnamed entries ({} in the archive)",
+        resolution.resolved,
+        resolution.unnamed,
+        resolution.entries
+    );
+    resolution
+}
+
+/// Opens an archive and names its entries from `names`. Unlike `PsarcFile::open_path`, a
+/// first entry that is not a NamesBlock is not an error: the entries are left unnamed
+/// for the dictionary to resolve.
+pub fn open_path_with_names(path: impl AsRef<Path>, names: &NameDictionary) -> io::Result<(PsarcFile, NameResolution)> {
+    let path = path.as_ref();
+    let data = fs::read(path)?;
+    let mut psarc = PsarcFile::open(&mut Cursor::new(&data))?;
+    if let Err(err) = psarc.read_manifest() {
+        tracing::warn!("No readable names block in {:?} ({}); entries are named by hash only", path, err);
+        for entry in &mut psarc.toc.entries {
+            entry.path = None;
+        }
+    }
+    let resolution = resolve_names(&mut psarc, names);
+    Ok((psarc, resolution))
+}
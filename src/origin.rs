@@ -0,0 +1,132 @@
+//! Telling official DLC from custom songs (CDLC).
+//!
+//! Nothing in a package states where it comes from, so the classification weighs a few
+//! signals:
+//! - A `toolkit.version` entry, written by the Custom Song Toolkit and DLC Builder only.
+//! - The name of a custom song tool in the manifests, xblocks or aggregate graph.
+//! - The Steam app id: the tools default to `248750` (Cherub Rock), official DLC carry
+//!   the id of their own store item.
+//!
+//! The first two are conclusive; the app id alone is a hint, since Cherub Rock itself is
+//! official DLC with that id.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Result;
+use crate::psarc::PsarcFile;
+use crate::toolkit::{ToolkitInfo, TOOLKIT_VERSION_FILE_NAME};
+
+/// App id the custom song tools give packages unless told otherwise.
+pub const TOOLKIT_DEFAULT_APP_ID: &str = "248750";
+
+/// App id of the base game, used by on-disc songs rather than DLC.
+const BASE_GAME_APP_ID: &str = "221680";
+
+/// Names of custom song tools found in the metadata they write, lowercase.
+const TOOL_SIGNATURES: [&str; 5] = ["rocksmith custom song toolkit", "rocksmithtoolkit", "customsforge", "dlc builder", "dlcbuilder"];
+
+/// Where a package comes from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PackageOrigin {
+    Official,
+    Custom,
+    #[default]
+    Unknown,
+}
+
+impl PackageOrigin {
+    pub fn name(self) -> &'static str {
+        match self {
+            PackageOrigin::Official => "official",
+            PackageOrigin::Custom => "custom",
+            PackageOrigin::Unknown => "unknown",
+        }
+    }
+}
+
+impl fmt::Display for PackageOrigin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl std::str::FromStr for PackageOrigin {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "official" | "odlc" => Ok(PackageOrigin::Official),
+            "custom" | "cdlc" => Ok(PackageOrigin::Custom),
+            "unknown" => Ok(PackageOrigin::Unknown),
+            other => Err(format!("Unknown package origin {:?} (expected official, custom or unknown)", other)),
+        }
+    }
+}
+
+/// The classification of a package and what it was based on.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OriginReport {
+    pub origin: PackageOrigin,
+    /// The signals found, such as `toolkit.version entry`.
+    pub signals: Vec<String>,
+    /// The `toolkit.version` entry, when the package has one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub toolkit: Option<ToolkitInfo>,
+}
+
+/// True for the metadata entries custom song tools write their name into.
+fn is_metadata(path: &str) -> bool {
+    [".json", ".hsan", ".xblock", ".nt"].iter().any(|ext| path.ends_with(ext))
+}
+
+/// Classifies `psarc` as official or custom. Metadata entries that cannot be read are
+/// skipped with a warning.
+pub fn classify_package(psarc: &PsarcFile) -> Result<OriginReport> {
+    let mut report = OriginReport::default();
+    let mut custom = false;
+    if let Some(toolkit) = psarc.toolkit_info()? {
+        report.signals.push(format!("{} entry", TOOLKIT_VERSION_FILE_NAME));
+        report.toolkit = Some(toolkit).filter(|t| !t.is_empty());
+        custom = true;
+    }
+    let metadata = psarc.toc.entries.iter().filter_map(|e| Some((e, e.path.as_deref().filter(|p| is_metadata(p))?)));
+    for (entry, path) in metadata {
+        let text = match psarc.inflate_entry_data(entry) {
+            Ok(data) => String::from_utf8_lossy(&data).to_lowercase(),
+            Err(err) => {
+                tracing::warn!("Could not read {}: {}", path, err);
+                continue;
+            }
+        };
+        if let Some(signature) = TOOL_SIGNATURES.iter().find(|s| text.contains(*s)) {
+            report.signals.push(format!("\"{}\" in {}", signature, path));
+            custom = true;
+            break;
+        }
+    }
+    let app_id = psarc.app_id()?;
+    let official_app_id = match app_id.as_deref() {
+        Some(TOOLKIT_DEFAULT_APP_ID) => {
+            report.signals.push(format!("toolkit default app id {}", TOOLKIT_DEFAULT_APP_ID));
+            false
+        }
+        Some(BASE_GAME_APP_ID) | Some("") | None => false,
+        Some(id) => {
+            report.signals.push(format!("store app id {}", id));
+            true
+        }
+    };
+    report.origin = if custom {
+        PackageOrigin::Custom
+    } else if official_app_id {
+        PackageOrigin::Official
+    } else if app_id.as_deref() == Some(TOOLKIT_DEFAULT_APP_ID) {
+        PackageOrigin::Custom
+    } else {
+        PackageOrigin::Unknown
+    };
+    Ok(report)
+}
@@ -0,0 +1,210 @@
+//! In-place updates of an archive on disk.
+//!
+//! Replacing an entry through `PsarcWriter` rewrites the whole archive. For the common
+//! edit of one entry, `replace_entry_in_place` appends the new blocks at the end of the
+//! file and rewrites only the header and the TOC; the old blocks stay behind as dead
+//! space. The entry is found by the hash of its path, so the NamesBlock is neither read
+//! nor changed.
+//!
+//! When the new content needs more blocks than the old one, its block sizes go at the end
+//! of the block size table and the TOC grows into the data region: the entries stored
+//! right after the TOC are first moved to the end of the file. `compact_path` rewrites an
+//! archive without its dead space.
+
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use byteorder::{BigEndian, WriteBytesExt};
+use serde::Serialize;
+
+use crate::psarc::{normalize_entry_path, PsarcFile, PsarcFileHeader, PsarcTOC, APP_ID_FILE_NAME, TOC_ENTRY_SIZE};
+use crate::repack::{check_app_id, strip_archive};
+use crate::writer::{encrypt_toc_bytes, store_blocks, PsarcWriter};
+
+/// Position of the TOC size in the header.
+const TOC_SIZE_OFFSET: u64 = 12;
+
+/// Outcome of `replace_entry_in_place`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct InPlaceReport {
+    /// Stored bytes of the new content, appended at the end of the file.
+    pub appended: u64,
+    /// Entries moved to the end of the file to make room for the grown TOC.
+    pub relocated: usize,
+    /// Bytes no entry uses any more, reclaimed by `compact_path`.
+    pub dead_bytes: u64,
+}
+
+/// Replaces the content of the entry at `entry_path` in the archive at `path` without
+/// rewriting the archive (see the module documentation). The entry is looked up by the
+/// hash of `entry_path` as given and normalized (see `normalize_entry_path`).
+///
+/// The new blocks, and any entry moved, are written and synced before the TOC, so an
+/// interrupted update leaves the archive as it was plus unused bytes at its end.
+pub fn replace_entry_in_place(path: &Path, entry_path: &str, data: &[u8]) -> io::Result<InPlaceReport> {
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let header = PsarcFileHeader::read_from(&mut file)?;
+    let mut toc = PsarcTOC::read_from(&mut file, &header)?;
+    let block_size = header.block_size;
+    // The block size table is written back in the width it was read in.
+    let width = PsarcTOC::table_width(&header, &toc.entries)?;
+    if header.toc_entry_size != TOC_ENTRY_SIZE {
+        let message = format!("TOC entries are {} bytes, not {}; compact the archive first", header.toc_entry_size, TOC_ENTRY_SIZE);
+        return Err(io::Error::new(io::ErrorKind::Unsupported, message));
+    }
+
+    let hashes = [PsarcFile::path_hash(entry_path), PsarcFile::path_hash(&normalize_entry_path(entry_path))];
+    let target = toc
+        .entries
+        .iter()
+        .skip(1)
+        .position(|entry| hashes.contains(&entry.hash))
+        .map(|position| position + 1)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("No entry {} in {:?}", entry_path, path)))?;
+
+    // The new block sizes take the entry's slots of the block size table when they fit,
+    // and go at the end of the table otherwise.
+    let (block_sizes, stored) = store_blocks(data, block_size, true)?;
+    if width < 4 && block_sizes.iter().any(|&size| size >> (width * 8) != 0) {
+        let message = format!("The new content has blocks too large for the {} byte block size table", width);
+        return Err(io::Error::new(io::ErrorKind::Unsupported, message));
+    }
+    let old_blocks = toc.entries[target].length.div_ceil(block_size as u64) as usize;
+    let start = if block_sizes.len() <= old_blocks {
+        toc.entries[target].start_block as usize
+    } else {
+        toc.zip_block_sizes.len()
+    };
+    let table_end = start + block_sizes.len();
+    if table_end > toc.zip_block_sizes.len() {
+        toc.zip_block_sizes.resize(table_end, 0);
+    }
+    toc.zip_block_sizes[start..table_end].copy_from_slice(&block_sizes);
+    // Slots past the last block in use are dropped: a table exactly as long as its entries
+    // need is what tells its width apart (see `PsarcTOC::table_width`).
+    let in_use = toc
+        .entries
+        .iter()
+        .enumerate()
+        .filter(|&(index, _)| index != target)
+        .map(|(_, entry)| entry.start_block as usize + entry.length.div_ceil(block_size as u64) as usize)
+        .fold(table_end, usize::max);
+    toc.zip_block_sizes.truncate(in_use);
+    let toc_size = PsarcTOC::byte_size_in(toc.entries.len(), toc.zip_block_sizes.len(), width)?;
+
+    // Entries stored where the TOC now ends move to the end of the file; entries sharing
+    // their data are moved once.
+    let file_length = file.seek(SeekFrom::End(0))?;
+    let mut end = file_length;
+    let mut moved: HashMap<u64, u64> = HashMap::new();
+    let mut report = InPlaceReport::default();
+    for index in (0..toc.entries.len()).filter(|&index| index != target) {
+        let entry = &toc.entries[index];
+        let length = toc.stored_length(entry, block_size).min(file_length.saturating_sub(entry.offset));
+        if length == 0 || entry.offset >= toc_size as u64 {
+            continue;
+        }
+        let offset = match moved.get(&entry.offset) {
+            Some(&offset) => offset,
+            None => {
+                let mut blocks = vec![0u8; length as usize];
+                file.seek(SeekFrom::Start(entry.offset))?;
+                file.read_exact(&mut blocks)?;
+                file.seek(SeekFrom::Start(end))?;
+                file.write_all(&blocks)?;
+                moved.insert(entry.offset, end);
+                end += length;
+                end - length
+            }
+        };
+        toc.entries[index].offset = offset;
+        report.relocated += 1;
+    }
+
+    file.seek(SeekFrom::Start(end))?;
+    file.write_all(&stored)?;
+    let entry = &mut toc.entries[target];
+    entry.start_block = u32::try_from(start)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "More blocks than the TOC can number"))?;
+    entry.length = data.len() as u64;
+    entry.offset = end;
+    report.appended = stored.len() as u64;
+    end += report.appended;
+    file.sync_data()?;
+
+    let mut toc_bytes = toc.to_bytes_in(width)?;
+    if toc.encrypted {
+        encrypt_toc_bytes(&mut toc_bytes)?;
+    }
+    file.seek(SeekFrom::Start(TOC_SIZE_OFFSET))?;
+    file.write_u32::<BigEndian>(toc_size)?;
+    file.seek(SeekFrom::Start(header.toc_offset))?;
+    file.write_all(&toc_bytes)?;
+    file.sync_all()?;
+
+    report.dead_bytes = dead_bytes_of(&toc, block_size, toc_size, end);
+    tracing::info!(
+        "Replaced {} in {:?}: {} bytes appended, {} entries moved, {} dead bytes",
+        entry_path,
+        path,
+        report.appended,
+        report.relocated,
+        report.dead_bytes
+    );
+    Ok(report)
+}
+
+/// Sets the `appid.appid` entry at the root of the archive at `path` to `app_id` with
+/// `replace_entry_in_place`. Fails with `ErrorKind::NotFound` when the archive has no such
+/// entry; `repack::set_app_id` adds one.
+pub fn set_app_id_in_place(path: &Path, app_id: &str) -> io::Result<InPlaceReport> {
+    check_app_id(app_id)?;
+    replace_entry_in_place(path, APP_ID_FILE_NAME, app_id.as_bytes())
+}
+
+/// Bytes of `archive` past its TOC that no entry uses, left behind by in-place updates.
+pub fn dead_bytes(archive: &PsarcFile) -> u64 {
+    dead_bytes_of(&archive.toc, archive.header.block_size, archive.header.toc_size, archive.data.len() as u64)
+}
+
+fn dead_bytes_of(toc: &PsarcTOC, block_size: u32, toc_size: u32, file_length: u64) -> u64 {
+    let mut extents: Vec<(u64, u64)> = toc
+        .entries
+        .iter()
+        .map(|entry| (entry.offset, (entry.offset + toc.stored_length(entry, block_size)).min(file_length)))
+        .filter(|(start, end)| end > start)
+        .collect();
+    extents.sort_unstable();
+    let mut live = 0;
+    let mut covered = 0;
+    for (start, end) in extents {
+        let start = start.max(covered);
+        if end > start {
+            live += end - start;
+            covered = end;
+        }
+    }
+    file_length.saturating_sub(toc_size as u64).saturating_sub(live)
+}
+
+/// Rebuilds `archive` without dead space: every entry is copied in TOC order, its stored
+/// blocks verbatim, behind a TOC laid out anew.
+pub fn compact_archive(archive: &PsarcFile) -> io::Result<PsarcWriter> {
+    Ok(strip_archive(archive, |_| false)?.0)
+}
+
+/// Compacts the archive at `input` into `output`, or over `input` itself when `output` is
+/// `None`, through a temporary file renamed into place. Returns the bytes reclaimed.
+pub fn compact_path(input: &Path, output: Option<&Path>) -> io::Result<u64> {
+    let archive = PsarcFile::open_path(input)?;
+    let writer = compact_archive(&archive)?;
+    let output = output.unwrap_or(input);
+    let mut temp = output.as_os_str().to_owned();
+    temp.push(".tmp");
+    writer.write_path(&temp)?;
+    fs::rename(&temp, output)?;
+    let reclaimed = (archive.data.len() as u64).saturating_sub(fs::metadata(output)?.len());
+    tracing::info!("Compacted {:?} into {:?}: {} bytes reclaimed", input, output, reclaimed);
+    Ok(reclaimed)
+}
@@ -0,0 +1,68 @@
+//! Sounding pitch of charted notes, as MIDI note numbers.
+//!
+//! The fretted pitch is the open string (standard tuning plus the chart's offsets) plus the
+//! fret. Harmonics sound elsewhere: a natural harmonic touched over a node sounds the
+//! partial of that node, and a pinch harmonic is rendered a fixed interval above the
+//! fretted note, the partial most players aim for.
+
+use crate::models::{Note, NoteMask};
+
+/// MIDI notes of the open strings in E standard, lowest string first.
+pub(crate) const GUITAR_STANDARD: [i32; 6] = [40, 45, 50, 55, 59, 64];
+pub(crate) const BASS_STANDARD: [i32; 4] = [28, 33, 38, 43];
+
+/// Interval above the fretted note given to pinch harmonics: an octave and a fifth.
+pub const PINCH_HARMONIC_INTERVAL: i32 = 19;
+
+/// Highest partial looked for when matching a fret to a harmonic node.
+const MAX_PARTIAL: u32 = 8;
+
+/// Largest distance, in frets, between the touched fret and a node.
+const NODE_TOLERANCE: f64 = 0.5;
+
+/// Sounding pitch of the open `string` (lowest first) under the tuning offsets of a chart.
+pub fn open_string_pitch(tuning: &[i16], string: usize, bass: bool) -> i32 {
+    let standard: &[i32] = if bass { &BASS_STANDARD } else { &GUITAR_STANDARD };
+    standard.get(string).copied().unwrap_or(64) + tuning.get(string).copied().unwrap_or(0) as i32
+}
+
+/// Semitones above the open string sounded by a natural harmonic touched at `fret`
+/// (counted from the capo). `None` when no node is close enough to the fret.
+///
+/// The nodes of partial `n` lie at `k/n` of the string; the lowest partial with a node
+/// within half a fret wins, so fret 7 gives the third partial (+19) and fret 5 the
+/// fourth (+24).
+pub fn natural_harmonic_interval(fret: u8) -> Option<i32> {
+    if fret == 0 {
+        return None;
+    }
+    (2..=MAX_PARTIAL).find_map(|partial| {
+        let on_node = (1..partial).any(|k| {
+            let node_fret = -12.0 * (1.0 - k as f64 / partial as f64).log2();
+            (node_fret - fret as f64).abs() <= NODE_TOLERANCE
+        });
+        on_node.then(|| (12.0 * (partial as f64).log2()).round() as i32)
+    })
+}
+
+/// Pitch of a string played at `fret` with the given technique flags. Fret 0 with a capo
+/// sounds at the capo; natural harmonics are measured from the capo too.
+pub fn sounding_pitch(tuning: &[i16], string: usize, fret: u8, mask: NoteMask, capo: u8, bass: bool) -> i32 {
+    let capo = if capo == u8::MAX { 0 } else { capo };
+    let open = open_string_pitch(tuning, string, bass);
+    let fretted = open + if fret == 0 { capo } else { fret } as i32;
+    if mask.contains(NoteMask::HARMONIC) {
+        if let Some(interval) = natural_harmonic_interval(fret.saturating_sub(capo)) {
+            return open + capo as i32 + interval;
+        }
+    }
+    if mask.contains(NoteMask::PINCH_HARMONIC) {
+        return fretted + PINCH_HARMONIC_INTERVAL;
+    }
+    fretted
+}
+
+/// Pitch of a single note of a chart, harmonics included.
+pub fn note_pitch(note: &Note, tuning: &[i16], capo: u8, bass: bool) -> i32 {
+    sounding_pitch(tuning, note.string_index as usize, note.fret_id, note.mask(), capo, bass)
+}
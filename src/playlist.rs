@@ -0,0 +1,205 @@
+//! Playlists and setlists built from search results (see `search`), so a curated
+//! practice set goes straight from the library index to a player or to TABS.
+//!
+//! Three formats are written:
+//! - M3U (`#EXTM3U`), one extracted audio file per song, for media players.
+//! - A JSON playlist of the same tracks with their artist, title and length.
+//! - A TABS setlist: the songs with their archive and arrangement entries, for the TABS
+//!   importer to load the charts.
+//!
+//! Audio is referenced as extracted: `<audio_dir>/<wem id>.ogg`, the wem being the full
+//! track played by the song's soundbank (see `bnk::resolve_audio_entries`).
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::psarc::PsarcFile;
+use crate::search::SearchHit;
+
+/// Version of the TABS setlist document written by `Playlist::write_setlist`.
+pub const SETLIST_VERSION: u32 = 1;
+
+/// Output format of a playlist.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaylistFormat {
+    M3u,
+    Json,
+    Setlist,
+}
+
+impl PlaylistFormat {
+    /// Format implied by a file extension: `.m3u`/`.m3u8`, `.setlist`, anything else JSON.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()).map(str::to_ascii_lowercase).as_deref() {
+            Some("m3u" | "m3u8") => PlaylistFormat::M3u,
+            Some("setlist") => PlaylistFormat::Setlist,
+            _ => PlaylistFormat::Json,
+        }
+    }
+}
+
+impl std::str::FromStr for PlaylistFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "m3u" | "m3u8" => Ok(PlaylistFormat::M3u),
+            "json" => Ok(PlaylistFormat::Json),
+            "setlist" | "tabs" => Ok(PlaylistFormat::Setlist),
+            other => Err(format!("Unknown playlist format {:?} (expected m3u, json or setlist)", other)),
+        }
+    }
+}
+
+/// One song of a playlist.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PlaylistEntry {
+    pub archive: PathBuf,
+    pub song_key: String,
+    pub artist: Option<String>,
+    pub title: Option<String>,
+    /// Length in seconds, from the longest arrangement.
+    pub song_length: f32,
+    /// Extracted audio of the full track, when it was resolved.
+    pub audio: Option<PathBuf>,
+    /// SNG entries of the arrangements that matched the query.
+    pub arrangements: Vec<String>,
+}
+
+impl PlaylistEntry {
+    /// `Artist - Title`, with the song key standing in for a missing title.
+    pub fn display_name(&self) -> String {
+        let title = self.title.as_deref().unwrap_or(&self.song_key);
+        match &self.artist {
+            Some(artist) => format!("{} - {}", artist, title),
+            None => title.to_string(),
+        }
+    }
+}
+
+/// An ordered list of songs.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Playlist {
+    pub name: String,
+    pub entries: Vec<PlaylistEntry>,
+}
+
+#[derive(Serialize)]
+struct JsonTrack<'a> {
+    path: Option<&'a Path>,
+    artist: Option<&'a str>,
+    title: Option<&'a str>,
+    duration: f32,
+}
+
+#[derive(Serialize)]
+struct Setlist<'a> {
+    format: &'static str,
+    version: u32,
+    name: &'a str,
+    songs: &'a [PlaylistEntry],
+}
+
+impl Playlist {
+    /// A playlist of the hits, in order, without audio.
+    pub fn from_hits(name: impl Into<String>, hits: &[SearchHit]) -> Self {
+        let entries = hits
+            .iter()
+            .map(|hit| PlaylistEntry {
+                archive: hit.archive.clone(),
+                song_key: hit.song_key.clone(),
+                artist: hit.artist.clone(),
+                title: hit.title.clone(),
+                song_length: hit.song_length,
+                audio: None,
+                arrangements: hit.entries.clone(),
+            })
+            .collect();
+        Playlist { name: name.into(), entries }
+    }
+
+    /// Points every entry at its extracted audio in `audio_dir`. Each archive is opened
+    /// once to resolve the wems of its songs; songs whose audio cannot be resolved keep
+    /// no audio, with a warning.
+    pub fn resolve_audio(mut self, audio_dir: &Path) -> Self {
+        let mut resolved: BTreeMap<PathBuf, BTreeMap<String, String>> = BTreeMap::new();
+        for entry in &mut self.entries {
+            let wems = resolved.entry(entry.archive.clone()).or_insert_with(|| {
+                let audio = PsarcFile::open_path(&entry.archive).and_then(|psarc| psarc.resolve_audio_entries());
+                match audio {
+                    Ok(songs) => songs
+                        .into_iter()
+                        .filter_map(|song| Some((song.song_key, song.main_wem?)))
+                        .collect(),
+                    Err(err) => {
+                        tracing::warn!("Could not resolve the audio of {:?}: {}", entry.archive, err);
+                        BTreeMap::new()
+                    }
+                }
+            });
+            let stem = wems
+                .get(&entry.song_key.to_lowercase())
+                .and_then(|wem| Path::new(wem).file_stem())
+                .map(|stem| stem.to_string_lossy().into_owned());
+            match stem {
+                Some(stem) => entry.audio = Some(audio_dir.join(format!("{}.ogg", stem))),
+                None => tracing::warn!("No audio found for {} in {:?}", entry.song_key, entry.archive),
+            }
+        }
+        self
+    }
+
+    /// Writes an extended M3U playlist. Songs without audio are skipped.
+    pub fn write_m3u<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "#EXTM3U")?;
+        if !self.name.is_empty() {
+            writeln!(writer, "#PLAYLIST:{}", self.name)?;
+        }
+        for entry in &self.entries {
+            let Some(audio) = &entry.audio else {
+                continue;
+            };
+            writeln!(writer, "#EXTINF:{},{}", entry.song_length.round() as i64, entry.display_name())?;
+            writeln!(writer, "{}", audio.display())?;
+        }
+        writer.flush()
+    }
+
+    /// Writes the tracks as a JSON array of `{path, artist, title, duration}`.
+    pub fn write_json<W: Write>(&self, writer: W) -> io::Result<()> {
+        let tracks: Vec<JsonTrack> = self
+            .entries
+            .iter()
+            .map(|entry| JsonTrack {
+                path: entry.audio.as_deref(),
+                artist: entry.artist.as_deref(),
+                title: entry.title.as_deref(),
+                duration: entry.song_length,
+            })
+            .collect();
+        serde_json::to_writer_pretty(writer, &tracks)?;
+        Ok(())
+    }
+
+    /// Writes the TABS setlist document:
+    /// `{"format": "tabs-setlist", "version": 1, "name": ..., "songs": [...]}`.
+    pub fn write_setlist<W: Write>(&self, writer: W) -> io::Result<()> {
+        let setlist = Setlist { format: "tabs-setlist", version: SETLIST_VERSION, name: &self.name, songs: &self.entries };
+        serde_json::to_writer_pretty(writer, &setlist)?;
+        Ok(())
+    }
+
+    /// Writes the playlist to `path` in `format`.
+    pub fn export(&self, path: &Path, format: PlaylistFormat) -> io::Result<()> {
+        let file = io::BufWriter::new(fs::File::create(path)?);
+        match format {
+            PlaylistFormat::M3u => self.write_m3u(file),
+            PlaylistFormat::Json => self.write_json(file),
+            PlaylistFormat::Setlist => self.write_setlist(file),
+        }
+    }
+}
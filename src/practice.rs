@@ -0,0 +1,115 @@
+//! Practice manifests: one loop per chart section, with a suggested speed ramp.
+//!
+//! Practice apps loop a section at reduced speed and raise the speed after each clean
+//! pass. The starting speed suggested here drops with the note density of the section,
+//! so fast runs start slower than sparse chord sections.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use serde::Serialize;
+
+use crate::psarc::SngAsset;
+use crate::song::Song;
+
+/// Speed step between two passes of a loop, in percent.
+const RAMP_STEP: u32 = 10;
+
+/// A practice loop over one section of an arrangement.
+#[derive(Debug, Clone, Serialize)]
+pub struct PracticeLoop {
+    /// Display label of the section, such as `Chorus 2`.
+    pub label: String,
+    /// Section name as written in the chart.
+    pub name: String,
+    pub start_ms: u32,
+    pub end_ms: u32,
+    /// Notes and chords of the full-difficulty chart within the section.
+    pub note_count: usize,
+    pub notes_per_second: f32,
+    /// Suggested playback speeds in percent, from the first pass up to 100.
+    pub speed_ramp: Vec<u32>,
+}
+
+/// Practice loops of one arrangement of a song.
+#[derive(Debug, Clone, Serialize)]
+pub struct PracticeManifest {
+    pub song_key: String,
+    pub arrangement: String,
+    pub song_length_ms: u32,
+    pub loops: Vec<PracticeLoop>,
+}
+
+/// First speed of a loop: 80% for sparse sections, down to 40% for the densest runs.
+fn starting_speed(notes_per_second: f32) -> u32 {
+    match notes_per_second {
+        n if n <= 2.0 => 80,
+        n if n <= 4.0 => 70,
+        n if n <= 6.0 => 60,
+        n if n <= 8.0 => 50,
+        _ => 40,
+    }
+}
+
+fn speed_ramp(notes_per_second: f32) -> Vec<u32> {
+    (starting_speed(notes_per_second)..=100).step_by(RAMP_STEP as usize).collect()
+}
+
+fn to_ms(seconds: f32) -> u32 {
+    (seconds.max(0.0) * 1000.0).round() as u32
+}
+
+/// One loop per section of a chart, in section order.
+pub fn practice_loops(sng: &SngAsset) -> Vec<PracticeLoop> {
+    let notes = sng.max_difficulty_notes();
+    sng.sections
+        .iter()
+        .enumerate()
+        .map(|(i, section)| {
+            let note_count = notes
+                .iter()
+                .filter(|note| note.time >= section.start_time && note.time < section.end_time)
+                .count();
+            let seconds = section.end_time - section.start_time;
+            let notes_per_second = if seconds > 0.0 { note_count as f32 / seconds } else { 0.0 };
+            PracticeLoop {
+                label: sng
+                    .section_labels
+                    .get(i)
+                    .map_or_else(|| section.name.clone(), |l| l.label.clone()),
+                name: section.name.clone(),
+                start_ms: to_ms(section.start_time),
+                end_ms: to_ms(section.end_time),
+                note_count,
+                notes_per_second,
+                speed_ramp: speed_ramp(notes_per_second),
+            }
+        })
+        .collect()
+}
+
+/// Builds the practice manifest of an arrangement (`lead`, `rhythm`, `bass`, ...).
+pub fn practice_manifest(song: &Song, arrangement: &str) -> io::Result<PracticeManifest> {
+    let found = song.arrangement(arrangement).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Song {} has no arrangement {}", song.key, arrangement),
+        )
+    })?;
+    let sng = found.sng()?;
+    Ok(PracticeManifest {
+        song_key: song.key.clone(),
+        arrangement: found.name.clone(),
+        song_length_ms: to_ms(sng.metadata.song_length),
+        loops: practice_loops(sng),
+    })
+}
+
+/// Writes the practice manifest of an arrangement as pretty-printed JSON.
+pub fn export_practice_manifest(song: &Song, arrangement: &str, output_path: &Path) -> io::Result<()> {
+    let manifest = practice_manifest(song, arrangement)?;
+    let json = serde_json::to_string_pretty(&manifest).map_err(io::Error::other)?;
+    fs::write(output_path, json)?;
+    tracing::info!("Written practice manifest to {:?}", output_path);
+    Ok(())
+}
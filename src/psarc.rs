@@ -1,19 +1,58 @@
-use std::io::{self, Read, Write, Seek, SeekFrom, Cursor};
+use std::io::{self, Read, Seek, SeekFrom, Cursor, Write};
 use byteorder::{BigEndian, LittleEndian, ReadBytesExt};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use flate2::read::DeflateDecoder;
+use std::collections::HashMap;
 use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
 use tracing;
-use serde_json;
+#[cfg(feature = "sng")]
 use serde::Serialize;
 
 
-use crate::decryptor::DecryptStream;
+use crate::content_type::ContentType;
+use crate::error::{PsarcError, Result};
+use crate::fingerprint::ArchiveFingerprint;
+use crate::glob::GlobPattern;
+use crate::bnk::{self, SongAudio};
+use crate::origin::{self, OriginReport};
+use crate::toolkit::{ToolkitInfo, TOOLKIT_VERSION_FILE_NAME};
+use crate::manifest::{self, SongManifest};
+use crate::extract::{ExtractOptions, ExtractReport, ExtractStage, OutputClaims, OutputWriter};
+#[cfg(feature = "sng")]
+use crate::extract::DifficultySelection;
+#[cfg(any(feature = "sng", feature = "audio"))]
+use crate::cache::{self, convert_cached};
+#[cfg(any(feature = "audio", feature = "image"))]
+use crate::cache::ConversionCache;
+use crate::md5::{self as digest, md5};
+#[cfg(feature = "sng")]
+use crate::md5::Md5;
+#[cfg(feature = "crypto")]
+use crate::decryptor::{CryptoKeys, DecryptStream, KeyRing, KeySet, Platform};
+#[cfg(feature = "crypto")]
+use crate::layout::PackageLayouts;
+#[cfg(feature = "image")]
+use crate::gfx::GfxAsset;
+#[cfg(feature = "image")]
+use crate::images::{convert_dds_cached, ImageOptions};
+#[cfg(feature = "sng")]
+use crate::sections::{normalize_sections, NormalizedSection};
+#[cfg(feature = "sng")]
+use crate::xml;
+#[cfg(all(feature = "sng", feature = "audio"))]
+use crate::song::Song;
+#[cfg(feature = "audio")]
+use crate::wem::{wem_to_wav, WemCodec, WemEntryInfo, WemInfo};
+#[cfg(feature = "audio")]
+use crate::audio::AudioConverter;
+#[cfg(feature = "sng")]
 use crate::models::{
     Bpm, Phrase, Chord, ChordNotes, Vocal, SymbolsHeader, SymbolsTexture,
     SymbolDefinition, PhraseIteration, PhraseExtraInfoByLevel, NLinkedDifficulty,
-    Action, Event, Tone, Dna, Section, Arrangement, Metadata, BinarySerializable,
-    read_vec, 
+    Action, Event, Tone, Dna, Section, Arrangement, Metadata, Note, BinarySerializable,
+    read_vec_from_buffer, ParseContext, ParseDiagnostics, ParseOptions,
 };
 
 bitflags::bitflags! {
@@ -44,6 +83,14 @@ pub struct PsarcFileHeader {
 }
 
 impl PsarcFileHeader {
+    /// Major and minor format version, `(1, 4)` for Rocksmith packages. Versions 1.0 to
+    /// 1.4 share this header and the TOC entry layout, which is sized by `toc_size` and
+    /// `toc_entry_size` rather than by the version, so entries other than the 30 bytes
+    /// of 1.4 are read too. `PsarcTOC::read_from` refuses other major versions.
+    pub fn version_parts(&self) -> (u16, u16) {
+        ((self.version >> 16) as u16, self.version as u16)
+    }
+
     /// Reads the header from a reader that implements `Read + Seek`.
     ///
     /// The header layout is 32 bytes:
@@ -57,12 +104,15 @@ impl PsarcFileHeader {
     /// - 4 bytes: ArchiveFlags (big-endian u32)
     /// 
     /// After reading, the current file offset is stored as `toc_offset`.
-    pub fn read_from<R: Read + Seek>(reader: &mut R) -> io::Result<Self> {
+    pub fn read_from<R: Read + Seek>(reader: &mut R) -> Result<Self> {
         reader.seek(SeekFrom::Start(0))?;
         
         let mut identifier_buf = [0u8; 4];
         reader.read_exact(&mut identifier_buf)?;
         let identifier = String::from_utf8_lossy(&identifier_buf).to_string();
+        if &identifier_buf != b"PSAR" {
+            return Err(PsarcError::InvalidHeader(format!("unknown identifier {:?}", identifier)));
+        }
         
         let version = reader.read_u32::<BigEndian>()?;
         
@@ -78,7 +128,7 @@ impl PsarcFileHeader {
         let raw_archive_flags = reader.read_u32::<BigEndian>()?;
         let archive_flags = PsarcArchiveFlags::from_bits_truncate(raw_archive_flags);
         
-        let toc_offset = reader.seek(SeekFrom::Current(0))?;
+        let toc_offset = reader.stream_position()?;
         
         Ok(PsarcFileHeader {
             identifier,
@@ -94,6 +144,9 @@ impl PsarcFileHeader {
     }
 }
 
+/// File name of the entry holding the Steam app id a package is tied to.
+pub const APP_ID_FILE_NAME: &str = "appid.appid";
+
 pub trait ReadSeek: Read + Seek {}
 impl<T: Read + Seek> ReadSeek for T {}
 
@@ -115,20 +168,14 @@ pub struct PsarcTOC {
     pub zip_block_sizes: Vec<u32>,
 }
 
-/// Helper: Reads a 40-bit unsigned integer (5 bytes) in BigEndian.
-fn read_u40_be<R: Read>(reader: &mut R) -> io::Result<u64> {
-    let mut buf = [0u8; 5];
-    reader.read_exact(&mut buf)?;
-    let value = ((buf[0] as u64) << 32)
-        | ((buf[1] as u64) << 24)
-        | ((buf[2] as u64) << 16)
-        | ((buf[3] as u64) << 8)
-        | (buf[4] as u64);
-    Ok(value)
+/// Helper: Reads a BigEndian unsigned integer `width` bytes wide (1 to 8; TOC entries of
+/// 30 bytes use 5).
+fn read_uint_be<R: Read>(reader: &mut R, width: usize) -> Result<u64> {
+    Ok(reader.read_uint::<BigEndian>(width)?)
 }
 
 /// Helper: Reads a 24-bit unsigned integer (3 bytes) in BigEndian.
-fn read_u24_be<R: Read>(reader: &mut R) -> io::Result<u32> {
+fn read_u24_be<R: Read>(reader: &mut R) -> Result<u32> {
     let mut buf = [0u8; 3];
     reader.read_exact(&mut buf)?;
     let value = ((buf[0] as u32) << 16)
@@ -137,28 +184,320 @@ fn read_u24_be<R: Read>(reader: &mut R) -> io::Result<u32> {
     Ok(value)
 }
 
+/// Size of one TOC entry: 16 byte hash, u32 start block, 40-bit length and offset.
+pub const TOC_ENTRY_SIZE: u32 = 30;
+
+/// Size of the fixed PSARC header.
+pub const HEADER_SIZE: u32 = 32;
+
+/// An entry for `PsarcTOC::rebuild` to lay out: its path, its inflated length and the
+/// stored size of each of its blocks, in block size table notation (0 for a full block
+/// stored raw).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TocLayoutEntry {
+    pub path: String,
+    pub length: u64,
+    pub block_sizes: Vec<u32>,
+}
+
+impl TocLayoutEntry {
+    /// Bytes the entry's blocks take in the data region.
+    pub fn stored_length(&self, block_size: u32) -> u64 {
+        self.block_sizes.iter().map(|&size| if size == 0 { block_size as u64 } else { size as u64 }).sum()
+    }
+}
+
+/// Helper: Writes a 40-bit unsigned integer (5 bytes) in BigEndian.
+fn write_u40_be<W: Write>(writer: &mut W, value: u64) -> Result<()> {
+    if value >> 40 != 0 {
+        return Err(PsarcError::BadToc(format!("{} does not fit in 40 bits", value)));
+    }
+    writer.write_all(&value.to_be_bytes()[3..])?;
+    Ok(())
+}
+
+/// Smallest TOC entry: hash, start block and one byte each for the length and offset.
+const MIN_TOC_ENTRY_SIZE: usize = 22;
+
+/// Width in bytes of one entry of the block size table as archives have always been read:
+/// log256 of the block size, rounded. For a block size of 65536, b_num is 2.
+fn block_size_width(block_size: u32) -> Result<usize> {
+    let b_num = (block_size as f64).log(256.0).round() as usize;
+    if !(2..=4).contains(&b_num) {
+        return Err(PsarcError::InvalidHeader(format!("unsupported block size {}", block_size)));
+    }
+    Ok(b_num)
+}
+
+/// Width in bytes of one entry of the block size table as it is written: the fewest bytes,
+/// 2 at least, holding the stored size of any block, a full block being stored as 0. Up to
+/// 64 KiB blocks it is 2, up to 16 MiB 3 (24-bit sizes), beyond that 4. It differs from
+/// `block_size_width` between 64 KiB and 1 MiB and past 16 MiB, where rounding gives a
+/// table too narrow for the sizes; `PsarcTOC::read_from` tells the two apart by the length
+/// of the table.
+fn written_block_size_width(block_size: u32) -> Result<usize> {
+    match block_size {
+        0 => Err(PsarcError::InvalidHeader("block size is zero".to_string())),
+        1..=0x1_0000 => Ok(2),
+        0x1_0001..=0x100_0000 => Ok(3),
+        _ => Ok(4),
+    }
+}
+
+/// Blocks the entries span: the block size table is at least this long.
+fn spanned_blocks(entries: &[PsarcTOCEntry], block_size: u32) -> u64 {
+    entries
+        .iter()
+        .map(|entry| u64::from(entry.start_block) + entry.length.div_ceil(block_size.max(1) as u64))
+        .max()
+        .unwrap_or(0)
+}
+
 impl PsarcTOC {
+    /// Size of the TOC of `entry_count` entries spanning `block_count` blocks of
+    /// `block_size`, the header included, as `PsarcFileHeader::toc_size` counts it.
+    pub fn byte_size(entry_count: usize, block_count: usize, block_size: u32) -> Result<u32> {
+        PsarcTOC::byte_size_in(entry_count, block_count, written_block_size_width(block_size)?)
+    }
+
+    /// `byte_size` with a block size table `width` bytes wide.
+    pub(crate) fn byte_size_in(entry_count: usize, block_count: usize, width: usize) -> Result<u32> {
+        let size = HEADER_SIZE as u64 + entry_count as u64 * TOC_ENTRY_SIZE as u64 + block_count as u64 * width as u64;
+        u32::try_from(size).map_err(|_| PsarcError::BadToc(format!("TOC of {} bytes is too large", size)))
+    }
+
+    /// Number of bytes `entry` occupies in the data region with blocks of `block_size`.
+    /// Blocks missing from the block size table are not counted.
+    pub fn stored_length(&self, entry: &PsarcTOCEntry, block_size: u32) -> u64 {
+        let block_size = block_size as u64;
+        if block_size == 0 {
+            return 0;
+        }
+        let start = entry.start_block as usize;
+        let num_blocks = entry.length.div_ceil(block_size) as usize;
+        self.zip_block_sizes
+            .iter()
+            .skip(start)
+            .take(num_blocks)
+            .map(|&s| if s == 0 { block_size } else { s as u64 })
+            .sum()
+    }
+
+    /// Lays out a TOC for `entries`, stored one after the other right after the TOC:
+    /// - the name hashes are recomputed, the MD5 of each path (entry 0, the NamesBlock,
+    ///   hashes to zeros as in game packages);
+    /// - start blocks and offsets follow from the block sizes of the entries before;
+    /// - the block size table is the entries' block sizes in order.
+    ///
+    /// The TOC is unencrypted; `PsarcWriter` encrypts the bytes of `to_bytes` when asked.
+    ///
+    /// ```
+    /// use psarc_unpacker::psarc::{PsarcFile, PsarcTOC, TocLayoutEntry};
+    /// # fn main() -> psarc_unpacker::error::Result<()> {
+    /// let entry = |path: &str, length: u64, block_sizes: Vec<u32>| TocLayoutEntry { path: path.to_string(), length, block_sizes };
+    /// let entries = [
+    ///     entry("NamesBlock.bin", 12, vec![20]),
+    ///     entry("songs/bin/generic/x_lead.sng", 70_000, vec![0, 1_000]),
+    ///     entry("empty.txt", 0, vec![]),
+    /// ];
+    /// let toc = PsarcTOC::rebuild(&entries, 65536)?;
+    /// // 32 header bytes, 3 entries of 30 bytes, 3 blocks of 2 bytes.
+    /// assert_eq!(PsarcTOC::byte_size(3, 3, 65536)?, 128);
+    /// // Larger blocks take 24-bit block sizes.
+    /// assert_eq!(PsarcTOC::byte_size(3, 3, 1 << 20)?, 131);
+    /// assert_eq!(toc.entries[0].hash, "0".repeat(32));
+    /// assert_eq!(toc.entries[1].hash, PsarcFile::path_hash("songs/bin/generic/x_lead.sng"));
+    /// assert_eq!((toc.entries[1].start_block, toc.entries[1].offset), (1, 148));
+    /// assert_eq!((toc.entries[2].start_block, toc.entries[2].offset), (3, 148 + 65_536 + 1_000));
+    /// assert_eq!(toc.zip_block_sizes, [20, 0, 1_000]);
+    /// assert_eq!(toc.to_bytes(65536)?.len(), 128 - 32);
+    /// // A 70 000 byte entry needs two blocks.
+    /// assert!(PsarcTOC::rebuild(&[entry("NamesBlock.bin", 0, vec![]), entry("a", 70_000, vec![0])], 65536).is_err());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn rebuild(entries: &[TocLayoutEntry], block_size: u32) -> Result<Self> {
+        let block_count = entries.iter().map(|e| e.block_sizes.len()).sum();
+        let mut offset = PsarcTOC::byte_size(entries.len(), block_count, block_size)? as u64;
+        let mut start_block = 0u32;
+        let mut toc_entries = Vec::with_capacity(entries.len());
+        for (index, entry) in entries.iter().enumerate() {
+            let expected_blocks = entry.length.div_ceil(block_size.max(1) as u64);
+            if entry.block_sizes.len() as u64 != expected_blocks {
+                return Err(PsarcError::BadToc(format!(
+                    "{} is {} bytes in {} blocks, {} blocks of {} were expected",
+                    entry.path,
+                    entry.length,
+                    entry.block_sizes.len(),
+                    expected_blocks,
+                    block_size
+                )));
+            }
+            let hash = if index == 0 { "0".repeat(32) } else { PsarcFile::path_hash(&entry.path) };
+            toc_entries.push(PsarcTOCEntry {
+                index: index as i32,
+                hash,
+                start_block,
+                length: entry.length,
+                offset,
+                path: Some(entry.path.clone()),
+            });
+            start_block = u32::try_from(start_block as usize + entry.block_sizes.len())
+                .map_err(|_| PsarcError::BadToc("more blocks than the TOC can number".to_string()))?;
+            offset += entry.stored_length(block_size);
+        }
+        Ok(PsarcTOC {
+            entries: toc_entries,
+            encrypted: false,
+            zip_block_sizes: entries.iter().flat_map(|e| e.block_sizes.iter().copied()).collect(),
+        })
+    }
+
+    /// The TOC as stored after the header, before any encryption: the entries, then the
+    /// block size table in the width `block_size` calls for.
+    pub fn to_bytes(&self, block_size: u32) -> Result<Vec<u8>> {
+        self.to_bytes_in(written_block_size_width(block_size)?)
+    }
+
+    /// `to_bytes` with a block size table `width` bytes wide.
+    pub(crate) fn to_bytes_in(&self, width: usize) -> Result<Vec<u8>> {
+        let mut toc = Vec::with_capacity(self.entries.len() * TOC_ENTRY_SIZE as usize + self.zip_block_sizes.len() * width);
+        for entry in &self.entries {
+            let mut hash = [0u8; 16];
+            for (byte, pair) in hash.iter_mut().zip(entry.hash.as_bytes().chunks(2)) {
+                let pair = std::str::from_utf8(pair).ok().and_then(|pair| u8::from_str_radix(pair, 16).ok());
+                *byte = pair.ok_or_else(|| PsarcError::BadToc(format!("invalid entry hash {:?}", entry.hash)))?;
+            }
+            toc.extend_from_slice(&hash);
+            toc.extend_from_slice(&entry.start_block.to_be_bytes());
+            write_u40_be(&mut toc, entry.length)?;
+            write_u40_be(&mut toc, entry.offset)?;
+        }
+        for &size in &self.zip_block_sizes {
+            let max = if width == 4 { u32::MAX } else { (1u32 << (width * 8)) - 1 };
+            if size > max {
+                return Err(PsarcError::BadToc(format!("block size {} does not fit {} bytes", size, width)));
+            }
+            toc.extend_from_slice(&size.to_be_bytes()[4 - width..]);
+        }
+        Ok(toc)
+    }
+
     /// Reads the TOC from a reader (which must be positioned at the start of the TOC)
     /// using header information.
     ///
-    /// If the header indicates that the TOC is encrypted, this function reads
-    /// `header.toc_size` bytes from the input, decrypts them using your provided
+    /// If the header indicates that the TOC is encrypted, this function reads the
+    /// `header.toc_size - 32` TOC bytes following the header, decrypts them using your provided
     /// `DecryptStream::new_psarc`, and then wraps the decrypted data in a Cursor.
-    pub fn read_from<R: Read + Seek>(mut reader: R, header: &PsarcFileHeader) -> io::Result<Self> {
+    pub fn read_from<R: Read + Seek>(reader: R, header: &PsarcFileHeader) -> Result<Self> {
+        #[cfg(feature = "crypto")]
+        return PsarcTOC::read_with_keys(reader, header, &CryptoKeys::default());
+        #[cfg(not(feature = "crypto"))]
+        PsarcTOC::read_toc(reader, header)
+    }
+
+    /// Names the first entry `NamesBlock.bin` and the others after the lines of its
+    /// content, in TOC order.
+    pub(crate) fn assign_paths(&mut self, names_block: &TextAsset) {
+        if self.entries.is_empty() {
+            return;
+        }
+        self.entries[0].path = Some("NamesBlock.bin".to_string());
+        tracing::trace!("Manifest text ({} bytes):", names_block.text.len());
+        tracing::trace!("{}", names_block.text);
+        if names_block.lines.len() >= self.entries.len() {
+            tracing::warn!(
+                "Manifest lists {} paths for {} entries; extra paths are ignored",
+                names_block.lines.len(),
+                self.entries.len() - 1
+            );
+        }
+        for (entry, line) in self.entries.iter_mut().skip(1).zip(&names_block.lines) {
+            entry.path = Some(line.to_string());
+        }
+    }
+
+    #[cfg(feature = "crypto")]
+    /// `read_from` decrypting the TOC with `keys.psarc` instead of the built-in key.
+    pub fn read_with_keys<R: Read + Seek>(reader: R, header: &PsarcFileHeader, keys: &CryptoKeys) -> Result<Self> {
+        PsarcTOC::read_toc(reader, header, &keys.psarc)
+    }
+
+    /// Whether every entry starts inside a file of `file_length` bytes and spans blocks of
+    /// the block size table: the known-plaintext check telling a TOC decrypted with the
+    /// right key from noise.
+    #[cfg(feature = "crypto")]
+    fn is_consistent(&self, file_length: u64, block_size: u32) -> bool {
+        let blocks = self.zip_block_sizes.len() as u64;
+        !self.entries.is_empty()
+            && block_size > 0
+            && self.entries.iter().all(|entry| {
+                let spanned = entry.length.div_ceil(block_size as u64);
+                entry.offset <= file_length && u64::from(entry.start_block) + spanned <= blocks
+            })
+    }
+
+    /// Width of the block size table of the archive of `header`, whose TOC starts with
+    /// `entries`. Archives have always been read with `block_size_width`; a table exactly
+    /// as long as the entries need in `written_block_size_width` is read in that width
+    /// instead, which is what `PsarcWriter` writes.
+    pub(crate) fn table_width(header: &PsarcFileHeader, entries: &[PsarcTOCEntry]) -> Result<usize> {
+        let written = written_block_size_width(header.block_size)?;
+        let entries_bytes = entries.len() as u64 * header.toc_entry_size as u64;
+        let table_bytes = (header.toc_size as u64).saturating_sub(HEADER_SIZE as u64 + entries_bytes);
+        match block_size_width(header.block_size) {
+            Ok(width) if width == written || table_bytes != written as u64 * spanned_blocks(entries, header.block_size) => {
+                Ok(width)
+            }
+            _ => Ok(written),
+        }
+    }
+
+    fn read_toc<R: Read + Seek>(
+        reader: R,
+        header: &PsarcFileHeader,
+        #[cfg(feature = "crypto")] key: &[u8; 32],
+    ) -> Result<Self> {
+        // 1.0 to 1.4 lay entries out alike; nothing is known of other major versions.
+        match header.version_parts() {
+            (1, _) => {}
+            (major, minor) => {
+                return Err(PsarcError::Unsupported(format!("PSARC version {}.{} is not supported", major, minor)));
+            }
+        }
         let encrypted = header.archive_flags.contains(PsarcArchiveFlags::TOC_ENCRYPTED);
         
-        // If encrypted, use your decryptor to decrypt header.toc_size bytes.
+        // If encrypted, use your decryptor to decrypt the TOC (toc_size includes the header).
+        #[cfg(feature = "crypto")]
         let mut toc_reader: Box<dyn ReadSeek> = if encrypted {
-            let toc_size = header.toc_size as usize;
-            let decrypt_stream = DecryptStream::new_psarc(&mut reader, toc_size)?;
+            let toc_size = (header.toc_size as usize).saturating_sub(32);
+            let decrypt_stream = DecryptStream::new_psarc_with_key(reader, toc_size, key)?;
             Box::new(decrypt_stream.reader)
         } else {
             Box::new(reader)
         };
+        #[cfg(not(feature = "crypto"))]
+        let mut toc_reader: Box<dyn ReadSeek> = if encrypted {
+            return Err(PsarcError::Unsupported(
+                "TOC is encrypted; enable the `crypto` feature to read it".to_string(),
+            ));
+        } else {
+            Box::new(reader)
+        };
         
+        // Each entry is a 16 byte hash and a u32 start block followed by the length and the
+        // offset: 40-bit in the 30 byte entries every known version writes, narrower when the
+        // header declares smaller entries. Bytes past the 30 are padding.
+        let entry_size = header.toc_entry_size as usize;
+        if entry_size < MIN_TOC_ENTRY_SIZE {
+            return Err(PsarcError::BadToc(format!("{} byte entries cannot hold an entry", entry_size)));
+        }
+        let field_width = ((entry_size - 20) / 2).min(5);
+        let padding = entry_size - 20 - 2 * field_width;
+
         // Read entry count (4 bytes, BigEndian).
         let entry_count = header.entry_count;
-        let mut entries = Vec::with_capacity(entry_count as usize);
+        let mut entries = Vec::with_capacity((entry_count as usize).min(4096));
         for i in 0..entry_count {
             let mut hash_bytes = [0u8; 16];
             toc_reader.read_exact(&mut hash_bytes)?;
@@ -166,8 +505,9 @@ impl PsarcTOC {
                 .map(|b| format!("{:02X}", b))
                 .collect::<String>();
             let start_block = toc_reader.read_u32::<BigEndian>()?;
-            let length = read_u40_be(&mut toc_reader)?;
-            let offset = read_u40_be(&mut toc_reader)?;
+            let length = read_uint_be(&mut toc_reader, field_width)?;
+            let offset = read_uint_be(&mut toc_reader, field_width)?;
+            io::copy(&mut (&mut toc_reader).take(padding as u64), &mut io::sink())?;
             entries.push(PsarcTOCEntry {
                 index: i as i32,
                 hash,
@@ -182,21 +522,23 @@ impl PsarcTOC {
         let toc_entries_bytes = (entry_count as usize) * (header.toc_entry_size as usize);
         let remaining = (header.toc_size as isize) - 32 - (toc_entries_bytes as isize);
         if remaining < 0 {
-            return Err(io::Error::new(io::ErrorKind::InvalidData, "TOC size too small"));
+            return Err(PsarcError::BadToc("size too small for its entries".to_string()));
         }
         
-        // Determine b_num = log256(header.block_size). For a block size of 65536, b_num should be 2.
-        let b_num = (header.block_size as f64).log(256.0).round() as usize;
-        let mut z_num = (remaining as usize) / b_num;
-        // Clamp to a safe maximum (e.g. 500) to avoid huge allocations.
-        z_num = z_num.min(500);
-        let mut zip_block_sizes = Vec::with_capacity(z_num);
+        let b_num = PsarcTOC::table_width(header, &entries)?;
+        let z_num = (remaining as usize) / b_num;
+        let leftover = remaining as usize - z_num * b_num;
+        if leftover != 0 {
+            tracing::warn!("TOC ends with {} bytes short of a block size", leftover);
+        }
+        // The count comes from the header, so only reserve a bounded amount up front.
+        let mut zip_block_sizes = Vec::with_capacity(z_num.min(4096));
         for _ in 0..z_num {
             let size = match b_num {
                 2 => toc_reader.read_u16::<BigEndian>()? as u32,
                 3 => read_u24_be(&mut toc_reader)?,
                 4 => toc_reader.read_u32::<BigEndian>()?,
-                _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "Unsupported block size base")),
+                _ => return Err(PsarcError::InvalidHeader("unsupported block size base".to_string())),
             };
             zip_block_sizes.push(size);
         }
@@ -210,7 +552,7 @@ impl PsarcTOC {
 }
 
 pub trait PsarcAsset {
-    fn read_from<R: Read + Seek>(&mut self, reader: &mut R, length: usize) -> io::Result<()>;
+    fn read_from<R: Read + Seek>(&mut self, reader: &mut R, length: usize) -> Result<()>;
 }
 
 #[derive(Default, Debug)]
@@ -226,11 +568,11 @@ pub struct SongArrangementAsset {
 }
 
 impl PsarcAsset for TextAsset {
-    fn read_from<R: Read + Seek>(&mut self, reader: &mut R, length: usize) -> io::Result<()> {
+    fn read_from<R: Read + Seek>(&mut self, reader: &mut R, _length: usize) -> Result<()> {
         let mut buf = Vec::new();
         reader.read_to_end(&mut buf)?;
         self.text = String::from_utf8(buf)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+            .map_err(|err| PsarcError::InvalidAsset(format!("Text entry is not UTF-8: {}", err)))?;
         self.lines = self.text.lines().map(|s| s.to_string()).collect();
         Ok(())
     }
@@ -239,6 +581,7 @@ impl PsarcAsset for TextAsset {
 /// ----------------- SNG Asset -----------------
 /// This struct represents the overall SNG asset. In the C# code the decryption/decompression
 /// is done first and then the asset is read in order.
+#[cfg(feature = "sng")]
 #[derive(Default, Debug, Serialize)]
 pub struct SngAsset {
     pub bpms: Vec<Bpm>,
@@ -259,22 +602,265 @@ pub struct SngAsset {
     pub sections: Vec<Section>,
     pub arrangements: Vec<Arrangement>,
     pub metadata: Metadata,
+    /// Canonical kind and index for each entry of `sections`, so exports label sections
+    /// consistently regardless of how the chart author spelled them.
+    pub section_labels: Vec<NormalizedSection>,
+    /// Strings that needed repairing while parsing (see `ParseOptions`).
+    pub diagnostics: ParseDiagnostics,
+}
+
+/// `SngAsset` as exported by `SngAsset::write_json`, borrowing everything but the
+/// arrangements of a single difficulty.
+#[cfg(feature = "sng")]
+#[derive(Serialize)]
+struct SngJson<'a> {
+    bpms: &'a [Bpm],
+    phrases: &'a [Phrase],
+    chords: &'a [Chord],
+    chord_notes: &'a [ChordNotes],
+    vocals: &'a [Vocal],
+    symbol_headers: Option<&'a [SymbolsHeader]>,
+    symbol_textures: Option<&'a [SymbolsTexture]>,
+    symbol_definitions: Option<&'a [SymbolDefinition]>,
+    phrase_iterations: &'a [PhraseIteration],
+    phrase_extra_info: &'a [PhraseExtraInfoByLevel],
+    nld: &'a [NLinkedDifficulty],
+    actions: &'a [Action],
+    events: &'a [Event],
+    tones: &'a [Tone],
+    dnas: &'a [Dna],
+    sections: &'a [Section],
+    arrangements: &'a [Arrangement],
+    metadata: &'a Metadata,
+    section_labels: &'a [NormalizedSection],
 }
 
+/// A parsed SNG arrangement: the name consumers look for, same type as `SngAsset`.
+#[cfg(feature = "sng")]
+pub type SngFile = SngAsset;
+
 /// For arrays that do not have a preceding count in the SNG file you might need to adjust
 /// the reading functions accordingly. Here we assume that each “array” is preceded by an i32 count.
+#[cfg(feature = "sng")]
 impl PsarcAsset for SngAsset {
-    fn read_from<R: Read + Seek>(&mut self, reader: &mut R, length: usize) -> io::Result<()> {
-        let mut decryptor = DecryptStream::new_sng(reader, length)?;
-        self.bpms = read_vec(&mut decryptor.reader, Bpm::read_from)?;
-        self.phrases = read_vec(&mut decryptor.reader, Phrase::read_from)?;
-        self.chords = read_vec(&mut decryptor.reader, Chord::read_from)?;
-        self.chord_notes = read_vec(&mut decryptor.reader, ChordNotes::read_from)?;
-        self.vocals = read_vec(&mut decryptor.reader, Vocal::read_from)?;
+    /// Without the archive to tell the platform, the PC key is used; see
+    /// `PsarcFile::read_sng_entry` for the key of the package's platform.
+    fn read_from<R: Read + Seek>(&mut self, reader: &mut R, length: usize) -> Result<()> {
+        let decryptor = DecryptStream::new_sng(reader, length, Platform::Pc)?;
+        let plain = decryptor.reader.into_inner();
+        self.read_plain(&plain, ParseOptions::default())
+    }
+}
+
+#[cfg(feature = "sng")]
+impl SngAsset {
+    /// Decrypts and parses an SNG file with a caller-supplied key.
+    pub fn decrypt_with_key(data: &[u8], key: &[u8; 32]) -> Result<Self> {
+        let mut decryptor = DecryptStream::new_sng_with_key(Cursor::new(data), data.len(), key)?;
+        SngAsset::parse(&mut decryptor.reader)
+    }
+
+    /// Decrypts an SNG file of a `platform` package with its key, and parses it.
+    pub fn decrypt(data: &[u8], platform: Platform, options: ParseOptions) -> Result<Self> {
+        let mut decryptor = DecryptStream::new_sng(Cursor::new(data), data.len(), platform)?;
+        SngAsset::parse_with(&mut decryptor.reader, options)
+    }
+
+    /// Parses the full SNG layout (beats, phrases, chords, difficulty levels, metadata)
+    /// from a decrypted, decompressed stream such as `DecryptStream::new_sng`'s reader.
+    pub fn parse<R: Read>(reader: &mut R) -> Result<Self> {
+        SngAsset::parse_with(reader, ParseOptions::default())
+    }
+
+    /// `parse` with explicit options; what had to be repaired is left in `diagnostics`.
+    pub fn parse_with<R: Read>(reader: &mut R, options: ParseOptions) -> Result<Self> {
+        let mut plain = Vec::new();
+        reader.read_to_end(&mut plain)?;
+        let mut asset = SngAsset::default();
+        asset.read_plain(&plain, options)?;
+        Ok(asset)
+    }
+
+    /// Highest difficulty level present in the chart.
+    pub fn max_level(&self) -> i32 {
+        self.arrangements.iter().map(|level| level.difficulty).max().unwrap_or(0)
+    }
+
+    /// Level holding the notes of phrase iteration `iteration` when the chart is played at
+    /// `difficulty`: the phrase's own maximum caps the difficulty, and when levels are
+    /// missing the highest one below is taken.
+    fn iteration_level(&self, iteration: &PhraseIteration, difficulty: i32) -> Option<&Arrangement> {
+        let max = self
+            .phrases
+            .get(iteration.phrase_id as usize)
+            .map_or(i32::MAX, |phrase| phrase.max_difficulty);
+        self.arrangements
+            .iter()
+            .filter(|level| level.difficulty <= difficulty.min(max))
+            .max_by_key(|level| level.difficulty)
+    }
+
+    /// Notes and chords played at `difficulty`, in time order.
+    pub fn notes_at_difficulty(&self, difficulty: i32) -> Vec<&Note> {
+        let mut notes = Vec::new();
+        for (i, iteration) in self.phrase_iterations.iter().enumerate() {
+            if let Some(level) = self.iteration_level(iteration, difficulty) {
+                notes.extend(level.notes.iter().filter(|note| note.phrase_iteration_id == i as i32));
+            }
+        }
+        notes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        notes
+    }
+
+    /// Notes and chords of the full chart: for each phrase iteration, the notes of the
+    /// difficulty level matching the phrase's maximum difficulty, in time order.
+    pub fn max_difficulty_notes(&self) -> Vec<&Note> {
+        self.notes_at_difficulty(i32::MAX)
+    }
+
+    /// The chart as played at `difficulty`, as one level: notes, anchors and fingerprints
+    /// of every phrase iteration come from the level `notes_at_difficulty` picks for it.
+    pub fn flattened_level(&self, difficulty: i32) -> Arrangement {
+        let difficulty = difficulty.min(self.max_level());
+        let base = self
+            .arrangements
+            .iter()
+            .filter(|level| level.difficulty <= difficulty)
+            .max_by_key(|level| level.difficulty);
+        let mut flat = Arrangement {
+            difficulty,
+            anchors: Vec::new(),
+            anchor_extensions: Vec::new(),
+            fingerprints1: Vec::new(),
+            fingerprints2: Vec::new(),
+            notes: Vec::new(),
+            phrase_count: base.map_or(0, |b| b.phrase_count),
+            average_notes_per_iteration: base.map(|b| b.average_notes_per_iteration.clone()).unwrap_or_default(),
+            phrase_iteration_count1: self.phrase_iterations.len() as i32,
+            notes_in_iteration1: Vec::new(),
+            phrase_iteration_count2: self.phrase_iterations.len() as i32,
+            notes_in_iteration2: Vec::new(),
+        };
+        for (i, iteration) in self.phrase_iterations.iter().enumerate() {
+            let Some(level) = self.iteration_level(iteration, difficulty) else {
+                flat.notes_in_iteration1.push(0);
+                flat.notes_in_iteration2.push(0);
+                continue;
+            };
+            let within = |time: f32| time >= iteration.start_time && time < iteration.next_phrase_time;
+            let count = flat.notes.len();
+            flat.notes.extend(level.notes.iter().filter(|n| n.phrase_iteration_id == i as i32).cloned());
+            let added = (flat.notes.len() - count) as i32;
+            flat.notes_in_iteration1.push(added);
+            flat.notes_in_iteration2.push(added);
+            flat.anchors.extend(level.anchors.iter().filter(|a| a.phrase_iteration_id == i as i32).cloned());
+            flat.anchor_extensions.extend(level.anchor_extensions.iter().filter(|a| within(a.beat_time)).cloned());
+            flat.fingerprints1.extend(level.fingerprints1.iter().filter(|f| within(f.start_time)).cloned());
+            flat.fingerprints2.extend(level.fingerprints2.iter().filter(|f| within(f.start_time)).cloned());
+        }
+        flat.notes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        flat
+    }
+
+    /// JSON document of the whole chart (beats, phrases, chords, every level's notes,
+    /// metadata...), on one line. With a difficulty, `arrangements` holds the single level
+    /// of `flattened_level` instead of every difficulty level.
+    pub fn to_json(&self, difficulty: Option<i32>) -> Result<Vec<u8>> {
+        let mut json = Vec::new();
+        self.write_json_document(&mut json, difficulty, false)?;
+        Ok(json)
+    }
+
+    /// `to_json` indented, as written by `write_json` and the extraction.
+    pub fn to_json_pretty(&self, difficulty: Option<i32>) -> Result<Vec<u8>> {
+        let mut json = Vec::new();
+        self.write_json(&mut json, difficulty)?;
+        Ok(json)
+    }
+
+    /// `to_json_pretty` serialized straight into `writer`: arrays are written element by
+    /// element, so a dense chart never exists as a whole JSON document in memory. Keys
+    /// follow the field order of `SngAsset`, so the output is the same from run to run.
+    pub fn write_json<W: Write>(&self, writer: W, difficulty: Option<i32>) -> Result<()> {
+        self.write_json_document(writer, difficulty, true)
+    }
+
+    /// `write_json` on one line, the layout of `to_json`.
+    pub fn write_json_compact<W: Write>(&self, writer: W, difficulty: Option<i32>) -> Result<()> {
+        self.write_json_document(writer, difficulty, false)
+    }
+
+    fn write_json_document<W: Write>(&self, writer: W, difficulty: Option<i32>, pretty: bool) -> Result<()> {
+        let flattened;
+        let arrangements = match difficulty {
+            Some(difficulty) => {
+                flattened = [self.flattened_level(difficulty)];
+                &flattened[..]
+            }
+            None => &self.arrangements[..],
+        };
+        let document = SngJson {
+            bpms: &self.bpms,
+            phrases: &self.phrases,
+            chords: &self.chords,
+            chord_notes: &self.chord_notes,
+            vocals: &self.vocals,
+            symbol_headers: self.symbol_headers.as_deref(),
+            symbol_textures: self.symbol_textures.as_deref(),
+            symbol_definitions: self.symbol_definitions.as_deref(),
+            phrase_iterations: &self.phrase_iterations,
+            phrase_extra_info: &self.phrase_extra_info,
+            nld: &self.nld,
+            actions: &self.actions,
+            events: &self.events,
+            tones: &self.tones,
+            dnas: &self.dnas,
+            sections: &self.sections,
+            arrangements,
+            metadata: &self.metadata,
+            section_labels: &self.section_labels,
+        };
+        if pretty {
+            serde_json::to_writer_pretty(writer, &document)?;
+        } else {
+            serde_json::to_writer(writer, &document)?;
+        }
+        Ok(())
+    }
+
+    /// Rocksmith arrangement XML of the chart, or the vocals XML for a vocals arrangement
+    /// (see the `xml` module). The arrangement name is left empty; see `write_xml`.
+    pub fn to_xml(&self) -> Result<Vec<u8>> {
+        let mut xml = Vec::new();
+        self.write_xml(&mut xml, None)?;
+        Ok(xml)
+    }
+
+    /// `to_xml` written into `writer`, naming the arrangement (`Lead`, `Bass`, ...) when
+    /// given.
+    pub fn write_xml<W: Write>(&self, writer: W, arrangement: Option<&str>) -> Result<()> {
+        if !self.vocals.is_empty() {
+            xml::write_vocals_xml(&self.vocals, writer)?;
+        } else {
+            xml::write_arrangement_xml(self, arrangement, writer)?;
+        }
+        Ok(())
+    }
+
+    /// Parses the decrypted, decompressed SNG layout. Array counts are bounded by the bytes
+    /// left, so a corrupt count fails before anything is read for it.
+    fn read_plain(&mut self, plain: &[u8], options: ParseOptions) -> Result<()> {
+        let reader = &mut Cursor::new(plain);
+        let context = &mut ParseContext::new(options);
+        self.bpms = read_vec_from_buffer(reader, |r| Bpm::read_with(r, context))?;
+        self.phrases = read_vec_from_buffer(reader, |r| Phrase::read_with(r, context))?;
+        self.chords = read_vec_from_buffer(reader, |r| Chord::read_with(r, context))?;
+        self.chord_notes = read_vec_from_buffer(reader, |r| ChordNotes::read_with(r, context))?;
+        self.vocals = read_vec_from_buffer(reader, |r| Vocal::read_with(r, context))?;
         let (headers, textures, definitions) = if !self.vocals.is_empty() {
-            let headers = read_vec(&mut decryptor.reader, SymbolsHeader::read_from)?;
-            let textures = read_vec(&mut decryptor.reader, SymbolsTexture::read_from)?;
-            let definitions = read_vec(&mut decryptor.reader, SymbolDefinition::read_from)?;
+            let headers = read_vec_from_buffer(reader, |r| SymbolsHeader::read_with(r, context))?;
+            let textures = read_vec_from_buffer(reader, |r| SymbolsTexture::read_with(r, context))?;
+            let definitions = read_vec_from_buffer(reader, |r| SymbolDefinition::read_with(r, context))?;
             (Some(headers), Some(textures), Some(definitions))
         } else {
             (None, None, None)
@@ -282,16 +868,18 @@ impl PsarcAsset for SngAsset {
         self.symbol_headers = headers;
         self.symbol_textures = textures;
         self.symbol_definitions = definitions;
-        self.phrase_iterations = read_vec(&mut decryptor.reader, PhraseIteration::read_from)?;
-        self.phrase_extra_info = read_vec(&mut decryptor.reader, PhraseExtraInfoByLevel::read_from)?;
-        self.nld = read_vec(&mut decryptor.reader, NLinkedDifficulty::read_from)?;
-        self.actions = read_vec(&mut decryptor.reader, Action::read_from)?;
-        self.events = read_vec(&mut decryptor.reader, Event::read_from)?;
-        self.tones = read_vec(&mut decryptor.reader, Tone::read_from)?;
-        self.dnas = read_vec(&mut decryptor.reader, Dna::read_from)?;
-        self.sections = read_vec(&mut decryptor.reader, Section::read_from)?;
-        self.arrangements = read_vec(&mut decryptor.reader, Arrangement::read_from)?;
-        self.metadata = Metadata::read_from(&mut decryptor.reader)?;
+        self.phrase_iterations = read_vec_from_buffer(reader, |r| PhraseIteration::read_with(r, context))?;
+        self.phrase_extra_info = read_vec_from_buffer(reader, |r| PhraseExtraInfoByLevel::read_with(r, context))?;
+        self.nld = read_vec_from_buffer(reader, |r| NLinkedDifficulty::read_with(r, context))?;
+        self.actions = read_vec_from_buffer(reader, |r| Action::read_with(r, context))?;
+        self.events = read_vec_from_buffer(reader, |r| Event::read_with(r, context))?;
+        self.tones = read_vec_from_buffer(reader, |r| Tone::read_with(r, context))?;
+        self.dnas = read_vec_from_buffer(reader, |r| Dna::read_with(r, context))?;
+        self.sections = read_vec_from_buffer(reader, |r| Section::read_with(r, context))?;
+        self.arrangements = read_vec_from_buffer(reader, |r| Arrangement::read_with(r, context))?;
+        self.metadata = Metadata::read_with(reader, context)?;
+        self.diagnostics = std::mem::take(&mut context.diagnostics);
+        self.section_labels = normalize_sections(self.sections.iter().map(|s| s.name.as_str()));
         Ok(())
     }
 }
@@ -316,13 +904,13 @@ pub struct BkhdAsset {
 
 
 impl PsarcAsset for BkhdAsset {
-    fn read_from<R: Read + Seek>(&mut self, reader: &mut R, _length: usize) -> io::Result<()> {
+    fn read_from<R: Read + Seek>(&mut self, reader: &mut R, _length: usize) -> Result<()> {
         let mut label_buf = [0u8; 4];
         reader.read_exact(&mut label_buf)?;
         let label = std::str::from_utf8(&label_buf)
-            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Invalid header label encoding"))?;
+            .map_err(|_| PsarcError::InvalidAsset("Invalid header label encoding".to_string()))?;
         if label != "BKHD" {
-            return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a valid bnk file"));
+            return Err(PsarcError::InvalidAsset("Not a valid bnk file".to_string()));
         }
 
         self.bkhd_length = reader.read_u32::<LittleEndian>()?;
@@ -330,15 +918,15 @@ impl PsarcAsset for BkhdAsset {
         self.bkhd_id = reader.read_u32::<LittleEndian>()?;
 
         let mut cur = self.bkhd_length.checked_sub(8)
-            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "bkhd_length less than 8"))?;
+            .ok_or_else(|| PsarcError::InvalidAsset("bkhd_length less than 8".to_string()))?;
         while cur > 0 {
             let _ = reader.read_i32::<LittleEndian>()?;
             cur = cur.saturating_sub(4);
         }
         let mut didx_label_buf = [0u8; 4];
         reader.read_exact(&mut didx_label_buf)?;
-        let didx_label = std::str::from_utf8(&didx_label_buf)
-            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Invalid DIDX label encoding"))?;
+        let _didx_label = std::str::from_utf8(&didx_label_buf)
+            .map_err(|_| PsarcError::InvalidAsset("Invalid DIDX label encoding".to_string()))?;
         self.didx_length = reader.read_u32::<LittleEndian>()?;
         let mut cur = self.didx_length;
         let mut d_list = Vec::new();
@@ -352,19 +940,100 @@ impl PsarcAsset for BkhdAsset {
         self.didx = d_list;
         let mut data_label_buf = [0u8; 4];
         reader.read_exact(&mut data_label_buf)?;
-        let data_label = std::str::from_utf8(&data_label_buf)
-            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Invalid DATA label encoding"))?;
+        let _data_label = std::str::from_utf8(&data_label_buf)
+            .map_err(|_| PsarcError::InvalidAsset("Invalid DATA label encoding".to_string()))?;
         self.data_length = reader.read_i32::<LittleEndian>()?;
 
         Ok(())
     }
 }
 
+/// Where an SNG JSON export goes: its label (entry path plus suffix) and the extraction
+/// settings it is written with.
+#[cfg(feature = "sng")]
+struct SngOutput<'a> {
+    label: &'a str,
+    entry_path: &'a str,
+    output_dir: &'a Path,
+    options: &'a ExtractOptions,
+    keep_going: bool,
+}
+
+/// Writer hashing the bytes that go through it, so a streamed output can be recorded in
+/// the job state.
+#[cfg(feature = "sng")]
+struct HashingWriter<W> {
+    inner: W,
+    md5: Md5,
+}
+
+#[cfg(feature = "sng")]
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.md5.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// True when `entry` is an SNG arrangement the options export as JSON.
+#[cfg(feature = "sng")]
+fn converts_to_json(entry: &PsarcTOCEntry, options: &ExtractOptions) -> bool {
+    entry.path.as_deref().is_some_and(|path| {
+        path.ends_with(".sng") && options.selects_entry(entry) && options.selects_content(path, ContentType::Sng)
+    })
+}
+
+/// Form of an entry path used to compare paths typed by users with archive paths:
+/// lowercase, `/` separated, without a leading `/` or `./`.
+pub fn normalize_entry_path(path: &str) -> String {
+    let path = path.replace('\\', "/").to_lowercase();
+    let mut path = path.as_str();
+    loop {
+        if let Some(rest) = path.strip_prefix("./") {
+            path = rest;
+        } else if let Some(rest) = path.strip_prefix('/') {
+            path = rest;
+        } else {
+            return path.to_string();
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct PsarcFile {
     pub header: PsarcFileHeader,
     pub toc: PsarcTOC,
     pub data: Vec<u8>,
+    index: EntryIndex,
+}
+
+/// Positions of the TOC entries by path, by normalized path (see `normalize_entry_path`)
+/// and by hash, so lookups do not walk the TOC.
+#[derive(Debug, Default)]
+struct EntryIndex {
+    paths: HashMap<String, usize>,
+    normalized: HashMap<String, usize>,
+    hashes: HashMap<String, usize>,
+}
+
+impl EntryIndex {
+    /// Indexes `entries`; the first of entries sharing a key wins, like a TOC walk.
+    fn build(entries: &[PsarcTOCEntry]) -> Self {
+        let mut index = EntryIndex::default();
+        for (position, entry) in entries.iter().enumerate() {
+            index.hashes.entry(entry.hash.to_ascii_uppercase()).or_insert(position);
+            if let Some(path) = entry.path.as_deref() {
+                index.paths.entry(path.to_string()).or_insert(position);
+                index.normalized.entry(normalize_entry_path(path)).or_insert(position);
+            }
+        }
+        index
+    }
 }
 
 impl PsarcFile {
@@ -372,13 +1041,169 @@ impl PsarcFile {
     /// 1. Reads the header.
     /// 2. Reads the TOC.
     /// 3. Seeks back to the start and reads the entire file into memory.
-    pub fn open<R: Read + Seek>(reader: &mut R) -> io::Result<Self> {
+    pub fn open<R: Read + Seek>(reader: &mut R) -> Result<Self> {
         let header = PsarcFileHeader::read_from(reader)?;
         let toc = PsarcTOC::read_from(&mut *reader, &header)?;
         reader.seek(SeekFrom::Start(0))?;
         let mut data = Vec::new();
         reader.read_to_end(&mut data)?;
-        Ok(PsarcFile { header, toc, data })
+        Ok(PsarcFile::from_parts(header, toc, data))
+    }
+
+    /// Assembles an archive from its parts, indexing the TOC entries.
+    fn from_parts(header: PsarcFileHeader, toc: PsarcTOC, data: Vec<u8>) -> Self {
+        let index = EntryIndex::build(&toc.entries);
+        PsarcFile { header, toc, data, index }
+    }
+
+    /// Rebuilds the lookup index of `entry_by_path` and `entry_by_hash`. Reading the
+    /// manifest does this; call it after changing `toc.entries` by hand.
+    pub fn reindex(&mut self) {
+        self.index = EntryIndex::build(&self.toc.entries);
+    }
+
+    /// TOC hash of an entry path: the MD5 of its bytes, as uppercase hex. The hash of a path
+    /// is what the TOC stores, so entries can be looked up before the manifest is read.
+    pub fn path_hash(path: &str) -> String {
+        digest::path_hash(path)
+    }
+
+    /// Opens a PSARC file from disk and reads its manifest, so entry paths are available
+    /// straight away.
+    pub fn open_path(path: impl AsRef<Path>) -> Result<Self> {
+        let data = fs::read(path.as_ref())?;
+        let mut cursor = Cursor::new(&data);
+        let mut psarc = PsarcFile::open(&mut cursor)?;
+        psarc.read_manifest()?;
+        Ok(psarc)
+    }
+
+    #[cfg(feature = "crypto")]
+    /// `open` decrypting the TOC with the given keys instead of the built-in ones.
+    pub fn open_with_keys<R: Read + Seek>(reader: &mut R, keys: &CryptoKeys) -> Result<Self> {
+        let header = PsarcFileHeader::read_from(reader)?;
+        let toc = PsarcTOC::read_with_keys(&mut *reader, &header, keys)?;
+        reader.seek(SeekFrom::Start(0))?;
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        Ok(PsarcFile::from_parts(header, toc, data))
+    }
+
+    #[cfg(feature = "crypto")]
+    /// `open_path` decrypting the TOC with the given keys instead of the built-in ones.
+    pub fn open_path_with_keys(path: impl AsRef<Path>, keys: &CryptoKeys) -> Result<Self> {
+        let data = fs::read(path.as_ref())?;
+        let mut psarc = PsarcFile::open_with_keys(&mut Cursor::new(&data), keys)?;
+        psarc.read_manifest()?;
+        Ok(psarc)
+    }
+
+    #[cfg(feature = "crypto")]
+    /// Opens an archive with the first key set of `ring` that decrypts its TOC, returning
+    /// the archive (paths read) and that set. A set is accepted when the decrypted TOC is
+    /// consistent, with every entry inside the file and the block table, and its first
+    /// entry reads as a NamesBlock. Use the returned set's `sng` key for the SNG entries.
+    pub fn open_with_key_ring<'a, R: Read + Seek>(reader: &mut R, ring: &'a KeyRing) -> Result<(Self, &'a KeySet)> {
+        reader.seek(SeekFrom::Start(0))?;
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        for set in ring.sets() {
+            let mut cursor = Cursor::new(&data);
+            let header = PsarcFileHeader::read_from(&mut cursor)?;
+            let toc = match PsarcTOC::read_with_keys(&mut cursor, &header, &set.keys) {
+                Ok(toc) if toc.is_consistent(data.len() as u64, header.block_size) => toc,
+                Ok(_) => {
+                    tracing::debug!("Key set {} does not decrypt the TOC", set.name);
+                    continue;
+                }
+                // No key makes an unknown version readable.
+                Err(err @ PsarcError::Unsupported(_)) => return Err(err),
+                Err(err) => {
+                    tracing::debug!("Key set {} does not decrypt the TOC: {}", set.name, err);
+                    continue;
+                }
+            };
+            let mut psarc = PsarcFile::from_parts(header, toc, data);
+            match psarc.read_manifest() {
+                Ok(()) => {
+                    tracing::debug!("TOC decrypted with key set {}", set.name);
+                    return Ok((psarc, set));
+                }
+                Err(err) => tracing::debug!("Key set {} does not give a readable NamesBlock: {}", set.name, err),
+            }
+            data = psarc.data;
+        }
+        Err(PsarcError::DecryptionFailed(format!("none of the {} key sets decrypts the TOC", ring.sets().len())))
+    }
+
+    #[cfg(feature = "crypto")]
+    /// `open_with_key_ring` for an archive on disk.
+    pub fn open_path_with_key_ring(path: impl AsRef<Path>, ring: &KeyRing) -> Result<(Self, &KeySet)> {
+        let data = fs::read(path.as_ref())?;
+        PsarcFile::open_with_key_ring(&mut Cursor::new(&data), ring)
+    }
+
+    #[cfg(feature = "sng")]
+    /// Inflates and decrypts an SNG entry with `keys.sng` instead of the built-in key.
+    pub fn inflate_sng_with_keys(&self, entry: &PsarcTOCEntry, keys: &CryptoKeys) -> Result<SngAsset> {
+        SngAsset::decrypt_with_key(&self.inflate_entry_data(entry)?, &keys.sng)
+    }
+
+    /// Platform of the package, from the folders of its entries (see
+    /// `PackageLayouts::detect_platform`). PC when no folder tells, or for platforms
+    /// without a known SNG key.
+    #[cfg(feature = "crypto")]
+    pub fn platform(&self) -> Platform {
+        let layouts = PackageLayouts::current();
+        layouts
+            .detect_platform(self.toc.entries.iter().filter_map(|e| e.path.as_deref()))
+            .and_then(Platform::from_name)
+            .unwrap_or_default()
+    }
+
+    #[cfg(feature = "sng")]
+    /// Inflates, decrypts and parses an SNG entry, with the SNG key of the package's
+    /// platform.
+    pub fn read_sng_entry(&self, entry: &PsarcTOCEntry) -> Result<SngFile> {
+        self.read_sng_entry_with(entry, ParseOptions::default())
+    }
+
+    /// `read_sng_entry` with explicit parse options, such as the policy for strings that
+    /// are not valid UTF-8.
+    #[cfg(feature = "sng")]
+    pub fn read_sng_entry_with(&self, entry: &PsarcTOCEntry, options: ParseOptions) -> Result<SngFile> {
+        SngAsset::decrypt(&self.inflate_entry_data(entry)?, self.platform(), options)
+    }
+
+    /// Finds the entry stored at `path`. An exact match is tried first; otherwise the
+    /// path is compared the way users type it: case-insensitively, with `\` accepted as
+    /// a separator and a leading `/` or `./` ignored (`Songs\Bin\Generic\x_lead.sng`
+    /// finds `songs/bin/generic/x_lead.sng`). Entries without a path, as before
+    /// `read_manifest`, are found by the hash of `path` or of its normalized form.
+    pub fn entry_by_path(&self, path: &str) -> Option<&PsarcTOCEntry> {
+        let normalized = normalize_entry_path(path);
+        let position = self.index.paths.get(path).or_else(|| self.index.normalized.get(&normalized));
+        position.and_then(|&position| self.toc.entries.get(position)).or_else(|| {
+            let by_hash = |path: &str| self.entry_by_hash(&Self::path_hash(path));
+            by_hash(path).or_else(|| by_hash(&normalized))
+        })
+    }
+
+    /// Finds the entry whose TOC hash is `hash`, in hex of either case.
+    pub fn entry_by_hash(&self, hash: &str) -> Option<&PsarcTOCEntry> {
+        let position = self.index.hashes.get(&hash.to_ascii_uppercase())?;
+        self.toc.entries.get(*position)
+    }
+
+    /// `entry_by_hash` for a hash as bytes.
+    pub fn entry_by_digest(&self, digest: &[u8; 16]) -> Option<&PsarcTOCEntry> {
+        self.entry_by_hash(&digest::to_hex(digest))
+    }
+
+    /// Inflates the entry at `path`, found as `entry_by_path` does.
+    pub fn read_path(&self, path: &str) -> Result<Vec<u8>> {
+        let entry = self.entry_by_path(path).ok_or_else(|| PsarcError::MissingEntry(path.to_string()))?;
+        self.inflate_entry_data(entry)
     }
 
     pub fn get_entry_by_file_name(&self, file_name: &str) -> Option<&PsarcTOCEntry> {
@@ -399,7 +1224,7 @@ impl PsarcFile {
     /// Inflates an entry into an asset of type T.
     /// This method creates a new cursor over the entire file data, then calls
     /// `inflate_entry_data` to perform block‑by‑block inflation of the specified entry.
-    pub fn inflate_entry_as<T: PsarcAsset + Default>(&self, entry: &PsarcTOCEntry) -> io::Result<T> {
+    pub fn inflate_entry_as<T: PsarcAsset + Default>(&self, entry: &PsarcTOCEntry) -> Result<T> {
         let inflated = self.inflate_entry_data(entry)?;
         let mut asset = T::default();
         let mut cursor = Cursor::new(inflated);
@@ -410,18 +1235,36 @@ impl PsarcFile {
 
     /// Performs block‑by‑block inflation (decompression) of the asset specified by `entry`.
     /// Returns a Vec<u8> containing the uncompressed asset data.
-    pub fn inflate_entry_data(&self, entry: &PsarcTOCEntry) -> io::Result<Vec<u8>> {
+    pub fn inflate_entry_data(&self, entry: &PsarcTOCEntry) -> Result<Vec<u8>> {
+        self.inflate_entry_head(entry, entry.length)
+    }
+
+    /// The first `length` bytes of the asset specified by `entry` (all of it when shorter),
+    /// inflating only the blocks they span.
+    pub fn inflate_entry_head(&self, entry: &PsarcTOCEntry, length: u64) -> Result<Vec<u8>> {
         let block_size = self.header.block_size as usize;
-        // Calculate how many blocks the uncompressed asset spans.
-        let num_blocks = ((entry.length as f64) / (block_size as f64)).ceil() as u32;
-        let last_block = entry.start_block + num_blocks - 1;
+        if block_size == 0 {
+            return Err(PsarcError::InvalidHeader("block size is zero".to_string()));
+        }
+        let length = length.min(entry.length);
+        if length == 0 {
+            return Ok(Vec::new());
+        }
+        // Calculate how many blocks the requested bytes span.
+        let num_blocks = length.div_ceil(block_size as u64);
+        let last_block = u64::from(entry.start_block) + num_blocks - 1;
+        if last_block > u32::MAX as u64 {
+            return Err(PsarcError::BadToc("entry spans past the last block".to_string()));
+        }
+        let last_block = last_block as u32;
         
-        // Create a cursor over the file data and seek to the asset's offset.
-        let mut cursor = Cursor::new(&self.data);
-        cursor.seek(SeekFrom::Start(entry.offset))?;
+        // Blocks are sliced out of the file data, so nothing is allocated for sizes the
+        // file does not back.
+        let data = self.data.as_slice();
+        let mut position = entry.offset.min(data.len() as u64) as usize;
         
         let mut output = Vec::new();
-        const ZIP_HEADER: u16 = 0x78DA;
+        const ZIP_HEADER: [u8; 2] = [0x78, 0xDA];
         
         // For each block index from entry.start_block to last_block:
         for block in entry.start_block..=last_block {
@@ -430,93 +1273,876 @@ impl PsarcFile {
             let zipblock_size = self.toc.zip_block_sizes.get(block as usize).copied().unwrap_or(0) as usize;
             
             if zipblock_size == 0 {
-                // Uncompressed: read a full block.
-                let mut buf = vec![0u8; block_size];
-                let n = cursor.read(&mut buf)?;
-                output.extend_from_slice(&buf[..n]);
+                // Uncompressed: a full block, or what is left of the file.
+                let end = position.saturating_add(block_size).min(data.len());
+                if end == position {
+                    break;
+                }
+                output.extend_from_slice(&data[position..end]);
+                position = end;
             } else {
-                // Peek at the first two bytes.
-                let pos = cursor.position();
-                let header_val = cursor.read_u16::<BigEndian>()?;
-                // Rewind 2 bytes.
-                cursor.seek(SeekFrom::Start(pos))?;
-                
-                if header_val == ZIP_HEADER {
+                let stored = data.get(position..).and_then(|rest| rest.get(..zipblock_size)).ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::UnexpectedEof, "block runs past the end of the archive")
+                })?;
+                if stored.starts_with(&ZIP_HEADER) {
                     // Compressed block: call unzip_block.
-                    let decompressed = unzip_block(&mut cursor, zipblock_size)?;
+                    let decompressed = unzip_block(&mut Cursor::new(stored), zipblock_size, block_size)?;
                     output.extend_from_slice(&decompressed);
                 } else {
-                    // Otherwise, read raw zipblock_size bytes.
-                    let mut buf = vec![0u8; zipblock_size];
-                    cursor.read_exact(&mut buf)?;
-                    output.extend_from_slice(&buf);
+                    // Otherwise, the block is stored raw.
+                    output.extend_from_slice(stored);
                 }
+                position += zipblock_size;
             }
         }
-        // Truncate the output to exactly entry.length bytes.
-        output.truncate(entry.length as usize);
+        // Truncate the output to exactly the requested length.
+        output.truncate(length as usize);
         Ok(output)
     }
 
+    /// Identity of the archive from its header and TOC, to recognise a renamed copy or a
+    /// patched variant without reading the entries (see `ArchiveFingerprint`).
+    pub fn fingerprint(&self) -> ArchiveFingerprint {
+        ArchiveFingerprint::of(self)
+    }
+
+    /// The per-arrangement manifests (`manifests/**/*.json`), typed. Manifests that do not
+    /// parse are skipped with a warning; see `manifest::read_hsan_manifests` for the HSAN.
+    pub fn read_manifests(&self) -> Result<Vec<SongManifest>> {
+        manifest::read_manifests(self)
+    }
+
+    /// The full track and preview wem of every song, resolved through the song soundbanks
+    /// (see `bnk::resolve_audio_entries`).
+    pub fn resolve_audio_entries(&self) -> Result<Vec<SongAudio>> {
+        bnk::resolve_audio_entries(self)
+    }
+
+    /// Number of bytes the entry occupies in the data region (its compressed size).
+    /// Blocks missing from the block size table are not counted.
+    pub fn stored_length(&self, entry: &PsarcTOCEntry) -> u64 {
+        self.toc.stored_length(entry, self.header.block_size)
+    }
+
+    /// Whether the archive is official DLC or a custom song, see `origin::classify_package`.
+    pub fn origin(&self) -> Result<OriginReport> {
+        origin::classify_package(self)
+    }
+
+    /// The `toolkit.version` entry of a custom package, if the archive has one.
+    pub fn toolkit_info(&self) -> Result<Option<ToolkitInfo>> {
+        match self.get_entry_by_file_name(TOOLKIT_VERSION_FILE_NAME) {
+            Some(entry) => Ok(Some(ToolkitInfo::parse(&String::from_utf8_lossy(&self.inflate_entry_data(entry)?)))),
+            None => Ok(None),
+        }
+    }
+
+    /// Steam app id stored in the `appid.appid` entry, if the archive has one.
+    pub fn app_id(&self) -> Result<Option<String>> {
+        match self.get_entry_by_file_name(APP_ID_FILE_NAME) {
+            Some(entry) => {
+                let asset: TextAsset = self.inflate_entry_as(entry)?;
+                Ok(Some(asset.text.trim().to_string()))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Inflates an entry and classifies it by its leading magic bytes.
+    pub fn sniff_entry(&self, entry: &PsarcTOCEntry) -> Result<ContentType> {
+        let data = self.inflate_entry_data(entry)?;
+        Ok(ContentType::sniff(&data))
+    }
+
+    #[cfg(feature = "image")]
+    /// Returns every entry whose payload is a DDS texture.
+    ///
+    /// Selection is done by the magic-byte sniffer rather than by folder, so lyric fonts
+    /// and UI atlases from static archives are returned alongside `gfxassets/album_art`.
+    /// Only the first block of each entry is inflated.
+    pub fn texture_entries(&self) -> Result<Vec<&PsarcTOCEntry>> {
+        let mut textures = Vec::new();
+        for entry in &self.toc.entries {
+            if self.texture_head(entry)?.is_some() {
+                textures.push(entry);
+            }
+        }
+        Ok(textures)
+    }
+
+    #[cfg(feature = "image")]
+    /// The first block of `entry` when it starts like a DDS texture.
+    fn texture_head(&self, entry: &PsarcTOCEntry) -> Result<Option<Vec<u8>>> {
+        let head = self.inflate_entry_head(entry, self.header.block_size as u64)?;
+        Ok(Some(head).filter(|head| ContentType::sniff(head) == ContentType::Dds))
+    }
+
+    #[cfg(feature = "image")]
+    /// Every DDS texture with its content. The first block read to sniff an entry is the
+    /// whole content of single-block textures; larger ones are inflated once more in full.
+    fn textures(&self) -> impl Iterator<Item = Result<(&PsarcTOCEntry, Vec<u8>)>> {
+        self.toc.entries.iter().filter_map(move |entry| match self.texture_head(entry) {
+            Ok(Some(head)) if head.len() as u64 == entry.length => Some(Ok((entry, head))),
+            Ok(Some(_)) => Some(self.inflate_entry_data(entry).map(|data| (entry, data))),
+            Ok(None) => None,
+            Err(err) => Some(Err(err)),
+        })
+    }
+
+    #[cfg(feature = "image")]
+    /// Writes every DDS texture found by `texture_entries` into `output_dir`.
+    pub fn dump_textures(&self, output_dir: &Path) -> Result<()> {
+        fs::create_dir_all(output_dir)?;
+        for texture in self.textures() {
+            let (entry, data) = texture?;
+            let file_name = match entry.path.as_deref().and_then(|p| Path::new(p).file_name()) {
+                Some(name) => name.to_string_lossy().to_string(),
+                None => format!("{}.{}", entry.hash, ContentType::Dds.extension()),
+            };
+            let output_path = output_dir.join(file_name);
+            fs::write(&output_path, data)?;
+            tracing::info!("Texture dumped to {:?}", output_path);
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "image")]
+    /// Converts every DDS texture found by `texture_entries` to an image in `output_dir`,
+    /// named after the entry with the extension of `options.format`, the DDS too when
+    /// `options.keep_dds` is set. Returns the written paths.
+    pub fn convert_textures(&self, output_dir: &Path, options: &ImageOptions) -> Result<Vec<PathBuf>> {
+        self.convert_textures_cached(output_dir, options, None)
+    }
+
+    #[cfg(feature = "image")]
+    /// `convert_textures` reusing the PNG files of `cache`.
+    pub fn convert_textures_cached(
+        &self,
+        output_dir: &Path,
+        options: &ImageOptions,
+        cache: Option<&ConversionCache>,
+    ) -> Result<Vec<PathBuf>> {
+        fs::create_dir_all(output_dir)?;
+        let mut written = Vec::new();
+        for texture in self.textures() {
+            let (entry, data) = texture?;
+            let stem = match entry.path.as_deref().and_then(|p| Path::new(p).file_stem()) {
+                Some(stem) => stem.to_string_lossy().to_string(),
+                None => entry.hash.clone(),
+            };
+            let output_path = output_dir.join(format!("{}.{}", stem, options.format.extension()));
+            fs::write(&output_path, convert_dds_cached(&data, options, cache)?)?;
+            tracing::info!("Texture converted to {:?}", output_path);
+            written.push(output_path);
+            if options.keep_dds {
+                let dds_path = output_dir.join(format!("{}.{}", stem, ContentType::Dds.extension()));
+                fs::write(&dds_path, &data)?;
+                written.push(dds_path);
+            }
+        }
+        Ok(written)
+    }
+
+    #[cfg(feature = "image")]
+    /// Converts every Scaleform GFx movie in the archive to a standard SWF in `output_dir`.
+    ///
+    /// Returns the parsed movies keyed by entry path so callers can list the embedded symbols.
+    pub fn extract_gfx_as_swf(&self, output_dir: &Path) -> Result<Vec<(String, GfxAsset)>> {
+        fs::create_dir_all(output_dir)?;
+        let mut movies = Vec::new();
+        for entry in &self.toc.entries {
+            let path = match &entry.path {
+                Some(path) if path.ends_with(".gfx") => path,
+                _ => continue,
+            };
+            let movie: GfxAsset = self.inflate_entry_as(entry)?;
+            let stem = Path::new(path)
+                .file_stem()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_else(|| entry.hash.clone());
+            let output_path = output_dir.join(format!("{}.swf", stem));
+            fs::write(&output_path, movie.to_swf())?;
+            tracing::info!("GFx movie {} written as {:?} ({} symbols)", path, output_path, movie.symbols.len());
+            movies.push((path.clone(), movie));
+        }
+        Ok(movies)
+    }
+
+    #[cfg(feature = "audio")]
+    /// Reads the format metadata (codec, channels, sample rate, duration) of every .wem entry
+    /// without converting the audio.
+    pub fn audio_info(&self) -> Result<Vec<WemEntryInfo>> {
+        let mut infos = Vec::new();
+        for entry in &self.toc.entries {
+            if let Some(path) = &entry.path {
+                if path.ends_with(".wem") {
+                    let info: WemInfo = self.inflate_entry_as(entry)?;
+                    tracing::trace!("{}: {} {}ch {}Hz {:.2}s", path, info.codec_name, info.channels, info.sample_rate, info.duration);
+                    infos.push(WemEntryInfo { path: path.clone(), info });
+                }
+            }
+        }
+        Ok(infos)
+    }
+
+    #[cfg(feature = "audio")]
+    /// Writes the result of `audio_info` as a pretty-printed JSON document.
+    pub fn export_audio_info_json(&self, output_path: &Path) -> Result<()> {
+        let infos = self.audio_info()?;
+        let json = serde_json::to_string_pretty(&infos)?;
+        fs::write(output_path, json)?;
+        tracing::info!("Written audio info to {:?}", output_path);
+        Ok(())
+    }
+
+    #[cfg(feature = "audio")]
+    /// Converts every PCM and ADPCM wem to a WAV file in `output_dir`.
+    ///
+    /// The codec is detected from the fmt chunk first, so Vorbis wems (which need the Ogg
+    /// reconstruction) are skipped instead of failing the whole run. Returns the written paths.
+    pub fn convert_uncompressed_audio_to_wav(&self, output_dir: &Path) -> Result<Vec<PathBuf>> {
+        self.convert_uncompressed_audio_to_wav_cached(output_dir, None)
+    }
+
+    #[cfg(feature = "audio")]
+    /// `convert_uncompressed_audio_to_wav` reusing the WAV files of `cache`.
+    pub fn convert_uncompressed_audio_to_wav_cached(
+        &self,
+        output_dir: &Path,
+        cache: Option<&ConversionCache>,
+    ) -> Result<Vec<PathBuf>> {
+        fs::create_dir_all(output_dir)?;
+        let mut written = Vec::new();
+        for entry in &self.toc.entries {
+            let path = match &entry.path {
+                Some(path) if path.ends_with(".wem") => path,
+                _ => continue,
+            };
+            let data = self.inflate_entry_data(entry)?;
+            let info = WemInfo::parse(&data)?;
+            match info.codec() {
+                WemCodec::Pcm | WemCodec::Adpcm => {
+                    let wav = convert_cached(cache, cache::WEM_WAV, &data, wem_to_wav)?;
+                    let stem = Path::new(path)
+                        .file_stem()
+                        .map(|s| s.to_string_lossy().to_string())
+                        .unwrap_or_else(|| entry.hash.clone());
+                    let output_path = output_dir.join(format!("{}.wav", stem));
+                    fs::write(&output_path, wav)?;
+                    tracing::info!("Converted {} ({}) to {:?}", path, info.codec_name, output_path);
+                    written.push(output_path);
+                }
+                codec => tracing::trace!("Skipping {}: {:?} needs a different conversion path", path, codec),
+            }
+        }
+        Ok(written)
+    }
+
+    #[cfg(feature = "audio")]
+    /// Converts every wem to a WAV or Ogg file in `output_dir` with `converter`, in
+    /// memory: nothing but the converted files is written. Wems of codecs the converter
+    /// does not handle are skipped. Returns the written paths.
+    pub fn convert_audio(&self, output_dir: &Path, converter: &AudioConverter) -> Result<Vec<PathBuf>> {
+        fs::create_dir_all(output_dir)?;
+        let mut written = Vec::new();
+        for entry in &self.toc.entries {
+            let path = match &entry.path {
+                Some(path) if path.ends_with(".wem") => path,
+                _ => continue,
+            };
+            let data = self.inflate_entry_data(entry)?;
+            let audio = match converter.convert(&data) {
+                Ok(audio) => audio,
+                Err(err) if err.kind() == std::io::ErrorKind::Unsupported => {
+                    tracing::trace!("Skipping {}: {}", path, err);
+                    continue;
+                }
+                Err(err) => return Err(err.into()),
+            };
+            let stem = Path::new(path)
+                .file_stem()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_else(|| entry.hash.clone());
+            let output_path = output_dir.join(format!("{}.{}", stem, audio.extension()));
+            fs::write(&output_path, audio.data())?;
+            tracing::info!("Converted {} to {:?}", path, output_path);
+            written.push(output_path);
+        }
+        Ok(written)
+    }
+
     /// Reads the manifest from TOC entry 0.
     /// Sets TOC.Entries[0].path to "NamesBlock.bin", inflates the entry as a TextPsarcAsset,
     /// and assigns each line as the path for subsequent TOC entries.
-    pub fn read_manifest(&mut self) -> io::Result<()> {
+    pub fn read_manifest(&mut self) -> Result<()> {
         if self.toc.entries.is_empty() {
             return Ok(());
         }
-        self.toc.entries[0].path = Some("NamesBlock.bin".to_string());
         let asset: TextAsset = self.inflate_entry_as(&self.toc.entries[0])?;
-        tracing::trace!("Manifest text ({} bytes):", asset.text.len());
-        tracing::trace!("{}", asset.text);
-        for (i, line) in asset.lines.iter().enumerate() {
-            self.toc.entries[i + 1].path = Some(line.to_string());
+        self.toc.assign_paths(&asset);
+        self.reindex();
+        Ok(())
+    }
+
+    #[cfg(feature = "sng")]
+    pub fn convert_sng_assets_to_json(&self, output_dir: &Path) -> Result<()> {
+        let mut report = ExtractReport::default();
+        self.write_sng_json(output_dir, &ExtractOptions::default(), &mut report, false, None)
+    }
+
+    #[cfg(feature = "sng")]
+    /// Writes every selected SNG arrangement as `<name>.sng.json` into `report.written`, or
+    /// one `<name>.sng.d<level>.json` per level when `options.difficulty` is `All`.
+    /// With `keep_going`, arrangements that fail to decode are recorded in
+    /// `report.conversion_failed` instead of aborting.
+    fn write_sng_json(
+        &self,
+        output_dir: &Path,
+        options: &ExtractOptions,
+        report: &mut ExtractReport,
+        keep_going: bool,
+        mut claims: Option<&mut OutputClaims>,
+    ) -> Result<()> {
+        for entry in &self.toc.entries {
+            if converts_to_json(entry, options) {
+                self.write_sng_entry_json(entry, output_dir, options, report, keep_going, claims.as_deref_mut())?;
+            }
         }
         Ok(())
     }
 
-    pub fn convert_sng_assets_to_json(&mut self, output_dir: &Path) -> io::Result<()> {
-        if self.toc.entries.is_empty() {
+    #[cfg(feature = "sng")]
+    /// Writes the JSON exports of one SNG entry (see `write_sng_json`).
+    fn write_sng_entry_json(
+        &self,
+        entry: &PsarcTOCEntry,
+        output_dir: &Path,
+        options: &ExtractOptions,
+        report: &mut ExtractReport,
+        keep_going: bool,
+        mut claims: Option<&mut OutputClaims>,
+    ) -> Result<()> {
+        let Some(path) = entry.path.as_deref() else {
             return Ok(());
-        }
+        };
+        let difficulty = match options.difficulty {
+            None => None,
+            Some(DifficultySelection::Max) => Some(i32::MAX),
+            Some(DifficultySelection::Level(level)) => Some(level),
+            Some(DifficultySelection::All) => {
+                if options.past_deadline() {
+                    report.not_started.push(format!("{}.json", path));
+                    return Ok(());
+                }
+                let asset = match self.read_sng_entry(entry) {
+                    Ok(asset) => asset,
+                    Err(err) if keep_going => {
+                        tracing::warn!("Failed to convert {}: {}", path, err);
+                        report.conversion_failed.push((path.to_string(), err.into()));
+                        return Ok(());
+                    }
+                    Err(err) => return Err(err),
+                };
+                for level in 0..=asset.max_level() {
+                    let label = format!("{}.d{}.json", path, level);
+                    let output = SngOutput { label: &label, entry_path: path, output_dir, options, keep_going };
+                    self.write_sng_output(output, report, claims.as_deref_mut(), |out| asset.write_json(out, Some(level)))?;
+                }
+                return Ok(());
+            }
+        };
+        let label = format!("{}.json", path);
+        let kind = match difficulty {
+            None => cache::SNG_JSON.to_string(),
+            Some(level) => format!("{}-d{}", cache::SNG_JSON, level),
+        };
+        let output = SngOutput { label: &label, entry_path: path, output_dir, options, keep_going };
+        let platform = self.platform();
+        let parse = |data: &[u8]| -> Result<SngAsset> {
+            let asset = SngAsset::decrypt(data, platform, ParseOptions::default())?;
+            tracing::trace!(
+                "Converted SNG asset from {} (metadata: {:?})",
+                path,
+                asset.metadata
+            );
+            Ok(asset)
+        };
+        self.write_sng_output(output, report, claims, |out| {
+            let data = self.inflate_entry_data(entry)?;
+            match options.cache.as_ref() {
+                // Cached exports are kept whole to be stored.
+                Some(cache) => {
+                    let json = convert_cached(Some(cache), &kind, &data, |data| Ok(parse(data)?.to_json_pretty(difficulty)?))?;
+                    out.write_all(&json)?;
+                    Ok(())
+                }
+                None => parse(&data)?.write_json(out, difficulty),
+            }
+        })
+    }
 
-        for entry in &self.toc.entries {
-            if let Some(ref path) = entry.path {
-                if path.ends_with(".sng") {
-                    let asset: SngAsset = self.inflate_entry_as(entry)?;
-                    tracing::trace!(
-                        "Converted SNG asset from {} (metadata: {:?})",
-                        path,
-                        asset.metadata
-                    );
-                    let json = serde_json::to_string_pretty(&asset)
-                        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-
-                    let file_name = Path::new(path)
-                        .file_name()
-                        .expect("Entry path should have a file name");
-                    let output_file_name = format!("{}.json", file_name.to_string_lossy());
-                    let output_file_path = output_dir.join(output_file_name);
-                    
-                    fs::write(&output_file_path, json)?;
-                    tracing::info!("Written JSON asset to {:?}", output_file_path);
+    #[cfg(feature = "sng")]
+    /// Writes one SNG JSON export unless the deadline passed, the file is kept or an
+    /// earlier run wrote it, running `export` only when the file is actually written.
+    /// `export` streams the JSON into the file, compressed with `options.compress_output`
+    /// which adds its extension to the name. With `keep_going`, any failure of `export`, a failed write
+    /// included, is recorded in `report.conversion_failed` and the partial file removed.
+    fn write_sng_output(
+        &self,
+        output: SngOutput,
+        report: &mut ExtractReport,
+        mut claims: Option<&mut OutputClaims>,
+        export: impl FnOnce(&mut dyn Write) -> Result<()>,
+    ) -> Result<()> {
+        let SngOutput { label, entry_path, output_dir, options, keep_going } = output;
+        let compressed;
+        let label = match options.compress_output {
+            Some(compression) => {
+                compressed = format!("{}.{}", label, compression.extension());
+                compressed.as_str()
+            }
+            None => label,
+        };
+        if options.past_deadline() {
+            report.not_started.push(label.to_string());
+            return Ok(());
+        }
+        let output_file_path = match options.output_path(output_dir, label) {
+            Some(output_path) => match claims.as_mut() {
+                Some(claims) => claims.claim(output_path, label),
+                None => output_path,
+            },
+            None => return Ok(()),
+        };
+        if !options.overwrite && output_file_path.exists() {
+            tracing::trace!("Keeping existing {:?}", output_file_path);
+            return Ok(());
+        }
+        if claims.as_ref().is_some_and(|claims| claims.resumed(label)) {
+            tracing::trace!("Keeping {:?} from an earlier run", output_file_path);
+            report.written.push(output_file_path);
+            return Ok(());
+        }
+        let mut digest = [0u8; 16];
+        let written: Result<()> = options.create_output(&output_file_path, |file| {
+            let mut hashed = HashingWriter { inner: file, md5: Md5::new() };
+            match options.compress_output {
+                Some(compression) => {
+                    // The serializer writes a few bytes at a time, too little for the encoder.
+                    let mut encoder = std::io::BufWriter::new(compression.encoder(&mut hashed));
+                    export(&mut encoder)?;
+                    encoder.into_inner().map_err(std::io::Error::from)?.finish()?;
                 }
+                None => export(&mut hashed)?,
             }
+            digest = hashed.md5.finish();
+            Ok(())
+        });
+        match written {
+            Ok(()) => {}
+            Err(err) if keep_going => {
+                tracing::warn!("Failed to convert {}: {}", entry_path, err);
+                if let Some(claims) = claims.as_mut() {
+                    claims.release(&output_file_path);
+                }
+                report.conversion_failed.push((entry_path.to_string(), err.into()));
+                return Ok(());
+            }
+            Err(err) => return Err(err),
+        }
+        if let Some(claims) = claims.as_mut() {
+            claims.record_digest(label, &output_file_path, &digest)?;
         }
+        tracing::info!("Written JSON asset to {:?}", output_file_path);
+        report.written.push(output_file_path);
         Ok(())
     }
 
-    pub fn dump_entries(&mut self, output_dir: &Path) -> io::Result<()> {
+    /// Extracts the entries whose path matches the glob `pattern` (`songs/arr/*.sng`,
+    /// `*.xml`) into `output_dir`, with the default options otherwise.
+    pub fn extract_matching(&self, pattern: &str, output_dir: &Path) -> Result<Vec<PathBuf>> {
+        let options = ExtractOptions::new().matching(GlobPattern::new(pattern)?);
+        self.dump_entries(output_dir, &options)
+    }
+
+    /// Extracts the entries selected by `options` into `output_dir` and returns every file
+    /// written, including the SNG JSON conversions when enabled.
+    pub fn dump_entries(&self, output_dir: &Path, options: &ExtractOptions) -> Result<Vec<PathBuf>> {
+        self.extract_into(output_dir, options, false, None, &mut |_, _| {}).map(|report| report.written)
+    }
+
+    /// Like `dump_entries`, but entries that cannot be inflated and SNG arrangements that
+    /// cannot be converted are recorded in the report instead of stopping the extraction.
+    /// Errors writing to `output_dir` still abort.
+    pub fn extract_entries(&self, output_dir: &Path, options: &ExtractOptions) -> Result<ExtractReport> {
+        self.extract_into(output_dir, options, true, None, &mut |_, _| {})
+    }
+
+    /// `extract_entries` in priority order, calling `on_stage` with the report so far each
+    /// time a stage is done. A UI can show the song info as soon as `ExtractStage::Metadata`
+    /// is reported while the audio is still being written.
+    pub fn extract_staged(
+        &self,
+        output_dir: &Path,
+        options: &ExtractOptions,
+        mut on_stage: impl FnMut(ExtractStage, &ExtractReport),
+    ) -> Result<ExtractReport> {
+        let options = options.clone().priority_order(true);
+        self.extract_into(output_dir, &options, true, None, &mut on_stage)
+    }
+
+    /// `extract_entries` for one archive of a batch: outputs already claimed by an earlier
+    /// archive are renamed through `claims`.
+    pub(crate) fn extract_claimed(
+        &self,
+        output_dir: &Path,
+        options: &ExtractOptions,
+        claims: &mut OutputClaims,
+    ) -> Result<ExtractReport> {
+        self.extract_into(output_dir, options, true, Some(claims), &mut |_, _| {})
+    }
+
+    /// `extract_entries` spread over `threads` worker threads, or one per core when 0:
+    /// entries are inflated, written and converted to JSON concurrently. Every failure,
+    /// including a failed write, is recorded for its entry in the report instead of
+    /// stopping the extraction; only creating `output_dir` aborts. `link_duplicates`,
+    /// `priority_order` and the deadline's stage order are not applied: each entry is
+    /// written on its own, in no particular order, and the report lists them in TOC order.
+    pub fn extract_entries_parallel(
+        &self,
+        output_dir: &Path,
+        options: &ExtractOptions,
+        threads: usize,
+    ) -> Result<ExtractReport> {
         fs::create_dir_all(output_dir)?;
+        let options = &options.clone().link_duplicates(false);
+        // Entries sharing an output are written by the last one, like `extract_entries`.
+        let mut outputs: HashMap<PathBuf, usize> = HashMap::new();
+        let mut jobs: Vec<(&PsarcTOCEntry, Option<PathBuf>)> = Vec::new();
         for entry in &self.toc.entries {
-            if let Some(path) = &entry.path {
+            let Some(path) = entry.path.as_deref().filter(|_| options.selects_entry(entry)) else {
+                continue;
+            };
+            let output_path = options.output_path(output_dir, path).filter(|output_path| {
+                let keep = !options.overwrite && output_path.exists();
+                if keep {
+                    tracing::trace!("Keeping existing {:?}", output_path);
+                }
+                !keep
+            });
+            if let Some(output_path) = &output_path {
+                if let Some(earlier) = outputs.insert(output_path.clone(), jobs.len()) {
+                    jobs[earlier].1 = None;
+                }
+            }
+            jobs.push((entry, output_path));
+        }
+
+        let threads = match threads {
+            0 => thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        }
+        .min(jobs.len().max(1));
+        let next = AtomicUsize::new(0);
+        let mut done: Vec<(usize, ExtractReport)> = thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut done = Vec::new();
+                        while let Some((entry, output_path)) = jobs.get(next.fetch_add(1, Ordering::Relaxed)) {
+                            let report = self.extract_entry(entry, output_path.as_deref(), output_dir, options);
+                            done.push((entry.index as usize, report));
+                        }
+                        done
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .flat_map(|worker| worker.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic)))
+                .collect()
+        });
+        done.sort_by_key(|(index, _)| *index);
+
+        let mut report = ExtractReport::default();
+        for (_, part) in done {
+            report.merge(part);
+        }
+        self.write_previews(output_dir, options, &mut report, true, None)?;
+        Ok(report)
+    }
+
+    /// One job of `extract_entries_parallel`: writes `entry` to `output_path` when given,
+    /// then its JSON exports.
+    fn extract_entry(
+        &self,
+        entry: &PsarcTOCEntry,
+        output_path: Option<&Path>,
+        output_dir: &Path,
+        options: &ExtractOptions,
+    ) -> ExtractReport {
+        let mut report = ExtractReport::default();
+        let Some(path) = entry.path.as_deref() else {
+            return report;
+        };
+        if let Some(output_path) = output_path {
+            if options.past_deadline() {
+                report.not_started.push(path.to_string());
+            } else {
                 tracing::trace!("Dumping entry: {}", path);
-                let data = self.inflate_entry_data(entry)?;
-                let output_path = output_dir.join(Path::new(path).file_name().unwrap());
-                let mut file = fs::File::create(&output_path)?;
-                file.write_all(&data)?;
-                tracing::info!("Data dumped to {:?}", output_path);
+                let written = self.inflate_entry_data(entry).and_then(|data| {
+                    if !options.selects_content(path, ContentType::sniff(&data)) {
+                        tracing::trace!("Skipping {}: not in the selected asset classes", path);
+                        return Ok(false);
+                    }
+                    options.write_output(output_path, &data)?;
+                    Ok(true)
+                });
+                match written {
+                    Ok(true) => {
+                        tracing::info!("Data dumped to {:?}", output_path);
+                        report.written.push(output_path.to_path_buf());
+                    }
+                    Ok(false) => {}
+                    Err(err) => {
+                        tracing::warn!("Failed to extract {}: {}", path, err);
+                        report.failed.push((path.to_string(), err.into()));
+                    }
+                }
+            }
+        }
+        #[cfg(feature = "sng")]
+        if options.convert_sng_to_json && converts_to_json(entry, options) {
+            if let Err(err) = self.write_sng_entry_json(entry, output_dir, options, &mut report, true, None) {
+                tracing::warn!("Failed to write the JSON export of {}: {}", path, err);
+                report.conversion_failed.push((path.to_string(), err.into()));
+            }
+        }
+        #[cfg(not(feature = "sng"))]
+        let _ = output_dir;
+        report
+    }
+
+    fn extract_into(
+        &self,
+        output_dir: &Path,
+        options: &ExtractOptions,
+        keep_going: bool,
+        mut claims: Option<&mut OutputClaims>,
+        on_stage: &mut dyn FnMut(ExtractStage, &ExtractReport),
+    ) -> Result<ExtractReport> {
+        fs::create_dir_all(output_dir)?;
+        let mut report = ExtractReport::default();
+        let mut writer = OutputWriter::new(options);
+        // First output written for each distinct content, keyed by length and MD5.
+        let mut by_content: HashMap<(usize, [u8; 16]), PathBuf> = HashMap::new();
+        let mut entries: Vec<&PsarcTOCEntry> = self.toc.entries.iter().collect();
+        let staged = options.staged();
+        if staged {
+            entries.sort_by_key(|e| e.path.as_deref().map_or(ExtractStage::Other, ExtractStage::of));
+        }
+        // Stages not finished yet, in order.
+        let mut stages = ExtractStage::ALL.iter().copied().peekable();
+        for entry in entries {
+            let path = match &entry.path {
+                Some(path) if options.selects_entry(entry) => path,
+                _ => continue,
+            };
+            if staged {
+                let stage = ExtractStage::of(path);
+                while let Some(done) = stages.next_if(|s| *s < stage) {
+                    writer.flush()?;
+                    self.finish_stage(done, output_dir, options, &mut report, keep_going, claims.as_deref_mut(), on_stage)?;
+                }
+            }
+            if options.past_deadline() {
+                report.not_started.push(path.clone());
+                continue;
+            }
+            let output_path = match options.output_path(output_dir, path) {
+                Some(output_path) => match claims.as_mut() {
+                    Some(claims) => claims.claim(output_path, path),
+                    None => output_path,
+                },
+                None => {
+                    tracing::warn!("Skipping entry without a file name: {}", path);
+                    continue;
+                }
+            };
+            if !options.overwrite && (output_path.exists() || writer.queued(&output_path)) {
+                tracing::trace!("Keeping existing {:?}", output_path);
+                continue;
+            }
+            if claims.as_ref().is_some_and(|claims| claims.resumed(path)) {
+                tracing::trace!("Keeping {:?} from an earlier run", output_path);
+                report.written.push(output_path);
+                continue;
+            }
+            tracing::trace!("Dumping entry: {}", path);
+            let data = match self.inflate_entry_data(entry) {
+                Ok(data) => data,
+                Err(err) if keep_going => {
+                    tracing::warn!("Failed to inflate {}: {}", path, err);
+                    if let Some(claims) = claims.as_mut() {
+                        claims.release(&output_path);
+                    }
+                    report.failed.push((path.clone(), err.into()));
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+            if !options.selects_content(path, ContentType::sniff(&data)) {
+                tracing::trace!("Skipping {}: not in the selected asset classes", path);
+                if let Some(claims) = claims.as_mut() {
+                    claims.release(&output_path);
+                }
+                continue;
+            }
+            if options.link_duplicates {
+                let key = (data.len(), md5(&data));
+                if let Some(original) = by_content.get(&key).filter(|original| **original != output_path) {
+                    writer.flush()?;
+                    if options.link_output(original, &output_path)? {
+                        if let Some(claims) = claims.as_mut() {
+                            claims.record(path, &output_path, &data)?;
+                        }
+                        tracing::info!("Linked {:?} to {:?}", output_path, original);
+                        report.linked.push(output_path.clone());
+                        report.written.push(output_path);
+                        continue;
+                    }
+                } else {
+                    by_content.insert(key, output_path.clone());
+                }
+            }
+            // A resumed job checks its recorded outputs on disk, so an output recorded
+            // before a crash could finish writing it is written again.
+            if let Some(claims) = claims.as_mut() {
+                claims.record(path, &output_path, &data)?;
+            }
+            writer.write(&output_path, data)?;
+            tracing::info!("Data dumped to {:?}", output_path);
+            report.written.push(output_path);
+        }
+        writer.flush()?;
+        if staged {
+            for done in stages {
+                self.finish_stage(done, output_dir, options, &mut report, keep_going, claims.as_deref_mut(), on_stage)?;
+            }
+        } else {
+            self.convert_charts(output_dir, options, &mut report, keep_going, claims.as_deref_mut())?;
+            self.write_previews(output_dir, options, &mut report, keep_going, claims)?;
+        }
+        Ok(report)
+    }
+
+    /// Ends a stage of a priority-ordered extraction: charts get their JSON conversion
+    /// before the audio starts, then `on_stage` is told.
+    #[allow(clippy::too_many_arguments)]
+    fn finish_stage(
+        &self,
+        stage: ExtractStage,
+        output_dir: &Path,
+        options: &ExtractOptions,
+        report: &mut ExtractReport,
+        keep_going: bool,
+        claims: Option<&mut OutputClaims>,
+        on_stage: &mut dyn FnMut(ExtractStage, &ExtractReport),
+    ) -> Result<()> {
+        match stage {
+            ExtractStage::Charts => self.convert_charts(output_dir, options, report, keep_going, claims)?,
+            ExtractStage::Audio => self.write_previews(output_dir, options, report, keep_going, claims)?,
+            _ => {}
+        }
+        on_stage(stage, report);
+        Ok(())
+    }
+
+    /// Writes the previews requested by `options.synthesize_preview`.
+    fn write_previews(
+        &self,
+        output_dir: &Path,
+        options: &ExtractOptions,
+        report: &mut ExtractReport,
+        keep_going: bool,
+        mut claims: Option<&mut OutputClaims>,
+    ) -> Result<()> {
+        let Some(seconds) = options.synthesize_preview else {
+            return Ok(());
+        };
+        #[cfg(not(all(feature = "sng", feature = "audio")))]
+        {
+            let _ = (seconds, output_dir, report, keep_going, &mut claims);
+            tracing::warn!("Preview synthesis requested but the `sng` or `audio` feature is disabled");
+        }
+        #[cfg(all(feature = "sng", feature = "audio"))]
+        let converter = AudioConverter::new().cache(options.cache.clone());
+        #[cfg(all(feature = "sng", feature = "audio"))]
+        for song in Song::list(self) {
+            if !song.preview_audio().is_empty() {
+                continue;
+            }
+            let stem = format!("{}_preview", song.key);
+            if options.past_deadline() {
+                report.not_started.push(stem);
+                continue;
+            }
+            // The format follows the codec of the main track: Ogg for Vorbis, WAV for PCM.
+            let exists = |extension: &str| {
+                options
+                    .output_path(output_dir, &format!("{}.{}", stem, extension))
+                    .is_some_and(|path| path.exists())
+            };
+            if !options.overwrite && (exists("ogg") || exists("wav")) {
+                continue;
+            }
+            let preview = match song.synthesize_preview(seconds, &converter) {
+                Ok(preview) => preview,
+                Err(err) if err.kind() == io::ErrorKind::Unsupported => {
+                    tracing::warn!("Cannot synthesize a preview for {}: {}", song.key, err);
+                    continue;
+                }
+                Err(err) if keep_going => {
+                    tracing::warn!("Failed to synthesize a preview for {}: {}", song.key, err);
+                    report.conversion_failed.push((stem, err));
+                    continue;
+                }
+                Err(err) => return Err(err.into()),
+            };
+            let name = format!("{}.{}", stem, preview.extension());
+            let Some(output_path) = options.output_path(output_dir, &name) else {
+                continue;
+            };
+            let output_path = match claims.as_mut() {
+                Some(claims) => claims.claim(output_path, &name),
+                None => output_path,
+            };
+            options.write_output(&output_path, preview.data())?;
+            if let Some(claims) = claims.as_mut() {
+                claims.record(&name, &output_path, preview.data())?;
             }
+            tracing::info!("Synthesized preview {:?}", output_path);
+            report.written.push(output_path);
+        }
+        Ok(())
+    }
+
+    fn convert_charts(
+        &self,
+        output_dir: &Path,
+        options: &ExtractOptions,
+        report: &mut ExtractReport,
+        keep_going: bool,
+        claims: Option<&mut OutputClaims>,
+    ) -> Result<()> {
+        #[cfg(feature = "sng")]
+        if options.convert_sng_to_json {
+            self.write_sng_json(output_dir, options, report, keep_going, claims)?;
+        }
+        #[cfg(not(feature = "sng"))]
+        if options.convert_sng_to_json {
+            let _ = (output_dir, report, keep_going, claims);
+            tracing::warn!("SNG to JSON conversion requested but the `sng` feature is disabled");
         }
         Ok(())
     }
@@ -527,19 +2153,220 @@ impl PsarcFile {
 /// This function mimics the C# UnzipBlock method by:
 /// 1. Skipping the first 2 bytes (the header bytes).
 /// 2. Reading the remaining bytes (size - 2) from the input.
-/// 3. Decompressing the data using DeflateDecoder.
-///
-pub fn unzip_block<R: Read + Seek>(reader: &mut R, size: usize) -> io::Result<Vec<u8>> {
+/// 3. Decompressing the data using DeflateDecoder, into at most `block_size` bytes: no
+///    block of the archive holds more, so a block inflating further is rejected.
+pub fn unzip_block<R: Read + Seek>(reader: &mut R, size: usize, block_size: usize) -> Result<Vec<u8>> {
     reader.seek(SeekFrom::Current(2))?;
     let comp_size = size.checked_sub(2)
-        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Size must be at least 2"))?;
+        .ok_or_else(|| PsarcError::BadToc("compressed block size must be at least 2".to_string()))?;
     
-    let mut comp_data = vec![0u8; comp_size];
-    reader.read_exact(&mut comp_data)?;
+    // The size comes from the block size table: read what is there rather than allocating
+    // for it up front.
+    let mut comp_data = Vec::new();
+    reader.take(comp_size as u64).read_to_end(&mut comp_data)?;
+    if comp_data.len() < comp_size {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "compressed block runs past the end of the data").into());
+    }
 
-    let mut decoder = DeflateDecoder::new(&comp_data[..]);
+    let mut decoder = DeflateDecoder::new(&comp_data[..]).take(block_size as u64 + 1);
     let mut decompressed = Vec::new();
-    decoder.read_to_end(&mut decompressed)?;
+    decoder.read_to_end(&mut decompressed).map_err(|err| PsarcError::Decompression(err.to_string()))?;
+    if decompressed.len() > block_size {
+        return Err(PsarcError::Decompression(format!("block inflates past the {} byte block size", block_size)));
+    }
     
     Ok(decompressed)
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout(path: &str, length: u64, block_sizes: Vec<u32>) -> TocLayoutEntry {
+        TocLayoutEntry { path: path.to_string(), length, block_sizes }
+    }
+
+    /// The header and TOC of an unencrypted archive laid out by `toc`, as `PsarcWriter`
+    /// writes them.
+    fn archive_bytes(toc: &PsarcTOC, block_size: u32) -> Vec<u8> {
+        let toc_size = PsarcTOC::byte_size(toc.entries.len(), toc.zip_block_sizes.len(), block_size).unwrap();
+        let mut bytes = b"PSAR".to_vec();
+        bytes.extend_from_slice(&0x0001_0004u32.to_be_bytes());
+        bytes.extend_from_slice(b"zlib");
+        for field in [toc_size, TOC_ENTRY_SIZE, toc.entries.len() as u32, block_size, 0] {
+            bytes.extend_from_slice(&field.to_be_bytes());
+        }
+        bytes.extend_from_slice(&toc.to_bytes(block_size).unwrap());
+        assert_eq!(bytes.len(), toc_size as usize);
+        bytes
+    }
+
+    fn read_back(bytes: &[u8]) -> PsarcTOC {
+        let mut reader = Cursor::new(bytes);
+        let header = PsarcFileHeader::read_from(&mut reader).unwrap();
+        PsarcTOC::read_from(&mut reader, &header).unwrap()
+    }
+
+    #[test]
+    fn rebuild_hashes_paths() {
+        let entries = [
+            layout("NamesBlock.bin", 30, vec![30]),
+            layout("songs/bin/generic/x_lead.sng", 10, vec![10]),
+            layout("manifests/songs_dlc_x/x_lead.json", 10, vec![10]),
+        ];
+        let toc = PsarcTOC::rebuild(&entries, 65536).unwrap();
+        assert_eq!(toc.entries[0].hash, "00000000000000000000000000000000");
+        for (entry, layout) in toc.entries.iter().zip(&entries).skip(1) {
+            assert_eq!(entry.hash, PsarcFile::path_hash(&layout.path));
+            assert_eq!(entry.path.as_deref(), Some(layout.path.as_str()));
+        }
+        assert_eq!(PsarcFile::path_hash("songs/bin/generic/x_lead.sng"), digest::path_hash("songs/bin/generic/x_lead.sng"));
+        assert!(!toc.encrypted);
+    }
+
+    #[test]
+    fn rebuild_lays_out_blocks() {
+        let block_size = 65536;
+        let entries = [
+            layout("NamesBlock.bin", 40, vec![25]),
+            layout("a", 3 * 65536 + 5, vec![0, 900, 0, 5]),
+            layout("b", 65536, vec![0]),
+            layout("c", 70_000, vec![1200, 300]),
+        ];
+        let toc = PsarcTOC::rebuild(&entries, block_size).unwrap();
+        let data_start = PsarcTOC::byte_size(4, 8, block_size).unwrap() as u64;
+        assert_eq!(data_start, 32 + 4 * 30 + 8 * 2);
+        let starts: Vec<_> = toc.entries.iter().map(|e| (e.start_block, e.offset)).collect();
+        assert_eq!(
+            starts,
+            [
+                (0, data_start),
+                (1, data_start + 25),
+                (5, data_start + 25 + 2 * 65536 + 905),
+                (6, data_start + 25 + 3 * 65536 + 905),
+            ]
+        );
+        assert_eq!(toc.zip_block_sizes, [25, 0, 900, 0, 5, 0, 1200, 300]);
+        for (entry, layout) in toc.entries.iter().zip(&entries) {
+            assert_eq!(toc.stored_length(entry, block_size), layout.stored_length(block_size));
+        }
+    }
+
+    #[test]
+    fn rebuild_zero_length_entries() {
+        let entries = [layout("NamesBlock.bin", 4, vec![4]), layout("empty", 0, vec![]), layout("after", 2, vec![2])];
+        let toc = PsarcTOC::rebuild(&entries, 65536).unwrap();
+        assert_eq!((toc.entries[1].start_block, toc.entries[1].length), (1, 0));
+        assert_eq!(toc.entries[1].offset, toc.entries[2].offset);
+        assert_eq!(toc.entries[2].start_block, 1);
+        assert_eq!(toc.stored_length(&toc.entries[1], 65536), 0);
+        // An empty entry listing a block is inconsistent.
+        assert!(PsarcTOC::rebuild(&[layout("NamesBlock.bin", 0, vec![]), layout("empty", 0, vec![7])], 65536).is_err());
+    }
+
+    #[test]
+    fn table_widths() {
+        for (block_size, width) in [(65536, 2), (1 << 20, 3), (1 << 28, 4)] {
+            let entries = [layout("NamesBlock.bin", 4, vec![4]), layout("a", block_size as u64 + 1, vec![0, 1])];
+            let toc = PsarcTOC::rebuild(&entries, block_size).unwrap();
+            assert_eq!(toc.to_bytes(block_size).unwrap().len(), 2 * 30 + 3 * width, "block size {}", block_size);
+            assert_eq!(
+                PsarcTOC::byte_size(2, 3, block_size).unwrap(),
+                HEADER_SIZE + 2 * TOC_ENTRY_SIZE + 3 * width as u32
+            );
+        }
+        assert!(PsarcTOC::byte_size(1, 1, 0).is_err());
+        // A stored size too wide for the table is refused.
+        let toc = PsarcTOC::rebuild(&[layout("NamesBlock.bin", 4, vec![70_000])], 65536).unwrap();
+        assert!(toc.to_bytes(65536).is_err());
+    }
+
+    #[test]
+    fn read_toc_round_trip() {
+        for block_size in [65536, 1 << 20, 1 << 28] {
+            let entries = [
+                layout("NamesBlock.bin", 40, vec![25]),
+                layout("a", 2 * block_size as u64 + 5, vec![0, 900, 5]),
+                layout("empty", 0, vec![]),
+                layout("c", 7, vec![7]),
+            ];
+            let toc = PsarcTOC::rebuild(&entries, block_size).unwrap();
+            let read = read_back(&archive_bytes(&toc, block_size));
+            assert_eq!(read.zip_block_sizes, toc.zip_block_sizes, "block size {}", block_size);
+            assert_eq!(read.entries.len(), toc.entries.len());
+            for (read, written) in read.entries.iter().zip(&toc.entries) {
+                assert_eq!(
+                    (&read.hash, read.start_block, read.length, read.offset),
+                    (&written.hash, written.start_block, written.length, written.offset)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn read_toc_checks_the_major_version() {
+        let toc = PsarcTOC::rebuild(&[layout("NamesBlock.bin", 10, vec![10])], 65536).unwrap();
+        let mut bytes = archive_bytes(&toc, 65536);
+        bytes[4..8].copy_from_slice(&0x0001_0000u32.to_be_bytes());
+        assert_eq!(read_back(&bytes).entries.len(), 1);
+
+        bytes[4..8].copy_from_slice(&0x0002_0000u32.to_be_bytes());
+        let mut reader = Cursor::new(&bytes);
+        let header = PsarcFileHeader::read_from(&mut reader).unwrap();
+        assert_eq!(header.version_parts(), (2, 0));
+        let err = PsarcTOC::read_from(&mut reader, &header).unwrap_err();
+        assert!(matches!(err, PsarcError::Unsupported(_)), "{}", err);
+    }
+
+    #[test]
+    fn table_width_of_64_and_128_kib_archives() {
+        let entries = |block_size: u32| {
+            [
+                layout("NamesBlock.bin", 40, vec![25]),
+                layout("a", 2 * block_size as u64 + 5, vec![0, 900, 5]),
+                layout("c", 7, vec![7]),
+            ]
+        };
+        // 64 KiB blocks: 2 bytes whichever way the table is sized.
+        let toc = PsarcTOC::rebuild(&entries(65536), 65536).unwrap();
+        assert_eq!(read_back(&archive_bytes(&toc, 65536)).zip_block_sizes, toc.zip_block_sizes);
+
+        // 128 KiB blocks: the writer's 3-byte table...
+        let toc = PsarcTOC::rebuild(&entries(1 << 17), 1 << 17).unwrap();
+        let written = archive_bytes(&toc, 1 << 17);
+        assert_eq!(written.len(), 32 + 3 * 30 + 5 * 3);
+        assert_eq!(read_back(&written).zip_block_sizes, toc.zip_block_sizes);
+
+        // ...and the 2-byte table archives have always been read with.
+        let mut legacy = written[..32 + 3 * 30].to_vec();
+        legacy[12..16].copy_from_slice(&(32u32 + 3 * 30 + 5 * 2).to_be_bytes());
+        legacy.extend_from_slice(&toc.to_bytes_in(2).unwrap()[3 * 30..]);
+        let read = read_back(&legacy);
+        assert_eq!(read.zip_block_sizes, toc.zip_block_sizes);
+        assert_eq!(read.entries[2].offset, toc.entries[2].offset);
+
+        // A 2-byte table with slots to spare is still read as one.
+        let mut spare = legacy.clone();
+        spare[12..16].copy_from_slice(&(32u32 + 3 * 30 + 6 * 2).to_be_bytes());
+        spare.extend_from_slice(&[0, 0]);
+        assert_eq!(read_back(&spare).zip_block_sizes.len(), 6);
+    }
+
+    #[test]
+    fn rebuild_errors() {
+        // More entries than a 32-bit TOC size can hold.
+        assert!(PsarcTOC::byte_size(200_000_000, 0, 65536).is_err());
+        assert!(PsarcTOC::byte_size(0, usize::MAX / 4, 65536).is_err());
+        // Lengths and offsets are 40-bit.
+        let mut toc = PsarcTOC::rebuild(&[layout("NamesBlock.bin", 0, vec![])], 65536).unwrap();
+        toc.entries[0].length = 1 << 40;
+        assert!(toc.to_bytes(65536).is_err());
+        toc.entries[0].length = (1 << 40) - 1;
+        toc.entries[0].offset = 1 << 40;
+        assert!(toc.to_bytes(65536).is_err());
+        toc.entries[0].offset = 0;
+        toc.entries[0].hash = "not hex".to_string();
+        assert!(toc.to_bytes(65536).is_err());
+        // Blocks must match the length.
+        assert!(PsarcTOC::rebuild(&[layout("NamesBlock.bin", 65537, vec![0])], 65536).is_err());
+    }
+}
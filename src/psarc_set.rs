@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::psarc::{PsarcFile, PsarcTOCEntry};
+
+/// An ordered stack of archives resolved like the game does: a base pack first, then
+/// update/patch archives on top. When several layers contain the same entry path, the
+/// topmost (most recently pushed) layer wins.
+#[derive(Debug, Default)]
+pub struct PsarcSet {
+    layers: Vec<(PathBuf, PsarcFile)>,
+}
+
+/// True when an archive file name looks like an update or patch pack.
+pub fn is_patch_archive(path: &Path) -> bool {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_lowercase())
+        .is_some_and(|name| name.contains("patch") || name.contains("update"))
+}
+
+/// Finds every `.psarc` file below `dir` (recursively), sorted by path.
+pub fn find_archives(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut archives = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        for entry in fs::read_dir(&current)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("psarc")) {
+                archives.push(path);
+            }
+        }
+    }
+    archives.sort();
+    Ok(archives)
+}
+
+impl PsarcSet {
+    /// Creates an empty set.
+    pub fn new() -> Self {
+        PsarcSet::default()
+    }
+
+    /// Opens every archive in `paths`. Base packs are layered first and patch archives (see
+    /// `is_patch_archive`) on top; otherwise the given order is kept.
+    pub fn open_paths<P: AsRef<Path>>(paths: &[P]) -> io::Result<Self> {
+        let mut ordered: Vec<&Path> = paths.iter().map(|p| p.as_ref()).collect();
+        ordered.sort_by_key(|p| is_patch_archive(p));
+        let mut set = PsarcSet::new();
+        for path in ordered {
+            set.push(path, PsarcFile::open_path(path)?);
+        }
+        Ok(set)
+    }
+
+    /// Adds an archive on top of the existing layers. Its manifest must already be read.
+    pub fn push(&mut self, source: impl Into<PathBuf>, archive: PsarcFile) {
+        self.layers.push((source.into(), archive));
+    }
+
+    /// The layers from bottom (base) to top (latest patch), with the path each was read from.
+    pub fn layers(&self) -> impl Iterator<Item = (&Path, &PsarcFile)> {
+        self.layers.iter().map(|(path, archive)| (path.as_path(), archive))
+    }
+
+    /// Finds the topmost archive containing `path` and its entry.
+    pub fn resolve(&self, path: &str) -> Option<(&PsarcFile, &PsarcTOCEntry)> {
+        self.layers.iter().rev().find_map(|(_, archive)| {
+            archive
+                .toc
+                .entries
+                .iter()
+                .find(|e| e.path.as_deref() == Some(path))
+                .map(|entry| (archive, entry))
+        })
+    }
+
+    /// Every distinct entry path visible through the set, each resolved to the topmost
+    /// layer that contains it. Entries are listed in the order they first appear.
+    pub fn resolved_entries(&self) -> Vec<(&PsarcFile, &PsarcTOCEntry)> {
+        let mut resolved: Vec<(&PsarcFile, &PsarcTOCEntry)> = Vec::new();
+        let mut positions: HashMap<&str, usize> = HashMap::new();
+        for (_, archive) in &self.layers {
+            for entry in &archive.toc.entries {
+                let path = match entry.path.as_deref() {
+                    Some(path) => path,
+                    None => continue,
+                };
+                match positions.get(path) {
+                    Some(&i) => resolved[i] = (archive, entry),
+                    None => {
+                        positions.insert(path, resolved.len());
+                        resolved.push((archive, entry));
+                    }
+                }
+            }
+        }
+        resolved
+    }
+
+    /// Inflates the topmost version of `path`.
+    pub fn read(&self, path: &str) -> io::Result<Vec<u8>> {
+        let (archive, entry) = self
+            .resolve(path)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("Entry not found: {}", path)))?;
+        Ok(archive.inflate_entry_data(entry)?)
+    }
+}
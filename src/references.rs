@@ -0,0 +1,200 @@
+//! Graph of the references between the entries of a package.
+//!
+//! Packages tie their assets together by name: xblocks and manifests point at charts,
+//! manifests, soundbanks and album art through URNs (`urn:image:dds:album_mysong`), the
+//! aggregate graph (`.nt`) lists the path of every asset, and soundbanks name their wems
+//! by id in the DIDX chunk. Resolving those names gives a graph that can be drawn (DOT)
+//! or processed (JSON), and whose unresolved references are the dangling ones.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+use serde::Serialize;
+
+use crate::psarc::{BkhdAsset, PsarcFile};
+
+/// Extensions of the entries whose text is searched for references.
+const TEXT_EXTENSIONS: [&str; 5] = ["xblock", "json", "hsan", "nt", "xml"];
+
+/// One reference of an entry to another.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct Reference {
+    pub from: String,
+    /// Path of the referenced entry; `None` when nothing in the package matches.
+    pub to: Option<String>,
+    /// The reference as written: a URN, an aggregate graph path or `wem:<id>`.
+    pub target: String,
+}
+
+/// Entries of a package and the references between them.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReferenceGraph {
+    pub entries: Vec<String>,
+    pub references: Vec<Reference>,
+}
+
+/// Lowercase file stem and extension of an entry path.
+fn stem_and_extension(path: &str) -> (String, String) {
+    let path = Path::new(path);
+    let part = |s: Option<&std::ffi::OsStr>| s.map(|s| s.to_string_lossy().to_lowercase()).unwrap_or_default();
+    (part(path.file_stem()), part(path.extension()))
+}
+
+/// Extension of the entries a URN kind names, for the kinds that name package entries.
+fn urn_extension(kind: &str) -> Option<&'static str> {
+    match kind {
+        "image:dds" => Some("dds"),
+        "application:musicgamesong" => Some("sng"),
+        "application:xml" => Some("xml"),
+        "database:json-db" => Some("json"),
+        "database:hsan-db" => Some("hsan"),
+        "audio:wwise-sound-bank" => Some("bnk"),
+        "emergent-world" => Some("xblock"),
+        "application:aggregategraph" => Some("nt"),
+        _ => None,
+    }
+}
+
+/// The URNs (`urn:<kind>:<name>`) written in `text`, as (kind, name).
+fn find_urns(text: &str) -> Vec<(String, String)> {
+    let is_urn_char = |c: char| c.is_ascii_alphanumeric() || matches!(c, ':' | '_' | '-' | '.');
+    text.match_indices("urn:")
+        .filter_map(|(pos, _)| {
+            let end = text[pos..].find(|c: char| !is_urn_char(c)).map_or(text.len(), |n| pos + n);
+            let (kind, name) = text[pos + 4..end].rsplit_once(':')?;
+            (!kind.is_empty() && !name.is_empty()).then(|| (kind.to_lowercase(), name.to_lowercase()))
+        })
+        .collect()
+}
+
+/// The asset paths listed by an aggregate graph (its `relpath` triples).
+fn find_graph_paths(text: &str) -> Vec<String> {
+    text.lines()
+        .filter(|line| line.contains("relpath>"))
+        .filter_map(|line| {
+            let value = line.split_once('"')?.1.rsplit_once('"')?.0;
+            Some(value.trim_start_matches('/').to_lowercase())
+        })
+        .collect()
+}
+
+impl ReferenceGraph {
+    /// Builds the graph of an archive whose manifest has been read.
+    pub fn build(archive: &PsarcFile) -> io::Result<Self> {
+        let entries: Vec<&str> = archive.toc.entries.iter().skip(1).filter_map(|e| e.path.as_deref()).collect();
+        // (stem, extension) → path, for resolving names.
+        let by_name: BTreeMap<(String, String), &str> =
+            entries.iter().map(|path| (stem_and_extension(path), *path)).collect();
+        let by_path: BTreeMap<String, &str> = entries.iter().map(|path| (path.to_lowercase(), *path)).collect();
+        let resolve_name = |name: &str, extension: &str| -> Option<String> {
+            if let Some(path) = by_name.get(&(name.to_string(), extension.to_string())) {
+                return Some(path.to_string());
+            }
+            // Album art names every size at once: `album_x` is `album_x_64.dds`, ...
+            (extension == "dds")
+                .then(|| {
+                    by_name.iter().find(|((stem, ext), _)| {
+                        ext == "dds"
+                            && stem.strip_prefix(name).and_then(|rest| rest.strip_prefix('_')).is_some_and(|size| {
+                                !size.is_empty() && size.chars().all(|c| c.is_ascii_digit())
+                            })
+                    })
+                })
+                .flatten()
+                .map(|(_, path)| path.to_string())
+        };
+
+        let mut references = BTreeSet::new();
+        for entry in archive.toc.entries.iter().skip(1) {
+            let Some(path) = entry.path.as_deref() else {
+                continue;
+            };
+            let (_, extension) = stem_and_extension(path);
+            if TEXT_EXTENSIONS.contains(&extension.as_str()) {
+                let data = archive.inflate_entry_data(entry)?;
+                let text = String::from_utf8_lossy(&data);
+                for (kind, name) in find_urns(&text) {
+                    let Some(target_extension) = urn_extension(&kind) else {
+                        continue;
+                    };
+                    let to = resolve_name(&name, target_extension);
+                    if to.as_deref() != Some(path) {
+                        references.insert(Reference { from: path.to_string(), to, target: format!("urn:{}:{}", kind, name) });
+                    }
+                }
+                if extension == "nt" {
+                    for listed in find_graph_paths(&text) {
+                        let to = by_path.get(&listed).map(|p| p.to_string());
+                        references.insert(Reference { from: path.to_string(), to, target: listed });
+                    }
+                }
+            } else if extension == "bnk" {
+                match archive.inflate_entry_as::<BkhdAsset>(entry) {
+                    Ok(bank) => {
+                        for didx in &bank.didx {
+                            let to = resolve_name(&didx.wem_id.to_string(), "wem");
+                            references.insert(Reference { from: path.to_string(), to, target: format!("wem:{}", didx.wem_id) });
+                        }
+                    }
+                    Err(err) => tracing::warn!("Could not parse soundbank {}: {}", path, err),
+                }
+            }
+        }
+        Ok(ReferenceGraph {
+            entries: entries.iter().map(|p| p.to_string()).collect(),
+            references: references.into_iter().collect(),
+        })
+    }
+
+    /// References that name nothing in the package.
+    pub fn dangling(&self) -> impl Iterator<Item = &Reference> {
+        self.references.iter().filter(|r| r.to.is_none())
+    }
+
+    /// Entries no other entry references.
+    pub fn unreferenced(&self) -> Vec<&str> {
+        let referenced: BTreeSet<&str> = self.references.iter().filter_map(|r| r.to.as_deref()).collect();
+        self.entries.iter().map(String::as_str).filter(|e| !referenced.contains(e)).collect()
+    }
+
+    /// The graph in Graphviz DOT. Dangling references point at red dashed nodes.
+    pub fn to_dot(&self) -> String {
+        let quote = |text: &str| format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""));
+        let mut dot = String::from("digraph package {\n    rankdir=LR;\n    node [shape=box, fontsize=10];\n");
+        for entry in &self.entries {
+            dot.push_str(&format!("    {};\n", quote(entry)));
+        }
+        for reference in &self.references {
+            match &reference.to {
+                Some(to) => dot.push_str(&format!("    {} -> {};\n", quote(&reference.from), quote(to))),
+                None => {
+                    let missing = quote(&reference.target);
+                    dot.push_str(&format!("    {} [color=red, style=dashed];\n", missing));
+                    dot.push_str(&format!("    {} -> {} [color=red];\n", quote(&reference.from), missing));
+                }
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+/// Writes the reference graph of an archive: DOT for a `.dot` or `.gv` path, JSON otherwise.
+pub fn export_reference_graph(archive: &PsarcFile, output_path: &Path) -> io::Result<()> {
+    let graph = ReferenceGraph::build(archive)?;
+    let dot = output_path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("dot") || ext.eq_ignore_ascii_case("gv"));
+    let data = if dot {
+        graph.to_dot().into_bytes()
+    } else {
+        serde_json::to_vec_pretty(&graph).map_err(io::Error::other)?
+    };
+    fs::write(output_path, data)?;
+    tracing::info!(
+        "Written reference graph of {} entries ({} dangling references) to {:?}",
+        graph.entries.len(),
+        graph.dangling().count(),
+        output_path
+    );
+    Ok(())
+}
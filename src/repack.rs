@@ -0,0 +1,981 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use serde::Serialize;
+
+#[cfg(feature = "image")]
+use crate::dds::{encode_dds, RgbaImage};
+#[cfg(any(feature = "image", feature = "sng"))]
+use crate::layout::PackageLayouts;
+#[cfg(feature = "audio")]
+use crate::psarc::{BkhdAsset, PsarcAsset};
+#[cfg(feature = "sng")]
+use crate::decryptor::{DecryptStream, Platform};
+#[cfg(feature = "sng")]
+use crate::lyrics::replace_vocals;
+#[cfg(feature = "sng")]
+use crate::models::{ParseOptions, Vocal};
+#[cfg(feature = "sng")]
+use crate::psarc::SngFile;
+use crate::psarc::{PsarcFile, PsarcTOCEntry, APP_ID_FILE_NAME};
+use crate::toolkit::{ToolkitInfo, TOOLKIT_VERSION_FILE_NAME};
+#[cfg(feature = "audio")]
+use crate::wem::WemInfo;
+use crate::writer::{copy_entry_compressed, copy_entry_compressed_as, store_blocks, PsarcWriter};
+
+/// Outcome of `strip_archive`.
+#[derive(Default, Debug, Clone, Serialize)]
+pub struct StripReport {
+    /// Number of entries copied to the new archive (NamesBlock excluded).
+    pub kept: usize,
+    /// Paths of the entries left out.
+    pub removed: Vec<String>,
+    /// Stored (compressed) bytes no longer in the archive.
+    pub bytes_saved: u64,
+}
+
+/// Rebuilds `archive` without the entries whose path matches `remove`.
+///
+/// The TOC, block size table and NamesBlock are regenerated, while the compressed blocks
+/// of the kept entries are copied verbatim, so slimming a pack (dropping preview audio or
+/// console variants) never inflates or recompresses anything.
+pub fn strip_archive<F>(archive: &PsarcFile, mut remove: F) -> io::Result<(PsarcWriter, StripReport)>
+where
+    F: FnMut(&str) -> bool,
+{
+    let mut writer = PsarcWriter::like(archive);
+    let mut report = StripReport::default();
+    for entry in archive.toc.entries.iter().skip(1) {
+        let path = entry.path.as_deref().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "Entry has no path; read the manifest first")
+        })?;
+        if remove(path) {
+            report.bytes_saved += archive.stored_length(entry);
+            report.removed.push(path.to_string());
+            continue;
+        }
+        copy_entry_compressed(archive, entry, &mut writer)?;
+        report.kept += 1;
+    }
+    Ok((writer, report))
+}
+
+/// Opens `input`, strips the matching entries and writes the result to `output`.
+pub fn strip_path<F>(input: &Path, output: &Path, remove: F) -> io::Result<StripReport>
+where
+    F: FnMut(&str) -> bool,
+{
+    let archive = PsarcFile::open_path(input)?;
+    let (writer, report) = strip_archive(&archive, remove)?;
+    writer.write_path(output)?;
+    tracing::info!("Stripped {} entries ({} bytes) from {:?}", report.removed.len(), report.bytes_saved, input);
+    Ok(report)
+}
+
+/// Outcome of `merge_archives`.
+#[derive(Default, Debug, Clone, Serialize)]
+pub struct MergeReport {
+    /// Number of entries in the merged archive (NamesBlock excluded).
+    pub entries: usize,
+    /// Paths present in several inputs with identical content, stored once.
+    pub deduplicated: Vec<String>,
+}
+
+/// The stored (compressed) bytes of an entry, if they lie inside the archive.
+fn stored_bytes<'a>(archive: &'a PsarcFile, entry: &PsarcTOCEntry) -> Option<&'a [u8]> {
+    let start = entry.offset as usize;
+    let end = start.saturating_add(archive.stored_length(entry) as usize);
+    archive.data.get(start..end)
+}
+
+/// True when two entries hold the same data, comparing the stored blocks first and only
+/// inflating when they differ (e.g. compressed by different tools).
+fn same_content(a: (&PsarcFile, &PsarcTOCEntry), b: (&PsarcFile, &PsarcTOCEntry)) -> io::Result<bool> {
+    if a.1.length != b.1.length {
+        return Ok(false);
+    }
+    if a.0.header.block_size == b.0.header.block_size {
+        if let (Some(stored_a), Some(stored_b)) = (stored_bytes(a.0, a.1), stored_bytes(b.0, b.1)) {
+            if stored_a == stored_b {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(a.0.inflate_entry_data(a.1)? == b.0.inflate_entry_data(b.1)?)
+}
+
+/// Combines several archives (typically single-song packs) into one.
+///
+/// Entries are taken in input order. A path found in several inputs is stored once when the
+/// copies are identical; differing copies are a collision and fail the merge, listing every
+/// colliding path. Blocks are copied verbatim when block sizes match and recompressed
+/// otherwise. The merged archive uses the block size and TOC encryption of the first input.
+pub fn merge_archives(archives: &[PsarcFile]) -> io::Result<(PsarcWriter, MergeReport)> {
+    let first = archives
+        .first()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No archives to merge"))?;
+    let mut writer = PsarcWriter::like(first);
+    let mut report = MergeReport::default();
+    let mut seen: HashMap<&str, (&PsarcFile, &PsarcTOCEntry)> = HashMap::new();
+    let mut collisions = Vec::new();
+    for archive in archives {
+        for entry in archive.toc.entries.iter().skip(1) {
+            let path = entry.path.as_deref().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "Entry has no path; read the manifest first")
+            })?;
+            if let Some(&existing) = seen.get(path) {
+                if same_content(existing, (archive, entry))? {
+                    report.deduplicated.push(path.to_string());
+                } else {
+                    collisions.push(path.to_string());
+                }
+                continue;
+            }
+            seen.insert(path, (archive, entry));
+            if archive.header.block_size == first.header.block_size {
+                copy_entry_compressed(archive, entry, &mut writer)?;
+            } else {
+                writer.add_entry(path, &archive.inflate_entry_data(entry)?)?;
+            }
+            report.entries += 1;
+        }
+    }
+    if !collisions.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("Conflicting entries with the same path: {}", collisions.join(", ")),
+        ));
+    }
+    Ok((writer, report))
+}
+
+/// Opens every archive in `inputs`, merges them and writes the result to `output`.
+pub fn merge_paths<P: AsRef<Path>>(inputs: &[P], output: &Path) -> io::Result<MergeReport> {
+    let archives = inputs
+        .iter()
+        .map(PsarcFile::open_path)
+        .collect::<crate::error::Result<Vec<_>>>()?;
+    let (writer, report) = merge_archives(&archives)?;
+    writer.write_path(output)?;
+    tracing::info!("Merged {} archives into {:?} ({} entries)", inputs.len(), output, report.entries);
+    Ok(report)
+}
+
+/// Outcome of `rekey_song`.
+#[derive(Default, Debug, Clone, Serialize)]
+pub struct RekeyReport {
+    /// `(old, new)` path of every renamed entry.
+    pub renamed: Vec<(String, String)>,
+    /// Paths (after renaming) of the text entries whose content referenced the key.
+    pub rewritten: Vec<String>,
+}
+
+/// Extensions of the text entries (manifests, xblocks, graphs) that reference a song key.
+const KEYED_TEXT_EXTENSIONS: [&str; 5] = [".json", ".hsan", ".xblock", ".nt", ".xml"];
+
+/// Replaces `old` with `new` in `text`, except in Wwise event names (`Play_<key>`,
+/// `Stop_<key>`): those are stored hashed inside the soundbank and must keep the old key.
+fn replace_key_in_text(text: &str, old: &str, new: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(pos) = rest.find(old) {
+        result.push_str(&rest[..pos]);
+        if result.ends_with("Play_") || result.ends_with("Stop_") {
+            result.push_str(old);
+        } else {
+            result.push_str(new);
+        }
+        rest = &rest[pos + old.len()..];
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Renames a song key (the DLC name) throughout an archive so it no longer collides with
+/// another chart: entry paths, manifests, xblocks and aggregate graphs are updated, as are
+/// both the original-case and lowercase spellings of the key.
+///
+/// SNG, soundbank and audio entries are copied without recompression; the SNG data does
+/// not carry the key, and soundbank event names keep the old key since they are hashed.
+pub fn rekey_song(archive: &PsarcFile, old_key: &str, new_key: &str) -> io::Result<(PsarcWriter, RekeyReport)> {
+    if old_key.is_empty() || new_key.is_empty() || !new_key.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid song key {:?}: keys must be non-empty and alphanumeric", new_key),
+        ));
+    }
+    let old_lower = old_key.to_lowercase();
+    let new_lower = new_key.to_lowercase();
+    let mut writer = PsarcWriter::like(archive);
+    let mut report = RekeyReport::default();
+    for entry in archive.toc.entries.iter().skip(1) {
+        let path = entry.path.as_deref().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "Entry has no path; read the manifest first")
+        })?;
+        let new_path = path.replace(&old_lower, &new_lower);
+        if new_path != path {
+            report.renamed.push((path.to_string(), new_path.clone()));
+        }
+
+        let is_text = KEYED_TEXT_EXTENSIONS.iter().any(|ext| path.ends_with(ext));
+        if is_text {
+            let data = archive.inflate_entry_data(entry)?;
+            if let Ok(text) = String::from_utf8(data) {
+                let mut replaced = replace_key_in_text(&text, old_key, new_key);
+                if old_lower != old_key {
+                    replaced = replace_key_in_text(&replaced, &old_lower, &new_lower);
+                }
+                if replaced != text {
+                    writer.add_entry(new_path.clone(), replaced.as_bytes())?;
+                    report.rewritten.push(new_path);
+                    continue;
+                }
+            }
+        }
+        copy_entry_compressed_as(archive, entry, &new_path, &mut writer)?;
+    }
+    Ok((writer, report))
+}
+
+/// Fails with `ErrorKind::InvalidInput` unless `app_id` is a numeric Steam app id.
+pub(crate) fn check_app_id(app_id: &str) -> io::Result<()> {
+    if app_id.is_empty() || !app_id.chars().all(|c| c.is_ascii_digit()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid app id {:?}: expected a numeric Steam app id", app_id),
+        ));
+    }
+    Ok(())
+}
+
+/// Rebuilds `archive` with its `appid.appid` entry set to `app_id`, adding the entry when it
+/// is missing. Mismatched app ids are a common reason custom packages fail to load.
+pub fn set_app_id(archive: &PsarcFile, app_id: &str) -> io::Result<PsarcWriter> {
+    check_app_id(app_id)?;
+    replace_root_entry(archive, APP_ID_FILE_NAME, app_id.as_bytes())
+}
+
+/// `set_app_id` for an archive being packed: the queued `appid.appid` entries of `writer`
+/// hold `app_id`, or a root one is added.
+pub fn set_app_id_in(writer: &mut PsarcWriter, app_id: &str) -> io::Result<()> {
+    check_app_id(app_id)?;
+    let paths: Vec<String> = writer
+        .paths()
+        .filter(|path| Path::new(path).file_name().is_some_and(|name| name == APP_ID_FILE_NAME))
+        .map(str::to_string)
+        .collect();
+    if paths.is_empty() {
+        return writer.add_entry(APP_ID_FILE_NAME, app_id.as_bytes());
+    }
+    paths.iter().try_for_each(|path| writer.replace_entry(path, app_id.as_bytes()))
+}
+
+/// Rebuilds `archive` with its `toolkit.version` entry written from `info`, adding the
+/// entry when it is missing, to credit the author of a repacked package.
+pub fn set_toolkit_info(archive: &PsarcFile, info: &ToolkitInfo) -> io::Result<PsarcWriter> {
+    replace_root_entry(archive, TOOLKIT_VERSION_FILE_NAME, info.to_text().as_bytes())
+}
+
+/// Copies `archive` with the entries named `file_name` holding `data`, or with a new
+/// root entry of that name when it has none.
+fn replace_root_entry(archive: &PsarcFile, file_name: &str, data: &[u8]) -> io::Result<PsarcWriter> {
+    let mut writer = PsarcWriter::like(archive);
+    let mut replaced = false;
+    for entry in archive.toc.entries.iter().skip(1) {
+        let path = entry.path.as_deref().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "Entry has no path; read the manifest first")
+        })?;
+        if Path::new(path).file_name().is_some_and(|name| name == file_name) {
+            writer.add_entry(path, data)?;
+            replaced = true;
+        } else {
+            copy_entry_compressed(archive, entry, &mut writer)?;
+        }
+    }
+    if !replaced {
+        writer.add_entry(file_name, data)?;
+    }
+    Ok(writer)
+}
+
+/// Outcome of `remove_arrangements` and `extract_arrangement`.
+#[derive(Default, Debug, Clone, Serialize)]
+pub struct ArrangementEditReport {
+    /// Arrangement file stems (`<key>_<arrangement>`) taken out of the package.
+    pub arrangements: Vec<String>,
+    /// Entries dropped because they belong to a removed arrangement.
+    pub removed_entries: Vec<String>,
+    /// Aggregate entries (hsan, xblock, graph) rewritten without the removed arrangements.
+    pub rewritten: Vec<String>,
+}
+
+/// Lowercase file stem of an entry path, e.g. `mysong_bass` for `songs/bin/generic/mysong_bass.sng`.
+fn lower_stem(path: &str) -> Option<String> {
+    Path::new(path).file_stem().map(|s| s.to_string_lossy().to_lowercase())
+}
+
+/// True when `text` mentions `stem` as a whole name (not as the prefix of `stem2`).
+fn mentions_stem(text: &str, stem: &str) -> bool {
+    let is_name_char = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    text.match_indices(stem).any(|(pos, _)| {
+        let before = text[..pos].chars().next_back();
+        let after = text[pos + stem.len()..].chars().next();
+        !before.is_some_and(is_name_char) && !after.is_some_and(is_name_char)
+    })
+}
+
+/// Removes the arrangement entries of an aggregate manifest (`.hsan`).
+fn strip_hsan(text: &str, stems: &[String]) -> io::Result<String> {
+    let mut value: serde_json::Value = serde_json::from_str(text).map_err(io::Error::other)?;
+    if let Some(entries) = value.get_mut("Entries").and_then(|e| e.as_object_mut()) {
+        entries.retain(|_, entry| {
+            let entry_text = entry.to_string().to_lowercase();
+            !stems.iter().any(|stem| mentions_stem(&entry_text, stem))
+        });
+    }
+    serde_json::to_string_pretty(&value).map_err(io::Error::other)
+}
+
+/// Removes the `<entity>` elements of a game xblock describing the arrangements.
+fn strip_xblock(text: &str, stems: &[String]) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("<entity") {
+        let end = match rest[start..].find("</entity>") {
+            Some(end) => start + end + "</entity>".len(),
+            None => break,
+        };
+        result.push_str(&rest[..start]);
+        let entity = &rest[start..end];
+        let lower = entity.to_lowercase();
+        if stems.iter().any(|stem| mentions_stem(&lower, stem)) {
+            // Drop the entity together with the line break following it.
+            rest = rest[end..].trim_start_matches(['\r', '\n', ' ', '\t']);
+            continue;
+        }
+        result.push_str(entity);
+        rest = &rest[end..];
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Removes every node of an aggregate graph (`.nt`) that describes one of the arrangements'
+/// assets. A node is the set of lines sharing the same subject.
+fn strip_graph(text: &str, stems: &[String]) -> String {
+    let subject = |line: &str| line.split_whitespace().next().unwrap_or("").to_string();
+    let removed: Vec<String> = text
+        .lines()
+        .filter(|line| {
+            let lower = line.to_lowercase();
+            stems.iter().any(|stem| mentions_stem(&lower, stem))
+        })
+        .map(subject)
+        .collect();
+    text.lines()
+        .filter(|line| !removed.contains(&subject(line)))
+        .map(|line| format!("{}\n", line))
+        .collect()
+}
+
+/// Rebuilds `archive` without the given arrangements (`bass`, `rhythm`, `vocals`, ...) of
+/// every song it contains. The arrangement's SNG, manifest and XML entries are dropped and
+/// the aggregate manifest, xblock and graph entries are rewritten without its references.
+pub fn remove_arrangements(archive: &PsarcFile, names: &[&str]) -> io::Result<(PsarcWriter, ArrangementEditReport)> {
+    let names: Vec<String> = names.iter().map(|n| n.to_lowercase()).collect();
+    let stems: Vec<String> = archive
+        .toc
+        .entries
+        .iter()
+        .filter_map(|e| e.path.as_deref())
+        .filter(|p| p.ends_with(".sng"))
+        .filter_map(lower_stem)
+        .filter(|stem| stem.rsplit_once('_').is_some_and(|(_, arr)| names.iter().any(|n| n == arr)))
+        .fold(Vec::new(), |mut stems, stem| {
+            if !stems.contains(&stem) {
+                stems.push(stem);
+            }
+            stems
+        });
+    if stems.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No arrangement named {} in the archive", names.join(", ")),
+        ));
+    }
+
+    let mut writer = PsarcWriter::like(archive);
+    let mut report = ArrangementEditReport {
+        arrangements: stems.clone(),
+        ..Default::default()
+    };
+    for entry in archive.toc.entries.iter().skip(1) {
+        let path = entry.path.as_deref().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "Entry has no path; read the manifest first")
+        })?;
+        if lower_stem(path).is_some_and(|stem| stems.contains(&stem)) {
+            report.removed_entries.push(path.to_string());
+            continue;
+        }
+        let is_aggregate = path.ends_with(".hsan") || path.ends_with(".xblock") || path.ends_with(".nt");
+        if is_aggregate {
+            let text = String::from_utf8(archive.inflate_entry_data(entry)?).map_err(io::Error::other)?;
+            let stripped = if path.ends_with(".hsan") {
+                strip_hsan(&text, &stems)?
+            } else if path.ends_with(".xblock") {
+                strip_xblock(&text, &stems)
+            } else {
+                strip_graph(&text, &stems)
+            };
+            if stripped != text {
+                writer.add_entry(path, stripped.as_bytes())?;
+                report.rewritten.push(path.to_string());
+                continue;
+            }
+        }
+        copy_entry_compressed(archive, entry, &mut writer)?;
+    }
+    Ok((writer, report))
+}
+
+/// Convenience wrapper around `remove_arrangements` for a single arrangement.
+pub fn remove_arrangement(archive: &PsarcFile, name: &str) -> io::Result<(PsarcWriter, ArrangementEditReport)> {
+    remove_arrangements(archive, &[name])
+}
+
+/// Distinct lowercase arrangement names found in the SNG entry paths of an archive.
+fn arrangement_names(archive: &PsarcFile) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for path in archive.toc.entries.iter().filter_map(|e| e.path.as_deref()).filter(|p| p.ends_with(".sng")) {
+        let arrangement = lower_stem(path).and_then(|stem| stem.rsplit_once('_').map(|(_, a)| a.to_string()));
+        if let Some(arrangement) = arrangement {
+            if !names.contains(&arrangement) {
+                names.push(arrangement);
+            }
+        }
+    }
+    names
+}
+
+/// Builds a minimal package holding only the given arrangement: every other arrangement
+/// (vocals included) is removed, shared assets such as audio and album art are kept.
+pub fn extract_arrangement(archive: &PsarcFile, name: &str) -> io::Result<(PsarcWriter, ArrangementEditReport)> {
+    let name = name.to_lowercase();
+    let arrangements = arrangement_names(archive);
+    if !arrangements.contains(&name) {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("No arrangement named {} in the archive", name)));
+    }
+    let others: Vec<&str> = arrangements.iter().map(String::as_str).filter(|a| *a != name).collect();
+    if others.is_empty() {
+        let (writer, _) = strip_archive(archive, |_| false)?;
+        return Ok((writer, ArrangementEditReport::default()));
+    }
+    remove_arrangements(archive, &others)
+}
+
+/// Pixel size of an album art entry, from its `_64.dds`/`_128.dds`/`_256.dds` suffix.
+#[cfg(feature = "image")]
+fn album_art_size(path: &str) -> Option<u32> {
+    if !PackageLayouts::current().is_album_art(path) || !path.ends_with(".dds") {
+        return None;
+    }
+    lower_stem(path)?.rsplit_once('_')?.1.parse().ok()
+}
+
+/// Rebuilds `archive` with every album art texture replaced by `art`, scaled to each
+/// texture's size (64, 128 and 256 pixels) and encoded as BC1 DDS, or BC3 when the art has
+/// transparency (see `dds::encode_dds`). Returns the writer and the replaced paths.
+#[cfg(feature = "image")]
+pub fn set_album_art(archive: &PsarcFile, art: &RgbaImage) -> io::Result<(PsarcWriter, Vec<String>)> {
+    let mut writer = PsarcWriter::like(archive);
+    let mut replaced = Vec::new();
+    for entry in archive.toc.entries.iter().skip(1) {
+        let path = entry.path.as_deref().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "Entry has no path; read the manifest first")
+        })?;
+        match album_art_size(path) {
+            Some(size) if size > 0 => {
+                writer.add_entry(path, &encode_dds(&art.resize(size, size)?))?;
+                replaced.push(path.to_string());
+            }
+            _ => copy_entry_compressed(archive, entry, &mut writer)?,
+        }
+    }
+    if replaced.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "No album art entries in the archive"));
+    }
+    Ok((writer, replaced))
+}
+
+/// Produces a Wwise `.wem` from another audio format (ogg, wav, ...).
+///
+/// The crate ships no encoder; callers plug in whatever they have available (the Wwise
+/// command line, a cross-platform re-implementation, a web service). Closures taking the
+/// source bytes implement it directly.
+#[cfg(feature = "audio")]
+pub trait WemEncoder {
+    fn encode(&self, audio: &[u8]) -> io::Result<Vec<u8>>;
+}
+
+#[cfg(feature = "audio")]
+impl<F: Fn(&[u8]) -> io::Result<Vec<u8>>> WemEncoder for F {
+    fn encode(&self, audio: &[u8]) -> io::Result<Vec<u8>> {
+        self(audio)
+    }
+}
+
+/// Which audio stream of a song `set_audio` replaces.
+#[cfg(feature = "audio")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioSlot {
+    Main,
+    Preview,
+}
+
+/// Outcome of `set_audio`.
+#[cfg(feature = "audio")]
+#[derive(Default, Debug, Clone, Serialize)]
+pub struct SetAudioReport {
+    /// Path of the replaced wem entry.
+    pub wem: String,
+    /// Soundbanks whose embedded prefetch data was refreshed.
+    pub banks: Vec<String>,
+}
+
+/// Overwrites the prefetch copy a soundbank keeps of `wem_id` (its DIDX/DATA slice) with the
+/// start of the new stream. Returns `None` when the bank does not embed that wem.
+#[cfg(feature = "audio")]
+fn patch_bank_prefetch(bank: &[u8], wem_id: u32, wem: &[u8]) -> io::Result<Option<Vec<u8>>> {
+    let mut parsed = BkhdAsset::default();
+    parsed.read_from(&mut io::Cursor::new(bank), bank.len())?;
+    let didx = match parsed.didx.iter().find(|d| d.wem_id == wem_id) {
+        Some(didx) => didx,
+        None => return Ok(None),
+    };
+    // BKHD header + body, DIDX header + body, DATA header.
+    let data_start = 8 + parsed.bkhd_length as usize + 8 + parsed.didx_length as usize + 8;
+    let start = data_start + didx.offset as usize;
+    let length = didx.length as usize;
+    let prefetch = wem.get(..length).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "New audio is shorter than the bank's prefetch data")
+    })?;
+    let mut patched = bank.to_vec();
+    patched
+        .get_mut(start..start + length)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Soundbank DATA section too short"))?
+        .copy_from_slice(prefetch);
+    Ok(Some(patched))
+}
+
+/// Finds the wem entry playing the main song or the preview, following the DIDX of the
+/// `song_*.bnk` / `song_*_preview.bnk` soundbanks. Archives without banks fall back to the
+/// largest (main) or smallest (preview) wem.
+#[cfg(feature = "audio")]
+fn find_audio_entry(archive: &PsarcFile, slot: AudioSlot) -> io::Result<&PsarcTOCEntry> {
+    let wems: Vec<&PsarcTOCEntry> = archive
+        .toc
+        .entries
+        .iter()
+        .filter(|e| e.path.as_deref().is_some_and(|p| p.ends_with(".wem")))
+        .collect();
+    for entry in &archive.toc.entries {
+        let stem = match entry.path.as_deref().filter(|p| p.ends_with(".bnk")).and_then(lower_stem) {
+            Some(stem) if stem.starts_with("song_") => stem,
+            _ => continue,
+        };
+        if stem.ends_with("_preview") != (slot == AudioSlot::Preview) {
+            continue;
+        }
+        let bank: BkhdAsset = archive.inflate_entry_as(entry)?;
+        for didx in &bank.didx {
+            let id = didx.wem_id.to_string();
+            if let Some(wem) = wems.iter().find(|w| w.path.as_deref().and_then(lower_stem).as_deref() == Some(&id)) {
+                return Ok(wem);
+            }
+        }
+    }
+    let by_size = wems.iter().copied();
+    match slot {
+        AudioSlot::Main => by_size.max_by_key(|e| e.length),
+        AudioSlot::Preview => by_size.min_by_key(|e| e.length),
+    }
+    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No audio entries in the archive"))
+}
+
+/// Rebuilds `archive` with the main or preview audio replaced.
+///
+/// `audio` is used as is when it is already a wem; anything else is handed to `encoder`.
+/// The wem keeps its id so soundbank references stay valid, and banks embedding a prefetch
+/// copy of it are refreshed with the start of the new stream.
+#[cfg(feature = "audio")]
+pub fn set_audio(
+    archive: &PsarcFile,
+    slot: AudioSlot,
+    audio: &[u8],
+    encoder: Option<&dyn WemEncoder>,
+) -> io::Result<(PsarcWriter, SetAudioReport)> {
+    let wem = if WemInfo::parse(audio).is_ok() {
+        audio.to_vec()
+    } else {
+        let encoder = encoder.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::Unsupported, "Audio is not a wem and no encoder was given")
+        })?;
+        let encoded = encoder.encode(audio)?;
+        WemInfo::parse(&encoded)?;
+        encoded
+    };
+
+    let target = find_audio_entry(archive, slot)?;
+    let target_path = target.path.clone().unwrap_or_default();
+    let wem_id: Option<u32> = lower_stem(&target_path).and_then(|s| s.parse().ok());
+    let mut writer = PsarcWriter::like(archive);
+    let mut report = SetAudioReport {
+        wem: target_path.clone(),
+        ..Default::default()
+    };
+    for entry in archive.toc.entries.iter().skip(1) {
+        let path = entry.path.as_deref().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "Entry has no path; read the manifest first")
+        })?;
+        if path == target_path {
+            writer.add_entry(path, &wem)?;
+            continue;
+        }
+        if let (Some(id), true) = (wem_id, path.ends_with(".bnk")) {
+            if let Some(patched) = patch_bank_prefetch(&archive.inflate_entry_data(entry)?, id, &wem)? {
+                writer.add_entry(path, &patched)?;
+                report.banks.push(path.to_string());
+                continue;
+            }
+        }
+        copy_entry_compressed(archive, entry, &mut writer)?;
+    }
+    Ok((writer, report))
+}
+
+/// Rebuilds `archive` with the lyrics of its vocals arrangement replaced by `vocals` (for
+/// example from `lyrics::parse_lrc`). The vocals SNG is decrypted, its vocal list swapped,
+/// and re-encrypted with its original IV; all other entries are copied compressed.
+/// Returns the writer and the path of the rewritten SNG.
+#[cfg(feature = "sng")]
+pub fn import_lyrics(archive: &PsarcFile, vocals: &[Vocal]) -> io::Result<(PsarcWriter, String)> {
+    let target = archive
+        .toc
+        .entries
+        .iter()
+        .find(|e| e.path.as_deref().is_some_and(|p| p.ends_with("_vocals.sng")))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "The archive has no vocals arrangement"))?;
+    let encrypted = archive.inflate_entry_data(target)?;
+    let iv: [u8; 16] = encrypted
+        .get(8..24)
+        .and_then(|iv| iv.try_into().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "SNG shorter than its header"))?;
+    let platform = archive.platform();
+    let plain = DecryptStream::new_sng(io::Cursor::new(&encrypted), encrypted.len(), platform)?.reader.into_inner();
+    let sng = DecryptStream::encrypt_sng_with_key(&replace_vocals(&plain, vocals)?, &iv, platform.sng_key())?;
+
+    let target_path = target.path.clone().unwrap_or_default();
+    let mut writer = PsarcWriter::like(archive);
+    for entry in archive.toc.entries.iter().skip(1) {
+        if entry.index == target.index {
+            writer.add_entry(target_path.as_str(), &sng)?;
+        } else {
+            copy_entry_compressed(archive, entry, &mut writer)?;
+        }
+    }
+    Ok((writer, target_path))
+}
+
+/// One manifest attribute brought in line with its chart by `refresh_manifests`.
+#[cfg(feature = "sng")]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ManifestChange {
+    /// Path of the manifest document (`.json` or `.hsan`).
+    pub manifest: String,
+    pub attribute: String,
+    pub old: serde_json::Value,
+    pub new: serde_json::Value,
+}
+
+/// Outcome of `refresh_manifests`.
+#[cfg(feature = "sng")]
+#[derive(Default, Debug, Clone, Serialize)]
+pub struct ManifestRefreshReport {
+    /// Attributes rewritten, by manifest.
+    pub changes: Vec<ManifestChange>,
+    /// SNG entries that could not be read, with the error; their manifests are left alone.
+    pub unreadable: Vec<(String, String)>,
+}
+
+/// Difference below which a numeric manifest attribute is left as it is, so refreshing an
+/// untouched package rewrites nothing.
+#[cfg(feature = "sng")]
+const ATTRIBUTE_TOLERANCE: f64 = 1e-3;
+
+/// Rounds a chart value to the three decimals the toolkits write.
+#[cfg(feature = "sng")]
+fn round3(value: f64) -> f64 {
+    (value * 1000.0).round() / 1000.0
+}
+
+/// The manifest attributes derived from a chart:
+/// - `SongLength` from the metadata;
+/// - `MaxPhraseDifficulty`, the highest difficulty level present;
+/// - `NotesEasy`, `NotesMedium` and `NotesHard`, the notes and chords played at a third,
+///   two thirds and all of that difficulty;
+/// - `Score_MaxNotes` (same as `NotesHard`) and `Score_PNV`, the points each of them is
+///   worth out of the chart's maximum score.
+#[cfg(feature = "sng")]
+pub fn chart_attributes(sng: &SngFile) -> serde_json::Map<String, serde_json::Value> {
+    let max = sng.max_level();
+    let notes = |difficulty: i32| sng.notes_at_difficulty(difficulty).len();
+    let hard = notes(max);
+    let points_per_note = if hard == 0 { 0.0 } else { sng.metadata.max_score / hard as f64 };
+    let mut attributes = serde_json::Map::new();
+    attributes.insert("SongLength".to_string(), round3(sng.metadata.song_length as f64).into());
+    attributes.insert("MaxPhraseDifficulty".to_string(), max.into());
+    attributes.insert("NotesEasy".to_string(), notes(max / 3).into());
+    attributes.insert("NotesMedium".to_string(), notes(max * 2 / 3).into());
+    attributes.insert("NotesHard".to_string(), hard.into());
+    attributes.insert("Score_MaxNotes".to_string(), hard.into());
+    attributes.insert("Score_PNV".to_string(), round3(points_per_note).into());
+    attributes
+}
+
+/// True when a manifest attribute already holds `new`.
+#[cfg(feature = "sng")]
+fn same_attribute(old: &serde_json::Value, new: &serde_json::Value) -> bool {
+    match (old.as_f64(), new.as_f64()) {
+        (Some(old), Some(new)) => (old - new).abs() < ATTRIBUTE_TOLERANCE,
+        _ => old == new,
+    }
+}
+
+/// Sets the attributes of entry `id` of a manifest document that `chart` has a value for
+/// and the entry already carries. Returns the changes.
+#[cfg(feature = "sng")]
+fn refresh_document(
+    document: &mut serde_json::Value,
+    path: &str,
+    id: &str,
+    chart: &serde_json::Map<String, serde_json::Value>,
+) -> Vec<ManifestChange> {
+    let mut changes = Vec::new();
+    let attributes = document
+        .get_mut("Entries")
+        .and_then(|entries| entries.get_mut(id))
+        .and_then(|entry| entry.get_mut("Attributes"))
+        .and_then(|attributes| attributes.as_object_mut());
+    for (attribute, old) in attributes.into_iter().flatten() {
+        let Some(new) = chart.get(attribute.as_str()) else {
+            continue;
+        };
+        if !same_attribute(old, new) {
+            changes.push(ManifestChange {
+                manifest: path.to_string(),
+                attribute: attribute.clone(),
+                old: old.clone(),
+                new: new.clone(),
+            });
+            *old = new.clone();
+        }
+    }
+    changes
+}
+
+/// Rewrites the manifests queued in `writer` from the SNG charts they describe, so a
+/// package whose charts were edited (a level removed, notes added) stays consistent. Each
+/// arrangement manifest `manifests/<folder>/<key>_<arrangement>.json` is matched with the
+/// SNG of the same name, and the HSAN entries with the manifest's entry id. Only the
+/// attributes of `chart_attributes` the manifests already carry are touched; vocals are
+/// skipped. The SNGs are decrypted with the key of the platform their paths belong to.
+#[cfg(feature = "sng")]
+pub fn refresh_manifests_in(writer: &mut PsarcWriter) -> io::Result<ManifestRefreshReport> {
+    let paths: Vec<String> = writer.paths().map(str::to_string).collect();
+    let platform = PackageLayouts::current()
+        .detect_platform(paths.iter().map(String::as_str))
+        .and_then(Platform::from_name)
+        .unwrap_or_default();
+    let mut report = ManifestRefreshReport::default();
+    let mut charts = HashMap::new();
+    for path in paths.iter().filter(|p| p.ends_with(".sng")) {
+        let Some(stem) = lower_stem(path).filter(|stem| !stem.ends_with("vocals")) else {
+            continue;
+        };
+        let data = writer.entry_data(path)?.unwrap_or_default();
+        match SngFile::decrypt(&data, platform, ParseOptions::default()) {
+            Ok(sng) => {
+                charts.insert(stem, chart_attributes(&sng));
+            }
+            Err(err) => {
+                tracing::warn!("Cannot read {} to refresh its manifest: {}", path, err);
+                report.unreadable.push((path.clone(), err.to_string()));
+            }
+        }
+    }
+    let is_manifest = |path: &&String, extension: &str| path.starts_with("manifests/") && path.ends_with(extension);
+    let read_document = |writer: &PsarcWriter, path: &str| -> io::Result<serde_json::Value> {
+        let data = writer.entry_data(path)?.unwrap_or_default();
+        serde_json::from_slice(&data).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path, err)))
+    };
+    // Chart attributes by manifest entry id, for the HSAN entries.
+    let mut by_id = HashMap::new();
+    for path in paths.iter().filter(|p| is_manifest(p, ".json")) {
+        let Some(chart) = lower_stem(path).and_then(|stem| charts.get(&stem)) else {
+            continue;
+        };
+        let mut document = read_document(writer, path)?;
+        let ids: Vec<String> = document["Entries"].as_object().map(|e| e.keys().cloned().collect()).unwrap_or_default();
+        let mut changes = Vec::new();
+        for id in ids {
+            changes.extend(refresh_document(&mut document, path, &id, chart));
+            by_id.insert(id, chart);
+        }
+        if !changes.is_empty() {
+            writer.replace_entry(path, &serde_json::to_vec_pretty(&document).map_err(io::Error::other)?)?;
+            report.changes.extend(changes);
+        }
+    }
+    for path in paths.iter().filter(|p| is_manifest(p, ".hsan")) {
+        let mut document = read_document(writer, path)?;
+        let mut changes = Vec::new();
+        for (id, chart) in &by_id {
+            changes.extend(refresh_document(&mut document, path, id, chart));
+        }
+        if !changes.is_empty() {
+            writer.replace_entry(path, &serde_json::to_vec_pretty(&document).map_err(io::Error::other)?)?;
+            report.changes.extend(changes);
+        }
+    }
+    tracing::debug!("Refreshed {} manifest attributes from {} charts", report.changes.len(), charts.len());
+    Ok(report)
+}
+
+/// Rebuilds `archive` with its manifests refreshed from its charts (see
+/// `refresh_manifests_in`). Entries other than the rewritten manifests are copied
+/// compressed.
+#[cfg(feature = "sng")]
+pub fn refresh_manifests(archive: &PsarcFile) -> io::Result<(PsarcWriter, ManifestRefreshReport)> {
+    let mut writer = PsarcWriter::like(archive);
+    for entry in archive.toc.entries.iter().skip(1) {
+        copy_entry_compressed(archive, entry, &mut writer)?;
+    }
+    let report = refresh_manifests_in(&mut writer)?;
+    Ok((writer, report))
+}
+
+/// The cheapest way to store an entry found by `analyze_compression`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CompressionChoice {
+    /// The blocks as stored are already the smallest.
+    Keep,
+    /// Recompressing every block at the best zlib level is smaller.
+    Recompress,
+    /// Storing the blocks raw is smaller (the data does not compress).
+    Store,
+}
+
+/// Stored sizes of one entry as it is, recompressed and uncompressed.
+#[derive(Debug, Clone, Serialize)]
+pub struct EntryCompression {
+    pub path: String,
+    /// Inflated length, which is also the size stored uncompressed.
+    pub length: u64,
+    /// Bytes the entry occupies in the archive now.
+    pub stored: u64,
+    /// Bytes after recompressing every block at the best level.
+    pub recompressed: u64,
+    pub choice: CompressionChoice,
+}
+
+impl EntryCompression {
+    /// Bytes saved by `choice`.
+    pub fn saving(&self) -> u64 {
+        match self.choice {
+            CompressionChoice::Keep => 0,
+            CompressionChoice::Recompress => self.stored - self.recompressed,
+            CompressionChoice::Store => self.stored - self.length,
+        }
+    }
+}
+
+/// Outcome of `analyze_compression`, and of `optimize_compression` which applies it.
+#[derive(Default, Debug, Clone, Serialize)]
+pub struct CompressionReport {
+    /// Every entry (NamesBlock excluded), in archive order.
+    pub entries: Vec<EntryCompression>,
+}
+
+impl CompressionReport {
+    /// Stored bytes of every entry now.
+    pub fn stored(&self) -> u64 {
+        self.entries.iter().map(|e| e.stored).sum()
+    }
+
+    /// Bytes saved by storing every entry its cheapest way.
+    pub fn saving(&self) -> u64 {
+        self.entries.iter().map(EntryCompression::saving).sum()
+    }
+
+    /// Entries that would shrink, biggest saving first.
+    pub fn improvable(&self) -> Vec<&EntryCompression> {
+        let mut entries: Vec<_> = self.entries.iter().filter(|e| e.choice != CompressionChoice::Keep).collect();
+        entries.sort_by_key(|e| std::cmp::Reverse(e.saving()));
+        entries
+    }
+}
+
+/// Measures, for every entry of `archive`, whether recompressing it at the best zlib level
+/// or storing it uncompressed would take less space than it does now. Entries are inflated
+/// and recompressed in memory; nothing is written.
+pub fn analyze_compression(archive: &PsarcFile) -> io::Result<CompressionReport> {
+    let mut report = CompressionReport::default();
+    for entry in archive.toc.entries.iter().skip(1) {
+        let path = entry.path.as_deref().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "Entry has no path; read the manifest first")
+        })?;
+        let data = archive.inflate_entry_data(entry)?;
+        let (_, recompressed) = store_blocks(&data, archive.header.block_size, true)?;
+        let stored = archive.stored_length(entry);
+        let recompressed = recompressed.len() as u64;
+        // Incompressible blocks are stored raw by the recompression too, so it is never
+        // bigger than the length; when it is not smaller either, storing raw says so.
+        let choice = if recompressed.min(entry.length) >= stored {
+            CompressionChoice::Keep
+        } else if recompressed < entry.length {
+            CompressionChoice::Recompress
+        } else {
+            CompressionChoice::Store
+        };
+        report.entries.push(EntryCompression { path: path.to_string(), length: entry.length, stored, recompressed, choice });
+    }
+    Ok(report)
+}
+
+/// Rebuilds `archive` storing every entry its cheapest way according to
+/// `analyze_compression`: entries already at their smallest are copied compressed, the
+/// others recompressed or stored raw.
+pub fn optimize_compression(archive: &PsarcFile) -> io::Result<(PsarcWriter, CompressionReport)> {
+    let report = analyze_compression(archive)?;
+    let mut writer = PsarcWriter::like(archive);
+    for (entry, analysis) in archive.toc.entries.iter().skip(1).zip(&report.entries) {
+        match analysis.choice {
+            CompressionChoice::Keep => copy_entry_compressed(archive, entry, &mut writer)?,
+            CompressionChoice::Recompress => writer.add_entry(analysis.path.as_str(), &archive.inflate_entry_data(entry)?)?,
+            CompressionChoice::Store => {
+                writer.add_entry_uncompressed(analysis.path.as_str(), &archive.inflate_entry_data(entry)?)?
+            }
+        }
+    }
+    Ok((writer, report))
+}
+
+/// Opens `input`, optimizes the storage of its entries and writes the result to `output`.
+pub fn optimize_compression_path(input: &Path, output: &Path) -> io::Result<CompressionReport> {
+    let archive = PsarcFile::open_path(input)?;
+    let (writer, report) = optimize_compression(&archive)?;
+    writer.write_path(output)?;
+    tracing::info!("Optimized {:?}: {} of {} stored bytes saved", input, report.saving(), report.stored());
+    Ok(report)
+}
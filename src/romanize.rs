@@ -0,0 +1,134 @@
+//! Kana to romaji transliteration (Hepburn), for lyric exports read by players that cannot
+//! render CJK text.
+//!
+//! Only kana are converted. Kanji need a dictionary to be read, which this crate does not
+//! ship; lyric exports pass the furigana reading of a syllable when it has one (see
+//! `lyrics::split_lyric`), other kanji are kept as they are. `unconverted_kanji` lists
+//! them, so callers can tell the user which lines are not fully romanized.
+
+/// Romaji of a hiragana, katakana being the same table shifted by 0x60.
+fn kana_romaji(kana: char) -> Option<&'static str> {
+    let hiragana = match kana {
+        '\u{30A1}'..='\u{30F6}' => char::from_u32(kana as u32 - 0x60)?,
+        _ => kana,
+    };
+    Some(match hiragana {
+        'あ' => "a", 'い' => "i", 'う' => "u", 'え' => "e", 'お' => "o",
+        'か' => "ka", 'き' => "ki", 'く' => "ku", 'け' => "ke", 'こ' => "ko",
+        'が' => "ga", 'ぎ' => "gi", 'ぐ' => "gu", 'げ' => "ge", 'ご' => "go",
+        'さ' => "sa", 'し' => "shi", 'す' => "su", 'せ' => "se", 'そ' => "so",
+        'ざ' => "za", 'じ' => "ji", 'ず' => "zu", 'ぜ' => "ze", 'ぞ' => "zo",
+        'た' => "ta", 'ち' => "chi", 'つ' => "tsu", 'て' => "te", 'と' => "to",
+        'だ' => "da", 'ぢ' => "ji", 'づ' => "zu", 'で' => "de", 'ど' => "do",
+        'な' => "na", 'に' => "ni", 'ぬ' => "nu", 'ね' => "ne", 'の' => "no",
+        'は' => "ha", 'ひ' => "hi", 'ふ' => "fu", 'へ' => "he", 'ほ' => "ho",
+        'ば' => "ba", 'び' => "bi", 'ぶ' => "bu", 'べ' => "be", 'ぼ' => "bo",
+        'ぱ' => "pa", 'ぴ' => "pi", 'ぷ' => "pu", 'ぺ' => "pe", 'ぽ' => "po",
+        'ま' => "ma", 'み' => "mi", 'む' => "mu", 'め' => "me", 'も' => "mo",
+        'や' => "ya", 'ゆ' => "yu", 'よ' => "yo",
+        'ら' => "ra", 'り' => "ri", 'る' => "ru", 'れ' => "re", 'ろ' => "ro",
+        'わ' => "wa", 'ゐ' => "i", 'ゑ' => "e", 'を' => "o", 'ん' => "n",
+        'ゔ' => "vu",
+        'ぁ' => "a", 'ぃ' => "i", 'ぅ' => "u", 'ぇ' => "e", 'ぉ' => "o",
+        'ゃ' => "ya", 'ゅ' => "yu", 'ょ' => "yo", 'ゎ' => "wa",
+        _ => return None,
+    })
+}
+
+/// Whether `c` is a kanji: a CJK unified or compatibility ideograph, or the `々` mark.
+pub fn is_kanji(c: char) -> bool {
+    matches!(c, '\u{3005}' | '\u{3400}'..='\u{4DBF}' | '\u{4E00}'..='\u{9FFF}' | '\u{F900}'..='\u{FAFF}')
+}
+
+/// The kanji `to_romaji` keeps in `text`, each once, in order of appearance.
+pub fn unconverted_kanji(text: &str) -> Vec<char> {
+    let mut kanji = Vec::new();
+    for c in text.chars().filter(|&c| is_kanji(c)) {
+        if !kanji.contains(&c) {
+            kanji.push(c);
+        }
+    }
+    kanji
+}
+
+fn is_small_y(kana: char) -> bool {
+    matches!(kana, 'ゃ' | 'ゅ' | 'ょ' | 'ャ' | 'ュ' | 'ョ')
+}
+
+fn is_small_vowel(kana: char) -> bool {
+    matches!(kana, 'ぁ' | 'ぃ' | 'ぅ' | 'ぇ' | 'ぉ' | 'ァ' | 'ィ' | 'ゥ' | 'ェ' | 'ォ')
+}
+
+/// Transliterates the kana of `text` into romaji; other characters are kept. Small `ゃゅょ`
+/// form digraphs (`しゃ` → `sha`), small vowels modify the syllable before them
+/// (`ファ` → `fa`), `っ` doubles the next consonant and `ー` repeats the last vowel.
+pub fn to_romaji(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut double_next = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            'っ' | 'ッ' => {
+                double_next = true;
+                continue;
+            }
+            'ー' => {
+                if let Some(vowel) = output.chars().last().filter(|v| "aeiou".contains(*v)) {
+                    output.push(vowel);
+                }
+                continue;
+            }
+            '　' => {
+                output.push(' ');
+                continue;
+            }
+            _ => {}
+        }
+        let Some(romaji) = kana_romaji(c) else {
+            double_next = false;
+            output.push(c);
+            continue;
+        };
+        let mut syllable = romaji.to_string();
+        if let Some(&next) = chars.peek() {
+            if is_small_y(next) && syllable.ends_with('i') && syllable.len() > 1 {
+                let glide = kana_romaji(next).unwrap_or_default();
+                syllable.pop();
+                // shi, chi and ji drop the y: sha, cha, ja.
+                if !(syllable.ends_with("sh") || syllable.ends_with("ch") || syllable == "j") {
+                    syllable.push('y');
+                }
+                syllable.push_str(&glide[1..]);
+                chars.next();
+            } else if is_small_vowel(next) && syllable.len() > 1 {
+                // The vowel replaces the one of the syllable: ティ → ti, チェ → che.
+                syllable.pop();
+                syllable.push_str(kana_romaji(next).unwrap_or_default());
+                chars.next();
+            }
+        }
+        if double_next {
+            if syllable.starts_with("ch") {
+                output.push('t');
+            } else if let Some(consonant) = syllable.chars().next().filter(|c| !"aeioun".contains(*c)) {
+                output.push(consonant);
+            }
+            double_next = false;
+        }
+        output.push_str(&syllable);
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kanji_are_kept_and_listed() {
+        let text = "愛してる 心の愛";
+        assert_eq!(to_romaji(text), "愛shiteru 心no愛");
+        assert_eq!(unconverted_kanji(&to_romaji(text)), ['愛', '心']);
+        assert!(unconverted_kanji(&to_romaji("あいしてる")).is_empty());
+    }
+}
@@ -0,0 +1,186 @@
+//! Searching a library index with a small query language, such as
+//! `artist:metallica tuning:drop-d year:1986-1991`.
+//!
+//! A query is a list of terms, all of which must match:
+//! - `artist:`, `title:` and `album:` match a substring of the song field, ignoring case.
+//! - `tuning:` matches the tuning name of an arrangement (`drop-d`, `"E Standard"`,
+//!   `eb-standard`), ignoring case, spaces and punctuation.
+//! - `arrangement:` matches the arrangement name (`lead`, `bass`, ...).
+//! - `year:` takes a year (`1986`) or an inclusive range (`1986-1991`).
+//! - `origin:` takes `official` or `custom` (see `origin::classify_package`).
+//! - A bare word matches the artist, title or album.
+//!
+//! Values holding spaces are quoted: `artist:"iron maiden"`. The tuning and arrangement
+//! terms select arrangements, so a hit lists only the SNG entries that matched them.
+
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use serde::Serialize;
+
+use crate::library::{ArchiveSummary, ArrangementSummary, SongSummary};
+use crate::origin::PackageOrigin;
+
+/// A parsed search query. Every term must match.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SongQuery {
+    pub artist: Vec<String>,
+    pub title: Vec<String>,
+    pub album: Vec<String>,
+    /// Normalized tuning names (see `normalize`).
+    pub tuning: Vec<String>,
+    pub arrangement: Vec<String>,
+    /// Inclusive year range.
+    pub year: Option<(i64, i64)>,
+    pub origin: Option<PackageOrigin>,
+    /// Bare words, matched against the artist, title or album.
+    pub text: Vec<String>,
+}
+
+/// Lowercase letters and digits of `value`, so `Drop D`, `drop-d` and `dropd` compare equal.
+fn normalize(value: &str) -> String {
+    value.chars().filter(|c| c.is_alphanumeric()).flat_map(char::to_lowercase).collect()
+}
+
+fn contains_ignore_case(field: Option<&str>, needle: &str) -> bool {
+    field.is_some_and(|field| field.to_lowercase().contains(needle))
+}
+
+/// Splits a query into its terms, keeping quoted values (`artist:"iron maiden"`) whole.
+fn split_terms(query: &str) -> Result<Vec<String>, String> {
+    let mut terms = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in query.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !current.is_empty() {
+                    terms.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if quoted {
+        return Err(format!("Unterminated quote in query: {}", query));
+    }
+    if !current.is_empty() {
+        terms.push(current);
+    }
+    Ok(terms)
+}
+
+fn parse_year(value: &str) -> Result<(i64, i64), String> {
+    let invalid = || format!("Invalid year: {} (expected a year or a range such as 1986-1991)", value);
+    let (from, to) = value.split_once('-').unwrap_or((value, value));
+    let from = from.trim().parse::<i64>().map_err(|_| invalid())?;
+    let to = to.trim().parse::<i64>().map_err(|_| invalid())?;
+    Ok((from.min(to), from.max(to)))
+}
+
+impl FromStr for SongQuery {
+    type Err = String;
+
+    fn from_str(query: &str) -> Result<Self, Self::Err> {
+        let mut parsed = SongQuery::default();
+        for term in split_terms(query)? {
+            let Some((field, value)) = term.split_once(':') else {
+                parsed.text.push(term.to_lowercase());
+                continue;
+            };
+            if value.is_empty() {
+                return Err(format!("Missing value after {}:", field));
+            }
+            match field.to_ascii_lowercase().as_str() {
+                "artist" => parsed.artist.push(value.to_lowercase()),
+                "title" | "song" => parsed.title.push(value.to_lowercase()),
+                "album" => parsed.album.push(value.to_lowercase()),
+                "tuning" => parsed.tuning.push(normalize(value)),
+                "arrangement" | "arr" => parsed.arrangement.push(value.to_lowercase()),
+                "year" => parsed.year = Some(parse_year(value)?),
+                "origin" => parsed.origin = Some(value.parse()?),
+                other => {
+                    return Err(format!(
+                        "Unknown search field {:?} (expected artist, title, album, tuning, year, arrangement or origin)",
+                        other
+                    ))
+                }
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+impl SongQuery {
+    /// True when the song level terms (artist, title, album, year, bare words) match.
+    fn matches_song(&self, song: &SongSummary) -> bool {
+        let (artist, title, album) = (song.artist.as_deref(), song.title.as_deref(), song.album.as_deref());
+        self.artist.iter().all(|v| contains_ignore_case(artist, v))
+            && self.title.iter().all(|v| contains_ignore_case(title, v))
+            && self.album.iter().all(|v| contains_ignore_case(album, v))
+            && self.year.is_none_or(|(from, to)| song.year.is_some_and(|y| (from..=to).contains(&y)))
+            && self.text.iter().all(|v| {
+                contains_ignore_case(artist, v) || contains_ignore_case(title, v) || contains_ignore_case(album, v)
+            })
+    }
+
+    /// True when the arrangement level terms (tuning, arrangement) match.
+    fn matches_arrangement(&self, arrangement: &ArrangementSummary) -> bool {
+        let tuning = normalize(&arrangement.tuning_name);
+        let name = arrangement.name.to_lowercase();
+        self.tuning.iter().all(|v| tuning == *v) && self.arrangement.iter().all(|v| name == *v)
+    }
+}
+
+/// A song matching a query, with the archive holding it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SearchHit {
+    pub archive: PathBuf,
+    pub song_key: String,
+    pub artist: Option<String>,
+    pub title: Option<String>,
+    /// Length in seconds, from the longest arrangement.
+    pub song_length: f32,
+    /// SNG entries of the arrangements that matched, in archive order.
+    pub entries: Vec<String>,
+}
+
+/// The songs of `archives` matching `query`. A song matches when its archive's origin and
+/// its song level terms do and, if the query has tuning or arrangement terms, at least one
+/// of its arrangements does.
+pub fn search_archives<'a>(
+    archives: impl IntoIterator<Item = &'a ArchiveSummary>,
+    query: &SongQuery,
+) -> Vec<SearchHit> {
+    archives
+        .into_iter()
+        .filter(|archive| query.origin.is_none_or(|origin| archive.origin.origin == origin))
+        .flat_map(|archive| archive.songs.iter().map(move |song| (archive.path.as_path(), song)))
+        .filter(|(_, song)| query.matches_song(song))
+        .filter_map(|(archive, song)| {
+            let entries: Vec<String> = song
+                .arrangements
+                .iter()
+                .filter(|a| query.matches_arrangement(a))
+                .map(|a| a.entry_path.clone())
+                .collect();
+            if entries.is_empty() && !(query.tuning.is_empty() && query.arrangement.is_empty()) {
+                return None;
+            }
+            Some(SearchHit {
+                archive: archive.to_path_buf(),
+                song_key: song.key.clone(),
+                artist: song.artist.clone(),
+                title: song.title.clone(),
+                song_length: song.song_length(),
+                entries,
+            })
+        })
+        .collect()
+}
+
+/// The songs of `summaries` (from `library::scan_library`) matching `query`.
+pub fn search(summaries: &[ArchiveSummary], query: &SongQuery) -> Vec<SearchHit> {
+    search_archives(summaries, query)
+}
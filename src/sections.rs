@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+use std::fmt;
+use serde::Serialize;
+
+/// Canonical section kinds used by Rocksmith charts.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub enum SectionKind {
+    Intro,
+    Verse,
+    PreVerse,
+    PostVerse,
+    PreChorus,
+    Chorus,
+    PostChorus,
+    PreBridge,
+    Bridge,
+    PostBridge,
+    Solo,
+    Riff,
+    Hook,
+    Head,
+    Melody,
+    Chords,
+    Tapping,
+    Breakdown,
+    Buildup,
+    Interlude,
+    Transition,
+    Variation,
+    Vamp,
+    Ambient,
+    FadeIn,
+    FadeOut,
+    NoGuitar,
+    Silence,
+    Outro,
+    /// Any name not matching a known kind, lowercased with separators removed.
+    Other(String),
+}
+
+impl SectionKind {
+    /// Maps a lowercase, separator-free name (e.g. `prechorus`, `vs`) to a kind.
+    fn from_compact(name: &str) -> Self {
+        match name {
+            "intro" => SectionKind::Intro,
+            "verse" | "vs" | "modverse" => SectionKind::Verse,
+            "preverse" | "prevs" => SectionKind::PreVerse,
+            "postverse" | "postvs" => SectionKind::PostVerse,
+            "prechorus" | "prech" => SectionKind::PreChorus,
+            "chorus" | "ch" | "modchorus" => SectionKind::Chorus,
+            "postchorus" | "postch" => SectionKind::PostChorus,
+            "prebridge" => SectionKind::PreBridge,
+            "bridge" | "modbridge" => SectionKind::Bridge,
+            "postbridge" => SectionKind::PostBridge,
+            "solo" | "guitarsolo" => SectionKind::Solo,
+            "riff" => SectionKind::Riff,
+            "hook" => SectionKind::Hook,
+            "head" => SectionKind::Head,
+            "melody" => SectionKind::Melody,
+            "chords" => SectionKind::Chords,
+            "tapping" => SectionKind::Tapping,
+            "breakdown" => SectionKind::Breakdown,
+            "buildup" => SectionKind::Buildup,
+            "interlude" => SectionKind::Interlude,
+            "transition" => SectionKind::Transition,
+            "variation" => SectionKind::Variation,
+            "vamp" => SectionKind::Vamp,
+            "ambient" => SectionKind::Ambient,
+            "fadein" => SectionKind::FadeIn,
+            "fadeout" => SectionKind::FadeOut,
+            "noguitar" => SectionKind::NoGuitar,
+            "silence" => SectionKind::Silence,
+            "outro" => SectionKind::Outro,
+            other => SectionKind::Other(other.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for SectionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self {
+            SectionKind::Intro => "Intro",
+            SectionKind::Verse => "Verse",
+            SectionKind::PreVerse => "Pre-Verse",
+            SectionKind::PostVerse => "Post-Verse",
+            SectionKind::PreChorus => "Pre-Chorus",
+            SectionKind::Chorus => "Chorus",
+            SectionKind::PostChorus => "Post-Chorus",
+            SectionKind::PreBridge => "Pre-Bridge",
+            SectionKind::Bridge => "Bridge",
+            SectionKind::PostBridge => "Post-Bridge",
+            SectionKind::Solo => "Solo",
+            SectionKind::Riff => "Riff",
+            SectionKind::Hook => "Hook",
+            SectionKind::Head => "Head",
+            SectionKind::Melody => "Melody",
+            SectionKind::Chords => "Chords",
+            SectionKind::Tapping => "Tapping",
+            SectionKind::Breakdown => "Breakdown",
+            SectionKind::Buildup => "Buildup",
+            SectionKind::Interlude => "Interlude",
+            SectionKind::Transition => "Transition",
+            SectionKind::Variation => "Variation",
+            SectionKind::Vamp => "Vamp",
+            SectionKind::Ambient => "Ambient",
+            SectionKind::FadeIn => "Fade In",
+            SectionKind::FadeOut => "Fade Out",
+            SectionKind::NoGuitar => "No Guitar",
+            SectionKind::Silence => "Silence",
+            SectionKind::Outro => "Outro",
+            SectionKind::Other(name) => return write!(f, "{}", name),
+        };
+        write!(f, "{}", label)
+    }
+}
+
+/// A section name reduced to a canonical kind and a 1-based index.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct NormalizedSection {
+    pub original: String,
+    pub kind: SectionKind,
+    pub index: u32,
+    /// Display label such as `Verse 2`.
+    pub label: String,
+}
+
+/// Splits a raw section name into its compact kind name and an explicit trailing number,
+/// e.g. `Verse 2` → (`verse`, Some(2)), `riff07` → (`riff`, Some(7)), `pre-chorus` →
+/// (`prechorus`, None).
+fn split_name(name: &str) -> (String, Option<u32>) {
+    let lower = name.trim().to_lowercase();
+    let digits_start = lower
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| c.is_ascii_digit())
+        .last()
+        .map(|(i, _)| i);
+    let (base, number) = match digits_start {
+        Some(i) if i > 0 => (&lower[..i], lower[i..].parse().ok()),
+        _ => (lower.as_str(), None),
+    };
+    let compact: String = base.chars().filter(|c| c.is_alphanumeric()).collect();
+    (compact, number)
+}
+
+/// Normalizes a single section name. Names without an explicit number get index 1.
+pub fn normalize_section_name(name: &str) -> NormalizedSection {
+    let (compact, number) = split_name(name);
+    let kind = SectionKind::from_compact(&compact);
+    let index = number.unwrap_or(1);
+    NormalizedSection {
+        original: name.to_string(),
+        label: format!("{} {}", kind, index),
+        kind,
+        index,
+    }
+}
+
+/// Normalizes the section names of a chart in order of appearance.
+///
+/// Explicit numbers in the name win; otherwise each kind is numbered by its occurrence, so
+/// `verse, chorus, verse` becomes `Verse 1, Chorus 1, Verse 2`.
+pub fn normalize_sections<'a, I>(names: I) -> Vec<NormalizedSection>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let mut seen: HashMap<SectionKind, u32> = HashMap::new();
+    names
+        .into_iter()
+        .map(|name| {
+            let (compact, number) = split_name(name);
+            let kind = SectionKind::from_compact(&compact);
+            let count = seen.entry(kind.clone()).or_insert(0);
+            *count += 1;
+            let index = number.unwrap_or(*count);
+            NormalizedSection {
+                original: name.to_string(),
+                label: format!("{} {}", kind, index),
+                kind,
+                index,
+            }
+        })
+        .collect()
+}
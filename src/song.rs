@@ -0,0 +1,355 @@
+use std::cell::OnceCell;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+use serde::Serialize;
+
+use crate::layout::PackageLayouts;
+use crate::library::split_sng_path;
+use crate::models::Vocal;
+use crate::psarc::{BkhdAsset, PsarcFile, PsarcTOCEntry, SngAsset};
+use crate::psarc_set::PsarcSet;
+use crate::sections::SectionKind;
+#[cfg(feature = "audio")]
+use crate::audio::{AudioConverter, ConvertedAudio};
+#[cfg(feature = "audio")]
+use crate::vorbis::ogg_excerpt;
+#[cfg(feature = "audio")]
+use crate::wem::{wem_excerpt_to_wav, WemCodec, WemInfo};
+
+/// Fade in and out applied to synthesized previews.
+#[cfg(feature = "audio")]
+const PREVIEW_FADE_SECONDS: f64 = 0.5;
+
+/// Length of the previews shipped with official songs.
+pub const DEFAULT_PREVIEW_SECONDS: f64 = 30.0;
+
+/// Entries visible to a song, each paired with the archive it is read from.
+type EntryView<'a> = Vec<(&'a PsarcFile, &'a PsarcTOCEntry)>;
+
+/// Arrangement name paired with the archive entry holding its SNG.
+type NamedEntry<'a> = (String, (&'a PsarcFile, &'a PsarcTOCEntry));
+
+/// Song level attributes taken from the arrangement manifests.
+#[derive(Default, Debug, Clone, Serialize)]
+pub struct SongMetadata {
+    pub artist: Option<String>,
+    pub title: Option<String>,
+    pub album: Option<String>,
+    pub year: Option<i64>,
+    pub song_length: Option<f64>,
+    pub average_tempo: Option<f64>,
+}
+
+/// A raw entry belonging to a song (album art, audio), read on demand.
+#[derive(Debug, Clone, Copy)]
+pub struct AssetHandle<'a> {
+    archive: &'a PsarcFile,
+    pub entry: &'a PsarcTOCEntry,
+}
+
+impl<'a> AssetHandle<'a> {
+    /// Path of the entry inside the archive.
+    pub fn path(&self) -> &'a str {
+        self.entry.path.as_deref().unwrap_or_default()
+    }
+
+    /// Inflates the entry data.
+    pub fn read(&self) -> io::Result<Vec<u8>> {
+        Ok(self.archive.inflate_entry_data(self.entry)?)
+    }
+}
+
+/// One arrangement of a song. The SNG is only decrypted and parsed on first access.
+#[derive(Debug)]
+pub struct SongArrangement<'a> {
+    archive: &'a PsarcFile,
+    pub name: String,
+    pub entry: &'a PsarcTOCEntry,
+    manifest: Option<serde_json::Value>,
+    sng: OnceCell<SngAsset>,
+}
+
+impl<'a> SongArrangement<'a> {
+    /// Parsed SNG data of this arrangement.
+    pub fn sng(&self) -> io::Result<&SngAsset> {
+        if let Some(sng) = self.sng.get() {
+            return Ok(sng);
+        }
+        let parsed = self.archive.read_sng_entry(self.entry)?;
+        Ok(self.sng.get_or_init(|| parsed))
+    }
+
+    /// `Attributes` object of the arrangement manifest, if the archive has one.
+    pub fn manifest_attributes(&self) -> Option<&serde_json::Value> {
+        self.manifest.as_ref()
+    }
+
+    /// True for vocals (and Japanese vocals) arrangements.
+    pub fn is_vocals(&self) -> bool {
+        self.name.contains("vocals")
+    }
+}
+
+/// High-level view of one song inside an archive.
+///
+/// Groups everything belonging to a song key — manifest metadata, arrangements, tones,
+/// lyrics, album art and audio — so consumers never need to deal with TOC entries or SNG
+/// internals. Heavy parts (SNG parsing, entry inflation) are loaded lazily.
+#[derive(Debug)]
+pub struct Song<'a> {
+    pub key: String,
+    pub metadata: SongMetadata,
+    arrangements: Vec<SongArrangement<'a>>,
+    art: Vec<AssetHandle<'a>>,
+    audio: Vec<AssetHandle<'a>>,
+    preview: Vec<AssetHandle<'a>>,
+}
+
+/// Reads the `Attributes` object of the manifest JSON named `<stem>.json`.
+fn read_manifest_attributes(view: &EntryView<'_>, stem: &str) -> Option<serde_json::Value> {
+    let manifest_name = format!("{}.json", stem);
+    let (archive, entry) = view.iter().find(|(_, e)| {
+        e.path
+            .as_deref()
+            .and_then(|p| Path::new(p).file_name())
+            .is_some_and(|name| name.to_string_lossy() == manifest_name)
+    })?;
+    let data = archive.inflate_entry_data(entry).ok()?;
+    let mut value: serde_json::Value = serde_json::from_slice(&data).ok()?;
+    value
+        .get_mut("Entries")?
+        .as_object_mut()?
+        .values_mut()
+        .find_map(|e| e.get_mut("Attributes").map(serde_json::Value::take))
+}
+
+fn file_stem(path: &str) -> String {
+    Path::new(path)
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+impl<'a> Song<'a> {
+    /// Lists every song of an archive, keyed by the song key found in the SNG entry names.
+    /// The manifest must have been read (`PsarcFile::read_manifest`) beforehand.
+    pub fn list(archive: &'a PsarcFile) -> Vec<Song<'a>> {
+        Song::list_view(archive.toc.entries.iter().map(|e| (archive, e)).collect())
+    }
+
+    /// Lists every song visible through a set of archives. Each asset is resolved through
+    /// the set, so patched versions take precedence over the base pack.
+    pub fn list_in_set(set: &'a PsarcSet) -> Vec<Song<'a>> {
+        Song::list_view(set.resolved_entries())
+    }
+
+    /// Opens the song with the given key (case-insensitive).
+    pub fn open(archive: &'a PsarcFile, key: &str) -> Option<Song<'a>> {
+        Song::list(archive).into_iter().find(|s| s.key.eq_ignore_ascii_case(key))
+    }
+
+    /// Opens the song with the given key (case-insensitive) from a set of archives.
+    pub fn open_in_set(set: &'a PsarcSet, key: &str) -> Option<Song<'a>> {
+        Song::list_in_set(set).into_iter().find(|s| s.key.eq_ignore_ascii_case(key))
+    }
+
+    fn list_view(view: EntryView<'a>) -> Vec<Song<'a>> {
+        let mut by_key: BTreeMap<String, Vec<NamedEntry<'a>>> = BTreeMap::new();
+        for &(archive, entry) in &view {
+            if let Some(path) = entry.path.as_deref().filter(|p| p.ends_with(".sng")) {
+                if let Some((key, name)) = split_sng_path(path) {
+                    by_key.entry(key).or_default().push((name, (archive, entry)));
+                }
+            }
+        }
+        let single_song = by_key.len() == 1;
+        by_key
+            .into_iter()
+            .map(|(key, arrangements)| Song::build(&view, key, arrangements, single_song))
+            .collect()
+    }
+
+    fn build(
+        view: &EntryView<'a>,
+        key: String,
+        arrangements: Vec<NamedEntry<'a>>,
+        single_song: bool,
+    ) -> Song<'a> {
+        let arrangements: Vec<SongArrangement<'a>> = arrangements
+            .into_iter()
+            .map(|(name, (archive, entry))| SongArrangement {
+                archive,
+                manifest: read_manifest_attributes(view, &file_stem(entry.path.as_deref().unwrap_or_default())),
+                name,
+                entry,
+                sng: OnceCell::new(),
+            })
+            .collect();
+
+        let mut metadata = SongMetadata::default();
+        for attrs in arrangements.iter().filter_map(|a| a.manifest.as_ref()) {
+            let text = |name: &str| attrs.get(name).and_then(|v| v.as_str()).map(|s| s.to_string());
+            metadata.artist = metadata.artist.take().or_else(|| text("ArtistName"));
+            metadata.title = metadata.title.take().or_else(|| text("SongName"));
+            metadata.album = metadata.album.take().or_else(|| text("AlbumName"));
+            metadata.year = metadata.year.or_else(|| attrs.get("SongYear").and_then(|v| v.as_i64()));
+            metadata.song_length = metadata.song_length.or_else(|| attrs.get("SongLength").and_then(|v| v.as_f64()));
+            metadata.average_tempo = metadata
+                .average_tempo
+                .or_else(|| attrs.get("SongAverageTempo").and_then(|v| v.as_f64()));
+        }
+
+        let lower_key = key.to_lowercase();
+        let layouts = PackageLayouts::current();
+        let art = view
+            .iter()
+            .filter(|(_, e)| {
+                e.path.as_deref().is_some_and(|p| {
+                    p.ends_with(".dds") && layouts.is_album_art(p) && (single_song || p.to_lowercase().contains(&lower_key))
+                })
+            })
+            .map(|&(archive, entry)| AssetHandle { archive, entry })
+            .collect();
+        let audio = Song::resolve_audio(view, &lower_key, single_song, false);
+        let preview = Song::resolve_audio(view, &lower_key, single_song, true);
+
+        Song { key, metadata, arrangements, art, audio, preview }
+    }
+
+    /// Finds the wem entries of a song: those referenced by the DIDX of its soundbanks
+    /// (`song_<key>.bnk`, `song_<key>_preview.bnk`), or every wem for single-song archives.
+    /// With `preview_only`, only the wems of the preview soundbank are returned.
+    fn resolve_audio(
+        view: &EntryView<'a>,
+        lower_key: &str,
+        single_song: bool,
+        preview_only: bool,
+    ) -> Vec<AssetHandle<'a>> {
+        let wems = view
+            .iter()
+            .filter(|(_, e)| e.path.as_deref().is_some_and(|p| p.ends_with(".wem")));
+        if single_song && !preview_only {
+            return wems.map(|&(archive, entry)| AssetHandle { archive, entry }).collect();
+        }
+        let mut wem_ids = Vec::new();
+        for &(archive, entry) in view {
+            let is_song_bank = entry.path.as_deref().is_some_and(|p| {
+                let p = p.to_lowercase();
+                p.ends_with(".bnk")
+                    && (single_song || p.contains(lower_key))
+                    && (!preview_only || p.ends_with("_preview.bnk"))
+            });
+            if is_song_bank {
+                match archive.inflate_entry_as::<BkhdAsset>(entry) {
+                    Ok(bank) => wem_ids.extend(bank.didx.iter().map(|d| d.wem_id.to_string())),
+                    Err(e) => tracing::warn!("Could not parse soundbank {:?}: {}", entry.path, e),
+                }
+            }
+        }
+        wems.filter(|(_, e)| wem_ids.contains(&file_stem(e.path.as_deref().unwrap_or_default())))
+            .map(|&(archive, entry)| AssetHandle { archive, entry })
+            .collect()
+    }
+
+    /// All arrangements, including vocals.
+    pub fn arrangements(&self) -> &[SongArrangement<'a>] {
+        &self.arrangements
+    }
+
+    /// Looks up an arrangement by name (`lead`, `rhythm`, `bass`, `vocals`, ...).
+    pub fn arrangement(&self, name: &str) -> Option<&SongArrangement<'a>> {
+        self.arrangements.iter().find(|a| a.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Tone definitions from the arrangement manifests, deduplicated by tone key.
+    pub fn tones(&self) -> Vec<serde_json::Value> {
+        let mut tones: Vec<serde_json::Value> = Vec::new();
+        for attrs in self.arrangements.iter().filter_map(|a| a.manifest.as_ref()) {
+            for tone in attrs.get("Tones").and_then(|t| t.as_array()).into_iter().flatten() {
+                let key = tone.get("Key").or_else(|| tone.get("Name"));
+                if !tones.iter().any(|t| t.get("Key").or_else(|| t.get("Name")) == key) {
+                    tones.push(tone.clone());
+                }
+            }
+        }
+        tones
+    }
+
+    /// Lyrics of the vocals arrangement, parsed on demand. Empty when the song has no vocals.
+    pub fn lyrics(&self) -> io::Result<&[Vocal]> {
+        match self.arrangements.iter().find(|a| a.name == "vocals") {
+            Some(vocals) => Ok(&vocals.sng()?.vocals),
+            None => Ok(&[]),
+        }
+    }
+
+    /// Album art textures (usually 64, 128 and 256 pixel DDS files).
+    pub fn album_art(&self) -> &[AssetHandle<'a>] {
+        &self.art
+    }
+
+    /// Audio streams (main song and preview wems).
+    pub fn audio(&self) -> &[AssetHandle<'a>] {
+        &self.audio
+    }
+
+    /// Wems of the preview soundbank. Empty for packages shipped without a preview.
+    pub fn preview_audio(&self) -> &[AssetHandle<'a>] {
+        &self.preview
+    }
+
+    /// The full-length song stream: the largest wem that is not a preview.
+    pub fn main_audio(&self) -> Option<&AssetHandle<'a>> {
+        self.audio
+            .iter()
+            .filter(|a| !self.preview.iter().any(|p| std::ptr::eq(p.entry, a.entry)))
+            .max_by_key(|a| a.entry.length)
+    }
+
+    /// Where a preview should start, in seconds: the manifest's `PreviewStartTime`, else
+    /// the start of the first chorus of the first instrument arrangement.
+    pub fn preview_start(&self) -> Option<f64> {
+        let from_manifest = self
+            .arrangements
+            .iter()
+            .filter_map(|a| a.manifest.as_ref())
+            .find_map(|attrs| attrs.get("PreviewStartTime").and_then(|v| v.as_f64()));
+        if from_manifest.is_some() {
+            return from_manifest;
+        }
+        self.arrangements
+            .iter()
+            .filter(|a| !a.is_vocals())
+            .find_map(|a| {
+                let sng = a.sng().ok()?;
+                sng.sections
+                    .iter()
+                    .zip(&sng.section_labels)
+                    .find(|(_, label)| label.kind == SectionKind::Chorus)
+                    .map(|(section, _)| section.start_time as f64)
+            })
+    }
+
+    #[cfg(feature = "audio")]
+    /// Builds a preview of `seconds` cut from the main track at `preview_start` (the start
+    /// of the song when unknown), for packages whose preview wem is missing.
+    ///
+    /// Vorbis tracks are rebuilt by `converter` and cut at packet boundaries into an Ogg
+    /// (see `vorbis::ogg_excerpt`); PCM and ADPCM tracks are cut into a WAV with short
+    /// fades. Other codecs give `ErrorKind::Unsupported`.
+    pub fn synthesize_preview(&self, seconds: f64, converter: &AudioConverter) -> io::Result<ConvertedAudio> {
+        let main = self
+            .main_audio()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("Song {} has no audio", self.key)))?;
+        let start = self.preview_start().unwrap_or(0.0);
+        let data = main.read()?;
+        match WemInfo::parse(&data)?.codec() {
+            WemCodec::Vorbis => {
+                let ogg = converter.convert(&data)?.into_data();
+                Ok(ConvertedAudio::Ogg(ogg_excerpt(&ogg, start, seconds)?))
+            }
+            _ => Ok(ConvertedAudio::Wav(wem_excerpt_to_wav(&data, start, seconds, PREVIEW_FADE_SECONDS)?)),
+        }
+    }
+}
@@ -0,0 +1,240 @@
+//! Finding Rocksmith 2014 and its DLC folder through the Steam installation, so a whole
+//! library can be processed without looking the paths up.
+//!
+//! Steam keeps its library folders in `steamapps/libraryfolders.vdf` and each game's
+//! install folder name in `steamapps/appmanifest_<app id>.acf`, both in Valve's
+//! KeyValues text format (see `parse_vdf`). The Steam installation itself is looked for in
+//! the default places of each platform, or in the folder named by `STEAM_DIR`:
+//! - Windows: `%ProgramFiles(x86)%\Steam`, `%ProgramFiles%\Steam`.
+//! - macOS: `~/Library/Application Support/Steam`.
+//! - Linux: `~/.steam/steam`, `~/.local/share/Steam` and the Flatpak folder.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::psarc_set::find_archives;
+
+/// Steam app id of Rocksmith 2014.
+pub const ROCKSMITH_2014_APP_ID: &str = "221680";
+
+/// Install folder of Rocksmith 2014 when its app manifest does not say.
+const DEFAULT_INSTALL_DIR: &str = "Rocksmith2014";
+
+/// Environment variable naming the Steam installation, checked before the defaults.
+pub const STEAM_DIR_ENV: &str = "STEAM_DIR";
+
+/// A KeyValues value: a string or a list of keyed values, in file order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VdfValue {
+    String(String),
+    Object(Vec<(String, VdfValue)>),
+}
+
+impl VdfValue {
+    /// The value of `key` in an object, ignoring case like Steam does.
+    pub fn get(&self, key: &str) -> Option<&VdfValue> {
+        match self {
+            VdfValue::Object(entries) => entries.iter().find(|(k, _)| k.eq_ignore_ascii_case(key)).map(|(_, v)| v),
+            VdfValue::String(_) => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            VdfValue::String(value) => Some(value),
+            VdfValue::Object(_) => None,
+        }
+    }
+
+    /// The entries of an object, empty for a string.
+    pub fn entries(&self) -> &[(String, VdfValue)] {
+        match self {
+            VdfValue::Object(entries) => entries,
+            VdfValue::String(_) => &[],
+        }
+    }
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// Splits KeyValues text into quoted strings (escapes resolved), bare words and braces.
+/// `//` comments are skipped.
+fn tokenize(text: &str) -> io::Result<Vec<String>> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '{' | '}' => tokens.push(c.to_string()),
+            '/' if chars.peek() == Some(&'/') => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '"' => {
+                let mut token = String::new();
+                loop {
+                    match chars.next().ok_or_else(|| invalid("Unterminated string in VDF"))? {
+                        '"' => break,
+                        '\\' => match chars.next() {
+                            Some('n') => token.push('\n'),
+                            Some('t') => token.push('\t'),
+                            Some(other) => token.push(other),
+                            None => return Err(invalid("Unterminated string in VDF")),
+                        },
+                        c => token.push(c),
+                    }
+                }
+                // Quoted tokens are marked so a quoted "{" is not a brace.
+                tokens.push(format!("\"{}", token));
+            }
+            c => {
+                let mut token = format!("\"{}", c);
+                while let Some(&next) = chars.peek() {
+                    if next.is_whitespace() || next == '{' || next == '}' || next == '"' {
+                        break;
+                    }
+                    token.push(next);
+                    chars.next();
+                }
+                tokens.push(token);
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+fn parse_object(tokens: &mut std::vec::IntoIter<String>, nested: bool) -> io::Result<VdfValue> {
+    let mut entries = Vec::new();
+    while let Some(token) = tokens.next() {
+        let key = match token.strip_prefix('"') {
+            Some(key) => key.to_string(),
+            None if token == "}" && nested => return Ok(VdfValue::Object(entries)),
+            None => return Err(invalid(format!("Unexpected {:?} in VDF", token))),
+        };
+        let value = match tokens.next() {
+            Some(token) if token == "{" => parse_object(tokens, true)?,
+            Some(token) => match token.strip_prefix('"') {
+                Some(value) => VdfValue::String(value.to_string()),
+                None => return Err(invalid(format!("Unexpected {:?} after {:?} in VDF", token, key))),
+            },
+            None => return Err(invalid(format!("Missing value for {:?} in VDF", key))),
+        };
+        entries.push((key, value));
+    }
+    if nested {
+        return Err(invalid("Unclosed object in VDF"));
+    }
+    Ok(VdfValue::Object(entries))
+}
+
+/// Parses a KeyValues (`.vdf`, `.acf`) document into an object holding its root entries.
+pub fn parse_vdf(text: &str) -> io::Result<VdfValue> {
+    parse_object(&mut tokenize(text)?.into_iter(), false)
+}
+
+/// Folders that hold a Steam installation: `STEAM_DIR` when set, then the default places
+/// of the platform, keeping those that exist.
+pub fn steam_roots() -> Vec<PathBuf> {
+    let mut candidates: Vec<PathBuf> = env::var_os(STEAM_DIR_ENV).map(PathBuf::from).into_iter().collect();
+    let home = env::var_os("HOME").map(PathBuf::from);
+    if cfg!(windows) {
+        for variable in ["ProgramFiles(x86)", "ProgramFiles"] {
+            candidates.extend(env::var_os(variable).map(|dir| PathBuf::from(dir).join("Steam")));
+        }
+    } else if cfg!(target_os = "macos") {
+        candidates.extend(home.map(|home| home.join("Library/Application Support/Steam")));
+    } else if let Some(home) = home {
+        candidates.push(home.join(".steam/steam"));
+        candidates.push(home.join(".local/share/Steam"));
+        candidates.push(home.join(".var/app/com.valvesoftware.Steam/.local/share/Steam"));
+    }
+    let mut roots: Vec<PathBuf> = Vec::new();
+    for candidate in candidates.into_iter().filter(|dir| dir.join("steamapps").is_dir()) {
+        // `~/.steam/steam` is usually a link to `~/.local/share/Steam`.
+        let resolved = fs::canonicalize(&candidate).unwrap_or(candidate);
+        if !roots.contains(&resolved) {
+            roots.push(resolved);
+        }
+    }
+    roots
+}
+
+/// Library folders of the Steam installation at `root`, `root` first. Both the current
+/// `libraryfolders.vdf` layout (`"0" { "path" "..." }`) and the older one (`"1" "..."`)
+/// are read; a missing file leaves `root` alone.
+pub fn library_folders(root: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut folders = vec![root.to_path_buf()];
+    let text = match fs::read_to_string(root.join("steamapps").join("libraryfolders.vdf")) {
+        Ok(text) => text,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(folders),
+        Err(err) => return Err(err),
+    };
+    let document = parse_vdf(&text)?;
+    let libraries = document.entries().first().map(|(_, value)| value.entries()).unwrap_or_default();
+    for (key, value) in libraries {
+        if !key.chars().all(|c| c.is_ascii_digit()) {
+            continue;
+        }
+        let path = value.as_str().or_else(|| value.get("path").and_then(VdfValue::as_str));
+        if let Some(path) = path.map(PathBuf::from) {
+            if !folders.contains(&path) {
+                folders.push(path);
+            }
+        }
+    }
+    Ok(folders)
+}
+
+/// Install folder of app `app_id` in a library folder, read from its app manifest.
+fn install_dir(library: &Path, app_id: &str) -> Option<PathBuf> {
+    let steamapps = library.join("steamapps");
+    let manifest = fs::read_to_string(steamapps.join(format!("appmanifest_{}.acf", app_id))).ok()?;
+    let name = parse_vdf(&manifest)
+        .ok()
+        .and_then(|document| {
+            let state = document.get("AppState")?;
+            state.get("installdir")?.as_str().map(str::to_string)
+        })
+        .unwrap_or_else(|| DEFAULT_INSTALL_DIR.to_string());
+    Some(steamapps.join("common").join(name))
+}
+
+/// Install folders of Rocksmith 2014 in every Steam library found.
+pub fn rocksmith_install_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    for root in steam_roots() {
+        let libraries = library_folders(&root).unwrap_or_else(|err| {
+            tracing::warn!("Could not read the Steam libraries of {:?}: {}", root, err);
+            vec![root.clone()]
+        });
+        for library in libraries {
+            let dir = install_dir(&library, ROCKSMITH_2014_APP_ID)
+                .unwrap_or_else(|| library.join("steamapps").join("common").join(DEFAULT_INSTALL_DIR));
+            if dir.is_dir() && !dirs.contains(&dir) {
+                dirs.push(dir);
+            }
+        }
+    }
+    dirs
+}
+
+/// `dlc` folders of the Rocksmith 2014 installations found.
+pub fn rocksmith_dlc_dirs() -> Vec<PathBuf> {
+    rocksmith_install_dirs().into_iter().map(|dir| dir.join("dlc")).filter(|dir| dir.is_dir()).collect()
+}
+
+/// Every `.psarc` below the DLC folders found, sorted by path within each folder.
+pub fn steam_dlc_archives() -> io::Result<Vec<PathBuf>> {
+    let mut archives = Vec::new();
+    for dir in rocksmith_dlc_dirs() {
+        archives.extend(find_archives(&dir)?);
+    }
+    Ok(archives)
+}
@@ -0,0 +1,230 @@
+//! Archive access reading entries from the file on demand instead of loading it whole.
+//!
+//! `PsarcStream` keeps the header and TOC only. `read_entries` reads the stored blocks of
+//! the requested entries, in order, on a background thread that runs up to `prefetch`
+//! blocks ahead of the block being inflated: on spinning disks and network shares the
+//! reads overlap with decompression and with whatever the caller does with each entry.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufReader, Cursor, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::thread;
+
+use flate2::read::DeflateDecoder;
+
+use crate::error::{PsarcError, Result};
+use crate::psarc::{normalize_entry_path, PsarcAsset, PsarcFileHeader, PsarcTOC, PsarcTOCEntry, TextAsset};
+
+/// Blocks read ahead by default: 1 MiB with the usual 64 KiB block size.
+const DEFAULT_PREFETCH: usize = 16;
+
+/// A block of an entry as stored in the data region.
+#[derive(Debug, Clone, Copy)]
+struct StoredBlock {
+    length: usize,
+    /// Listed in the block size table; zlib data unless the block does not start with
+    /// the zlib header.
+    compressed: bool,
+}
+
+/// An archive on disk read entry by entry.
+#[derive(Debug)]
+pub struct PsarcStream {
+    path: PathBuf,
+    pub header: PsarcFileHeader,
+    pub toc: PsarcTOC,
+    prefetch: usize,
+    /// Size of the file when it was opened; entries claiming blocks past it are refused
+    /// before anything is allocated for them.
+    file_length: u64,
+}
+
+impl PsarcStream {
+    /// Reads the header, the TOC and the NamesBlock, so entry paths are available
+    /// straight away. The entries themselves are not read.
+    pub fn open_path(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = File::open(&path)?;
+        let file_length = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+        let header = PsarcFileHeader::read_from(&mut reader)?;
+        let toc = PsarcTOC::read_from(&mut reader, &header)?;
+        let mut stream = PsarcStream { path, header, toc, prefetch: DEFAULT_PREFETCH, file_length };
+        if let Some(names_entry) = stream.toc.entries.first().cloned() {
+            let data = stream.read_entry(&names_entry)?;
+            let mut names_block = TextAsset::default();
+            names_block.read_from(&mut Cursor::new(&data), data.len())?;
+            stream.toc.assign_paths(&names_block);
+        }
+        Ok(stream)
+    }
+
+    /// Number of blocks the background thread reads ahead of the one being inflated (at
+    /// least 1).
+    pub fn prefetch(mut self, blocks: usize) -> Self {
+        self.prefetch = blocks.max(1);
+        self
+    }
+
+    /// The archive file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Finds the entry stored at `path`, compared like `PsarcFile::entry_by_path`.
+    pub fn entry_by_path(&self, path: &str) -> Option<&PsarcTOCEntry> {
+        let entries = || self.toc.entries.iter().filter_map(|e| e.path.as_deref().map(|p| (e, p)));
+        if let Some((entry, _)) = entries().find(|(_, p)| *p == path) {
+            return Some(entry);
+        }
+        let wanted = normalize_entry_path(path);
+        entries().find(|(_, p)| normalize_entry_path(p) == wanted).map(|(entry, _)| entry)
+    }
+
+    /// Reads and inflates one entry.
+    pub fn read_entry(&self, entry: &PsarcTOCEntry) -> Result<Vec<u8>> {
+        match self.read_entries(vec![entry.clone()])?.next() {
+            Some(read) => Ok(read?.1),
+            None => Err(PsarcError::MissingEntry(entry.path.clone().unwrap_or_else(|| entry.hash.clone()))),
+        }
+    }
+
+    /// Reads and inflates `entries` in the given order, reading ahead on a background
+    /// thread. Entries sorted by offset make the reads sequential. Dropping the iterator
+    /// stops the thread.
+    pub fn read_entries(&self, entries: Vec<PsarcTOCEntry>) -> Result<PrefetchedEntries> {
+        let planned = entries
+            .into_iter()
+            .map(|entry| {
+                let blocks = self.stored_blocks(&entry)?;
+                Ok((entry, blocks))
+            })
+            .collect::<Result<VecDeque<_>>>()?;
+        let reads: Vec<(u64, Vec<StoredBlock>)> =
+            planned.iter().map(|(entry, blocks)| (entry.offset, blocks.clone())).collect();
+
+        let block_size = self.header.block_size as usize;
+        let (sender, receiver) = sync_channel(self.prefetch);
+        let path = self.path.clone();
+        thread::spawn(move || {
+            let mut file = match File::open(&path) {
+                Ok(file) => file,
+                Err(err) => {
+                    let _ = sender.send(Err(err));
+                    return;
+                }
+            };
+            for (offset, blocks) in reads {
+                if let Err(err) = file.seek(SeekFrom::Start(offset)) {
+                    let _ = sender.send(Err(err));
+                    return;
+                }
+                for block in blocks {
+                    let mut data = vec![0u8; block.length];
+                    let read = file.read_exact(&mut data).map(|()| data);
+                    let failed = read.is_err();
+                    // The receiver is gone when the caller dropped the iterator.
+                    if sender.send(read).is_err() || failed {
+                        return;
+                    }
+                }
+            }
+        });
+        Ok(PrefetchedEntries { planned, receiver, failed: false, block_size })
+    }
+
+    /// The blocks `entry` occupies, as the block size table describes them. A block
+    /// missing from the table is stored raw, like in `PsarcFile::inflate_entry_data`.
+    fn stored_blocks(&self, entry: &PsarcTOCEntry) -> Result<Vec<StoredBlock>> {
+        let block_size = self.header.block_size as u64;
+        if block_size == 0 {
+            return Err(PsarcError::InvalidHeader("block size is zero".to_string()));
+        }
+        let num_blocks = entry.length.div_ceil(block_size);
+        if u64::from(entry.start_block) + num_blocks > u64::from(u32::MAX) + 1 {
+            return Err(PsarcError::BadToc("entry spans past the last block".to_string()));
+        }
+        let blocks: Vec<StoredBlock> = (0..num_blocks)
+            .map(|i| {
+                let index = (u64::from(entry.start_block) + i) as usize;
+                match self.toc.zip_block_sizes.get(index).copied().unwrap_or(0) {
+                    0 => StoredBlock {
+                        length: block_size.min(entry.length - i * block_size) as usize,
+                        compressed: false,
+                    },
+                    size => StoredBlock { length: size as usize, compressed: true },
+                }
+            })
+            .collect();
+        let stored: u64 = blocks.iter().map(|block| block.length as u64).sum();
+        if entry.offset.saturating_add(stored) > self.file_length {
+            return Err(PsarcError::BadToc(format!("entry {} runs past the end of the archive", entry.index)));
+        }
+        Ok(blocks)
+    }
+}
+
+/// Entries read by `PsarcStream::read_entries`, with their inflated content.
+pub struct PrefetchedEntries {
+    planned: VecDeque<(PsarcTOCEntry, Vec<StoredBlock>)>,
+    receiver: Receiver<io::Result<Vec<u8>>>,
+    failed: bool,
+    block_size: usize,
+}
+
+impl PrefetchedEntries {
+    fn inflate(&self, entry: &PsarcTOCEntry, blocks: &[StoredBlock]) -> Result<Vec<u8>> {
+        const ZIP_HEADER: [u8; 2] = [0x78, 0xDA];
+        let mut output = Vec::with_capacity((entry.length as usize).min(1 << 24));
+        for block in blocks {
+            let data = self
+                .receiver
+                .recv()
+                .map_err(|_| PsarcError::Io(io::Error::other("prefetch thread stopped")))??;
+            if block.compressed && data.starts_with(&ZIP_HEADER) {
+                // As in `unzip_block`, a block never inflates past the block size.
+                let inflated = DeflateDecoder::new(&data[2..])
+                    .take(self.block_size as u64 + 1)
+                    .read_to_end(&mut output)
+                    .map_err(|err| PsarcError::Decompression(err.to_string()))?;
+                if inflated > self.block_size {
+                    return Err(PsarcError::Decompression(format!(
+                        "block inflates past the {} byte block size",
+                        self.block_size
+                    )));
+                }
+            } else {
+                output.extend_from_slice(&data);
+            }
+        }
+        output.truncate(entry.length as usize);
+        Ok(output)
+    }
+}
+
+impl Iterator for PrefetchedEntries {
+    type Item = Result<(PsarcTOCEntry, Vec<u8>)>;
+
+    /// The next entry and its content. After an error the remaining entries are skipped,
+    /// since the blocks received no longer line up with them.
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let (entry, blocks) = self.planned.pop_front()?;
+        match self.inflate(&entry, &blocks) {
+            Ok(data) => Some(Ok((entry, data))),
+            Err(err) => {
+                self.failed = true;
+                Some(Err(err))
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = if self.failed { 0 } else { self.planned.len() };
+        (0, Some(remaining))
+    }
+}
@@ -0,0 +1,126 @@
+use std::io;
+use std::path::Path;
+use serde::Serialize;
+
+use crate::psarc::{PsarcFile, PsarcTOCEntry, SngAsset};
+use crate::wem::leading_silence;
+
+/// Allowed drift (seconds) between the manifest SongOffset and the SNG start time.
+const OFFSET_TOLERANCE: f64 = 0.01;
+
+/// Amplitude (fraction of full scale) above which audio is considered non-silent.
+const SILENCE_THRESHOLD: f32 = 0.01;
+
+/// A likely audio/chart synchronisation problem.
+#[derive(Debug, Clone, Serialize)]
+pub enum SyncIssue {
+    /// The manifest SongOffset does not match the negated SNG start time.
+    OffsetMismatch { song_offset: f64, start_time: f32 },
+    /// Notes are charted before the song's start time.
+    NotesBeforeStart { first_note_time: f32, start_time: f32 },
+    /// The audio is still silent when the first note is due.
+    AudioStartsAfterFirstNote { leading_silence: f64, first_note_time: f32 },
+}
+
+/// Result of the sync check for one arrangement.
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncReport {
+    pub entry_path: String,
+    pub first_note_time: f32,
+    pub start_time: f32,
+    pub song_offset: Option<f64>,
+    pub audio_path: Option<String>,
+    /// Leading silence of the main audio in seconds; `None` when it could not be measured
+    /// (Vorbis audio is not decoded in-crate).
+    pub leading_silence: Option<f64>,
+    pub issues: Vec<SyncIssue>,
+}
+
+/// Earliest note time over all difficulty levels, falling back to the first-note time
+/// stored in the metadata.
+fn first_note_time(asset: &SngAsset) -> f32 {
+    asset
+        .arrangements
+        .iter()
+        .filter_map(|level| level.notes.first().map(|n| n.time))
+        .fold(None, |min: Option<f32>, t| Some(min.map_or(t, |m| m.min(t))))
+        .unwrap_or(asset.metadata.unk11_first_note_time)
+}
+
+/// Reads `Attributes.SongOffset` from the manifest JSON matching an SNG entry
+/// (`.../<key>_<arrangement>.json`).
+fn manifest_song_offset(psarc: &PsarcFile, sng_path: &str) -> Option<f64> {
+    let stem = Path::new(sng_path).file_stem()?.to_string_lossy().to_string();
+    let manifest_name = format!("{}.json", stem);
+    let entry = psarc.get_entry_by_file_name(&manifest_name)?;
+    let data = psarc.inflate_entry_data(entry).ok()?;
+    let value: serde_json::Value = serde_json::from_slice(&data).ok()?;
+    value
+        .get("Entries")?
+        .as_object()?
+        .values()
+        .find_map(|e| e.get("Attributes")?.get("SongOffset")?.as_f64())
+}
+
+/// The main song audio, taken as the largest wem (previews are short excerpts).
+fn main_audio_entry(psarc: &PsarcFile) -> Option<&PsarcTOCEntry> {
+    psarc
+        .toc
+        .entries
+        .iter()
+        .filter(|e| e.path.as_deref().is_some_and(|p| p.ends_with(".wem")))
+        .max_by_key(|e| e.length)
+}
+
+/// Cross-checks the first note time, the manifest SongOffset and the leading silence of the
+/// main audio for every instrumental arrangement of the archive.
+pub fn check_sync(psarc: &PsarcFile) -> io::Result<Vec<SyncReport>> {
+    let audio_entry = main_audio_entry(psarc);
+    let silence = match audio_entry {
+        Some(entry) => leading_silence(&psarc.inflate_entry_data(entry)?, SILENCE_THRESHOLD)?,
+        None => None,
+    };
+
+    let mut reports = Vec::new();
+    for entry in &psarc.toc.entries {
+        let path = match &entry.path {
+            Some(p) if p.ends_with(".sng") && !p.contains("vocals") => p,
+            _ => continue,
+        };
+        let asset = psarc.read_sng_entry(entry)?;
+        let first_note = first_note_time(&asset);
+        let start_time = asset.metadata.start_time;
+        let song_offset = manifest_song_offset(psarc, path);
+
+        let mut issues = Vec::new();
+        if let Some(offset) = song_offset {
+            if (offset + start_time as f64).abs() > OFFSET_TOLERANCE {
+                issues.push(SyncIssue::OffsetMismatch { song_offset: offset, start_time });
+            }
+        }
+        if first_note < start_time {
+            issues.push(SyncIssue::NotesBeforeStart { first_note_time: first_note, start_time });
+        }
+        if let Some(silence) = silence {
+            if silence > first_note as f64 {
+                issues.push(SyncIssue::AudioStartsAfterFirstNote {
+                    leading_silence: silence,
+                    first_note_time: first_note,
+                });
+            }
+        }
+        for issue in &issues {
+            tracing::warn!("{}: possible sync problem: {:?}", path, issue);
+        }
+        reports.push(SyncReport {
+            entry_path: path.clone(),
+            first_note_time: first_note,
+            start_time,
+            song_offset,
+            audio_path: audio_entry.and_then(|e| e.path.clone()),
+            leading_silence: silence,
+            issues,
+        });
+    }
+    Ok(reports)
+}
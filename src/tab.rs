@@ -0,0 +1,362 @@
+//! Plain-text (ASCII) tablature of an arrangement.
+//!
+//! The chart (full difficulty unless a level is chosen) is laid on a grid built from the beat map: every beat is split
+//! into `TabOptions::columns_per_beat` columns and bar lines fall on the downbeats, so
+//! measures line up with the song's own meter rather than a fixed number of columns.
+//! Two arrangements can share one grid, drawn as a two-staff tab for duo practice.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::pitch::open_string_pitch;
+use crate::psarc::SngAsset;
+use crate::song::Song;
+
+const NOTE_NAMES: [&str; 12] = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+
+/// Layout of a rendered tab.
+#[derive(Debug, Clone)]
+pub struct TabOptions {
+    /// Columns per beat: 4 gives sixteenth-note resolution.
+    pub columns_per_beat: usize,
+    /// Measures on one line of tab before wrapping.
+    pub measures_per_line: usize,
+    /// Strings drawn: 6 for guitar, 4 for bass.
+    pub strings: usize,
+    /// Dynamic difficulty level to draw. `None` draws the full chart.
+    pub difficulty: Option<i32>,
+}
+
+impl Default for TabOptions {
+    fn default() -> Self {
+        TabOptions { columns_per_beat: 4, measures_per_line: 4, strings: 6, difficulty: None }
+    }
+}
+
+impl TabOptions {
+    pub fn new() -> Self {
+        TabOptions::default()
+    }
+
+    /// Defaults for an arrangement name: four strings for bass, six otherwise.
+    pub fn for_arrangement(name: &str) -> Self {
+        TabOptions {
+            strings: if name.to_lowercase().contains("bass") { 4 } else { 6 },
+            ..TabOptions::default()
+        }
+    }
+
+    pub fn columns_per_beat(mut self, columns: usize) -> Self {
+        self.columns_per_beat = columns.max(1);
+        self
+    }
+
+    pub fn measures_per_line(mut self, measures: usize) -> Self {
+        self.measures_per_line = measures.max(1);
+        self
+    }
+
+    pub fn strings(mut self, strings: usize) -> Self {
+        self.strings = strings.clamp(1, 6);
+        self
+    }
+
+    pub fn difficulty(mut self, difficulty: Option<i32>) -> Self {
+        self.difficulty = difficulty;
+        self
+    }
+}
+
+/// Name of each string (lowest first) from the tuning offsets of the chart.
+fn string_names(tuning: &[i16], strings: usize) -> Vec<String> {
+    (0..strings)
+        .map(|s| NOTE_NAMES[open_string_pitch(tuning, s, strings == 4).rem_euclid(12) as usize].to_string())
+        .collect()
+}
+
+/// Grid column of a time: the beat it falls in, plus its position within that beat.
+fn column_of(beats: &[f32], time: f32, columns_per_beat: usize) -> usize {
+    let beat = beats.partition_point(|b| *b <= time).saturating_sub(1);
+    let length = match (beats.get(beat), beats.get(beat + 1)) {
+        (Some(start), Some(next)) => next - start,
+        _ if beat > 0 => beats[beat] - beats[beat - 1],
+        _ => 0.0,
+    };
+    let fraction = if length > 0.0 { ((time - beats[beat]) / length).max(0.0) } else { 0.0 };
+    beat * columns_per_beat + (fraction * columns_per_beat as f32).round() as usize
+}
+
+/// One staff of a rendered tab: string names and the frets of each grid column.
+struct Staff {
+    names: Vec<String>,
+    /// Column → fret per string.
+    cells: BTreeMap<usize, [Option<u8>; 6]>,
+}
+
+impl Staff {
+    /// The chart of `sng` placed on the grid of `beats`.
+    fn new(sng: &SngAsset, options: &TabOptions, beats: &[f32]) -> Self {
+        let per_beat = options.columns_per_beat.max(1);
+        let mut cells: BTreeMap<usize, [Option<u8>; 6]> = BTreeMap::new();
+        for note in sng.notes_at_difficulty(options.difficulty.unwrap_or(i32::MAX)) {
+            let column = cells.entry(column_of(beats, note.time, per_beat)).or_default();
+            match usize::try_from(note.chord_id).ok().and_then(|id| sng.chords.get(id)) {
+                Some(chord) => {
+                    for (string, fret) in chord.frets.iter().enumerate() {
+                        if *fret != u8::MAX {
+                            column[string] = Some(*fret);
+                        }
+                    }
+                }
+                None => {
+                    if let Some(cell) = column.get_mut(note.string_index as usize) {
+                        *cell = Some(note.fret_id);
+                    }
+                }
+            }
+        }
+        Staff { names: string_names(&sng.metadata.tuning, options.strings.clamp(1, 6)), cells }
+    }
+}
+
+/// Renders the chart of an arrangement as ASCII tab, highest string on top.
+pub fn render_ascii_tab(sng: &SngAsset, options: &TabOptions) -> io::Result<String> {
+    let beats: Vec<f32> = sng.bpms.iter().map(|b| b.time).collect();
+    if beats.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Chart has no beat map"));
+    }
+    let staff = Staff::new(sng, options, &beats);
+    Ok(render_staves(sng, &[staff], options))
+}
+
+/// Renders two charts of a song as a two-staff tab, `first` above `second`, for players
+/// practising the parts together. Both are laid on the beat map of `first`, so a column
+/// is the same moment on both staves; the layout (columns per beat, measures per line)
+/// comes from `first_options`. `midi::duo_midi` writes the same pair as a two-voice file
+/// for notation programs.
+pub fn render_duo_tab(
+    first: &SngAsset,
+    first_options: &TabOptions,
+    second: &SngAsset,
+    second_options: &TabOptions,
+) -> io::Result<String> {
+    let beats: Vec<f32> = first.bpms.iter().map(|b| b.time).collect();
+    if beats.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Chart has no beat map"));
+    }
+    let second_options = second_options.clone().columns_per_beat(first_options.columns_per_beat);
+    let staves = [Staff::new(first, first_options, &beats), Staff::new(second, &second_options, &beats)];
+    Ok(render_staves(first, &staves, first_options))
+}
+
+/// Lays staves out measure by measure on the beat map of `sng`. Columns are as wide as
+/// the widest fret of any staff, so the staves stay aligned.
+fn render_staves(sng: &SngAsset, staves: &[Staff], options: &TabOptions) -> String {
+    let per_beat = options.columns_per_beat.max(1);
+    let beats = sng.bpms.len();
+
+    // Measures as [start, end) beat ranges, split on the downbeats; the last measure is
+    // stretched to hold notes past the end of the beat map.
+    let mut starts: Vec<usize> = sng
+        .bpms
+        .iter()
+        .enumerate()
+        .filter(|(_, bpm)| bpm.beat == 0)
+        .map(|(i, _)| i)
+        .collect();
+    if starts.first() != Some(&0) {
+        starts.insert(0, 0);
+    }
+    let last_column = staves.iter().filter_map(|staff| staff.cells.keys().next_back()).copied().max().unwrap_or(0);
+    let end_beat = beats.max(last_column / per_beat + 1);
+    let measures: Vec<(usize, usize)> = starts
+        .iter()
+        .enumerate()
+        .map(|(i, &start)| (start, starts.get(i + 1).copied().unwrap_or(end_beat)))
+        .collect();
+
+    let label_width = staves.iter().flat_map(|staff| &staff.names).map(|n| n.len()).max().unwrap_or(1);
+    let mut tab = String::new();
+    for line in measures.chunks(options.measures_per_line.max(1)) {
+        let mut rows: Vec<Vec<String>> = staves
+            .iter()
+            .map(|staff| staff.names.iter().map(|name| format!("{:<width$}|", name, width = label_width)).collect())
+            .collect();
+        for &(start, end) in line {
+            for column in start * per_beat..end * per_beat {
+                let text: Vec<Vec<Option<String>>> = staves
+                    .iter()
+                    .map(|staff| {
+                        let frets = staff.cells.get(&column);
+                        (0..staff.names.len())
+                            .map(|s| frets.and_then(|f| f[s]).map(|fret| fret.to_string()))
+                            .collect()
+                    })
+                    .collect();
+                let width = text.iter().flatten().flatten().map(String::len).max().unwrap_or(1);
+                for (staff_rows, staff_text) in rows.iter_mut().zip(&text) {
+                    for (row, cell) in staff_rows.iter_mut().zip(staff_text) {
+                        let cell = cell.as_deref().unwrap_or("");
+                        row.push_str(cell);
+                        row.push_str(&"-".repeat(width - cell.len() + 1));
+                    }
+                }
+            }
+            for row in rows.iter_mut().flatten() {
+                row.push('|');
+            }
+        }
+        for staff_rows in &rows {
+            for row in staff_rows.iter().rev() {
+                tab.push_str(row);
+                tab.push('\n');
+            }
+            tab.push('\n');
+        }
+    }
+    tab
+}
+
+/// Writes the tab of an arrangement (`lead`, `rhythm`, `bass`, ...) of a song to a text
+/// file, headed by the artist and title, with the default layout for that arrangement.
+pub fn export_ascii_tab(song: &Song, arrangement: &str, output_path: &Path) -> io::Result<()> {
+    let found = song.arrangement(arrangement).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Song {} has no arrangement {}", song.key, arrangement),
+        )
+    })?;
+    let tab = render_ascii_tab(found.sng()?, &TabOptions::for_arrangement(&found.name))?;
+    let title = song.metadata.title.as_deref().unwrap_or(&song.key);
+    let header = match &song.metadata.artist {
+        Some(artist) => format!("{} - {} ({})\n\n", artist, title, found.name),
+        None => format!("{} ({})\n\n", title, found.name),
+    };
+    fs::write(output_path, header + &tab)?;
+    tracing::info!("Written tab to {:?}", output_path);
+    Ok(())
+}
+
+/// Writes two arrangements of a song (typically `lead` and `rhythm`) as one two-staff
+/// tab, `first` on top, aligned on the beat map of `first`.
+pub fn export_duo_tab(song: &Song, first: &str, second: &str, output_path: &Path) -> io::Result<()> {
+    let find = |name: &str| {
+        song.arrangement(name).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("Song {} has no arrangement {}", song.key, name))
+        })
+    };
+    let (first, second) = (find(first)?, find(second)?);
+    let tab = render_duo_tab(
+        first.sng()?,
+        &TabOptions::for_arrangement(&first.name),
+        second.sng()?,
+        &TabOptions::for_arrangement(&second.name),
+    )?;
+    let title = song.metadata.title.as_deref().unwrap_or(&song.key);
+    let header = match &song.metadata.artist {
+        Some(artist) => format!("{} - {} ({} / {})\n\n", artist, title, first.name, second.name),
+        None => format!("{} ({} / {})\n\n", title, first.name, second.name),
+    };
+    fs::write(output_path, header + &tab)?;
+    tracing::info!("Written tab to {:?}", output_path);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Arrangement, Bpm, Note, PhraseIteration};
+
+    fn bpm(time: f32, beat: i16) -> Bpm {
+        Bpm { time, measure: 0, beat, phrase_iteration: 0, mask: 0 }
+    }
+
+    fn note(time: f32, string_index: u8, fret_id: u8) -> Note {
+        Note {
+            note_mask: 0,
+            note_flags: 0,
+            hash: 0,
+            time,
+            string_index,
+            fret_id,
+            anchor_fret_id: 0,
+            anchor_width: 0,
+            chord_id: -1,
+            chord_notes_id: -1,
+            phrase_id: 0,
+            phrase_iteration_id: 0,
+            finger_print_id: [-1, -1],
+            next_iter_note: -1,
+            prev_iter_note: -1,
+            parent_prev_note: -1,
+            slide_to: u8::MAX,
+            slide_unpitch_to: u8::MAX,
+            left_hand: u8::MAX,
+            tap: u8::MAX,
+            pick_direction: 0,
+            slap: u8::MAX,
+            pluck: u8::MAX,
+            vibrato: 0,
+            sustain: 0.0,
+            max_bend: 0.0,
+            bend_data: Vec::new(),
+        }
+    }
+
+    /// A single-level chart of `notes` on a four-beat bar at 120 BPM.
+    fn chart(notes: Vec<Note>) -> SngAsset {
+        let level = Arrangement {
+            difficulty: 0,
+            anchors: Vec::new(),
+            anchor_extensions: Vec::new(),
+            fingerprints1: Vec::new(),
+            fingerprints2: Vec::new(),
+            notes,
+            phrase_count: 0,
+            average_notes_per_iteration: Vec::new(),
+            phrase_iteration_count1: 0,
+            notes_in_iteration1: Vec::new(),
+            phrase_iteration_count2: 0,
+            notes_in_iteration2: Vec::new(),
+        };
+        SngAsset {
+            bpms: vec![bpm(0.0, 0), bpm(0.5, 1), bpm(1.0, 2), bpm(1.5, 3)],
+            phrase_iterations: vec![PhraseIteration {
+                phrase_id: 0,
+                start_time: 0.0,
+                next_phrase_time: 2.0,
+                difficulty: [0; 3],
+            }],
+            arrangements: vec![level],
+            ..SngAsset::default()
+        }
+    }
+
+    #[test]
+    fn times_map_onto_beat_columns() {
+        let beats = [0.0, 0.5, 1.0, 2.0];
+        assert_eq!(column_of(&beats, 0.0, 4), 0);
+        assert_eq!(column_of(&beats, 0.5, 4), 4);
+        assert_eq!(column_of(&beats, 0.625, 4), 5);
+        // The third beat lasts a second, so a quarter of it is one column.
+        assert_eq!(column_of(&beats, 1.25, 4), 9);
+        // Past the beat map the last beat length repeats.
+        assert_eq!(column_of(&beats, 2.5, 4), 14);
+        // Notes before the first beat land on column 0.
+        assert_eq!(column_of(&[1.0, 1.5], 0.2, 4), 0);
+    }
+
+    #[test]
+    fn notes_are_drawn_in_their_columns() {
+        let sng = chart(vec![note(0.5, 0, 3), note(1.25, 5, 12)]);
+        let tab = render_ascii_tab(&sng, &TabOptions::new().columns_per_beat(2)).unwrap();
+        let rows: Vec<&str> = tab.lines().collect();
+        assert_eq!(rows.len(), 7);
+        // The two-digit fret widens its column on every string.
+        assert_eq!(rows[0], "E|----------12-----|");
+        assert_eq!(rows[5], "E|----3------------|");
+        assert!(rows[6].is_empty());
+    }
+}
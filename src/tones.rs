@@ -0,0 +1,198 @@
+use std::io;
+use std::path::Path;
+use serde_json::Value;
+
+use crate::psarc::PsarcFile;
+use crate::writer::{copy_entry_compressed, PsarcWriter};
+
+/// A tone slot of an arrangement. Tone change events in the SNG refer to slots A-D, so
+/// replacing the tone held by a slot changes every section using it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToneSlot {
+    Base,
+    A,
+    B,
+    C,
+    D,
+}
+
+impl ToneSlot {
+    /// Manifest attribute naming the tone held by this slot.
+    pub fn attribute(&self) -> &'static str {
+        match self {
+            ToneSlot::Base => "Tone_Base",
+            ToneSlot::A => "Tone_A",
+            ToneSlot::B => "Tone_B",
+            ToneSlot::C => "Tone_C",
+            ToneSlot::D => "Tone_D",
+        }
+    }
+}
+
+impl std::str::FromStr for ToneSlot {
+    type Err = String;
+
+    /// `base`, `a`, `b`, `c` or `d`.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "base" => Ok(ToneSlot::Base),
+            "a" => Ok(ToneSlot::A),
+            "b" => Ok(ToneSlot::B),
+            "c" => Ok(ToneSlot::C),
+            "d" => Ok(ToneSlot::D),
+            other => Err(format!("Unknown tone slot {:?}, expected `base`, `a`, `b`, `c` or `d`", other)),
+        }
+    }
+}
+
+/// Tone key used by manifests to reference a tone (falls back to its name).
+fn tone_key(tone: &Value) -> Option<&str> {
+    tone.get("Key").or_else(|| tone.get("Name")).and_then(|k| k.as_str())
+}
+
+/// The `Attributes` objects of a manifest JSON document.
+fn manifest_attributes(manifest: &mut Value) -> Vec<&mut Value> {
+    manifest
+        .get_mut("Entries")
+        .and_then(|e| e.as_object_mut())
+        .map(|entries| entries.values_mut().filter_map(|e| e.get_mut("Attributes")).collect())
+        .unwrap_or_default()
+}
+
+/// True for per-arrangement manifest entries (`manifests/.../<key>_<arrangement>.json`).
+fn is_manifest(path: &str) -> bool {
+    path.starts_with("manifests/") && path.ends_with(".json")
+}
+
+/// Finds a tone definition by key or name (case-insensitive) in the manifests of an
+/// archive, ready to be imported into another package.
+pub fn export_tone(archive: &PsarcFile, key: &str) -> io::Result<Value> {
+    for entry in &archive.toc.entries {
+        if !entry.path.as_deref().is_some_and(is_manifest) {
+            continue;
+        }
+        let mut manifest: Value = serde_json::from_slice(&archive.inflate_entry_data(entry)?).map_err(io::Error::other)?;
+        for attrs in manifest_attributes(&mut manifest) {
+            let found = attrs
+                .get("Tones")
+                .and_then(|t| t.as_array())
+                .and_then(|tones| tones.iter().find(|t| tone_key(t).is_some_and(|k| k.eq_ignore_ascii_case(key))));
+            if let Some(tone) = found {
+                return Ok(tone.clone());
+            }
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::NotFound, format!("No tone named {} in the archive", key)))
+}
+
+/// Every tone key defined in the manifests of an archive, without duplicates.
+pub fn tone_keys(archive: &PsarcFile) -> io::Result<Vec<String>> {
+    let mut keys = Vec::new();
+    for entry in &archive.toc.entries {
+        if !entry.path.as_deref().is_some_and(is_manifest) {
+            continue;
+        }
+        let mut manifest: Value = serde_json::from_slice(&archive.inflate_entry_data(entry)?).map_err(io::Error::other)?;
+        for attrs in manifest_attributes(&mut manifest) {
+            for tone in attrs.get("Tones").and_then(|t| t.as_array()).into_iter().flatten() {
+                if let Some(key) = tone_key(tone).map(str::to_string) {
+                    if !keys.contains(&key) {
+                        keys.push(key);
+                    }
+                }
+            }
+        }
+    }
+    Ok(keys)
+}
+
+/// Puts `tone` into `slot` of an arrangement's manifest: the tone previously held by the
+/// slot is replaced in the `Tones` list unless another slot still uses it (otherwise the
+/// tone is appended), and the slot attribute, plus `Tone_Default` when it named the same
+/// tone, now reference the new tone key.
+/// Returns true when the manifest changed.
+fn apply_tone(attrs: &mut Value, tone: &Value, slot: ToneSlot) -> bool {
+    let new_key = match tone_key(tone) {
+        Some(key) => key.to_string(),
+        None => return false,
+    };
+    let old_key = attrs.get(slot.attribute()).and_then(|k| k.as_str()).map(str::to_string);
+    // The old tone stays when another slot still uses it.
+    let old_shared = [ToneSlot::Base, ToneSlot::A, ToneSlot::B, ToneSlot::C, ToneSlot::D]
+        .iter()
+        .filter(|other| **other != slot)
+        .any(|other| old_key.is_some() && attrs.get(other.attribute()).and_then(|k| k.as_str()) == old_key.as_deref());
+    let tones = match attrs.get_mut("Tones").and_then(|t| t.as_array_mut()) {
+        Some(tones) => tones,
+        None => return false,
+    };
+    let replaced = |t: &Value| {
+        tone_key(t) == Some(new_key.as_str()) || (!old_shared && old_key.is_some() && tone_key(t) == old_key.as_deref())
+    };
+    match tones.iter().position(replaced) {
+        Some(i) => {
+            tones[i] = tone.clone();
+            // Drop a second match, e.g. the old tone when the new key was already listed.
+            if let Some(j) = tones.iter().skip(i + 1).position(replaced) {
+                tones.remove(i + 1 + j);
+            }
+        }
+        None => tones.push(tone.clone()),
+    }
+    if let Some(object) = attrs.as_object_mut() {
+        if (slot == ToneSlot::Base || !old_shared)
+            && old_key.is_some()
+            && object.get("Tone_Default").and_then(|k| k.as_str()) == old_key.as_deref() {
+            object.insert("Tone_Default".to_string(), Value::String(new_key.clone()));
+        }
+        object.insert(slot.attribute().to_string(), Value::String(new_key));
+    }
+    true
+}
+
+/// Rebuilds `archive` with `tone` (from `export_tone`) applied to `slot` of the named
+/// arrangement (`lead`, `rhythm`, `bass`, ...). Tone change events keep working since they
+/// refer to slots, not tone keys. Returns the writer and the rewritten manifest paths.
+pub fn import_tone(
+    archive: &PsarcFile,
+    arrangement: &str,
+    tone: &Value,
+    slot: ToneSlot,
+) -> io::Result<(PsarcWriter, Vec<String>)> {
+    if tone_key(tone).is_none() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Tone has neither a Key nor a Name"));
+    }
+    let suffix = format!("_{}", arrangement.to_lowercase());
+    let mut writer = PsarcWriter::like(archive);
+    let mut rewritten = Vec::new();
+    for entry in archive.toc.entries.iter().skip(1) {
+        let path = entry.path.as_deref().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "Entry has no path; read the manifest first")
+        })?;
+        let is_target = is_manifest(path)
+            && Path::new(path)
+                .file_stem()
+                .is_some_and(|stem| stem.to_string_lossy().to_lowercase().ends_with(&suffix));
+        if is_target {
+            let mut manifest: Value = serde_json::from_slice(&archive.inflate_entry_data(entry)?).map_err(io::Error::other)?;
+            let mut changed = false;
+            for attrs in manifest_attributes(&mut manifest) {
+                changed |= apply_tone(attrs, tone, slot);
+            }
+            if changed {
+                let json = serde_json::to_string_pretty(&manifest).map_err(io::Error::other)?;
+                writer.add_entry(path, json.as_bytes())?;
+                rewritten.push(path.to_string());
+                continue;
+            }
+        }
+        copy_entry_compressed(archive, entry, &mut writer)?;
+    }
+    if rewritten.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No manifest with tones for arrangement {}", arrangement),
+        ));
+    }
+    Ok((writer, rewritten))
+}
@@ -0,0 +1,115 @@
+//! The `toolkit.version` entry custom song tools (the Custom Song Toolkit, DLC Builder)
+//! put at the root of their packages: one `Key: Value` line per field.
+//!
+//! ```text
+//! Toolkit version: 2.9.2.1-0e9bdaa9
+//! Package Author: someone
+//! Package Version: 3
+//! Package Comment: Fixed the bass tone
+//! ```
+//!
+//! Early toolkits wrote the version alone, without a key. Lines with other keys are kept,
+//! so a parsed entry is written back as it was.
+
+use serde::{Deserialize, Serialize};
+
+/// File name of the entry, at the root of the package.
+pub const TOOLKIT_VERSION_FILE_NAME: &str = "toolkit.version";
+
+/// The fields of a `toolkit.version` entry.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ToolkitInfo {
+    /// Version of the tool that built the package (`2.9.2.1-0e9bdaa9`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub toolkit_version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub package_author: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub package_version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub package_comment: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub package_rating: Option<String>,
+    /// Lines with any other key, in entry order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub other: Vec<(String, String)>,
+}
+
+impl ToolkitInfo {
+    pub fn new() -> Self {
+        ToolkitInfo::default()
+    }
+
+    pub fn toolkit_version(mut self, version: impl Into<String>) -> Self {
+        self.toolkit_version = Some(version.into());
+        self
+    }
+
+    pub fn package_author(mut self, author: impl Into<String>) -> Self {
+        self.package_author = Some(author.into());
+        self
+    }
+
+    pub fn package_version(mut self, version: impl Into<String>) -> Self {
+        self.package_version = Some(version.into());
+        self
+    }
+
+    pub fn package_comment(mut self, comment: impl Into<String>) -> Self {
+        self.package_comment = Some(comment.into());
+        self
+    }
+
+    pub fn package_rating(mut self, rating: impl Into<String>) -> Self {
+        self.package_rating = Some(rating.into());
+        self
+    }
+
+    /// Parses the text of a `toolkit.version` entry. Keys are matched ignoring case; a line
+    /// without a key is the toolkit version of early toolkits.
+    pub fn parse(text: &str) -> Self {
+        let mut info = ToolkitInfo::default();
+        for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let Some((key, value)) = line.split_once(':') else {
+                info.toolkit_version.get_or_insert_with(|| line.to_string());
+                continue;
+            };
+            let value = value.trim().to_string();
+            let field = match key.trim().to_ascii_lowercase().as_str() {
+                "toolkit version" => &mut info.toolkit_version,
+                "package author" => &mut info.package_author,
+                "package version" => &mut info.package_version,
+                "package comment" => &mut info.package_comment,
+                "package rating" => &mut info.package_rating,
+                _ => {
+                    info.other.push((key.trim().to_string(), value));
+                    continue;
+                }
+            };
+            *field = Some(value);
+        }
+        info
+    }
+
+    /// The entry text, fields in the order the toolkit writes them, CRLF separated.
+    pub fn to_text(&self) -> String {
+        let fields = [
+            ("Toolkit version", &self.toolkit_version),
+            ("Package Author", &self.package_author),
+            ("Package Version", &self.package_version),
+            ("Package Comment", &self.package_comment),
+            ("Package Rating", &self.package_rating),
+        ];
+        let mut lines: Vec<String> = fields
+            .iter()
+            .filter_map(|(key, value)| value.as_ref().map(|value| format!("{}: {}", key, value)))
+            .collect();
+        lines.extend(self.other.iter().map(|(key, value)| format!("{}: {}", key, value)));
+        lines.join("\r\n")
+    }
+
+    /// True when no field is set.
+    pub fn is_empty(&self) -> bool {
+        *self == ToolkitInfo::default()
+    }
+}
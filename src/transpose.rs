@@ -0,0 +1,317 @@
+//! Transposition of charts: moving every note along the neck, retuning, and changing
+//! the capo.
+//!
+//! Each operation checks the whole chart first and only then changes it, so an operation
+//! that would push any note off the neck fails with the chart untouched. Positions are
+//! absolute frets: a charted fret 0 is an open string, which sits at the capo when there
+//! is one.
+
+use std::io;
+
+use crate::psarc::SngAsset;
+
+/// Highest fret a transposed note may land on.
+pub const MAX_FRET: i32 = 24;
+
+/// Frets of a chart that are not played, such as the strings a chord leaves out.
+const UNUSED: u8 = u8::MAX;
+
+/// The capo fret of a chart, 0 without a capo.
+fn capo_of(sng: &SngAsset) -> i32 {
+    match sng.metadata.capo_fret_id {
+        UNUSED => 0,
+        capo => capo as i32,
+    }
+}
+
+/// Absolute position of a charted fret.
+fn position(fret: u8, capo: i32) -> i32 {
+    if fret == 0 { capo } else { fret as i32 }
+}
+
+/// Charted fret of an absolute position, `None` when it cannot be played.
+fn charted(position: i32, capo: i32) -> Option<u8> {
+    if position == capo {
+        Some(0)
+    } else if position > capo && position <= MAX_FRET {
+        Some(position as u8)
+    } else {
+        None
+    }
+}
+
+/// Calls `visit` with the string, time and fret of every played fret of the chart.
+fn visit_frets(sng: &mut SngAsset, visit: &mut dyn FnMut(usize, f32, &mut u8) -> io::Result<()>) -> io::Result<()> {
+    for arrangement in sng.arrangements.iter_mut() {
+        for note in arrangement.notes.iter_mut() {
+            let string = note.string_index as usize;
+            if string >= 6 {
+                continue;
+            }
+            for fret in [&mut note.fret_id, &mut note.slide_to, &mut note.slide_unpitch_to] {
+                if *fret != UNUSED {
+                    visit(string, note.time, fret)?;
+                }
+            }
+        }
+    }
+    for chord in sng.chords.iter_mut() {
+        for (string, fret) in chord.frets.iter_mut().enumerate() {
+            if *fret != UNUSED {
+                visit(string, -1.0, fret)?;
+            }
+        }
+    }
+    for chord_notes in sng.chord_notes.iter_mut() {
+        for string in 0..6 {
+            for fret in [&mut chord_notes.slide_to[string], &mut chord_notes.slide_unpitch_to[string]] {
+                if *fret != UNUSED {
+                    visit(string, -1.0, fret)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Moves every fret of the chart to `moved(string, position)`, played relative to
+/// `new_capo`, and shifts the anchors and chord pitches to match. Nothing changes when a
+/// fret would leave the neck.
+fn refret(sng: &mut SngAsset, new_capo: i32, pitch_shift: i32, moved: &dyn Fn(usize, i32) -> i32) -> io::Result<()> {
+    if !(0..=MAX_FRET).contains(&new_capo) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Capo at fret {} is off the neck", new_capo)));
+    }
+    let capo = capo_of(sng);
+    visit_frets(sng, &mut |string, time, fret| match charted(moved(string, position(*fret, capo)), new_capo) {
+        Some(_) => Ok(()),
+        None => {
+            let at = if time >= 0.0 { format!(" at {:.3}s", time) } else { " in a chord".to_string() };
+            Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Fret {} on string {}{} cannot be played after transposing", fret, string, at),
+            ))
+        }
+    })?;
+    visit_frets(sng, &mut |string, _, fret| {
+        *fret = charted(moved(string, position(*fret, capo)), new_capo).unwrap_or(*fret);
+        Ok(())
+    })?;
+
+    // Hand positions have no string: move them by the average shift of the strings.
+    let shift = |fret: u8| -> u8 {
+        let from = fret as i32;
+        let total: i32 = (0..6).map(|string| moved(string, from) - from).sum();
+        (from + (total as f32 / 6.0).round() as i32).clamp(1, MAX_FRET) as u8
+    };
+    for arrangement in sng.arrangements.iter_mut() {
+        for anchor in arrangement.anchors.iter_mut() {
+            anchor.fret_id = shift(anchor.fret_id);
+        }
+        for note in arrangement.notes.iter_mut() {
+            if note.anchor_fret_id != UNUSED && note.anchor_fret_id != 0 {
+                note.anchor_fret_id = shift(note.anchor_fret_id);
+            }
+        }
+    }
+    for chord in sng.chords.iter_mut() {
+        for (note, fret) in chord.notes.iter_mut().zip(chord.frets) {
+            if fret != UNUSED && *note >= 0 {
+                *note += pitch_shift;
+            }
+        }
+    }
+    if new_capo != 0 || sng.metadata.capo_fret_id != UNUSED {
+        sng.metadata.capo_fret_id = new_capo as u8;
+    }
+    Ok(())
+}
+
+/// Transposes the chart by `semitones`, moving every note (and the capo, if any) along
+/// the neck. Without a capo, open strings become fretted notes, so transposing down
+/// fails on charts that use them.
+pub fn shift_frets(sng: &mut SngAsset, semitones: i32) -> io::Result<()> {
+    let capo = capo_of(sng);
+    let new_capo = if capo == 0 { 0 } else { capo + semitones };
+    refret(sng, new_capo, semitones, &|_, position| position + semitones)?;
+    tracing::debug!("Shifted chart by {} frets", semitones);
+    Ok(())
+}
+
+/// Retunes the chart to `tuning` (semitone offsets from standard, lowest string first)
+/// keeping every note at its pitch: tuning a string up moves its notes down the neck.
+pub fn retune(sng: &mut SngAsset, tuning: &[i16]) -> io::Result<()> {
+    let old = sng.metadata.tuning.clone();
+    if tuning.len() != old.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Tuning has {} strings, the chart {}", tuning.len(), old.len()),
+        ));
+    }
+    let deltas: Vec<i32> = old.iter().zip(tuning).map(|(from, to)| (*to - *from) as i32).collect();
+    refret(sng, capo_of(sng), 0, &|string, position| {
+        position - deltas.get(string).copied().unwrap_or(0)
+    })?;
+    sng.metadata.tuning = tuning.to_vec();
+    tracing::debug!("Retuned chart from {:?} to {:?}", old, tuning);
+    Ok(())
+}
+
+/// Plays the chart with a capo at `capo` (0 removes it), keeping every note at its pitch.
+/// Fails when a note lies below the new capo.
+pub fn set_capo(sng: &mut SngAsset, capo: u8) -> io::Result<()> {
+    refret(sng, capo as i32, 0, &|_, position| position)
+}
+
+/// Converts a chart to the tuning of another, the way players move a song in a lower
+/// tuning onto a guitar left in standard: retunes to `tuning`, transposing up by however
+/// many semitones keep the notes on the neck, so the chart stays as close to its pitch as
+/// possible. The result has no capo. Returns the transposition in semitones.
+pub fn convert_tuning(sng: &mut SngAsset, tuning: &[i16]) -> io::Result<i32> {
+    let capo = sng.metadata.capo_fret_id;
+    set_capo(sng, 0)?;
+    let deltas: Vec<i32> = sng.metadata.tuning.iter().zip(tuning).map(|(from, to)| (*to - *from) as i32).collect();
+    // Strings tuned up move their notes down the neck; the lowest note of each string
+    // must stay at or above the nut.
+    let mut needed = 0;
+    visit_frets(sng, &mut |string, _, fret| {
+        needed = needed.max(deltas.get(string).copied().unwrap_or(0) - *fret as i32);
+        Ok(())
+    })?;
+    let converted = shift_frets(sng, needed).and_then(|_| {
+        retune(sng, tuning).inspect_err(|_| {
+            let _ = shift_frets(sng, -needed);
+        })
+    });
+    if let Err(err) = converted {
+        let _ = set_capo(sng, if capo == UNUSED { 0 } else { capo });
+        return Err(err);
+    }
+    Ok(needed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Arrangement, Chord, Note};
+
+    fn note(time: f32, string_index: u8, fret_id: u8) -> Note {
+        Note {
+            note_mask: 0,
+            note_flags: 0,
+            hash: 0,
+            time,
+            string_index,
+            fret_id,
+            anchor_fret_id: fret_id.max(1),
+            anchor_width: 4,
+            chord_id: -1,
+            chord_notes_id: -1,
+            phrase_id: 0,
+            phrase_iteration_id: 0,
+            finger_print_id: [-1, -1],
+            next_iter_note: -1,
+            prev_iter_note: -1,
+            parent_prev_note: -1,
+            slide_to: UNUSED,
+            slide_unpitch_to: UNUSED,
+            left_hand: UNUSED,
+            tap: UNUSED,
+            pick_direction: 0,
+            slap: UNUSED,
+            pluck: UNUSED,
+            vibrato: 0,
+            sustain: 0.0,
+            max_bend: 0.0,
+            bend_data: Vec::new(),
+        }
+    }
+
+    /// A chart in standard tuning: an open low E, a slide from fret 3 to 5 on the A
+    /// string, and an open-position E minor chord.
+    fn chart() -> SngAsset {
+        let mut slide = note(1.0, 1, 3);
+        slide.slide_to = 5;
+        let level = Arrangement {
+            difficulty: 0,
+            anchors: Vec::new(),
+            anchor_extensions: Vec::new(),
+            fingerprints1: Vec::new(),
+            fingerprints2: Vec::new(),
+            notes: vec![note(0.5, 0, 0), slide],
+            phrase_count: 0,
+            average_notes_per_iteration: Vec::new(),
+            phrase_iteration_count1: 0,
+            notes_in_iteration1: Vec::new(),
+            phrase_iteration_count2: 0,
+            notes_in_iteration2: Vec::new(),
+        };
+        let mut sng = SngAsset {
+            arrangements: vec![level],
+            chords: vec![Chord {
+                mask: 0,
+                frets: [0, 2, 2, 0, 0, 0],
+                fingers: [UNUSED; 6],
+                notes: [40, 47, 52, 55, 59, 64],
+                name: "Em".to_string(),
+            }],
+            ..SngAsset::default()
+        };
+        sng.metadata.tuning = vec![0; 6];
+        sng.metadata.capo_fret_id = UNUSED;
+        sng
+    }
+
+    fn frets(sng: &SngAsset) -> Vec<(u8, u8, u8)> {
+        sng.arrangements[0].notes.iter().map(|n| (n.fret_id, n.slide_to, n.anchor_fret_id)).collect()
+    }
+
+    #[test]
+    fn shifting_moves_every_fret() {
+        let mut sng = chart();
+        shift_frets(&mut sng, 2).unwrap();
+        assert_eq!(frets(&sng), [(2, UNUSED, 3), (5, 7, 5)]);
+        assert_eq!(sng.chords[0].frets, [2, 4, 4, 2, 2, 2]);
+        assert_eq!(sng.chords[0].notes, [42, 49, 54, 57, 61, 66]);
+        assert_eq!(sng.metadata.capo_fret_id, UNUSED);
+    }
+
+    #[test]
+    fn notes_pushed_off_the_neck_leave_the_chart_untouched() {
+        let mut sng = chart();
+        let err = shift_frets(&mut sng, -1).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("string 0 at 0.500s"), "{}", err);
+        assert_eq!(frets(&sng), [(0, UNUSED, 1), (3, 5, 3)]);
+
+        let err = shift_frets(&mut sng, MAX_FRET - 4).unwrap_err();
+        assert!(err.to_string().contains("string 1"), "{}", err);
+        assert_eq!(sng.chords[0].frets, [0, 2, 2, 0, 0, 0]);
+    }
+
+    #[test]
+    fn a_capo_moves_with_the_chart() {
+        let mut sng = chart();
+        sng.metadata.capo_fret_id = 2;
+        // Open strings sit at the capo, so they stay open when the capo moves.
+        shift_frets(&mut sng, -2).unwrap();
+        assert_eq!(sng.metadata.capo_fret_id, 0);
+        assert_eq!(frets(&sng), [(0, UNUSED, 1), (1, 3, 1)]);
+    }
+
+    #[test]
+    fn capo_and_tuning_changes_keep_the_pitch() {
+        let mut sng = chart();
+        assert!(set_capo(&mut sng, 1).is_err());
+        assert_eq!(sng.metadata.capo_fret_id, UNUSED);
+        assert_eq!(set_capo(&mut sng, MAX_FRET as u8 + 1).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+        // Tuning the A string up a tone moves its notes two frets down.
+        let mut tuning = vec![0; 6];
+        tuning[1] = 2;
+        retune(&mut sng, &tuning).unwrap();
+        assert_eq!(frets(&sng), [(0, UNUSED, 1), (1, 3, 3)]);
+        assert_eq!(sng.chords[0].frets, [0, 0, 2, 0, 0, 0]);
+        assert_eq!(sng.metadata.tuning, tuning);
+        assert_eq!(retune(&mut sng, &[0; 4]).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+}
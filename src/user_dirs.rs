@@ -0,0 +1,130 @@
+//! Per-user folders for what outlives a run: the conversion cache, configuration files
+//! (folder layouts, entry name dictionaries) and data such as library indexes.
+//!
+//! The folders follow the conventions of each platform:
+//!
+//! | | cache | config | data |
+//! |---|---|---|---|
+//! | Linux | `$XDG_CACHE_HOME` or `~/.cache` | `$XDG_CONFIG_HOME` or `~/.config` | `$XDG_DATA_HOME` or `~/.local/share` |
+//! | macOS | `~/Library/Caches` | `~/Library/Application Support` | `~/Library/Application Support` |
+//! | Windows | `%LOCALAPPDATA%` | `%APPDATA%` | `%APPDATA%` |
+//!
+//! each with a `psarc_unpacker` folder below (`cache`, `config` and `data` on Windows and
+//! macOS, where the bases are shared). `PSARC_UNPACKER_CACHE_DIR`,
+//! `PSARC_UNPACKER_CONFIG_DIR` and `PSARC_UNPACKER_DATA_DIR` replace a folder outright,
+//! and so do the setters of `UserDirs`.
+
+use std::env;
+use std::path::{Path, PathBuf};
+
+use crate::cache::ConversionCache;
+use crate::md5::{md5, to_hex};
+
+/// Name of the folder of this tool in the platform folders.
+const APP_DIR_NAME: &str = "psarc_unpacker";
+
+/// Environment variables replacing the cache, config and data folders.
+pub const CACHE_DIR_ENV: &str = "PSARC_UNPACKER_CACHE_DIR";
+pub const CONFIG_DIR_ENV: &str = "PSARC_UNPACKER_CONFIG_DIR";
+pub const DATA_DIR_ENV: &str = "PSARC_UNPACKER_DATA_DIR";
+
+/// Layout overrides read when no other file is given (see `layout`).
+pub const LAYOUTS_FILE_NAME: &str = "layouts.json";
+/// Entry name dictionary read when no other file is given (see `names`).
+pub const NAMES_FILE_NAME: &str = "names.txt";
+
+/// The cache, config and data folders of the current user. A folder is `None` when the
+/// environment names no home to put it in.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UserDirs {
+    cache: Option<PathBuf>,
+    config: Option<PathBuf>,
+    data: Option<PathBuf>,
+}
+
+/// A non-empty environment variable as a path.
+fn env_path(name: &str) -> Option<PathBuf> {
+    env::var_os(name).filter(|value| !value.is_empty()).map(PathBuf::from)
+}
+
+impl UserDirs {
+    /// The folders of the platform, with the environment overrides applied.
+    pub fn platform() -> Self {
+        let home = env_path("HOME");
+        let (cache, config, data) = if cfg!(windows) {
+            let local = env_path("LOCALAPPDATA").map(|dir| dir.join(APP_DIR_NAME));
+            let roaming = env_path("APPDATA").map(|dir| dir.join(APP_DIR_NAME));
+            (local.map(|dir| dir.join("cache")), roaming.as_ref().map(|dir| dir.join("config")), roaming.map(|dir| dir.join("data")))
+        } else if cfg!(target_os = "macos") {
+            let library = home.map(|home| home.join("Library"));
+            let support = library.as_ref().map(|dir| dir.join("Application Support").join(APP_DIR_NAME));
+            (
+                library.map(|dir| dir.join("Caches").join(APP_DIR_NAME)),
+                support.as_ref().map(|dir| dir.join("config")),
+                support.map(|dir| dir.join("data")),
+            )
+        } else {
+            let xdg = |variable: &str, fallback: &str| {
+                env_path(variable).or_else(|| home.as_ref().map(|home| home.join(fallback))).map(|dir| dir.join(APP_DIR_NAME))
+            };
+            (xdg("XDG_CACHE_HOME", ".cache"), xdg("XDG_CONFIG_HOME", ".config"), xdg("XDG_DATA_HOME", ".local/share"))
+        };
+        UserDirs {
+            cache: env_path(CACHE_DIR_ENV).or(cache),
+            config: env_path(CONFIG_DIR_ENV).or(config),
+            data: env_path(DATA_DIR_ENV).or(data),
+        }
+    }
+
+    pub fn cache(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.cache = Some(dir.into());
+        self
+    }
+
+    pub fn config(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.config = Some(dir.into());
+        self
+    }
+
+    pub fn data(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.data = Some(dir.into());
+        self
+    }
+
+    pub fn cache_dir(&self) -> Option<&Path> {
+        self.cache.as_deref()
+    }
+
+    pub fn config_dir(&self) -> Option<&Path> {
+        self.config.as_deref()
+    }
+
+    pub fn data_dir(&self) -> Option<&Path> {
+        self.data.as_deref()
+    }
+
+    /// The conversion cache, in the cache folder.
+    pub fn conversion_cache(&self) -> Option<ConversionCache> {
+        self.cache.as_ref().map(ConversionCache::new)
+    }
+
+    /// The layout overrides file of the config folder, when there is one.
+    pub fn layouts_file(&self) -> Option<PathBuf> {
+        self.config.as_ref().map(|dir| dir.join(LAYOUTS_FILE_NAME)).filter(|path| path.is_file())
+    }
+
+    /// The entry name dictionary of the config folder, when there is one.
+    pub fn names_file(&self) -> Option<PathBuf> {
+        self.config.as_ref().map(|dir| dir.join(NAMES_FILE_NAME)).filter(|path| path.is_file())
+    }
+
+    /// Index file of the library below `folder`, in the data folder:
+    /// `indexes/<name>_<hash>.json`, the hash telling apart folders of the same name.
+    pub fn library_index(&self, folder: &Path) -> Option<PathBuf> {
+        let folder = folder.canonicalize().unwrap_or_else(|_| folder.to_path_buf());
+        let name = folder.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        let hash = to_hex(&md5(folder.to_string_lossy().as_bytes()));
+        let file_name = format!("{}_{}.json", name, &hash[..12]).to_lowercase();
+        self.data.as_ref().map(|dir| dir.join("indexes").join(file_name))
+    }
+}
@@ -0,0 +1,899 @@
+//! Rebuilding a standard Ogg Vorbis file from a Wwise Vorbis wem, on byte buffers.
+//!
+//! Wwise strips Vorbis streams down to what its own decoder needs:
+//! - There are no identification or comment headers. Their fields live in the `fmt ` and
+//!   `vorb` chunks instead.
+//! - The setup header drops fields Vorbis fixes anyway (floor and mapping types, window
+//!   and transform types).
+//! - Codebooks are usually referred to by id into a shared library (see `codebook`)
+//!   rather than stored in the stream.
+//! - Audio packets carry a 2 or 6 byte size/granule header instead of Ogg paging. With
+//!   "modified" packets they also lose the window shape bits of long blocks.
+//!
+//! `wem_to_ogg` puts all of this back, the way ww2ogg does, and writes one packet per
+//! Ogg page. Granule positions are computed from the block sizes of the packets, as
+//! revorb would, and the last page is trimmed to the sample count of the wem. The
+//! player therefore gets the exact length and working seeking without a second pass.
+//!
+//! Streams from the oldest Wwise versions, which keep a full header triad (`vorb` chunks
+//! of 0x28 or 0x2C bytes), are rejected with `ErrorKind::Unsupported`.
+//!
+//! Whether the audio packets are modified is read from the `vorb` data, which some
+//! encoders get wrong; `wem_to_ogg_with` takes the packet format to use instead, like the
+//! `--mod-packets` and `--no-mod-packets` switches of ww2ogg.
+
+use std::io;
+use serde::Serialize;
+
+use crate::codebook::CodebookLibrary;
+use crate::wem::{read_riff_chunks, read_u16_at, read_u32_at, WemInfo, WEM_CODEC_VORBIS};
+
+/// Vendor string of the rebuilt comment header.
+const VENDOR: &str = "converted from Audiokinetic Wwise by psarc_unpacker";
+
+/// `vorb` signal values of streams whose packets keep their standard first byte.
+const UNMODIFIED_PACKET_SIGNALS: [u32; 4] = [0x4A, 0x4B, 0x69, 0x70];
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// Layout of the audio packets of a Wwise Vorbis stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PacketFormat {
+    /// Packets without their packet type and long block window shape bits.
+    Modified,
+    /// Packets starting like standard Vorbis audio packets.
+    Standard,
+}
+
+impl PacketFormat {
+    /// The other format.
+    pub fn alternate(self) -> Self {
+        match self {
+            PacketFormat::Modified => PacketFormat::Standard,
+            PacketFormat::Standard => PacketFormat::Modified,
+        }
+    }
+}
+
+impl std::str::FromStr for PacketFormat {
+    type Err = String;
+
+    /// `modified` or `standard`.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "modified" => Ok(PacketFormat::Modified),
+            "standard" => Ok(PacketFormat::Standard),
+            other => Err(format!("Unknown packet format {:?}, expected `modified` or `standard`", other)),
+        }
+    }
+}
+
+/// Number of bits needed to hold `value`.
+fn ilog(value: u32) -> u32 {
+    32 - value.leading_zeros()
+}
+
+/// Reads bits least significant first, the Vorbis bit order.
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        BitReader { data, position: 0 }
+    }
+
+    fn read(&mut self, bits: u32) -> io::Result<u32> {
+        let mut value = 0;
+        for bit in 0..bits {
+            let byte = self.data.get(self.position / 8).ok_or_else(|| invalid("Vorbis packet ended early"))?;
+            if (byte >> (self.position % 8)) & 1 != 0 {
+                value |= 1 << bit;
+            }
+            self.position += 1;
+        }
+        Ok(value)
+    }
+
+    fn bits_read(&self) -> usize {
+        self.position
+    }
+}
+
+/// Writes bits least significant first.
+#[derive(Default)]
+struct BitWriter {
+    data: Vec<u8>,
+    bits: usize,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, bits: u32) {
+        for bit in 0..bits {
+            if self.bits.is_multiple_of(8) {
+                self.data.push(0);
+            }
+            if (value >> bit) & 1 != 0 {
+                *self.data.last_mut().unwrap() |= 1 << (self.bits % 8);
+            }
+            self.bits += 1;
+        }
+    }
+
+    /// Copies `reader`'s next `bits` bits.
+    fn copy(&mut self, reader: &mut BitReader, bits: u32) -> io::Result<u32> {
+        let value = reader.read(bits)?;
+        self.write(value, bits);
+        Ok(value)
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        if self.bits.is_multiple_of(8) {
+            self.data.extend_from_slice(bytes);
+            self.bits += bytes.len() * 8;
+        } else {
+            bytes.iter().for_each(|&byte| self.write(byte as u32, 8));
+        }
+    }
+
+    /// Packet type byte and `vorbis` signature of a header packet.
+    fn write_header_start(&mut self, packet_type: u8) {
+        self.write(packet_type as u32, 8);
+        self.write_bytes(b"vorbis");
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+/// Number of values of a lookup type 1 codebook: the largest integer whose
+/// `dimensions`th power does not exceed `entries`.
+fn lookup1_values(entries: u32, dimensions: u32) -> io::Result<u32> {
+    if entries == 0 || dimensions == 0 {
+        return Err(invalid("Vorbis codebook with a lookup table but no entries"));
+    }
+    let power = |value: u64| (0..dimensions).fold(1u64, |acc, _| acc.saturating_mul(value));
+    let mut values = (entries as f64).powf(1.0 / dimensions as f64).floor() as u64;
+    while power(values) > entries as u64 {
+        values -= 1;
+    }
+    while power(values + 1) <= entries as u64 {
+        values += 1;
+    }
+    Ok(values as u32)
+}
+
+/// Rebuilds one codebook from the Wwise packed form to the Vorbis one.
+fn rebuild_codebook(input: &mut BitReader, output: &mut BitWriter) -> io::Result<()> {
+    let dimensions = input.read(4)?;
+    let entries = input.read(14)?;
+    output.write(0x564342, 24);
+    output.write(dimensions, 16);
+    output.write(entries, 24);
+
+    let ordered = output.copy(input, 1)?;
+    if ordered != 0 {
+        output.copy(input, 5)?;
+        let mut current = 0;
+        while current < entries {
+            current += output.copy(input, ilog(entries - current))?;
+        }
+        if current > entries {
+            return Err(invalid("Vorbis codebook has more ordered lengths than entries"));
+        }
+    } else {
+        let length_bits = input.read(3)?;
+        if length_bits == 0 || length_bits > 5 {
+            return Err(invalid(format!("Invalid Vorbis codeword length size {}", length_bits)));
+        }
+        let sparse = output.copy(input, 1)?;
+        for _ in 0..entries {
+            let present = if sparse != 0 { output.copy(input, 1)? != 0 } else { true };
+            if present {
+                output.write(input.read(length_bits)?, 5);
+            }
+        }
+    }
+
+    let lookup_type = input.read(1)?;
+    output.write(lookup_type, 4);
+    if lookup_type == 1 {
+        output.copy(input, 32)?;
+        output.copy(input, 32)?;
+        let value_bits = output.copy(input, 4)? + 1;
+        output.copy(input, 1)?;
+        for _ in 0..lookup1_values(entries, dimensions)? {
+            output.copy(input, value_bits)?;
+        }
+    }
+    Ok(())
+}
+
+/// Rebuilds codebook `id` of `library`, which must be used up exactly.
+fn rebuild_library_codebook(library: &CodebookLibrary, id: u32, output: &mut BitWriter) -> io::Result<()> {
+    let codebook = library.codebook(id as usize)?;
+    let mut input = BitReader::new(codebook);
+    rebuild_codebook(&mut input, output)?;
+    // A packed codebook using every bit of its last byte still gets a trailing zero byte.
+    if input.bits_read() / 8 + 1 != codebook.len() {
+        return Err(invalid(format!("Codebook {} does not match its size in the library", id)));
+    }
+    Ok(())
+}
+
+/// Vorbis fields of a wem, from its `fmt ` and `vorb` chunks.
+struct VorbisStream {
+    big_endian: bool,
+    channels: u32,
+    sample_rate: u32,
+    avg_bytes_per_second: u32,
+    sample_count: u32,
+    data_offset: usize,
+    data_end: usize,
+    setup_packet_offset: usize,
+    first_audio_packet_offset: usize,
+    /// Audio packets lost their packet type and window shape bits.
+    mod_packets: bool,
+    /// Packet headers are 2 bytes (size only) instead of 6 (size and granule).
+    no_granule: bool,
+    blocksize_0_pow: u32,
+    blocksize_1_pow: u32,
+}
+
+impl VorbisStream {
+    fn parse(data: &[u8]) -> io::Result<Self> {
+        let (big_endian, chunks) = read_riff_chunks(data)?;
+        let chunk = |id: &[u8; 4]| chunks.iter().find(|c| &c.id == id);
+        let fmt = chunk(b"fmt ").ok_or_else(|| invalid("wem has no fmt chunk"))?;
+        let payload = chunk(b"data").ok_or_else(|| invalid("wem has no data chunk"))?;
+        if fmt.size < 16 || read_u16_at(data, fmt.offset, big_endian)? != WEM_CODEC_VORBIS {
+            return Err(invalid("wem is not Wwise Vorbis"));
+        }
+        // The vorb data is a chunk of its own, or follows the fields of a 0x42 byte fmt
+        // chunk, laid out like a 0x2A byte vorb chunk.
+        let (vorb_offset, vorb_size) = match chunk(b"vorb") {
+            Some(vorb) => (vorb.offset, vorb.size),
+            None if fmt.size == 0x42 => (fmt.offset + 0x18, 0x2A),
+            None => return Err(invalid("Vorbis wem has no vorb data")),
+        };
+        let read_u32 = |offset: usize| read_u32_at(data, vorb_offset + offset, big_endian);
+        let byte = |offset: usize| {
+            data.get(vorb_offset + offset).map(|&b| b as u32).ok_or_else(|| invalid("wem vorb data truncated"))
+        };
+        let (mod_packets, no_granule, offsets_at, blocksizes_at) = match vorb_size {
+            0x2A => (!UNMODIFIED_PACKET_SIGNALS.contains(&read_u32(0x04)?), true, 0x10, 0x28),
+            0x32 | 0x34 => (false, false, 0x18, 0x30),
+            0x28 | 0x2C => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "Vorbis wems with a full header triad (old Wwise) are not supported",
+                ))
+            }
+            other => return Err(invalid(format!("Unknown vorb data size 0x{:X}", other))),
+        };
+        Ok(VorbisStream {
+            big_endian,
+            channels: read_u16_at(data, fmt.offset + 2, big_endian)? as u32,
+            sample_rate: read_u32_at(data, fmt.offset + 4, big_endian)?,
+            avg_bytes_per_second: read_u32_at(data, fmt.offset + 8, big_endian)?,
+            sample_count: read_u32(0)?,
+            data_offset: payload.offset,
+            data_end: payload.offset + payload.size,
+            setup_packet_offset: read_u32(offsets_at)? as usize,
+            first_audio_packet_offset: read_u32(offsets_at + 4)? as usize,
+            mod_packets,
+            no_granule,
+            blocksize_0_pow: byte(blocksizes_at)?,
+            blocksize_1_pow: byte(blocksizes_at + 1)?,
+        })
+    }
+
+    fn header_size(&self) -> usize {
+        if self.no_granule {
+            2
+        } else {
+            6
+        }
+    }
+
+    /// The Wwise packet at `offset` (absolute).
+    fn packet(&self, data: &[u8], offset: usize) -> io::Result<Packet> {
+        let size = read_u16_at(data, offset, self.big_endian)? as usize;
+        let granule = if self.no_granule { 0 } else { read_u32_at(data, offset + 2, self.big_endian)? };
+        let start = offset + self.header_size();
+        let end = start + size;
+        if end > self.data_end {
+            return Err(invalid(format!("Vorbis packet at {} runs past the data chunk", offset)));
+        }
+        Ok(Packet { start, end, granule })
+    }
+}
+
+/// A packet of the data chunk: its payload range and Wwise granule.
+struct Packet {
+    start: usize,
+    end: usize,
+    granule: u32,
+}
+
+/// The rebuilt setup header, and the block flag of each mode for the audio packets.
+fn rebuild_setup(
+    stream: &VorbisStream,
+    setup: &[u8],
+    codebooks: Option<&CodebookLibrary>,
+) -> io::Result<(Vec<u8>, Vec<bool>)> {
+    let mut input = BitReader::new(setup);
+    let mut output = BitWriter::default();
+    output.write_header_start(5);
+
+    let codebook_count = output.copy(&mut input, 8)? + 1;
+    for _ in 0..codebook_count {
+        match codebooks {
+            Some(library) => rebuild_library_codebook(library, input.read(10)?, &mut output)?,
+            None => rebuild_codebook(&mut input, &mut output)?,
+        }
+    }
+    // Time domain transforms: one placeholder.
+    output.write(0, 6);
+    output.write(0, 16);
+
+    let floor_count = output.copy(&mut input, 6)? + 1;
+    for _ in 0..floor_count {
+        // Floor type 1, the only one Wwise uses.
+        output.write(1, 16);
+        let partitions = output.copy(&mut input, 5)?;
+        let mut partition_classes = Vec::with_capacity(partitions as usize);
+        for _ in 0..partitions {
+            partition_classes.push(output.copy(&mut input, 4)?);
+        }
+        let class_count = partition_classes.iter().max().map_or(0, |max| max + 1);
+        let mut class_dimensions = Vec::with_capacity(class_count as usize);
+        for _ in 0..class_count {
+            class_dimensions.push(output.copy(&mut input, 3)? + 1);
+            let subclasses = output.copy(&mut input, 2)?;
+            if subclasses != 0 && output.copy(&mut input, 8)? >= codebook_count {
+                return Err(invalid("Invalid floor masterbook"));
+            }
+            for _ in 0..1u32 << subclasses {
+                let book = output.copy(&mut input, 8)?;
+                if book > codebook_count {
+                    return Err(invalid("Invalid floor subclass book"));
+                }
+            }
+        }
+        output.copy(&mut input, 2)?;
+        let range_bits = output.copy(&mut input, 4)?;
+        for class in partition_classes {
+            for _ in 0..class_dimensions[class as usize] {
+                output.copy(&mut input, range_bits)?;
+            }
+        }
+    }
+
+    let residue_count = output.copy(&mut input, 6)? + 1;
+    for _ in 0..residue_count {
+        let residue_type = input.read(2)?;
+        if residue_type > 2 {
+            return Err(invalid(format!("Invalid residue type {}", residue_type)));
+        }
+        output.write(residue_type, 16);
+        output.copy(&mut input, 24)?;
+        output.copy(&mut input, 24)?;
+        output.copy(&mut input, 24)?;
+        let classifications = output.copy(&mut input, 6)? + 1;
+        if output.copy(&mut input, 8)? >= codebook_count {
+            return Err(invalid("Invalid residue classbook"));
+        }
+        let mut cascades = Vec::with_capacity(classifications as usize);
+        for _ in 0..classifications {
+            let low_bits = output.copy(&mut input, 3)?;
+            let high_bits = if output.copy(&mut input, 1)? != 0 { output.copy(&mut input, 5)? } else { 0 };
+            cascades.push(high_bits * 8 + low_bits);
+        }
+        for cascade in cascades {
+            for bit in 0..8 {
+                if cascade & (1 << bit) != 0 && output.copy(&mut input, 8)? >= codebook_count {
+                    return Err(invalid("Invalid residue book"));
+                }
+            }
+        }
+    }
+
+    let mapping_count = output.copy(&mut input, 6)? + 1;
+    for _ in 0..mapping_count {
+        // Mapping type 0, the only one.
+        output.write(0, 16);
+        let submaps = if output.copy(&mut input, 1)? != 0 { output.copy(&mut input, 4)? + 1 } else { 1 };
+        if output.copy(&mut input, 1)? != 0 {
+            let coupling_steps = output.copy(&mut input, 8)? + 1;
+            let channel_bits = ilog(stream.channels.saturating_sub(1));
+            for _ in 0..coupling_steps {
+                let magnitude = output.copy(&mut input, channel_bits)?;
+                let angle = output.copy(&mut input, channel_bits)?;
+                if magnitude == angle || magnitude >= stream.channels || angle >= stream.channels {
+                    return Err(invalid("Invalid channel coupling"));
+                }
+            }
+        }
+        if output.copy(&mut input, 2)? != 0 {
+            return Err(invalid("Mapping reserved field is not zero"));
+        }
+        if submaps > 1 {
+            for _ in 0..stream.channels {
+                if output.copy(&mut input, 4)? >= submaps {
+                    return Err(invalid("Invalid mapping multiplex"));
+                }
+            }
+        }
+        for _ in 0..submaps {
+            output.copy(&mut input, 8)?;
+            if output.copy(&mut input, 8)? >= floor_count {
+                return Err(invalid("Invalid mapping floor"));
+            }
+            if output.copy(&mut input, 8)? >= residue_count {
+                return Err(invalid("Invalid mapping residue"));
+            }
+        }
+    }
+
+    let mode_count = output.copy(&mut input, 6)? + 1;
+    let mut block_flags = Vec::with_capacity(mode_count as usize);
+    for _ in 0..mode_count {
+        block_flags.push(output.copy(&mut input, 1)? != 0);
+        // Window and transform types, always 0.
+        output.write(0, 16);
+        output.write(0, 16);
+        if output.copy(&mut input, 8)? >= mapping_count {
+            return Err(invalid("Invalid mode mapping"));
+        }
+    }
+    // Framing bit.
+    output.write(1, 1);
+
+    if input.bits_read().div_ceil(8) != setup.len() {
+        return Err(invalid("Vorbis setup packet size does not match its content"));
+    }
+    Ok((output.into_bytes(), block_flags))
+}
+
+fn identification_header(stream: &VorbisStream) -> Vec<u8> {
+    let mut output = BitWriter::default();
+    output.write_header_start(1);
+    output.write(0, 32);
+    output.write(stream.channels, 8);
+    output.write(stream.sample_rate, 32);
+    output.write(0, 32);
+    output.write(stream.avg_bytes_per_second.wrapping_mul(8), 32);
+    output.write(0, 32);
+    output.write(stream.blocksize_0_pow, 4);
+    output.write(stream.blocksize_1_pow, 4);
+    output.write(1, 1);
+    output.into_bytes()
+}
+
+fn comment_header(comments: &[String]) -> Vec<u8> {
+    let mut output = BitWriter::default();
+    output.write_header_start(3);
+    output.write(VENDOR.len() as u32, 32);
+    output.write_bytes(VENDOR.as_bytes());
+    output.write(comments.len() as u32, 32);
+    for comment in comments {
+        output.write(comment.len() as u32, 32);
+        output.write_bytes(comment.as_bytes());
+    }
+    output.write(1, 1);
+    output.into_bytes()
+}
+
+const fn ogg_crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u32) << 24;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000_0000 != 0 { (crc << 1) ^ 0x04C1_1DB7 } else { crc << 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+static OGG_CRC_TABLE: [u32; 256] = ogg_crc_table();
+
+fn ogg_crc(data: &[u8]) -> u32 {
+    data.iter().fold(0, |crc, &byte| (crc << 8) ^ OGG_CRC_TABLE[((crc >> 24) as u8 ^ byte) as usize])
+}
+
+/// Writes packets into Ogg pages of a single logical stream.
+struct OggWriter {
+    output: Vec<u8>,
+    sequence: u32,
+}
+
+impl OggWriter {
+    const SERIAL: u32 = 1;
+
+    /// Writes `packet` on pages of its own, continued over several pages when it needs
+    /// more than 255 lacing values. `granule` goes on the page the packet ends on.
+    fn write_packet(&mut self, packet: &[u8], granule: u64, last: bool) {
+        let mut lacing = vec![255u8; packet.len() / 255];
+        lacing.push((packet.len() % 255) as u8);
+        let pages = lacing.len().div_ceil(255);
+        let mut body = packet;
+        for (index, segments) in lacing.chunks(255).enumerate() {
+            let final_page = index + 1 == pages;
+            let size: usize = segments.iter().map(|&s| s as usize).sum();
+            let mut flags = 0u8;
+            if index > 0 {
+                flags |= OGG_CONTINUED;
+            }
+            if self.sequence == 0 {
+                flags |= OGG_FIRST_PAGE;
+            }
+            if last && final_page {
+                flags |= OGG_LAST_PAGE;
+            }
+            let page = OggPage {
+                flags,
+                granule: if final_page { granule } else { u64::MAX },
+                serial: Self::SERIAL,
+                segments,
+                body: &body[..size],
+            };
+            page.write(&mut self.output, self.sequence);
+            body = &body[size..];
+            self.sequence += 1;
+        }
+    }
+}
+
+const OGG_CONTINUED: u8 = 1;
+const OGG_FIRST_PAGE: u8 = 2;
+const OGG_LAST_PAGE: u8 = 4;
+
+/// One page of an Ogg stream. `granule` is `u64::MAX` on pages where no packet ends.
+struct OggPage<'a> {
+    flags: u8,
+    granule: u64,
+    serial: u32,
+    segments: &'a [u8],
+    body: &'a [u8],
+}
+
+impl<'a> OggPage<'a> {
+    /// Splits `ogg` into its pages.
+    fn read_all(mut ogg: &'a [u8]) -> io::Result<Vec<OggPage<'a>>> {
+        let mut pages = Vec::new();
+        while !ogg.is_empty() {
+            if ogg.len() < 27 || &ogg[..4] != b"OggS" {
+                return Err(invalid("Not an Ogg page"));
+            }
+            let segment_count = ogg[26] as usize;
+            let segments = ogg.get(27..27 + segment_count).ok_or_else(|| invalid("Truncated Ogg page"))?;
+            let body_end = 27 + segment_count + segments.iter().map(|&s| s as usize).sum::<usize>();
+            let body = ogg.get(27 + segment_count..body_end).ok_or_else(|| invalid("Truncated Ogg page"))?;
+            pages.push(OggPage {
+                flags: ogg[5],
+                granule: u64::from_le_bytes(ogg[6..14].try_into().unwrap()),
+                serial: u32::from_le_bytes(ogg[14..18].try_into().unwrap()),
+                segments,
+                body,
+            });
+            ogg = &ogg[body_end..];
+        }
+        Ok(pages)
+    }
+
+    /// Packets ending on this page.
+    fn packets_ended(&self) -> usize {
+        self.segments.iter().filter(|&&segment| segment < 255).count()
+    }
+
+    fn write(&self, output: &mut Vec<u8>, sequence: u32) {
+        let page_start = output.len();
+        output.extend_from_slice(b"OggS");
+        output.push(0);
+        output.push(self.flags);
+        output.extend_from_slice(&self.granule.to_le_bytes());
+        output.extend_from_slice(&self.serial.to_le_bytes());
+        output.extend_from_slice(&sequence.to_le_bytes());
+        output.extend_from_slice(&[0; 4]);
+        output.push(self.segments.len() as u8);
+        output.extend_from_slice(self.segments);
+        output.extend_from_slice(self.body);
+        let crc = ogg_crc(&output[page_start..]);
+        output[page_start + 22..page_start + 26].copy_from_slice(&crc.to_le_bytes());
+    }
+}
+
+/// Cuts `duration` seconds starting at `start` out of an Ogg Vorbis file with one packet
+/// per page, as `wem_to_ogg` writes them, without decoding it. The cut is moved back when
+/// the stream ends before `start + duration`.
+///
+/// The three header pages are kept, followed by the audio packets covering the excerpt
+/// and the packet before them, which a decoder only uses to prime the overlap of the
+/// first. Granule positions are rebased on that primer packet and the last one is
+/// trimmed to the end of the excerpt, so the excerpt starts within a block (at most 2048
+/// samples) of `start` and ends on the exact sample.
+pub fn ogg_excerpt(ogg: &[u8], start: f64, duration: f64) -> io::Result<Vec<u8>> {
+    let pages = OggPage::read_all(ogg)?;
+    let mut packets = 0;
+    let header_pages = pages
+        .iter()
+        .position(|page| {
+            packets += page.packets_ended();
+            packets >= 3
+        })
+        .ok_or_else(|| invalid("Ogg stream ends in its Vorbis headers"))?
+        + 1;
+    let identification = pages[0].body;
+    if identification.len() < 16 || &identification[..7] != b"\x01vorbis" {
+        return Err(invalid("Ogg stream does not start with a Vorbis identification header"));
+    }
+    let rate = u32::from_le_bytes(identification[12..16].try_into().unwrap()) as f64;
+
+    // Each audio packet, as the range of its pages and the granule it ends on.
+    let mut audio = Vec::new();
+    let mut first_page = header_pages;
+    for (index, page) in pages.iter().enumerate().skip(header_pages) {
+        if page.granule != u64::MAX {
+            audio.push((first_page..index + 1, page.granule));
+            first_page = index + 1;
+        }
+    }
+    let total = audio.last().map_or(0, |(_, granule)| *granule);
+    let length = ((duration.max(0.0) * rate) as u64).min(total);
+    let first_sample = ((start.max(0.0) * rate) as u64).min(total - length);
+    let last_sample = first_sample + length;
+
+    let first = audio.iter().position(|(_, granule)| *granule > first_sample).unwrap_or(audio.len());
+    let primer = first.saturating_sub(1);
+    let base = audio.get(primer).map_or(0, |(_, granule)| *granule).min(first_sample);
+    let end = audio[first..]
+        .iter()
+        .position(|(_, granule)| *granule >= last_sample)
+        .map_or(audio.len(), |index| first + index + 1);
+
+    let mut output = Vec::new();
+    let mut sequence = 0;
+    for page in &pages[..header_pages] {
+        page.write(&mut output, sequence);
+        sequence += 1;
+    }
+    let kept = &audio[primer.min(end)..end];
+    for (index, (range, granule)) in kept.iter().cloned().enumerate() {
+        let last = index + 1 == kept.len();
+        for page in &pages[range] {
+            let ends_packet = page.granule != u64::MAX;
+            let mut flags = page.flags & !OGG_LAST_PAGE;
+            if last && ends_packet {
+                flags |= OGG_LAST_PAGE;
+            }
+            let granule = match ends_packet {
+                true if last => granule.min(last_sample) - base,
+                true => granule.saturating_sub(base),
+                false => u64::MAX,
+            };
+            OggPage { flags, granule, ..*page }.write(&mut output, sequence);
+            sequence += 1;
+        }
+    }
+    Ok(output)
+}
+
+/// The packet format the `vorb` data of a Wwise Vorbis wem declares.
+pub fn packet_format(data: &[u8]) -> io::Result<PacketFormat> {
+    let stream = VorbisStream::parse(data)?;
+    Ok(if stream.mod_packets { PacketFormat::Modified } else { PacketFormat::Standard })
+}
+
+/// Converts a Wwise Vorbis wem to an Ogg Vorbis file.
+///
+/// `codebooks` is the library the stream's codebook ids refer to (see
+/// `CodebookLibrary::load`); `None` reads the codebooks from the setup packet itself, for
+/// wems encoded with inline codebooks. The loops and cues of the wem become comments
+/// (see `WemInfo::vorbis_comments`).
+pub fn wem_to_ogg(data: &[u8], codebooks: Option<&CodebookLibrary>) -> io::Result<Vec<u8>> {
+    wem_to_ogg_with(data, codebooks, None)
+}
+
+/// `wem_to_ogg` reading the audio packets in `packet_format` rather than the format the
+/// `vorb` data declares, when given.
+pub fn wem_to_ogg_with(
+    data: &[u8],
+    codebooks: Option<&CodebookLibrary>,
+    packet_format: Option<PacketFormat>,
+) -> io::Result<Vec<u8>> {
+    let mut stream = VorbisStream::parse(data)?;
+    if let Some(format) = packet_format {
+        stream.mod_packets = format == PacketFormat::Modified;
+    }
+    let comments = WemInfo::parse(data)?.vorbis_comments();
+
+    let setup = stream.packet(data, stream.data_offset + stream.setup_packet_offset)?;
+    if setup.granule != 0 {
+        return Err(invalid("Vorbis setup packet has a granule"));
+    }
+    if setup.end != stream.data_offset + stream.first_audio_packet_offset {
+        return Err(invalid("First Vorbis audio packet does not follow the setup packet"));
+    }
+    let (setup_header, block_flags) = rebuild_setup(&stream, &data[setup.start..setup.end], codebooks)?;
+    let mode_bits = ilog(block_flags.len() as u32 - 1);
+    let block_flag = |mode: u32| {
+        block_flags.get(mode as usize).copied().ok_or_else(|| invalid(format!("Invalid Vorbis mode {}", mode)))
+    };
+
+    let mut ogg = OggWriter { output: Vec::with_capacity(data.len() + data.len() / 8), sequence: 0 };
+    ogg.write_packet(&identification_header(&stream), 0, false);
+    ogg.write_packet(&comment_header(&comments), 0, false);
+    ogg.write_packet(&setup_header, 0, false);
+
+    let mut offset = setup.end;
+    let mut previous_flag = false;
+    let mut previous_blocksize = 0u64;
+    let mut granule = 0u64;
+    while offset < stream.data_end {
+        let packet = stream.packet(data, offset)?;
+        let payload = &data[packet.start..packet.end];
+        let mut output = BitWriter::default();
+        let flag = match payload.first() {
+            None => None,
+            Some(_) if stream.mod_packets => {
+                // Put back the packet type bit and, for long blocks, the previous and next
+                // window shapes, which Wwise dropped.
+                let mut input = BitReader::new(payload);
+                output.write(0, 1);
+                let mode = output.copy(&mut input, mode_bits)?;
+                let flag = block_flag(mode)?;
+                if flag {
+                    let next_flag = match stream.packet(data, packet.end) {
+                        Ok(next) if next.end > next.start => {
+                            block_flag(BitReader::new(&data[next.start..next.end]).read(mode_bits)?)?
+                        }
+                        _ => false,
+                    };
+                    output.write(previous_flag as u32, 1);
+                    output.write(next_flag as u32, 1);
+                }
+                output.write(input.read(8 - mode_bits)?, 8 - mode_bits);
+                output.write_bytes(&payload[1..]);
+                Some(flag)
+            }
+            Some(&first) => {
+                output.write_bytes(payload);
+                Some(block_flag((first as u32 >> 1) & ((1 << mode_bits) - 1))?)
+            }
+        };
+        if let Some(flag) = flag {
+            let blocksize = 1u64 << if flag { stream.blocksize_1_pow } else { stream.blocksize_0_pow };
+            if previous_blocksize != 0 {
+                granule += (previous_blocksize + blocksize) / 4;
+            }
+            previous_blocksize = blocksize;
+            previous_flag = flag;
+        }
+        offset = packet.end;
+        let last = offset >= stream.data_end;
+        let page_granule = if last && stream.sample_count > 0 { granule.min(stream.sample_count as u64) } else { granule };
+        ogg.write_packet(&output.into_bytes(), page_granule, last);
+    }
+    Ok(ogg.output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 1000;
+    /// Samples each short block packet adds: (256 + 256) / 4.
+    const PACKET_SAMPLES: u64 = 128;
+
+    /// The smallest setup Wwise could write: one inline two-entry codebook, an empty
+    /// floor, one residue, one mapping, and a short and a long block mode.
+    fn setup_packet() -> Vec<u8> {
+        let mut setup = BitWriter::default();
+        for (value, bits) in [
+            (0, 8),
+            // Codebook: 1 dimension, 2 entries, unordered, 1 bit lengths, not sparse.
+            (1, 4), (2, 14), (0, 1), (1, 3), (0, 1), (0, 1), (0, 1), (0, 1),
+            // Floor: no partitions.
+            (0, 6), (0, 5), (0, 2), (0, 4),
+            // Residue type 0 with one classification.
+            (0, 6), (0, 2), (0, 24), (0, 24), (0, 24), (0, 6), (0, 8), (0, 3), (0, 1),
+            // Mapping: one submap, no coupling.
+            (0, 6), (0, 1), (0, 1), (0, 2), (0, 8), (0, 8), (0, 8),
+            // Modes: short and long blocks.
+            (1, 6), (0, 1), (0, 8), (1, 1), (0, 8),
+        ] {
+            setup.write(value, bits);
+        }
+        setup.into_bytes()
+    }
+
+    /// A mono Wwise Vorbis wem at `RATE` Hz with standard packets: the setup packet, then
+    /// `packets` short block audio packets.
+    fn vorbis_wem(packets: u8) -> Vec<u8> {
+        let mut fmt = [0u8; 0x18];
+        fmt[..2].copy_from_slice(&WEM_CODEC_VORBIS.to_le_bytes());
+        fmt[2..4].copy_from_slice(&1u16.to_le_bytes());
+        fmt[4..8].copy_from_slice(&RATE.to_le_bytes());
+        let setup = setup_packet();
+        let mut payload = (setup.len() as u16).to_le_bytes().to_vec();
+        payload.extend_from_slice(&setup);
+        let first_audio = payload.len();
+        for packet in 0..packets {
+            payload.extend_from_slice(&[3, 0, 0, packet, 0xA5]);
+        }
+        let mut vorb = [0u8; 0x2A];
+        vorb[..4].copy_from_slice(&((packets as u32 - 1) * PACKET_SAMPLES as u32).to_le_bytes());
+        vorb[0x04..0x08].copy_from_slice(&UNMODIFIED_PACKET_SIGNALS[0].to_le_bytes());
+        vorb[0x14..0x18].copy_from_slice(&(first_audio as u32).to_le_bytes());
+        vorb[0x28..0x2A].copy_from_slice(&[8, 11]);
+        let mut wem = b"RIFF\0\0\0\0WAVE".to_vec();
+        for (id, chunk) in [(b"fmt ", &fmt[..]), (b"vorb", &vorb[..]), (b"data", &payload[..])] {
+            wem.extend_from_slice(id);
+            wem.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+            wem.extend_from_slice(chunk);
+        }
+        wem
+    }
+
+    /// (granule, flags, body) of every page, after checking the CRC and sequence numbers.
+    fn pages(ogg: &[u8]) -> Vec<(u64, u8, Vec<u8>)> {
+        let pages = OggPage::read_all(ogg).unwrap();
+        let mut rewritten = Vec::new();
+        for (sequence, page) in pages.iter().enumerate() {
+            page.write(&mut rewritten, sequence as u32);
+        }
+        assert_eq!(rewritten, ogg, "CRC or sequence numbers differ");
+        pages.iter().map(|page| (page.granule, page.flags, page.body.to_vec())).collect()
+    }
+
+    #[test]
+    fn rebuilds_a_vorbis_wem() {
+        let pages = pages(&wem_to_ogg(&vorbis_wem(40), None).unwrap());
+        assert_eq!(pages.len(), 43);
+        assert!(pages[0].2.starts_with(b"\x01vorbis"));
+        assert_eq!(pages[0].1, OGG_FIRST_PAGE);
+        assert_eq!(pages[3].0, 0);
+        assert_eq!(pages[42], (39 * PACKET_SAMPLES, OGG_LAST_PAGE, vec![0, 39, 0xA5]));
+    }
+
+    #[test]
+    fn excerpt_is_cut_at_packet_boundaries() {
+        let ogg = wem_to_ogg(&vorbis_wem(40), None).unwrap();
+        // Samples 1000 to 2000: packets ending at 1024 to 2048, after the primer ending
+        // at 896.
+        let excerpt = pages(&ogg_excerpt(&ogg, 1.0, 1.0).unwrap());
+        let headers = pages(&ogg)[..3].to_vec();
+        assert_eq!(excerpt[..3], headers);
+        let audio = &excerpt[3..];
+        assert_eq!(audio.len(), 10);
+        assert_eq!(audio[0], (0, 0, vec![0, 7, 0xA5]));
+        assert_eq!(audio[1].0, 1024 - 896);
+        assert_eq!(audio[9], (2000 - 896, OGG_LAST_PAGE, vec![0, 16, 0xA5]));
+    }
+
+    #[test]
+    fn excerpt_moves_back_from_the_end() {
+        let ogg = wem_to_ogg(&vorbis_wem(40), None).unwrap();
+        let total = 39 * PACKET_SAMPLES;
+        let excerpt = pages(&ogg_excerpt(&ogg, 4.5, 2.0).unwrap());
+        let last = excerpt.last().unwrap();
+        assert_eq!(last.1, OGG_LAST_PAGE);
+        assert_eq!(last.2, vec![0, 39, 0xA5]);
+        let primer = (total - 2000) / PACKET_SAMPLES * PACKET_SAMPLES;
+        assert_eq!(last.0, total - primer);
+
+        let whole = pages(&ogg_excerpt(&ogg, 0.0, 60.0).unwrap());
+        assert_eq!(whole, pages(&ogg));
+    }
+}
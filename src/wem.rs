@@ -0,0 +1,514 @@
+use std::io::{self, Read, Seek};
+use serde::Serialize;
+
+use crate::psarc::PsarcAsset;
+
+/// Wwise codec ids found in the `fmt ` chunk of a wem.
+pub const WEM_CODEC_PCM: u16 = 0x0001;
+pub const WEM_CODEC_ADPCM: u16 = 0x0002;
+pub const WEM_CODEC_VORBIS: u16 = 0xFFFF;
+
+/// Codec family of a wem, deciding which conversion path applies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum WemCodec {
+    /// Raw PCM, written to WAV as is.
+    Pcm,
+    /// Wwise IMA ADPCM, decoded to 16-bit PCM.
+    Adpcm,
+    /// Wwise Vorbis, which needs the full Ogg reconstruction.
+    Vorbis,
+    /// Any other codec id.
+    Other(u16),
+}
+
+impl From<u16> for WemCodec {
+    fn from(codec_id: u16) -> Self {
+        match codec_id {
+            WEM_CODEC_PCM => WemCodec::Pcm,
+            WEM_CODEC_ADPCM => WemCodec::Adpcm,
+            WEM_CODEC_VORBIS => WemCodec::Vorbis,
+            other => WemCodec::Other(other),
+        }
+    }
+}
+
+/// Metadata of a Wwise wem stream, read from its RIFF/RIFX chunks without decoding audio.
+#[derive(Default, Debug, Clone, Serialize)]
+pub struct WemInfo {
+    pub big_endian: bool,
+    pub codec_id: u16,
+    pub codec_name: String,
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub avg_bytes_per_second: u32,
+    pub block_align: u16,
+    pub bits_per_sample: u16,
+    /// Total sample count per channel, when the stream declares it (Vorbis).
+    pub sample_count: Option<u32>,
+    pub data_size: u32,
+    /// Duration in seconds; exact when the sample count is known, estimated from the
+    /// byte rate otherwise.
+    pub duration: f64,
+    /// Loop regions from the `smpl` chunk, in samples.
+    pub loops: Vec<WemLoop>,
+    /// Cue points from the `cue ` chunk, in samples.
+    pub cues: Vec<WemCue>,
+}
+
+/// A loop region declared in the `smpl` chunk of a wem.
+#[derive(Debug, Clone, Serialize)]
+pub struct WemLoop {
+    pub cue_point_id: u32,
+    pub loop_type: u32,
+    pub start: u32,
+    pub end: u32,
+    /// Number of repetitions, 0 meaning infinite.
+    pub play_count: u32,
+}
+
+/// A cue point declared in the `cue ` chunk of a wem.
+#[derive(Debug, Clone, Serialize)]
+pub struct WemCue {
+    pub id: u32,
+    pub position: u32,
+    pub sample_offset: u32,
+}
+
+/// Audio metadata of one wem entry of an archive.
+#[derive(Debug, Clone, Serialize)]
+pub struct WemEntryInfo {
+    pub path: String,
+    pub info: WemInfo,
+}
+
+/// A RIFF chunk located inside a wem: its four character id and the byte range of its payload.
+#[derive(Debug, Clone)]
+pub struct RiffChunk {
+    pub id: [u8; 4],
+    pub offset: usize,
+    pub size: usize,
+}
+
+/// Reads a u16 honoring the RIFF (little-endian) or RIFX (big-endian) byte order.
+pub(crate) fn read_u16_at(data: &[u8], offset: usize, big_endian: bool) -> io::Result<u16> {
+    let bytes: [u8; 2] = data
+        .get(offset..offset + 2)
+        .and_then(|b| b.try_into().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "wem chunk truncated"))?;
+    Ok(if big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) })
+}
+
+/// Reads a u32 honoring the RIFF (little-endian) or RIFX (big-endian) byte order.
+pub(crate) fn read_u32_at(data: &[u8], offset: usize, big_endian: bool) -> io::Result<u32> {
+    let bytes: [u8; 4] = data
+        .get(offset..offset + 4)
+        .and_then(|b| b.try_into().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "wem chunk truncated"))?;
+    Ok(if big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+}
+
+/// Splits a RIFF/RIFX file into its top-level chunks.
+///
+/// Returns the byte order flag and the chunk list. Chunks that run past the end of the
+/// data are clamped rather than rejected, since some wems declare padded sizes.
+pub fn read_riff_chunks(data: &[u8]) -> io::Result<(bool, Vec<RiffChunk>)> {
+    let big_endian = match data.get(..4) {
+        Some(b"RIFF") => false,
+        Some(b"RIFX") => true,
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a valid wem file")),
+    };
+    if data.get(8..12) != Some(b"WAVE") {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Missing WAVE form type"));
+    }
+
+    let mut chunks = Vec::new();
+    let mut pos = 12;
+    while pos + 8 <= data.len() {
+        let mut id = [0u8; 4];
+        id.copy_from_slice(&data[pos..pos + 4]);
+        let size = read_u32_at(data, pos + 4, big_endian)? as usize;
+        let offset = pos + 8;
+        let size = size.min(data.len() - offset);
+        chunks.push(RiffChunk { id, offset, size });
+        // Chunks are word aligned.
+        pos = offset + size + (size & 1);
+    }
+    Ok((big_endian, chunks))
+}
+
+/// Returns a human readable name for a Wwise codec id.
+pub fn codec_name(codec_id: u16) -> &'static str {
+    match codec_id {
+        WEM_CODEC_PCM => "PCM",
+        WEM_CODEC_ADPCM => "Wwise IMA ADPCM",
+        0x0166 => "XMA2",
+        0x3039 => "Opus (WEM)",
+        WEM_CODEC_VORBIS => "Vorbis",
+        _ => "Unknown",
+    }
+}
+
+impl WemInfo {
+    /// Parses the `fmt ` (and, for Vorbis, `vorb`) chunk of a wem.
+    pub fn parse(data: &[u8]) -> io::Result<Self> {
+        let (big_endian, chunks) = read_riff_chunks(data)?;
+        let fmt = chunks
+            .iter()
+            .find(|c| &c.id == b"fmt ")
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "wem has no fmt chunk"))?;
+        if fmt.size < 16 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "wem fmt chunk too small"));
+        }
+
+        let codec_id = read_u16_at(data, fmt.offset, big_endian)?;
+        let channels = read_u16_at(data, fmt.offset + 2, big_endian)?;
+        let sample_rate = read_u32_at(data, fmt.offset + 4, big_endian)?;
+        let avg_bytes_per_second = read_u32_at(data, fmt.offset + 8, big_endian)?;
+        let block_align = read_u16_at(data, fmt.offset + 12, big_endian)?;
+        let bits_per_sample = read_u16_at(data, fmt.offset + 14, big_endian)?;
+        let data_size = chunks
+            .iter()
+            .find(|c| &c.id == b"data")
+            .map(|c| c.size as u32)
+            .unwrap_or(0);
+
+        // Vorbis wems store the sample count at the start of the vorb data, which is either
+        // its own chunk or embedded at offset 0x18 of an extended (0x42 byte) fmt chunk.
+        let sample_count = if codec_id == WEM_CODEC_VORBIS {
+            if let Some(vorb) = chunks.iter().find(|c| &c.id == b"vorb") {
+                Some(read_u32_at(data, vorb.offset, big_endian)?)
+            } else if fmt.size == 0x42 {
+                Some(read_u32_at(data, fmt.offset + 0x18, big_endian)?)
+            } else {
+                None
+            }
+        } else if codec_id == WEM_CODEC_PCM && block_align > 0 {
+            Some(data_size / block_align as u32)
+        } else {
+            None
+        };
+
+        let loops = match chunks.iter().find(|c| &c.id == b"smpl") {
+            Some(smpl) => read_loops(data, smpl, big_endian)?,
+            None => Vec::new(),
+        };
+        let cues = match chunks.iter().find(|c| &c.id == b"cue ") {
+            Some(cue) => read_cues(data, cue, big_endian)?,
+            None => Vec::new(),
+        };
+
+        let duration = match sample_count {
+            Some(samples) if sample_rate > 0 => samples as f64 / sample_rate as f64,
+            _ if avg_bytes_per_second > 0 => data_size as f64 / avg_bytes_per_second as f64,
+            _ => 0.0,
+        };
+
+        Ok(WemInfo {
+            big_endian,
+            codec_id,
+            codec_name: codec_name(codec_id).to_string(),
+            channels,
+            sample_rate,
+            avg_bytes_per_second,
+            block_align,
+            bits_per_sample,
+            sample_count,
+            data_size,
+            duration,
+            loops,
+            cues,
+        })
+    }
+
+    /// Returns the codec family of this stream.
+    pub fn codec(&self) -> WemCodec {
+        WemCodec::from(self.codec_id)
+    }
+
+    /// Builds Vorbis comment fields describing the loops and cues of this stream.
+    ///
+    /// The first loop is exposed as `LOOPSTART`/`LOOPLENGTH` (in samples), the form most
+    /// players understand, and every cue point becomes a `CHAPTERnnn` entry so previews can
+    /// be looped seamlessly once the audio is re-encoded to Ogg.
+    pub fn vorbis_comments(&self) -> Vec<String> {
+        let mut comments = Vec::new();
+        if let Some(first) = self.loops.first() {
+            comments.push(format!("LOOPSTART={}", first.start));
+            comments.push(format!("LOOPLENGTH={}", first.end.saturating_sub(first.start) + 1));
+        }
+        for (i, cue) in self.cues.iter().enumerate() {
+            let seconds = if self.sample_rate > 0 {
+                cue.sample_offset as f64 / self.sample_rate as f64
+            } else {
+                0.0
+            };
+            let millis = (seconds * 1000.0).round() as u64;
+            comments.push(format!(
+                "CHAPTER{:03}={:02}:{:02}:{:02}.{:03}",
+                i + 1,
+                millis / 3_600_000,
+                (millis / 60_000) % 60,
+                (millis / 1000) % 60,
+                millis % 1000
+            ));
+            comments.push(format!("CHAPTER{:03}NAME=Cue {}", i + 1, cue.id));
+        }
+        comments
+    }
+}
+
+/// Reads the loop list of a `smpl` chunk (36 byte header followed by 24 byte loops).
+fn read_loops(data: &[u8], smpl: &RiffChunk, big_endian: bool) -> io::Result<Vec<WemLoop>> {
+    if smpl.size < 36 {
+        return Ok(Vec::new());
+    }
+    let count = read_u32_at(data, smpl.offset + 28, big_endian)? as usize;
+    let available = (smpl.size - 36) / 24;
+    let mut loops = Vec::with_capacity(count.min(available));
+    for i in 0..count.min(available) {
+        let base = smpl.offset + 36 + i * 24;
+        loops.push(WemLoop {
+            cue_point_id: read_u32_at(data, base, big_endian)?,
+            loop_type: read_u32_at(data, base + 4, big_endian)?,
+            start: read_u32_at(data, base + 8, big_endian)?,
+            end: read_u32_at(data, base + 12, big_endian)?,
+            play_count: read_u32_at(data, base + 20, big_endian)?,
+        });
+    }
+    Ok(loops)
+}
+
+/// Reads the cue points of a `cue ` chunk (count followed by 24 byte cue points).
+fn read_cues(data: &[u8], cue: &RiffChunk, big_endian: bool) -> io::Result<Vec<WemCue>> {
+    if cue.size < 4 {
+        return Ok(Vec::new());
+    }
+    let count = read_u32_at(data, cue.offset, big_endian)? as usize;
+    let available = (cue.size - 4) / 24;
+    let mut cues = Vec::with_capacity(count.min(available));
+    for i in 0..count.min(available) {
+        let base = cue.offset + 4 + i * 24;
+        cues.push(WemCue {
+            id: read_u32_at(data, base, big_endian)?,
+            position: read_u32_at(data, base + 4, big_endian)?,
+            sample_offset: read_u32_at(data, base + 20, big_endian)?,
+        });
+    }
+    Ok(cues)
+}
+
+impl PsarcAsset for WemInfo {
+    fn read_from<R: Read + Seek>(&mut self, reader: &mut R, _length: usize) -> crate::error::Result<()> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        *self = WemInfo::parse(&data)?;
+        Ok(())
+    }
+}
+
+/// IMA ADPCM step size table.
+const IMA_STEP_TABLE: [i32; 89] = [
+    7, 8, 9, 10, 11, 12, 13, 14, 16, 17, 19, 21, 23, 25, 28, 31, 34, 37, 41, 45, 50, 55, 60, 66,
+    73, 80, 88, 97, 107, 118, 130, 143, 157, 173, 190, 209, 230, 253, 279, 307, 337, 371, 408,
+    449, 494, 544, 598, 658, 724, 796, 876, 963, 1060, 1166, 1282, 1411, 1552, 1707, 1878, 2066,
+    2272, 2499, 2749, 3024, 3327, 3660, 4026, 4428, 4871, 5358, 5894, 6484, 7132, 7845, 8630,
+    9493, 10442, 11487, 12635, 13899, 15289, 16818, 18500, 20350, 22385, 24623, 27086, 29794,
+    32767,
+];
+
+/// IMA ADPCM step index adjustment per nibble.
+const IMA_INDEX_TABLE: [i32; 16] = [-1, -1, -1, -1, 2, 4, 6, 8, -1, -1, -1, -1, 2, 4, 6, 8];
+
+/// Converts a PCM or ADPCM wem directly to a 16-bit (or native PCM width) WAV file.
+///
+/// Vorbis wems are rejected with `ErrorKind::Unsupported`; they need the Ogg
+/// reconstruction instead, so callers should check `WemInfo::codec` first.
+pub fn wem_to_wav(data: &[u8]) -> io::Result<Vec<u8>> {
+    let info = WemInfo::parse(data)?;
+    let (_, chunks) = read_riff_chunks(data)?;
+    let payload = chunks
+        .iter()
+        .find(|c| &c.id == b"data")
+        .map(|c| &data[c.offset..c.offset + c.size])
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "wem has no data chunk"))?;
+
+    match info.codec() {
+        WemCodec::Pcm => {
+            let mut samples = payload.to_vec();
+            // RIFX stores samples big-endian; WAV wants little-endian.
+            if info.big_endian && info.bits_per_sample == 16 {
+                for pair in samples.chunks_exact_mut(2) {
+                    pair.swap(0, 1);
+                }
+            }
+            Ok(build_wav(info.channels, info.sample_rate, info.bits_per_sample, &samples))
+        }
+        WemCodec::Adpcm => {
+            let pcm = decode_wwise_ima(payload, info.channels, info.block_align, info.big_endian)?;
+            let mut samples = Vec::with_capacity(pcm.len() * 2);
+            for sample in pcm {
+                samples.extend_from_slice(&sample.to_le_bytes());
+            }
+            Ok(build_wav(info.channels, info.sample_rate, 16, &samples))
+        }
+        WemCodec::Vorbis => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Vorbis wem requires Ogg reconstruction",
+        )),
+        WemCodec::Other(id) => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("Unsupported wem codec 0x{:04X}", id),
+        )),
+    }
+}
+
+/// Decodes a 16-bit PCM or ADPCM wem into interleaved samples.
+///
+/// Returns `ErrorKind::Unsupported` for Vorbis and other codecs.
+pub fn decode_pcm16(data: &[u8]) -> io::Result<(WemInfo, Vec<i16>)> {
+    let info = WemInfo::parse(data)?;
+    let (_, chunks) = read_riff_chunks(data)?;
+    let payload = chunks
+        .iter()
+        .find(|c| &c.id == b"data")
+        .map(|c| &data[c.offset..c.offset + c.size])
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "wem has no data chunk"))?;
+    let samples = match info.codec() {
+        WemCodec::Pcm if info.bits_per_sample == 16 => payload
+            .chunks_exact(2)
+            .map(|b| if info.big_endian { i16::from_be_bytes([b[0], b[1]]) } else { i16::from_le_bytes([b[0], b[1]]) })
+            .collect(),
+        WemCodec::Adpcm => decode_wwise_ima(payload, info.channels, info.block_align, info.big_endian)?,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("Cannot decode {} wem to PCM", info.codec_name),
+            ))
+        }
+    };
+    Ok((info, samples))
+}
+
+/// Cuts `duration` seconds starting at `start` out of a PCM or ADPCM wem into a 16-bit WAV,
+/// fading in and out over `fade` seconds so the excerpt does not click. The cut is moved
+/// back when the stream ends before `start + duration`.
+///
+/// Returns `ErrorKind::Unsupported` for Vorbis and other codecs.
+pub fn wem_excerpt_to_wav(data: &[u8], start: f64, duration: f64, fade: f64) -> io::Result<Vec<u8>> {
+    let (info, samples) = decode_pcm16(data)?;
+    let channels = info.channels.max(1) as usize;
+    let rate = info.sample_rate as f64;
+    let total_frames = samples.len() / channels;
+    let length = ((duration.max(0.0) * rate) as usize).min(total_frames);
+    let first = ((start.max(0.0) * rate) as usize).min(total_frames - length);
+    let fade_frames = ((fade.max(0.0) * rate) as usize).min(length / 2);
+
+    let mut pcm = Vec::with_capacity(length * channels * 2);
+    for frame in 0..length {
+        let edge = frame.min(length - 1 - frame);
+        let gain = if edge < fade_frames { edge as f32 / fade_frames as f32 } else { 1.0 };
+        for &sample in &samples[(first + frame) * channels..(first + frame + 1) * channels] {
+            pcm.extend_from_slice(&((sample as f32 * gain) as i16).to_le_bytes());
+        }
+    }
+    Ok(build_wav(info.channels, info.sample_rate, 16, &pcm))
+}
+
+/// Measures the leading silence of a wem in seconds: the time until any sample exceeds
+/// `threshold` (as a fraction of full scale).
+///
+/// Returns `Ok(None)` when the codec cannot be decoded in-crate (Vorbis).
+pub fn leading_silence(data: &[u8], threshold: f32) -> io::Result<Option<f64>> {
+    let (info, samples) = match decode_pcm16(data) {
+        Ok(decoded) => decoded,
+        Err(e) if e.kind() == io::ErrorKind::Unsupported => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    if info.channels == 0 || info.sample_rate == 0 {
+        return Ok(None);
+    }
+    let limit = (threshold.clamp(0.0, 1.0) * i16::MAX as f32) as i32;
+    let first_loud = samples
+        .iter()
+        .position(|&s| (s as i32).abs() > limit)
+        .unwrap_or(samples.len());
+    let frame = first_loud / info.channels as usize;
+    Ok(Some(frame as f64 / info.sample_rate as f64))
+}
+
+/// Decodes Wwise IMA ADPCM into interleaved 16-bit samples.
+///
+/// Each block of `block_align` bytes holds one sub-block per channel. A sub-block starts
+/// with a 4 byte header (initial sample, step index, reserved) followed by nibbles, low
+/// nibble first.
+fn decode_wwise_ima(data: &[u8], channels: u16, block_align: u16, big_endian: bool) -> io::Result<Vec<i16>> {
+    let channels = channels as usize;
+    let block_align = block_align as usize;
+    if channels == 0 || block_align == 0 || !block_align.is_multiple_of(channels) || block_align / channels <= 4 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid ADPCM block layout"));
+    }
+    let channel_block = block_align / channels;
+    let samples_per_block = (channel_block - 4) * 2 + 1;
+
+    let mut output = Vec::with_capacity(data.len() / block_align * samples_per_block * channels);
+    let mut decoded = vec![Vec::with_capacity(samples_per_block); channels];
+    for block in data.chunks_exact(block_align) {
+        for (ch, sub) in block.chunks_exact(channel_block).enumerate() {
+            let header = [sub[0], sub[1]];
+            let mut hist = if big_endian { i16::from_be_bytes(header) } else { i16::from_le_bytes(header) } as i32;
+            let mut index = (sub[2] as i32).clamp(0, 88);
+            let samples = &mut decoded[ch];
+            samples.clear();
+            samples.push(hist as i16);
+            for byte in &sub[4..] {
+                for nibble in [byte & 0x0F, byte >> 4] {
+                    let step = IMA_STEP_TABLE[index as usize];
+                    let mut diff = step >> 3;
+                    if nibble & 1 != 0 {
+                        diff += step >> 2;
+                    }
+                    if nibble & 2 != 0 {
+                        diff += step >> 1;
+                    }
+                    if nibble & 4 != 0 {
+                        diff += step;
+                    }
+                    if nibble & 8 != 0 {
+                        diff = -diff;
+                    }
+                    hist = (hist + diff).clamp(i16::MIN as i32, i16::MAX as i32);
+                    index = (index + IMA_INDEX_TABLE[nibble as usize]).clamp(0, 88);
+                    samples.push(hist as i16);
+                }
+            }
+        }
+        for i in 0..samples_per_block {
+            for samples in &decoded {
+                output.push(samples[i]);
+            }
+        }
+    }
+    Ok(output)
+}
+
+/// Wraps little-endian PCM samples in a canonical 44 byte WAV header.
+pub fn build_wav(channels: u16, sample_rate: u32, bits_per_sample: u16, samples: &[u8]) -> Vec<u8> {
+    let block_align = channels * bits_per_sample.div_ceil(8);
+    let byte_rate = sample_rate * block_align as u32;
+    let mut wav = Vec::with_capacity(44 + samples.len());
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + samples.len() as u32).to_le_bytes());
+    wav.extend_from_slice(b"WAVE");
+    wav.extend_from_slice(b"fmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&WEM_CODEC_PCM.to_le_bytes());
+    wav.extend_from_slice(&channels.to_le_bytes());
+    wav.extend_from_slice(&sample_rate.to_le_bytes());
+    wav.extend_from_slice(&byte_rate.to_le_bytes());
+    wav.extend_from_slice(&block_align.to_le_bytes());
+    wav.extend_from_slice(&bits_per_sample.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&(samples.len() as u32).to_le_bytes());
+    wav.extend_from_slice(samples);
+    wav
+}
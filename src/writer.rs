@@ -0,0 +1,395 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use byteorder::{BigEndian, WriteBytesExt};
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+#[cfg(feature = "crypto")]
+use crate::decryptor::DecryptStream;
+use crate::error::PsarcError;
+use crate::lint::{validate_pack, LintReport};
+use crate::psarc::{PsarcArchiveFlags, PsarcFile, PsarcTOC, PsarcTOCEntry, TocLayoutEntry, TOC_ENTRY_SIZE};
+
+/// First bytes of a block compressed at the best level.
+const ZLIB_HEADER: [u8; 2] = [0x78, 0xDA];
+
+/// An entry queued for writing, already split into stored (compressed or raw) blocks.
+#[derive(Debug)]
+struct PendingEntry {
+    path: String,
+    length: u64,
+    /// Stored size of every block, in block size table notation (0 = a full raw block).
+    block_sizes: Vec<u32>,
+    /// The stored blocks back to back.
+    data: Vec<u8>,
+}
+
+/// Builds a PSARC archive.
+///
+/// Entries are added in order and the NamesBlock (entry 0) is generated from their paths
+/// when the archive is written. Blocks are zlib-compressed at the best level so the
+/// `0x78DA` header the reader looks for is produced.
+#[derive(Debug)]
+pub struct PsarcWriter {
+    block_size: u32,
+    encrypt_toc: bool,
+    entries: Vec<PendingEntry>,
+}
+
+impl Default for PsarcWriter {
+    fn default() -> Self {
+        PsarcWriter {
+            block_size: 65536,
+            encrypt_toc: cfg!(feature = "crypto"),
+            entries: Vec::new(),
+        }
+    }
+}
+
+impl PsarcWriter {
+    /// A writer with 64 KiB blocks and an encrypted TOC (when the `crypto` feature is on),
+    /// as used by Rocksmith 2014 packages.
+    pub fn new() -> Self {
+        PsarcWriter::default()
+    }
+
+    /// A writer using the same block size and TOC encryption as an existing archive, so its
+    /// blocks can be copied without recompression.
+    pub fn like(archive: &PsarcFile) -> Self {
+        PsarcWriter::new()
+            .block_size(archive.header.block_size)
+            .encrypt_toc(archive.toc.encrypted)
+    }
+
+    /// Block size of the archive. The block size table is written in the fewest bytes its
+    /// sizes need: 2 up to 64 KiB blocks, 3 up to 16 MiB, 4 beyond.
+    pub fn block_size(mut self, block_size: u32) -> Self {
+        self.block_size = block_size;
+        self
+    }
+
+    pub fn encrypt_toc(mut self, encrypt: bool) -> Self {
+        self.encrypt_toc = encrypt;
+        self
+    }
+
+    /// Paths of the entries added so far, in archive order.
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        self.entries.iter().map(|e| e.path.as_str())
+    }
+
+    /// True when an entry with this path has already been added.
+    pub fn contains(&self, path: &str) -> bool {
+        self.entries.iter().any(|e| e.path == path)
+    }
+
+    /// Compresses `data` block by block and queues it under `path`.
+    pub fn add_entry(&mut self, path: impl Into<String>, data: &[u8]) -> io::Result<()> {
+        self.add_blocks(path.into(), data, true)
+    }
+
+    /// Adds `(path, data)` pairs in order with `add_entry`.
+    pub fn add_entries<P: Into<String>, D: AsRef<[u8]>>(
+        &mut self,
+        entries: impl IntoIterator<Item = (P, D)>,
+    ) -> io::Result<()> {
+        for (path, data) in entries {
+            self.add_entry(path, data.as_ref())?;
+        }
+        Ok(())
+    }
+
+    /// Adds every file below `dir`, in path order, under its path relative to `dir` with
+    /// `/` separators: a folder extracted with `preserve_paths` packs back into the same
+    /// layout. A `NamesBlock.bin` at the top is skipped, since the writer generates it.
+    /// Returns the number of files added.
+    pub fn add_dir(&mut self, dir: impl AsRef<Path>) -> io::Result<usize> {
+        let mut files = Vec::new();
+        collect_files(dir.as_ref(), String::new(), &mut files)?;
+        files.sort();
+        let mut added = 0;
+        for (path, file) in files {
+            if path == "NamesBlock.bin" {
+                tracing::trace!("Skipping {:?}, the NamesBlock is generated", file);
+                continue;
+            }
+            self.add_entry(path, &fs::read(&file)?)?;
+            added += 1;
+        }
+        Ok(added)
+    }
+
+    /// A default writer (see `new`) holding the files below `dir` (see `add_dir`).
+    pub fn from_dir(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let mut writer = PsarcWriter::new();
+        writer.add_dir(dir)?;
+        Ok(writer)
+    }
+
+    /// Queues `data` under `path` without compressing it: every block is stored raw.
+    /// Larger than `add_entry`, but read back without inflating anything.
+    pub fn add_entry_uncompressed(&mut self, path: impl Into<String>, data: &[u8]) -> io::Result<()> {
+        self.add_blocks(path.into(), data, false)
+    }
+
+    /// The content of the queued entry `path`, inflated, or `None` when there is none.
+    pub fn entry_data(&self, path: &str) -> io::Result<Option<Vec<u8>>> {
+        let Some(entry) = self.entries.iter().find(|e| e.path == path) else {
+            return Ok(None);
+        };
+        let block_size = self.block_size as u64;
+        let mut data = Vec::with_capacity(entry.length as usize);
+        let mut stored = entry.data.as_slice();
+        for &recorded in &entry.block_sizes {
+            let size = if recorded == 0 { block_size as usize } else { recorded as usize };
+            let block = stored.get(..size).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::UnexpectedEof, format!("Stored blocks of {} are truncated", path))
+            })?;
+            // Read like `PsarcFile::inflate_entry_data`: only a block recorded as 0 is raw
+            // whatever its first bytes (see `store_blocks`).
+            if recorded != 0 && block.starts_with(&ZLIB_HEADER) {
+                ZlibDecoder::new(block).read_to_end(&mut data)?;
+            } else {
+                data.extend_from_slice(block);
+            }
+            stored = &stored[size..];
+        }
+        data.truncate(entry.length as usize);
+        Ok(Some(data))
+    }
+
+    /// Replaces the content of the queued entry `path` with `data`, compressed, keeping its
+    /// place in the archive. Adds the entry when there is none.
+    pub fn replace_entry(&mut self, path: &str, data: &[u8]) -> io::Result<()> {
+        let Some(position) = self.entries.iter().position(|e| e.path == path) else {
+            return self.add_entry(path, data);
+        };
+        let (block_sizes, stored) = store_blocks(data, self.block_size, true)?;
+        let entry = &mut self.entries[position];
+        entry.length = data.len() as u64;
+        entry.block_sizes = block_sizes;
+        entry.data = stored;
+        Ok(())
+    }
+
+    fn add_blocks(&mut self, path: String, data: &[u8], compress: bool) -> io::Result<()> {
+        self.check_new_path(&path)?;
+        let (block_sizes, stored) = store_blocks(data, self.block_size, compress)?;
+        self.entries.push(PendingEntry {
+            path,
+            length: data.len() as u64,
+            block_sizes,
+            data: stored,
+        });
+        Ok(())
+    }
+
+    /// Queues an entry from its already stored blocks. `block_sizes` uses the block size
+    /// table notation of this writer's block size.
+    pub(crate) fn add_stored(
+        &mut self,
+        path: String,
+        length: u64,
+        block_sizes: Vec<u32>,
+        data: Vec<u8>,
+    ) -> io::Result<()> {
+        self.check_new_path(&path)?;
+        self.entries.push(PendingEntry { path, length, block_sizes, data });
+        Ok(())
+    }
+
+    fn check_new_path(&self, path: &str) -> io::Result<()> {
+        if path.is_empty() || path.contains('\n') {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid entry path: {:?}", path)));
+        }
+        if self.contains(path) {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("Duplicate entry path: {}", path)));
+        }
+        Ok(())
+    }
+
+    /// Checks the queued entries, block size and TOC encryption against what the game
+    /// loader accepts (see `lint::validate_pack`), without writing anything.
+    pub fn validate(&self) -> LintReport {
+        validate_pack(self.paths(), self.block_size, self.encrypt_toc)
+    }
+
+    /// Serializes the archive: header, TOC (encrypted if requested), block size table and
+    /// the data region.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let names = self.entries.iter().map(|e| e.path.as_str()).collect::<Vec<_>>().join("\n");
+        let mut names_writer = PsarcWriter::new().block_size(self.block_size);
+        names_writer.add_entry("NamesBlock.bin", names.as_bytes())?;
+        let names_entry = names_writer.entries.remove(0);
+
+        let all_entries = std::iter::once(&names_entry).chain(&self.entries);
+        let layout: Vec<TocLayoutEntry> = all_entries
+            .clone()
+            .map(|e| TocLayoutEntry { path: e.path.clone(), length: e.length, block_sizes: e.block_sizes.clone() })
+            .collect();
+        let entry_count = layout.len() as u32;
+        let block_count = layout.iter().map(|e| e.block_sizes.len()).sum();
+        let toc_size = PsarcTOC::byte_size(layout.len(), block_count, self.block_size)?;
+        let mut toc = PsarcTOC::rebuild(&layout, self.block_size)?.to_bytes(self.block_size)?;
+
+        let flags = if self.encrypt_toc {
+            encrypt_toc_bytes(&mut toc)?;
+            PsarcArchiveFlags::TOC_ENCRYPTED
+        } else {
+            PsarcArchiveFlags::NONE
+        };
+
+        writer.write_all(b"PSAR")?;
+        writer.write_u32::<BigEndian>(0x0001_0004)?;
+        writer.write_all(b"zlib")?;
+        writer.write_u32::<BigEndian>(toc_size)?;
+        writer.write_u32::<BigEndian>(TOC_ENTRY_SIZE)?;
+        writer.write_u32::<BigEndian>(entry_count)?;
+        writer.write_u32::<BigEndian>(self.block_size)?;
+        writer.write_u32::<BigEndian>(flags.bits())?;
+        writer.write_all(&toc)?;
+        for entry in all_entries {
+            writer.write_all(&entry.data)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the archive to a file.
+    pub fn write_path(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let file = io::BufWriter::new(fs::File::create(path.as_ref())?);
+        self.write_to(file)?;
+        tracing::info!("Written archive {:?} ({} entries)", path.as_ref(), self.entries.len());
+        Ok(())
+    }
+}
+
+/// Lists the files below `dir` as (entry path, file) pairs, `prefix` being the entry path
+/// of `dir` itself.
+fn collect_files(dir: &Path, prefix: String, files: &mut Vec<(String, PathBuf)>) -> io::Result<()> {
+    for item in fs::read_dir(dir)? {
+        let item = item?;
+        let name = item.file_name().into_string().map_err(|name| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("File name is not UTF-8: {:?}", name))
+        })?;
+        let path = format!("{}{}", prefix, name);
+        let file = item.path();
+        if file.is_dir() {
+            collect_files(&file, format!("{}/", path), files)?;
+        } else {
+            files.push((path, file));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "crypto")]
+pub(crate) fn encrypt_toc_bytes(toc: &mut [u8]) -> io::Result<()> {
+    DecryptStream::encrypt_psarc(toc);
+    Ok(())
+}
+
+#[cfg(not(feature = "crypto"))]
+pub(crate) fn encrypt_toc_bytes(_toc: &mut [u8]) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "TOC encryption requires the `crypto` feature",
+    ))
+}
+
+/// Splits `data` into blocks of `block_size` and stores them: zlib-compressed at the best
+/// level when `compress` is set and that makes the block smaller, raw otherwise. Returns the
+/// block size table entries and the stored bytes.
+pub(crate) fn store_blocks(data: &[u8], block_size: u32, compress: bool) -> io::Result<(Vec<u32>, Vec<u8>)> {
+    let block_size = block_size as usize;
+    if block_size == 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Block size is zero"));
+    }
+    let mut block_sizes = Vec::new();
+    let mut stored = Vec::new();
+    for block in data.chunks(block_size) {
+        // Readers inflate every block starting with the zlib header unless it is recorded
+        // as a full raw block (0), so a shorter raw block starting with one is stored
+        // compressed even when that makes it larger.
+        let ambiguous = block.len() < block_size && block.starts_with(&ZLIB_HEADER);
+        if compress || ambiguous {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+            encoder.write_all(block)?;
+            let compressed = encoder.finish()?;
+            if compressed.len() < block.len() || ambiguous {
+                block_sizes.push(compressed.len() as u32);
+                stored.extend_from_slice(&compressed);
+                continue;
+            }
+        }
+        // Incompressible (or not compressed): store the block as is. A full raw block is
+        // recorded as 0.
+        block_sizes.push(if block.len() == block_size { 0 } else { block.len() as u32 });
+        stored.extend_from_slice(block);
+    }
+    Ok((block_sizes, stored))
+}
+
+/// Moves an entry from `src` into `dst` without inflating it: the stored blocks and their
+/// slice of the block size table are copied as they are. Both archives must use the same
+/// block size (see `PsarcWriter::like`), and the entry keeps its path.
+pub fn copy_entry_compressed(src: &PsarcFile, entry: &PsarcTOCEntry, dst: &mut PsarcWriter) -> io::Result<()> {
+    let path = entry.path.as_deref().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "Entry has no path; read the manifest first")
+    })?;
+    copy_entry_compressed_as(src, entry, path, dst)
+}
+
+/// Like `copy_entry_compressed`, but stores the entry under a new path.
+pub fn copy_entry_compressed_as(
+    src: &PsarcFile,
+    entry: &PsarcTOCEntry,
+    path: &str,
+    dst: &mut PsarcWriter,
+) -> io::Result<()> {
+    if src.header.block_size != dst.block_size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Block size mismatch: source uses {}, destination {}",
+                src.header.block_size, dst.block_size
+            ),
+        ));
+    }
+    let (block_sizes, data) = stored_blocks(src, entry)?;
+    dst.add_stored(path.to_string(), entry.length, block_sizes, data.to_vec())
+}
+
+/// Returns the stored (still compressed) blocks of an entry: the block size table slice and
+/// the bytes of the data region they cover. Blocks running past the end of the archive are
+/// a `BadToc` error: copying them short would shift every entry written after them.
+fn stored_blocks<'a>(archive: &'a PsarcFile, entry: &PsarcTOCEntry) -> io::Result<(Vec<u32>, &'a [u8])> {
+    let block_size = archive.header.block_size as u64;
+    if block_size == 0 {
+        return Err(PsarcError::InvalidHeader("block size is zero".to_string()).into());
+    }
+    let num_blocks = entry.length.div_ceil(block_size) as usize;
+    let start = entry.start_block as usize;
+    let sizes = archive
+        .toc
+        .zip_block_sizes
+        .get(start..start.saturating_add(num_blocks))
+        .ok_or_else(|| PsarcError::BadToc(format!("blocks of entry {} missing from the block size table", entry.hash)))?
+        .to_vec();
+    let stored_length: u64 = sizes.iter().map(|&s| if s == 0 { block_size } else { s as u64 }).sum();
+    let data = entry
+        .offset
+        .checked_add(stored_length)
+        .and_then(|end| archive.data.get(usize::try_from(entry.offset).ok()?..usize::try_from(end).ok()?))
+        .ok_or_else(|| {
+            PsarcError::BadToc(format!(
+                "blocks of entry {} run past the end of the archive ({} bytes from offset {}, {} available)",
+                entry.hash,
+                stored_length,
+                entry.offset,
+                archive.data.len()
+            ))
+        })?;
+    Ok((sizes, data))
+}
@@ -0,0 +1,312 @@
+//! Export of SNG arrangements to the Rocksmith 2014 arrangement XML (`<song version="7">`)
+//! read by EOF and the community toolkits, and of vocals to the `<vocals>` XML.
+//!
+//! Everything the SNG keeps is written: beats, phrases and their iterations, linked
+//! difficulties, chord templates, tone changes, sections, events, and every difficulty
+//! level with its notes, chords, anchors and hand shapes. Song metadata the SNG does not
+//! store (title, artist, album, tone names) is left empty.
+
+use std::io::{self, Write};
+
+use crate::library::average_tempo;
+use crate::models::{ChordNotes, Note, NoteMask, Vocal};
+use crate::psarc::SngAsset;
+
+/// Formats a time or length in seconds the way the toolkits write them: `12.345`.
+fn seconds(value: f32) -> String {
+    format!("{:.3}", value)
+}
+
+/// A byte field where 255 means unset, written as -1.
+fn byte(value: u8) -> i32 {
+    if value == u8::MAX { -1 } else { i32::from(value) }
+}
+
+fn flag(mask: NoteMask, flag: NoteMask) -> u8 {
+    u8::from(mask.contains(flag))
+}
+
+/// Escapes text for an attribute value.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Writes `<name a="1" b="2"/>` (or an opening tag when `open`) at `depth` levels of indent.
+fn element<W: Write>(out: &mut W, depth: usize, name: &str, attributes: &[(&str, String)], open: bool) -> io::Result<()> {
+    write!(out, "{:indent$}<{}", "", name, indent = depth * 2)?;
+    for (key, value) in attributes {
+        write!(out, " {}=\"{}\"", key, escape(value))?;
+    }
+    writeln!(out, "{}>", if open { "" } else { "/" })
+}
+
+fn close<W: Write>(out: &mut W, depth: usize, name: &str) -> io::Result<()> {
+    writeln!(out, "{:indent$}</{}>", "", name, indent = depth * 2)
+}
+
+/// Writes `<name>text</name>`.
+fn text_element<W: Write>(out: &mut W, depth: usize, name: &str, text: &str) -> io::Result<()> {
+    writeln!(out, "{:indent$}<{name}>{}</{name}>", "", escape(text), indent = depth * 2, name = name)
+}
+
+/// Writes a `<name count="n">` list with one child per item, or `<name count="0"/>`.
+fn list<W: Write, T>(
+    out: &mut W,
+    depth: usize,
+    name: &str,
+    items: &[T],
+    mut write_item: impl FnMut(&mut W, &T) -> io::Result<()>,
+) -> io::Result<()> {
+    let count = [("count", items.len().to_string())];
+    if items.is_empty() {
+        return element(out, depth, name, &count, false);
+    }
+    element(out, depth, name, &count, true)?;
+    for item in items {
+        write_item(out, item)?;
+    }
+    close(out, depth, name)
+}
+
+/// Attributes shared by single notes and the notes of a chord.
+fn note_attributes(time: f32, string: u8, fret: u8, sustain: f32, mask: NoteMask) -> Vec<(&'static str, String)> {
+    vec![
+        ("time", seconds(time)),
+        ("linkNext", flag(mask, NoteMask::PARENT).to_string()),
+        ("accent", flag(mask, NoteMask::ACCENT).to_string()),
+        ("fret", fret.to_string()),
+        ("hammerOn", flag(mask, NoteMask::HAMMER_ON).to_string()),
+        ("harmonic", flag(mask, NoteMask::HARMONIC).to_string()),
+        ("hopo", u8::from(mask.intersects(NoteMask::HAMMER_ON | NoteMask::PULL_OFF)).to_string()),
+        ("ignore", flag(mask, NoteMask::IGNORE).to_string()),
+        ("mute", flag(mask, NoteMask::FRET_HAND_MUTE).to_string()),
+        ("palmMute", flag(mask, NoteMask::PALM_MUTE).to_string()),
+        ("pullOff", flag(mask, NoteMask::PULL_OFF).to_string()),
+        ("string", string.to_string()),
+        ("sustain", seconds(sustain)),
+        ("tremolo", flag(mask, NoteMask::TREMOLO).to_string()),
+        ("harmonicPinch", flag(mask, NoteMask::PINCH_HARMONIC).to_string()),
+    ]
+}
+
+fn write_bend_values<W: Write>(out: &mut W, depth: usize, bends: &[(f32, f32)]) -> io::Result<()> {
+    list(out, depth, "bendValues", bends, |out, (time, step)| {
+        element(out, depth + 1, "bendValue", &[("time", seconds(*time)), ("step", format!("{:.3}", step))], false)
+    })
+}
+
+fn write_note<W: Write>(out: &mut W, depth: usize, note: &Note) -> io::Result<()> {
+    let mask = note.mask();
+    let mut attributes = note_attributes(note.time, note.string_index, note.fret_id, note.sustain, mask);
+    attributes.extend([
+        ("bend", format!("{:.3}", note.max_bend)),
+        ("leftHand", byte(note.left_hand).to_string()),
+        ("slideTo", byte(note.slide_to).to_string()),
+        ("slideUnpitchTo", byte(note.slide_unpitch_to).to_string()),
+        ("pickDirection", byte(note.pick_direction).max(0).to_string()),
+        ("pluck", byte(note.pluck).to_string()),
+        ("slap", byte(note.slap).to_string()),
+        ("tap", u8::from(note.tap != 0 && note.tap != u8::MAX).to_string()),
+        ("vibrato", note.vibrato.to_string()),
+    ]);
+    let bends: Vec<(f32, f32)> = note.bend_data.iter().map(|b| (b.time, b.step)).collect();
+    if bends.is_empty() {
+        return element(out, depth, "note", &attributes, false);
+    }
+    element(out, depth, "note", &attributes, true)?;
+    write_bend_values(out, depth + 1, &bends)?;
+    close(out, depth, "note")
+}
+
+/// Writes a chord and, unless it is a high density repeat, one `chordNote` per fretted
+/// string of its template with the techniques of `ChordNotes`.
+fn write_chord<W: Write>(out: &mut W, depth: usize, sng: &SngAsset, chord: &Note) -> io::Result<()> {
+    let mask = chord.mask();
+    let attributes = [
+        ("time", seconds(chord.time)),
+        ("chordId", chord.chord_id.to_string()),
+        ("linkNext", flag(mask, NoteMask::PARENT).to_string()),
+        ("accent", flag(mask, NoteMask::ACCENT).to_string()),
+        ("fretHandMute", flag(mask, NoteMask::FRET_HAND_MUTE).to_string()),
+        ("highDensity", flag(mask, NoteMask::HIGH_DENSITY).to_string()),
+        ("ignore", flag(mask, NoteMask::IGNORE).to_string()),
+        ("palmMute", flag(mask, NoteMask::PALM_MUTE).to_string()),
+        ("hopo", u8::from(mask.intersects(NoteMask::HAMMER_ON | NoteMask::PULL_OFF)).to_string()),
+        ("strum", "down".to_string()),
+    ];
+    let template = usize::try_from(chord.chord_id).ok().and_then(|id| sng.chords.get(id));
+    let Some(template) = template.filter(|_| !mask.contains(NoteMask::HIGH_DENSITY)) else {
+        return element(out, depth, "chord", &attributes, false);
+    };
+    let techniques: Option<&ChordNotes> = usize::try_from(chord.chord_notes_id).ok().and_then(|id| sng.chord_notes.get(id));
+    element(out, depth, "chord", &attributes, true)?;
+    for (string, &fret) in template.frets.iter().enumerate().filter(|(_, &fret)| fret != u8::MAX) {
+        let string_mask = techniques.map_or(NoteMask::empty(), |t| NoteMask::from_bits_truncate(t.note_mask[string] as u32));
+        let mut attributes = note_attributes(chord.time, string as u8, fret, chord.sustain, string_mask);
+        let bends: Vec<(f32, f32)> = techniques
+            .map(|t| {
+                let bend = &t.bend_data[string];
+                let used = (bend.used_count.max(0) as usize).min(bend.bend_data.len());
+                bend.bend_data[..used].iter().map(|b| (b.time, b.step)).collect()
+            })
+            .unwrap_or_default();
+        if let Some(t) = techniques {
+            attributes.extend([
+                ("bend", format!("{:.3}", bends.iter().map(|b| b.1).fold(0.0, f32::max))),
+                ("slideTo", byte(t.slide_to[string]).to_string()),
+                ("slideUnpitchTo", byte(t.slide_unpitch_to[string]).to_string()),
+                ("vibrato", t.vibrato[string].to_string()),
+            ]);
+        }
+        if bends.is_empty() {
+            element(out, depth + 1, "chordNote", &attributes, false)?;
+        } else {
+            element(out, depth + 1, "chordNote", &attributes, true)?;
+            write_bend_values(out, depth + 2, &bends)?;
+            close(out, depth + 1, "chordNote")?;
+        }
+    }
+    close(out, depth, "chord")
+}
+
+/// Writes the instrument arrangement XML of `sng`. `arrangement` (`Lead`, `Bass`, ...) is
+/// not stored in the SNG; callers take it from the entry or manifest name.
+pub fn write_arrangement_xml<W: Write>(sng: &SngAsset, arrangement: Option<&str>, mut out: W) -> io::Result<()> {
+    let out = &mut out;
+    let metadata = &sng.metadata;
+    writeln!(out, "<?xml version=\"1.0\" encoding=\"utf-8\"?>")?;
+    element(out, 0, "song", &[("version", "7".to_string())], true)?;
+    text_element(out, 1, "title", "")?;
+    text_element(out, 1, "arrangement", arrangement.unwrap_or(""))?;
+    text_element(out, 1, "part", &metadata.part.to_string())?;
+    text_element(out, 1, "offset", &seconds(0.0 - metadata.start_time))?;
+    text_element(out, 1, "centOffset", "0")?;
+    text_element(out, 1, "songLength", &seconds(metadata.song_length))?;
+    text_element(out, 1, "startBeat", &seconds(sng.bpms.first().map_or(metadata.start_time, |b| b.time)))?;
+    text_element(out, 1, "averageTempo", &seconds(average_tempo(sng)))?;
+    let tuning: Vec<(&str, String)> = ["string0", "string1", "string2", "string3", "string4", "string5"]
+        .into_iter()
+        .enumerate()
+        .map(|(i, name)| (name, metadata.tuning.get(i).copied().unwrap_or(0).to_string()))
+        .collect();
+    element(out, 1, "tuning", &tuning, false)?;
+    text_element(out, 1, "capo", &byte(metadata.capo_fret_id).max(0).to_string())?;
+    text_element(out, 1, "artistName", "")?;
+    text_element(out, 1, "albumName", "")?;
+    text_element(out, 1, "lastConversionDateTime", &metadata.last_conversion_date_time)?;
+
+    list(out, 1, "phrases", &sng.phrases, |out, phrase| {
+        let attributes = [
+            ("disparity", phrase.disparity.to_string()),
+            ("ignore", phrase.ignore.to_string()),
+            ("maxDifficulty", phrase.max_difficulty.to_string()),
+            ("name", phrase.name.clone()),
+            ("solo", phrase.solo.to_string()),
+        ];
+        element(out, 2, "phrase", &attributes, false)
+    })?;
+    list(out, 1, "phraseIterations", &sng.phrase_iterations, |out, iteration| {
+        let attributes =
+            [("time", seconds(iteration.start_time)), ("phraseId", iteration.phrase_id.to_string()), ("variation", String::new())];
+        let heroes: Vec<(usize, i32)> =
+            iteration.difficulty.iter().enumerate().filter(|(_, &d)| d > 0).map(|(i, &d)| (i + 1, d)).collect();
+        if heroes.is_empty() {
+            return element(out, 2, "phraseIteration", &attributes, false);
+        }
+        element(out, 2, "phraseIteration", &attributes, true)?;
+        list(out, 3, "heroLevels", &heroes, |out, (hero, difficulty)| {
+            element(out, 4, "heroLevel", &[("hero", hero.to_string()), ("difficulty", difficulty.to_string())], false)
+        })?;
+        close(out, 2, "phraseIteration")
+    })?;
+    list(out, 1, "newLinkedDiffs", &sng.nld, |out, nld| {
+        let phrases = nld.nld_phrase.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(",");
+        let attributes = [("levelBreak", nld.level_break.to_string()), ("ratio", "1.000".to_string()), ("phrases", phrases)];
+        element(out, 2, "newLinkedDiff", &attributes, false)
+    })?;
+    element(out, 1, "linkedDiffs", &[("count", "0".to_string())], false)?;
+    element(out, 1, "phraseProperties", &[("count", "0".to_string())], false)?;
+    list(out, 1, "chordTemplates", &sng.chords, |out, chord| {
+        // Arpeggio templates are told apart by their display name.
+        let display = if chord.mask & 1 != 0 { format!("{}-arp", chord.name) } else { chord.name.clone() };
+        let mut attributes = vec![("chordName", chord.name.clone()), ("displayName", display)];
+        const FINGERS: [&str; 6] = ["finger0", "finger1", "finger2", "finger3", "finger4", "finger5"];
+        const FRETS: [&str; 6] = ["fret0", "fret1", "fret2", "fret3", "fret4", "fret5"];
+        attributes.extend(FINGERS.iter().zip(chord.fingers).map(|(name, finger)| (*name, byte(finger).to_string())));
+        attributes.extend(FRETS.iter().zip(chord.frets).map(|(name, fret)| (*name, byte(fret).to_string())));
+        element(out, 2, "chordTemplate", &attributes, false)
+    })?;
+    element(out, 1, "fretHandMuteTemplates", &[("count", "0".to_string())], false)?;
+    list(out, 1, "ebeats", &sng.bpms, |out, bpm| {
+        let measure = if bpm.beat == 0 { i32::from(bpm.measure) } else { -1 };
+        element(out, 2, "ebeat", &[("time", seconds(bpm.time)), ("measure", measure.to_string())], false)
+    })?;
+    list(out, 1, "tones", &sng.tones, |out, tone| {
+        element(out, 2, "tone", &[("time", seconds(tone.time)), ("id", tone.tone_id.to_string())], false)
+    })?;
+    list(out, 1, "sections", &sng.sections, |out, section| {
+        let attributes =
+            [("name", section.name.clone()), ("number", section.number.to_string()), ("startTime", seconds(section.start_time))];
+        element(out, 2, "section", &attributes, false)
+    })?;
+    list(out, 1, "events", &sng.events, |out, event| {
+        element(out, 2, "event", &[("time", seconds(event.time)), ("code", event.event_name.clone())], false)
+    })?;
+    element(out, 1, "transcriptionTrack", &[("difficulty", "-1".to_string())], false)?;
+    list(out, 1, "levels", &sng.arrangements, |out, level| {
+        element(out, 2, "level", &[("difficulty", level.difficulty.to_string())], true)?;
+        let (chords, notes): (Vec<&Note>, Vec<&Note>) =
+            level.notes.iter().partition(|note| note.mask().contains(NoteMask::CHORD));
+        list(out, 3, "notes", &notes, |out, note| write_note(out, 4, note))?;
+        list(out, 3, "chords", &chords, |out, chord| write_chord(out, 4, sng, chord))?;
+        element(out, 3, "fretHandMutes", &[("count", "0".to_string())], false)?;
+        list(out, 3, "anchors", &level.anchors, |out, anchor| {
+            let attributes = [
+                ("time", seconds(anchor.start_beat_time)),
+                ("fret", anchor.fret_id.to_string()),
+                ("width", format!("{:.3}", anchor.width as f32)),
+            ];
+            element(out, 4, "anchor", &attributes, false)
+        })?;
+        let mut shapes: Vec<_> = level.fingerprints1.iter().chain(&level.fingerprints2).collect();
+        shapes.sort_by(|a, b| a.start_time.total_cmp(&b.start_time));
+        list(out, 3, "handShapes", &shapes, |out, shape| {
+            let attributes = [
+                ("chordId", shape.chord_id.to_string()),
+                ("startTime", seconds(shape.start_time)),
+                ("endTime", seconds(shape.end_time)),
+            ];
+            element(out, 4, "handShape", &attributes, false)
+        })?;
+        close(out, 2, "level")
+    })?;
+    close(out, 0, "song")?;
+    out.flush()
+}
+
+/// Writes the vocals XML (`<vocals count="n"><vocal time note length lyric/>...`).
+pub fn write_vocals_xml<W: Write>(vocals: &[Vocal], mut out: W) -> io::Result<()> {
+    let out = &mut out;
+    writeln!(out, "<?xml version=\"1.0\" encoding=\"utf-8\"?>")?;
+    list(out, 0, "vocals", vocals, |out, vocal| {
+        let attributes = [
+            ("time", seconds(vocal.time)),
+            ("note", vocal.note.to_string()),
+            ("length", seconds(vocal.length)),
+            ("lyric", vocal.lyric.clone()),
+        ];
+        element(out, 1, "vocal", &attributes, false)
+    })?;
+    out.flush()
+}
@@ -0,0 +1,457 @@
+//! Minimal Zstandard encoder, used for `--compress-output zstd` (see
+//! `extract::OutputCompression`).
+//!
+//! Like `md5`, this is a small self-contained implementation rather than a dependency: the
+//! text exports it compresses only need a fast greedy match finder. Each block stores its
+//! literals raw and codes its sequences with the predefined FSE tables of RFC 8878, so no
+//! Huffman or FSE table description is ever written. Blocks that do not shrink are stored
+//! as raw blocks, and every block is independent of the previous ones. The frames carry no
+//! content size or checksum, so `ZstdEncoder` can stream.
+
+use std::io::{self, Write};
+
+const MAGIC: u32 = 0xFD2F_B528;
+/// Largest block, and the window announced in the frame header.
+const BLOCK_SIZE: usize = 1 << 17;
+/// Window_Descriptor for a 2^17 byte window: exponent 7, mantissa 0.
+const WINDOW_DESCRIPTOR: u8 = (17 - 10) << 3;
+const MIN_MATCH: usize = 4;
+const HASH_LOG: u32 = 15;
+
+const RAW_BLOCK: u32 = 0;
+const RLE_BLOCK: u32 = 1;
+const COMPRESSED_BLOCK: u32 = 2;
+
+/// Predefined literal length distribution (RFC 8878, 3.1.1.3.2.2.1).
+const LITERAL_LENGTH_COUNTS: [i16; 36] = [
+    4, 3, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 3, 2, 1, 1, 1, 1, 1, -1, -1, -1, -1,
+];
+const LITERAL_LENGTH_LOG: u32 = 6;
+/// Predefined match length distribution.
+const MATCH_LENGTH_COUNTS: [i16; 53] = [
+    1, 4, 3, 2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1, -1, -1,
+];
+const MATCH_LENGTH_LOG: u32 = 6;
+/// Predefined offset code distribution.
+const OFFSET_COUNTS: [i16; 29] = [
+    1, 1, 1, 1, 1, 1, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1,
+];
+const OFFSET_LOG: u32 = 5;
+
+/// Literal length codes from 16 up as (baseline, extra bits); codes below 16 are the
+/// length itself.
+const LITERAL_LENGTH_CODES: [(u32, u32); 20] = [
+    (16, 1), (18, 1), (20, 1), (22, 1), (24, 2), (28, 2), (32, 3), (40, 3), (48, 4), (64, 6),
+    (128, 7), (256, 8), (512, 9), (1024, 10), (2048, 11), (4096, 12), (8192, 13), (16384, 14),
+    (32768, 15), (65536, 16),
+];
+/// Match length codes from 32 up as (baseline, extra bits); codes below 32 are the length
+/// minus 3.
+const MATCH_LENGTH_CODES: [(u32, u32); 21] = [
+    (35, 1), (37, 1), (39, 1), (41, 1), (43, 2), (47, 2), (51, 3), (59, 3), (67, 4), (83, 4),
+    (99, 5), (131, 7), (259, 8), (515, 9), (1027, 10), (2051, 11), (4099, 12), (8195, 13),
+    (16387, 14), (32771, 15), (65539, 16),
+];
+
+/// Compresses `data` into a single Zstandard frame.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut encoder = ZstdEncoder::new(Vec::new());
+    encoder.write_all(data).expect("writing to a Vec cannot fail");
+    encoder.finish().expect("writing to a Vec cannot fail")
+}
+
+/// Streaming encoder writing one Zstandard frame into `W`. Call `finish` to write the last
+/// block; dropping the encoder leaves the frame truncated.
+pub struct ZstdEncoder<W: Write> {
+    inner: W,
+    pending: Vec<u8>,
+    started: bool,
+    tables: Tables,
+}
+
+impl<W: Write> ZstdEncoder<W> {
+    pub fn new(inner: W) -> Self {
+        ZstdEncoder { inner, pending: Vec::with_capacity(BLOCK_SIZE), started: false, tables: Tables::new() }
+    }
+
+    /// Writes the buffered data as the last block and returns the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_block(true)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn write_block(&mut self, last: bool) -> io::Result<()> {
+        if !self.started {
+            self.inner.write_all(&MAGIC.to_le_bytes())?;
+            // Frame_Header_Descriptor: no content size, not single segment, no checksum
+            // and no dictionary.
+            self.inner.write_all(&[0, WINDOW_DESCRIPTOR])?;
+            self.started = true;
+        }
+        let block = std::mem::take(&mut self.pending);
+        let mut out = Vec::new();
+        encode_block(&block, last, &self.tables, &mut out);
+        self.inner.write_all(&out)?;
+        self.pending = block;
+        self.pending.clear();
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for ZstdEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.pending.len() == BLOCK_SIZE {
+            self.write_block(false)?;
+        }
+        let taken = buf.len().min(BLOCK_SIZE - self.pending.len());
+        self.pending.extend_from_slice(&buf[..taken]);
+        Ok(taken)
+    }
+
+    /// Flushes the inner writer. Buffered data stays until the block is full, since every
+    /// block boundary costs compression.
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn block_header(out: &mut Vec<u8>, last: bool, block_type: u32, size: usize) {
+    let header = last as u32 | block_type << 1 | (size as u32) << 3;
+    out.extend_from_slice(&header.to_le_bytes()[..3]);
+}
+
+/// Appends `block` to `out` as a compressed, RLE or raw block, whichever is smallest.
+fn encode_block(block: &[u8], last: bool, tables: &Tables, out: &mut Vec<u8>) {
+    if block.len() > 1 && block.iter().all(|&byte| byte == block[0]) {
+        block_header(out, last, RLE_BLOCK, block.len());
+        out.push(block[0]);
+        return;
+    }
+    let compressed = compress_block(block, tables);
+    if compressed.len() < block.len() {
+        block_header(out, last, COMPRESSED_BLOCK, compressed.len());
+        out.extend_from_slice(&compressed);
+    } else {
+        block_header(out, last, RAW_BLOCK, block.len());
+        out.extend_from_slice(block);
+    }
+}
+
+struct Sequence {
+    literal_length: u32,
+    match_length: u32,
+    offset: u32,
+}
+
+/// Greedy match finder over a hash of the next four bytes. Returns the sequences and the
+/// literals they consume, trailing literals included.
+fn find_sequences(block: &[u8]) -> (Vec<Sequence>, Vec<u8>) {
+    let mut sequences = Vec::new();
+    let mut literals = Vec::new();
+    let mut table = vec![u32::MAX; 1 << HASH_LOG];
+    let hash = |at: usize| {
+        let word = u32::from_le_bytes(block[at..at + 4].try_into().unwrap());
+        (word.wrapping_mul(0x9E37_79B1) >> (32 - HASH_LOG)) as usize
+    };
+    let mut anchor = 0;
+    let mut position = 0;
+    while position + MIN_MATCH <= block.len() {
+        let slot = hash(position);
+        let candidate = table[slot];
+        table[slot] = position as u32;
+        if candidate != u32::MAX && block[candidate as usize..][..MIN_MATCH] == block[position..][..MIN_MATCH] {
+            let candidate = candidate as usize;
+            let length = MIN_MATCH
+                + block[position + MIN_MATCH..]
+                    .iter()
+                    .zip(&block[candidate + MIN_MATCH..])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+            literals.extend_from_slice(&block[anchor..position]);
+            sequences.push(Sequence {
+                literal_length: (position - anchor) as u32,
+                match_length: length as u32,
+                offset: (position - candidate) as u32,
+            });
+            position += length;
+            anchor = position;
+        } else {
+            position += 1;
+        }
+    }
+    literals.extend_from_slice(&block[anchor..]);
+    (sequences, literals)
+}
+
+/// Literals and sequences sections of a compressed block.
+fn compress_block(block: &[u8], tables: &Tables) -> Vec<u8> {
+    let (sequences, literals) = find_sequences(block);
+    let mut out = Vec::with_capacity(block.len());
+
+    // Raw literals section, with the shortest size format that fits.
+    let size = literals.len() as u32;
+    if size < 32 {
+        out.push((size << 3) as u8);
+    } else if size < 4096 {
+        out.extend_from_slice(&((size << 4) | 0b0100).to_le_bytes()[..2]);
+    } else {
+        out.extend_from_slice(&((size << 4) | 0b1100).to_le_bytes()[..3]);
+    }
+    out.extend_from_slice(&literals);
+
+    let count = sequences.len();
+    if count < 128 {
+        out.push(count as u8);
+    } else if count < 0x7F00 {
+        out.extend_from_slice(&[(count >> 8) as u8 + 0x80, count as u8]);
+    } else {
+        out.push(0xFF);
+        out.extend_from_slice(&((count - 0x7F00) as u16).to_le_bytes());
+    }
+    if count == 0 {
+        return out;
+    }
+    // Predefined mode for literal lengths, offsets and match lengths.
+    out.push(0);
+
+    let codes: Vec<_> = sequences
+        .iter()
+        .map(|sequence| {
+            let (literal_code, literal_bits, literal_extra) = literal_length_code(sequence.literal_length);
+            let (match_code, match_bits, match_extra) = match_length_code(sequence.match_length);
+            // Offset_Value above 3 is a plain offset, so repeat offsets are never used.
+            let offset_value = sequence.offset + 3;
+            let offset_code = 31 - offset_value.leading_zeros();
+            let offset_extra = offset_value - (1 << offset_code);
+            [(literal_code, literal_bits, literal_extra), (match_code, match_bits, match_extra), (offset_code, offset_code, offset_extra)]
+        })
+        .collect();
+
+    // The decoder reads the bitstream backwards, so the last sequence is written first.
+    let mut bits = BitWriter::default();
+    let [literal, matched, offset] = codes[count - 1];
+    let mut literal_state = tables.literal_lengths.initial_state(literal.0);
+    let mut offset_state = tables.offsets.initial_state(offset.0);
+    let mut match_state = tables.match_lengths.initial_state(matched.0);
+    bits.add(literal.2, literal.1);
+    bits.add(matched.2, matched.1);
+    bits.add(offset.2, offset.1);
+    for &[literal, matched, offset] in codes[..count - 1].iter().rev() {
+        tables.offsets.encode(&mut bits, &mut offset_state, offset.0);
+        tables.match_lengths.encode(&mut bits, &mut match_state, matched.0);
+        tables.literal_lengths.encode(&mut bits, &mut literal_state, literal.0);
+        bits.add(literal.2, literal.1);
+        bits.add(matched.2, matched.1);
+        bits.add(offset.2, offset.1);
+    }
+    bits.add(match_state, tables.match_lengths.log);
+    bits.add(offset_state, tables.offsets.log);
+    bits.add(literal_state, tables.literal_lengths.log);
+    out.extend_from_slice(&bits.close());
+    out
+}
+
+/// (code, extra bit count, extra bits) of a literal length.
+fn literal_length_code(length: u32) -> (u32, u32, u32) {
+    if length < 16 {
+        return (length, 0, 0);
+    }
+    let index = LITERAL_LENGTH_CODES.iter().rposition(|&(baseline, _)| baseline <= length).unwrap();
+    let (baseline, bits) = LITERAL_LENGTH_CODES[index];
+    (16 + index as u32, bits, length - baseline)
+}
+
+/// (code, extra bit count, extra bits) of a match length.
+fn match_length_code(length: u32) -> (u32, u32, u32) {
+    if length < 35 {
+        return (length - 3, 0, 0);
+    }
+    let index = MATCH_LENGTH_CODES.iter().rposition(|&(baseline, _)| baseline <= length).unwrap();
+    let (baseline, bits) = MATCH_LENGTH_CODES[index];
+    (32 + index as u32, bits, length - baseline)
+}
+
+/// Forward bit writer; the stream ends with a marker bit so the decoder can find its start.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    container: u64,
+    filled: u32,
+}
+
+impl BitWriter {
+    fn add(&mut self, value: u32, count: u32) {
+        let mask = (1u64 << count) - 1;
+        self.container |= (value as u64 & mask) << self.filled;
+        self.filled += count;
+        while self.filled >= 8 {
+            self.bytes.push(self.container as u8);
+            self.container >>= 8;
+            self.filled -= 8;
+        }
+    }
+
+    fn close(mut self) -> Vec<u8> {
+        self.add(1, 1);
+        if self.filled > 0 {
+            self.bytes.push(self.container as u8);
+        }
+        self.bytes
+    }
+}
+
+struct Tables {
+    literal_lengths: FseTable,
+    match_lengths: FseTable,
+    offsets: FseTable,
+}
+
+impl Tables {
+    fn new() -> Self {
+        Tables {
+            literal_lengths: FseTable::new(&LITERAL_LENGTH_COUNTS, LITERAL_LENGTH_LOG),
+            match_lengths: FseTable::new(&MATCH_LENGTH_COUNTS, MATCH_LENGTH_LOG),
+            offsets: FseTable::new(&OFFSET_COUNTS, OFFSET_LOG),
+        }
+    }
+}
+
+/// FSE encoding table built from a normalized distribution, spreading the symbols the way
+/// the decoder does.
+struct FseTable {
+    log: u32,
+    /// Next state, indexed by each symbol's slice start plus the shifted current state.
+    states: Vec<u32>,
+    /// Per symbol: (delta used to compute the bit count, start of its slice in `states`).
+    transforms: Vec<(u32, i32)>,
+}
+
+impl FseTable {
+    fn new(counts: &[i16], log: u32) -> Self {
+        let size = 1usize << log;
+        let mask = size - 1;
+        let mut symbols = vec![0u32; size];
+
+        // Symbols with a "less than one" probability take the last cells.
+        let mut high = size - 1;
+        let mut cumulative = vec![0usize; counts.len() + 1];
+        for (symbol, &count) in counts.iter().enumerate() {
+            if count == -1 {
+                cumulative[symbol + 1] = cumulative[symbol] + 1;
+                symbols[high] = symbol as u32;
+                high -= 1;
+            } else {
+                cumulative[symbol + 1] = cumulative[symbol] + count as usize;
+            }
+        }
+        let step = (size >> 1) + (size >> 3) + 3;
+        let mut position = 0;
+        for (symbol, &count) in counts.iter().enumerate() {
+            for _ in 0..count.max(0) {
+                symbols[position] = symbol as u32;
+                position = (position + step) & mask;
+                while position > high {
+                    position = (position + step) & mask;
+                }
+            }
+        }
+
+        let mut states = vec![0u32; size];
+        let mut next = cumulative.clone();
+        for (cell, &symbol) in symbols.iter().enumerate() {
+            states[next[symbol as usize]] = (size + cell) as u32;
+            next[symbol as usize] += 1;
+        }
+
+        let transforms = counts
+            .iter()
+            .enumerate()
+            .map(|(symbol, &count)| {
+                let start = cumulative[symbol] as i32;
+                match count {
+                    -1 | 1 => ((log << 16) - size as u32, start - 1),
+                    0 => (((log + 1) << 16) - size as u32, 0),
+                    count => {
+                        let count = count as u32;
+                        let max_bits = log - (31 - (count - 1).leading_zeros());
+                        ((max_bits << 16) - (count << max_bits), start - count as i32)
+                    }
+                }
+            })
+            .collect();
+        FseTable { log, states, transforms }
+    }
+
+    fn next_state(&self, value: u32, bits: u32, symbol: u32) -> u32 {
+        let (_, start) = self.transforms[symbol as usize];
+        self.states[((value >> bits) as i32 + start) as usize]
+    }
+
+    /// State after encoding the first symbol, which costs no bits.
+    fn initial_state(&self, symbol: u32) -> u32 {
+        let (delta, _) = self.transforms[symbol as usize];
+        let bits = (delta + (1 << 15)) >> 16;
+        self.next_state((bits << 16) - delta, bits, symbol)
+    }
+
+    fn encode(&self, bits: &mut BitWriter, state: &mut u32, symbol: u32) {
+        let (delta, _) = self.transforms[symbol as usize];
+        let count = (*state + delta) >> 16;
+        bits.add(*state, count);
+        *state = self.next_state(*state, count, symbol);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame_header() -> Vec<u8> {
+        [MAGIC.to_le_bytes().as_slice(), &[0, WINDOW_DESCRIPTOR]].concat()
+    }
+
+    #[test]
+    fn empty_input_is_one_empty_raw_block() {
+        assert_eq!(compress(&[]), [frame_header(), vec![1, 0, 0]].concat());
+    }
+
+    #[test]
+    fn runs_become_rle_blocks() {
+        let data = vec![7u8; BLOCK_SIZE + 10];
+        let mut expected = frame_header();
+        block_header(&mut expected, false, RLE_BLOCK, BLOCK_SIZE);
+        expected.push(7);
+        block_header(&mut expected, true, RLE_BLOCK, 10);
+        expected.push(7);
+        assert_eq!(compress(&data), expected);
+    }
+
+    #[test]
+    fn repetitive_text_is_compressed() {
+        let data = "{\"time\": 1.5, \"fret\": 3, \"string\": 2}\n".repeat(1000);
+        let frame = compress(data.as_bytes());
+        let header = u32::from_le_bytes([frame[6], frame[7], frame[8], 0]);
+        assert_eq!(header & 1, 1);
+        assert_eq!(header >> 1 & 3, COMPRESSED_BLOCK);
+        assert_eq!(frame.len(), 9 + (header >> 3) as usize);
+        assert!(frame.len() < data.len() / 10);
+    }
+
+    #[test]
+    fn length_codes_round_trip() {
+        for length in 0..70_000 {
+            let (code, bits, extra) = literal_length_code(length);
+            let baseline = if code < 16 { code } else { LITERAL_LENGTH_CODES[code as usize - 16].0 };
+            assert_eq!(baseline + extra, length);
+            assert!(extra < 1 << bits);
+        }
+        for length in 3..70_000 {
+            let (code, bits, extra) = match_length_code(length);
+            let baseline = if code < 32 { code + 3 } else { MATCH_LENGTH_CODES[code as usize - 32].0 };
+            assert_eq!(baseline + extra, length);
+            assert!(extra < 1 << bits);
+        }
+    }
+}
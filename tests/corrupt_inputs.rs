@@ -0,0 +1,196 @@
+//! Corrupt and hostile inputs must fail with an error: never a panic, and never an
+//! allocation sized by a header field the file does not back.
+
+use std::io::Cursor;
+
+use psarc_unpacker::psarc::PsarcFile;
+use psarc_unpacker::writer::{copy_entry_compressed, PsarcWriter};
+
+/// Header offsets of the fields the cases below overwrite.
+const TOC_SIZE: usize = 12;
+const ENTRY_COUNT: usize = 20;
+const BLOCK_SIZE: usize = 24;
+const ARCHIVE_FLAGS: usize = 28;
+
+fn sample_archive() -> Vec<u8> {
+    let mut writer = PsarcWriter::new();
+    writer.add_entry("manifests/songs/sample.hsan", br#"{"Entries":{}}"#).unwrap();
+    writer.add_entry("songs/bin/generic/sample.bin", &[7u8; 70_000]).unwrap();
+    writer.add_entry("audio/windows/sample.wem", b"RIFF").unwrap();
+    let mut bytes = Vec::new();
+    writer.write_to(&mut bytes).unwrap();
+    bytes
+}
+
+fn set_u32(bytes: &mut [u8], offset: usize, value: u32) {
+    bytes[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+}
+
+/// Opens `bytes` and inflates every entry, returning whether all of it succeeded.
+fn read_all(bytes: &[u8]) -> bool {
+    let Ok(mut archive) = PsarcFile::open(&mut Cursor::new(bytes)) else {
+        return false;
+    };
+    let names = archive.read_manifest().is_ok();
+    let entries = archive.toc.entries.clone();
+    entries.iter().fold(names, |ok, entry| archive.inflate_entry_data(entry).is_ok() && ok)
+}
+
+#[test]
+fn sample_reads() {
+    assert!(read_all(&sample_archive()));
+}
+
+#[test]
+fn truncated_header() {
+    let bytes = sample_archive();
+    for length in [0, 3, 4, 10, 31] {
+        assert!(PsarcFile::open(&mut Cursor::new(&bytes[..length])).is_err(), "{} byte header", length);
+    }
+}
+
+#[test]
+fn truncated_anywhere() {
+    let bytes = sample_archive();
+    for length in 0..bytes.len() {
+        read_all(&bytes[..length]);
+    }
+}
+
+#[test]
+fn huge_block_size() {
+    for block_size in [u32::MAX, 1 << 31, 1 << 24, 0] {
+        let mut bytes = sample_archive();
+        set_u32(&mut bytes, BLOCK_SIZE, block_size);
+        assert!(!read_all(&bytes), "block size {}", block_size);
+    }
+}
+
+#[test]
+fn block_inflating_past_block_size() {
+    // The 70000 byte entry has a compressed 65536 byte block; read with 4 KiB blocks it
+    // would inflate to 16 times the block size.
+    let mut bytes = sample_archive();
+    set_u32(&mut bytes, BLOCK_SIZE, 4096);
+    assert!(!read_all(&bytes));
+}
+
+#[test]
+fn copying_blocks_past_the_end() {
+    // The last entry ends two bytes past the truncated data; copying it short would
+    // misplace every entry written after it.
+    let bytes = sample_archive();
+    let mut archive = PsarcFile::open(&mut Cursor::new(&bytes[..bytes.len() - 2])).unwrap();
+    archive.read_manifest().unwrap();
+    let last = archive.toc.entries.iter().max_by_key(|entry| entry.offset).unwrap();
+    let mut writer = PsarcWriter::new();
+    let err = copy_entry_compressed(&archive, last, &mut writer).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    assert!(err.to_string().contains("past the end"), "{}", err);
+}
+
+#[test]
+fn bad_toc_count() {
+    for count in [u32::MAX, 1 << 28, 5] {
+        let mut bytes = sample_archive();
+        set_u32(&mut bytes, ENTRY_COUNT, count);
+        assert!(PsarcFile::open(&mut Cursor::new(&bytes)).is_err(), "{} entries", count);
+    }
+}
+
+#[test]
+fn huge_toc_size() {
+    for flags in [0, 4] {
+        let mut bytes = sample_archive();
+        set_u32(&mut bytes, TOC_SIZE, u32::MAX);
+        set_u32(&mut bytes, ARCHIVE_FLAGS, flags);
+        assert!(PsarcFile::open(&mut Cursor::new(&bytes)).is_err(), "flags {}", flags);
+    }
+}
+
+#[test]
+fn flipped_bytes() {
+    let original = sample_archive();
+    let mut state = 0x9E37_79B9u32;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state as usize
+    };
+    for _ in 0..2000 {
+        let mut bytes = original.clone();
+        for _ in 0..1 + next() % 4 {
+            let at = next() % bytes.len();
+            bytes[at] ^= 1 << (next() % 8);
+        }
+        read_all(&bytes);
+    }
+}
+
+#[cfg(feature = "image")]
+mod dds {
+    use psarc_unpacker::dds::{decode_dds, encode_dds_bc1, RgbaImage};
+
+    const HEIGHT: usize = 12;
+    const WIDTH: usize = 16;
+    const MIP_COUNT: usize = 28;
+    const FOUR_CC: usize = 84;
+
+    fn sample_dds() -> Vec<u8> {
+        encode_dds_bc1(&RgbaImage { width: 8, height: 8, pixels: vec![200; 8 * 8 * 4] })
+    }
+
+    fn set_u32(bytes: &mut [u8], offset: usize, value: u32) {
+        bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    #[test]
+    fn sample_decodes() {
+        assert!(decode_dds(&sample_dds(), 0, 0).is_ok());
+    }
+
+    #[test]
+    fn mip_count_overflow() {
+        for mips in [33, 64, u32::MAX] {
+            let mut dds = sample_dds();
+            set_u32(&mut dds, MIP_COUNT, mips);
+            assert!(decode_dds(&dds, 0, 0).is_err(), "{} mips", mips);
+            assert!(decode_dds(&dds, 40, 0).is_err());
+        }
+    }
+
+    #[test]
+    fn dimensions_overflow() {
+        for mips in [1, 32] {
+            let mut dds = sample_dds();
+            set_u32(&mut dds, WIDTH, u32::MAX);
+            set_u32(&mut dds, HEIGHT, u32::MAX);
+            set_u32(&mut dds, MIP_COUNT, mips);
+            for mip in [0, mips - 1] {
+                assert!(decode_dds(&dds, mip, 0).is_err(), "mip {} of {}", mip, mips);
+            }
+        }
+    }
+
+    #[test]
+    fn dx10_array_overflow() {
+        let mut dds = sample_dds();
+        dds[FOUR_CC..FOUR_CC + 4].copy_from_slice(b"DX10");
+        dds.splice(128..128, [0u8; 20]);
+        set_u32(&mut dds, 128, 71);
+        set_u32(&mut dds, 136, 0x4);
+        set_u32(&mut dds, 140, u32::MAX);
+        assert!(decode_dds(&dds, 0, 5).is_err());
+        set_u32(&mut dds, 140, 1 << 30);
+        assert!(decode_dds(&dds, 0, (1 << 30) - 1).is_err());
+    }
+
+    #[test]
+    fn resize_too_large() {
+        let image = RgbaImage { width: 2, height: 2, pixels: vec![0; 16] };
+        assert!(image.resize(u32::MAX, u32::MAX).is_err());
+        let image = RgbaImage { width: 2, height: 2, pixels: vec![0; 3] };
+        assert_eq!(image.resize(1, 1).unwrap().pixels.len(), 4);
+    }
+}
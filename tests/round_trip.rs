@@ -0,0 +1,130 @@
+use std::io::{Cursor, Write};
+
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use psarc_unpacker::psarc::PsarcFile;
+use psarc_unpacker::writer::PsarcWriter;
+
+/// Bytes zlib cannot shrink, from a xorshift generator.
+fn noise(length: usize, mut state: u32) -> Vec<u8> {
+    (0..length)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+fn zlib(data: &[u8]) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+fn pack(entries: &[(&str, &[u8])], block_size: u32) -> PsarcFile {
+    let mut writer = PsarcWriter::new().block_size(block_size);
+    for (path, data) in entries {
+        writer.add_entry(*path, data).unwrap();
+    }
+    let mut bytes = Vec::new();
+    writer.write_to(&mut bytes).unwrap();
+    PsarcFile::open(&mut Cursor::new(bytes)).unwrap()
+}
+
+fn read_back(archive: &PsarcFile, path: &str) -> Vec<u8> {
+    let entry = archive.entry_by_path(path).unwrap();
+    archive.inflate_entry_data(entry).unwrap()
+}
+
+#[test]
+fn raw_block_starting_like_zlib_reads_back() {
+    // A zlib stream of noise does not compress, so it would be stored as a raw partial
+    // block beginning with 78 DA.
+    let payload = zlib(&noise(1000, 0x2545_F491));
+    assert_eq!(payload.len(), 1011);
+    assert!(payload.starts_with(&[0x78, 0xDA]));
+    let tail = [noise(65536, 7), payload.clone()].concat();
+
+    let archive = pack(&[("audio/windows/nested.bin", &payload), ("audio/windows/tail.bin", &tail)], 65536);
+    assert_eq!(read_back(&archive, "audio/windows/nested.bin"), payload);
+    assert_eq!(read_back(&archive, "audio/windows/tail.bin"), tail);
+}
+
+#[test]
+fn writer_entry_data_matches_reader() {
+    let payload = zlib(&noise(1000, 99));
+    let mut writer = PsarcWriter::new();
+    writer.add_entry("songs/bin/data.bin", &payload).unwrap();
+    writer.add_entry_uncompressed("songs/bin/raw.bin", &payload).unwrap();
+    assert_eq!(writer.entry_data("songs/bin/data.bin").unwrap().unwrap(), payload);
+    assert_eq!(writer.entry_data("songs/bin/raw.bin").unwrap().unwrap(), payload);
+}
+
+#[test]
+fn block_sizes_read_back() {
+    let data = [noise(3000, 5), vec![9u8; 300_000], noise(200_000, 11)].concat();
+    for block_size in [1024, 4096, 65536, 1 << 17, 1 << 20, 1 << 25] {
+        let archive = pack(&[("songs/bin/generic/data.bin", &data)], block_size);
+        assert_eq!(read_back(&archive, "songs/bin/generic/data.bin"), data, "block size {}", block_size);
+    }
+}
+
+#[test]
+fn replace_in_place_keeps_table_width() {
+    let path = std::env::temp_dir().join(format!("psarc_round_trip_{}.psarc", std::process::id()));
+    let first = noise(150_000, 3);
+    let second = [noise(400_000, 4), vec![1u8; 100_000]].concat();
+    let mut writer = PsarcWriter::new().block_size(1 << 17);
+    writer.add_entry("a.bin", &first).unwrap();
+    writer.add_entry("b.bin", b"short").unwrap();
+    writer.write_path(&path).unwrap();
+
+    psarc_unpacker::patch::replace_entry_in_place(&path, "b.bin", &second).unwrap();
+    psarc_unpacker::patch::replace_entry_in_place(&path, "a.bin", b"tiny").unwrap();
+    let archive = PsarcFile::open_path(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(read_back(&archive, "a.bin"), b"tiny");
+    assert_eq!(read_back(&archive, "b.bin"), second);
+}
+
+#[test]
+fn app_id_is_set_when_packing_and_in_place() {
+    let path = std::env::temp_dir().join(format!("psarc_app_id_{}.psarc", std::process::id()));
+    let mut writer = PsarcWriter::new();
+    writer.add_entry("songs/bin/data.bin", b"chart").unwrap();
+    assert!(psarc_unpacker::repack::set_app_id_in(&mut writer, "24824x").is_err());
+    assert!(psarc_unpacker::patch::set_app_id_in_place(&path, "").is_err());
+    psarc_unpacker::repack::set_app_id_in(&mut writer, "248750").unwrap();
+    psarc_unpacker::repack::set_app_id_in(&mut writer, "221680").unwrap();
+    writer.write_path(&path).unwrap();
+    assert_eq!(PsarcFile::open_path(&path).unwrap().app_id().unwrap().as_deref(), Some("221680"));
+
+    psarc_unpacker::patch::set_app_id_in_place(&path, "248750").unwrap();
+    let archive = PsarcFile::open_path(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(archive.app_id().unwrap().as_deref(), Some("248750"));
+    assert_eq!(archive.toc.entries.len(), 3);
+}
+
+#[test]
+fn entry_head_inflates_only_the_leading_blocks() {
+    let data = noise(5000, 42);
+    let archive = pack(&[("songs/bin/generic/data.bin", &data)], 1024);
+    let entry = archive.entry_by_path("songs/bin/generic/data.bin").unwrap();
+    assert_eq!(archive.inflate_entry_head(entry, 1500).unwrap(), &data[..1500]);
+    assert_eq!(archive.inflate_entry_head(entry, 1 << 20).unwrap(), data);
+    assert!(archive.inflate_entry_head(entry, 0).unwrap().is_empty());
+}
+
+#[cfg(feature = "image")]
+#[test]
+fn textures_are_found_across_blocks() {
+    let texture = [b"DDS ".as_slice(), &noise(3000, 3)].concat();
+    let archive = pack(&[("gfxassets/album_art/art_256.dds", &texture), ("audio/windows/song.wem", &noise(3000, 5))], 1024);
+    let textures = archive.texture_entries().unwrap();
+    assert_eq!(textures.len(), 1);
+    assert_eq!(textures[0].hash, archive.entry_by_path("gfxassets/album_art/art_256.dds").unwrap().hash);
+    assert_eq!(archive.inflate_entry_data(textures[0]).unwrap(), texture);
+}